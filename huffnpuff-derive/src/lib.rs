@@ -0,0 +1,84 @@
+//! `#[derive(Versioned)]` for huffnpuff. Use it through huffnpuff's `derive` feature rather than
+//! depending on this crate directly.
+
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{DeriveInput, LitInt, Token, Type, parse_macro_input};
+
+/// Implement `huffnpuff::Versioned`. The version is given by `#[huffnpuff(version = N)]`, and
+/// values written with older versions can be converted by `From` with
+/// `#[huffnpuff(version = N, from(1 = TypeV1, 2 = TypeV2))]`, which needs huffnpuff's `decode`
+/// feature.
+#[proc_macro_derive(Versioned, attributes(huffnpuff))]
+pub fn derive_versioned(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match versioned(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(error) => error.to_compile_error().into(),
+    }
+}
+
+fn versioned(input: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let mut version = None;
+    let mut older: Vec<(LitInt, Type)> = Vec::new();
+    for attr in input
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("huffnpuff"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("version") {
+                version = Some(meta.value()?.parse::<LitInt>()?);
+                Ok(())
+            } else if meta.path.is_ident("from") {
+                let content;
+                syn::parenthesized!(content in meta.input);
+                while !content.is_empty() {
+                    let version = content.parse::<LitInt>()?;
+                    content.parse::<Token![=]>()?;
+                    older.push((version, content.parse()?));
+                    if !content.is_empty() {
+                        content.parse::<Token![,]>()?;
+                    }
+                }
+                Ok(())
+            } else {
+                Err(meta.error("expected `version` or `from`"))
+            }
+        })?;
+    }
+    let version = version.ok_or_else(|| {
+        syn::Error::new_spanned(
+            &input.ident,
+            "missing the version, add `#[huffnpuff(version = N)]`",
+        )
+    })?;
+    let version = version.base10_parse::<u32>()?;
+
+    let name = &input.ident;
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+    let predicates = where_clause.map(|clause| &clause.predicates);
+    let migrations = (!older.is_empty()).then(|| {
+        let migrations = older.iter().map(|(version, ty)| {
+            quote! {
+                .migrate(#version, |old: #ty| <Self as ::core::convert::From<#ty>>::from(old))
+            }
+        });
+        quote! {
+            fn migrations() -> ::huffnpuff::Migrations<Self> {
+                ::huffnpuff::Migrations::new(#version)#(#migrations)*
+            }
+        }
+    });
+
+    Ok(quote! {
+        impl #impl_generics ::huffnpuff::Versioned for #name #type_generics
+        where
+            Self: ::huffnpuff::Huffable,
+            #predicates
+        {
+            const VERSION: u32 = #version;
+            #migrations
+        }
+    })
+}
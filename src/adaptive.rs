@@ -0,0 +1,431 @@
+//! Adaptive huffman coding (the FGK algorithm), where the encoder and decoder both start from an
+//! empty tree and update it in the same way after every symbol. No tree is sent, and coding can
+//! start before the whole message is known, so it suits streams. Codes are a little longer than
+//! with a tree built from the whole message, since the tree only catches up with the data as it
+//! goes.
+//!
+//! Each byte is coded by its path in the current tree. A byte that hasn't been seen yet is coded
+//! by the path to the "not yet transmitted" (NYT) leaf followed by the byte's 9 bit value, and so
+//! is EOM, value 256, which ends the message.
+
+#[cfg(feature = "encode")]
+use std::io::{self, Write};
+
+use crate::bits::BitOrder;
+#[cfg(feature = "decode")]
+use crate::bits::BitReader;
+#[cfg(feature = "encode")]
+use crate::bits::BitWriter;
+#[cfg(feature = "encode")]
+use crate::frame;
+#[cfg(feature = "decode")]
+use crate::huffman;
+#[cfg(feature = "decode")]
+use crate::reserve;
+#[cfg(feature = "decode")]
+use crate::ring::{SplitBytes, SplitOut};
+
+/// The number of values: every byte, and EOM
+const VALUES: usize = 257;
+
+/// The most nodes a tree has: a leaf for every value, the NYT leaf, and the inner nodes joining
+/// them
+const NODES: usize = 2 * VALUES + 1;
+
+/// The value standing for EOM
+const EOM: u16 = 256;
+
+/// No node, for the parent of the root and the leaves of values not seen yet
+const NONE: u16 = u16::MAX;
+
+/// The tree shared by the encoder and decoder. Nodes are numbered so that weights never decrease
+/// as the number goes up and siblings are numbered next to each other, the sibling property that
+/// keeps the tree a huffman tree. The root is the highest numbered node. It's kept in fixed size
+/// arrays, so decoding with it doesn't allocate.
+#[derive(Clone)]
+struct Tree {
+    weight: [u64; NODES],
+    parent: [u16; NODES],
+    /// The left child of each inner node, whose right child is numbered one higher, or [`NONE`]
+    /// for leaves
+    left: [u16; NODES],
+    /// The value of each leaf
+    value: [u16; NODES],
+    /// The leaf of each value, or [`NONE`] if it hasn't been seen yet
+    leaf: [u16; VALUES],
+    nyt: u16,
+}
+
+impl Tree {
+    fn new() -> Self {
+        let root = (NODES - 1) as u16;
+        Self {
+            weight: [0; NODES],
+            parent: [NONE; NODES],
+            left: [NONE; NODES],
+            value: [NONE; NODES],
+            leaf: [NONE; VALUES],
+            nyt: root,
+        }
+    }
+
+    fn root() -> u16 {
+        (NODES - 1) as u16
+    }
+
+    /// Give the NYT leaf two children, a new NYT leaf and a leaf for `value`, returning the new
+    /// leaf
+    fn add(&mut self, value: u16) -> u16 {
+        let (old, nyt, leaf) = (self.nyt, self.nyt - 2, self.nyt - 1);
+        self.left[old as usize] = nyt;
+        for node in [nyt, leaf] {
+            self.parent[node as usize] = old;
+        }
+        self.value[leaf as usize] = value;
+        self.leaf[value as usize] = leaf;
+        self.nyt = nyt;
+        leaf
+    }
+
+    /// Count another occurrence of the value at `node`, moving nodes to keep the sibling property
+    fn update(&mut self, mut node: u16) {
+        loop {
+            // The highest numbered node of the same weight takes this node's place, unless it's
+            // the parent
+            let mut leader = node;
+            while leader < Self::root()
+                && self.weight[leader as usize + 1] == self.weight[node as usize]
+            {
+                leader += 1;
+            }
+            if leader != node && leader != self.parent[node as usize] {
+                self.swap(node, leader);
+                node = leader;
+            }
+            self.weight[node as usize] += 1;
+            if node == Self::root() {
+                return;
+            }
+            node = self.parent[node as usize];
+        }
+    }
+
+    /// Swap the subtrees numbered `a` and `b`, which keep their places under their parents
+    fn swap(&mut self, a: u16, b: u16) {
+        let (a, b) = (a as usize, b as usize);
+        self.left.swap(a, b);
+        self.value.swap(a, b);
+        self.weight.swap(a, b);
+        if self.nyt as usize == a {
+            self.nyt = b as u16;
+        } else if self.nyt as usize == b {
+            self.nyt = a as u16;
+        }
+        for node in [a, b] {
+            match self.left[node] {
+                NONE if self.value[node] != NONE => {
+                    self.leaf[self.value[node] as usize] = node as u16;
+                }
+                NONE => {}
+                left => {
+                    self.parent[left as usize] = node as u16;
+                    self.parent[left as usize + 1] = node as u16;
+                }
+            }
+        }
+    }
+
+    /// Write the code for `value`, and count it
+    #[cfg(feature = "encode")]
+    fn encode(&mut self, value: u16, bits: &mut BitWriter) {
+        let leaf = self.leaf[value as usize];
+        let node = match leaf {
+            NONE => self.nyt,
+            leaf => leaf,
+        };
+        // The path is found from the leaf up, so it's gathered and written in reverse. Depths past
+        // 128 would take more than 2^64 symbols to reach.
+        let (mut path, mut len) = (0u128, 0);
+        let mut child = node;
+        while child != Self::root() {
+            let parent = self.parent[child as usize];
+            path = path << 1 | (child != self.left[parent as usize]) as u128;
+            len += 1;
+            child = parent;
+        }
+        for i in 0..len {
+            bits.write_bit(path >> i & 1 == 1);
+        }
+        if leaf == NONE {
+            bits.write_bits(value as u64, 9);
+            let leaf = self.add(value);
+            self.update(leaf);
+        } else {
+            self.update(leaf);
+        }
+    }
+
+    /// Read the code for a value, and count it
+    #[cfg(feature = "decode")]
+    fn decode(&mut self, bits: &mut BitReader) -> Result<u16, huffman::Error> {
+        let mut node = Self::root();
+        while self.left[node as usize] != NONE {
+            let bit = bits.read_bit().ok_or(huffman::Error::TruncatedMessage)?;
+            node = self.left[node as usize] + bit as u16;
+        }
+        if node != self.nyt {
+            let value = self.value[node as usize];
+            self.update(node);
+            return Ok(value);
+        }
+
+        let value = bits.read_n(9).ok_or(huffman::Error::TruncatedMessage)? as u16;
+        // A new value must be one, and one that hasn't been seen
+        if value as usize >= VALUES || self.leaf[value as usize] != NONE {
+            return Err(huffman::Error::FailedToDecodeHuffmanTree);
+        }
+        let leaf = self.add(value);
+        self.update(leaf);
+        Ok(value)
+    }
+}
+
+/// Code `bytes`, followed by EOM
+#[cfg(feature = "encode")]
+pub(crate) fn encode(bytes: &[u8], order: BitOrder) -> Vec<u8> {
+    let mut tree = Box::new(Tree::new());
+    let mut bits = BitWriter::with_order(order);
+    for &byte in bytes {
+        tree.encode(byte as u16, &mut bits);
+    }
+    tree.encode(EOM, &mut bits);
+    bits.into_bytes()
+}
+
+/// Decode values from `bits` into `push` up to EOM, or until `push` returns false
+#[cfg(feature = "decode")]
+fn decode_with(
+    bits: &mut BitReader,
+    mut push: impl FnMut(u8) -> Result<bool, crate::Error>,
+) -> Result<(), crate::Error> {
+    let mut tree = Tree::new();
+    loop {
+        match tree.decode(bits)? {
+            EOM => return Ok(()),
+            value => {
+                if !push(value as u8)? {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+/// Decode the first message in `bytes`, as [`huffman::decode_member`] does
+#[cfg(feature = "decode")]
+pub(crate) fn decode_member(
+    bytes: &[u8],
+    order: BitOrder,
+    max_len: usize,
+) -> Result<(Vec<u8>, usize), crate::Error> {
+    let mut bits = BitReader::with_order(bytes, order);
+    let mut decoded = Vec::new();
+    decode_with(&mut bits, |byte| {
+        reserve::push(&mut decoded, byte)?;
+        Ok(decoded.len() <= max_len)
+    })?;
+    Ok((decoded, bits.position().div_ceil(8)))
+}
+
+/// Like [`decode_member`], but decodes into `out`, as [`huffman::decode_member_into_split`] does
+#[cfg(feature = "decode")]
+pub(crate) fn decode_member_into_split(
+    bytes: SplitBytes,
+    order: BitOrder,
+    out: &mut SplitOut,
+) -> Result<usize, crate::Error> {
+    let mut bits = bytes.bits(order);
+    decode_with(&mut bits, |byte| out.push(byte).map(|()| true))?;
+    Ok(bits.position().div_ceil(8))
+}
+
+/// Lazily decodes the symbols of a message, see [`decode_iter`]
+#[cfg(feature = "decode")]
+pub(crate) struct DecodeIter<'a> {
+    tree: Box<Tree>,
+    bits: BitReader<'a>,
+    done: bool,
+    /// Whether the bits ran out before EOM
+    truncated: bool,
+}
+
+#[cfg(feature = "decode")]
+impl Iterator for DecodeIter<'_> {
+    type Item = u8;
+
+    fn next(&mut self) -> Option<u8> {
+        if self.done {
+            return None;
+        }
+        match self.tree.decode(&mut self.bits) {
+            Ok(EOM) => {
+                self.done = true;
+                None
+            }
+            Ok(value) => Some(value as u8),
+            Err(_) => {
+                self.done = true;
+                self.truncated = true;
+                None
+            }
+        }
+    }
+}
+
+#[cfg(feature = "decode")]
+impl DecodeIter<'_> {
+    /// Whether the iterator ended because the message was cut off or damaged before its end
+    pub fn truncated(&self) -> bool {
+        self.truncated
+    }
+}
+
+/// Decode the message in `bytes` one symbol at a time
+#[cfg(feature = "decode")]
+pub(crate) fn decode_iter(bytes: &[u8], order: BitOrder) -> DecodeIter<'_> {
+    DecodeIter {
+        tree: Box::new(Tree::new()),
+        bits: BitReader::with_order(bytes, order),
+        done: false,
+        truncated: false,
+    }
+}
+
+/// Compresses a stream of bytes written to it into a single frame, coding each byte as it's
+/// written with adaptive huffman coding, so nothing but the partly written byte at the end of the
+/// output is held back. [`AdaptiveWriter::finish`] must be called to end the frame. The frame is
+/// decoded like any other, by [`decompress`](crate::decompress) or
+/// [`decode_iter`](crate::decode_iter).
+///
+/// ```
+/// use std::io::Write;
+///
+/// let mut writer = huffnpuff::AdaptiveWriter::new(Vec::new())?;
+/// for line in ["first line\n", "second line\n"] {
+///     writer.write_all(line.as_bytes())?;
+/// }
+/// let compressed = writer.finish()?;
+/// assert_eq!(huffnpuff::decompress(&compressed)?, b"first line\nsecond line\n");
+/// # Ok::<(), huffnpuff::Error>(())
+/// ```
+#[cfg(feature = "encode")]
+pub struct AdaptiveWriter<W: Write> {
+    writer: W,
+    tree: Box<Tree>,
+    bits: BitWriter,
+}
+
+#[cfg(feature = "encode")]
+impl<W: Write> AdaptiveWriter<W> {
+    /// Start a frame in `writer`, writing its header straight away
+    pub fn new(mut writer: W) -> Result<Self, crate::Error> {
+        writer.write_all(&frame::plain_header(frame::Encoding::Adaptive))?;
+        Ok(Self {
+            writer,
+            tree: Box::new(Tree::new()),
+            bits: BitWriter::new(),
+        })
+    }
+
+    /// The underlying writer. Writing to it directly corrupts the frame.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    /// End the frame, returning the underlying writer
+    pub fn finish(mut self) -> Result<W, crate::Error> {
+        self.tree.encode(EOM, &mut self.bits);
+        self.bits.align_to_byte();
+        self.writer.write_all(&self.bits.take_bytes())?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+#[cfg(feature = "encode")]
+impl<W: Write> Write for AdaptiveWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for &byte in buf {
+            self.tree.encode(byte as u16, &mut self.bits);
+        }
+        self.writer.write_all(&self.bits.take_bytes())?;
+        Ok(buf.len())
+    }
+
+    /// Flushes the underlying writer. The bits of the last, partly coded byte stay held back.
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let inputs: [&[u8]; 4] = [
+            b"",
+            b"a",
+            b"abracadabra, abracadabra, abracadabra",
+            &(0..=255).cycle().take(5000).collect::<Vec<u8>>(),
+        ];
+        for input in inputs {
+            for order in [BitOrder::Lsb0, BitOrder::Msb0] {
+                let encoded = encode(input, order);
+                let (decoded, len) = decode_member(&encoded, order, usize::MAX).unwrap();
+                assert_eq!(decoded, input);
+                assert_eq!(len, encoded.len());
+                assert_eq!(decode_iter(&encoded, order).collect::<Vec<u8>>(), input);
+            }
+        }
+    }
+
+    #[test]
+    fn adapts() {
+        // Text is coded about as well as with a tree of its own, without sending the tree
+        let text = b"the tree follows the data as it's coded, the tree follows the data".repeat(20);
+        let adaptive = encode(&text, BitOrder::Lsb0);
+        let static_tree = huffman::encode(&text, BitOrder::Lsb0).unwrap();
+        assert!(adaptive.len() < static_tree.len() + static_tree.len() / 20);
+
+        // The sibling property holds throughout
+        let mut tree = Tree::new();
+        let mut bits = BitWriter::new();
+        for &byte in &text {
+            tree.encode(byte as u16, &mut bits);
+            assert!(tree.weight[tree.nyt as usize..].is_sorted());
+        }
+    }
+
+    #[test]
+    fn damaged() {
+        let encoded = encode(b"cut off partway through", BitOrder::Lsb0);
+        let truncated = &encoded[..encoded.len() - 3];
+        assert_eq!(
+            decode_member(truncated, BitOrder::Lsb0, usize::MAX),
+            Err(huffman::Error::TruncatedMessage.into())
+        );
+        let mut iter = decode_iter(truncated, BitOrder::Lsb0);
+        assert_eq!(iter.by_ref().count(), 22);
+        assert!(iter.truncated());
+
+        // The first value is new, and 9 bits of ones isn't a value
+        assert_eq!(
+            decode_member(&[0xff, 0xff], BitOrder::Lsb0, usize::MAX),
+            Err(huffman::Error::FailedToDecodeHuffmanTree.into())
+        );
+        let (decoded, _) = decode_member(&encoded, BitOrder::Lsb0, 5).unwrap();
+        assert_eq!(decoded, b"cut of");
+    }
+}
@@ -0,0 +1,932 @@
+use std::collections::{BTreeMap, HashMap};
+#[cfg(feature = "encode")]
+use std::hash::{DefaultHasher, Hash, Hasher};
+#[cfg(feature = "decode")]
+use std::io;
+use std::io::Read;
+#[cfg(feature = "encode")]
+use std::io::{Seek, SeekFrom, Write};
+
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "encode")]
+use crate::huff;
+#[cfg(feature = "decode")]
+use crate::puff;
+#[cfg(feature = "encode")]
+use crate::varint;
+
+const MAGIC: &[u8; 4] = b"HNPA";
+/// From version 2, each entry is preceded by a header holding its name, length and checksum, and
+/// the entries are followed by [`END_OF_ENTRIES`], so an archive can be read front to back by an
+/// [`ArchiveStream`]. From version 3, an entry whose compressed data is the same as an earlier
+/// entry's is written as a [`REPEAT`] of it instead, and its table of contents entry points at
+/// the earlier entry's data.
+const VERSION: u8 = 3;
+const ENTRY: u8 = 1;
+/// Followed by the entry's name and the index of the earlier entry it repeats, in the order the
+/// entries were written
+const REPEAT: u8 = 2;
+const END_OF_ENTRIES: u8 = 0;
+/// Entry names in headers longer than this are taken to be corrupt, rather than allocated for
+#[cfg(feature = "decode")]
+const MAX_NAME_LEN: u64 = 64 * 1024;
+const HEADER_SIZE: usize = MAGIC.len() + 1;
+/// The footer is the offset of the table of contents followed by the magic bytes again, so the
+/// table of contents can be located from the end of the archive
+const FOOTER_SIZE: usize = 8 + MAGIC.len();
+
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The data does not look like an archive produced by huffnpuff
+    BadMagic,
+    /// The archive was written with a format version this library doesn't understand
+    UnsupportedVersion(u8),
+    /// The archive is too short to contain a header, table of contents and footer
+    Truncated,
+    /// The table of contents points outside the archive, or could not be decoded
+    CorruptTableOfContents,
+    /// An entry with this name was already added to the archive
+    DuplicateEntry(String),
+    /// There is no entry with this name in the archive
+    EntryNotFound(String),
+    /// The stored checksum for this entry doesn't match its data
+    ChecksumMismatch(String),
+    /// The archive was written by version 1 of the format, whose entries don't carry their
+    /// names, so it can only be read with an [`ArchiveReader`]
+    NotStreamable,
+    /// The header of an entry in the body of the archive could not be read
+    CorruptEntryHeader,
+    /// The entry's data is the same as that of the earlier entry with this name, which an
+    /// [`ArchiveStream`] has already passed, so the value read for that entry should be used
+    DuplicateData(String),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Error::BadMagic => "the data is not an archive",
+            Error::UnsupportedVersion(version) => {
+                return write!(f, "the archive has unsupported format version {version}");
+            }
+            Error::Truncated => "the archive is too short to hold its table of contents",
+            Error::CorruptTableOfContents => "the archive's table of contents is damaged",
+            Error::DuplicateEntry(name) => {
+                return write!(f, "the archive already has an entry named {name:?}");
+            }
+            Error::EntryNotFound(name) => {
+                return write!(f, "the archive has no entry named {name:?}");
+            }
+            Error::ChecksumMismatch(name) => {
+                return write!(f, "the entry named {name:?} doesn't match its checksum");
+            }
+            Error::NotStreamable => {
+                "the archive's entries don't carry their names, so it can't be streamed"
+            }
+            Error::CorruptEntryHeader => "the header of an archive entry is damaged",
+            Error::DuplicateData(name) => {
+                return write!(f, "the entry has the same data as the entry named {name:?}");
+            }
+        })
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct TocEntry {
+    name: String,
+    offset: u64,
+    len: u64,
+    checksum: u32,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Toc {
+    metadata: BTreeMap<String, String>,
+    entries: Vec<TocEntry>,
+}
+
+/// Returns the format version
+fn check_header(header: &[u8]) -> Result<u8, Error> {
+    if &header[..MAGIC.len()] != MAGIC {
+        return Err(Error::BadMagic);
+    }
+    match header[MAGIC.len()] {
+        version @ 1..=VERSION => Ok(version),
+        version => Err(Error::UnsupportedVersion(version)),
+    }
+}
+
+/// Returns the offset of the table of contents
+fn check_footer(footer: &[u8], archive_len: u64) -> Result<u64, Error> {
+    if &footer[8..] != MAGIC {
+        return Err(Error::BadMagic);
+    }
+    let toc_offset = u64::from_le_bytes(footer[..8].try_into().unwrap());
+    if toc_offset < HEADER_SIZE as u64 || toc_offset > archive_len - FOOTER_SIZE as u64 {
+        return Err(Error::CorruptTableOfContents);
+    }
+    Ok(toc_offset)
+}
+
+/// The table of contents is stored uncompressed, so that metadata and entry names can be read
+/// without decoding anything else. Any bytes after it up to the footer are padding.
+fn parse_toc(toc: &[u8], toc_offset: u64) -> Result<Toc, Error> {
+    let toc: Toc = bincode::deserialize(toc).map_err(|_| Error::CorruptTableOfContents)?;
+    for entry in &toc.entries {
+        let in_bounds = entry
+            .offset
+            .checked_add(entry.len)
+            .is_some_and(|end| entry.offset >= HEADER_SIZE as u64 && end <= toc_offset);
+        if !in_bounds {
+            return Err(Error::CorruptTableOfContents);
+        }
+    }
+    Ok(toc)
+}
+
+/// Writes named, individually compressed values followed by a table of contents, so that each
+/// entry can later be decoded on its own by an [`ArchiveReader`]
+#[cfg(feature = "encode")]
+pub struct ArchiveWriter<W: Write> {
+    writer: W,
+    /// The format version, which is kept when appending to an older archive
+    version: u8,
+    position: u64,
+    toc: Toc,
+    /// When appending, the length of the existing archive. The new archive is padded out to at
+    /// least this length so no stale bytes are left after the new footer.
+    min_len: u64,
+    /// Whether values the same as earlier ones are written as repeats, see
+    /// [`ArchiveWriter::set_deduplicate`]
+    deduplicate: bool,
+    /// The entries written by this writer, by a hash of their compressed data, its length and
+    /// its checksum, for finding duplicates
+    written: HashMap<(u64, u64, u32), u64>,
+}
+
+#[cfg(feature = "encode")]
+impl<W: Write> ArchiveWriter<W> {
+    /// Start a new archive, writing the archive header to `writer`
+    pub fn new(mut writer: W) -> Result<Self, crate::Error> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[VERSION])?;
+
+        Ok(Self {
+            writer,
+            version: VERSION,
+            position: HEADER_SIZE as u64,
+            toc: Toc::default(),
+            min_len: 0,
+            deduplicate: false,
+            written: HashMap::new(),
+        })
+    }
+
+    /// Attach a key/value pair to the archive, such as a creation time, the producing program or a
+    /// comment. Metadata can be read by [`ArchiveReader::metadata`] without decoding any entries.
+    /// Setting an existing key replaces its value.
+    pub fn set_metadata(&mut self, key: impl Into<String>, value: impl Into<String>) {
+        self.toc.metadata.insert(key.into(), value.into());
+    }
+
+    /// Store values added from now on that compress to the same bytes as one added earlier by
+    /// this writer only once, with later entries sharing the first one's data. This suits
+    /// archives read with an [`ArchiveReader`], but an [`ArchiveStream`] has passed the shared
+    /// data by the time it reaches a later entry, so reading that entry's data from the stream
+    /// fails with [`DuplicateData`](Error::DuplicateData). Archives meant to be restored by
+    /// streaming should be left without it, which is the default. Appending to an archive
+    /// written before version 3 of the format never deduplicates.
+    pub fn set_deduplicate(&mut self, deduplicate: bool) {
+        self.deduplicate = deduplicate;
+    }
+
+    /// Compress `value` and add it to the archive under `name`. Names must be unique. With
+    /// [`set_deduplicate`](ArchiveWriter::set_deduplicate), a value that compresses to the same
+    /// bytes as one added earlier by this writer isn't stored again.
+    pub fn add<T>(&mut self, name: impl Into<String>, value: &T) -> Result<(), crate::Error>
+    where
+        T: serde::Serialize,
+    {
+        let name = name.into();
+        if self.toc.entries.iter().any(|entry| entry.name == name) {
+            return Err(Error::DuplicateEntry(name).into());
+        }
+
+        let compressed = huff(value)?;
+        let checksum = crc32fast::hash(&compressed);
+        let mut hasher = DefaultHasher::new();
+        compressed.hash(&mut hasher);
+        let key = (hasher.finish(), compressed.len() as u64, checksum);
+        if self.version >= 3
+            && self.deduplicate
+            && let Some(&index) = self.written.get(&key)
+        {
+            let mut header = vec![REPEAT];
+            varint::write(name.len() as u64, &mut header);
+            header.extend_from_slice(name.as_bytes());
+            varint::write(index, &mut header);
+            self.writer.write_all(&header)?;
+            self.position += header.len() as u64;
+
+            let original = &self.toc.entries[index as usize];
+            self.toc.entries.push(TocEntry {
+                name,
+                ..original.clone()
+            });
+            return Ok(());
+        }
+
+        if self.version >= 2 {
+            let mut header = vec![ENTRY];
+            varint::write(name.len() as u64, &mut header);
+            header.extend_from_slice(name.as_bytes());
+            varint::write(compressed.len() as u64, &mut header);
+            header.extend_from_slice(&checksum.to_le_bytes());
+            self.writer.write_all(&header)?;
+            self.position += header.len() as u64;
+        }
+        self.writer.write_all(&compressed)?;
+
+        self.written.insert(key, self.toc.entries.len() as u64);
+        self.toc.entries.push(TocEntry {
+            name,
+            offset: self.position,
+            len: compressed.len() as u64,
+            checksum,
+        });
+        self.position += compressed.len() as u64;
+
+        Ok(())
+    }
+
+    /// Write the table of contents and footer, returning the underlying writer
+    pub fn finish(mut self) -> Result<W, crate::Error> {
+        if self.version >= 2 {
+            self.writer.write_all(&[END_OF_ENTRIES])?;
+            self.position += 1;
+        }
+        let toc = bincode::serialize(&self.toc)?;
+        self.writer.write_all(&toc)?;
+
+        let len = self.position + (toc.len() + FOOTER_SIZE) as u64;
+        let padding = self.min_len.saturating_sub(len) as usize;
+        self.writer.write_all(&vec![0; padding])?;
+        self.writer.write_all(&self.position.to_le_bytes())?;
+        self.writer.write_all(MAGIC)?;
+        self.writer.flush()?;
+
+        Ok(self.writer)
+    }
+}
+
+#[cfg(feature = "encode")]
+impl<W: Read + Write + Seek> ArchiveWriter<W> {
+    /// Reopen an existing archive to add more entries. New entries are written over the old table
+    /// of contents, so the data of existing entries is never rewritten; a new table of contents
+    /// and footer are written by [`ArchiveWriter::finish`].
+    pub fn append(mut file: W) -> Result<Self, crate::Error> {
+        let archive_len = file.seek(SeekFrom::End(0))?;
+        if archive_len < (HEADER_SIZE + FOOTER_SIZE) as u64 {
+            return Err(Error::Truncated.into());
+        }
+
+        let mut header = [0; HEADER_SIZE];
+        file.seek(SeekFrom::Start(0))?;
+        file.read_exact(&mut header)?;
+        let version = check_header(&header)?;
+
+        let mut footer = [0; FOOTER_SIZE];
+        file.seek(SeekFrom::End(-(FOOTER_SIZE as i64)))?;
+        file.read_exact(&mut footer)?;
+        let toc_offset = check_footer(&footer, archive_len)?;
+
+        let mut toc = vec![0; (archive_len - FOOTER_SIZE as u64 - toc_offset) as usize];
+        file.seek(SeekFrom::Start(toc_offset))?;
+        file.read_exact(&mut toc)?;
+        let toc = parse_toc(&toc, toc_offset)?;
+
+        // New entries are written over the end of entries marker, which is written again after
+        // them
+        let mut position = toc_offset;
+        if version >= 2 {
+            let mut end = [0];
+            position = toc_offset
+                .checked_sub(1)
+                .filter(|&end| end >= HEADER_SIZE as u64)
+                .ok_or(Error::CorruptTableOfContents)?;
+            file.seek(SeekFrom::Start(position))?;
+            file.read_exact(&mut end)?;
+            if end != [END_OF_ENTRIES] {
+                return Err(Error::CorruptTableOfContents.into());
+            }
+        }
+        file.seek(SeekFrom::Start(position))?;
+        Ok(Self {
+            writer: file,
+            version,
+            position,
+            toc,
+            min_len: archive_len,
+            deduplicate: false,
+            written: HashMap::new(),
+        })
+    }
+}
+
+/// Random access to the entries of an archive produced by [`ArchiveWriter`]. Only the table of
+/// contents is decoded up front; entries are checked and decompressed when they are requested.
+#[cfg(feature = "decode")]
+pub struct ArchiveReader<'a> {
+    bytes: &'a [u8],
+    toc: Toc,
+    index: HashMap<String, usize>,
+}
+
+#[cfg(feature = "decode")]
+impl<'a> ArchiveReader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Result<Self, crate::Error> {
+        if bytes.len() < HEADER_SIZE + FOOTER_SIZE {
+            return Err(Error::Truncated.into());
+        }
+        check_header(&bytes[..HEADER_SIZE])?;
+
+        let footer_start = bytes.len() - FOOTER_SIZE;
+        let toc_offset = check_footer(&bytes[footer_start..], bytes.len() as u64)?;
+        let toc = parse_toc(&bytes[toc_offset as usize..footer_start], toc_offset)?;
+
+        let index = toc
+            .entries
+            .iter()
+            .enumerate()
+            .map(|(i, entry)| (entry.name.clone(), i))
+            .collect();
+
+        Ok(Self { bytes, toc, index })
+    }
+
+    /// Decompress the entry stored under `name`
+    pub fn get<T>(&self, name: &str) -> Result<T, crate::Error>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        let entry = self
+            .index
+            .get(name)
+            .map(|&i| &self.toc.entries[i])
+            .ok_or_else(|| Error::EntryNotFound(name.to_owned()))?;
+
+        // Bounds were checked when the table of contents was read
+        let start = entry.offset as usize;
+        let data = &self.bytes[start..start + entry.len as usize];
+        if crc32fast::hash(data) != entry.checksum {
+            return Err(Error::ChecksumMismatch(entry.name.clone()).into());
+        }
+
+        puff(data)
+    }
+
+    /// The key/value metadata attached to the archive by [`ArchiveWriter::set_metadata`]
+    pub fn metadata(&self) -> &BTreeMap<String, String> {
+        &self.toc.metadata
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.index.contains_key(name)
+    }
+
+    /// The names of the entries in the order they were added
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.toc.entries.iter().map(|entry| entry.name.as_str())
+    }
+
+    pub fn len(&self) -> usize {
+        self.toc.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.toc.entries.is_empty()
+    }
+}
+
+/// Reads the entries of an archive produced by [`ArchiveWriter`] front to back, from a source
+/// that can't seek, such as a pipe or a download. Neither the whole archive nor its table of
+/// contents is held in memory; each entry is yielded in turn, and its compressed data is only read
+/// as the caller asks for it. Data left unread is skipped by [`ArchiveStream::next_entry`].
+#[cfg(feature = "decode")]
+pub struct ArchiveStream<R> {
+    reader: R,
+    /// The bytes of the current entry's data not read yet
+    remaining: u64,
+    done: bool,
+    /// The name, compressed length and checksum of each entry so far, which repeats refer to
+    entries: Vec<(String, u64, u32)>,
+}
+
+#[cfg(feature = "decode")]
+impl<R: Read> ArchiveStream<R> {
+    /// Start reading an archive, which reads its header
+    pub fn new(mut reader: R) -> Result<Self, crate::Error> {
+        let mut header = [0; HEADER_SIZE];
+        reader
+            .read_exact(&mut header)
+            .map_err(|error| match error.kind() {
+                io::ErrorKind::UnexpectedEof => crate::Error::from(Error::Truncated),
+                _ => error.into(),
+            })?;
+        if check_header(&header)? < 2 {
+            return Err(Error::NotStreamable.into());
+        }
+        Ok(Self {
+            reader,
+            remaining: 0,
+            done: false,
+            entries: Vec::new(),
+        })
+    }
+
+    /// The next entry, or `None` once every entry has been read
+    pub fn next_entry(&mut self) -> Result<Option<StreamEntry<'_, R>>, crate::Error> {
+        if self.done {
+            return Ok(None);
+        }
+        io::copy(
+            &mut (&mut self.reader).take(self.remaining),
+            &mut io::sink(),
+        )?;
+        self.remaining = 0;
+
+        let mut marker = [0];
+        self.reader.read_exact(&mut marker)?;
+        let repeat = match marker[0] {
+            ENTRY => false,
+            REPEAT => true,
+            END_OF_ENTRIES => {
+                self.done = true;
+                return Ok(None);
+            }
+            _ => return Err(Error::CorruptEntryHeader.into()),
+        };
+        let name_len = read_varint(&mut self.reader)?
+            .filter(|&len| len <= MAX_NAME_LEN)
+            .ok_or(Error::CorruptEntryHeader)?;
+        let mut name = vec![0; name_len as usize];
+        self.reader.read_exact(&mut name)?;
+        let name = String::from_utf8(name).map_err(|_| Error::CorruptEntryHeader)?;
+
+        let (len, checksum, duplicate_of) = if repeat {
+            let (original, len, checksum) = read_varint(&mut self.reader)?
+                .and_then(|index| usize::try_from(index).ok())
+                .and_then(|index| self.entries.get(index))
+                .ok_or(Error::CorruptEntryHeader)?;
+            (*len, *checksum, Some(original.clone()))
+        } else {
+            let len = read_varint(&mut self.reader)?.ok_or(Error::CorruptEntryHeader)?;
+            let mut checksum = [0; 4];
+            self.reader.read_exact(&mut checksum)?;
+            self.remaining = len;
+            (len, u32::from_le_bytes(checksum), None)
+        };
+        self.entries.push((name.clone(), len, checksum));
+        Ok(Some(StreamEntry {
+            stream: self,
+            name,
+            len,
+            checksum,
+            duplicate_of,
+            hasher: crc32fast::Hasher::new(),
+        }))
+    }
+
+    /// The underlying reader
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+/// Read a varint from `reader`, returning `None` if it doesn't fit in 64 bits
+#[cfg(feature = "decode")]
+fn read_varint<R: Read>(reader: &mut R) -> io::Result<Option<u64>> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let mut byte = [0];
+        reader.read_exact(&mut byte)?;
+        value |= ((byte[0] & 0x7f) as u64) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(Some(value));
+        }
+    }
+    Ok(None)
+}
+
+/// An entry of an archive being read by an [`ArchiveStream`]. Reading it reads the entry's
+/// compressed data, as [`huff`](crate::huff) produced it, and fails with
+/// [`ChecksumMismatch`](Error::ChecksumMismatch) at the end of the data if it was damaged.
+///
+/// In archives written with [`ArchiveWriter::set_deduplicate`], an entry whose data is the same
+/// as an earlier entry's has none of its own in the stream. Reading it fails with
+/// [`DuplicateData`](Error::DuplicateData), and [`duplicate_of`](StreamEntry::duplicate_of) names
+/// the entry whose value to use.
+#[cfg(feature = "decode")]
+pub struct StreamEntry<'a, R> {
+    stream: &'a mut ArchiveStream<R>,
+    name: String,
+    len: u64,
+    checksum: u32,
+    duplicate_of: Option<String>,
+    hasher: crc32fast::Hasher,
+}
+
+#[cfg(feature = "decode")]
+impl<R: Read> StreamEntry<'_, R> {
+    /// The name the entry was added under
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The length of the entry's compressed data
+    pub fn compressed_len(&self) -> u64 {
+        self.len
+    }
+
+    /// The checksum of the entry's compressed data
+    pub fn checksum(&self) -> u32 {
+        self.checksum
+    }
+
+    /// The name of the earlier entry whose data this entry shares, if it does
+    pub fn duplicate_of(&self) -> Option<&str> {
+        self.duplicate_of.as_deref()
+    }
+
+    /// Read, check and decompress the entry
+    pub fn value<T>(mut self) -> Result<T, crate::Error>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        let mut data = Vec::new();
+        self.read_to_end(&mut data)?;
+        puff(&data)
+    }
+}
+
+#[cfg(feature = "decode")]
+impl<R: Read> Read for StreamEntry<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(original) = &self.duplicate_of {
+            return Err(crate::Error::from(Error::DuplicateData(original.clone())).into());
+        }
+        let len = buf
+            .len()
+            .min(self.stream.remaining.try_into().unwrap_or(usize::MAX));
+        if len == 0 {
+            if self.stream.remaining == 0 && self.hasher.clone().finalize() != self.checksum {
+                let error = Error::ChecksumMismatch(self.name.clone());
+                return Err(crate::Error::from(error).into());
+            }
+            return Ok(0);
+        }
+        let read = self.stream.reader.read(&mut buf[..len])?;
+        if read == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        self.hasher.update(&buf[..read]);
+        self.stream.remaining -= read as u64;
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_archive() -> Vec<u8> {
+        let mut writer = ArchiveWriter::new(Vec::new()).unwrap();
+        writer.add("greeting", &"Hello, world!").unwrap();
+        writer.add("answer", &42u32).unwrap();
+        writer.add("list", &vec![1u8, 2, 3, 4, 5]).unwrap();
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn roundtrip() {
+        let bytes = sample_archive();
+        let reader = ArchiveReader::new(&bytes).unwrap();
+
+        assert_eq!(reader.len(), 3);
+        assert_eq!(
+            reader.names().collect::<Vec<_>>(),
+            vec!["greeting", "answer", "list"]
+        );
+        assert_eq!(reader.get::<u32>("answer").unwrap(), 42);
+        assert_eq!(reader.get::<String>("greeting").unwrap(), "Hello, world!");
+        assert_eq!(reader.get::<Vec<u8>>("list").unwrap(), vec![1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn append() {
+        let mut file = std::io::Cursor::new(sample_archive());
+        let original_len = file.get_ref().len();
+
+        let mut writer = ArchiveWriter::append(&mut file).unwrap();
+        writer.add("appended", &"more data").unwrap();
+        assert!(matches!(
+            writer.add("answer", &0u32),
+            Err(crate::Error::Archive(Error::DuplicateEntry(_)))
+        ));
+        writer.finish().unwrap();
+
+        let bytes = file.into_inner();
+        assert!(bytes.len() > original_len);
+        let reader = ArchiveReader::new(&bytes).unwrap();
+        assert_eq!(
+            reader.names().collect::<Vec<_>>(),
+            vec!["greeting", "answer", "list", "appended"]
+        );
+        assert_eq!(reader.get::<u32>("answer").unwrap(), 42);
+        assert_eq!(reader.get::<String>("appended").unwrap(), "more data");
+    }
+
+    #[test]
+    fn stream() {
+        let mut file = std::io::Cursor::new(sample_archive());
+        let mut writer = ArchiveWriter::append(&mut file).unwrap();
+        writer.add("appended", &"more data").unwrap();
+        writer.finish().unwrap();
+        let bytes = file.into_inner();
+
+        let mut stream = ArchiveStream::new(bytes.as_slice()).unwrap();
+        let entry = stream.next_entry().unwrap().unwrap();
+        assert_eq!(entry.name(), "greeting");
+        assert_eq!(entry.value::<String>().unwrap(), "Hello, world!");
+        // Entries that aren't read are skipped
+        assert_eq!(stream.next_entry().unwrap().unwrap().name(), "answer");
+        let mut entry = stream.next_entry().unwrap().unwrap();
+        assert_eq!(entry.name(), "list");
+        let mut first = [0; 2];
+        entry.read_exact(&mut first).unwrap();
+        let entry = stream.next_entry().unwrap().unwrap();
+        assert_eq!(entry.name(), "appended");
+        let mut compressed = Vec::new();
+        let len = entry.compressed_len();
+        std::io::copy(&mut { entry }, &mut compressed).unwrap();
+        assert_eq!(compressed.len() as u64, len);
+        assert_eq!(puff::<String>(&compressed).unwrap(), "more data");
+        assert!(stream.next_entry().unwrap().is_none());
+        assert!(stream.next_entry().unwrap().is_none());
+
+        let empty = ArchiveWriter::new(Vec::new()).unwrap().finish().unwrap();
+        let mut stream = ArchiveStream::new(empty.as_slice()).unwrap();
+        assert!(stream.next_entry().unwrap().is_none());
+    }
+
+    #[test]
+    fn duplicates() {
+        let defaults: Vec<u64> = (0..1000).collect();
+        let write = |deduplicate| {
+            let mut writer = ArchiveWriter::new(Vec::new()).unwrap();
+            writer.set_deduplicate(deduplicate);
+            writer.add("first", &defaults).unwrap();
+            writer.add("other", &"something else").unwrap();
+            for i in 0..50 {
+                writer.add(format!("copy {i}"), &defaults).unwrap();
+            }
+            writer.finish().unwrap()
+        };
+        let compressed_len = huff(&defaults).unwrap().len();
+
+        // Without deduplication, which is the default, every entry streams on its own
+        let bytes = write(false);
+        assert!(bytes.len() > 51 * compressed_len);
+        let mut stream = ArchiveStream::new(bytes.as_slice()).unwrap();
+        let mut names = 0;
+        while let Some(entry) = stream.next_entry().unwrap() {
+            assert_eq!(entry.duplicate_of(), None);
+            if entry.name() != "other" {
+                assert_eq!(entry.value::<Vec<u64>>().unwrap(), defaults);
+            }
+            names += 1;
+        }
+        assert_eq!(names, 52);
+
+        let bytes = write(true);
+        // The data is stored once, and each copy only takes a few bytes besides its table of
+        // contents entry
+        assert!(bytes.len() < compressed_len + 50 * 50);
+
+        let reader = ArchiveReader::new(&bytes).unwrap();
+        assert_eq!(reader.len(), 52);
+        assert_eq!(reader.get::<Vec<u64>>("copy 49").unwrap(), defaults);
+        assert_eq!(reader.get::<String>("other").unwrap(), "something else");
+
+        let mut stream = ArchiveStream::new(bytes.as_slice()).unwrap();
+        let first = stream.next_entry().unwrap().unwrap();
+        assert_eq!(first.duplicate_of(), None);
+        assert_eq!(first.value::<Vec<u64>>().unwrap(), defaults);
+        assert_eq!(stream.next_entry().unwrap().unwrap().duplicate_of(), None);
+        let copy = stream.next_entry().unwrap().unwrap();
+        assert_eq!(
+            (copy.name(), copy.duplicate_of()),
+            ("copy 0", Some("first"))
+        );
+        assert_eq!(copy.compressed_len(), compressed_len as u64);
+        assert_eq!(
+            copy.value::<Vec<u64>>(),
+            Err(crate::Error::Archive(Error::DuplicateData(
+                "first".to_owned()
+            )))
+        );
+        let mut names = 3;
+        while let Some(entry) = stream.next_entry().unwrap() {
+            assert_eq!(entry.duplicate_of(), Some("first"));
+            names += 1;
+        }
+        assert_eq!(names, 52);
+    }
+
+    #[test]
+    fn stream_errors() {
+        let mut bytes = sample_archive();
+        let offset = ArchiveReader::new(&bytes).unwrap().toc.entries[0].offset as usize;
+        bytes[offset + 1] ^= 0xff;
+        let mut stream = ArchiveStream::new(bytes.as_slice()).unwrap();
+        assert_eq!(
+            stream.next_entry().unwrap().unwrap().value::<String>(),
+            Err(crate::Error::Archive(Error::ChecksumMismatch(
+                "greeting".to_owned()
+            )))
+        );
+        assert_eq!(stream.next_entry().unwrap().unwrap().value::<u32>(), Ok(42));
+
+        let bytes = sample_archive();
+        assert_eq!(
+            ArchiveStream::new(&bytes[..3]).err(),
+            Some(crate::Error::Archive(Error::Truncated))
+        );
+        let mut stream = ArchiveStream::new(&bytes[..HEADER_SIZE + 4]).unwrap();
+        assert!(stream.next_entry().err().unwrap().is_truncation());
+    }
+
+    /// An archive as version 1 of the format wrote it, without entry headers
+    fn version_1_archive() -> Vec<u8> {
+        let compressed = huff(&42u32).unwrap();
+        let toc = Toc {
+            metadata: BTreeMap::new(),
+            entries: vec![TocEntry {
+                name: "answer".to_owned(),
+                offset: HEADER_SIZE as u64,
+                len: compressed.len() as u64,
+                checksum: crc32fast::hash(&compressed),
+            }],
+        };
+        let mut bytes = [&MAGIC[..], &[1], &compressed].concat();
+        let toc_offset = bytes.len() as u64;
+        bytes.extend(bincode::serialize(&toc).unwrap());
+        bytes.extend(toc_offset.to_le_bytes());
+        bytes.extend(MAGIC);
+        bytes
+    }
+
+    #[test]
+    fn version_1() {
+        let mut file = std::io::Cursor::new(version_1_archive());
+        assert_eq!(
+            ArchiveReader::new(file.get_ref())
+                .unwrap()
+                .get::<u32>("answer"),
+            Ok(42)
+        );
+        assert_eq!(
+            ArchiveStream::new(file.get_ref().as_slice()).err(),
+            Some(crate::Error::Archive(Error::NotStreamable))
+        );
+
+        // Appending keeps the old layout, which has no repeats
+        let mut writer = ArchiveWriter::append(&mut file).unwrap();
+        writer.add("appended", &7u8).unwrap();
+        writer.add("again", &7u8).unwrap();
+        writer.finish().unwrap();
+        let bytes = file.into_inner();
+        assert_eq!(bytes[MAGIC.len()], 1);
+        let reader = ArchiveReader::new(&bytes).unwrap();
+        assert_eq!(reader.get::<u32>("answer"), Ok(42));
+        assert_eq!(reader.get::<u8>("appended"), Ok(7));
+        assert_eq!(reader.get::<u8>("again"), Ok(7));
+    }
+
+    #[test]
+    fn append_preserves_existing_entries() {
+        let original = sample_archive();
+        let data_end = {
+            let reader = ArchiveReader::new(&original).unwrap();
+            let last = reader.toc.entries.last().unwrap();
+            (last.offset + last.len) as usize
+        };
+
+        let mut file = std::io::Cursor::new(original.clone());
+        let mut writer = ArchiveWriter::append(&mut file).unwrap();
+        writer.add("one", &1u8).unwrap();
+        writer.add("two", &2u8).unwrap();
+        writer.finish().unwrap();
+
+        assert_eq!(file.get_ref()[..data_end], original[..data_end]);
+    }
+
+    #[test]
+    fn metadata() {
+        let mut writer = ArchiveWriter::new(Vec::new()).unwrap();
+        writer.set_metadata("created_at", "2024-01-01T00:00:00Z");
+        writer.set_metadata("comment", "nightly export, which has a fairly long comment");
+        writer.add("answer", &42u32).unwrap();
+        let mut file = std::io::Cursor::new(writer.finish().unwrap());
+
+        let reader = ArchiveReader::new(file.get_ref()).unwrap();
+        assert_eq!(
+            reader.metadata().get("created_at").map(String::as_str),
+            Some("2024-01-01T00:00:00Z")
+        );
+        assert_eq!(reader.metadata().len(), 2);
+
+        // Shrinking the metadata while appending leaves padding before the footer
+        let mut writer = ArchiveWriter::append(&mut file).unwrap();
+        writer.set_metadata("comment", "short");
+        writer.finish().unwrap();
+
+        let reader = ArchiveReader::new(file.get_ref()).unwrap();
+        assert_eq!(
+            reader.metadata().get("comment").map(String::as_str),
+            Some("short")
+        );
+        assert_eq!(reader.get::<u32>("answer").unwrap(), 42);
+    }
+
+    #[test]
+    fn empty_archive() {
+        let bytes = ArchiveWriter::new(Vec::new()).unwrap().finish().unwrap();
+        let reader = ArchiveReader::new(&bytes).unwrap();
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn missing_and_duplicate_entries() {
+        let bytes = sample_archive();
+        let reader = ArchiveReader::new(&bytes).unwrap();
+        assert!(matches!(
+            reader.get::<u32>("nope"),
+            Err(crate::Error::Archive(Error::EntryNotFound(name))) if name == "nope"
+        ));
+
+        let mut writer = ArchiveWriter::new(Vec::new()).unwrap();
+        writer.add("a", &1u8).unwrap();
+        assert!(matches!(
+            writer.add("a", &2u8),
+            Err(crate::Error::Archive(Error::DuplicateEntry(_)))
+        ));
+    }
+
+    #[test]
+    fn corrupted_entry() {
+        let mut bytes = sample_archive();
+        let offset = ArchiveReader::new(&bytes).unwrap().toc.entries[0].offset as usize;
+        bytes[offset + 1] ^= 0xff;
+
+        let reader = ArchiveReader::new(&bytes).unwrap();
+        assert!(matches!(
+            reader.get::<String>("greeting"),
+            Err(crate::Error::Archive(Error::ChecksumMismatch(_)))
+        ));
+        // Other entries are unaffected
+        assert_eq!(reader.get::<u32>("answer").unwrap(), 42);
+    }
+
+    #[test]
+    fn invalid_archives() {
+        let bytes = sample_archive();
+
+        assert!(matches!(
+            ArchiveReader::new(&bytes[..HEADER_SIZE]),
+            Err(crate::Error::Archive(Error::Truncated))
+        ));
+        assert!(matches!(
+            ArchiveReader::new(&bytes[..bytes.len() - 1]),
+            Err(crate::Error::Archive(Error::BadMagic))
+        ));
+
+        let mut future = bytes.clone();
+        future[MAGIC.len()] = VERSION + 1;
+        assert!(matches!(
+            ArchiveReader::new(&future),
+            Err(crate::Error::Archive(Error::UnsupportedVersion(_)))
+        ));
+
+        let mut bad_offset = bytes.clone();
+        let footer_start = bad_offset.len() - FOOTER_SIZE;
+        bad_offset[footer_start..footer_start + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(matches!(
+            ArchiveReader::new(&bad_offset),
+            Err(crate::Error::Archive(Error::CorruptTableOfContents))
+        ));
+    }
+}
@@ -0,0 +1,263 @@
+//! Compression of Apache Arrow arrays, one buffer at a time. Every buffer of an array, including
+//! its validity bitmap, its offsets and the buffers of its children, is compressed on its own, so
+//! the array's layout is kept exactly and the buffers can also be used as the bodies of a custom
+//! compression for Arrow IPC.
+
+use arrow_buffer::Buffer;
+#[cfg(feature = "decode")]
+use arrow_buffer::{BooleanBuffer, NullBuffer};
+use arrow_data::ArrayData;
+use arrow_schema::{ArrowError, DataType};
+
+use crate::frame;
+#[cfg(feature = "encode")]
+use crate::options::EncodeOptions;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The decompressed buffers don't make a valid array of the recorded type
+    Invalid(ArrowError),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Invalid(error) => write!(f, "the buffers don't make a valid array: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Arrow's errors can't be compared, so they're equal when their messages are
+impl PartialEq for Error {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Error::Invalid(a), Error::Invalid(b)) => a.to_string() == b.to_string(),
+        }
+    }
+}
+
+/// Compress the bytes of a single Arrow buffer
+#[cfg(feature = "encode")]
+pub fn compress_buffer(buffer: &Buffer) -> Result<Vec<u8>, crate::Error> {
+    frame::encode(
+        buffer.as_slice(),
+        &frame::Header::default(),
+        &EncodeOptions::default(),
+    )
+}
+
+/// Decompress a buffer produced by [`compress_buffer`]
+#[cfg(feature = "decode")]
+pub fn decompress_buffer(bytes: &[u8]) -> Result<Buffer, crate::Error> {
+    Ok(Buffer::from_vec(frame::decode(bytes)?.payload))
+}
+
+/// An Arrow array with each of its buffers compressed, made by [`compress_array`]
+// The layout is only read back when decompressing
+#[cfg_attr(not(feature = "decode"), allow(dead_code))]
+#[derive(Debug, Clone)]
+pub struct CompressedArray {
+    data_type: DataType,
+    len: usize,
+    offset: usize,
+    nulls: Option<CompressedNulls>,
+    buffers: Vec<Vec<u8>>,
+    children: Vec<CompressedArray>,
+}
+
+/// A validity bitmap, which can start partway through its buffer when the array is a slice
+#[cfg_attr(not(feature = "decode"), allow(dead_code))]
+#[derive(Debug, Clone)]
+struct CompressedNulls {
+    buffer: Vec<u8>,
+    offset: usize,
+    len: usize,
+}
+
+impl CompressedArray {
+    pub fn data_type(&self) -> &DataType {
+        &self.data_type
+    }
+
+    /// The number of elements in the array
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The total size of the compressed buffers, including those of the children
+    pub fn compressed_len(&self) -> usize {
+        self.nulls.as_ref().map_or(0, |nulls| nulls.buffer.len())
+            + self.buffers.iter().map(Vec::len).sum::<usize>()
+            + self
+                .children
+                .iter()
+                .map(CompressedArray::compressed_len)
+                .sum::<usize>()
+    }
+}
+
+/// Compress every buffer of `array`
+#[cfg(feature = "encode")]
+pub fn compress_array(array: &ArrayData) -> Result<CompressedArray, crate::Error> {
+    let nulls = array
+        .nulls()
+        .map(|nulls| {
+            let bits = nulls.inner();
+            Ok::<_, crate::Error>(CompressedNulls {
+                buffer: compress_buffer(bits.inner())?,
+                offset: bits.offset(),
+                len: bits.len(),
+            })
+        })
+        .transpose()?;
+    Ok(CompressedArray {
+        data_type: array.data_type().clone(),
+        len: array.len(),
+        offset: array.offset(),
+        nulls,
+        buffers: array
+            .buffers()
+            .iter()
+            .map(compress_buffer)
+            .collect::<Result<_, _>>()?,
+        children: array
+            .child_data()
+            .iter()
+            .map(compress_array)
+            .collect::<Result<_, _>>()?,
+    })
+}
+
+/// Decompress an array produced by [`compress_array`], checking that it's valid
+#[cfg(feature = "decode")]
+pub fn decompress_array(compressed: &CompressedArray) -> Result<ArrayData, crate::Error> {
+    let nulls = compressed
+        .nulls
+        .as_ref()
+        .map(|nulls| {
+            let buffer = decompress_buffer(&nulls.buffer)?;
+            if (nulls.offset + nulls.len).div_ceil(8) > buffer.len() {
+                return Err(Error::Invalid(ArrowError::InvalidArgumentError(
+                    "validity bitmap is too short".to_owned(),
+                ))
+                .into());
+            }
+            Ok::<_, crate::Error>(NullBuffer::new(BooleanBuffer::new(
+                buffer,
+                nulls.offset,
+                nulls.len,
+            )))
+        })
+        .transpose()?;
+    let buffers = compressed
+        .buffers
+        .iter()
+        .map(|buffer| decompress_buffer(buffer))
+        .collect::<Result<_, _>>()?;
+    let children = compressed
+        .children
+        .iter()
+        .map(decompress_array)
+        .collect::<Result<_, _>>()?;
+
+    ArrayData::builder(compressed.data_type.clone())
+        .len(compressed.len)
+        .offset(compressed.offset)
+        .nulls(nulls)
+        .buffers(buffers)
+        .child_data(children)
+        .build()
+        .map_err(|error| Error::Invalid(error).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_schema::Field;
+
+    use super::*;
+
+    fn validity(bits: &[bool]) -> NullBuffer {
+        NullBuffer::new(BooleanBuffer::from(bits))
+    }
+
+    #[test]
+    fn buffer_roundtrip() {
+        let buffer = Buffer::from_slice_ref([3i64, 1, 4, 1, 5, 9, 2, 6].repeat(20));
+        let compressed = compress_buffer(&buffer).unwrap();
+        assert!(compressed.len() < buffer.len());
+        assert_eq!(decompress_buffer(&compressed).unwrap(), buffer);
+    }
+
+    #[test]
+    fn strings_with_nulls() {
+        let values = b"redgreenredbluered";
+        let offsets = [0i32, 3, 8, 8, 11, 15, 18];
+        let array = ArrayData::builder(DataType::Utf8)
+            .len(6)
+            .add_buffer(Buffer::from_slice_ref(offsets))
+            .add_buffer(Buffer::from_slice_ref(values))
+            .nulls(Some(validity(&[true, true, false, true, true, true])))
+            .build()
+            .unwrap();
+
+        let compressed = compress_array(&array).unwrap();
+        assert_eq!(compressed.data_type(), &DataType::Utf8);
+        assert_eq!(compressed.len(), 6);
+        let decompressed = decompress_array(&compressed).unwrap();
+        assert_eq!(decompressed, array);
+        assert_eq!(decompressed.null_count(), 1);
+
+        // Slices keep their offset into the buffers
+        let slice = array.slice(2, 3);
+        assert_eq!(
+            decompress_array(&compress_array(&slice).unwrap()).unwrap(),
+            slice
+        );
+    }
+
+    #[test]
+    fn nested() {
+        let values = ArrayData::builder(DataType::Int32)
+            .len(5)
+            .add_buffer(Buffer::from_slice_ref([1i32, 2, 3, 4, 5]))
+            .build()
+            .unwrap();
+        let list = ArrayData::builder(DataType::List(Arc::new(Field::new(
+            "item",
+            DataType::Int32,
+            false,
+        ))))
+        .len(3)
+        .add_buffer(Buffer::from_slice_ref([0i32, 2, 2, 5]))
+        .child_data(vec![values])
+        .nulls(Some(validity(&[true, false, true])))
+        .build()
+        .unwrap();
+
+        let compressed = compress_array(&list).unwrap();
+        assert_eq!(decompress_array(&compressed).unwrap(), list);
+    }
+
+    #[test]
+    fn invalid() {
+        let array = ArrayData::builder(DataType::Int64)
+            .len(4)
+            .add_buffer(Buffer::from_slice_ref([1i64, 2, 3, 4]))
+            .build()
+            .unwrap();
+        let mut compressed = compress_array(&array).unwrap();
+        compressed.len = 10;
+        assert!(matches!(
+            decompress_array(&compressed),
+            Err(crate::Error::Arrow(Error::Invalid(_)))
+        ));
+    }
+}
@@ -0,0 +1,298 @@
+//! Adapters for the `futures-io` traits, which work with any async runtime
+
+use std::io;
+#[cfg(feature = "encode")]
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+#[cfg(feature = "decode")]
+use futures_io::AsyncRead;
+#[cfg(feature = "encode")]
+use futures_io::AsyncWrite;
+#[cfg(feature = "encode")]
+use futures_sink::Sink;
+
+#[cfg(feature = "decode")]
+use crate::DecodedReader;
+#[cfg(feature = "encode")]
+use crate::block::BlockWriter;
+#[cfg(feature = "encode")]
+use crate::transport;
+
+/// Decoding happens in memory, so reads are always ready
+#[cfg(feature = "decode")]
+impl AsyncRead for DecodedReader<'_> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Poll::Ready(io::Read::read(self.get_mut(), buf))
+    }
+}
+
+/// The async counterpart of [`BlockWriter`], writing a block stream to an [`AsyncWrite`].
+/// Blocks are compressed as they fill up and written out as the underlying writer accepts them.
+/// Closing the writer writes the final block and the end marker.
+#[cfg(feature = "encode")]
+pub struct AsyncBlockWriter<W> {
+    writer: W,
+    /// Compresses into a buffer that is drained into `writer`. `None` once closed.
+    blocks: Option<BlockWriter<Vec<u8>>>,
+    /// Output waiting to be written to `writer`
+    pending: Vec<u8>,
+    /// How much of `pending` has been written
+    written: usize,
+}
+
+#[cfg(feature = "encode")]
+impl<W: AsyncWrite + Unpin> AsyncBlockWriter<W> {
+    pub fn new(writer: W, block_size: usize) -> Result<Self, crate::Error> {
+        Ok(Self {
+            writer,
+            blocks: Some(BlockWriter::new(Vec::new(), block_size)?),
+            pending: Vec::new(),
+            written: 0,
+        })
+    }
+
+    /// The underlying writer
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    /// Write out everything that has been compressed so far
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if let Some(blocks) = &mut self.blocks {
+            self.pending.append(blocks.get_mut());
+        }
+        poll_write_pending(&mut self.writer, cx, &mut self.pending, &mut self.written)
+    }
+}
+
+/// Write all of `pending` from `written` onwards, then clear it
+#[cfg(feature = "encode")]
+fn poll_write_pending<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    cx: &mut Context<'_>,
+    pending: &mut Vec<u8>,
+    written: &mut usize,
+) -> Poll<io::Result<()>> {
+    while *written < pending.len() {
+        let len = std::task::ready!(Pin::new(&mut *writer).poll_write(cx, &pending[*written..]))?;
+        if len == 0 {
+            return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+        }
+        *written += len;
+    }
+    pending.clear();
+    *written = 0;
+    Poll::Ready(Ok(()))
+}
+
+#[cfg(feature = "encode")]
+impl<W: AsyncWrite + Unpin> AsyncWrite for AsyncBlockWriter<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        std::task::ready!(this.poll_drain(cx))?;
+        let Some(blocks) = &mut this.blocks else {
+            return Poll::Ready(Err(io::Error::other("write after close")));
+        };
+        Poll::Ready(io::Write::write(blocks, buf))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        std::task::ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.writer).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if let Some(blocks) = this.blocks.take() {
+            let mut rest = blocks.finish().map_err(io::Error::from)?;
+            this.pending.append(&mut rest);
+        }
+        std::task::ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.writer).poll_close(cx)
+    }
+}
+
+/// A [`Sink`] of values, which compresses each value and writes it to an [`AsyncWrite`] as a
+/// length-prefixed frame, like [`write_frame`](crate::write_frame). The frames can be read back
+/// with [`read_frame`](crate::read_frame).
+///
+/// At most one compressed value is held waiting for the writer. The sink isn't ready for another
+/// until that value has been written, so a slow writer holds back whatever is sending values.
+#[cfg(feature = "encode")]
+pub struct HuffSink<T, W> {
+    writer: W,
+    /// The frame waiting to be written to `writer`
+    pending: Vec<u8>,
+    /// How much of `pending` has been written
+    written: usize,
+    _type: PhantomData<fn(&T)>,
+}
+
+#[cfg(feature = "encode")]
+impl<T, W: AsyncWrite + Unpin> HuffSink<T, W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            pending: Vec::new(),
+            written: 0,
+            _type: PhantomData,
+        }
+    }
+
+    /// The underlying writer. Any frame not yet written is dropped.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+#[cfg(feature = "encode")]
+impl<T: serde::Serialize, W: AsyncWrite + Unpin> Sink<T> for HuffSink<T, W> {
+    type Error = crate::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        poll_write_pending(&mut this.writer, cx, &mut this.pending, &mut this.written)
+            .map_err(Into::into)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: T) -> Result<(), Self::Error> {
+        let this = self.get_mut();
+        debug_assert!(this.pending.is_empty(), "start_send without poll_ready");
+        transport::write_prefixed(&mut this.pending, &crate::huff(&item)?)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        std::task::ready!(poll_write_pending(
+            &mut this.writer,
+            cx,
+            &mut this.pending,
+            &mut this.written
+        ))?;
+        Pin::new(&mut this.writer)
+            .poll_flush(cx)
+            .map_err(Into::into)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        std::task::ready!(poll_write_pending(
+            &mut this.writer,
+            cx,
+            &mut this.pending,
+            &mut this.written
+        ))?;
+        Pin::new(&mut this.writer)
+            .poll_close(cx)
+            .map_err(Into::into)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::SinkExt;
+    use futures::executor::block_on;
+    use futures::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    #[test]
+    fn read() {
+        let message = "read through futures-io".repeat(10);
+        let compressed = crate::huff(&message).unwrap();
+
+        let mut reader = DecodedReader::new(&compressed).unwrap();
+        let mut decoded = Vec::new();
+        block_on(reader.read_to_end(&mut decoded)).unwrap();
+        assert_eq!(decoded, bincode::serialize(&message).unwrap());
+    }
+
+    #[test]
+    fn write_blocks() {
+        let data: Vec<u8> = (0..20_000u32).map(|i| (i % 7) as u8).collect();
+
+        let mut writer = AsyncBlockWriter::new(Vec::new(), 4096).unwrap();
+        block_on(async {
+            for chunk in data.chunks(1000) {
+                writer.write_all(chunk).await.unwrap();
+            }
+            writer.close().await.unwrap();
+        });
+        let compressed = writer.into_inner();
+        assert_eq!(crate::decompress_blocks(&compressed).unwrap(), data);
+    }
+
+    /// Accepts at most a few bytes per write, and is only ready every other time it's polled,
+    /// like a congested socket
+    struct Congested {
+        written: Vec<u8>,
+        ready: bool,
+    }
+
+    impl AsyncWrite for Congested {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            let this = self.get_mut();
+            this.ready = !this.ready;
+            if !this.ready {
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            let len = buf.len().min(3);
+            this.written.extend_from_slice(&buf[..len]);
+            Poll::Ready(Ok(len))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[test]
+    fn sink() {
+        let values: Vec<Vec<String>> = (0..20)
+            .map(|i| (0..i).map(|j| format!("value {j}")).collect())
+            .collect();
+        let congested = Congested {
+            written: Vec::new(),
+            ready: false,
+        };
+        let mut sink = HuffSink::new(congested);
+        block_on(async {
+            for value in &values {
+                sink.send(value.clone()).await.unwrap();
+            }
+            sink.close().await.unwrap();
+        });
+
+        let written = sink.into_inner().written;
+        let mut reader = written.as_slice();
+        for value in &values {
+            let read: Vec<String> = crate::read_frame(&mut reader).unwrap().unwrap();
+            assert_eq!(&read, value);
+        }
+        assert!(
+            crate::read_frame::<_, Vec<String>>(&mut reader)
+                .unwrap()
+                .is_none()
+        );
+    }
+}
@@ -0,0 +1,157 @@
+//! Serde formats other than bincode for the values in frames, so compressed values can be read by
+//! systems that already speak postcard or MessagePack once they're decompressed
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The backend couldn't serialize the value, with the backend's description of why
+    Serialize(String),
+    /// The decompressed bytes aren't a value of the requested type in the backend's format, with
+    /// the backend's description of why
+    Deserialize(String),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Serialize(reason) => {
+                write!(f, "the backend couldn't serialize the value: {reason}")
+            }
+            Error::Deserialize(reason) => {
+                write!(f, "the backend couldn't deserialize the value: {reason}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// A serde format that values are serialized with before they're compressed, for
+/// [`huff_with`](crate::huff_with) and [`puff_with`](crate::puff_with). [`Bincode`] is the format
+/// [`huff`](crate::huff) uses. With the `postcard` and `msgpack` features, [`Postcard`] and
+/// [`MessagePack`] are backends too, and other formats can be plugged in by implementing this.
+///
+/// The frame doesn't record the backend, so a value must be decompressed with the backend it was
+/// compressed with.
+pub trait SerializeBackend {
+    fn serialize<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, crate::Error>;
+
+    fn deserialize<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, crate::Error>;
+}
+
+/// Bincode 1 with its default options, as used by [`huff`](crate::huff) and
+/// [`puff`](crate::puff)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Bincode;
+
+impl SerializeBackend for Bincode {
+    fn serialize<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, crate::Error> {
+        Ok(bincode::serialize(value)?)
+    }
+
+    fn deserialize<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, crate::Error> {
+        Ok(bincode::deserialize(bytes)?)
+    }
+}
+
+/// [Postcard](https://postcard.jamesmunns.com), a compact format common on embedded devices
+#[cfg(feature = "postcard")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Postcard;
+
+#[cfg(feature = "postcard")]
+impl SerializeBackend for Postcard {
+    fn serialize<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, crate::Error> {
+        postcard::to_allocvec(value).map_err(|error| Error::Serialize(error.to_string()).into())
+    }
+
+    fn deserialize<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, crate::Error> {
+        postcard::from_bytes(bytes).map_err(|error| Error::Deserialize(error.to_string()).into())
+    }
+}
+
+/// MessagePack, with structs written as maps from field names to values, which is what other
+/// MessagePack libraries expect
+#[cfg(feature = "msgpack")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MessagePack;
+
+#[cfg(feature = "msgpack")]
+impl SerializeBackend for MessagePack {
+    fn serialize<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, crate::Error> {
+        rmp_serde::to_vec_named(value).map_err(|error| Error::Serialize(error.to_string()).into())
+    }
+
+    fn deserialize<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, crate::Error> {
+        rmp_serde::from_slice(bytes).map_err(|error| Error::Deserialize(error.to_string()).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::{huff_with, puff_with};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Reading {
+        sensor: String,
+        values: Vec<u16>,
+    }
+
+    fn reading() -> Reading {
+        Reading {
+            sensor: "greenhouse-3".to_owned(),
+            values: (0..200).map(|i| 400 + i % 7).collect(),
+        }
+    }
+
+    #[test]
+    fn bincode() {
+        let compressed = huff_with::<Bincode, _>(&reading()).unwrap();
+        assert_eq!(compressed, crate::huff(&reading()).unwrap());
+        assert_eq!(
+            puff_with::<Bincode, Reading>(&compressed).unwrap(),
+            reading()
+        );
+    }
+
+    #[cfg(feature = "postcard")]
+    #[test]
+    fn postcard() {
+        let compressed = huff_with::<Postcard, _>(&reading()).unwrap();
+        assert_eq!(
+            crate::decompress(&compressed).unwrap(),
+            postcard::to_allocvec(&reading()).unwrap()
+        );
+        assert_eq!(
+            puff_with::<Postcard, Reading>(&compressed).unwrap(),
+            reading()
+        );
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[test]
+    fn msgpack() {
+        let compressed = huff_with::<MessagePack, _>(&reading()).unwrap();
+        let decompressed = crate::decompress(&compressed).unwrap();
+        assert_eq!(
+            rmp_serde::from_slice::<Reading>(&decompressed).unwrap(),
+            reading()
+        );
+        assert_eq!(
+            puff_with::<MessagePack, Reading>(&compressed).unwrap(),
+            reading()
+        );
+
+        let error = puff_with::<MessagePack, Reading>(&crate::compress(&[0xc1]).unwrap());
+        assert!(matches!(
+            error,
+            Err(crate::Error::Backend(Error::Deserialize(_)))
+        ));
+    }
+}
@@ -0,0 +1,421 @@
+//! Compress, decompress and inspect files from the command line, for debugging payloads and
+//! checking output from other implementations of the format
+
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::process::ExitCode;
+use std::sync::atomic::AtomicBool;
+use std::time::Duration;
+
+use huffnpuff::{
+    BlockError, BlockWriter, Codebook, DEFAULT_BLOCK_SIZE, DirWatcher, MessageTree, PuffReader,
+    StreamLayout, WatchOptions,
+};
+
+const USAGE: &str = "\
+usage: huffnpuff compress [--block-size N | --format csv] INPUT [-o OUTPUT]
+       huffnpuff compress --resume [--block-size N] INPUT -o OUTPUT
+       huffnpuff decompress [--format csv] INPUT [-o OUTPUT]
+       huffnpuff inspect INPUT
+       huffnpuff inspect --codebook CODEBOOK
+       huffnpuff watch DIR --out OUTDIR [--interval SECS] [--debounce SECS]
+                       [--exclude GLOB]... [--block-size N]
+
+Reads standard input when INPUT is -, and writes standard output without -o.
+compress writes a single frame, or a block stream with --block-size.
+decompress reads either, and inspect shows how either is laid out.
+--format csv compresses a CSV file column by column, and decompresses it again.
+--resume continues a block stream whose writing to OUTPUT was interrupted, from
+its last intact block, with the block size it was started with.
+watch compresses each file under DIR into OUTDIR as it appears or changes, once
+it has gone --debounce seconds without changing, and leaves out files matching
+any --exclude glob. It keeps going until it's interrupted.";
+
+/// The parsed command line
+struct Args {
+    command: String,
+    input: String,
+    output: Option<String>,
+    block_size: Option<usize>,
+    codebook: bool,
+    /// Whether the input is compressed, or decompressed, as a CSV file
+    csv: bool,
+    /// Whether to continue a block stream already partly written to the output
+    resume: bool,
+    /// How often to scan the watched directory
+    interval: Option<Duration>,
+    /// How long a watched file must go unchanged before it's compressed
+    debounce: Option<Duration>,
+    /// Globs of watched files to leave alone
+    exclude: Vec<String>,
+}
+
+fn parse_seconds(flag: &str, seconds: Option<String>) -> Result<Duration, String> {
+    let seconds = seconds.ok_or_else(|| format!("{flag} needs a number of seconds"))?;
+    seconds
+        .parse()
+        .ok()
+        .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+        .ok_or_else(|| format!("invalid number of seconds {seconds:?}"))
+}
+
+fn parse(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+    let command = args.next().ok_or("missing command")?;
+    if !["compress", "decompress", "inspect", "watch"].contains(&command.as_str()) {
+        return Err(format!("unknown command {command:?}"));
+    }
+    let mut input = None;
+    let mut output = None;
+    let mut block_size = None;
+    let mut codebook = false;
+    let mut csv = false;
+    let mut resume = false;
+    let mut interval = None;
+    let mut debounce = None;
+    let mut exclude = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" | "--output" | "--out" => output = Some(args.next().ok_or("-o needs a file")?),
+            "--block-size" => {
+                let size = args.next().ok_or("--block-size needs a size")?;
+                let size = size
+                    .parse()
+                    .map_err(|_| format!("invalid block size {size:?}"))?;
+                block_size = Some(size);
+            }
+            "--codebook" => codebook = true,
+            "--resume" => resume = true,
+            "--interval" => interval = Some(parse_seconds(&arg, args.next())?),
+            "--debounce" => debounce = Some(parse_seconds(&arg, args.next())?),
+            "--exclude" => exclude.push(args.next().ok_or("--exclude needs a glob")?),
+            "--format" => match args.next().ok_or("--format needs a format")?.as_str() {
+                "csv" => csv = true,
+                format => return Err(format!("unknown format {format:?}")),
+            },
+            "-" => input = Some(arg),
+            flag if flag.starts_with('-') => return Err(format!("unknown option {flag}")),
+            _ if input.is_some() => return Err(format!("unexpected argument {arg:?}")),
+            _ => input = Some(arg),
+        }
+    }
+    if csv && (command == "inspect" || block_size.is_some()) {
+        return Err(
+            "--format csv only works with compress and decompress, without --block-size".into(),
+        );
+    }
+    if resume && (command != "compress" || csv || output.is_none()) {
+        return Err("--resume only works with compress, and needs an output file".into());
+    }
+    if command == "watch" && (csv || resume || output.is_none()) {
+        return Err(
+            "watch needs an output directory, and doesn't take --format or --resume".into(),
+        );
+    }
+    if command != "watch" && (interval.is_some() || debounce.is_some() || !exclude.is_empty()) {
+        return Err("--interval, --debounce and --exclude only work with watch".into());
+    }
+    Ok(Args {
+        command,
+        input: input.ok_or("missing input file")?,
+        output,
+        block_size,
+        codebook,
+        csv,
+        resume,
+        interval,
+        debounce,
+        exclude,
+    })
+}
+
+fn read_input(path: &str) -> io::Result<Vec<u8>> {
+    if path == "-" {
+        let mut bytes = Vec::new();
+        io::stdin().read_to_end(&mut bytes)?;
+        return Ok(bytes);
+    }
+    fs::read(path)
+}
+
+fn write_output(path: Option<&str>, bytes: &[u8]) -> io::Result<()> {
+    match path {
+        Some(path) => fs::write(path, bytes),
+        None => io::stdout().write_all(bytes),
+    }
+}
+
+/// Decompress a block stream, or a single frame if the input doesn't start like a block stream
+fn decompress(bytes: &[u8]) -> Result<Vec<u8>, huffnpuff::Error> {
+    match PuffReader::new(bytes) {
+        Ok(mut reader) => {
+            let mut decompressed = Vec::new();
+            reader.read_to_end(&mut decompressed)?;
+            Ok(decompressed)
+        }
+        Err(huffnpuff::Error::Block(BlockError::BadMagic | BlockError::Truncated)) => {
+            huffnpuff::decompress(bytes)
+        }
+        Err(error) => Err(error),
+    }
+}
+
+fn inspect(bytes: &[u8]) -> Result<String, huffnpuff::Error> {
+    let layout = StreamLayout::read(bytes)?;
+    let mut out = match layout.block_size {
+        Some(block_size) => format!(
+            "block stream of {} bytes, {} blocks of {block_size} bytes\n",
+            layout.len,
+            layout.frames.len()
+        ),
+        None => format!("frame of {} bytes\n", layout.len),
+    };
+    let decoded_len = layout.decoded_len();
+    out.push_str(&format!("decoded: {decoded_len} bytes"));
+    if decoded_len > 0 {
+        out.push_str(&format!(
+            ", ratio {:.3}",
+            layout.len as f64 / decoded_len as f64
+        ));
+    }
+    out.push_str(&format!(
+        "\nheaders: {} bytes, trees: {} bits, framing: {} bytes\n",
+        layout.header_len(),
+        layout.tree_bits(),
+        layout.framing_len()
+    ));
+    for (i, frame) in layout.frames.iter().enumerate() {
+        out.push_str(&format!(
+            "frame {i}: {} bytes, header {} bytes, ",
+            frame.len, frame.header_len
+        ));
+        match frame.tree_bits {
+            Some(bits) => out.push_str(&format!("tree {bits} bits, ")),
+            None => out.push_str("no tree, "),
+        }
+        out.push_str(&format!("decoded {} bytes\n", frame.decoded_len));
+    }
+    if layout.block_size.is_none()
+        && let Some(tree) = MessageTree::from_message(bytes)?
+    {
+        out.push_str(&format!("\n{tree}"));
+    }
+    Ok(out)
+}
+
+/// An error's message followed by the messages of the errors that caused it
+fn describe(error: &dyn std::error::Error) -> String {
+    let mut message = error.to_string();
+    let mut source = error.source();
+    while let Some(error) = source {
+        message.push_str(&format!(": {error}"));
+        source = error.source();
+    }
+    message
+}
+
+/// Compress the input into a block stream at `output`, skipping the input the stream's intact
+/// blocks already hold. A missing or empty output is started from scratch.
+fn compress_resumed(input: &str, output: &str, block_size: usize) -> Result<(), String> {
+    let output_error = |error: huffnpuff::Error| format!("{output}: {}", describe(&error));
+    let input_error = |error: io::Error| format!("{input}: {}", describe(&error));
+    let file = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(output)
+        .map_err(|error| format!("{output}: {}", describe(&error)))?;
+    let is_empty = file
+        .metadata()
+        .map_err(|error| format!("{output}: {}", describe(&error)))?
+        .len()
+        == 0;
+    let (mut writer, consumed) = if is_empty {
+        (BlockWriter::new(file, block_size).map_err(output_error)?, 0)
+    } else {
+        BlockWriter::resume(file).map_err(output_error)?
+    };
+
+    let mut reader: Box<dyn Read> = if input == "-" {
+        let mut stdin = io::stdin().lock();
+        let skipped =
+            io::copy(&mut (&mut stdin).take(consumed), &mut io::sink()).map_err(input_error)?;
+        if skipped < consumed {
+            return Err(format!("{input}: shorter than what {output} already holds"));
+        }
+        Box::new(stdin)
+    } else {
+        let mut file = fs::File::open(input).map_err(input_error)?;
+        if file.metadata().map_err(input_error)?.len() < consumed {
+            return Err(format!("{input}: shorter than what {output} already holds"));
+        }
+        file.seek(SeekFrom::Start(consumed)).map_err(input_error)?;
+        Box::new(file)
+    };
+    let mut buffer = vec![0; DEFAULT_BLOCK_SIZE];
+    loop {
+        let len = reader.read(&mut buffer).map_err(input_error)?;
+        if len == 0 {
+            break;
+        }
+        writer
+            .write_all(&buffer[..len])
+            .map_err(|error| format!("{output}: {}", describe(&error)))?;
+    }
+    writer.finish().map_err(output_error)?;
+    Ok(())
+}
+
+/// Compress the files under `dir` into `out` as they change, until the process is interrupted
+fn watch(args: Args) -> Result<(), String> {
+    let defaults = WatchOptions::default();
+    let options = WatchOptions {
+        poll_interval: args.interval.unwrap_or(defaults.poll_interval),
+        debounce: args.debounce.unwrap_or(defaults.debounce),
+        exclude: args.exclude,
+        block_size: args.block_size.unwrap_or(defaults.block_size),
+    };
+    let out = args.output.expect("checked when parsing");
+    let mut watcher = DirWatcher::new(&args.input, &out, options)
+        .map_err(|error| format!("{out}: {}", describe(&error)))?;
+    watcher
+        .run(&AtomicBool::new(false), |path| {
+            println!("{}", path.display())
+        })
+        .map_err(|error| format!("{}: {}", args.input, describe(&error)))
+}
+
+fn run(args: Args) -> Result<(), String> {
+    if args.command == "watch" {
+        return watch(args);
+    }
+    if args.resume {
+        let output = args.output.as_deref().expect("checked when parsing");
+        let block_size = args.block_size.unwrap_or(DEFAULT_BLOCK_SIZE);
+        return compress_resumed(&args.input, output, block_size);
+    }
+    let input =
+        read_input(&args.input).map_err(|error| format!("{}: {}", args.input, describe(&error)))?;
+    let output = args.output.as_deref();
+    let result = match args.command.as_str() {
+        "compress" if args.csv => {
+            huffnpuff::compress_csv(&input).map(|compressed| write_output(output, &compressed))
+        }
+        "compress" => match args.block_size {
+            Some(block_size) => huffnpuff::compress_blocks(&input, block_size),
+            None => huffnpuff::compress(&input),
+        }
+        .map(|compressed| write_output(output, &compressed)),
+        "decompress" if args.csv => huffnpuff::decompress_csv(&input)
+            .map(|decompressed| write_output(output, &decompressed)),
+        "decompress" => decompress(&input).map(|decompressed| write_output(output, &decompressed)),
+        "inspect" if args.codebook => Codebook::from_bytes(&input)
+            .map(|codebook| write_output(output, codebook.to_string().as_bytes())),
+        _ => inspect(&input).map(|report| write_output(output, report.as_bytes())),
+    };
+    result
+        .map_err(|error| format!("{}: {}", args.input, describe(&error)))?
+        .map_err(|error| format!("{}: {}", output.unwrap_or("stdout"), describe(&error)))
+}
+
+fn main() -> ExitCode {
+    let args = match parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(error) => {
+            eprintln!("huffnpuff: {error}\n\n{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+    match run(args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("huffnpuff: {error}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Result<Args, String> {
+        parse(line.split_whitespace().map(str::to_owned))
+    }
+
+    #[test]
+    fn parse_args() {
+        let parsed = args("compress --block-size 4096 in.txt -o out.hnp").unwrap();
+        assert_eq!(parsed.command, "compress");
+        assert_eq!(parsed.input, "in.txt");
+        assert_eq!(parsed.output.as_deref(), Some("out.hnp"));
+        assert_eq!(parsed.block_size, Some(4096));
+
+        assert!(args("inspect --codebook book.bin").unwrap().codebook);
+        assert!(args("compress --format csv data.csv").unwrap().csv);
+        assert!(args("decompress --format csv data.hnp").unwrap().csv);
+        assert!(args("compress --format json data.json").is_err());
+        assert!(args("compress --format csv --block-size 4096 data.csv").is_err());
+        assert!(args("inspect --format csv data.hnp").is_err());
+        assert!(
+            args("compress --resume dump.sql -o dump.hnpb")
+                .unwrap()
+                .resume
+        );
+        assert!(args("compress --resume dump.sql").is_err());
+        assert!(args("decompress --resume dump.hnpb -o dump.sql").is_err());
+
+        let parsed = args("watch logs --out archive --interval 0.5 --debounce 3 --exclude *.tmp --exclude cache/**").unwrap();
+        assert_eq!(parsed.input, "logs");
+        assert_eq!(parsed.output.as_deref(), Some("archive"));
+        assert_eq!(parsed.interval, Some(Duration::from_millis(500)));
+        assert_eq!(parsed.debounce, Some(Duration::from_secs(3)));
+        assert_eq!(parsed.exclude, ["*.tmp", "cache/**"]);
+        assert!(args("watch logs").is_err());
+        assert!(args("watch logs --out archive --debounce soon").is_err());
+        assert!(args("compress --exclude *.tmp in.txt").is_err());
+        assert_eq!(args("decompress -").unwrap().input, "-");
+        assert!(args("compress").is_err());
+        assert!(args("compress a b").is_err());
+        assert!(args("compress --level 3 a").is_err());
+        assert!(args("frob a").is_err());
+    }
+
+    #[test]
+    fn frames_and_block_streams() {
+        let data = b"the command line reads frames and block streams alike. ".repeat(40);
+        let frame = huffnpuff::compress(&data).unwrap();
+        let blocks = huffnpuff::compress_blocks(&data, 500).unwrap();
+        assert_eq!(decompress(&frame).unwrap(), data);
+        assert_eq!(decompress(&blocks).unwrap(), data);
+
+        assert!(inspect(&frame).unwrap().starts_with("frame of"));
+        let report = inspect(&blocks).unwrap();
+        assert!(report.starts_with("block stream of"));
+        assert_eq!(report.matches("decoded 500 bytes").count(), 4);
+    }
+
+    #[test]
+    fn resume() {
+        let dir = std::env::temp_dir().join(format!("huffnpuff-cli-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("dump.txt");
+        let output = dir.join("dump.hnpb");
+        let data = b"a large dump, compressed a block at a time. ".repeat(500);
+        fs::write(&input, &data).unwrap();
+        let (input, output) = (input.to_str().unwrap(), output.to_str().unwrap());
+
+        // From scratch, and after being interrupted partway through
+        compress_resumed(input, output, 4096).unwrap();
+        let complete = fs::read(output).unwrap();
+        assert_eq!(decompress(&complete).unwrap(), data);
+        fs::write(output, &complete[..complete.len() / 2]).unwrap();
+        compress_resumed(input, output, 4096).unwrap();
+        assert_eq!(fs::read(output).unwrap(), complete);
+
+        fs::write(input, &data[..100]).unwrap();
+        fs::write(output, &complete[..complete.len() / 2]).unwrap();
+        assert!(compress_resumed(input, output, 4096).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,336 @@
+//! Bit packing for the huffman coder. Bits are gathered in a word and moved in and out of the
+//! buffer a byte at a time, rather than one at a time.
+
+/// The order in which bits fill each byte
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BitOrder {
+    /// Bits fill each byte starting from its least significant bit, and multi-bit values are
+    /// written least significant bit first
+    #[default]
+    Lsb0,
+    /// Bits fill each byte starting from its most significant bit, and multi-bit values are
+    /// written most significant bit first, as in DEFLATE-style hardware decoders
+    Msb0,
+}
+
+/// The low `count` bits set, for `count` up to 64
+fn mask(count: usize) -> u64 {
+    match count {
+        64 => u64::MAX,
+        _ => (1 << count) - 1,
+    }
+}
+
+/// Writes individual bits and small bit fields into a byte buffer, packed according to a
+/// [`BitOrder`]. This is the bit packing used by the huffman coder.
+#[derive(Debug, Clone)]
+pub struct BitWriter {
+    bytes: Vec<u8>,
+    order: BitOrder,
+    /// Bits not yet making up a whole byte, fewer than 8 between writes. They're the low bits in
+    /// both orders: for [`BitOrder::Lsb0`] the first is the least significant, and for
+    /// [`BitOrder::Msb0`] the most significant.
+    pending: u128,
+    pending_len: usize,
+}
+
+impl Default for BitWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BitWriter {
+    pub fn new() -> Self {
+        Self::with_order(BitOrder::default())
+    }
+
+    pub fn with_order(order: BitOrder) -> Self {
+        Self {
+            bytes: Vec::new(),
+            order,
+            pending: 0,
+            pending_len: 0,
+        }
+    }
+
+    pub fn order(&self) -> BitOrder {
+        self.order
+    }
+
+    pub fn write_bit(&mut self, bit: bool) {
+        self.write_bits(bit as u64, 1);
+    }
+
+    /// Write the low `count` bits of `value`, in the writer's [`BitOrder`]. `count` can be at most
+    /// 64.
+    pub fn write_bits(&mut self, value: u64, count: usize) {
+        assert!(count <= 64, "can write at most 64 bits at a time");
+        let value = u128::from(value & mask(count));
+        self.pending = match self.order {
+            BitOrder::Lsb0 => self.pending | value << self.pending_len,
+            BitOrder::Msb0 => self.pending << count | value,
+        };
+        self.pending_len += count;
+
+        let whole = self.pending_len / 8;
+        let rest = self.pending_len % 8;
+        match self.order {
+            BitOrder::Lsb0 => {
+                self.bytes
+                    .extend_from_slice(&self.pending.to_le_bytes()[..whole]);
+                self.pending >>= whole * 8;
+            }
+            BitOrder::Msb0 => {
+                let bytes = (self.pending >> rest).to_be_bytes();
+                self.bytes.extend_from_slice(&bytes[bytes.len() - whole..]);
+                self.pending &= (1 << rest) - 1;
+            }
+        }
+        self.pending_len = rest;
+    }
+
+    /// Pad with zero bits up to the next byte boundary
+    pub fn align_to_byte(&mut self) {
+        if self.pending_len > 0 {
+            self.write_bits(0, 8 - self.pending_len);
+        }
+    }
+
+    /// The number of bits written so far
+    pub fn len(&self) -> usize {
+        self.bytes.len() * 8 + self.pending_len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Remove the whole bytes written so far and return them, leaving the bits of a partly written
+    /// byte. [`BitWriter::len`] counts from after them.
+    #[cfg(feature = "encode")]
+    pub(crate) fn take_bytes(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.bytes)
+    }
+
+    /// The written bits, with the final byte padded with zero bits
+    pub fn into_bytes(mut self) -> Vec<u8> {
+        self.align_to_byte();
+        self.bytes
+    }
+}
+
+/// Reads individual bits and small bit fields from a byte buffer packed by a [`BitWriter`]
+#[derive(Debug, Clone)]
+pub struct BitReader<'a> {
+    bytes: &'a [u8],
+    /// Bytes that follow `bytes`, for data split in two, like the halves of a ring buffer
+    rest: &'a [u8],
+    order: BitOrder,
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self::with_order(bytes, BitOrder::default())
+    }
+
+    pub fn with_order(bytes: &'a [u8], order: BitOrder) -> Self {
+        Self {
+            bytes,
+            rest: &[],
+            order,
+            position: 0,
+        }
+    }
+
+    /// Read the bytes of `first` followed by those of `second`, as if they were one slice
+    #[cfg(feature = "decode")]
+    pub(crate) fn split(first: &'a [u8], second: &'a [u8], order: BitOrder) -> Self {
+        Self {
+            bytes: first,
+            rest: second,
+            order,
+            position: 0,
+        }
+    }
+
+    pub fn order(&self) -> BitOrder {
+        self.order
+    }
+
+    pub fn read_bit(&mut self) -> Option<bool> {
+        let index = self.position / 8;
+        let byte = match self.bytes.get(index) {
+            Some(byte) => byte,
+            None => self.rest.get(index - self.bytes.len())?,
+        };
+        let shift = match self.order {
+            BitOrder::Lsb0 => self.position % 8,
+            BitOrder::Msb0 => 7 - self.position % 8,
+        };
+        self.position += 1;
+        Some(byte >> shift & 1 == 1)
+    }
+
+    /// Read a `count` bit value written by [`BitWriter::write_bits`]. Returns `None`, without
+    /// consuming anything, if fewer than `count` bits remain.
+    pub fn read_n(&mut self, count: usize) -> Option<u64> {
+        let value = self.peek(count)?;
+        self.position += count;
+        Some(value)
+    }
+
+    /// Like [`BitReader::read_n`], but without consuming the bits
+    pub fn peek(&self, count: usize) -> Option<u64> {
+        assert!(count <= 64, "can read at most 64 bits at a time");
+        if count == 0 {
+            return Some(0);
+        }
+        if count > self.remaining() {
+            return None;
+        }
+        // The bits span at most 9 bytes, which are loaded into one word
+        let first = self.position / 8;
+        let last = (self.position + count).div_ceil(8);
+        let mut word = [0; 16];
+        if last <= self.bytes.len() {
+            word[..last - first].copy_from_slice(&self.bytes[first..last]);
+        } else {
+            let bytes = self.bytes.iter().chain(self.rest).skip(first);
+            for (slot, &byte) in word[..last - first].iter_mut().zip(bytes) {
+                *slot = byte;
+            }
+        }
+        let shift = self.position % 8;
+        let value = match self.order {
+            BitOrder::Lsb0 => u128::from_le_bytes(word) >> shift,
+            BitOrder::Msb0 => u128::from_be_bytes(word) << shift >> (128 - count),
+        };
+        Some(value as u64 & mask(count))
+    }
+
+    /// Skip to the next byte boundary
+    pub fn align_to_byte(&mut self) {
+        self.position = self.position.next_multiple_of(8).min(self.len());
+    }
+
+    /// Move to the bit at `position`, which is clamped to the end of the data
+    pub fn seek(&mut self, position: usize) {
+        self.position = position.min(self.len());
+    }
+
+    /// The number of bits read so far
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// The number of bits left to read
+    pub fn remaining(&self) -> usize {
+        self.len() - self.position
+    }
+
+    fn len(&self) -> usize {
+        (self.bytes.len() + self.rest.len()) * 8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        for order in [BitOrder::Lsb0, BitOrder::Msb0] {
+            let mut writer = BitWriter::with_order(order);
+            writer.write_bit(true);
+            writer.write_bits(0b101, 3);
+            writer.write_bits(u64::MAX, 64);
+            writer.align_to_byte();
+            writer.write_bits(0xabcd, 16);
+            assert_eq!(writer.len(), 88);
+
+            let bytes = writer.into_bytes();
+            assert_eq!(bytes.len(), 11);
+
+            let mut reader = BitReader::with_order(&bytes, order);
+            assert_eq!(reader.read_bit(), Some(true));
+            assert_eq!(reader.peek(3), Some(0b101));
+            assert_eq!(reader.read_n(3), Some(0b101));
+            assert_eq!(reader.read_n(64), Some(u64::MAX));
+            reader.align_to_byte();
+            assert_eq!(reader.position(), 72);
+            assert_eq!(reader.read_n(16), Some(0xabcd));
+            assert_eq!(reader.remaining(), 0);
+            assert_eq!(reader.read_bit(), None);
+
+            reader.seek(1);
+            assert_eq!(reader.read_n(3), Some(0b101));
+            reader.seek(1000);
+            assert_eq!(reader.position(), 88);
+        }
+    }
+
+    #[test]
+    fn packing() {
+        let mut writer = BitWriter::new();
+        writer.write_bits(0b1, 1);
+        writer.write_bits(0b0110, 4);
+        assert_eq!(writer.into_bytes(), vec![0b0000_1101]);
+
+        let mut writer = BitWriter::with_order(BitOrder::Msb0);
+        writer.write_bits(0b1, 1);
+        writer.write_bits(0b0110, 4);
+        assert_eq!(writer.into_bytes(), vec![0b1011_0000]);
+    }
+
+    #[test]
+    fn short_reads() {
+        let bytes = [0xff];
+        let mut reader = BitReader::new(&bytes);
+        assert_eq!(reader.read_n(9), None);
+        assert_eq!(reader.position(), 0);
+        assert_eq!(reader.read_n(8), Some(0xff));
+        reader.align_to_byte();
+        assert_eq!(reader.position(), 8);
+    }
+
+    #[test]
+    fn every_width_at_every_offset() {
+        for order in [BitOrder::Lsb0, BitOrder::Msb0] {
+            let mut writer = BitWriter::with_order(order);
+            let mut written = Vec::new();
+            for offset in 0..8 {
+                for count in 0..=64 {
+                    let value = 0x9e37_79b9_7f4a_7c15u64.rotate_left((offset * 65 + count) as u32);
+                    writer.write_bits(u64::MAX, offset);
+                    // Bits above `count` are ignored
+                    writer.write_bits(value, count);
+                    written.push((offset, count, value & mask(count)));
+                }
+            }
+            let bytes = writer.into_bytes();
+
+            let mut reader = BitReader::with_order(&bytes, order);
+            for (offset, count, value) in written {
+                assert_eq!(reader.read_n(offset), Some(mask(offset)));
+                let start = reader.position();
+                assert_eq!(
+                    reader.read_n(count),
+                    Some(value),
+                    "{order:?} {offset} {count}"
+                );
+
+                // The same value, a bit at a time
+                reader.seek(start);
+                let bits: Vec<bool> = (0..count).map(|_| reader.read_bit().unwrap()).collect();
+                let expected: Vec<bool> = match order {
+                    BitOrder::Lsb0 => (0..count).map(|i| value >> i & 1 == 1).collect(),
+                    BitOrder::Msb0 => (0..count).rev().map(|i| value >> i & 1 == 1).collect(),
+                };
+                assert_eq!(bits, expected);
+            }
+        }
+    }
+}
@@ -0,0 +1,1120 @@
+#[cfg(feature = "encode")]
+use std::fs::File;
+#[cfg(feature = "encode")]
+use std::io::Write;
+use std::io::{self, Read, Seek, SeekFrom};
+
+use crate::frame;
+#[cfg(feature = "decode")]
+use crate::options::DecodeOptions;
+#[cfg(feature = "encode")]
+use crate::options::EncodeOptions;
+
+const MAGIC: &[u8; 4] = b"HNPB";
+const VERSION: u8 = 1;
+/// Magic, version and the nominal uncompressed size of each block
+pub(crate) const HEADER_SIZE: usize = MAGIC.len() + 1 + 4;
+/// Each block is preceded by the length of its frame, a checksum of the frame, and a rolling
+/// digest of every frame up to and including this one. A zero length marks the end of the stream,
+/// and is followed by zero checksum and the final digest.
+pub(crate) const BLOCK_HEADER_SIZE: usize = 12;
+/// A block size that keeps the memory used for buffering small, while leaving the tree at the
+/// start of each block a negligible part of it
+#[cfg(feature = "encode")]
+pub const DEFAULT_BLOCK_SIZE: usize = 1 << 16;
+
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The data does not look like a block stream produced by huffnpuff
+    BadMagic,
+    /// The stream was written with a format version this library doesn't understand
+    UnsupportedVersion(u8),
+    /// The block size must be greater than zero and fit in 32 bits
+    InvalidBlockSize,
+    /// The stream ends before its end marker
+    Truncated,
+    /// The checksum of the block with this index doesn't match its data
+    ChecksumMismatch(usize),
+    /// The rolling digest stored after the block with this index doesn't match the blocks so far
+    DigestMismatch(usize),
+    /// The block with this index decompresses to more than the block size, or to less without
+    /// being the last block, or its header gives a length no block of the block size compresses to
+    WrongBlockLength(usize),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Error::BadMagic => "the data is not a block stream",
+            Error::UnsupportedVersion(version) => {
+                return write!(
+                    f,
+                    "the block stream has unsupported format version {version}"
+                );
+            }
+            Error::InvalidBlockSize => {
+                "the block size must be greater than zero and fit in 32 bits"
+            }
+            Error::Truncated => "the block stream ends before its end marker",
+            Error::ChecksumMismatch(index) => {
+                return write!(f, "block {index} doesn't match its checksum");
+            }
+            Error::DigestMismatch(index) => {
+                return write!(f, "the blocks up to block {index} don't match their digest");
+            }
+            Error::WrongBlockLength(index) => {
+                return write!(f, "block {index} has the wrong length");
+            }
+        })
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// How much of a possibly incomplete block stream is intact, see [`verify_blocks`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BlockVerification {
+    /// The number of leading blocks whose checksums and digests are correct
+    pub blocks: usize,
+    /// The length of the verified prefix of the stream. A sender can resume a transfer by sending
+    /// everything from this offset onwards.
+    pub verified_len: usize,
+    /// The rolling digest after the last verified block. It is equal to the digest a sender gets
+    /// by verifying its own stream truncated to `verified_len`, which confirms that both sides
+    /// hold the same prefix.
+    pub digest: u32,
+    /// Whether the end marker was reached, meaning the whole stream is present and intact
+    pub complete: bool,
+}
+
+/// Split `bytes` into blocks of `block_size` bytes and compress each independently, with a
+/// checksum per block and a rolling digest so a partial copy can be verified block by block
+#[cfg(feature = "encode")]
+pub fn compress_blocks(bytes: &[u8], block_size: usize) -> Result<Vec<u8>, crate::Error> {
+    let out = Vec::with_capacity(HEADER_SIZE + bytes.len() / 2);
+    let mut writer = BlockWriter::new(out, block_size)?;
+    writer.write_all(bytes)?;
+    writer.finish()
+}
+
+/// Like [`huff`](crate::huff), but the serialized value is compressed as a block stream, as by
+/// [`compress_blocks`], with a tree for each block. Values made of distinct regions, like a table
+/// of numbers followed by long text, compress better than with one tree for all of it. Decoded
+/// with [`puff_blocks`].
+#[cfg(feature = "encode")]
+pub fn huff_blocks<T>(value: &T, block_size: usize) -> Result<Vec<u8>, crate::Error>
+where
+    T: serde::Serialize,
+{
+    compress_blocks(&bincode::serialize(value)?, block_size)
+}
+
+/// The streaming counterpart of [`huff`](crate::huff) for data too large to hold in memory: a
+/// [`BlockWriter`], whose output is read back incrementally by a [`PuffReader`]
+#[cfg(feature = "encode")]
+pub type HuffWriter<W> = BlockWriter<W>;
+
+/// Writes a block stream incrementally, in the same format as [`compress_blocks`]. Every
+/// `block_size` bytes the current block is compressed with a tree trained on that block alone,
+/// so long streams whose statistics drift, like logs that move through different phases, keep
+/// compressing well instead of being stuck with a model of their beginning.
+///
+/// [`BlockWriter::finish`] must be called to compress the final partial block and write the end
+/// marker.
+#[cfg(feature = "encode")]
+pub struct BlockWriter<W: Write> {
+    writer: W,
+    block_size: usize,
+    buffer: Vec<u8>,
+    digest: crc32fast::Hasher,
+}
+
+#[cfg(feature = "encode")]
+impl<W: Write> BlockWriter<W> {
+    /// Start a block stream, writing its header to `writer`
+    pub fn new(mut writer: W, block_size: usize) -> Result<Self, crate::Error> {
+        let block_size_field = u32::try_from(block_size)
+            .ok()
+            .filter(|&size| size > 0)
+            .ok_or(Error::InvalidBlockSize)?;
+
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[VERSION])?;
+        writer.write_all(&block_size_field.to_le_bytes())?;
+        Ok(Self {
+            writer,
+            block_size,
+            buffer: Vec::with_capacity(block_size),
+            digest: crc32fast::Hasher::new(),
+        })
+    }
+
+    /// Like [`BlockWriter::new`], with blocks of [`DEFAULT_BLOCK_SIZE`]
+    pub fn with_default_block_size(writer: W) -> Result<Self, crate::Error> {
+        Self::new(writer, DEFAULT_BLOCK_SIZE)
+    }
+
+    /// Compress and write out the buffered block, retraining the tree
+    fn write_block(&mut self) -> Result<(), crate::Error> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let frame = encode_block(&self.buffer)?;
+        self.buffer.clear();
+        write_frame(&mut self.writer, &mut self.digest, &frame)
+    }
+
+    /// The underlying writer. Writing to it directly will corrupt the stream.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    /// Write the final block and the end marker, returning the underlying writer
+    pub fn finish(mut self) -> Result<W, crate::Error> {
+        self.write_block()?;
+        self.writer.write_all(&0u32.to_le_bytes())?;
+        self.writer.write_all(&0u32.to_le_bytes())?;
+        self.writer
+            .write_all(&self.digest.finalize().to_le_bytes())?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// Compress one block into its frame
+#[cfg(feature = "encode")]
+fn encode_block(block: &[u8]) -> Result<Vec<u8>, crate::Error> {
+    frame::encode(block, &frame::Header::default(), &EncodeOptions::default())
+}
+
+/// Write a block's frame, preceded by its length, checksum and the rolling digest including it
+#[cfg(feature = "encode")]
+fn write_frame<W: Write>(
+    writer: &mut W,
+    digest: &mut crc32fast::Hasher,
+    frame: &[u8],
+) -> Result<(), crate::Error> {
+    digest.update(frame);
+    writer.write_all(&(frame.len() as u32).to_le_bytes())?;
+    writer.write_all(&crc32fast::hash(frame).to_le_bytes())?;
+    writer.write_all(&digest.clone().finalize().to_le_bytes())?;
+    writer.write_all(frame)?;
+    Ok(())
+}
+
+/// Like [`compress_blocks`], but the blocks are compressed on several threads. The output is the
+/// same as [`compress_blocks`] gives, so it's read back with [`decompress_blocks`],
+/// [`decompress_blocks_parallel`] or a [`PuffReader`].
+#[cfg(all(feature = "encode", feature = "parallel"))]
+pub fn compress_blocks_parallel(bytes: &[u8], block_size: usize) -> Result<Vec<u8>, crate::Error> {
+    let mut out = Vec::with_capacity(HEADER_SIZE + bytes.len() / 2);
+    let writer = BlockWriter::new(&mut out, block_size)?;
+    let blocks: Vec<_> = bytes.chunks(block_size).collect();
+    let frames = on_threads(&blocks, |block| encode_block(block))?;
+
+    let mut digest = writer.digest;
+    for frame in &frames {
+        write_frame(&mut out, &mut digest, frame)?;
+    }
+    out.extend_from_slice(&0u32.to_le_bytes());
+    out.extend_from_slice(&0u32.to_le_bytes());
+    out.extend_from_slice(&digest.finalize().to_le_bytes());
+    Ok(out)
+}
+
+/// Apply `f` to each item, with the items shared out in order among as many threads as there are
+/// cores, stopping at the first error
+#[cfg(feature = "parallel")]
+fn on_threads<I: Sync, T: Send>(
+    items: &[I],
+    f: impl Fn(&I) -> Result<T, crate::Error> + Sync,
+) -> Result<Vec<T>, crate::Error> {
+    let threads = std::thread::available_parallelism().map_or(1, |threads| threads.get());
+    let share = items.len().div_ceil(threads).max(1);
+    let f = &f;
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = items
+            .chunks(share)
+            .map(|chunk| scope.spawn(move || chunk.iter().map(f).collect::<Result<Vec<_>, _>>()))
+            .collect();
+        let mut results = Vec::with_capacity(items.len());
+        for handle in handles {
+            let chunk = handle
+                .join()
+                .unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+            results.extend(chunk?);
+        }
+        Ok(results)
+    })
+}
+
+#[cfg(feature = "encode")]
+impl BlockWriter<File> {
+    /// Continue a block stream that was being written to `file` when it was interrupted. The
+    /// blocks already in the file are checked without decompressing them, and the file is cut
+    /// back to the end of the last intact one. Returns the writer and the number of input bytes
+    /// those blocks hold, which is where the input should be picked up again.
+    ///
+    /// Only a full block is followed by another block, so the last intact block is kept only when
+    /// the header of a block after it was read. Otherwise it may be the short final block, and it
+    /// is dropped, along with any end marker, and written again.
+    pub fn resume(mut file: File) -> Result<(Self, u64), crate::Error> {
+        file.seek(SeekFrom::Start(0))?;
+        let mut reader = io::BufReader::new(&mut file);
+        let mut header = [0; HEADER_SIZE];
+        reader
+            .read_exact(&mut header)
+            .map_err(|error| match error.kind() {
+                io::ErrorKind::UnexpectedEof => crate::Error::from(Error::Truncated),
+                _ => error.into(),
+            })?;
+        if &header[..MAGIC.len()] != MAGIC {
+            return Err(Error::BadMagic.into());
+        }
+        if header[MAGIC.len()] != VERSION {
+            return Err(Error::UnsupportedVersion(header[MAGIC.len()]).into());
+        }
+        let block_size = u32::from_le_bytes(header[MAGIC.len() + 1..].try_into().unwrap());
+
+        // The end of each intact block, and the rolling digest after it
+        let mut intact = vec![(HEADER_SIZE as u64, crc32fast::Hasher::new())];
+        let mut complete = false;
+        let mut followed = false;
+        let mut frame = Vec::new();
+        loop {
+            let mut block_header = [0; BLOCK_HEADER_SIZE];
+            if reader.read_exact(&mut block_header).is_err() {
+                break;
+            }
+            let field =
+                |i: usize| u32::from_le_bytes(block_header[i * 4..i * 4 + 4].try_into().unwrap());
+            let (len, checksum, digest) = (field(0) as usize, field(1), field(2));
+            // A length no block compresses to is a torn header rather than a block to allocate for
+            if len > frame::max_len(block_size as usize) {
+                break;
+            }
+            followed = len > 0;
+            frame.resize(len, 0);
+            if reader.read_exact(&mut frame).is_err() {
+                break;
+            }
+            let (end, rolling) = intact.last().unwrap();
+            let mut rolling = rolling.clone();
+            rolling.update(&frame);
+            if (len > 0 && crc32fast::hash(&frame) != checksum)
+                || rolling.clone().finalize() != digest
+            {
+                break;
+            }
+            if len == 0 {
+                complete = true;
+                break;
+            }
+            intact.push((end + (BLOCK_HEADER_SIZE + len) as u64, rolling));
+            followed = false;
+        }
+        drop(reader);
+
+        if (complete || !followed) && intact.len() > 1 {
+            intact.pop();
+        }
+        let (end, digest) = intact.pop().unwrap();
+        let blocks = intact.len() as u64;
+        file.set_len(end)?;
+        file.seek(SeekFrom::Start(end))?;
+        let block_size = block_size as usize;
+        Ok((
+            Self {
+                writer: file,
+                block_size,
+                buffer: Vec::with_capacity(block_size),
+                digest,
+            },
+            blocks * block_size as u64,
+        ))
+    }
+}
+
+#[cfg(feature = "encode")]
+impl<W: Write> Write for BlockWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = buf.len().min(self.block_size - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..len]);
+        if self.buffer.len() == self.block_size {
+            self.write_block().map_err(io::Error::from)?;
+        }
+        Ok(len)
+    }
+
+    /// Flushes the underlying writer. The partial block being buffered isn't written, since that
+    /// would split it into a block of its own.
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Decompress a whole stream produced by [`compress_blocks`], checking every block
+#[cfg(feature = "decode")]
+pub fn decompress_blocks(bytes: &[u8]) -> Result<Vec<u8>, crate::Error> {
+    let (block_size, frames) = block_frames(bytes)?;
+    let mut out = Vec::new();
+    for (index, frame) in frames.iter().enumerate() {
+        // Only the last block may be short, so a short block before this one is an error
+        if out.len() < index * block_size as usize {
+            return Err(Error::WrongBlockLength(index - 1).into());
+        }
+        out.extend_from_slice(&decode_block(frame, block_size as usize, index)?);
+    }
+    Ok(out)
+}
+
+/// Decompress the frame of the block with this index. Decoding stops as soon as the output passes
+/// the block size, so a small frame that expands to gigabytes is rejected without allocating them.
+#[cfg(feature = "decode")]
+pub(crate) fn decode_block(
+    frame: &[u8],
+    block_size: usize,
+    index: usize,
+) -> Result<Vec<u8>, crate::Error> {
+    let options = DecodeOptions {
+        max_decompressed_len: Some(block_size),
+        ..DecodeOptions::default()
+    };
+    match frame::decode_with_options(frame, None, &options) {
+        Ok(frame) if !frame.payload.is_empty() => Ok(frame.payload),
+        Ok(_) | Err(crate::Error::LimitExceeded) => Err(Error::WrongBlockLength(index).into()),
+        Err(error) => Err(error),
+    }
+}
+
+/// Decompress a value compressed by [`huff_blocks`]. The value is deserialized as its blocks are
+/// decompressed, one at a time, so the decompressed bytes are never all in memory at once. Every
+/// block is checked, including those after the end of the value.
+#[cfg(feature = "decode")]
+pub fn puff_blocks<T>(bytes: &[u8]) -> Result<T, crate::Error>
+where
+    T: serde::de::DeserializeOwned,
+{
+    let mut reader = PuffReader::new(bytes)?;
+    let value = bincode::deserialize_from(&mut reader).map_err(|error| match *error {
+        // Errors from the reader come back out as they were
+        bincode::ErrorKind::Io(error) => crate::Error::from(error),
+        error => crate::Error::Bincode(Box::new(error)),
+    })?;
+    io::copy(&mut reader, &mut io::sink())?;
+    Ok(value)
+}
+
+/// Like [`decompress_blocks`], but once every block has been checked, the blocks are
+/// decompressed on several threads
+#[cfg(all(feature = "decode", feature = "parallel"))]
+pub fn decompress_blocks_parallel(bytes: &[u8]) -> Result<Vec<u8>, crate::Error> {
+    let (block_size, frames) = block_frames(bytes)?;
+    let indexed: Vec<_> = frames.into_iter().enumerate().collect();
+    let blocks = on_threads(&indexed, |&(index, frame)| {
+        decode_block(frame, block_size as usize, index)
+    })?;
+    let last = blocks.len().saturating_sub(1);
+    if let Some(index) = blocks[..last]
+        .iter()
+        .position(|block| block.len() < block_size as usize)
+    {
+        return Err(Error::WrongBlockLength(index).into());
+    }
+    Ok(blocks.concat())
+}
+
+/// Whether `bytes` starts like a block stream
+#[cfg(feature = "decode")]
+pub(crate) fn is_block_stream(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+/// The block size of a block stream and the frame of each of its blocks, checked as
+/// [`decompress_blocks`] checks them
+#[cfg(feature = "decode")]
+pub(crate) fn block_frames(bytes: &[u8]) -> Result<(u32, Vec<&[u8]>), crate::Error> {
+    let mut reader = BlockReader::new(bytes)?;
+    let block_size = u32::from_le_bytes(bytes[MAGIC.len() + 1..HEADER_SIZE].try_into().unwrap());
+    let mut frames = Vec::new();
+    while let Some(frame) = reader.next_block()? {
+        frames.push(frame);
+    }
+    Ok((block_size, frames))
+}
+
+/// Reads a block stream produced by [`compress_blocks`] or [`BlockWriter`] incrementally,
+/// decompressing one block at a time and checking each block's checksum.
+///
+/// When the source is also [`Seek`], the reader is too. Every block but the last holds exactly the
+/// stream's block size, so the block holding an offset is known directly. Seeking skips over the
+/// blocks before it using their lengths, without reading them, and remembers where each block
+/// starts for later seeks. The rolling digest can only be checked when every block has been read
+/// in order, so it isn't checked after a seek.
+#[cfg(feature = "decode")]
+pub struct PuffReader<R> {
+    reader: R,
+    block_size: u64,
+    /// Where each block found so far starts, relative to the start of the stream
+    starts: Vec<u64>,
+    /// The number of blocks, once the end marker has been found
+    blocks: Option<usize>,
+    /// How far into the stream `reader` is
+    stream_position: u64,
+    /// The decompressed block with index `index`, or empty before the first block is read
+    block: Vec<u8>,
+    index: usize,
+    /// The position in the decompressed data, which may be inside `block` or past the end
+    position: u64,
+    digest: Option<crc32fast::Hasher>,
+}
+
+#[cfg(feature = "decode")]
+impl<R: Read> PuffReader<R> {
+    /// Start reading a block stream, which reads its header
+    pub fn new(mut reader: R) -> Result<Self, crate::Error> {
+        let mut header = [0; HEADER_SIZE];
+        read_exact(&mut reader, &mut header)?;
+        let block_size = read_header(&header)?;
+
+        Ok(Self {
+            reader,
+            block_size: block_size.into(),
+            starts: vec![HEADER_SIZE as u64],
+            blocks: None,
+            stream_position: HEADER_SIZE as u64,
+            block: Vec::new(),
+            index: 0,
+            position: 0,
+            digest: Some(crc32fast::Hasher::new()),
+        })
+    }
+
+    /// The number of decompressed bytes in each block but the last
+    pub fn block_size(&self) -> u64 {
+        self.block_size
+    }
+
+    /// Read the length, checksum and digest before a block, or the end marker
+    fn read_block_header(&mut self) -> Result<(usize, u32, u32), crate::Error> {
+        let mut header = [0; BLOCK_HEADER_SIZE];
+        read_exact(&mut self.reader, &mut header)?;
+        self.stream_position += BLOCK_HEADER_SIZE as u64;
+        Ok(read_block_header(&header))
+    }
+
+    /// Read block `index`, which the source must be positioned at. Returns `false` if the end
+    /// marker is there instead.
+    fn read_block(&mut self, index: usize) -> Result<bool, crate::Error> {
+        let (len, checksum, digest) = self.read_block_header()?;
+        // Checked before allocating, so a damaged length can't ask for gigabytes
+        if len > frame::max_len(self.block_size as usize) {
+            return Err(Error::WrongBlockLength(index).into());
+        }
+        let mut frame = vec![0; len];
+        read_exact(&mut self.reader, &mut frame)?;
+        self.stream_position += len as u64;
+
+        if len > 0 && crc32fast::hash(&frame) != checksum {
+            return Err(Error::ChecksumMismatch(index).into());
+        }
+        if let Some(rolling) = &mut self.digest {
+            rolling.update(&frame);
+            if rolling.clone().finalize() != digest {
+                return Err(Error::DigestMismatch(index).into());
+            }
+        }
+        if len == 0 {
+            self.blocks = Some(index);
+            return Ok(false);
+        }
+        if self.starts.len() == index + 1 {
+            self.starts.push(self.stream_position);
+        }
+
+        self.block = decode_block(&frame, self.block_size as usize, index)?;
+        self.index = index;
+        Ok(true)
+    }
+
+    /// The decompressed bytes left in the current block from the current position
+    fn remaining(&self) -> &[u8] {
+        let block_start = self.index as u64 * self.block_size;
+        self.position
+            .checked_sub(block_start)
+            .and_then(|offset| self.block.get(usize::try_from(offset).ok()?..))
+            .unwrap_or_default()
+    }
+
+    /// Read blocks in order until there's something left to read at the current position, if
+    /// the position is at the end of the current block
+    fn fill(&mut self) -> Result<(), crate::Error> {
+        while self.remaining().is_empty() {
+            let block_end = self.index as u64 * self.block_size + self.block.len() as u64;
+            let next = self.index + usize::from(!self.block.is_empty());
+            let in_order = self.starts.get(next) == Some(&self.stream_position);
+            if self.position != block_end || !in_order || self.blocks.is_some_and(|n| next >= n) {
+                return Ok(());
+            }
+            let short = !self.block.is_empty() && (self.block.len() as u64) < self.block_size;
+            if self.read_block(next)? && short {
+                return Err(Error::WrongBlockLength(self.index - 1).into());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Check the header at the start of a block stream, returning its block size
+#[cfg(feature = "decode")]
+pub(crate) fn read_header(header: &[u8; HEADER_SIZE]) -> Result<u32, Error> {
+    if &header[..MAGIC.len()] != MAGIC {
+        return Err(Error::BadMagic);
+    }
+    if header[MAGIC.len()] != VERSION {
+        return Err(Error::UnsupportedVersion(header[MAGIC.len()]));
+    }
+    let block_size = u32::from_le_bytes(header[MAGIC.len() + 1..].try_into().unwrap());
+    if block_size == 0 {
+        return Err(Error::InvalidBlockSize);
+    }
+    Ok(block_size)
+}
+
+/// The length, checksum and rolling digest in the header before a block
+#[cfg(feature = "decode")]
+pub(crate) fn read_block_header(header: &[u8; BLOCK_HEADER_SIZE]) -> (usize, u32, u32) {
+    let field = |i: usize| u32::from_le_bytes(header[i * 4..i * 4 + 4].try_into().unwrap());
+    (field(0) as usize, field(1), field(2))
+}
+
+/// Like [`Read::read_exact`], where running out is the stream being truncated
+#[cfg(feature = "decode")]
+fn read_exact<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<(), crate::Error> {
+    reader.read_exact(buf).map_err(|error| match error.kind() {
+        io::ErrorKind::UnexpectedEof => Error::Truncated.into(),
+        _ => error.into(),
+    })
+}
+
+#[cfg(feature = "decode")]
+impl<R: Read> Read for PuffReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.fill().map_err(io::Error::from)?;
+        let remaining = self.remaining();
+        let len = buf.len().min(remaining.len());
+        buf[..len].copy_from_slice(&remaining[..len]);
+        self.position += len as u64;
+        Ok(len)
+    }
+}
+
+#[cfg(feature = "decode")]
+impl<R: Read + Seek> PuffReader<R> {
+    /// Move the source to `position` in the stream
+    fn seek_stream(&mut self, position: u64) -> Result<(), crate::Error> {
+        if position != self.stream_position {
+            let offset = position as i64 - self.stream_position as i64;
+            self.reader.seek(SeekFrom::Current(offset))?;
+            self.stream_position = position;
+        }
+        Ok(())
+    }
+
+    /// Find where the next unknown block starts from the length of the last known one, or that
+    /// the last known one is the end marker
+    fn skip_block(&mut self) -> Result<(), crate::Error> {
+        let index = self.starts.len() - 1;
+        let start = self.starts[index];
+        self.seek_stream(start)?;
+        match self.read_block_header()? {
+            (0, _, _) => self.blocks = Some(index),
+            (len, _, _) => self.starts.push(start + (BLOCK_HEADER_SIZE + len) as u64),
+        }
+        Ok(())
+    }
+
+    /// Where block `index` starts, or `None` if the stream ends before it
+    fn find_block(&mut self, index: usize) -> Result<Option<u64>, crate::Error> {
+        while self.starts.len() <= index && self.blocks.is_none() {
+            self.skip_block()?;
+        }
+        match self.blocks {
+            Some(blocks) if index >= blocks => Ok(None),
+            _ => Ok(Some(self.starts[index])),
+        }
+    }
+
+    /// Read block `index` out of order, if it exists
+    fn load_block(&mut self, index: usize) -> Result<(), crate::Error> {
+        if self.index == index && !self.block.is_empty() {
+            return Ok(());
+        }
+        if let Some(start) = self.find_block(index)? {
+            self.digest = None;
+            self.seek_stream(start)?;
+            self.read_block(index)?;
+        }
+        Ok(())
+    }
+
+    /// The length of the decompressed data, which needs the last block decompressed
+    fn decompressed_len(&mut self) -> Result<u64, crate::Error> {
+        while self.blocks.is_none() {
+            self.skip_block()?;
+        }
+        let Some(last) = self.blocks.unwrap().checked_sub(1) else {
+            return Ok(0);
+        };
+        self.load_block(last)?;
+        Ok(last as u64 * self.block_size + self.block.len() as u64)
+    }
+}
+
+#[cfg(feature = "decode")]
+impl<R: Read + Seek> Seek for PuffReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(position) => Some(position),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+            SeekFrom::End(offset) => self
+                .decompressed_len()
+                .map_err(io::Error::from)?
+                .checked_add_signed(offset),
+        }
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "seek before the start"))?;
+
+        let index = usize::try_from(position / self.block_size).unwrap_or(usize::MAX);
+        self.load_block(index).map_err(io::Error::from)?;
+        self.position = position;
+        Ok(position)
+    }
+}
+
+/// Check how much of a possibly truncated or damaged block stream is intact, without
+/// decompressing anything
+pub fn verify_blocks(bytes: &[u8]) -> Result<BlockVerification, crate::Error> {
+    let mut reader = BlockReader::new(bytes)?;
+    let mut verification = BlockVerification {
+        blocks: 0,
+        verified_len: reader.position,
+        digest: reader.digest.clone().finalize(),
+        complete: false,
+    };
+
+    loop {
+        match reader.next_block() {
+            Ok(Some(_)) => {
+                verification.blocks += 1;
+                verification.verified_len = reader.position;
+                verification.digest = reader.digest.clone().finalize();
+            }
+            Ok(None) => {
+                verification.complete = true;
+                return Ok(verification);
+            }
+            Err(_) => return Ok(verification),
+        }
+    }
+}
+
+/// Walks the blocks of a stream, checking checksums and the rolling digest as it goes
+struct BlockReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    index: usize,
+    digest: crc32fast::Hasher,
+}
+
+impl<'a> BlockReader<'a> {
+    fn new(bytes: &'a [u8]) -> Result<Self, Error> {
+        if bytes.len() < HEADER_SIZE {
+            return Err(Error::Truncated);
+        }
+        if &bytes[..MAGIC.len()] != MAGIC {
+            return Err(Error::BadMagic);
+        }
+        if bytes[MAGIC.len()] != VERSION {
+            return Err(Error::UnsupportedVersion(bytes[MAGIC.len()]));
+        }
+
+        Ok(Self {
+            bytes,
+            position: HEADER_SIZE,
+            index: 0,
+            digest: crc32fast::Hasher::new(),
+        })
+    }
+
+    /// Returns the next block's frame, or `None` at the end marker
+    fn next_block(&mut self) -> Result<Option<&'a [u8]>, Error> {
+        let header = self
+            .bytes
+            .get(self.position..self.position + BLOCK_HEADER_SIZE)
+            .ok_or(Error::Truncated)?;
+        let field = |i: usize| u32::from_le_bytes(header[i * 4..i * 4 + 4].try_into().unwrap());
+        let (len, checksum, digest) = (field(0) as usize, field(1), field(2));
+
+        let start = self.position + BLOCK_HEADER_SIZE;
+        let frame = self.bytes.get(start..start + len).ok_or(Error::Truncated)?;
+
+        if len > 0 && crc32fast::hash(frame) != checksum {
+            return Err(Error::ChecksumMismatch(self.index));
+        }
+        let mut rolling = self.digest.clone();
+        rolling.update(frame);
+        if rolling.clone().finalize() != digest {
+            return Err(Error::DigestMismatch(self.index));
+        }
+
+        self.digest = rolling;
+        self.position = start + len;
+        if len == 0 {
+            return Ok(None);
+        }
+        self.index += 1;
+        Ok(Some(frame))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Vec<u8> {
+        (0..10_000u32)
+            .flat_map(|i| (i % 251).to_le_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn roundtrip() {
+        let data = sample();
+        let compressed = compress_blocks(&data, 4096).unwrap();
+        assert_eq!(decompress_blocks(&compressed).unwrap(), data);
+
+        let empty = compress_blocks(&[], 4096).unwrap();
+        assert_eq!(decompress_blocks(&empty).unwrap(), Vec::<u8>::new());
+
+        assert!(matches!(
+            compress_blocks(&data, 0),
+            Err(crate::Error::Block(Error::InvalidBlockSize))
+        ));
+    }
+
+    #[test]
+    fn large_stream() {
+        let len = 3 << 20;
+        let tail = sample();
+        let mut input = io::repeat(b'x').take(len).chain(tail.as_slice());
+        let mut writer = HuffWriter::with_default_block_size(Vec::new()).unwrap();
+        io::copy(&mut input, &mut writer).unwrap();
+        let compressed = writer.finish().unwrap();
+        assert!(compressed.len() < 100_000);
+
+        let mut reader = PuffReader::new(compressed.as_slice()).unwrap();
+        let mut head = vec![0; 100];
+        reader.read_exact(&mut head).unwrap();
+        assert_eq!(head, [b'x'; 100]);
+        let rest = io::copy(&mut reader, &mut io::sink()).unwrap();
+        assert_eq!(rest, len - 100 + sample().len() as u64);
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn parallel() {
+        let data = sample();
+        let compressed = compress_blocks_parallel(&data, 4096).unwrap();
+        assert_eq!(compressed, compress_blocks(&data, 4096).unwrap());
+        assert_eq!(decompress_blocks_parallel(&compressed).unwrap(), data);
+
+        let empty = compress_blocks_parallel(&[], 4096).unwrap();
+        assert_eq!(
+            decompress_blocks_parallel(&empty).unwrap(),
+            Vec::<u8>::new()
+        );
+
+        let mut damaged = compressed.clone();
+        damaged[HEADER_SIZE + BLOCK_HEADER_SIZE + 10] ^= 1;
+        assert_eq!(
+            decompress_blocks_parallel(&damaged),
+            Err(Error::ChecksumMismatch(0).into())
+        );
+    }
+
+    #[test]
+    fn damaged_length() {
+        let mut compressed = compress_blocks(&sample(), 4096).unwrap();
+        compressed[HEADER_SIZE..HEADER_SIZE + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        let mut reader = PuffReader::new(compressed.as_slice()).unwrap();
+        let error = reader.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(crate::Error::from(error), Error::WrongBlockLength(0).into());
+    }
+
+    #[test]
+    fn expanding_block() {
+        // A frame of a few bytes that expands to a mebibyte, in a stream of 16 byte blocks
+        let bomb = vec![0; 1 << 20];
+        let frame = encode_block(&bomb).unwrap();
+        assert!(frame.len() < 32);
+        let mut writer = BlockWriter::new(Vec::new(), 16).unwrap();
+        write_frame(&mut writer.writer, &mut writer.digest, &frame).unwrap();
+        let compressed = writer.finish().unwrap();
+
+        let expected = Err(Error::WrongBlockLength(0).into());
+        assert_eq!(decompress_blocks(&compressed), expected);
+        #[cfg(feature = "parallel")]
+        assert_eq!(decompress_blocks_parallel(&compressed), expected);
+        let mut reader = PuffReader::new(compressed.as_slice()).unwrap();
+        let error = reader.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(Err(crate::Error::from(error)), expected);
+
+        // A short block that isn't the last one
+        let mut writer = BlockWriter::new(Vec::new(), 16).unwrap();
+        for block in [&b"short"[..], &[0; 16]] {
+            let frame = encode_block(block).unwrap();
+            write_frame(&mut writer.writer, &mut writer.digest, &frame).unwrap();
+        }
+        let compressed = writer.finish().unwrap();
+        assert_eq!(decompress_blocks(&compressed), expected);
+        #[cfg(feature = "parallel")]
+        assert_eq!(decompress_blocks_parallel(&compressed), expected);
+    }
+
+    #[test]
+    fn streaming_writer() {
+        let data = sample();
+        let mut writer = BlockWriter::new(Vec::new(), 4096).unwrap();
+        for chunk in data.chunks(1000) {
+            writer.write_all(chunk).unwrap();
+        }
+        let streamed = writer.finish().unwrap();
+        assert_eq!(decompress_blocks(&streamed).unwrap(), data);
+        assert_eq!(verify_blocks(&streamed).unwrap().blocks, 10);
+    }
+
+    #[test]
+    fn retrains_per_block() {
+        // Two phases with unrelated statistics compress better with a tree for each
+        let mut data = b"aaaaaaaabbbbcc".repeat(300);
+        data.extend(b"xyzxyzxyzxyzxyzw".repeat(300));
+        let single = compress_blocks(&data, data.len()).unwrap();
+        let windowed = compress_blocks(&data, 4200).unwrap();
+        assert!(windowed.len() < single.len());
+        assert_eq!(decompress_blocks(&windowed).unwrap(), data);
+    }
+
+    #[test]
+    fn values() {
+        let value: (Vec<u32>, String) = (
+            (0..2000).map(|i| i * 7).collect(),
+            "a long stretch of text after the numbers. ".repeat(200),
+        );
+        let compressed = huff_blocks(&value, 4096).unwrap();
+        assert!(compressed.len() < crate::huff(&value).unwrap().len());
+        assert_eq!(
+            puff_blocks::<(Vec<u32>, String)>(&compressed).unwrap(),
+            value
+        );
+
+        let mut damaged = compressed.clone();
+        let last = damaged.len() - 20;
+        damaged[last] ^= 1;
+        assert!(puff_blocks::<(Vec<u32>, String)>(&damaged).is_err());
+        assert_eq!(
+            puff_blocks::<(Vec<u32>, String)>(&compressed[..compressed.len() - 1]),
+            Err(Error::Truncated.into())
+        );
+    }
+
+    #[test]
+    fn verify_complete() {
+        let compressed = compress_blocks(&sample(), 4096).unwrap();
+        let verification = verify_blocks(&compressed).unwrap();
+
+        assert_eq!(verification.blocks, 10);
+        assert_eq!(
+            verification.verified_len,
+            compressed.len() - BLOCK_HEADER_SIZE
+        );
+        assert!(verification.complete);
+    }
+
+    #[test]
+    fn resume_truncated_transfer() {
+        let compressed = compress_blocks(&sample(), 4096).unwrap();
+
+        // The receiver got part of the stream, ending in the middle of a block
+        let received = &compressed[..compressed.len() / 2];
+        assert!(matches!(
+            decompress_blocks(received),
+            Err(crate::Error::Block(Error::Truncated))
+        ));
+        let verification = verify_blocks(received).unwrap();
+        assert!(!verification.complete);
+        assert!(verification.blocks > 0);
+
+        // The sender confirms it agrees about the verified prefix and sends the rest
+        let sender = verify_blocks(&compressed[..verification.verified_len]).unwrap();
+        assert_eq!(sender.digest, verification.digest);
+
+        let mut resumed = received[..verification.verified_len].to_vec();
+        resumed.extend_from_slice(&compressed[verification.verified_len..]);
+        assert_eq!(decompress_blocks(&resumed).unwrap(), sample());
+    }
+
+    #[test]
+    fn streaming_reader() {
+        let data = sample();
+        let compressed = compress_blocks(&data, 4096).unwrap();
+        let mut reader = PuffReader::new(compressed.as_slice()).unwrap();
+        let mut decompressed = Vec::new();
+        reader.read_to_end(&mut decompressed).unwrap();
+        assert_eq!(decompressed, data);
+
+        let empty = compress_blocks(&[], 4096).unwrap();
+        let mut decompressed = Vec::new();
+        PuffReader::new(empty.as_slice())
+            .unwrap()
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert!(decompressed.is_empty());
+
+        let truncated = &compressed[..compressed.len() / 2];
+        let mut reader = PuffReader::new(truncated).unwrap();
+        assert!(reader.read_to_end(&mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn seek() {
+        let data = sample();
+        let compressed = compress_blocks(&data, 4096).unwrap();
+        let mut reader = PuffReader::new(io::Cursor::new(compressed)).unwrap();
+
+        let mut read_at = |pos: SeekFrom, len: usize| {
+            let position = reader.seek(pos).unwrap() as usize;
+            let mut buf = vec![0; len];
+            let read = reader.read(&mut buf).unwrap();
+            (position, buf[..read].to_vec())
+        };
+        let (position, bytes) = read_at(SeekFrom::Start(10_000), 100);
+        assert_eq!(bytes, data[position..position + 100]);
+        let (position, bytes) = read_at(SeekFrom::Start(5), 10);
+        assert_eq!((position, bytes.as_slice()), (5, &data[5..15]));
+        let (position, bytes) = read_at(SeekFrom::Current(30_000), 10);
+        assert_eq!(
+            (position, bytes.as_slice()),
+            (30_015, &data[30_015..30_025])
+        );
+        let (position, bytes) = read_at(SeekFrom::End(-3), 10);
+        assert_eq!(
+            (position, bytes.as_slice()),
+            (data.len() - 3, &data[data.len() - 3..])
+        );
+        let (position, bytes) = read_at(SeekFrom::End(10), 10);
+        assert_eq!((position, bytes.len()), (data.len() + 10, 0));
+        let (position, bytes) = read_at(SeekFrom::Start(4096 * 3), 4096);
+        assert_eq!(
+            (position, bytes.as_slice()),
+            (4096 * 3, &data[4096 * 3..4096 * 4])
+        );
+
+        // Reading on from a seek crosses into the following blocks
+        reader.seek(SeekFrom::Start(4000)).unwrap();
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, data[4000..]);
+        assert!(reader.seek(SeekFrom::Current(-50_000)).is_err());
+    }
+
+    #[test]
+    fn resume_interrupted_compression() {
+        let dir = std::env::temp_dir().join(format!("huffnpuff-resume-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("stream.hnpb");
+        let data = sample();
+        let compressed = compress_blocks(&data, 4096).unwrap();
+        let open = || {
+            std::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(&path)
+                .unwrap()
+        };
+
+        // Interrupted partway through a block, partway through the end marker after the short
+        // final block, finished, and interrupted before any blocks
+        let end_marker = compressed.len() - BLOCK_HEADER_SIZE;
+        for written in [
+            compressed.len() * 2 / 3,
+            end_marker + 4,
+            compressed.len(),
+            HEADER_SIZE,
+        ] {
+            std::fs::write(&path, &compressed[..written]).unwrap();
+            let (mut writer, consumed) = BlockWriter::resume(open()).unwrap();
+            assert_eq!(consumed % 4096, 0);
+            assert!(consumed < data.len() as u64);
+            writer.write_all(&data[consumed as usize..]).unwrap();
+            writer.finish().unwrap();
+
+            let resumed = std::fs::read(&path).unwrap();
+            assert_eq!(decompress_blocks(&resumed).unwrap(), data);
+            assert!(verify_blocks(&resumed).unwrap().complete);
+        }
+
+        // A damaged block is written again along with everything after it
+        let mut damaged = compressed.clone();
+        damaged[HEADER_SIZE + BLOCK_HEADER_SIZE + 5] ^= 1;
+        std::fs::write(&path, &damaged).unwrap();
+        let (mut writer, consumed) = BlockWriter::resume(open()).unwrap();
+        assert_eq!(consumed, 0);
+        writer.write_all(&data).unwrap();
+        writer.finish().unwrap();
+        assert_eq!(
+            decompress_blocks(&std::fs::read(&path).unwrap()).unwrap(),
+            data
+        );
+
+        // A damaged length could have been the end marker, so the block before it is written
+        // again too
+        let mut torn = compressed.clone();
+        let first_len =
+            u32::from_le_bytes(compressed[HEADER_SIZE..HEADER_SIZE + 4].try_into().unwrap());
+        let second_block = HEADER_SIZE + BLOCK_HEADER_SIZE + first_len as usize;
+        torn[second_block..second_block + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        std::fs::write(&path, &torn).unwrap();
+        let (writer, consumed) = BlockWriter::resume(open()).unwrap();
+        assert_eq!(consumed, 0);
+        drop(writer);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), HEADER_SIZE as u64);
+
+        std::fs::write(&path, b"not a block stream").unwrap();
+        assert!(matches!(
+            BlockWriter::resume(open()),
+            Err(crate::Error::Block(Error::BadMagic))
+        ));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn damaged_block() {
+        let mut compressed = compress_blocks(&sample(), 4096).unwrap();
+        let second_block = {
+            let mut reader = BlockReader::new(&compressed).unwrap();
+            reader.next_block().unwrap();
+            reader.position
+        };
+        compressed[second_block + BLOCK_HEADER_SIZE + 3] ^= 0x10;
+
+        assert!(matches!(
+            decompress_blocks(&compressed),
+            Err(crate::Error::Block(Error::ChecksumMismatch(1)))
+        ));
+        let verification = verify_blocks(&compressed).unwrap();
+        assert_eq!(verification.blocks, 1);
+        assert_eq!(verification.verified_len, second_block);
+    }
+}
@@ -0,0 +1,93 @@
+use std::marker::PhantomData;
+use std::sync::OnceLock;
+
+/// Holds a value compressed, and decodes it the first time it's accessed. The decoded value is
+/// kept until [`evict`](HuffCell::evict) drops it again, so a large collection of values can stay
+/// compressed at rest and only the ones in use take up their full size.
+pub struct HuffCell<T> {
+    bytes: Vec<u8>,
+    value: OnceLock<T>,
+    _type: PhantomData<fn() -> T>,
+}
+
+impl<T> HuffCell<T> {
+    /// A cell holding a value compressed by [`huff`](crate::huff), which is only checked when the
+    /// value is first accessed
+    pub fn from_bytes(bytes: Vec<u8>) -> Self {
+        Self {
+            bytes,
+            value: OnceLock::new(),
+            _type: PhantomData,
+        }
+    }
+
+    /// The compressed value
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Take the compressed value out of the cell
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    /// Whether the value is currently held decoded
+    pub fn is_decoded(&self) -> bool {
+        self.value.get().is_some()
+    }
+
+    /// Drop the decoded value, if there is one, leaving only the compressed bytes
+    pub fn evict(&mut self) {
+        self.value.take();
+    }
+}
+
+#[cfg(feature = "encode")]
+impl<T: serde::Serialize> HuffCell<T> {
+    /// Compress `value` into a cell. The value itself isn't kept.
+    pub fn new(value: &T) -> Result<Self, crate::Error> {
+        Ok(Self::from_bytes(crate::huff(value)?))
+    }
+}
+
+#[cfg(feature = "decode")]
+impl<T: serde::de::DeserializeOwned> HuffCell<T> {
+    /// The value, decoding it if it isn't held decoded already
+    pub fn get(&self) -> Result<&T, crate::Error> {
+        if let Some(value) = self.value.get() {
+            return Ok(value);
+        }
+        let value = crate::puff(&self.bytes)?;
+        Ok(self.value.get_or_init(|| value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_on_access() {
+        let value: Vec<String> = (0..100).map(|i| format!("value {i}")).collect();
+        let mut cell = HuffCell::new(&value).unwrap();
+        assert!(!cell.is_decoded());
+        assert_eq!(cell.get().unwrap(), &value);
+        assert!(cell.is_decoded());
+        assert!(std::ptr::eq(cell.get().unwrap(), cell.get().unwrap()));
+
+        cell.evict();
+        assert!(!cell.is_decoded());
+        assert_eq!(cell.get().unwrap(), &value);
+        let bytes = cell.into_bytes();
+        assert_eq!(crate::puff::<Vec<String>>(&bytes).unwrap(), value);
+    }
+
+    #[test]
+    fn errors_on_access() {
+        let mut bytes = crate::huff(&vec!["value".to_owned(); 10]).unwrap();
+        bytes.truncate(3);
+        let cell = HuffCell::<Vec<String>>::from_bytes(bytes);
+        assert!(cell.get().is_err());
+        assert!(!cell.is_decoded());
+    }
+}
@@ -0,0 +1,164 @@
+//! Stable numeric codes for errors, for callers outside Rust and for telemetry, which can classify
+//! failures by number instead of parsing messages. A code never changes meaning once assigned, and
+//! new errors get new codes. Zero is never a code, so it can mean success across an FFI boundary.
+
+/// The kind of an [`Error`](crate::Error), as returned by
+/// [`Error::error_code`](crate::Error::error_code). Errors from modules behind features have codes
+/// whether or not the features are enabled, so the codes are the same in every build. With the
+/// `wasm` feature, the codes are exported to JavaScript as an enum too.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "wasm", wasm_bindgen::prelude::wasm_bindgen)]
+#[non_exhaustive]
+#[repr(u32)]
+pub enum ErrorCode {
+    Bincode = 1,
+    Huffman = 2,
+    Frame = 3,
+    File = 4,
+    Archive = 5,
+    Arrow = 6,
+    Block = 7,
+    Columnar = 8,
+    Crypto = 9,
+    Csv = 10,
+    Delta = 11,
+    Ints = 12,
+    Lines = 13,
+    Text = 14,
+    Ndjson = 15,
+    Negotiate = 16,
+    Ndarray = 17,
+    Packet = 18,
+    Records = 19,
+    Runs = 20,
+    Schema = 21,
+    Series = 22,
+    Signing = 23,
+    Transform = 24,
+    Transport = 25,
+    WebSocket = 26,
+    Io = 27,
+    TypeMismatch = 28,
+    MemoryLimit = 29,
+    AllocationFailed = 30,
+    TimedOut = 31,
+    DoesNotFit = 32,
+    Backend = 33,
+    LimitExceeded = 34,
+}
+
+impl ErrorCode {
+    /// Every code, in order
+    const ALL: [ErrorCode; 34] = [
+        ErrorCode::Bincode,
+        ErrorCode::Huffman,
+        ErrorCode::Frame,
+        ErrorCode::File,
+        ErrorCode::Archive,
+        ErrorCode::Arrow,
+        ErrorCode::Block,
+        ErrorCode::Columnar,
+        ErrorCode::Crypto,
+        ErrorCode::Csv,
+        ErrorCode::Delta,
+        ErrorCode::Ints,
+        ErrorCode::Lines,
+        ErrorCode::Text,
+        ErrorCode::Ndjson,
+        ErrorCode::Negotiate,
+        ErrorCode::Ndarray,
+        ErrorCode::Packet,
+        ErrorCode::Records,
+        ErrorCode::Runs,
+        ErrorCode::Schema,
+        ErrorCode::Series,
+        ErrorCode::Signing,
+        ErrorCode::Transform,
+        ErrorCode::Transport,
+        ErrorCode::WebSocket,
+        ErrorCode::Io,
+        ErrorCode::TypeMismatch,
+        ErrorCode::MemoryLimit,
+        ErrorCode::AllocationFailed,
+        ErrorCode::TimedOut,
+        ErrorCode::DoesNotFit,
+        ErrorCode::Backend,
+        ErrorCode::LimitExceeded,
+    ];
+
+    /// The error code with the number `code`, if there is one
+    pub fn from_u32(code: u32) -> Option<Self> {
+        Self::ALL.into_iter().find(|&known| known as u32 == code)
+    }
+
+    /// A short description of the errors with this code
+    pub fn message(self) -> &'static str {
+        match self {
+            ErrorCode::Bincode => "the value couldn't be serialized or deserialized",
+            ErrorCode::Huffman => "a huffman coded message is invalid",
+            ErrorCode::Frame => "a frame or its header is invalid",
+            ErrorCode::File => "a compressed file is invalid",
+            ErrorCode::Archive => "an archive is invalid",
+            ErrorCode::Arrow => "an Arrow array couldn't be compressed or decompressed",
+            ErrorCode::Block => "a block stream is invalid",
+            ErrorCode::Columnar => "columnar data is invalid",
+            ErrorCode::Crypto => "encryption or decryption failed",
+            ErrorCode::Csv => "compressed CSV is invalid",
+            ErrorCode::Delta => "a delta is invalid or doesn't match its base",
+            ErrorCode::Ints => "compressed integers are invalid",
+            ErrorCode::Lines => "compressed lines are invalid",
+            ErrorCode::Text => "compressed text is invalid",
+            ErrorCode::Ndjson => "compressed NDJSON is invalid",
+            ErrorCode::Negotiate => "no codec could be agreed on",
+            ErrorCode::Ndarray => "an array couldn't be compressed or decompressed",
+            ErrorCode::Packet => "a packet is invalid",
+            ErrorCode::Records => "a record stream is invalid",
+            ErrorCode::Runs => "a run length coded message is invalid",
+            ErrorCode::Schema => "a versioned value is invalid or can't be migrated",
+            ErrorCode::Series => "a time series is invalid",
+            ErrorCode::Signing => "a signature is missing or invalid",
+            ErrorCode::Transform => "a transform couldn't be reversed",
+            ErrorCode::Transport => "a transport message is invalid",
+            ErrorCode::WebSocket => "a WebSocket message is invalid",
+            ErrorCode::Io => "an I/O error occurred",
+            ErrorCode::TypeMismatch => "the data holds a different type than requested",
+            ErrorCode::MemoryLimit => "decoding would exceed the memory limit",
+            ErrorCode::AllocationFailed => "memory for the decoded data couldn't be allocated",
+            ErrorCode::TimedOut => "decoding didn't finish before the deadline",
+            ErrorCode::DoesNotFit => "the compressed value doesn't fit",
+            ErrorCode::Backend => "the value couldn't be serialized or deserialized by its backend",
+            ErrorCode::LimitExceeded => "the decompressed data is longer than the limit",
+        }
+    }
+}
+
+/// The description of the errors with the numeric code `code`, as returned by
+/// [`Error::code`](crate::Error::code), or `None` if no error has that code
+pub fn error_message(code: u32) -> Option<&'static str> {
+    ErrorCode::from_u32(code).map(ErrorCode::message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codes() {
+        for (i, code) in ErrorCode::ALL.into_iter().enumerate() {
+            assert_eq!(code as u32, i as u32 + 1);
+            assert_eq!(ErrorCode::from_u32(code as u32), Some(code));
+        }
+        assert_eq!(ErrorCode::from_u32(0), None);
+        assert_eq!(error_message(35), None);
+
+        let error = crate::puff::<String>(&[]).unwrap_err();
+        assert_eq!(error.error_code(), ErrorCode::Huffman);
+        assert_eq!(error.code(), 2);
+        assert_eq!(
+            error_message(29),
+            Some("decoding would exceed the memory limit")
+        );
+        assert_eq!(crate::Error::TimedOut.code(), 31);
+    }
+}
@@ -0,0 +1,975 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+use crate::bits::BitOrder;
+#[cfg(feature = "decode")]
+use crate::frame;
+use crate::huffman;
+
+/// A huffman tree trained ahead of time on sample data. Messages coded with a codebook leave out
+/// the tree, which otherwise dominates the size of small messages, and instead record the
+/// codebook's [id](Codebook::id). The same codebook is needed to decode them, so it is typically
+/// trained once and distributed to both sides with [`Codebook::to_bytes`]:
+///
+/// ```
+/// use huffnpuff::{Codebook, huff, huff_with_codebook, puff_with_codebook};
+///
+/// #[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq)]
+/// struct Reading {
+///     sensor: String,
+///     celsius: i16,
+/// }
+///
+/// let reading = |i: i16| Reading { sensor: format!("probe-{}", i % 4), celsius: 20 + i % 5 };
+/// let samples: Vec<Vec<u8>> = (0..100).map(|i| bincode::serialize(&reading(i)).unwrap()).collect();
+/// let samples: Vec<&[u8]> = samples.iter().map(Vec::as_slice).collect();
+/// let trained = Codebook::train(&samples);
+///
+/// // Stored or sent once, then loaded wherever messages are coded or decoded
+/// let codebook = Codebook::from_bytes(&trained.to_bytes())?;
+/// let compressed = huff_with_codebook(&reading(7), &codebook)?;
+/// assert!(compressed.len() < huff(&reading(7))?.len());
+/// assert_eq!(puff_with_codebook::<Reading>(&compressed, &codebook)?, reading(7));
+/// # Ok::<(), huffnpuff::Error>(())
+/// ```
+///
+/// Every byte value has a code in a trained codebook, including ones that never appeared in the
+/// samples, so any message can be coded with any codebook; it just won't compress well if its
+/// statistics differ from the samples. A codebook loaded from a tree that lacks some byte values,
+/// such as one taken from a compressed message, codes those bytes with an escape: the end of
+/// message code followed by the byte itself. [`CodebookStats::escapes`] counts how often that
+/// happens.
+///
+/// A codebook is never modified after it's trained or loaded, and coding only reads it, so one
+/// codebook can be shared by any number of threads, for example in an `Arc`, with no locking.
+///
+/// Preset codebooks can also be defined in a `const` or `static` by their code lengths, with
+/// [`Codebook::from_code_lengths`], so they're ready without any setup at startup.
+///
+/// Codebooks are equal when they code every message the same way, and hash by their
+/// [digest](Codebook::digest), so identical codebooks can be found in maps and sets, or shared
+/// with a [`CodebookCache`].
+#[derive(Debug, Clone)]
+pub struct Codebook {
+    code: Code,
+    id: u32,
+    digest: u64,
+}
+
+/// Distinguishes the two kinds of codebook in digests, since a preset and a loaded copy of it
+/// code differently
+const TREE_DIGEST: u8 = 0;
+const CANONICAL_DIGEST: u8 = 1;
+
+/// 64 bit FNV-1a of `kind` followed by `bytes`, which can be computed in a const
+const fn digest(kind: u8, bytes: &[u8]) -> u64 {
+    let mut digest = (0xcbf29ce484222325u64 ^ kind as u64).wrapping_mul(0x100000001b3);
+    let mut i = 0;
+    while i < bytes.len() {
+        digest = (digest ^ bytes[i] as u64).wrapping_mul(0x100000001b3);
+        i += 1;
+    }
+    digest
+}
+
+// Presets are built in consts, where the large variant can't be boxed
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Clone)]
+enum Code {
+    /// Trained or loaded, coding with the paths through the tree
+    Tree(huffman::Tree),
+    Canonical(huffman::Canonical),
+}
+
+impl Codebook {
+    /// Train a codebook on the byte frequencies of `samples`
+    #[cfg(feature = "encode")]
+    pub fn train<S: AsRef<[u8]>>(samples: impl IntoIterator<Item = S>) -> Self {
+        let mut trainer = CodebookTrainer::new();
+        for sample in samples {
+            trainer.add(sample.as_ref());
+        }
+        trainer.finish()
+    }
+
+    /// Like [`Codebook::train`], but counts the samples on several threads, for corpora large
+    /// enough that counting takes minutes. Threads take samples from `samples` a batch at a time,
+    /// and `progress` is called from them with the totals so far after each batch. The codebook is
+    /// the same as [`Codebook::train`] would make.
+    ///
+    /// Samples are taken from the iterator one at a time, so an iterator that reads files should
+    /// produce each file's contents cheaply; otherwise give each thread its own
+    /// [`CodebookTrainer`] and merge them.
+    #[cfg(all(feature = "encode", feature = "parallel"))]
+    pub fn train_parallel<I>(samples: I, progress: impl Fn(TrainingProgress) + Sync) -> Self
+    where
+        I: IntoIterator,
+        I::IntoIter: Send,
+        I::Item: AsRef<[u8]> + Send,
+    {
+        use std::sync::Mutex;
+        use std::sync::atomic::{AtomicU64, Ordering};
+
+        /// The number of samples a thread takes at a time
+        const BATCH: usize = 64;
+
+        let samples = Mutex::new(samples.into_iter());
+        let counted_samples = AtomicU64::new(0);
+        let counted_bytes = AtomicU64::new(0);
+        let threads = std::thread::available_parallelism().map_or(1, |threads| threads.get());
+        let trainers: Vec<CodebookTrainer> = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..threads)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut trainer = CodebookTrainer::new();
+                        loop {
+                            let batch: Vec<_> = samples
+                                .lock()
+                                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                                .by_ref()
+                                .take(BATCH)
+                                .collect();
+                            if batch.is_empty() {
+                                return trainer;
+                            }
+                            let bytes: usize =
+                                batch.iter().map(|sample| sample.as_ref().len()).sum();
+                            for sample in &batch {
+                                trainer.add(sample.as_ref());
+                            }
+                            progress(TrainingProgress {
+                                samples: counted_samples
+                                    .fetch_add(batch.len() as u64, Ordering::Relaxed)
+                                    + batch.len() as u64,
+                                bytes: counted_bytes.fetch_add(bytes as u64, Ordering::Relaxed)
+                                    + bytes as u64,
+                            });
+                        }
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().expect("counting samples doesn't panic"))
+                .collect()
+        });
+
+        let mut trainer = CodebookTrainer::new();
+        for other in &trainers {
+            trainer.merge(other);
+        }
+        trainer.finish()
+    }
+
+    fn new(tree: huffman::Tree) -> Self {
+        let serialized = tree.serialize();
+        Self {
+            code: Code::Tree(tree),
+            id: crc32fast::hash(&serialized),
+            digest: digest(TREE_DIGEST, &serialized),
+        }
+    }
+
+    /// A codebook with canonical codes of the given lengths, in bits, for each byte value followed
+    /// by the end of message, as in DEFLATE. Every length must be between 1 and 32, and together
+    /// they must make a complete code. Invalid lengths fail to compile in a const context and
+    /// panic otherwise:
+    ///
+    /// ```
+    /// use huffnpuff::Codebook;
+    ///
+    /// // 8 bit codes for every byte value but 255, which shares a 9 bit code's worth of space
+    /// // with the end of message
+    /// const LENGTHS: [u8; 257] = {
+    ///     let mut lengths = [8; 257];
+    ///     lengths[255] = 9;
+    ///     lengths[256] = 9;
+    ///     lengths
+    /// };
+    /// static PRESET: Codebook = Codebook::from_code_lengths(LENGTHS);
+    ///
+    /// let compressed = huffnpuff::huff_with_codebook(&"a tea set", &PRESET).unwrap();
+    /// let decoded: String = huffnpuff::puff_with_codebook(&compressed, &PRESET).unwrap();
+    /// assert_eq!(decoded, "a tea set");
+    /// ```
+    ///
+    /// The id is derived from the lengths, so it differs from that of a codebook loaded from this
+    /// one's [`to_bytes`](Codebook::to_bytes), although they code the same way.
+    pub const fn from_code_lengths(lengths: [u8; 257]) -> Self {
+        // 32 bit FNV-1a, as for fingerprints, which can be computed in a const
+        let mut id = 0x811c9dc5u32;
+        let mut i = 0;
+        while i < lengths.len() {
+            id = (id ^ lengths[i] as u32).wrapping_mul(0x01000193);
+            i += 1;
+        }
+        Self {
+            code: Code::Canonical(huffman::Canonical::new(lengths)),
+            id,
+            digest: digest(CANONICAL_DIGEST, &lengths),
+        }
+    }
+
+    /// Identifies the codebook in the header of messages coded with it. It is derived from the
+    /// codebook's contents, so the same codebook always has the same id.
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// A 64 bit digest of the codebook's contents, which is stable across platforms and releases.
+    /// Equal codebooks have equal digests, so it can key a cache of codebooks or tell whether two
+    /// services were given the same one. Unlike the [id](Codebook::id), it isn't stored in
+    /// messages, and is long enough that different codebooks are very unlikely to share it.
+    pub fn digest(&self) -> u64 {
+        self.digest
+    }
+
+    /// Serialize the codebook, to be loaded again with [`Codebook::from_bytes`]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.tree().serialize()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, crate::Error> {
+        Ok(Self::new(huffman::Tree::deserialize(bytes)?))
+    }
+
+    /// The codebook's tree as JSON, for visualizing what was learned from the samples. Every node
+    /// is an object with its `code` as a string of bits, which is empty for the root, and its
+    /// `count` if the codebook was trained rather than loaded. Inner nodes have `left` and `right`
+    /// children, and leaves have the byte `symbol` they stand for, or `"eom": true` for the end of
+    /// message.
+    pub fn to_json(&self) -> String {
+        self.tree().to_json()
+    }
+
+    /// The length of the code for each byte value, followed by that for the end of message, which
+    /// is what [`Codebook::from_code_lengths`] takes. Values that a trained codebook never saw
+    /// still have codes, so none of the lengths is zero.
+    pub fn code_lengths(&self) -> [u8; 257] {
+        match &self.code {
+            Code::Tree(tree) => tree.code_lengths(),
+            Code::Canonical(canonical) => canonical.lengths(),
+        }
+    }
+
+    /// Rust source for a `static` named `name` holding a preset with this codebook's
+    /// [code lengths](Codebook::code_lengths), for keeping a codebook in version control and
+    /// building it in with `include!`. The preset compresses exactly as well as this codebook,
+    /// but with canonical codes, so it has a different id and the two can't decode each other's
+    /// messages. Lengths over 32 bits, which only very skewed samples produce, make the source
+    /// fail to compile.
+    pub fn to_rust_source(&self, name: &str) -> String {
+        let mut source = format!(
+            "/// A preset codebook generated by huffnpuff, with the code lengths of codebook {:#010x}\n\
+             pub static {name}: huffnpuff::Codebook = huffnpuff::Codebook::from_code_lengths([\n",
+            self.id
+        );
+        for row in self.code_lengths().chunks(16) {
+            let row: Vec<String> = row.iter().map(u8::to_string).collect();
+            source.push_str(&format!("    {},\n", row.join(", ")));
+        }
+        source.push_str("]);\n");
+        source
+    }
+
+    /// The tree, which presets have to build
+    pub(crate) fn tree(&self) -> Cow<'_, huffman::Tree> {
+        match &self.code {
+            Code::Tree(tree) => Cow::Borrowed(tree),
+            Code::Canonical(canonical) => Cow::Owned(canonical.to_tree()),
+        }
+    }
+
+    /// Code `bytes` without including the tree, also returning whether any bytes had to be
+    /// escaped
+    #[cfg(feature = "encode")]
+    pub(crate) fn encode(&self, bytes: &[u8], order: BitOrder) -> (Vec<u8>, bool) {
+        match &self.code {
+            Code::Tree(tree) => tree.encode(bytes, order),
+            // Every length is at least one, so nothing needs escaping
+            Code::Canonical(canonical) => (canonical.encode(bytes, order), false),
+        }
+    }
+
+    /// Decode the first message coded with this codebook in `bytes`, also returning the number of
+    /// bytes that message occupied. `escapes` is whether the message was coded with escapes.
+    /// Decoding stops once the output is longer than `max_len`.
+    #[cfg(feature = "decode")]
+    pub(crate) fn decode_member(
+        &self,
+        bytes: &[u8],
+        order: BitOrder,
+        escapes: bool,
+        max_len: usize,
+    ) -> Result<(Vec<u8>, usize), crate::Error> {
+        match &self.code {
+            Code::Tree(tree) => tree.decode_member(bytes, order, escapes, max_len),
+            Code::Canonical(canonical) => canonical.decode_member(bytes, order, max_len),
+        }
+    }
+
+    /// The length of the code for `byte`, or for EOM if it's `None`
+    #[cfg(feature = "encode")]
+    fn code_len(&self, byte: Option<u8>) -> usize {
+        match &self.code {
+            Code::Tree(tree) => tree.code_len(byte),
+            Code::Canonical(canonical) => canonical.code_len(byte),
+        }
+    }
+}
+
+impl PartialEq for Codebook {
+    fn eq(&self, other: &Self) -> bool {
+        self.digest == other.digest
+            && match (&self.code, &other.code) {
+                (Code::Tree(a), Code::Tree(b)) => a.serialize() == b.serialize(),
+                (Code::Canonical(a), Code::Canonical(b)) => a.lengths() == b.lengths(),
+                _ => false,
+            }
+    }
+}
+
+impl Eq for Codebook {}
+
+impl Hash for Codebook {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.digest.hash(state);
+    }
+}
+
+/// Draws the codebook's tree, with the code and value of every leaf
+impl fmt::Display for Codebook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.tree().fmt(f)
+    }
+}
+
+/// Keeps the most recently used of a bounded number of codebooks, keyed by their
+/// [digest](Codebook::digest), so that many encoders and decoders given identical codebooks share
+/// one copy of it instead of each holding their own. Looking codebooks up updates the order of use,
+/// so a cache shared between threads needs a lock around it, such as a `Mutex`.
+#[derive(Debug)]
+pub struct CodebookCache {
+    capacity: usize,
+    /// Each codebook, with the tick at which it was last used
+    codebooks: HashMap<u64, (Arc<Codebook>, u64)>,
+    tick: u64,
+}
+
+impl CodebookCache {
+    /// A cache holding at most `capacity` codebooks, which must be more than zero
+    pub fn new(capacity: usize) -> Self {
+        assert!(
+            capacity > 0,
+            "a codebook cache must hold at least one codebook"
+        );
+        Self {
+            capacity,
+            codebooks: HashMap::new(),
+            tick: 0,
+        }
+    }
+
+    /// The cached codebook equal to `codebook`, or `codebook` itself after caching it, evicting the
+    /// least recently used codebook if the cache is full
+    pub fn insert(&mut self, codebook: Codebook) -> Arc<Codebook> {
+        self.tick += 1;
+        if let Some((cached, used)) = self.codebooks.get_mut(&codebook.digest)
+            && **cached == codebook
+        {
+            *used = self.tick;
+            return cached.clone();
+        }
+        if self.codebooks.len() == self.capacity
+            && !self.codebooks.contains_key(&codebook.digest)
+            && let Some(&oldest) = self
+                .codebooks
+                .iter()
+                .min_by_key(|(_, (_, used))| *used)
+                .map(|(digest, _)| digest)
+        {
+            self.codebooks.remove(&oldest);
+        }
+        let codebook = Arc::new(codebook);
+        self.codebooks
+            .insert(codebook.digest, (codebook.clone(), self.tick));
+        codebook
+    }
+
+    /// Load a codebook serialized by [`Codebook::to_bytes`], sharing the cached copy if there is
+    /// one
+    pub fn load(&mut self, bytes: &[u8]) -> Result<Arc<Codebook>, crate::Error> {
+        Ok(self.insert(Codebook::from_bytes(bytes)?))
+    }
+
+    /// The cached codebook with `digest`, if there is one
+    pub fn get(&mut self, digest: u64) -> Option<Arc<Codebook>> {
+        self.tick += 1;
+        let (codebook, used) = self.codebooks.get_mut(&digest)?;
+        *used = self.tick;
+        Some(codebook.clone())
+    }
+
+    pub fn len(&self) -> usize {
+        self.codebooks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.codebooks.is_empty()
+    }
+}
+
+/// The huffman tree embedded in a compressed message, for seeing what the encoder built. It is
+/// drawn by its [`Display`](fmt::Display) implementation.
+#[derive(Debug, Clone)]
+pub struct MessageTree {
+    tree: huffman::Tree,
+}
+
+impl MessageTree {
+    /// Read the tree of the first message in `bytes`. Messages that are stored as they are, coded
+    /// as runs or coded with a [`Codebook`] don't carry a tree, and give `None`.
+    #[cfg(feature = "decode")]
+    pub fn from_message(bytes: &[u8]) -> Result<Option<Self>, crate::Error> {
+        Ok(frame::embedded_tree(bytes)?.map(|tree| Self { tree }))
+    }
+}
+
+impl MessageTree {
+    /// The tree as JSON, in the same shape as [`Codebook::to_json`]. Trees read from messages
+    /// have no counts.
+    pub fn to_json(&self) -> String {
+        self.tree.to_json()
+    }
+}
+
+impl fmt::Display for MessageTree {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.tree.fmt(f)
+    }
+}
+
+/// Counts the byte frequencies of samples to train a [`Codebook`] on, for corpora that are
+/// gathered piece by piece. Trainers fed different parts of a corpus, for example on different
+/// threads or machines, can be merged, and make the same codebook as one trainer fed everything.
+#[cfg(feature = "encode")]
+#[derive(Debug, Clone)]
+pub struct CodebookTrainer {
+    frequencies: [u64; 256],
+    samples: u64,
+}
+
+#[cfg(feature = "encode")]
+impl CodebookTrainer {
+    pub fn new() -> Self {
+        Self {
+            frequencies: [0; 256],
+            samples: 0,
+        }
+    }
+
+    /// Count the bytes of one sample
+    pub fn add(&mut self, sample: &[u8]) {
+        for &byte in sample {
+            self.frequencies[byte as usize] += 1;
+        }
+        self.samples += 1;
+    }
+
+    /// Add the counts of another trainer to this one's
+    pub fn merge(&mut self, other: &CodebookTrainer) {
+        for (count, other) in self.frequencies.iter_mut().zip(other.frequencies) {
+            *count += other;
+        }
+        self.samples += other.samples;
+    }
+
+    pub fn samples(&self) -> u64 {
+        self.samples
+    }
+
+    /// The number of bytes counted, across all samples
+    pub fn bytes(&self) -> u64 {
+        self.frequencies.iter().sum()
+    }
+
+    /// Train a codebook on the samples counted so far
+    pub fn finish(&self) -> Codebook {
+        Codebook::new(huffman::Tree::train(&self.frequencies, self.samples))
+    }
+}
+
+#[cfg(feature = "encode")]
+impl Default for CodebookTrainer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// How far [`Codebook::train_parallel`] has got
+#[cfg(all(feature = "encode", feature = "parallel"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TrainingProgress {
+    /// The number of samples counted
+    pub samples: u64,
+    /// The number of bytes counted, across all samples
+    pub bytes: u64,
+}
+
+/// How well a codebook's code lengths fit the messages it's used for. A codebook is trained on
+/// samples, and as the data drifts away from them, messages take more bits than a tree trained on
+/// the data itself would need. Tracking that excess over time tells when a deployed codebook has
+/// gone stale and should be retrained.
+#[cfg(feature = "encode")]
+#[derive(Debug, Clone)]
+pub struct CodebookStats {
+    /// The code length of each byte value, and of EOM at the end
+    code_lens: [usize; 257],
+    frequencies: [u64; 256],
+    messages: u64,
+    /// The messages with escapes, each of which has a flag bit after its end marker
+    escaped_messages: u64,
+}
+
+#[cfg(feature = "encode")]
+impl CodebookStats {
+    /// Start collecting statistics about messages coded with `codebook`
+    pub fn new(codebook: &Codebook) -> Self {
+        let mut code_lens = [0; 257];
+        for (byte, len) in code_lens.iter_mut().enumerate() {
+            *len = codebook.code_len(u8::try_from(byte).ok());
+        }
+        Self {
+            code_lens,
+            frequencies: [0; 256],
+            messages: 0,
+            escaped_messages: 0,
+        }
+    }
+
+    /// Record a serialized message that was coded with the codebook
+    pub fn record(&mut self, message: &[u8]) {
+        let mut escaped = false;
+        for &byte in message {
+            self.frequencies[byte as usize] += 1;
+            escaped |= self.code_lens[byte as usize] == 0;
+        }
+        self.messages += 1;
+        self.escaped_messages += escaped as u64;
+    }
+
+    pub fn messages(&self) -> u64 {
+        self.messages
+    }
+
+    /// The number of bytes recorded, across all messages
+    pub fn symbols(&self) -> u64 {
+        self.frequencies.iter().sum()
+    }
+
+    /// The number of recorded bytes the codebook has no code for, which were escaped
+    pub fn escapes(&self) -> u64 {
+        self.counts()
+            .take(256)
+            .filter(|&(len, _)| len == 0)
+            .map(|(_, count)| count)
+            .sum()
+    }
+
+    /// The fraction of recorded bytes that were escaped. Escapes are costly, so a rate that isn't
+    /// close to zero means the codebook should be retrained with samples that include those bytes.
+    pub fn escape_rate(&self) -> f64 {
+        match self.symbols() {
+            0 => 0.0,
+            symbols => self.escapes() as f64 / symbols as f64,
+        }
+    }
+
+    /// The bits the codebook used for the recorded messages, including their end markers. An
+    /// escaped byte takes the end marker's code, a flag bit and the byte's 8 bits, and a message
+    /// with escapes a flag bit after its end marker.
+    pub fn coded_bits(&self) -> u64 {
+        let escape_len = self.code_lens[256] as u64 + 9;
+        let coded: u64 = self
+            .counts()
+            .map(|(len, count)| match len {
+                0 => escape_len * count,
+                len => len as u64 * count,
+            })
+            .sum();
+        coded + self.escaped_messages
+    }
+
+    /// The entropy of the recorded messages: the fewest bits any code based on their combined
+    /// byte frequencies could use. A tree trained on the recorded messages themselves gets close
+    /// to this.
+    pub fn optimal_bits(&self) -> f64 {
+        let total = (self.symbols() + self.messages) as f64;
+        self.counts()
+            .filter(|&(_, count)| count > 0)
+            .map(|(_, count)| count as f64 * (total / count as f64).log2())
+            .sum()
+    }
+
+    /// The bits spent beyond [`optimal_bits`](CodebookStats::optimal_bits)
+    pub fn excess_bits(&self) -> f64 {
+        self.coded_bits() as f64 - self.optimal_bits()
+    }
+
+    /// The excess as a fraction of the optimal size, so `0.1` means the codebook used 10% more
+    /// bits than needed. Codes are whole bits, and every byte value keeps a code in case it turns
+    /// up, so even data just like the samples has some excess; it's a growing ratio that means the
+    /// data has drifted.
+    pub fn excess_ratio(&self) -> f64 {
+        let optimal = self.optimal_bits();
+        if optimal == 0.0 {
+            return 0.0;
+        }
+        self.excess_bits() / optimal
+    }
+
+    /// The fraction of recorded bytes whose code is no longer than their ideal length, rounded up
+    /// to a whole bit, given how often they occurred. Bytes that were rare or missing in the
+    /// samples but common in the messages get long codes and count as misses, as do escaped
+    /// bytes.
+    pub fn hit_rate(&self) -> f64 {
+        let symbols = self.symbols();
+        if symbols == 0 {
+            return 1.0;
+        }
+        let total = (symbols + self.messages) as f64;
+        let hits: u64 = self
+            .counts()
+            .take(256)
+            .filter(|&(len, count)| {
+                count > 0 && len > 0 && len as f64 <= (total / count as f64).log2().ceil()
+            })
+            .map(|(_, count)| count)
+            .sum();
+        hits as f64 / symbols as f64
+    }
+
+    /// The code length and count of each byte value, then of EOM
+    fn counts(&self) -> impl Iterator<Item = (usize, u64)> + '_ {
+        let counts = self.frequencies.iter().copied().chain([self.messages]);
+        self.code_lens.iter().copied().zip(counts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serialize() {
+        let codebook = Codebook::train(["first sample", "second sample"]);
+        let loaded = Codebook::from_bytes(&codebook.to_bytes()).unwrap();
+        assert_eq!(loaded.id(), codebook.id());
+        assert_eq!(loaded.to_bytes(), codebook.to_bytes());
+
+        assert!(Codebook::from_bytes(&[]).is_err());
+    }
+
+    fn corpus() -> Vec<String> {
+        (0..5000)
+            .map(|i| format!("{{\"id\":{i},\"kind\":\"{}\"}}", ["click", "view"][i % 2]))
+            .collect()
+    }
+
+    #[test]
+    fn merged_trainers() {
+        let corpus = corpus();
+        let (first, second) = corpus.split_at(1234);
+        let mut trainer = CodebookTrainer::new();
+        first
+            .iter()
+            .for_each(|sample| trainer.add(sample.as_bytes()));
+        let mut other = CodebookTrainer::new();
+        second
+            .iter()
+            .for_each(|sample| other.add(sample.as_bytes()));
+        trainer.merge(&other);
+
+        assert_eq!(trainer.samples(), 5000);
+        assert_eq!(trainer.finish(), Codebook::train(&corpus));
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn train_parallel() {
+        let corpus = corpus();
+        let total: usize = corpus.iter().map(String::len).sum();
+        // Threads report in any order, so the totals only add up to the corpus at their largest
+        let most = std::sync::Mutex::new((0, 0));
+        let codebook = Codebook::train_parallel(&corpus, |progress| {
+            let mut most = most.lock().unwrap();
+            *most = (most.0.max(progress.samples), most.1.max(progress.bytes));
+        });
+        assert_eq!(codebook, Codebook::train(&corpus));
+        assert_eq!(most.into_inner().unwrap(), (5000, total as u64));
+    }
+
+    #[test]
+    fn shared_between_threads() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Codebook>();
+
+        let codebook = std::sync::Arc::new(Codebook::train(["thread 0", "thread 1", "thread 2"]));
+        let handles: Vec<_> = (0..4)
+            .map(|i| {
+                let codebook = codebook.clone();
+                std::thread::spawn(move || {
+                    let message = format!("message from thread {i}");
+                    let compressed = crate::huff_with_codebook(&message, &codebook).unwrap();
+                    let decoded: String =
+                        crate::puff_with_codebook(&compressed, &codebook).unwrap();
+                    assert_eq!(decoded, message);
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn equality() {
+        let samples = ["first sample", "second sample"];
+        let codebook = Codebook::train(samples);
+        let loaded = Codebook::from_bytes(&codebook.to_bytes()).unwrap();
+        assert_eq!(loaded, codebook);
+        assert_eq!(loaded.digest(), codebook.digest());
+        assert_ne!(Codebook::train(["other samples"]), codebook);
+
+        let set: std::collections::HashSet<_> = [codebook, loaded, PRESET.clone()].into();
+        assert_eq!(set.len(), 2);
+        // A preset and a loaded copy of it code differently
+        let copy = Codebook::from_bytes(&PRESET.to_bytes()).unwrap();
+        assert_ne!(copy, PRESET);
+        assert_ne!(copy.digest(), PRESET.digest());
+        assert_eq!(Codebook::from_code_lengths(TEXT), PRESET);
+        // Digests are stable, so can be stored and compared between releases
+        assert_eq!(PRESET.digest(), 0xb2c0_4968_1347_f530);
+    }
+
+    #[test]
+    fn cache() {
+        let tenants: Vec<Codebook> = (0..3)
+            .map(|i| Codebook::train([format!("tenant {i} data").repeat(i + 1)]))
+            .collect();
+        let mut cache = CodebookCache::new(2);
+        let first = cache.insert(tenants[0].clone());
+        let again = cache.load(&tenants[0].to_bytes()).unwrap();
+        assert!(Arc::ptr_eq(&first, &again));
+        assert_eq!(cache.len(), 1);
+
+        cache.insert(tenants[1].clone());
+        // Using the first codebook makes the second the least recently used
+        assert!(cache.get(tenants[0].digest()).is_some());
+        cache.insert(tenants[2].clone());
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get(tenants[1].digest()).is_none());
+        assert!(Arc::ptr_eq(
+            &cache.get(tenants[0].digest()).unwrap(),
+            &first
+        ));
+        assert!(cache.get(tenants[2].digest()).is_some());
+    }
+
+    #[test]
+    fn display() {
+        let codebook = Codebook::train(["aaaaaaaab"]);
+        let drawn = codebook.to_string();
+        assert!(drawn.starts_with("root ("));
+        assert!(drawn.contains(" 'a' (8)\n"));
+        // Loaded codebooks have no counts
+        let loaded = Codebook::from_bytes(&codebook.to_bytes())
+            .unwrap()
+            .to_string();
+        assert!(loaded.starts_with("root\n"));
+        assert_eq!(loaded.lines().count(), drawn.lines().count());
+    }
+
+    #[test]
+    fn message_tree() {
+        let message = "a message with a tree of its own".repeat(4);
+        let compressed = crate::huff(&message).unwrap();
+        let drawn = MessageTree::from_message(&compressed)
+            .unwrap()
+            .unwrap()
+            .to_string();
+        assert!(drawn.contains(" 'w'\n"));
+        assert!(drawn.contains(" EOM\n"));
+        let json = MessageTree::from_message(&compressed)
+            .unwrap()
+            .unwrap()
+            .to_json();
+        assert!(json.contains(&format!(r#""symbol":{}}}"#, b'w')));
+
+        let literal = crate::huff(&"short").unwrap();
+        assert!(MessageTree::from_message(&literal).unwrap().is_none());
+    }
+
+    /// Short codes for lowercase text, then 8 bits for digits, most capitals and the punctuation
+    /// between them, which fills up the code, and 9 bits for everything else
+    const TEXT: [u8; 257] = {
+        let mut lengths = [9; 257];
+        let mut byte = b'a';
+        while byte <= b'z' {
+            lengths[byte as usize] = 6;
+            byte += 1;
+        }
+        lengths[b' ' as usize] = 4;
+        let mut byte = b'0';
+        while byte < b'Z' {
+            lengths[byte as usize] = 8;
+            byte += 1;
+        }
+        lengths
+    };
+    static PRESET: Codebook = Codebook::from_code_lengths(TEXT);
+
+    #[test]
+    fn preset() {
+        let message = "a preset codebook in read only memory, with no setup at startup".to_owned();
+        let compressed = crate::huff_with_codebook(&message, &PRESET).unwrap();
+        assert!(compressed.len() < bincode::serialized_size(&message).unwrap() as usize);
+        assert_eq!(
+            crate::puff_with_codebook::<String>(&compressed, &PRESET).unwrap(),
+            message
+        );
+
+        // A loaded copy codes the same way, but has its own id
+        let loaded = Codebook::from_bytes(&PRESET.to_bytes()).unwrap();
+        assert_ne!(loaded.id(), PRESET.id());
+        for order in [BitOrder::Lsb0, BitOrder::Msb0] {
+            let (coded, _) = PRESET.encode(message.as_bytes(), order);
+            assert_eq!(
+                loaded.encode(message.as_bytes(), order),
+                (coded.clone(), false)
+            );
+            let (decoded, len) = PRESET
+                .decode_member(&coded, order, false, usize::MAX)
+                .unwrap();
+            assert_eq!((decoded.as_slice(), len), (message.as_bytes(), coded.len()));
+        }
+        assert!(PRESET.to_string().contains(" 'a'\n"));
+        assert_eq!(Codebook::from_code_lengths(TEXT).id(), PRESET.id());
+    }
+
+    #[test]
+    fn rust_source() {
+        let samples: Vec<String> = (0..50).map(|i| format!("sensor {i}: nominal")).collect();
+        let codebook = Codebook::train(&samples);
+        let source = codebook.to_rust_source("SENSORS");
+        assert!(source.starts_with("/// A preset codebook"));
+        assert!(source.contains(
+            "pub static SENSORS: huffnpuff::Codebook = huffnpuff::Codebook::from_code_lengths([\n"
+        ));
+        assert!(source.ends_with("]);\n"));
+
+        // The numbers in the array are the code lengths, which make a working preset
+        let array = &source[source.find('[').unwrap() + 1..source.rfind(']').unwrap()];
+        let lengths: Vec<u8> = array
+            .split(|c: char| !c.is_ascii_digit())
+            .filter(|number| !number.is_empty())
+            .map(|number| number.parse().unwrap())
+            .collect();
+        assert_eq!(lengths, codebook.code_lengths());
+        let preset = Codebook::from_code_lengths(lengths.try_into().unwrap());
+        assert_eq!(preset.code_lengths(), codebook.code_lengths());
+        assert_eq!(
+            preset.to_rust_source("SENSORS").lines().count(),
+            source.lines().count()
+        );
+
+        let message = "sensor 51: nominal".to_owned();
+        let compressed = crate::huff_with_codebook(&message, &preset).unwrap();
+        assert_eq!(
+            compressed.len(),
+            crate::huff_with_codebook(&message, &codebook)
+                .unwrap()
+                .len()
+        );
+        assert_eq!(
+            crate::puff_with_codebook::<String>(&compressed, &preset).unwrap(),
+            message
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "complete code")]
+    fn preset_incomplete() {
+        let mut lengths = TEXT;
+        lengths[256] = 10;
+        Codebook::from_code_lengths(lengths);
+    }
+
+    #[test]
+    fn stats() {
+        let samples: Vec<String> = (0..100).map(|i| format!("reading {}", i % 10)).collect();
+        let codebook = Codebook::train(&samples);
+
+        let mut matching = CodebookStats::new(&codebook);
+        for sample in &samples {
+            matching.record(sample.as_bytes());
+        }
+        assert_eq!(matching.messages(), 100);
+        assert_eq!(matching.symbols(), 900);
+        assert!(matching.coded_bits() as f64 >= matching.optimal_bits());
+        assert!(matching.excess_ratio() < 0.2);
+        assert!(matching.hit_rate() > 0.9);
+
+        // Data unlike the samples takes many more bits than it needs
+        let mut drifted = CodebookStats::new(&codebook);
+        for i in 0..100 {
+            drifted.record(format!("ERROR #{}", i % 7).as_bytes());
+        }
+        assert!(drifted.excess_ratio() > 0.5);
+        assert!(drifted.hit_rate() < 0.5);
+    }
+
+    #[test]
+    fn escapes() {
+        // A tree taken from a message has codes only for the bytes in that message
+        let sample = bincode::serialize("abcabcabc").unwrap();
+        let tree = huffman::encode(&sample, BitOrder::Lsb0).unwrap();
+        let codebook = Codebook::from_bytes(&tree).unwrap();
+        assert!(codebook.code_lengths()[..256].contains(&0));
+
+        // Without anything to escape, messages are coded as they always were
+        let compressed = crate::huff_with_codebook(&"cbacbaabc", &codebook).unwrap();
+        assert!(!crate::frame::read_header(&compressed).unwrap().0.escapes);
+
+        let value = "abcabcabcabc!?".to_owned();
+        let mut stats = CodebookStats::new(&codebook);
+        stats.record(&bincode::serialize(&value).unwrap());
+        assert_eq!(stats.escapes(), 3);
+        assert_eq!(stats.escape_rate(), 3.0 / 22.0);
+        assert!(stats.hit_rate() < 1.0 - stats.escape_rate() + 1e-9);
+        // The header is the magic bytes, the version, two bytes of flags, the codebook id and the
+        // checksum
+        let compressed = crate::huff_with_codebook(&value, &codebook).unwrap();
+        assert_eq!(
+            compressed.len(),
+            14 + stats.coded_bits().div_ceil(8) as usize
+        );
+        assert_eq!(
+            crate::puff_with_codebook::<String>(&compressed, &codebook).unwrap(),
+            value
+        );
+
+        let bytes = bincode::serialize(&value).unwrap();
+        for order in [BitOrder::Lsb0, BitOrder::Msb0] {
+            let (coded, escapes) = codebook.encode(&bytes, order);
+            assert!(escapes);
+            let (decoded, len) = codebook
+                .decode_member(&coded, order, true, usize::MAX)
+                .unwrap();
+            assert_eq!((decoded, len), (bytes.clone(), coded.len()));
+        }
+    }
+}
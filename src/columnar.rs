@@ -0,0 +1,247 @@
+//! Compression of slices of similar values, such as a `Vec` of structs. Serialized one after
+//! another, the fields of each value are interleaved with those of the next, so a byte from an id
+//! sits between bytes of a name and a timestamp. Here each value is serialized on its own, and the
+//! bytes at the same position in every value are grouped together before the whole is compressed.
+//! For values that serialize to the same layout, like structs of numbers, that puts each field of
+//! every value side by side, where delta and run length coding turn counters and repeated values
+//! into long runs.
+
+use crate::frame;
+#[cfg(feature = "encode")]
+use crate::options::EncodeOptions;
+#[cfg(feature = "encode")]
+use crate::transform::Transform;
+use crate::varint;
+
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The columns don't hold the values their lengths describe
+    Malformed,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Error::Malformed => "the columns don't hold the values their lengths describe",
+        })
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Marks columnar slices of `T`, so they can't be mistaken for other values
+fn tag<T>() -> u32 {
+    crate::fingerprint(&format!(
+        "huffnpuff::columnar::{}",
+        std::any::type_name::<T>()
+    ))
+}
+
+/// Compress a slice of values with their bytes grouped by position, to be decoded with
+/// [`puff_columnar`]
+#[cfg(feature = "encode")]
+pub fn huff_columnar<T: serde::Serialize>(values: &[T]) -> Result<Vec<u8>, crate::Error> {
+    let rows = values
+        .iter()
+        .map(bincode::serialize)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // The count, then either the length all the values share plus one, or zero followed by the
+    // length of each value
+    let mut payload = Vec::new();
+    varint::write(rows.len() as u64, &mut payload);
+    let width = rows.iter().map(Vec::len).max().unwrap_or(0);
+    if rows.iter().all(|row| row.len() == width) {
+        varint::write(width as u64 + 1, &mut payload);
+    } else {
+        varint::write(0, &mut payload);
+        for row in &rows {
+            varint::write(row.len() as u64, &mut payload);
+        }
+    }
+    let mut starts = column_starts(rows.iter().map(Vec::len), width);
+    let columns_start = payload.len();
+    payload.resize(columns_start + starts.last().map_or(0, |&end| end), 0);
+    for row in &rows {
+        for (start, &byte) in starts.iter_mut().zip(row) {
+            payload[columns_start + *start] = byte;
+            *start += 1;
+        }
+    }
+
+    let header = frame::Header {
+        fingerprint: Some(tag::<T>()),
+        ..frame::Header::default()
+    };
+    let options = EncodeOptions {
+        transforms: vec![Transform::Delta, Transform::RunLength],
+        ..EncodeOptions::default()
+    };
+    frame::encode(&payload, &header, &options)
+}
+
+/// Decode a slice of values compressed with [`huff_columnar`]
+#[cfg(feature = "decode")]
+pub fn puff_columnar<T: serde::de::DeserializeOwned>(bytes: &[u8]) -> Result<Vec<T>, crate::Error> {
+    let frame = frame::decode(bytes)?;
+    if frame.header.fingerprint != Some(tag::<T>()) {
+        return Err(crate::Error::TypeMismatch);
+    }
+
+    let mut rest = frame.payload.as_slice();
+    let count = read_len(&mut rest)?;
+    // Only values that serialize to nothing can outnumber the bytes left, and a count that large
+    // is rejected rather than allocated for
+    if count > rest.len().max(1) * 8 {
+        return Err(Error::Malformed.into());
+    }
+    let lengths = match read_len(&mut rest)? {
+        0 => (0..count)
+            .map(|_| read_len(&mut rest))
+            .collect::<Result<Vec<_>, _>>()?,
+        width => vec![width - 1; count],
+    };
+    let total = lengths
+        .iter()
+        .try_fold(0usize, |total, &len| total.checked_add(len))
+        .filter(|&total| total == rest.len())
+        .ok_or(Error::Malformed)?;
+
+    let width = lengths.iter().copied().max().unwrap_or(0);
+    let mut starts = column_starts(lengths.iter().copied(), width);
+    debug_assert_eq!(starts.last().map_or(0, |&end| end), total);
+    let rows: Vec<Vec<u8>> = lengths
+        .iter()
+        .map(|&len| {
+            starts[..len]
+                .iter_mut()
+                .map(|start| {
+                    *start += 1;
+                    rest[*start - 1]
+                })
+                .collect()
+        })
+        .collect();
+
+    rows.iter()
+        .map(|row| Ok(bincode::deserialize(row)?))
+        .collect()
+}
+
+/// Where each column starts among the grouped bytes of rows with the given lengths, followed by
+/// where the last one ends. Working these out up front means the bytes can be grouped, or put back
+/// into rows, in a single pass over the rows, however much longer one row is than the rest.
+fn column_starts(lengths: impl Iterator<Item = usize>, width: usize) -> Vec<usize> {
+    // How many rows end at each length, and from that how many rows reach each column
+    let mut ends = vec![0usize; width + 1];
+    let mut reaching = 0;
+    for len in lengths {
+        ends[len] += 1;
+        reaching += 1;
+    }
+    let mut starts = Vec::with_capacity(width + 1);
+    let mut start = 0;
+    for &ended in &ends {
+        starts.push(start);
+        reaching -= ended;
+        start += reaching;
+    }
+    starts
+}
+
+/// Read a varint holding a count or length
+#[cfg(feature = "decode")]
+fn read_len(rest: &mut &[u8]) -> Result<usize, Error> {
+    varint::read(rest)
+        .and_then(|len| usize::try_from(len).ok())
+        .ok_or(Error::Malformed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Reading {
+        sensor: u32,
+        timestamp: u64,
+        value: f32,
+    }
+
+    fn readings(count: u32) -> Vec<Reading> {
+        (0..count)
+            .map(|i| Reading {
+                sensor: i % 4,
+                timestamp: 1_700_000_000 + u64::from(i) * 10,
+                value: 20.0 + (i % 17) as f32 * 0.25,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn roundtrip() {
+        for count in [0, 1, 1000] {
+            let values = readings(count);
+            let compressed = huff_columnar(&values).unwrap();
+            assert_eq!(puff_columnar::<Reading>(&compressed).unwrap(), values);
+        }
+
+        // Values of different lengths
+        let names: Vec<String> = (0..200).map(|i| "x".repeat(i % 13)).collect();
+        let compressed = huff_columnar(&names).unwrap();
+        assert_eq!(puff_columnar::<String>(&compressed).unwrap(), names);
+    }
+
+    #[test]
+    fn one_long_value() {
+        // Grouping column by column over every row would take 10^10 steps here
+        let mut values = vec![Vec::new(); 100_000];
+        values[50_000] = vec![7u8; 100_000];
+        let compressed = huff_columnar(&values).unwrap();
+        assert_eq!(puff_columnar::<Vec<u8>>(&compressed).unwrap(), values);
+    }
+
+    #[test]
+    fn smaller_than_interleaved() {
+        let values = readings(5000);
+        let columnar = huff_columnar(&values).unwrap();
+        let interleaved = crate::huff(&values).unwrap();
+        let transformed = crate::transform::Pipeline::new()
+            .delta()
+            .run_length()
+            .huff(&values)
+            .unwrap();
+        assert!(columnar.len() * 4 < interleaved.len());
+        assert!(columnar.len() * 4 < transformed.len());
+    }
+
+    #[test]
+    fn malformed() {
+        let compressed = huff_columnar(&readings(10)).unwrap();
+        assert_eq!(
+            puff_columnar::<u32>(&compressed),
+            Err(crate::Error::TypeMismatch)
+        );
+
+        let header = frame::Header {
+            fingerprint: Some(tag::<u8>()),
+            ..frame::Header::default()
+        };
+        let options = EncodeOptions::default();
+        // Three values of one byte each, with only two bytes
+        let short = frame::encode(&[3, 2, 1, 2], &header, &options).unwrap();
+        assert_eq!(
+            puff_columnar::<u8>(&short),
+            Err(crate::Error::Columnar(Error::Malformed))
+        );
+        let lengths = frame::encode(&[2, 0, 1, 2, 7, 7], &header, &options).unwrap();
+        assert_eq!(
+            puff_columnar::<u8>(&lengths),
+            Err(crate::Error::Columnar(Error::Malformed))
+        );
+        let valid = frame::encode(&[2, 2, 7, 8], &header, &options).unwrap();
+        assert_eq!(puff_columnar::<u8>(&valid).unwrap(), [7, 8]);
+    }
+}
@@ -0,0 +1,351 @@
+//! Structural comparison of two compressed streams. When one output compresses worse than
+//! another, the sizes alone don't say why. Comparing the layout of the streams shows whether the
+//! headers grew, a transform was dropped, the trees got bigger, or the blocks were split
+//! differently, and comparing the trees shows which bytes got longer codes.
+
+use std::fmt;
+
+use crate::{block, frame, huffman};
+
+/// How a single frame is laid out
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameLayout {
+    /// The number of bytes the frame occupies
+    pub len: usize,
+    /// The number of bytes of the frame header, including the magic bytes and version byte
+    pub header_len: usize,
+    /// The number of bits of the tree at the start of the message, or `None` if the message
+    /// doesn't carry a tree of its own
+    pub tree_bits: Option<usize>,
+    /// The length of the payload once decoded
+    pub decoded_len: usize,
+    header: frame::Header,
+    code_lengths: Option<[u8; 257]>,
+}
+
+impl FrameLayout {
+    /// The number of bytes of the coded message, including its tree
+    pub fn message_len(&self) -> usize {
+        self.len - self.header_len
+    }
+
+    /// The length of the code for each byte value, followed by that for EOM, or `None` if the
+    /// message doesn't carry a tree of its own. Bytes that don't occur have no code, and length 0.
+    pub fn code_lengths(&self) -> Option<[u8; 257]> {
+        self.code_lengths
+    }
+}
+
+/// How a compressed stream is laid out: a single frame, or the frames of a block stream
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StreamLayout {
+    /// The number of bytes of the whole stream
+    pub len: usize,
+    /// The block size of a block stream, or `None` for a single frame
+    pub block_size: Option<u32>,
+    pub frames: Vec<FrameLayout>,
+}
+
+impl StreamLayout {
+    /// Read the layout of a frame, as produced by [`huff`](crate::huff) and its variants, or of a
+    /// block stream. Every frame is decoded to find its length, so frames coded with a
+    /// [`Codebook`](crate::Codebook) or encrypted can't be read.
+    pub fn read(bytes: &[u8]) -> Result<Self, crate::Error> {
+        let (block_size, frames) = match block::is_block_stream(bytes) {
+            true => {
+                let (block_size, frames) = block::block_frames(bytes)?;
+                (Some(block_size), frames)
+            }
+            false => (None, vec![bytes]),
+        };
+        Ok(Self {
+            len: bytes.len(),
+            block_size,
+            frames: frames
+                .into_iter()
+                .map(read_frame)
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    /// The total number of bytes of frame headers
+    pub fn header_len(&self) -> usize {
+        self.frames.iter().map(|frame| frame.header_len).sum()
+    }
+
+    /// The total number of bits of trees
+    pub fn tree_bits(&self) -> usize {
+        self.frames.iter().filter_map(|frame| frame.tree_bits).sum()
+    }
+
+    /// The number of bytes outside the frames, which are the stream and block headers of a block
+    /// stream, or anything following a single frame
+    pub fn framing_len(&self) -> usize {
+        self.len - self.frames.iter().map(|frame| frame.len).sum::<usize>()
+    }
+
+    /// The total length of the decoded payloads
+    pub fn decoded_len(&self) -> usize {
+        self.frames.iter().map(|frame| frame.decoded_len).sum()
+    }
+}
+
+fn read_frame(bytes: &[u8]) -> Result<FrameLayout, crate::Error> {
+    let decoded = frame::decode(bytes)?;
+    let (_, header_len) = frame::read_header(bytes)?;
+    let tree = frame::embedded_tree(bytes)?;
+    let tree_bits = match tree {
+        Some(_) if decoded.header.canonical => {
+            Some(huffman::read_canonical(&bytes[header_len..], decoded.header.bit_order)?.1)
+        }
+        Some(_) => Some(huffman::tree_bits(
+            &bytes[header_len..],
+            decoded.header.bit_order,
+        )?),
+        None => None,
+    };
+    Ok(FrameLayout {
+        len: decoded.len,
+        header_len,
+        tree_bits,
+        decoded_len: decoded.payload.len(),
+        header: decoded.header,
+        code_lengths: tree.map(|tree| tree.code_lengths()),
+    })
+}
+
+/// A header field that differs between the frames at the same position of two streams
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeaderChange {
+    /// The index of the frame in both streams
+    pub frame: usize,
+    pub field: &'static str,
+    pub before: String,
+    pub after: String,
+}
+
+/// A value whose code has a different length in the trees of the frames at the same position of
+/// two streams
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodeChange {
+    /// The index of the frame in both streams
+    pub frame: usize,
+    /// The byte value, or `None` for the end of message
+    pub symbol: Option<u8>,
+    /// The lengths of the code in bits, zero if the tree has no code for the value
+    pub before: u8,
+    pub after: u8,
+}
+
+/// The differences between two compressed streams, see [`compare_streams`]. Its
+/// [`Display`](fmt::Display) implementation writes a report of them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StreamDiff {
+    pub before: StreamLayout,
+    pub after: StreamLayout,
+    pub header_changes: Vec<HeaderChange>,
+    /// Only compared between frames that both carry a tree
+    pub code_changes: Vec<CodeChange>,
+}
+
+impl StreamDiff {
+    /// Whether the streams are laid out the same way, with the same headers and trees. Their
+    /// sizes may still differ, if the messages coded with those trees differ.
+    pub fn is_same_structure(&self) -> bool {
+        self.before.block_size == self.after.block_size
+            && self.before.frames.len() == self.after.frames.len()
+            && self.header_changes.is_empty()
+            && self.code_changes.is_empty()
+    }
+}
+
+/// Compare the layouts of two streams that [`StreamLayout::read`] can read, such as the output of
+/// two versions of an encoder for the same value. Frames are compared with the frame at the same
+/// position in the other stream.
+pub fn compare_streams(before: &[u8], after: &[u8]) -> Result<StreamDiff, crate::Error> {
+    let before = StreamLayout::read(before)?;
+    let after = StreamLayout::read(after)?;
+
+    let mut header_changes = Vec::new();
+    let mut code_changes = Vec::new();
+    for (index, (old, new)) in before.frames.iter().zip(&after.frames).enumerate() {
+        let (a, b) = (&old.header, &new.header);
+        let mut compare = |field, before: String, after: String| {
+            if before != after {
+                header_changes.push(HeaderChange {
+                    frame: index,
+                    field,
+                    before,
+                    after,
+                });
+            }
+        };
+        compare(
+            "encoding",
+            format!("{:?}", a.encoding),
+            format!("{:?}", b.encoding),
+        );
+        compare(
+            "bit_order",
+            format!("{:?}", a.bit_order),
+            format!("{:?}", b.bit_order),
+        );
+        compare(
+            "transforms",
+            format!("{:?}", a.transforms),
+            format!("{:?}", b.transforms),
+        );
+        compare(
+            "fingerprint",
+            format!("{:?}", a.fingerprint),
+            format!("{:?}", b.fingerprint),
+        );
+        compare(
+            "schema_version",
+            format!("{:?}", a.schema_version),
+            format!("{:?}", b.schema_version),
+        );
+        let interval = |header: &frame::Header| {
+            format!("{:?}", header.checkpoints.as_ref().map(|c| c.interval))
+        };
+        compare("checkpoint_interval", interval(a), interval(b));
+
+        if let (Some(old_lengths), Some(new_lengths)) = (old.code_lengths, new.code_lengths) {
+            for (value, (&before, &after)) in old_lengths.iter().zip(&new_lengths).enumerate() {
+                if before != after {
+                    code_changes.push(CodeChange {
+                        frame: index,
+                        symbol: u8::try_from(value).ok(),
+                        before,
+                        after,
+                    });
+                }
+            }
+        }
+    }
+
+    Ok(StreamDiff {
+        before,
+        after,
+        header_changes,
+        code_changes,
+    })
+}
+
+impl fmt::Display for StreamDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (before, after) = (&self.before, &self.after);
+        let mut line = |name: &str, before: usize, after: usize, unit: &str| {
+            let change = after as i128 - before as i128;
+            writeln!(f, "{name}: {before} -> {after} {unit} ({change:+})")
+        };
+        line("size", before.len, after.len, "bytes")?;
+        line(
+            "decoded",
+            before.decoded_len(),
+            after.decoded_len(),
+            "bytes",
+        )?;
+        line("frames", before.frames.len(), after.frames.len(), "")?;
+        line("headers", before.header_len(), after.header_len(), "bytes")?;
+        line("trees", before.tree_bits(), after.tree_bits(), "bits")?;
+        line(
+            "framing",
+            before.framing_len(),
+            after.framing_len(),
+            "bytes",
+        )?;
+        if before.block_size != after.block_size {
+            writeln!(
+                f,
+                "block size: {:?} -> {:?}",
+                before.block_size, after.block_size
+            )?;
+        }
+        for change in &self.header_changes {
+            writeln!(
+                f,
+                "frame {} {}: {} -> {}",
+                change.frame, change.field, change.before, change.after
+            )?;
+        }
+        for change in &self.code_changes {
+            match change.symbol {
+                Some(byte) => write!(f, "frame {} code for {:?}", change.frame, byte as char)?,
+                None => write!(f, "frame {} code for EOM", change.frame)?,
+            }
+            writeln!(f, ": {} -> {} bits", change.before, change.after)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{EncodeOptions, Pipeline};
+
+    fn text() -> String {
+        (0..300)
+            .map(|i| format!("reading {i} is {} degrees\n", i % 40))
+            .collect()
+    }
+
+    #[test]
+    fn identical() {
+        let compressed = crate::huff(&text()).unwrap();
+        let diff = compare_streams(&compressed, &compressed).unwrap();
+        assert!(diff.is_same_structure());
+        assert_eq!(diff.before, diff.after);
+        assert_eq!(diff.before.frames.len(), 1);
+        assert_eq!(diff.before.framing_len(), 0);
+        assert!(diff.before.tree_bits() > 0);
+    }
+
+    #[test]
+    fn changed_options() {
+        let before = crate::huff(&text()).unwrap();
+        let after = Pipeline::new().delta().huff(&text()).unwrap();
+        let diff = compare_streams(&before, &after).unwrap();
+        assert!(!diff.is_same_structure());
+        assert_eq!(
+            diff.header_changes,
+            [HeaderChange {
+                frame: 0,
+                field: "transforms",
+                before: "[]".to_owned(),
+                after: "[Delta]".to_owned(),
+            }]
+        );
+        assert!(!diff.code_changes.is_empty());
+        let report = diff.to_string();
+        assert!(report.contains("frame 0 transforms: [] -> [Delta]"));
+        assert!(report.contains(&format!("size: {} -> {} bytes", before.len(), after.len())));
+
+        let options = EncodeOptions {
+            checkpoint_interval: Some(64),
+            ..EncodeOptions::default()
+        };
+        let after = crate::huff_with_options(&text(), &options).unwrap();
+        let diff = compare_streams(&before, &after).unwrap();
+        assert_eq!(diff.header_changes[0].field, "checkpoint_interval");
+        assert!(diff.code_changes.is_empty());
+        assert!(diff.after.header_len() > diff.before.header_len());
+    }
+
+    #[test]
+    fn block_layouts() {
+        let bytes = text().into_bytes();
+        let before = crate::compress_blocks(&bytes, 4096).unwrap();
+        let after = crate::compress_blocks(&bytes, 1024).unwrap();
+        let diff = compare_streams(&before, &after).unwrap();
+        assert_eq!(diff.before.block_size, Some(4096));
+        assert_eq!(diff.after.frames.len(), bytes.len().div_ceil(1024));
+        assert_eq!(diff.before.decoded_len(), diff.after.decoded_len());
+        assert!(diff.after.framing_len() > diff.before.framing_len());
+        assert!(
+            diff.to_string()
+                .contains("block size: Some(4096) -> Some(1024)")
+        );
+    }
+}
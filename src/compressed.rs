@@ -0,0 +1,150 @@
+//! A typed handle on compressed bytes, for storing values compressed without losing track of
+//! their type
+
+use std::fmt;
+use std::marker::PhantomData;
+
+#[cfg(feature = "decode")]
+use serde::{Deserialize, Deserializer};
+use serde::{Serialize, Serializer};
+
+/// A value of type `T` compressed by [`huff`](crate::huff), which can only be unpacked as a `T`.
+/// Unlike a [`HuffCell`](crate::HuffCell), it never keeps the decoded value, so it's a plain
+/// handle on the bytes that can be cloned, compared and stored.
+///
+/// It serializes as the compressed bytes, so it can be a field of another struct, and the struct
+/// is serialized with the value still compressed. The bytes are only checked when the value is
+/// unpacked.
+pub struct Compressed<T> {
+    bytes: Vec<u8>,
+    _type: PhantomData<fn() -> T>,
+}
+
+impl<T> Compressed<T> {
+    /// Wrap bytes produced by [`huff`](crate::huff) from a `T`, such as a blob loaded from a
+    /// database. Nothing checks that they really hold a `T` until they're unpacked.
+    pub fn from_bytes(bytes: Vec<u8>) -> Self {
+        Self {
+            bytes,
+            _type: PhantomData,
+        }
+    }
+
+    /// The compressed value
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Take the compressed value out of the wrapper
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+#[cfg(feature = "encode")]
+impl<T: Serialize> Compressed<T> {
+    /// Compress `value`
+    pub fn pack(value: &T) -> Result<Self, crate::Error> {
+        Ok(Self::from_bytes(crate::huff(value)?))
+    }
+}
+
+#[cfg(feature = "decode")]
+impl<T: serde::de::DeserializeOwned> Compressed<T> {
+    /// Decompress the value
+    pub fn unpack(&self) -> Result<T, crate::Error> {
+        crate::puff(&self.bytes)
+    }
+}
+
+impl<T> Clone for Compressed<T> {
+    fn clone(&self) -> Self {
+        Self::from_bytes(self.bytes.clone())
+    }
+}
+
+impl<T> PartialEq for Compressed<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.bytes == other.bytes
+    }
+}
+
+impl<T> Eq for Compressed<T> {}
+
+impl<T> fmt::Debug for Compressed<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Compressed")
+            .field("type", &std::any::type_name::<T>())
+            .field("len", &self.bytes.len())
+            .finish()
+    }
+}
+
+impl<T> Serialize for Compressed<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(&self.bytes)
+    }
+}
+
+#[cfg(feature = "decode")]
+impl<'de, T> Deserialize<'de> for Compressed<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let bytes = deserializer.deserialize_bytes(crate::field::BytesVisitor)?;
+        Ok(Self::from_bytes(bytes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Row {
+        id: u64,
+        history: Compressed<Vec<String>>,
+    }
+
+    #[test]
+    fn pack_and_unpack() {
+        let history: Vec<String> = (0..50).map(|i| format!("state {}", i % 4)).collect();
+        let packed = Compressed::pack(&history).unwrap();
+        assert_eq!(packed.as_bytes(), crate::huff(&history).unwrap());
+        assert_eq!(packed.unpack().unwrap(), history);
+
+        let stored = packed.clone().into_bytes();
+        let loaded = Compressed::<Vec<String>>::from_bytes(stored);
+        assert_eq!(loaded, packed);
+        assert_eq!(loaded.unpack().unwrap(), history);
+    }
+
+    #[test]
+    fn nested() {
+        let history: Vec<String> = (0..50).map(|i| format!("state {}", i % 4)).collect();
+        let row = Row {
+            id: 3,
+            history: Compressed::pack(&history).unwrap(),
+        };
+        let serialized = bincode::serialize(&row).unwrap();
+        assert!(serialized.len() < bincode::serialized_size(&history).unwrap() as usize);
+        let deserialized: Row = bincode::deserialize(&serialized).unwrap();
+        assert_eq!(deserialized, row);
+        assert_eq!(deserialized.history.unpack().unwrap(), history);
+
+        // The whole row can be compressed as well
+        let row_compressed = crate::huff(&row).unwrap();
+        assert_eq!(crate::puff::<Row>(&row_compressed).unwrap(), row);
+    }
+
+    #[test]
+    fn damaged() {
+        let mut bytes = crate::huff(&vec!["value".to_owned(); 10]).unwrap();
+        bytes.truncate(3);
+        assert!(
+            Compressed::<Vec<String>>::from_bytes(bytes)
+                .unpack()
+                .is_err()
+        );
+    }
+}
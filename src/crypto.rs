@@ -0,0 +1,152 @@
+use chacha20poly1305::XChaCha20Poly1305;
+#[cfg(feature = "decode")]
+use chacha20poly1305::XNonce;
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+#[cfg(feature = "encode")]
+use chacha20poly1305::aead::{AeadCore, OsRng};
+
+#[cfg(feature = "encode")]
+use crate::bits::BitOrder;
+use crate::{frame, huffman};
+
+/// The size of the tag that authenticates an encrypted payload
+#[cfg(feature = "encode")]
+const TAG_SIZE: usize = 16;
+
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The data was not encrypted by [`crate::huff_encrypted`]
+    NotEncrypted,
+    /// The data could not be decrypted, because the key is wrong or the data was modified
+    AuthenticationFailed,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Error::NotEncrypted => "the data is not encrypted",
+            Error::AuthenticationFailed => "the data couldn't be decrypted with the key",
+        })
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Compress a value, then encrypt it with XChaCha20-Poly1305 under `key`. A random nonce is
+/// generated for each call and stored in the frame header along with the length of the
+/// encrypted payload; the whole header is authenticated as associated data.
+#[cfg(feature = "encode")]
+pub fn huff_encrypted<T>(value: &T, key: &[u8; 32]) -> Result<Vec<u8>, crate::Error>
+where
+    T: serde::Serialize,
+{
+    let bincoded_bytes = bincode::serialize(value)?;
+    let message = huffman::encode(&bincoded_bytes, BitOrder::Lsb0)?;
+
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let header = frame::Header {
+        sealed: Some(frame::Sealed {
+            nonce: nonce.into(),
+            len: (message.len() + TAG_SIZE) as u32,
+        }),
+        ..frame::Header::default()
+    };
+    let mut frame = frame::write_header(&header);
+
+    let cipher = XChaCha20Poly1305::new(key.into());
+    let sealed = cipher
+        .encrypt(
+            &nonce,
+            Payload {
+                msg: &message,
+                aad: &frame,
+            },
+        )
+        .expect("encrypting an in-memory buffer cannot fail");
+    frame.extend_from_slice(&sealed);
+
+    Ok(frame)
+}
+
+/// Decrypt and decode a buffer produced by [`huff_encrypted`]
+#[cfg(feature = "decode")]
+pub fn puff_encrypted<T>(bytes: &[u8], key: &[u8; 32]) -> Result<T, crate::Error>
+where
+    T: serde::de::DeserializeOwned,
+{
+    let (header, start) = frame::read_header(bytes)?;
+    let sealed = header.sealed.ok_or(Error::NotEncrypted)?;
+    let end = start
+        .checked_add(sealed.len as usize)
+        .filter(|&end| end <= bytes.len())
+        .ok_or(Error::AuthenticationFailed)?;
+
+    let cipher = XChaCha20Poly1305::new(key.into());
+    let message = cipher
+        .decrypt(
+            XNonce::from_slice(&sealed.nonce),
+            Payload {
+                msg: &bytes[start..end],
+                aad: &bytes[..start],
+            },
+        )
+        .map_err(|_| Error::AuthenticationFailed)?;
+
+    let (bincoded_bytes, _) = huffman::decode_member(&message, header.bit_order, usize::MAX)?;
+    Ok(bincode::deserialize(&bincoded_bytes)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: [u8; 32] = [7; 32];
+
+    #[test]
+    fn roundtrip() {
+        let message = "attack at dawn, attack at dawn, attack at dawn".to_owned();
+        let encrypted = huff_encrypted(&message, &KEY).unwrap();
+
+        assert_eq!(puff_encrypted::<String>(&encrypted, &KEY).unwrap(), message);
+        // Nonces are random, so the same value encrypts differently each time
+        assert_ne!(huff_encrypted(&message, &KEY).unwrap(), encrypted);
+    }
+
+    #[test]
+    fn wrong_key_or_tampering() {
+        let encrypted = huff_encrypted(&42u64, &KEY).unwrap();
+
+        assert!(matches!(
+            puff_encrypted::<u64>(&encrypted, &[8; 32]),
+            Err(crate::Error::Crypto(Error::AuthenticationFailed))
+        ));
+
+        // Every byte, including the header, is authenticated
+        for i in 1..encrypted.len() {
+            let mut tampered = encrypted.clone();
+            tampered[i] ^= 1;
+            assert!(puff_encrypted::<u64>(&tampered, &KEY).is_err());
+        }
+
+        assert!(matches!(
+            puff_encrypted::<u64>(&encrypted[..encrypted.len() - 1], &KEY),
+            Err(crate::Error::Crypto(Error::AuthenticationFailed))
+        ));
+    }
+
+    #[test]
+    fn plain_and_encrypted_are_not_confused() {
+        let encrypted = huff_encrypted(&42u64, &KEY).unwrap();
+        assert!(matches!(
+            crate::puff::<u64>(&encrypted),
+            Err(crate::Error::Frame(frame::Error::Encrypted))
+        ));
+
+        let plain = crate::huff(&42u64).unwrap();
+        assert!(matches!(
+            puff_encrypted::<u64>(&plain, &KEY),
+            Err(crate::Error::Crypto(Error::NotEncrypted))
+        ));
+    }
+}
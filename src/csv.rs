@@ -0,0 +1,443 @@
+//! Columnar compression of CSV files. Values in the same column tend to resemble each other far
+//! more than the values next to them in a row, so the file is split into columns and each column
+//! is coded on its own, as whichever of these is smallest:
+//!
+//! - the values as they are,
+//! - a dictionary of the distinct values and the index of each value, for categories,
+//! - the differences between consecutive values, for integers like ids and timestamps.
+//!
+//! The first row is kept apart, since it's usually a header that looks nothing like the rest.
+//! Decompression reproduces the file byte for byte, including quoting and line endings. Files
+//! that aren't a simple grid, such as ones with rows of different lengths, are compressed whole.
+
+use crate::frame;
+#[cfg(feature = "encode")]
+use crate::options::EncodeOptions;
+use crate::varint;
+
+/// The whole file is compressed as one block
+const WHOLE: u8 = 0;
+/// The file is split into columns
+const COLUMNS: u8 = 1;
+
+/// Lines end with `\r\n` rather than `\n`
+const CRLF: u8 = 1;
+/// The last line ends with a line ending
+const TRAILING_NEWLINE: u8 = 2;
+
+/// The values are stored as they are
+const TEXT: u8 = 0;
+/// The distinct values are stored once, followed by the index of each value
+const DICTIONARY: u8 = 1;
+/// The values are integers, stored as the differences between consecutive values
+const DELTA: u8 = 2;
+
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The data doesn't have the layout written by [`compress_csv`]
+    Malformed,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Error::Malformed => "the data doesn't have the layout of compressed CSV",
+        })
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// The rows of a CSV file, split into fields, with the layout needed to put them back together
+#[cfg(feature = "encode")]
+struct Grid<'a> {
+    rows: Vec<Vec<&'a [u8]>>,
+    flags: u8,
+}
+
+/// Split `bytes` into rows of fields. Quoted fields are kept with their quotes, so they are
+/// reproduced exactly. Returns `None` if the file isn't a grid with the same number of fields in
+/// every row and the same line ending on every line.
+#[cfg(feature = "encode")]
+fn parse(bytes: &[u8]) -> Option<Grid<'_>> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut crlf = None;
+    let mut quoted = false;
+    let mut start = 0;
+    for (i, &byte) in bytes.iter().enumerate() {
+        match byte {
+            b'"' => quoted = !quoted,
+            b',' if !quoted => {
+                row.push(&bytes[start..i]);
+                start = i + 1;
+            }
+            b'\n' if !quoted => {
+                let ends_with_cr = i > start && bytes[i - 1] == b'\r';
+                if *crlf.get_or_insert(ends_with_cr) != ends_with_cr {
+                    return None;
+                }
+                row.push(&bytes[start..i - ends_with_cr as usize]);
+                rows.push(std::mem::take(&mut row));
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    let trailing_newline = start == bytes.len();
+    if !trailing_newline {
+        row.push(&bytes[start..]);
+        rows.push(row);
+    }
+
+    let width = rows.first()?.len();
+    if rows.iter().any(|row| row.len() != width) {
+        return None;
+    }
+    let mut flags = 0;
+    if crlf == Some(true) {
+        flags |= CRLF;
+    }
+    if trailing_newline {
+        flags |= TRAILING_NEWLINE;
+    }
+    Some(Grid { rows, flags })
+}
+
+/// Compress a CSV file column by column. The result is decompressed with [`decompress_csv`].
+#[cfg(feature = "encode")]
+pub fn compress_csv(bytes: &[u8]) -> Result<Vec<u8>, crate::Error> {
+    let options = EncodeOptions::default();
+    let Some(grid) = parse(bytes) else {
+        let mut out = vec![WHOLE];
+        out.extend(compress(bytes, &options)?);
+        return Ok(out);
+    };
+
+    let (header, rows) = grid
+        .rows
+        .split_first()
+        .expect("a grid has at least one row");
+    let mut out = vec![COLUMNS, grid.flags];
+    varint::write(header.len() as u64, &mut out);
+    varint::write(rows.len() as u64, &mut out);
+    write_block(&encode_text(header), &options, &mut out)?;
+
+    for column in 0..header.len() {
+        let values: Vec<&[u8]> = rows.iter().map(|row| row[column]).collect();
+        let mut candidates = vec![(TEXT, encode_text(&values))];
+        if let Some(dictionary) = encode_dictionary(&values) {
+            candidates.push((DICTIONARY, dictionary));
+        }
+        if let Some(deltas) = encode_deltas(&values) {
+            candidates.push((DELTA, deltas));
+        }
+
+        let mut best: Option<(u8, Vec<u8>)> = None;
+        for (kind, encoded) in candidates {
+            let compressed = compress(&encoded, &options)?;
+            if best
+                .as_ref()
+                .is_none_or(|(_, best)| compressed.len() < best.len())
+            {
+                best = Some((kind, compressed));
+            }
+        }
+        let (kind, compressed) = best.expect("text is always a candidate");
+        out.push(kind);
+        varint::write(compressed.len() as u64, &mut out);
+        out.extend(compressed);
+    }
+    Ok(out)
+}
+
+#[cfg(feature = "encode")]
+fn write_block(
+    bytes: &[u8],
+    options: &EncodeOptions,
+    out: &mut Vec<u8>,
+) -> Result<(), crate::Error> {
+    let compressed = compress(bytes, options)?;
+    varint::write(compressed.len() as u64, out);
+    out.extend(compressed);
+    Ok(())
+}
+
+/// Compress one block. Empty blocks, such as the columns of a file with only a header, take no
+/// space at all.
+#[cfg(feature = "encode")]
+fn compress(bytes: &[u8], options: &EncodeOptions) -> Result<Vec<u8>, crate::Error> {
+    if bytes.is_empty() {
+        return Ok(Vec::new());
+    }
+    frame::encode(bytes, &frame::Header::default(), options)
+}
+
+#[cfg(feature = "decode")]
+fn decompress(bytes: &[u8]) -> Result<Vec<u8>, crate::Error> {
+    if bytes.is_empty() {
+        return Ok(Vec::new());
+    }
+    Ok(frame::decode(bytes)?.payload)
+}
+
+#[cfg(feature = "encode")]
+fn encode_text(values: &[&[u8]]) -> Vec<u8> {
+    let mut out = Vec::new();
+    for value in values {
+        varint::write(value.len() as u64, &mut out);
+        out.extend_from_slice(value);
+    }
+    out
+}
+
+/// Only worthwhile when values repeat, on average at least twice each
+#[cfg(feature = "encode")]
+fn encode_dictionary(values: &[&[u8]]) -> Option<Vec<u8>> {
+    let mut distinct: Vec<&[u8]> = Vec::new();
+    let mut indices = std::collections::HashMap::new();
+    let mut out = Vec::new();
+    for value in values {
+        let next = distinct.len();
+        let index = *indices.entry(*value).or_insert(next);
+        if index == next {
+            distinct.push(value);
+        }
+        varint::write(index as u64, &mut out);
+    }
+    if distinct.len() * 2 > values.len() {
+        return None;
+    }
+    let mut encoded = Vec::new();
+    varint::write(distinct.len() as u64, &mut encoded);
+    encoded.extend(encode_text(&distinct));
+    encoded.extend(out);
+    Some(encoded)
+}
+
+/// Only possible when every value is an integer written the way Rust writes it, so that it can be
+/// reproduced exactly
+#[cfg(feature = "encode")]
+fn encode_deltas(values: &[&[u8]]) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    let mut previous = 0i64;
+    for value in values {
+        let text = std::str::from_utf8(value).ok()?;
+        let number: i64 = text.parse().ok()?;
+        if number.to_string() != text {
+            return None;
+        }
+        varint::write(zigzag(number.wrapping_sub(previous)), &mut out);
+        previous = number;
+    }
+    Some(out)
+}
+
+#[cfg(feature = "encode")]
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+#[cfg(feature = "decode")]
+fn unzigzag(value: u64) -> i64 {
+    (value >> 1) as i64 ^ -((value & 1) as i64)
+}
+
+/// Decompress a CSV file produced by [`compress_csv`]
+#[cfg(feature = "decode")]
+pub fn decompress_csv(bytes: &[u8]) -> Result<Vec<u8>, crate::Error> {
+    let (&layout, rest) = bytes.split_first().ok_or(Error::Malformed)?;
+    match layout {
+        WHOLE => return decompress(rest),
+        COLUMNS => {}
+        _ => return Err(Error::Malformed.into()),
+    }
+
+    let (&flags, mut rest) = rest.split_first().ok_or(Error::Malformed)?;
+    let width = read_count(&mut rest)?;
+    let len = read_count(&mut rest)?;
+    let header = decode_text(&read_block(&mut rest)?, width)?;
+    if header.len() != width {
+        return Err(Error::Malformed.into());
+    }
+
+    let mut columns = Vec::with_capacity(width.min(rest.len()));
+    for _ in 0..width {
+        let (&kind, tail) = rest.split_first().ok_or(Error::Malformed)?;
+        rest = tail;
+        let block = read_block(&mut rest)?;
+        let values = match kind {
+            TEXT => decode_text(&block, len)?,
+            DICTIONARY => decode_dictionary(&block, len)?,
+            DELTA => decode_deltas(&block, len)?,
+            _ => return Err(Error::Malformed.into()),
+        };
+        if values.len() != len {
+            return Err(Error::Malformed.into());
+        }
+        columns.push(values);
+    }
+    if !rest.is_empty() {
+        return Err(Error::Malformed.into());
+    }
+
+    let newline: &[u8] = if flags & CRLF != 0 { b"\r\n" } else { b"\n" };
+    let mut out = Vec::new();
+    for row in 0..=len {
+        if row > 0 {
+            out.extend_from_slice(newline);
+        }
+        for column in 0..width {
+            if column > 0 {
+                out.push(b',');
+            }
+            match row {
+                0 => out.extend_from_slice(&header[column]),
+                _ => out.extend_from_slice(&columns[column][row - 1]),
+            }
+        }
+    }
+    if flags & TRAILING_NEWLINE != 0 {
+        out.extend_from_slice(newline);
+    }
+    Ok(out)
+}
+
+#[cfg(feature = "decode")]
+fn read_count(bytes: &mut &[u8]) -> Result<usize, Error> {
+    varint::read(bytes)
+        .and_then(|count| usize::try_from(count).ok())
+        .ok_or(Error::Malformed)
+}
+
+#[cfg(feature = "decode")]
+fn read_block(bytes: &mut &[u8]) -> Result<Vec<u8>, crate::Error> {
+    let len = read_count(bytes)?;
+    if len > bytes.len() {
+        return Err(Error::Malformed.into());
+    }
+    let (block, rest) = bytes.split_at(len);
+    *bytes = rest;
+    decompress(block)
+}
+
+#[cfg(feature = "decode")]
+fn read_values(bytes: &mut &[u8], len: usize) -> Result<Vec<Vec<u8>>, Error> {
+    let mut values = Vec::with_capacity(len.min(bytes.len()));
+    for _ in 0..len {
+        let value_len = read_count(bytes)?;
+        if value_len > bytes.len() {
+            return Err(Error::Malformed);
+        }
+        let (value, rest) = bytes.split_at(value_len);
+        values.push(value.to_vec());
+        *bytes = rest;
+    }
+    Ok(values)
+}
+
+#[cfg(feature = "decode")]
+fn decode_text(mut bytes: &[u8], len: usize) -> Result<Vec<Vec<u8>>, Error> {
+    let values = read_values(&mut bytes, len)?;
+    if !bytes.is_empty() {
+        return Err(Error::Malformed);
+    }
+    Ok(values)
+}
+
+#[cfg(feature = "decode")]
+fn decode_dictionary(mut bytes: &[u8], len: usize) -> Result<Vec<Vec<u8>>, Error> {
+    let distinct_len = read_count(&mut bytes)?;
+    let distinct = read_values(&mut bytes, distinct_len)?;
+    let mut values = Vec::with_capacity(len.min(bytes.len()));
+    for _ in 0..len {
+        let index = read_count(&mut bytes)?;
+        values.push(distinct.get(index).ok_or(Error::Malformed)?.clone());
+    }
+    Ok(values)
+}
+
+#[cfg(feature = "decode")]
+fn decode_deltas(mut bytes: &[u8], len: usize) -> Result<Vec<Vec<u8>>, Error> {
+    let mut values = Vec::with_capacity(len.min(bytes.len()));
+    let mut previous = 0i64;
+    for _ in 0..len {
+        let delta = varint::read(&mut bytes).ok_or(Error::Malformed)?;
+        previous = previous.wrapping_add(unzigzag(delta));
+        values.push(previous.to_string().into_bytes());
+    }
+    Ok(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn export(rows: usize) -> String {
+        let mut csv = "id,timestamp,region,status,message\n".to_owned();
+        for i in 0..rows {
+            csv += &format!(
+                "{},{},{},{},\"item {}, checked\"\n",
+                1000 + i,
+                1_700_000_000 + i * 15,
+                ["eu-west", "us-east", "ap-south"][i % 3],
+                ["ok", "ok", "ok", "retry"][i % 4],
+                i * 7 % 13,
+            );
+        }
+        csv
+    }
+
+    #[test]
+    fn roundtrip() {
+        for csv in [
+            export(200),
+            export(0),
+            "a,b\r\n1,2\r\n-3,\"x\r\ny\"".to_owned(),
+            "only".to_owned(),
+            ",\n,\n".to_owned(),
+            "007,+5,-0\n1,2,3\n".to_owned(),
+            "\"unterminated,quote\n1,2\n".to_owned(),
+        ] {
+            let compressed = compress_csv(csv.as_bytes()).unwrap();
+            assert_eq!(compressed[0], COLUMNS, "{csv:?}");
+            assert_eq!(decompress_csv(&compressed).unwrap(), csv.as_bytes());
+        }
+    }
+
+    #[test]
+    fn smaller_than_rows() {
+        let csv = export(500);
+        let columnar = compress_csv(csv.as_bytes()).unwrap();
+        let whole = frame::encode(
+            csv.as_bytes(),
+            &frame::Header::default(),
+            &EncodeOptions::default(),
+        )
+        .unwrap();
+        assert!(columnar.len() * 2 < whole.len());
+    }
+
+    #[test]
+    fn not_a_grid() {
+        for csv in ["a,b\n1\n", "a\r\nb\n", ""] {
+            let compressed = compress_csv(csv.as_bytes()).unwrap();
+            assert_eq!(compressed[0], WHOLE, "{csv:?}");
+            assert_eq!(decompress_csv(&compressed).unwrap(), csv.as_bytes());
+        }
+    }
+
+    #[test]
+    fn malformed() {
+        let compressed = compress_csv(export(10).as_bytes()).unwrap();
+        assert!(matches!(
+            decompress_csv(&compressed[..compressed.len() - 1]),
+            Err(crate::Error::Csv(Error::Malformed))
+        ));
+        assert!(matches!(
+            decompress_csv(&[7]),
+            Err(crate::Error::Csv(Error::Malformed))
+        ));
+    }
+}
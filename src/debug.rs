@@ -0,0 +1,265 @@
+//! Context for decode errors, for triaging reports of corrupt data from the field without
+//! reproducing them under a debugger. The context says where in the input decoding failed and
+//! shows the bytes around that place, and for a damaged tree, how much of the tree could be read.
+
+use std::fmt;
+
+use crate::{frame, huffman};
+
+/// How many bytes of input are shown before and after the place decoding failed
+const WINDOW_RADIUS: usize = 32;
+
+/// The part of a frame in which decoding failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Location {
+    /// The magic bytes, version byte or header at the start of the frame
+    Header,
+    /// The huffman tree at the start of the frame's message
+    Tree,
+    /// The message, or the value serialized in it
+    Message,
+    /// The input ended before the frame did
+    End,
+}
+
+/// How much of a damaged tree could be read
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartialTree {
+    /// The number of bits of the tree's shape that were read
+    pub shape_bits: usize,
+    /// The number of leaves in the part of the shape that was read
+    pub leaves: usize,
+    /// The symbols of the leaves whose values were read, in order, `None` being the end of
+    /// message marker
+    pub symbols: Vec<Option<u8>>,
+    /// What's wrong with the tree
+    pub problem: &'static str,
+}
+
+/// Where decoding failed and the input around that place, see [`ErrorContext::new`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ErrorContext {
+    pub location: Location,
+    /// The offset into the input of the byte where decoding failed
+    pub byte_offset: usize,
+    /// The offset in bits of the failure, for failures inside a huffman coded message
+    pub bit_offset: Option<usize>,
+    /// The offset into the input of the first byte of `window`
+    pub window_start: usize,
+    /// Up to a few dozen bytes of input on either side of `byte_offset`
+    pub window: Vec<u8>,
+    /// The part of the tree that was read, if the tree is what's damaged
+    pub tree: Option<PartialTree>,
+}
+
+impl ErrorContext {
+    /// Work out where decoding the first frame of `bytes` failed with `error`. This re-reads the
+    /// input, so it costs nothing until there's an error, and can be used with the error of any of
+    /// the decoding functions. Failures in the serialized value can only be placed at the start
+    /// of the message.
+    pub fn new(bytes: &[u8], error: &crate::Error) -> Self {
+        let (location, byte_offset, bit_offset, tree) = locate(bytes, error);
+        let window_start = byte_offset.saturating_sub(WINDOW_RADIUS);
+        let window_end = byte_offset.saturating_add(WINDOW_RADIUS).min(bytes.len());
+        Self {
+            location,
+            byte_offset,
+            bit_offset,
+            window_start,
+            window: bytes[window_start.min(window_end)..window_end].to_vec(),
+            tree,
+        }
+    }
+
+    /// The window as a hexdump, 16 bytes to a line with their offsets and characters, marking the
+    /// byte where decoding failed
+    pub fn hexdump(&self) -> String {
+        let mut out = String::new();
+        for (row, chunk) in self.window.chunks(16).enumerate() {
+            let start = self.window_start + row * 16;
+            let hex: Vec<String> = chunk.iter().map(|byte| format!("{byte:02x}")).collect();
+            let text: String = chunk
+                .iter()
+                .map(|&byte| match byte {
+                    b' '..=b'~' => byte as char,
+                    _ => '.',
+                })
+                .collect();
+            out.push_str(&format!("{start:08x}  {:<47}  |{text}|\n", hex.join(" ")));
+            if (start..start + chunk.len()).contains(&self.byte_offset) {
+                let column = 10 + (self.byte_offset - start) * 3;
+                out.push_str(&format!("{:column$}^^\n", ""));
+            }
+        }
+        out
+    }
+}
+
+impl fmt::Display for ErrorContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "in the {:?} at byte {}", self.location, self.byte_offset)?;
+        if let Some(bit_offset) = self.bit_offset {
+            write!(f, " (bit {bit_offset})")?;
+        }
+        writeln!(f)?;
+        f.write_str(&self.hexdump())?;
+        if let Some(tree) = &self.tree {
+            writeln!(
+                f,
+                "tree: {}, after {} bits of shape with {} leaves",
+                tree.problem, tree.shape_bits, tree.leaves
+            )?;
+            let symbols: Vec<String> = tree
+                .symbols
+                .iter()
+                .map(|symbol| match symbol {
+                    Some(byte) => format!("{byte:02x}"),
+                    None => "EOM".to_owned(),
+                })
+                .collect();
+            writeln!(f, "symbols read: [{}]", symbols.join(" "))?;
+        }
+        Ok(())
+    }
+}
+
+/// A decode error along with its [`ErrorContext`], returned by [`puff_debug`]
+#[derive(Debug)]
+pub struct DebugError {
+    pub error: crate::Error,
+    pub context: Box<ErrorContext>,
+}
+
+impl fmt::Display for DebugError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // The error is returned by `source`, so only where it happened is described here
+        write!(f, "decoding failed {}", self.context)
+    }
+}
+
+impl std::error::Error for DebugError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// Like [`puff`](crate::puff), but errors come with the context of where decoding failed
+pub fn puff_debug<T>(bytes: &[u8]) -> Result<T, DebugError>
+where
+    T: serde::de::DeserializeOwned,
+{
+    crate::puff(bytes).map_err(|error| DebugError {
+        context: Box::new(ErrorContext::new(bytes, &error)),
+        error,
+    })
+}
+
+fn locate(
+    bytes: &[u8],
+    error: &crate::Error,
+) -> (Location, usize, Option<usize>, Option<PartialTree>) {
+    let end = (Location::End, bytes.len(), None, None);
+    let (header, start) = match frame::read_header(bytes) {
+        Ok(header) => header,
+        Err(_) if error.is_truncation() => return end,
+        Err(crate::Error::Frame(frame::Error::BadMagic)) => {
+            return (Location::Header, 0, None, None);
+        }
+        Err(crate::Error::Frame(frame::Error::UnsupportedVersion(_))) => {
+            return (Location::Header, frame::MAGIC.len(), None, None);
+        }
+        // Past the version byte, header errors are in the flags and the fields they announce
+        Err(_) => {
+            let flags = frame::MAGIC.len() + 1;
+            return (Location::Header, bytes.len().min(flags), None, None);
+        }
+    };
+
+    let tree = (header.encoding == frame::Encoding::Huffman
+        && header.codebook.is_none()
+        && !header.canonical
+        && header.sealed.is_none())
+    .then(|| huffman::read_partial_tree(&bytes[start..], header.bit_order));
+    match tree {
+        Some((bits, Some(tree))) => {
+            let bit_offset = start * 8 + bits;
+            (Location::Tree, bit_offset / 8, Some(bit_offset), Some(tree))
+        }
+        _ if error.is_truncation() => end,
+        Some((bits, None)) => (
+            Location::Message,
+            start + bits / 8,
+            Some(start * 8 + bits),
+            None,
+        ),
+        None => (Location::Message, start, None, None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn damaged_tree() {
+        let compressed =
+            crate::huff(&"some text to compress, long enough to code".repeat(4)).unwrap();
+        // The shape of the tree ends early, which leaves it without an EOM
+        let (_, start) = frame::read_header(&compressed).unwrap();
+        let mut damaged = compressed[..start].to_vec();
+        damaged.extend_from_slice(&[0; 8]);
+        let error = puff_debug::<String>(&damaged).unwrap_err();
+        assert!(error.error.is_corruption());
+        let source = std::error::Error::source(&error).unwrap();
+        assert_eq!(source.downcast_ref::<crate::Error>(), Some(&error.error));
+        assert!(error.to_string().starts_with("decoding failed in the "));
+
+        let context = error.context;
+        assert_eq!(context.location, Location::Tree);
+        let tree = context.tree.as_ref().unwrap();
+        assert_eq!(tree.shape_bits, 64);
+        assert_eq!(context.bit_offset, Some(start * 8 + 64));
+        assert_eq!(context.byte_offset, start + 8);
+        assert_eq!(context.window, damaged);
+        assert!(context.hexdump().starts_with("00000000  89 48 50"));
+        assert!(context.to_string().contains("tree: "));
+    }
+
+    #[test]
+    fn other_failures() {
+        let compressed = crate::huff(&"some text".repeat(20)).unwrap();
+
+        let error = puff_debug::<String>(&compressed[..1]).unwrap_err();
+        assert_eq!(error.context.location, Location::End);
+        assert_eq!(error.context.byte_offset, 1);
+
+        let mut future = compressed.clone();
+        future[frame::MAGIC.len()] = 0x7f;
+        let error = puff_debug::<String>(&future).unwrap_err();
+        assert_eq!(
+            (error.context.location, error.context.byte_offset),
+            (Location::Header, frame::MAGIC.len())
+        );
+        let error = puff_debug::<String>(&future[frame::MAGIC.len()..]).unwrap_err();
+        assert_eq!(
+            (error.context.location, error.context.byte_offset),
+            (Location::Header, 0)
+        );
+
+        // A literal message, which has no tree
+        let literal = crate::huff(&1u8).unwrap();
+        let error = puff_debug::<u64>(&literal).unwrap_err();
+        let (_, start) = frame::read_header(&literal).unwrap();
+        assert_eq!(
+            (error.context.location, error.context.byte_offset),
+            (Location::Message, start)
+        );
+
+        // Bytes that aren't UTF-8, which fail to deserialize after the message is decoded
+        let compressed = crate::huff(&[0xff, 0xfe, 0xff, 0xff].repeat(100)).unwrap();
+        let error = puff_debug::<String>(&compressed).unwrap_err();
+        assert_eq!(error.context.location, Location::Message);
+        assert!(error.context.bit_offset.is_some());
+        assert!(error.context.tree.is_none());
+    }
+}
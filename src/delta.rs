@@ -0,0 +1,154 @@
+//! Patches between two values of the same type. Consecutive values like game state snapshots are
+//! usually mostly the same, so instead of compressing the new value, the patch compresses the
+//! difference between the serialized old and new values, which is mostly zeros.
+//!
+//! The serialized values are compared byte by byte at the same offsets, so this works best for
+//! types whose fields serialize to fixed positions, like structs of numbers. A field that changes
+//! length, like a string that grows, shifts everything after it, which makes the rest of the patch
+//! as large as the value itself.
+
+use crate::frame;
+#[cfg(feature = "encode")]
+use crate::options::EncodeOptions;
+use crate::varint;
+
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The patch doesn't hold a length followed by that many bytes
+    Malformed,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Error::Malformed => "the patch is malformed",
+        })
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// The fingerprint of patches for values of type `T`. It differs from the fingerprint of `T`
+/// itself, so patches can't be mistaken for whole values or the other way around.
+fn tag<T>() -> u32 {
+    crate::fingerprint(&format!("delta:{}", std::any::type_name::<T>()))
+}
+
+/// Compress the changes from `old` to `new`, to be applied to `old` with [`puff_delta`]
+#[cfg(feature = "encode")]
+pub fn huff_delta<T>(old: &T, new: &T) -> Result<Vec<u8>, crate::Error>
+where
+    T: serde::Serialize,
+{
+    let old = bincode::serialize(old)?;
+    let new = bincode::serialize(new)?;
+
+    let mut patch = Vec::with_capacity(new.len() + 10);
+    varint::write(new.len() as u64, &mut patch);
+    patch.extend(
+        new.iter()
+            .enumerate()
+            .map(|(i, byte)| byte ^ old.get(i).copied().unwrap_or(0)),
+    );
+
+    let header = frame::Header {
+        fingerprint: Some(tag::<T>()),
+        ..frame::Header::default()
+    };
+    frame::encode(&patch, &header, &EncodeOptions::default())
+}
+
+/// Apply a patch produced by [`huff_delta`] to `old`, which must be the same value the patch was
+/// made from
+#[cfg(feature = "decode")]
+pub fn puff_delta<T>(old: &T, patch: &[u8]) -> Result<T, crate::Error>
+where
+    T: serde::Serialize + serde::de::DeserializeOwned,
+{
+    let frame = frame::decode(patch)?;
+    if frame.header.fingerprint != Some(tag::<T>()) {
+        return Err(crate::Error::TypeMismatch);
+    }
+
+    let mut rest = frame.payload.as_slice();
+    let len = varint::read(&mut rest).ok_or(Error::Malformed)?;
+    if len != rest.len() as u64 {
+        return Err(Error::Malformed.into());
+    }
+    let old = bincode::serialize(old)?;
+    let new: Vec<u8> = rest
+        .iter()
+        .enumerate()
+        .map(|(i, byte)| byte ^ old.get(i).copied().unwrap_or(0))
+        .collect();
+    Ok(bincode::deserialize(&new)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Snapshot {
+        tick: u64,
+        positions: Vec<(f32, f32)>,
+        health: Vec<u16>,
+    }
+
+    fn snapshot(tick: u64) -> Snapshot {
+        Snapshot {
+            tick,
+            positions: (0..64)
+                .map(|i| (i as f32, if i == tick as usize % 64 { 1.5 } else { 0.0 }))
+                .collect(),
+            health: vec![100; 64],
+        }
+    }
+
+    #[test]
+    fn roundtrip() {
+        let old = snapshot(1);
+        let new = snapshot(2);
+        let patch = huff_delta(&old, &new).unwrap();
+        assert_eq!(puff_delta(&old, &patch).unwrap(), new);
+
+        // Mostly unchanged values make much smaller patches than compressing the whole value
+        assert!(patch.len() * 4 < crate::huff(&new).unwrap().len());
+    }
+
+    #[test]
+    fn length_changes() {
+        let old = vec![1u32, 2, 3, 4, 5, 6, 7, 8];
+        for new in [
+            vec![1u32, 2, 3],
+            vec![1u32, 2, 3, 4, 5, 6, 7, 8, 9, 10],
+            vec![],
+        ] {
+            let patch = huff_delta(&old, &new).unwrap();
+            assert_eq!(puff_delta(&old, &patch).unwrap(), new);
+        }
+    }
+
+    #[test]
+    fn not_a_patch() {
+        let value = snapshot(1);
+        let whole = crate::huff(&value).unwrap();
+        assert!(matches!(
+            puff_delta(&value, &whole),
+            Err(crate::Error::TypeMismatch)
+        ));
+
+        let patch = huff_delta(&value, &snapshot(2)).unwrap();
+        assert!(matches!(
+            crate::puff::<Snapshot>(&patch),
+            Err(crate::Error::TypeMismatch)
+        ));
+        assert!(matches!(
+            puff_delta(&"another type".to_owned(), &patch),
+            Err(crate::Error::TypeMismatch)
+        ));
+    }
+}
@@ -0,0 +1,235 @@
+//! Adapters for the `embedded-io` and `embedded-io-async` traits, so the streaming types plug into
+//! embedded HALs and async drivers the way the std adapters plug into std
+
+use std::io;
+
+#[cfg(feature = "encode")]
+use crate::block::BlockWriter;
+#[cfg(feature = "decode")]
+use crate::{DecodedReader, block::PuffReader};
+
+/// Wraps a device implementing the `embedded-io` traits to implement [`io::Read`] and
+/// [`io::Write`], so a [`BlockWriter`] or [`PuffReader`] can write to or read from it directly
+pub struct FromEmbedded<T> {
+    inner: T,
+}
+
+impl<T> FromEmbedded<T> {
+    pub fn new(inner: T) -> Self {
+        Self { inner }
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+/// Device errors only have a kind in common, so their details are kept in the message
+fn to_io_error<E: embedded_io::Error>(error: E) -> io::Error {
+    io::Error::new(error.kind().into(), format!("{error:?}"))
+}
+
+impl<T: embedded_io::Read> io::Read for FromEmbedded<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf).map_err(to_io_error)
+    }
+}
+
+impl<T: embedded_io::Write> io::Write for FromEmbedded<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf).map_err(to_io_error)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush().map_err(to_io_error)
+    }
+}
+
+#[cfg(feature = "encode")]
+impl<W: io::Write> embedded_io::ErrorType for BlockWriter<W> {
+    type Error = io::Error;
+}
+
+#[cfg(feature = "encode")]
+impl<W: io::Write> embedded_io::Write for BlockWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        io::Write::write(self, buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::Write::flush(self)
+    }
+}
+
+#[cfg(feature = "decode")]
+impl<R: io::Read> embedded_io::ErrorType for PuffReader<R> {
+    type Error = io::Error;
+}
+
+#[cfg(feature = "decode")]
+impl<R: io::Read> embedded_io::Read for PuffReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        io::Read::read(self, buf)
+    }
+}
+
+#[cfg(feature = "decode")]
+impl embedded_io::ErrorType for DecodedReader<'_> {
+    type Error = io::Error;
+}
+
+#[cfg(feature = "decode")]
+impl embedded_io::Read for DecodedReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        io::Read::read(self, buf)
+    }
+}
+
+/// Decoding happens in memory, so reads never wait
+#[cfg(feature = "decode")]
+impl embedded_io_async::Read for DecodedReader<'_> {
+    async fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        io::Read::read(self, buf)
+    }
+}
+
+/// The `embedded-io-async` counterpart of [`BlockWriter`], writing a block stream to an async
+/// device. Blocks are compressed as they fill up and written out before the write that filled them
+/// returns. [`EmbeddedBlockWriter::finish`] must be called to write the final block and the end
+/// marker.
+#[cfg(feature = "encode")]
+pub struct EmbeddedBlockWriter<W> {
+    writer: W,
+    /// Compresses into a buffer that is drained into `writer`
+    blocks: BlockWriter<Vec<u8>>,
+}
+
+#[cfg(feature = "encode")]
+impl<W: embedded_io_async::Write> EmbeddedBlockWriter<W> {
+    pub fn new(writer: W, block_size: usize) -> Result<Self, crate::Error> {
+        Ok(Self {
+            writer,
+            blocks: BlockWriter::new(Vec::new(), block_size)?,
+        })
+    }
+
+    /// Write out everything that has been compressed so far
+    async fn drain(&mut self) -> io::Result<()> {
+        let pending = self.blocks.get_mut();
+        if !pending.is_empty() {
+            self.writer.write_all(pending).await.map_err(to_io_error)?;
+            pending.clear();
+        }
+        Ok(())
+    }
+
+    /// Write the final block and the end marker, returning the underlying device
+    pub async fn finish(mut self) -> Result<W, crate::Error> {
+        let rest = self.blocks.finish()?;
+        self.writer.write_all(&rest).await.map_err(to_io_error)?;
+        self.writer.flush().await.map_err(to_io_error)?;
+        Ok(self.writer)
+    }
+}
+
+#[cfg(feature = "encode")]
+impl<W> embedded_io::ErrorType for EmbeddedBlockWriter<W> {
+    type Error = io::Error;
+}
+
+#[cfg(feature = "encode")]
+impl<W: embedded_io_async::Write> embedded_io_async::Write for EmbeddedBlockWriter<W> {
+    async fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = io::Write::write(&mut self.blocks, buf)?;
+        self.drain().await?;
+        Ok(len)
+    }
+
+    /// Flushes the device. As with [`BlockWriter`], the partial block being buffered isn't
+    /// written.
+    async fn flush(&mut self) -> io::Result<()> {
+        self.drain().await?;
+        self.writer.flush().await.map_err(to_io_error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+
+    use super::*;
+
+    /// A device that accepts at most a few bytes per write, like a UART with a small FIFO
+    struct Uart {
+        sent: Vec<u8>,
+    }
+
+    impl embedded_io::ErrorType for Uart {
+        type Error = embedded_io::ErrorKind;
+    }
+
+    impl embedded_io::Write for Uart {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+            let len = buf.len().min(7);
+            self.sent.extend_from_slice(&buf[..len]);
+            Ok(len)
+        }
+
+        fn flush(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    impl embedded_io_async::Write for Uart {
+        async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+            embedded_io::Write::write(self, buf)
+        }
+    }
+
+    fn input() -> Vec<u8> {
+        b"sensor reading 21.5C, humidity 40%\n".repeat(200)
+    }
+
+    #[test]
+    fn blocking() {
+        let uart = FromEmbedded::new(Uart { sent: Vec::new() });
+        let mut writer = BlockWriter::new(uart, 1024).unwrap();
+        embedded_io::Write::write_all(&mut writer, &input()).unwrap();
+        let sent = writer.finish().unwrap().into_inner().sent;
+
+        let mut reader = PuffReader::new(sent.as_slice()).unwrap();
+        let mut decoded = vec![0; input().len()];
+        embedded_io::Read::read_exact(&mut reader, &mut decoded).unwrap();
+        assert_eq!(decoded, input());
+        assert_eq!(
+            embedded_io::Read::read(&mut reader, &mut [0; 8]).unwrap(),
+            0
+        );
+    }
+
+    #[test]
+    fn asynchronous() {
+        let sent = block_on(async {
+            let mut writer = EmbeddedBlockWriter::new(Uart { sent: Vec::new() }, 1024).unwrap();
+            embedded_io_async::Write::write_all(&mut writer, &input())
+                .await
+                .unwrap();
+            writer.finish().await.unwrap().sent
+        });
+        assert_eq!(crate::decompress_blocks(&sent).unwrap(), input());
+
+        let compressed = crate::huff(&"read without waiting".to_owned()).unwrap();
+        let mut reader = DecodedReader::new(&compressed).unwrap();
+        let mut decoded = vec![0; 28];
+        block_on(embedded_io_async::Read::read_exact(
+            &mut reader,
+            &mut decoded,
+        ))
+        .unwrap();
+        assert_eq!(&decoded[8..], b"read without waiting");
+    }
+}
@@ -0,0 +1,288 @@
+//! Per-field compression, for use with serde's field attributes. Control over how individual
+//! fields of a struct are treated goes through serde rather than a derive macro of this crate's
+//! own, so it works with any serde format:
+//!
+//! - `#[serde(with = "huffnpuff::field")]` compresses the field on its own, with a tree trained
+//!   only on that field
+//! - `#[serde(with = "huffnpuff::field::numeric")]` compresses a sequence of integers as
+//!   [`huff_ints`](crate::huff_ints) does, as differences from the integer before
+//! - [`dictionary::serialize`] and [`dictionary::deserialize`] compress the field with a shared
+//!   [`Codebook`](crate::Codebook), named by a type implementing [`Dictionary`], so short fields
+//!   don't each carry a tree
+//! - `#[serde(skip)]` leaves the field out entirely
+//!
+//! The other fields are serialized as usual. This is most useful when the rest of the struct isn't
+//! compressed at all, or when one field has very different statistics to the others:
+//!
+//! ```
+//! use serde::{Deserialize, Serialize};
+//!
+//! #[derive(Serialize, Deserialize, PartialEq, Debug)]
+//! struct Record {
+//!     hash: [u8; 32],
+//!     #[serde(with = "huffnpuff::field")]
+//!     log: String,
+//!     #[serde(with = "huffnpuff::field::numeric")]
+//!     timestamps: Vec<u64>,
+//!     #[serde(skip)]
+//!     cached_len: usize,
+//! }
+//!
+//! let record = Record {
+//!     hash: [0xa5; 32],
+//!     log: "INFO INFO INFO WARN INFO INFO".repeat(20),
+//!     timestamps: (0..100).map(|i| 1_700_000_000 + i * 15).collect(),
+//!     cached_len: 0,
+//! };
+//! let bytes = bincode::serialize(&record).unwrap();
+//! assert!(bytes.len() < 32 + record.log.len() + 8 * record.timestamps.len());
+//! assert_eq!(bincode::deserialize::<Record>(&bytes).unwrap(), record);
+//! ```
+
+#[cfg(feature = "decode")]
+use std::fmt;
+
+#[cfg(feature = "decode")]
+use serde::Deserializer;
+#[cfg(feature = "decode")]
+use serde::de::{self, DeserializeOwned, Visitor};
+#[cfg(feature = "encode")]
+use serde::{Serialize, Serializer};
+
+/// Serialize `value` as the bytes produced by [`huff`](crate::huff)
+#[cfg(feature = "encode")]
+pub fn serialize<T, S>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
+where
+    T: Serialize,
+    S: Serializer,
+{
+    let bytes = crate::huff(value).map_err(serde::ser::Error::custom)?;
+    serializer.serialize_bytes(&bytes)
+}
+
+/// Deserialize a value from bytes written by [`serialize`]
+#[cfg(feature = "decode")]
+pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+where
+    T: DeserializeOwned,
+    D: Deserializer<'de>,
+{
+    let bytes = deserializer.deserialize_bytes(BytesVisitor)?;
+    crate::puff(&bytes).map_err(de::Error::custom)
+}
+
+/// A codebook shared by every field that names it, for [`dictionary::serialize`] and
+/// [`dictionary::deserialize`]
+pub trait Dictionary {
+    /// The codebook fields are compressed with. It must stay the same for as long as data
+    /// compressed with it is kept.
+    fn codebook() -> &'static crate::Codebook;
+}
+
+/// Compression of a field with a shared [`Codebook`](crate::Codebook), which is named by a type
+/// implementing [`Dictionary`]. Serde's `with` can't name the dictionary, so the functions are
+/// given to `serialize_with` and `deserialize_with` instead:
+///
+/// ```
+/// use std::sync::LazyLock;
+///
+/// use huffnpuff::Codebook;
+/// use huffnpuff::field::Dictionary;
+/// use serde::{Deserialize, Serialize};
+///
+/// struct Logs;
+///
+/// impl Dictionary for Logs {
+///     fn codebook() -> &'static Codebook {
+///         static CODEBOOK: LazyLock<Codebook> =
+///             LazyLock::new(|| Codebook::train(["INFO started", "WARN slow", "INFO stopped"]));
+///         &CODEBOOK
+///     }
+/// }
+///
+/// #[derive(Serialize, Deserialize, PartialEq, Debug)]
+/// struct Event {
+///     id: u32,
+///     #[serde(
+///         serialize_with = "huffnpuff::field::dictionary::serialize::<Logs, _, _>",
+///         deserialize_with = "huffnpuff::field::dictionary::deserialize::<Logs, _, _>"
+///     )]
+///     message: String,
+/// }
+///
+/// let event = Event {
+///     id: 1,
+///     message: "INFO started".to_owned(),
+/// };
+/// let bytes = bincode::serialize(&event).unwrap();
+/// assert_eq!(bincode::deserialize::<Event>(&bytes).unwrap(), event);
+/// ```
+pub mod dictionary {
+    #[cfg(feature = "decode")]
+    use serde::Deserializer;
+    #[cfg(feature = "decode")]
+    use serde::de::{self, DeserializeOwned};
+    #[cfg(feature = "encode")]
+    use serde::{Serialize, Serializer};
+
+    use super::Dictionary;
+
+    /// Serialize `value` as the bytes produced by [`huff_with_codebook`](crate::huff_with_codebook)
+    /// with the codebook of `D`
+    #[cfg(feature = "encode")]
+    pub fn serialize<D, T, S>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        D: Dictionary,
+        T: Serialize,
+        S: Serializer,
+    {
+        let bytes =
+            crate::huff_with_codebook(value, D::codebook()).map_err(serde::ser::Error::custom)?;
+        serializer.serialize_bytes(&bytes)
+    }
+
+    /// Deserialize a value from bytes written by [`serialize`] with the same dictionary
+    #[cfg(feature = "decode")]
+    pub fn deserialize<'de, D, T, De>(deserializer: De) -> Result<T, De::Error>
+    where
+        D: Dictionary,
+        T: DeserializeOwned,
+        De: Deserializer<'de>,
+    {
+        let bytes = deserializer.deserialize_bytes(super::BytesVisitor)?;
+        crate::puff_with_codebook(&bytes, D::codebook()).map_err(de::Error::custom)
+    }
+}
+
+/// Compression of a field holding a sequence of integers, as [`huff_ints`](crate::huff_ints) does,
+/// for use with `#[serde(with = "huffnpuff::field::numeric")]`. Sorted or nearby values, like ids
+/// and timestamps, take about a byte each before they're compressed.
+pub mod numeric {
+    #[cfg(feature = "decode")]
+    use serde::Deserializer;
+    #[cfg(feature = "encode")]
+    use serde::Serializer;
+    #[cfg(feature = "decode")]
+    use serde::de;
+
+    /// Serialize `values` as the bytes produced by [`huff_ints`](crate::huff_ints)
+    #[cfg(feature = "encode")]
+    pub fn serialize<S: Serializer>(values: &[u64], serializer: S) -> Result<S::Ok, S::Error> {
+        let bytes = crate::huff_ints(values).map_err(serde::ser::Error::custom)?;
+        serializer.serialize_bytes(&bytes)
+    }
+
+    /// Deserialize integers from bytes written by [`serialize`]
+    #[cfg(feature = "decode")]
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u64>, D::Error> {
+        let bytes = deserializer.deserialize_bytes(super::BytesVisitor)?;
+        crate::puff_ints(&bytes).map_err(de::Error::custom)
+    }
+}
+
+/// Accepts bytes however the format chooses to represent them, since formats without a native
+/// byte string type deserialize them as a sequence
+#[cfg(feature = "decode")]
+pub(crate) struct BytesVisitor;
+
+#[cfg(feature = "decode")]
+impl<'de> Visitor<'de> for BytesVisitor {
+    type Value = Vec<u8>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("compressed bytes")
+    }
+
+    fn visit_bytes<E: de::Error>(self, bytes: &[u8]) -> Result<Vec<u8>, E> {
+        Ok(bytes.to_vec())
+    }
+
+    fn visit_byte_buf<E: de::Error>(self, bytes: Vec<u8>) -> Result<Vec<u8>, E> {
+        Ok(bytes)
+    }
+
+    fn visit_seq<A: de::SeqAccess<'de>>(self, mut seq: A) -> Result<Vec<u8>, A::Error> {
+        let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(byte) = seq.next_element()? {
+            bytes.push(byte);
+        }
+        Ok(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+
+    use crate::field::Dictionary;
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct Mixed {
+        id: u64,
+        #[serde(with = "crate::field")]
+        text: String,
+        #[serde(with = "crate::field")]
+        numbers: Vec<u32>,
+    }
+
+    #[test]
+    fn inside_envelope() {
+        let value = Mixed {
+            id: 7,
+            text: "abababababababababab".repeat(10),
+            numbers: vec![1; 100],
+        };
+        let compressed = crate::huff(&value).unwrap();
+        assert_eq!(crate::puff::<Mixed>(&compressed).unwrap(), value);
+    }
+
+    struct Names;
+
+    impl crate::field::Dictionary for Names {
+        fn codebook() -> &'static crate::Codebook {
+            static CODEBOOK: std::sync::LazyLock<crate::Codebook> =
+                std::sync::LazyLock::new(|| crate::Codebook::train(["alice", "bob", "carol"]));
+            &CODEBOOK
+        }
+    }
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct Modes {
+        #[serde(
+            serialize_with = "crate::field::dictionary::serialize::<Names, _, _>",
+            deserialize_with = "crate::field::dictionary::deserialize::<Names, _, _>"
+        )]
+        name: String,
+        #[serde(with = "crate::field::numeric")]
+        ids: Vec<u64>,
+        #[serde(skip)]
+        scratch: u8,
+    }
+
+    #[test]
+    fn modes() {
+        let value = Modes {
+            name: "carol".to_owned(),
+            ids: (1000..1200).collect(),
+            scratch: 0,
+        };
+        let bytes = bincode::serialize(&value).unwrap();
+        assert!(bytes.len() < 8 * value.ids.len() / 2);
+        assert_eq!(bincode::deserialize::<Modes>(&bytes).unwrap(), value);
+
+        let named = crate::huff_with_codebook(&value.name, Names::codebook()).unwrap();
+        assert!(bytes.windows(named.len()).any(|window| window == named));
+    }
+
+    #[test]
+    fn corrupt_field() {
+        let value = Mixed {
+            id: 7,
+            text: "text".to_owned(),
+            numbers: vec![],
+        };
+        let mut bytes = bincode::serialize(&value).unwrap();
+        bytes.truncate(20);
+        assert!(bincode::deserialize::<Mixed>(&bytes).is_err());
+    }
+}
@@ -0,0 +1,147 @@
+use std::fs;
+use std::path::Path;
+#[cfg(feature = "encode")]
+use std::{fs::File, io::Write, path::PathBuf};
+
+/// The size of the checksum that follows the compressed value
+#[cfg(feature = "decode")]
+const CHECKSUM_SIZE: usize = 4;
+
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The file is too short to hold a checksum
+    Truncated,
+    /// The file's contents don't match its checksum
+    ChecksumMismatch,
+    /// The path has no file name to write to
+    InvalidPath,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Error::Truncated => "the file is too short to hold a checksum",
+            Error::ChecksumMismatch => "the file doesn't match its checksum",
+            Error::InvalidPath => "the path has no file name",
+        })
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Persists compressed values to disk safely. Files hold the output of [`huff`](crate::huff)
+/// followed by a CRC-32 of it, little endian.
+pub struct HuffFile;
+
+impl HuffFile {
+    /// Compress `value` and write it to `path`, atomically replacing any existing file. The data
+    /// is written to a temporary file in the same directory, synced to disk, and then renamed over
+    /// `path`, so readers only ever see the old or the new contents, even after a crash.
+    #[cfg(feature = "encode")]
+    pub fn write<T>(path: impl AsRef<Path>, value: &T) -> Result<(), crate::Error>
+    where
+        T: serde::Serialize,
+    {
+        let path = path.as_ref();
+        let mut contents = crate::huff(value)?;
+        contents.extend_from_slice(&crc32fast::hash(&contents).to_le_bytes());
+
+        let temp_path = temp_path(path)?;
+        let written = File::create(&temp_path).and_then(|mut file| {
+            file.write_all(&contents)?;
+            file.sync_all()
+        });
+        if let Err(error) = written.and_then(|()| fs::rename(&temp_path, path)) {
+            let _ = fs::remove_file(&temp_path);
+            return Err(error.into());
+        }
+
+        // Sync the directory so the rename itself survives a crash. Directories can't be opened
+        // for syncing on every platform, so this is best effort.
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty())
+            && let Ok(dir) = File::open(dir)
+        {
+            let _ = dir.sync_all();
+        }
+        Ok(())
+    }
+
+    /// Read a value written by [`HuffFile::write`], verifying its checksum
+    #[cfg(feature = "decode")]
+    pub fn read<T>(path: impl AsRef<Path>) -> Result<T, crate::Error>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        let contents = fs::read(path)?;
+        let split = contents
+            .len()
+            .checked_sub(CHECKSUM_SIZE)
+            .ok_or(Error::Truncated)?;
+        let (compressed, checksum) = contents.split_at(split);
+        if crc32fast::hash(compressed).to_le_bytes() != checksum {
+            return Err(Error::ChecksumMismatch.into());
+        }
+        crate::puff(compressed)
+    }
+}
+
+/// A hidden file next to `path`, unique to this process
+#[cfg(feature = "encode")]
+fn temp_path(path: &Path) -> Result<PathBuf, Error> {
+    let name = path.file_name().ok_or(Error::InvalidPath)?;
+    let mut temp_name = std::ffi::OsString::from(".");
+    temp_name.push(name);
+    temp_name.push(format!(".{}.tmp", std::process::id()));
+    Ok(path.with_file_name(temp_name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("huffnpuff-{name}-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn roundtrip() {
+        let dir = test_dir("file-roundtrip");
+        let path = dir.join("value.hnp");
+
+        HuffFile::write(&path, &vec!["first"; 10]).unwrap();
+        HuffFile::write(&path, &vec!["second"; 10]).unwrap();
+        assert_eq!(
+            HuffFile::read::<Vec<String>>(&path).unwrap(),
+            vec!["second"; 10]
+        );
+
+        // Only the final file is left behind
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn corrupted() {
+        let dir = test_dir("file-corrupted");
+        let path = dir.join("value.hnp");
+        HuffFile::write(&path, &"some text".to_owned()).unwrap();
+
+        let mut contents = fs::read(&path).unwrap();
+        contents[3] ^= 1;
+        fs::write(&path, &contents).unwrap();
+        assert!(matches!(
+            HuffFile::read::<String>(&path),
+            Err(crate::Error::File(Error::ChecksumMismatch))
+        ));
+
+        fs::write(&path, [1, 2]).unwrap();
+        assert!(matches!(
+            HuffFile::read::<String>(&path),
+            Err(crate::Error::File(Error::Truncated))
+        ));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,1857 @@
+use std::time::Instant;
+
+use crate::bits::BitOrder;
+use crate::codebook::Codebook;
+#[cfg(feature = "decode")]
+use crate::options::DecodeOptions;
+#[cfg(feature = "encode")]
+use crate::options::{EncodeOptions, SymbolWidth};
+#[cfg(feature = "decode")]
+use crate::reserve;
+#[cfg(feature = "decode")]
+use crate::ring::{self, SplitBytes, SplitOut};
+use crate::transform::{self, Transform};
+use crate::{adaptive, huffman, runs, varint, words};
+
+/// The version of the frame format written by this library
+const VERSION: u8 = 3;
+
+/// Frames start with these bytes, then the version byte. The first has its lowest bit set and
+/// isn't the version byte of an older frame, so frames can't be mistaken for older messages, and
+/// has its highest bit set, so a transfer that strips the high bit is noticed.
+pub(crate) const MAGIC: [u8; 3] = [0x89, b'H', b'P'];
+
+/// The length of the magic bytes, version byte and flags byte of a frame with no optional fields
+pub(crate) const PLAIN_HEADER_LEN: usize = MAGIC.len() + 2;
+
+/// Before version 3, framed messages started with a version byte whose lowest bit is always set.
+/// Messages written before framing existed (version 0) start directly with the huffman tree, whose
+/// first bit is always 0 since the root of the tree is an inner node, so the two can always be
+/// told apart.
+const FRAME_MARKER: u8 = 1;
+
+/// Bits of the flags byte that follows the version byte, from version 2 onwards. Each flag
+/// indicates that an optional field is present in the header.
+const FLAG_FINGERPRINT: u8 = 1 << 0;
+const FLAG_SEALED: u8 = 1 << 1;
+const FLAG_MSB0: u8 = 1 << 2;
+const FLAG_RUNS: u8 = 1 << 3;
+const FLAG_LITERAL: u8 = 1 << 4;
+const FLAG_CODEBOOK: u8 = 1 << 5;
+const FLAG_TRANSFORMS: u8 = 1 << 6;
+/// A second flags byte follows the first, which has no bits left for new flags
+const FLAG_EXTENDED: u8 = 1 << 7;
+
+/// Bits of the second flags byte
+const FLAG_CHECKPOINTS: u8 = 1 << 0;
+const FLAG_SCHEMA_VERSION: u8 = 1 << 1;
+const FLAG_ALIGNED: u8 = 1 << 2;
+const FLAG_ESCAPES: u8 = 1 << 3;
+const FLAG_REPEAT: u8 = 1 << 4;
+const FLAG_CANONICAL: u8 = 1 << 5;
+const FLAG_CHECKSUM: u8 = 1 << 6;
+/// The message is coded by 16 bit words, see [`words`]. The first flags byte has neither the runs
+/// nor the literal flag.
+const FLAG_WORDS: u8 = 1 << 7;
+
+/// The largest alignment a frame can be padded to
+pub(crate) const MAX_ALIGNMENT: usize = 4096;
+
+/// The size of the nonce carried by sealed frames
+pub(crate) const NONCE_SIZE: usize = 24;
+
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The data doesn't start with the magic bytes of a frame, so it wasn't written by this
+    /// library, or was written before frames had them and is read by
+    /// [`puff_legacy`](crate::puff_legacy)
+    BadMagic,
+    /// The data was written by a newer version of the format than this library can read
+    UnsupportedVersion(u8),
+    /// The frame header uses flags that this library doesn't understand
+    UnsupportedFlags(u8),
+    /// The data ended in the middle of the frame header
+    TruncatedHeader,
+    /// The data ended in the middle of a literal message
+    TruncatedLiteral,
+    /// The message was coded with the codebook with this id, which wasn't provided
+    CodebookRequired(u32),
+    /// The message was coded with the codebook with this id, but a different one was provided
+    CodebookMismatch(u32),
+    /// The data was transformed with a transform this library doesn't know about
+    UnsupportedTransform(u8),
+    /// The table of a [`Remap`](crate::Remap) transform in the header isn't a bijection
+    InvalidRemap,
+    /// The decoder checkpoints in the header have an interval of zero
+    InvalidCheckpoints,
+    /// The payload of the frame is encrypted, and must be decoded with the key it was sealed with
+    Encrypted,
+    /// The data is in the current format, but was expected to be in one from before frames started
+    /// with magic bytes
+    NotLegacy,
+    /// The data ended in the padding after a message
+    TruncatedPadding,
+    /// The padding after a message isn't all zeros, or its alignment isn't valid
+    InvalidPadding,
+    /// The frame repeats an earlier frame of a buffer made of several, so it can only be decoded
+    /// along with the frames before it, by [`puff_all`](crate::puff_all) or
+    /// [`puff_iter`](crate::puff_iter)
+    Repeat,
+    /// The payload of the frame was transformed, and undoing the transforms needs memory of its
+    /// own, so it can't be decoded into a slice
+    Transformed,
+    /// The payload of the frame was coded by 16 bit words, whose code needs memory of its own, so
+    /// it can't be decoded into a slice
+    WordCoded,
+    /// The decoded payload doesn't match the checksum in the header
+    ChecksumMismatch,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Error::BadMagic => "the data is not a frame written by huffnpuff",
+            Error::UnsupportedVersion(version) => {
+                return write!(f, "the frame has unsupported format version {version}");
+            }
+            Error::UnsupportedFlags(flags) => {
+                return write!(f, "the frame header has unsupported flags {flags:#04x}");
+            }
+            Error::TruncatedHeader => "the data ends in the middle of the frame header",
+            Error::TruncatedLiteral => "the data ends in the middle of a literal message",
+            Error::CodebookRequired(id) => {
+                return write!(f, "the message needs codebook {id:#010x}");
+            }
+            Error::CodebookMismatch(id) => {
+                return write!(
+                    f,
+                    "the message was coded with codebook {id:#010x}, not the one provided"
+                );
+            }
+            Error::UnsupportedTransform(transform) => {
+                return write!(f, "the frame uses unknown transform {transform}");
+            }
+            Error::InvalidRemap => "the frame's remap table isn't a bijection",
+            Error::InvalidCheckpoints => "the frame's decoder checkpoints have an interval of zero",
+            Error::Encrypted => "the frame is encrypted",
+            Error::NotLegacy => "the data is in the current format, not a legacy one",
+            Error::TruncatedPadding => "the data ends in the padding after the message",
+            Error::InvalidPadding => "the padding after the message is invalid",
+            Error::Repeat => {
+                "the frame repeats an earlier frame, so it can't be decoded on its own"
+            }
+            Error::Transformed => {
+                "the frame's payload was transformed, so it can't be decoded into a slice"
+            }
+            Error::WordCoded => {
+                "the frame's payload was coded by words, so it can't be decoded into a slice"
+            }
+            Error::ChecksumMismatch => "the payload doesn't match the frame's checksum",
+        })
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// The optional fields that can be carried in a frame header
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct Header {
+    /// Identifies the type that was serialized into the payload
+    pub fingerprint: Option<u32>,
+    /// Present when the encoded message has been encrypted
+    pub sealed: Option<Sealed>,
+    /// How the bits of the encoded message are packed
+    pub bit_order: BitOrder,
+    /// How the message is encoded. This is chosen by [`encode`].
+    pub encoding: Encoding,
+    /// The id of the codebook a huffman coded message was coded with, in which case the message
+    /// doesn't include a tree
+    pub codebook: Option<u32>,
+    /// Whether the codebook message has escapes for bytes the codebook has no code for, see
+    /// [`Codebook`]
+    pub escapes: bool,
+    /// Whether a huffman coded message without a codebook starts with the lengths of its codes
+    /// instead of its tree, see [`huffman::encode_canonical`]
+    pub canonical: bool,
+    /// The transforms that were applied to the payload before coding it, in order
+    pub transforms: Vec<Transform>,
+    /// Where a decoder can start partway through a huffman coded message
+    pub checkpoints: Option<Checkpoints>,
+    /// The caller's version of the layout of the serialized value
+    pub schema_version: Option<u32>,
+    /// The frame is padded with zeros to a multiple of this many bytes, a power of two
+    pub alignment: Option<usize>,
+    /// The frame stands for the frame this many frames before it, and has no message of its own
+    pub repeat: Option<u64>,
+    /// The CRC-32 of the payload, before any transforms
+    pub checksum: Option<u32>,
+}
+
+/// The ways the message following a header can be encoded
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Encoding {
+    #[default]
+    Huffman,
+    /// A list of runs of repeated bytes, see [`runs`]
+    Runs,
+    /// The bytes themselves, preceded by their length as a varint
+    Literal,
+    /// Adaptive huffman coding, see [`adaptive`]. It has both the runs and literal flags.
+    Adaptive,
+    /// Huffman coding by 16 bit words, see [`words`]
+    Words,
+}
+
+/// Describes an encrypted payload, which directly follows the header
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Sealed {
+    pub nonce: [u8; NONCE_SIZE],
+    /// The length of the encrypted payload, including its authentication tag
+    pub len: u32,
+}
+
+/// Points in a huffman coded message from which decoding can start with the message's tree,
+/// instead of from the start. The `i`th offset is where the code for symbol `interval * (i + 1)`
+/// starts, in bits from the start of the message.
+///
+/// In the header, these are the interval and the number of offsets as varints, followed by the
+/// offsets, each as a varint of its distance from the one before.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Checkpoints {
+    pub interval: usize,
+    pub offsets: Vec<usize>,
+}
+
+impl Checkpoints {
+    #[cfg(feature = "encode")]
+    fn write(&self, out: &mut Vec<u8>) {
+        varint::write(self.interval as u64, out);
+        varint::write(self.offsets.len() as u64, out);
+        let mut previous = 0;
+        for &offset in &self.offsets {
+            varint::write((offset - previous) as u64, out);
+            previous = offset;
+        }
+    }
+
+    /// The number of bytes [`Checkpoints::write`] writes
+    #[cfg(feature = "encode")]
+    fn len(&self) -> usize {
+        let mut out = Vec::new();
+        self.write(&mut out);
+        out.len()
+    }
+
+    #[cfg(feature = "decode")]
+    fn read(bytes: &mut &[u8]) -> Result<Self, Error> {
+        let mut read = || -> Result<usize, Error> {
+            let value = varint::read(bytes).ok_or(Error::TruncatedHeader)?;
+            usize::try_from(value).map_err(|_| Error::InvalidCheckpoints)
+        };
+        let interval = read()?;
+        if interval == 0 {
+            return Err(Error::InvalidCheckpoints);
+        }
+        let count = read()?;
+        let mut offsets = Vec::new();
+        let mut previous = 0usize;
+        for _ in 0..count {
+            previous = previous
+                .checked_add(read()?)
+                .ok_or(Error::InvalidCheckpoints)?;
+            offsets.push(previous);
+        }
+        Ok(Self { interval, offsets })
+    }
+
+    /// The last checkpoint at or before output offset `offset`, as the output offset and bit
+    /// offset to start decoding from, or `None` if there isn't one after the start
+    #[cfg(feature = "decode")]
+    pub fn before(&self, offset: usize) -> Option<(usize, usize)> {
+        let index = (offset / self.interval).min(self.offsets.len());
+        let bit_offset = *self.offsets.get(index.checked_sub(1)?)?;
+        Some((index * self.interval, bit_offset))
+    }
+}
+
+impl Header {
+    #[cfg(feature = "encode")]
+    fn write(&self, out: &mut Vec<u8>) {
+        let mut flags = 0;
+        let mut extended_flags = 0;
+        if self.fingerprint.is_some() {
+            flags |= FLAG_FINGERPRINT;
+        }
+        if self.sealed.is_some() {
+            flags |= FLAG_SEALED;
+        }
+        if self.codebook.is_some() {
+            flags |= FLAG_CODEBOOK;
+        }
+        if !self.transforms.is_empty() {
+            flags |= FLAG_TRANSFORMS;
+        }
+        if self.checkpoints.is_some() {
+            extended_flags |= FLAG_CHECKPOINTS;
+        }
+        if self.schema_version.is_some() {
+            extended_flags |= FLAG_SCHEMA_VERSION;
+        }
+        if self.alignment.is_some() {
+            extended_flags |= FLAG_ALIGNED;
+        }
+        if self.escapes {
+            extended_flags |= FLAG_ESCAPES;
+        }
+        if self.repeat.is_some() {
+            extended_flags |= FLAG_REPEAT;
+        }
+        if self.canonical {
+            extended_flags |= FLAG_CANONICAL;
+        }
+        if self.checksum.is_some() {
+            extended_flags |= FLAG_CHECKSUM;
+        }
+        if self.bit_order == BitOrder::Msb0 {
+            flags |= FLAG_MSB0;
+        }
+        match self.encoding {
+            Encoding::Huffman => {}
+            Encoding::Runs => flags |= FLAG_RUNS,
+            Encoding::Literal => flags |= FLAG_LITERAL,
+            Encoding::Adaptive => flags |= FLAG_RUNS | FLAG_LITERAL,
+            Encoding::Words => extended_flags |= FLAG_WORDS,
+        }
+        if extended_flags != 0 {
+            out.extend_from_slice(&[flags | FLAG_EXTENDED, extended_flags]);
+        } else {
+            out.push(flags);
+        }
+
+        if let Some(fingerprint) = self.fingerprint {
+            out.extend_from_slice(&fingerprint.to_le_bytes());
+        }
+        if let Some(sealed) = &self.sealed {
+            out.extend_from_slice(&sealed.nonce);
+            out.extend_from_slice(&sealed.len.to_le_bytes());
+        }
+        if let Some(codebook) = self.codebook {
+            out.extend_from_slice(&codebook.to_le_bytes());
+        }
+        if !self.transforms.is_empty() {
+            out.push(self.transforms.len() as u8);
+            for transform in &self.transforms {
+                transform.write(out);
+            }
+        }
+        if let Some(checkpoints) = &self.checkpoints {
+            checkpoints.write(out);
+        }
+        if let Some(version) = self.schema_version {
+            varint::write(version as u64, out);
+        }
+        if let Some(alignment) = self.alignment {
+            out.push(alignment.trailing_zeros() as u8);
+        }
+        if let Some(distance) = self.repeat {
+            varint::write(distance, out);
+        }
+        if let Some(checksum) = self.checksum {
+            out.extend_from_slice(&checksum.to_le_bytes());
+        }
+    }
+
+    /// Read a header, returning it along with the number of bytes it occupied
+    #[cfg(feature = "decode")]
+    fn read(bytes: &[u8]) -> Result<(Self, usize), Error> {
+        let (&flags, mut rest) = bytes.split_first().ok_or(Error::TruncatedHeader)?;
+        let mut extended_flags = 0;
+        if flags & FLAG_EXTENDED != 0 {
+            // Every extended flag is in use, so there are no unknown ones to reject
+            let (&extended, tail) = rest.split_first().ok_or(Error::TruncatedHeader)?;
+            extended_flags = extended;
+            rest = tail;
+        }
+
+        let mut header = Header::default();
+        if flags & FLAG_MSB0 != 0 {
+            header.bit_order = BitOrder::Msb0;
+        }
+        header.encoding = match (flags & FLAG_RUNS != 0, flags & FLAG_LITERAL != 0) {
+            (false, false) => Encoding::Huffman,
+            (true, false) => Encoding::Runs,
+            (false, true) => Encoding::Literal,
+            (true, true) => Encoding::Adaptive,
+        };
+        if extended_flags & FLAG_WORDS != 0 {
+            if header.encoding != Encoding::Huffman {
+                return Err(Error::UnsupportedFlags(FLAG_WORDS));
+            }
+            header.encoding = Encoding::Words;
+        }
+        if flags & FLAG_FINGERPRINT != 0 {
+            let (fingerprint, tail) = rest.split_first_chunk().ok_or(Error::TruncatedHeader)?;
+            header.fingerprint = Some(u32::from_le_bytes(*fingerprint));
+            rest = tail;
+        }
+        if flags & FLAG_SEALED != 0 {
+            let (nonce, tail) = rest.split_first_chunk().ok_or(Error::TruncatedHeader)?;
+            let (len, tail) = tail.split_first_chunk().ok_or(Error::TruncatedHeader)?;
+            header.sealed = Some(Sealed {
+                nonce: *nonce,
+                len: u32::from_le_bytes(*len),
+            });
+            rest = tail;
+        }
+        if flags & FLAG_CODEBOOK != 0 {
+            let (codebook, tail) = rest.split_first_chunk().ok_or(Error::TruncatedHeader)?;
+            header.codebook = Some(u32::from_le_bytes(*codebook));
+            rest = tail;
+        }
+        if flags & FLAG_TRANSFORMS != 0 {
+            let (&count, tail) = rest.split_first().ok_or(Error::TruncatedHeader)?;
+            rest = tail;
+            header.transforms = (0..count)
+                .map(|_| Transform::read(&mut rest))
+                .collect::<Result<_, _>>()?;
+        }
+        if extended_flags & FLAG_CHECKPOINTS != 0 {
+            header.checkpoints = Some(Checkpoints::read(&mut rest)?);
+        }
+        if extended_flags & FLAG_SCHEMA_VERSION != 0 {
+            let version = varint::read(&mut rest).ok_or(Error::TruncatedHeader)?;
+            header.schema_version =
+                Some(u32::try_from(version).map_err(|_| Error::TruncatedHeader)?);
+        }
+        if extended_flags & FLAG_ALIGNED != 0 {
+            let (&shift, tail) = rest.split_first().ok_or(Error::TruncatedHeader)?;
+            let alignment = 1usize
+                .checked_shl(shift as u32)
+                .filter(|&alignment| alignment <= MAX_ALIGNMENT)
+                .ok_or(Error::InvalidPadding)?;
+            header.alignment = Some(alignment);
+            rest = tail;
+        }
+        header.escapes = extended_flags & FLAG_ESCAPES != 0;
+        header.canonical = extended_flags & FLAG_CANONICAL != 0;
+        if extended_flags & FLAG_REPEAT != 0 {
+            header.repeat = Some(varint::read(&mut rest).ok_or(Error::TruncatedHeader)?);
+        }
+        if extended_flags & FLAG_CHECKSUM != 0 {
+            let (checksum, tail) = rest.split_first_chunk().ok_or(Error::TruncatedHeader)?;
+            header.checksum = Some(u32::from_le_bytes(*checksum));
+            rest = tail;
+        }
+
+        Ok((header, bytes.len() - rest.len()))
+    }
+}
+
+/// A decoded frame
+#[cfg(feature = "decode")]
+#[derive(Debug)]
+pub(crate) struct Frame {
+    pub header: Header,
+    pub payload: Vec<u8>,
+    /// The number of bytes the encoded frame occupied, including any padding
+    pub len: usize,
+}
+
+/// Encode `bytes` in a frame with the optional fields of `header`, choosing the smallest
+/// encoding allowed by `options`
+#[cfg(feature = "encode")]
+pub(crate) fn encode(
+    bytes: &[u8],
+    header: &Header,
+    options: &EncodeOptions,
+) -> Result<Vec<u8>, crate::Error> {
+    encode_with_codebook(bytes, header, options, None)
+}
+
+/// Like [`encode`], but huffman codes the message with `codebook` instead of a tree of its own.
+/// Runs or a literal are still used when they're smaller.
+#[cfg(feature = "encode")]
+pub(crate) fn encode_with_codebook(
+    bytes: &[u8],
+    header: &Header,
+    options: &EncodeOptions,
+    codebook: Option<&Codebook>,
+) -> Result<Vec<u8>, crate::Error> {
+    Ok(encode_with_encoding(bytes, header, options, codebook)?.0)
+}
+
+/// Like [`encode_with_codebook`], also returning the encoding that was chosen
+#[cfg(feature = "encode")]
+pub(crate) fn encode_with_encoding(
+    bytes: &[u8],
+    header: &Header,
+    options: &EncodeOptions,
+    codebook: Option<&Codebook>,
+) -> Result<(Vec<u8>, Encoding), crate::Error> {
+    assert!(
+        options.transforms.len() <= u8::MAX as usize,
+        "too many transforms"
+    );
+    // Once the deadline passes, the remaining stages are skipped and the data is stored
+    let expired = || {
+        options
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+    };
+
+    let mut header = header.clone();
+    header.bit_order = options.bit_order;
+    header.alignment = options.alignment;
+    if let Some(alignment) = options.alignment {
+        assert!(
+            alignment.is_power_of_two() && alignment <= MAX_ALIGNMENT,
+            "alignment must be a power of two no more than {MAX_ALIGNMENT}"
+        );
+    }
+    if let Some(max) = options.max_code_len {
+        assert!(
+            (9..=huffman::MAX_CANONICAL_LEN as u8).contains(&max),
+            "max code length must be between 9 and {}",
+            huffman::MAX_CANONICAL_LEN
+        );
+    }
+    header.codebook = None;
+    header.escapes = false;
+    header.canonical = false;
+    header.checkpoints = None;
+    header.checksum = options.checksum.then(|| crc32fast::hash(bytes));
+    header.transforms = if expired() {
+        Vec::new()
+    } else {
+        options.transforms.clone()
+    };
+    let transformed;
+    let bytes = if header.transforms.is_empty() {
+        bytes
+    } else {
+        transformed = transform::apply_all(&header.transforms, bytes);
+        &transformed
+    };
+    let literal = || {
+        let mut message = Vec::with_capacity(bytes.len() + 1);
+        varint::write(bytes.len() as u64, &mut message);
+        message.extend_from_slice(bytes);
+        message
+    };
+
+    let message = if (codebook.is_none()
+        && !bytes.is_empty()
+        && bytes.len() < options.literal_threshold)
+        || expired()
+    {
+        header.encoding = Encoding::Literal;
+        literal()
+    } else if let Some(runs) = runs::encode_if_smaller(bytes) {
+        header.encoding = Encoding::Runs;
+        runs
+    } else {
+        let mut encoding = Encoding::Huffman;
+        let coded = match codebook {
+            Some(codebook) => {
+                let (coded, escapes) = codebook.encode(bytes, header.bit_order);
+                let own_tree = match options.hybrid_codebook && !bytes.is_empty() {
+                    true if options.fast_tree => {
+                        Some(huffman::encode_fast(bytes, header.bit_order)?)
+                    }
+                    true => Some(huffman::encode(bytes, header.bit_order)?),
+                    false => None,
+                };
+                match own_tree {
+                    Some(own_tree) if own_tree.len() < coded.len() => own_tree,
+                    _ => {
+                        header.codebook = Some(codebook.id());
+                        header.escapes = escapes;
+                        coded
+                    }
+                }
+            }
+            None if options.adaptive => {
+                encoding = Encoding::Adaptive;
+                adaptive::encode(bytes, header.bit_order)
+            }
+            None if options.symbol_width == SymbolWidth::Word
+                && let Some(coded) = words::encode(bytes, header.bit_order) =>
+            {
+                encoding = Encoding::Words;
+                coded
+            }
+            // Offsets into transformed data wouldn't help find a place in the output
+            None => match options.checkpoint_interval {
+                Some(interval)
+                    if header.transforms.is_empty() && options.max_code_len.is_none() =>
+                {
+                    assert!(interval > 0, "checkpoint interval must be more than zero");
+                    let (coded, offsets) = huffman::encode_with_checkpoints(
+                        bytes,
+                        header.bit_order,
+                        interval,
+                        options.fast_tree,
+                    )?;
+                    if !offsets.is_empty() {
+                        header.checkpoints = Some(Checkpoints { interval, offsets });
+                    }
+                    coded
+                }
+                _ => {
+                    let canonical = match options.canonical || options.max_code_len.is_some() {
+                        true => huffman::encode_canonical(
+                            bytes,
+                            header.bit_order,
+                            options.fast_tree,
+                            options.max_code_len,
+                        )?,
+                        false => None,
+                    };
+                    match canonical {
+                        Some(coded) => {
+                            header.canonical = true;
+                            coded
+                        }
+                        None if options.fast_tree => huffman::encode_fast(bytes, header.bit_order)?,
+                        None => huffman::encode(bytes, header.bit_order)?,
+                    }
+                }
+            },
+        };
+        let checkpoints_len = header.checkpoints.as_ref().map_or(0, Checkpoints::len);
+        // Data that doesn't compress is stored as it is, which bounds the size of a frame, see
+        // `max_len`
+        if literal_len(bytes.len()) < coded.len() + checkpoints_len {
+            header.encoding = Encoding::Literal;
+            header.codebook = None;
+            header.escapes = false;
+            header.canonical = false;
+            header.checkpoints = None;
+            literal()
+        } else {
+            header.encoding = encoding;
+            coded
+        }
+    };
+
+    let mut frame = write_header(&header);
+    frame.extend_from_slice(&message);
+    if let Some(alignment) = header.alignment {
+        frame.resize(frame.len().next_multiple_of(alignment), 0);
+    }
+    Ok((frame, header.encoding))
+}
+
+/// Frame a message huffman coded by the caller, or store the data it codes as a literal if that's
+/// smaller. `parts` are the coded bytes, in order.
+#[cfg(feature = "encode")]
+pub(crate) fn frame_message(message: Vec<u8>, parts: &[&[u8]]) -> Vec<u8> {
+    let mut header = Header::default();
+    let len = parts.iter().map(|part| part.len()).sum();
+    if literal_len(len) < message.len() {
+        header.encoding = Encoding::Literal;
+        let mut frame = write_header(&header);
+        varint::write(len as u64, &mut frame);
+        for part in parts {
+            frame.extend_from_slice(part);
+        }
+        frame
+    } else {
+        let mut frame = write_header(&header);
+        frame.extend(message);
+        frame
+    }
+}
+
+/// The size of a literal message holding `len` bytes
+pub(crate) fn literal_len(len: usize) -> usize {
+    varint::len(len as u64) + len
+}
+
+/// An upper bound on the size of a frame holding `len` bytes of data, as encoded by
+/// [`encode_with_codebook`] without transforms or encryption. The largest header has a
+/// fingerprint, a codebook id and a checksum, and the message is never larger than a literal one.
+pub(crate) fn max_len(len: usize) -> usize {
+    let header = MAGIC.len() + 1 + 2 + 4 + 4 + 4;
+    header + literal_len(len)
+}
+
+/// The magic bytes, version byte and header [`write_header`] writes for a default header with
+/// `encoding`, without allocating
+pub(crate) fn plain_header(encoding: Encoding) -> [u8; PLAIN_HEADER_LEN] {
+    let flags = match encoding {
+        Encoding::Huffman => 0,
+        Encoding::Runs => FLAG_RUNS,
+        Encoding::Literal => FLAG_LITERAL,
+        Encoding::Adaptive => FLAG_RUNS | FLAG_LITERAL,
+        Encoding::Words => panic!("word coded frames have a second flags byte"),
+    };
+    let [a, b, c] = MAGIC;
+    [a, b, c, VERSION, flags]
+}
+
+/// Like [`plain_header`], with a checksum of the payload
+#[cfg(feature = "encode")]
+pub(crate) fn checksummed_header(
+    encoding: Encoding,
+    checksum: u32,
+) -> [u8; PLAIN_HEADER_LEN + 1 + 4] {
+    let mut out = [0; PLAIN_HEADER_LEN + 1 + 4];
+    let (plain, rest) = out.split_at_mut(PLAIN_HEADER_LEN);
+    plain.copy_from_slice(&plain_header(encoding));
+    plain[PLAIN_HEADER_LEN - 1] |= FLAG_EXTENDED;
+    rest[0] = FLAG_CHECKSUM;
+    rest[1..].copy_from_slice(&checksum.to_le_bytes());
+    out
+}
+
+/// The magic bytes, version byte and header of a frame in the current format
+#[cfg(feature = "encode")]
+pub(crate) fn write_header(header: &Header) -> Vec<u8> {
+    let mut out = MAGIC.to_vec();
+    out.push(VERSION);
+    header.write(&mut out);
+    out
+}
+
+/// Whether `bytes` starts with a message written before frames started with magic bytes: one from
+/// before framing existed, which has no version byte or header, or a frame of version 1 or 2
+pub(crate) fn is_legacy(bytes: &[u8]) -> bool {
+    !bytes.starts_with(&MAGIC)
+        && bytes
+            .first()
+            .is_some_and(|&first| first & FRAME_MARKER == 0 || matches!(first >> 1, 1 | 2))
+}
+
+/// Read the magic bytes, version byte and header at the start of `bytes`. Returns the header
+/// along with the offset at which the encoded message starts.
+#[cfg(feature = "decode")]
+pub(crate) fn read_header(bytes: &[u8]) -> Result<(Header, usize), crate::Error> {
+    if bytes.is_empty() {
+        return Err(huffman::Error::NoData.into());
+    }
+    let Some(rest) = bytes.strip_prefix(&MAGIC) else {
+        // Data that ends partway through the magic bytes is a frame cut off, not something else
+        return Err(match MAGIC.starts_with(bytes) {
+            true => Error::TruncatedHeader,
+            false => Error::BadMagic,
+        }
+        .into());
+    };
+    let (&version, rest) = rest.split_first().ok_or(Error::TruncatedHeader)?;
+    if version != VERSION {
+        return Err(Error::UnsupportedVersion(version).into());
+    }
+    let (header, header_len) = Header::read(rest)?;
+    Ok((header, MAGIC.len() + 1 + header_len))
+}
+
+/// Read the version byte and header at the start of a message written before frames started
+/// with magic bytes, see [`is_legacy`], dispatching on the format version
+#[cfg(feature = "decode")]
+fn read_legacy_header(bytes: &[u8]) -> Result<(Header, usize), crate::Error> {
+    let Some(&first) = bytes.first() else {
+        return Err(huffman::Error::NoData.into());
+    };
+    if !is_legacy(bytes) {
+        return Err(match bytes.starts_with(&MAGIC) {
+            true => Error::NotLegacy,
+            false => Error::UnsupportedVersion(first >> 1),
+        }
+        .into());
+    }
+    if first & FRAME_MARKER == 0 {
+        return Ok((Header::default(), 0));
+    }
+    let (header, header_len) = match first >> 1 {
+        1 => (Header::default(), 0),
+        _ => Header::read(&bytes[1..])?,
+    };
+    Ok((header, 1 + header_len))
+}
+
+/// Decode the first frame in `bytes`
+#[cfg(feature = "decode")]
+pub(crate) fn decode(bytes: &[u8]) -> Result<Frame, crate::Error> {
+    decode_with_codebook(bytes, None)
+}
+
+/// Like [`decode`], but can also decode frames coded with `codebook`
+#[cfg(feature = "decode")]
+pub(crate) fn decode_with_codebook(
+    bytes: &[u8],
+    codebook: Option<&Codebook>,
+) -> Result<Frame, crate::Error> {
+    // Frames here may be followed by more frames or other data, which strict decoding rejects
+    let options = DecodeOptions {
+        strict: false,
+        ..DecodeOptions::default()
+    };
+    decode_with_options(bytes, codebook, &options)
+}
+
+/// Like [`decode_with_codebook`], with control over how the data is decoded
+#[cfg(feature = "decode")]
+pub(crate) fn decode_with_options(
+    bytes: &[u8],
+    codebook: Option<&Codebook>,
+    options: &DecodeOptions,
+) -> Result<Frame, crate::Error> {
+    let (header, start) = read_header(bytes)?;
+    decode_message(bytes, header, start, codebook, options)
+}
+
+/// Decode a message written before frames started with magic bytes, see [`is_legacy`]. Fails
+/// with [`Error::NotLegacy`] for a frame in the current format.
+#[cfg(feature = "decode")]
+pub(crate) fn decode_legacy(bytes: &[u8]) -> Result<Frame, crate::Error> {
+    let (header, start) = read_legacy_header(bytes)?;
+    let options = DecodeOptions {
+        strict: false,
+        ..DecodeOptions::default()
+    };
+    decode_message(bytes, header, start, None, &options)
+}
+
+/// Decode the message of the frame in `bytes` whose header, read already, ends at `start`
+#[cfg(feature = "decode")]
+fn decode_message(
+    bytes: &[u8],
+    header: Header,
+    start: usize,
+    codebook: Option<&Codebook>,
+    options: &DecodeOptions,
+) -> Result<Frame, crate::Error> {
+    if header.sealed.is_some() {
+        return Err(Error::Encrypted.into());
+    }
+    if header.repeat.is_some() {
+        return Err(Error::Repeat.into());
+    }
+
+    let timed_out = || {
+        if options
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            Err(crate::Error::TimedOut)
+        } else {
+            Ok(())
+        }
+    };
+    timed_out()?;
+
+    let message = &bytes[start..];
+    // A tree built for decoding counts against the memory limit, and the output gets the rest
+    let limit = options.memory_limit.unwrap_or(usize::MAX);
+    let tree_memory = match (header.encoding, header.codebook) {
+        (Encoding::Huffman, None) if !options.low_memory && !header.canonical => {
+            huffman::tree_memory(message, header.bit_order)?
+        }
+        (Encoding::Words, _) => words::code_memory(message, header.bit_order)?,
+        _ => 0,
+    };
+    let memory_len = limit
+        .checked_sub(tree_memory)
+        .ok_or(crate::Error::MemoryLimit)?;
+    let max_len = memory_len.min(options.max_decompressed_len.unwrap_or(usize::MAX));
+    // Running out of room is down to the caller's length limit when that's the tighter one
+    let over_limit = |error| match error {
+        crate::Error::MemoryLimit if max_len < memory_len => crate::Error::LimitExceeded,
+        error => error,
+    };
+
+    let decoded = || -> Result<_, crate::Error> {
+        let (payload, len) = match header.encoding {
+            Encoding::Huffman => match header.codebook {
+                None if header.canonical => {
+                    huffman::decode_canonical_member(message, header.bit_order, max_len)?
+                }
+                None if options.low_memory => {
+                    huffman::decode_member_in_place(message, header.bit_order, max_len)?
+                }
+                None if options.strict => {
+                    huffman::decode_member_strict(message, header.bit_order, max_len).map_err(
+                        |error| match error {
+                            crate::Error::Huffman(error) => error.offset_by(start * 8).into(),
+                            error => error,
+                        },
+                    )?
+                }
+                None => huffman::decode_member(message, header.bit_order, max_len)?,
+                Some(id) => required_codebook(id, codebook)?.decode_member(
+                    message,
+                    header.bit_order,
+                    header.escapes,
+                    max_len,
+                )?,
+            },
+            Encoding::Runs => runs::decode_member(message, max_len)?,
+            Encoding::Adaptive => adaptive::decode_member(message, header.bit_order, max_len)?,
+            Encoding::Words => words::decode_member(message, header.bit_order, max_len)?,
+            Encoding::Literal => {
+                let (literal, len) = read_literal(message)?;
+                if literal.len() > max_len {
+                    return Err(crate::Error::MemoryLimit);
+                }
+                let mut payload = reserve::with_capacity(literal.len())?;
+                payload.extend_from_slice(literal);
+                (payload, len)
+            }
+        };
+        timed_out()?;
+        let payload = transform::invert_all(&header.transforms, payload, max_len)?;
+        if payload.len() > max_len {
+            return Err(crate::Error::MemoryLimit);
+        }
+        Ok((payload, len))
+    };
+    let (payload, len) = decoded().map_err(over_limit)?;
+    if header
+        .checksum
+        .is_some_and(|checksum| crc32fast::hash(&payload) != checksum)
+    {
+        return Err(Error::ChecksumMismatch.into());
+    }
+    timed_out()?;
+    let len = start + len + check_padding(&header, (&bytes[start + len..], &[]), start + len)?;
+    if options.strict && len < bytes.len() {
+        return Err(huffman::Error::TrailingData(len * 8).into());
+    }
+    Ok(Frame {
+        header,
+        payload,
+        len,
+    })
+}
+
+/// Decode the payload of the first frame in `bytes` into `out`, without allocating, returning the
+/// frame's header and the length of the payload. Frames that were transformed or coded with a
+/// codebook can't be decoded this way.
+#[cfg(feature = "decode")]
+pub(crate) fn decode_into_slice(
+    bytes: &[u8],
+    out: &mut [u8],
+) -> Result<(Header, usize), crate::Error> {
+    let mut out = SplitOut::new(out, &mut []);
+    let (header, _) = decode_into_split(SplitBytes::new(bytes, &[]), &mut out)?;
+    Ok((header, out.len()))
+}
+
+/// Like [`decode_into_slice`], for a frame and output that are each split in two. Returns the
+/// frame's header and the number of bytes the frame occupied.
+#[cfg(feature = "decode")]
+pub(crate) fn decode_into_split(
+    bytes: SplitBytes,
+    out: &mut SplitOut,
+) -> Result<(Header, usize), crate::Error> {
+    let (header, start) = ring::read_header(bytes)?;
+    if header.sealed.is_some() {
+        return Err(Error::Encrypted.into());
+    }
+    if header.repeat.is_some() {
+        return Err(Error::Repeat.into());
+    }
+    if !header.transforms.is_empty() {
+        return Err(Error::Transformed.into());
+    }
+
+    let mut message = bytes;
+    message.advance(start);
+    let len = match header.encoding {
+        Encoding::Huffman => match header.codebook {
+            None if header.canonical => {
+                huffman::decode_canonical_into_split(message, header.bit_order, out)?
+            }
+            None => huffman::decode_member_into_split(message, header.bit_order, out)?,
+            Some(id) => return Err(Error::CodebookRequired(id).into()),
+        },
+        Encoding::Runs => runs::decode_member_into_split(message, out)?,
+        Encoding::Adaptive => adaptive::decode_member_into_split(message, header.bit_order, out)?,
+        Encoding::Words => return Err(Error::WordCoded.into()),
+        Encoding::Literal => {
+            let mut rest = message;
+            let literal = rest
+                .read_varint()
+                .and_then(|len| usize::try_from(len).ok())
+                .and_then(|len| rest.take(len))
+                .ok_or(Error::TruncatedLiteral)?;
+            let (first, second) = literal.parts();
+            out.extend(first)?;
+            out.extend(second)?;
+            message.len() - rest.len()
+        }
+    };
+    if let Some(checksum) = header.checksum {
+        let (first, second) = out.written();
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(first);
+        hasher.update(second);
+        if hasher.finalize() != checksum {
+            return Err(Error::ChecksumMismatch.into());
+        }
+    }
+    message.advance(len);
+    let end = start + len;
+    let padding = check_padding(&header, message.parts(), end)?;
+    Ok((header, end + padding))
+}
+
+/// Check the padding at the start of `rest`, which follows a message ending `end` bytes into its
+/// frame, returning its length. `rest` may be split in two.
+#[cfg(feature = "decode")]
+fn check_padding(header: &Header, rest: (&[u8], &[u8]), end: usize) -> Result<usize, Error> {
+    let Some(alignment) = header.alignment else {
+        return Ok(0);
+    };
+    let len = end.next_multiple_of(alignment) - end;
+    if rest.0.len() + rest.1.len() < len {
+        return Err(Error::TruncatedPadding);
+    }
+    if rest.0.iter().chain(rest.1).take(len).any(|&byte| byte != 0) {
+        return Err(Error::InvalidPadding);
+    }
+    Ok(len)
+}
+
+/// The tree at the start of the first frame in `bytes`, or `None` if the frame's message doesn't
+/// carry a tree of its own
+#[cfg(feature = "decode")]
+pub(crate) fn embedded_tree(bytes: &[u8]) -> Result<Option<huffman::Tree>, crate::Error> {
+    let (header, start) = read_header(bytes)?;
+    if header.sealed.is_some() {
+        return Err(Error::Encrypted.into());
+    }
+    if header.repeat.is_some() {
+        return Err(Error::Repeat.into());
+    }
+    match (header.encoding, header.codebook) {
+        (Encoding::Huffman, None) if header.canonical => Ok(Some(
+            huffman::read_canonical(&bytes[start..], header.bit_order)?
+                .0
+                .to_tree(),
+        )),
+        (Encoding::Huffman, None) => Ok(Some(huffman::Tree::deserialize_with_order(
+            &bytes[start..],
+            header.bit_order,
+        )?)),
+        _ => Ok(None),
+    }
+}
+
+/// Check that `codebook` is the one with the id recorded in a header
+#[cfg(feature = "decode")]
+fn required_codebook(id: u32, codebook: Option<&Codebook>) -> Result<&Codebook, Error> {
+    match codebook {
+        Some(codebook) if codebook.id() == id => Ok(codebook),
+        Some(_) => Err(Error::CodebookMismatch(id)),
+        None => Err(Error::CodebookRequired(id)),
+    }
+}
+
+/// Split a literal message from the start of `bytes`, also returning the number of bytes it
+/// occupied
+#[cfg(feature = "decode")]
+fn read_literal(bytes: &[u8]) -> Result<(&[u8], usize), Error> {
+    let mut rest = bytes;
+    let len = varint::read(&mut rest).ok_or(Error::TruncatedLiteral)?;
+    let literal = usize::try_from(len)
+        .ok()
+        .and_then(|len| rest.get(..len))
+        .ok_or(Error::TruncatedLiteral)?;
+    Ok((literal, bytes.len() - rest.len() + literal.len()))
+}
+
+/// Lazily decodes the payload of a frame, see [`decode_iter`]
+#[cfg(feature = "decode")]
+pub(crate) struct Symbols<'a> {
+    source: Source<'a>,
+    /// The checksum in the header, and a hash of the bytes decoded so far, while every byte from
+    /// the start has been decoded in order
+    checksum: Option<(u32, crc32fast::Hasher)>,
+    /// Whether the bytes ended without matching the checksum
+    mismatch: bool,
+}
+
+#[cfg(feature = "decode")]
+enum Source<'a> {
+    Huffman(huffman::DecodeIter<'a>),
+    Runs(runs::DecodeIter),
+    Adaptive(adaptive::DecodeIter<'a>),
+    Literal(std::iter::Copied<std::slice::Iter<'a, u8>>),
+    /// Transforms can't be reversed lazily, so transformed frames are decoded up front
+    Buffered(std::vec::IntoIter<u8>),
+}
+
+#[cfg(feature = "decode")]
+impl Iterator for Symbols<'_> {
+    type Item = u8;
+
+    fn next(&mut self) -> Option<u8> {
+        let byte = match &mut self.source {
+            Source::Huffman(symbols) => symbols.next(),
+            Source::Runs(symbols) => symbols.next(),
+            Source::Adaptive(symbols) => symbols.next(),
+            Source::Literal(symbols) => symbols.next(),
+            Source::Buffered(symbols) => symbols.next(),
+        };
+        match byte {
+            Some(byte) => {
+                if let Some((_, hasher)) = &mut self.checksum {
+                    hasher.update(&[byte]);
+                }
+            }
+            None if !self.truncated() => {
+                if let Some((checksum, hasher)) = self.checksum.take() {
+                    self.mismatch = hasher.finalize() != checksum;
+                }
+            }
+            None => {}
+        }
+        byte
+    }
+}
+
+#[cfg(feature = "decode")]
+impl Symbols<'_> {
+    /// Whether the symbols ended because the message was cut off before its end
+    fn truncated(&self) -> bool {
+        match &self.source {
+            Source::Huffman(symbols) => symbols.truncated(),
+            Source::Adaptive(symbols) => symbols.truncated(),
+            _ => false,
+        }
+    }
+
+    /// Why the symbols ended early or were wrong, once they've run out: the message was cut off,
+    /// or the bytes don't match the frame's checksum
+    pub(crate) fn error(&self) -> Option<crate::Error> {
+        if self.truncated() {
+            return Some(huffman::Error::TruncatedMessage.into());
+        }
+        self.mismatch.then(|| Error::ChecksumMismatch.into())
+    }
+
+    /// Whether decoding can resume from a checkpoint, with [`resume`](Self::resume)
+    pub(crate) fn can_resume(&self) -> bool {
+        matches!(self.source, Source::Huffman(_))
+    }
+
+    /// Resume decoding from the checkpoint at `bit_offset` into the message. The bytes before it
+    /// are skipped, so the checksum can no longer be checked.
+    pub(crate) fn resume(&mut self, bit_offset: usize) {
+        if let Source::Huffman(symbols) = &mut self.source {
+            symbols.resume(bit_offset);
+            self.checksum = None;
+        }
+    }
+}
+
+/// Lazily decode the payload of the first frame in `bytes`
+#[cfg(feature = "decode")]
+pub(crate) fn decode_iter(bytes: &[u8]) -> Result<Symbols<'_>, crate::Error> {
+    let (header, start) = read_header(bytes)?;
+    if header.sealed.is_some() {
+        return Err(Error::Encrypted.into());
+    }
+    if header.repeat.is_some() {
+        return Err(Error::Repeat.into());
+    }
+    // Canonical codes are only decoded all at once
+    if !header.transforms.is_empty() || header.canonical || header.encoding == Encoding::Words {
+        return Ok(Symbols {
+            source: Source::Buffered(decode(bytes)?.payload.into_iter()),
+            checksum: None,
+            mismatch: false,
+        });
+    }
+
+    let message = &bytes[start..];
+    let source = match header.encoding {
+        Encoding::Huffman => match header.codebook {
+            None => Source::Huffman(huffman::decode_iter(message, header.bit_order)?),
+            Some(id) => return Err(Error::CodebookRequired(id).into()),
+        },
+        Encoding::Runs => Source::Runs(runs::decode_iter(message)?),
+        Encoding::Adaptive => Source::Adaptive(adaptive::decode_iter(message, header.bit_order)),
+        Encoding::Words => unreachable!("word coded frames are decoded up front"),
+        Encoding::Literal => Source::Literal(read_literal(message)?.0.iter().copied()),
+    };
+    Ok(Symbols {
+        source,
+        checksum: header
+            .checksum
+            .map(|checksum| (checksum, crc32fast::Hasher::new())),
+        mismatch: false,
+    })
+}
+
+/// Lazily decodes the frames of a buffer made of several frames appended together, one at a
+/// time. Iteration stops after the first error, since the start of the next frame is unknown.
+#[cfg(feature = "decode")]
+pub(crate) struct Frames<'a> {
+    bytes: &'a [u8],
+    codebook: Option<&'a Codebook>,
+    position: usize,
+    /// Where each frame so far started, for finding the frames that repeat frames stand for
+    starts: Vec<usize>,
+}
+
+#[cfg(feature = "decode")]
+impl<'a> Frames<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self::with_codebook(bytes, None)
+    }
+
+    /// Like [`Frames::new`], but can also decode frames coded with `codebook`
+    pub fn with_codebook(bytes: &'a [u8], codebook: Option<&'a Codebook>) -> Self {
+        Self {
+            bytes,
+            codebook,
+            position: 0,
+            starts: Vec::new(),
+        }
+    }
+
+    /// Decode the frame at `position`, or the frame it repeats
+    fn decode_next(&self) -> Result<Frame, crate::Error> {
+        let rest = &self.bytes[self.position..];
+        let (header, start) = read_header(rest)?;
+        let Some(distance) = header.repeat else {
+            return decode_with_codebook(rest, self.codebook);
+        };
+        let repeated = usize::try_from(distance)
+            .ok()
+            .and_then(|distance| self.starts.len().checked_sub(distance))
+            .and_then(|index| self.starts.get(index))
+            .ok_or(Error::Repeat)?;
+        let mut frame = decode_with_codebook(&self.bytes[*repeated..], self.codebook)?;
+        frame.len = start + check_padding(&header, (&rest[start..], &[]), start)?;
+        Ok(frame)
+    }
+}
+
+#[cfg(feature = "decode")]
+impl Iterator for Frames<'_> {
+    type Item = Result<Frame, crate::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.position == self.bytes.len() {
+            return None;
+        }
+        match self.decode_next() {
+            Ok(frame) => {
+                self.starts.push(self.position);
+                self.position += frame.len;
+                Some(Ok(frame))
+            }
+            Err(error) => {
+                self.position = self.bytes.len();
+                Some(Err(error))
+            }
+        }
+    }
+}
+
+/// A frame that stands for the frame `distance` frames before it
+#[cfg(feature = "encode")]
+pub(crate) fn repeat_frame(distance: u64) -> Vec<u8> {
+    write_header(&Header {
+        repeat: Some(distance),
+        ..Header::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Options that always huffman code the message, however short it is
+    fn huffman_only() -> EncodeOptions {
+        EncodeOptions {
+            literal_threshold: 0,
+            ..EncodeOptions::default()
+        }
+    }
+
+    #[test]
+    fn roundtrip() {
+        let message = b"a framed message, a framed message, a framed message";
+        let frame = encode(message, &Header::default(), &huffman_only()).unwrap();
+
+        assert_eq!(frame[..PLAIN_HEADER_LEN - 1], [0x89, b'H', b'P', VERSION]);
+        let decoded = decode(&frame).unwrap();
+        assert_eq!(decoded.payload, message);
+        let header = Header {
+            checksum: Some(crc32fast::hash(message)),
+            ..Header::default()
+        };
+        assert_eq!(decoded.header, header);
+        assert_eq!(decoded.len, frame.len());
+    }
+
+    #[test]
+    fn fingerprint() {
+        let header = Header {
+            fingerprint: Some(0xdeadbeef),
+            ..Header::default()
+        };
+        let frame = encode(b"fingerprinted", &header, &huffman_only()).unwrap();
+
+        let decoded = decode(&frame).unwrap();
+        assert_eq!(decoded.payload, b"fingerprinted");
+        assert_eq!(decoded.header.fingerprint, header.fingerprint);
+
+        assert!(matches!(
+            decode(&frame[..4]),
+            Err(crate::Error::Frame(Error::TruncatedHeader))
+        ));
+    }
+
+    #[test]
+    fn msb0() {
+        let options = EncodeOptions {
+            bit_order: BitOrder::Msb0,
+            ..huffman_only()
+        };
+        let frame = encode(b"most significant bit first", &Header::default(), &options).unwrap();
+
+        let decoded = decode(&frame).unwrap();
+        assert_eq!(decoded.payload, b"most significant bit first");
+        assert_eq!(decoded.header.bit_order, BitOrder::Msb0);
+    }
+
+    #[test]
+    fn literal() {
+        let options = EncodeOptions {
+            checksum: false,
+            ..EncodeOptions::default()
+        };
+        let frame = encode(b"tiny", &Header::default(), &options).unwrap();
+        let [a, b, c] = MAGIC;
+        assert_eq!(
+            frame,
+            [a, b, c, VERSION, FLAG_LITERAL, 4, b't', b'i', b'n', b'y']
+        );
+
+        let decoded = decode(&frame).unwrap();
+        assert_eq!(decoded.header.encoding, Encoding::Literal);
+        assert_eq!(decoded.payload, b"tiny");
+        assert_eq!(decoded.len, frame.len());
+        assert!(decode_iter(&frame).unwrap().eq(*b"tiny"));
+
+        assert!(matches!(
+            decode(&frame[..frame.len() - 2]),
+            Err(crate::Error::Frame(Error::TruncatedLiteral))
+        ));
+
+        // Long enough inputs are still huffman coded
+        let long = b"long enough to be worth building a tree for ".repeat(4);
+        let frame = encode(&long, &Header::default(), &EncodeOptions::default()).unwrap();
+        assert_eq!(decode(&frame).unwrap().header.encoding, Encoding::Huffman);
+    }
+
+    #[test]
+    fn runs() {
+        let zeros = vec![0u8; 1 << 20];
+        let frame = encode(&zeros, &Header::default(), &huffman_only()).unwrap();
+        assert!(frame.len() < 20);
+
+        let decoded = decode(&frame).unwrap();
+        assert_eq!(decoded.header.encoding, Encoding::Runs);
+        assert_eq!(decoded.payload, zeros);
+        assert_eq!(decoded.len, frame.len());
+        assert!(decode_iter(&frame).unwrap().eq(zeros));
+
+        let varied = b"varied enough to be huffman coded".repeat(4);
+        let frame = encode(&varied, &Header::default(), &huffman_only()).unwrap();
+        assert_eq!(decode(&frame).unwrap().header.encoding, Encoding::Huffman);
+    }
+
+    #[test]
+    fn codebook() {
+        let message = b"a message coded with a codebook";
+        let codebook = Codebook::train([&message[..]]);
+        let frame = encode_with_codebook(
+            message,
+            &Header::default(),
+            &huffman_only(),
+            Some(&codebook),
+        )
+        .unwrap();
+        let (_, start) = read_header(&frame).unwrap();
+        assert!(frame.len() - start < huffman::encode(message, BitOrder::Lsb0).unwrap().len());
+
+        let decoded = decode_with_codebook(&frame, Some(&codebook)).unwrap();
+        assert_eq!(decoded.payload, message);
+        assert_eq!(decoded.header.codebook, Some(codebook.id()));
+        assert_eq!(decoded.len, frame.len());
+
+        assert!(matches!(
+            decode(&frame),
+            Err(crate::Error::Frame(Error::CodebookRequired(id))) if id == codebook.id()
+        ));
+        let other = Codebook::train([&b"something else entirely"[..]]);
+        assert!(matches!(
+            decode_with_codebook(&frame, Some(&other)),
+            Err(crate::Error::Frame(Error::CodebookMismatch(_)))
+        ));
+    }
+
+    #[test]
+    fn canonical() {
+        let message = b"{\"id\":17,\"name\":\"sensor-4\",\"ok\":true}".repeat(3);
+        let frame = encode(&message, &Header::default(), &huffman_only()).unwrap();
+        for bit_order in [BitOrder::Lsb0, BitOrder::Msb0] {
+            let options = EncodeOptions {
+                canonical: true,
+                bit_order,
+                ..huffman_only()
+            };
+            let canonical = encode(&message, &Header::default(), &options).unwrap();
+            assert!(canonical.len() + 8 < frame.len());
+            assert_eq!(
+                canonical,
+                encode(&message, &Header::default(), &options).unwrap()
+            );
+
+            let decoded = decode(&canonical).unwrap();
+            assert!(decoded.header.canonical);
+            assert_eq!(decoded.payload, message);
+            assert_eq!(decoded.len, canonical.len());
+            assert_eq!(
+                decode_iter(&canonical).unwrap().collect::<Vec<u8>>(),
+                message
+            );
+            let mut out = [0; 256];
+            let (_, len) = decode_into_slice(&canonical, &mut out).unwrap();
+            assert_eq!(&out[..len], message);
+            let tree = embedded_tree(&canonical).unwrap().unwrap();
+            let own_tree = embedded_tree(&frame).unwrap().unwrap();
+            assert_eq!(tree.code_lengths(), own_tree.code_lengths());
+
+            // Cut off in the code lengths and in the message
+            for len in [4, canonical.len() - 1] {
+                assert!(decode(&canonical[..len]).is_err());
+            }
+        }
+    }
+
+    #[test]
+    fn max_code_len() {
+        // Byte counts following the Fibonacci numbers give codes of up to 20 bits, and mixing
+        // the bytes up keeps the message from being stored as runs
+        let (mut a, mut b) = (1, 1);
+        let mut message = Vec::new();
+        for byte in 0..20 {
+            message.extend(std::iter::repeat_n(byte, a));
+            (a, b) = (b, a + b);
+        }
+        let len = message.len();
+        for i in 0..len {
+            message.swap(i, i * 7919 % len);
+        }
+        let options = EncodeOptions {
+            max_code_len: Some(15),
+            checkpoint_interval: Some(100),
+            ..huffman_only()
+        };
+        let frame = encode(&message, &Header::default(), &options).unwrap();
+        let decoded = decode(&frame).unwrap();
+        assert!(decoded.header.canonical);
+        assert!(decoded.header.checkpoints.is_none());
+        assert_eq!(decoded.payload, message);
+        let lengths = embedded_tree(&frame).unwrap().unwrap().code_lengths();
+        assert_eq!(lengths.into_iter().max(), Some(15));
+
+        // Limiting costs a little
+        let unlimited = encode(&message, &Header::default(), &huffman_only()).unwrap();
+        assert!(frame.len() < unlimited.len() + unlimited.len() / 20);
+    }
+
+    #[test]
+    fn strict() {
+        let strict = DecodeOptions::default();
+        let message = b"decoded strictly, with nothing after it".repeat(3);
+        let frame = encode(&message, &Header::default(), &huffman_only()).unwrap();
+        let decoded = decode_with_options(&frame, None, &strict).unwrap();
+        assert_eq!(decoded.payload, message);
+
+        // Bytes after the frame are ignored unless decoding strictly
+        let mut trailing = frame.clone();
+        trailing.push(0);
+        assert_eq!(decode(&trailing).unwrap().payload, message);
+        assert_eq!(
+            decode_with_options(&trailing, None, &strict).unwrap_err(),
+            huffman::Error::TrailingData(frame.len() * 8).into()
+        );
+        let literal = encode(b"stored", &Header::default(), &EncodeOptions::default()).unwrap();
+        assert_eq!(
+            decode_with_options(&[&literal[..], b"?"].concat(), None, &strict).unwrap_err(),
+            huffman::Error::TrailingData(literal.len() * 8).into()
+        );
+    }
+
+    #[test]
+    fn checksum() {
+        let message = b"checked when it's decoded";
+        let frame = encode(message, &Header::default(), &EncodeOptions::default()).unwrap();
+        let decoded = decode(&frame).unwrap();
+        assert_eq!(decoded.header.checksum, Some(crc32fast::hash(message)));
+        assert_eq!(decoded.payload, message);
+        let options = EncodeOptions {
+            checksum: false,
+            ..EncodeOptions::default()
+        };
+        let unchecked = encode(message, &Header::default(), &options).unwrap();
+        assert_eq!(decode(&unchecked).unwrap().header.checksum, None);
+        assert_eq!(frame.len(), unchecked.len() + 5);
+
+        // The message is a literal, so a damaged byte still decodes
+        let mut damaged = frame.clone();
+        *damaged.last_mut().unwrap() ^= 1;
+        let mismatch = crate::Error::Frame(Error::ChecksumMismatch);
+        assert_eq!(decode(&damaged).unwrap_err(), mismatch);
+        let mut symbols = decode_iter(&damaged).unwrap();
+        symbols.by_ref().for_each(drop);
+        assert_eq!(
+            symbols.error(),
+            Some(crate::Error::Frame(Error::ChecksumMismatch))
+        );
+        let mut symbols = decode_iter(&frame).unwrap();
+        assert!(symbols.by_ref().eq(*message));
+        assert_eq!(symbols.error(), None);
+        assert_eq!(
+            decode_into_slice(&damaged, &mut [0; 64]).unwrap_err(),
+            mismatch
+        );
+        assert_eq!(
+            decode_into_slice(&frame, &mut [0; 64]).unwrap().1,
+            message.len()
+        );
+
+        let options = EncodeOptions {
+            transforms: vec![Transform::Delta],
+            ..options
+        };
+        let frame = encode(&[3; 500], &Header::default(), &options).unwrap();
+        assert_eq!(decode(&frame).unwrap().payload, [3; 500]);
+    }
+
+    #[test]
+    fn adaptive() {
+        let message = b"short messages spend much of their frame on the tree";
+        let options = EncodeOptions {
+            adaptive: true,
+            ..huffman_only()
+        };
+        let frame = encode(message, &Header::default(), &options).unwrap();
+        assert!(
+            frame.len()
+                < encode(message, &Header::default(), &huffman_only())
+                    .unwrap()
+                    .len()
+        );
+
+        let decoded = decode(&frame).unwrap();
+        assert_eq!(decoded.header.encoding, Encoding::Adaptive);
+        assert_eq!(decoded.payload, message);
+        assert_eq!(decoded.len, frame.len());
+        assert_eq!(decode_iter(&frame).unwrap().collect::<Vec<u8>>(), message);
+        let mut out = [0; 64];
+        let (_, len) = decode_into_slice(&frame, &mut out).unwrap();
+        assert_eq!(&out[..len], message);
+    }
+
+    #[test]
+    fn words() {
+        let message: Vec<u8> = (0..400u16)
+            .flat_map(|i| (1000 + i % 9 * 257).to_le_bytes())
+            .collect();
+        let options = EncodeOptions {
+            symbol_width: SymbolWidth::Word,
+            ..huffman_only()
+        };
+        let frame = encode(&message, &Header::default(), &options).unwrap();
+        assert!(
+            frame.len()
+                < encode(&message, &Header::default(), &huffman_only())
+                    .unwrap()
+                    .len()
+        );
+
+        let decoded = decode(&frame).unwrap();
+        assert_eq!(decoded.header.encoding, Encoding::Words);
+        assert_eq!(decoded.payload, message);
+        assert_eq!(decoded.len, frame.len());
+        assert_eq!(decode_iter(&frame).unwrap().collect::<Vec<u8>>(), message);
+        assert_eq!(
+            decode_into_slice(&frame, &mut [0; 1024]).unwrap_err(),
+            Error::WordCoded.into()
+        );
+
+        // A single byte has no words, so it's coded some other way
+        let frame = encode(b"x", &Header::default(), &options).unwrap();
+        assert_ne!(decode(&frame).unwrap().header.encoding, Encoding::Words);
+    }
+
+    #[test]
+    fn hybrid_codebook() {
+        let samples: Vec<Vec<u8>> = (0..20)
+            .map(|i| format!("status {i}: ok").into_bytes())
+            .collect();
+        let codebook = Codebook::train(&samples);
+        let options = EncodeOptions {
+            hybrid_codebook: true,
+            ..huffman_only()
+        };
+        let encode = |message: &[u8], options: &EncodeOptions| {
+            encode_with_codebook(message, &Header::default(), options, Some(&codebook)).unwrap()
+        };
+
+        let typical = b"status 21: ok, status 22: ok";
+        let frame = encode(typical, &options);
+        assert_eq!(frame, encode(typical, &huffman_only()));
+        assert_eq!(
+            decode_with_codebook(&frame, Some(&codebook))
+                .unwrap()
+                .header
+                .codebook,
+            Some(codebook.id())
+        );
+
+        // Nothing like the samples, so every byte would be escaped
+        let atypical: Vec<u8> = (0..=255).cycle().take(2000).map(|b: u8| b / 8).collect();
+        let frame = encode(&atypical, &options);
+        assert!(frame.len() < encode(&atypical, &huffman_only()).len());
+        let decoded = decode_with_codebook(&frame, Some(&codebook)).unwrap();
+        assert_eq!(decoded.header.codebook, None);
+        assert_eq!(decoded.payload, atypical);
+        assert_eq!(decode(&frame).unwrap().payload, atypical);
+    }
+
+    #[test]
+    fn transforms() {
+        let options = EncodeOptions {
+            transforms: vec![
+                Transform::Delta,
+                Transform::Bwt,
+                Transform::Remap(crate::Remap::dense(&[1, 0]).unwrap()),
+            ],
+            ..huffman_only()
+        };
+        let message: Vec<u8> = (0..200).collect();
+        let frame = encode(&message, &Header::default(), &options).unwrap();
+
+        let decoded = decode(&frame).unwrap();
+        assert_eq!(decoded.header.transforms, options.transforms);
+        assert_eq!(decoded.payload, message);
+        assert_eq!(decoded.len, frame.len());
+        assert!(decode_iter(&frame).unwrap().eq(message));
+
+        // The second transform, after the version, both flags bytes, the count and the first
+        let mut unknown = frame.clone();
+        unknown[MAGIC.len() + 5] = 200;
+        assert!(matches!(
+            decode(&unknown),
+            Err(crate::Error::Frame(Error::UnsupportedTransform(200)))
+        ));
+    }
+
+    #[test]
+    fn checkpoints() {
+        let options = EncodeOptions {
+            checkpoint_interval: Some(10),
+            ..huffman_only()
+        };
+        let message = "checkpoints every ten symbols, ".repeat(8).into_bytes();
+        let frame = encode(&message, &Header::default(), &options).unwrap();
+        let (header, start) = read_header(&frame).unwrap();
+        let checkpoints = header.checkpoints.unwrap();
+        assert_eq!(checkpoints.interval, 10);
+        assert_eq!(checkpoints.offsets.len(), (message.len() - 1) / 10);
+        assert_eq!(decode(&frame).unwrap().payload, message);
+
+        // Decoding from each checkpoint gives the rest of the message
+        for (i, &offset) in checkpoints.offsets.iter().enumerate() {
+            let mut symbols = huffman::decode_iter(&frame[start..], header.bit_order).unwrap();
+            symbols.resume(offset);
+            assert!(symbols.eq(message[10 * (i + 1)..].iter().copied()));
+        }
+        assert_eq!(checkpoints.before(9), None);
+        assert_eq!(checkpoints.before(25), Some((20, checkpoints.offsets[1])));
+        assert_eq!(
+            checkpoints.before(usize::MAX),
+            Some((240, checkpoints.offsets[23]))
+        );
+
+        // Short messages, and those with transforms, don't get any
+        let frame = encode(b"too short", &Header::default(), &options).unwrap();
+        assert_eq!(decode(&frame).unwrap().header.checkpoints, None);
+        let transformed = EncodeOptions {
+            transforms: vec![Transform::Delta],
+            ..options
+        };
+        let frame = encode(&message, &Header::default(), &transformed).unwrap();
+        assert_eq!(decode(&frame).unwrap().header.checkpoints, None);
+
+        let header = Header {
+            checkpoints: Some(Checkpoints {
+                interval: 0,
+                offsets: Vec::new(),
+            }),
+            ..Header::default()
+        };
+        assert!(matches!(
+            read_header(&write_header(&header)),
+            Err(crate::Error::Frame(Error::InvalidCheckpoints))
+        ));
+    }
+
+    #[test]
+    fn incompressible() {
+        // Every byte value once, which huffman coding can only make bigger
+        let message: Vec<u8> = (0..=255).collect();
+        let frame = encode(&message, &Header::default(), &huffman_only()).unwrap();
+        assert_eq!(decode(&frame).unwrap().header.encoding, Encoding::Literal);
+        assert!(frame.len() <= max_len(message.len()));
+        assert_eq!(decode(&frame).unwrap().payload, message);
+    }
+
+    #[test]
+    fn legacy_unframed() {
+        let message = b"written before frames had a version";
+        let legacy = huffman::encode(message, BitOrder::Lsb0).unwrap();
+
+        assert!(is_legacy(&legacy));
+        assert_eq!(decode_legacy(&legacy).unwrap().payload, message);
+        assert!(matches!(
+            decode(&legacy),
+            Err(crate::Error::Frame(Error::BadMagic))
+        ));
+
+        let framed = encode(message, &Header::default(), &huffman_only()).unwrap();
+        assert!(!is_legacy(&framed));
+        assert!(!is_legacy(&[]));
+        assert!(matches!(
+            decode_legacy(&framed),
+            Err(crate::Error::Frame(Error::NotLegacy))
+        ));
+    }
+
+    #[test]
+    fn aligned() {
+        let messages: [&[u8]; 3] = [
+            b"short",
+            b"a longer message, aligned to a sector",
+            &[9; 100],
+        ];
+        for alignment in [4, 8, 512] {
+            let options = EncodeOptions {
+                alignment: Some(alignment),
+                ..huffman_only()
+            };
+            let frames: Vec<_> = messages
+                .iter()
+                .map(|message| encode(message, &Header::default(), &options).unwrap())
+                .collect();
+            for (frame, message) in frames.iter().zip(messages) {
+                assert_eq!(frame.len() % alignment, 0);
+                let decoded = decode(frame).unwrap();
+                assert_eq!(
+                    (decoded.payload.as_slice(), decoded.len),
+                    (message, frame.len())
+                );
+            }
+
+            let concatenated = frames.concat();
+            let payloads: Vec<_> = Frames::new(&concatenated)
+                .map(|frame| frame.unwrap().payload)
+                .collect();
+            assert_eq!(payloads, messages);
+        }
+
+        let options = EncodeOptions {
+            alignment: Some(512),
+            ..huffman_only()
+        };
+        let mut frame = encode(b"short", &Header::default(), &options).unwrap();
+        assert!(matches!(
+            decode(&frame[..frame.len() - 1]),
+            Err(crate::Error::Frame(Error::TruncatedPadding))
+        ));
+        *frame.last_mut().unwrap() = 1;
+        assert!(matches!(
+            decode(&frame),
+            Err(crate::Error::Frame(Error::InvalidPadding))
+        ));
+    }
+
+    #[test]
+    fn version_1() {
+        let message = b"written before frames had a header";
+        let mut frame = vec![(1 << 1) | FRAME_MARKER];
+        frame.extend_from_slice(&huffman::encode(message, BitOrder::Lsb0).unwrap());
+
+        assert!(is_legacy(&frame));
+        assert_eq!(decode_legacy(&frame).unwrap().payload, message);
+    }
+
+    #[test]
+    fn magic() {
+        let frame = encode(b"magic", &Header::default(), &huffman_only()).unwrap();
+        for len in 1..MAGIC.len() + 1 {
+            assert!(matches!(
+                decode(&frame[..len]),
+                Err(crate::Error::Frame(Error::TruncatedHeader))
+            ));
+        }
+        for first in [0x09, b'H', 0x88] {
+            let mut damaged = frame.clone();
+            damaged[0] = first;
+            assert!(matches!(
+                decode(&damaged),
+                Err(crate::Error::Frame(Error::BadMagic))
+            ));
+        }
+    }
+
+    #[test]
+    fn unsupported_version_and_flags() {
+        let mut frame = encode(b"from the future", &Header::default(), &huffman_only()).unwrap();
+        frame[MAGIC.len()] = VERSION + 1;
+        assert!(matches!(
+            decode(&frame),
+            Err(crate::Error::Frame(Error::UnsupportedVersion(v))) if v == VERSION + 1
+        ));
+
+        let mut frame = encode(b"from the future", &Header::default(), &huffman_only()).unwrap();
+        frame[MAGIC.len() + 1] = FLAG_EXTENDED | FLAG_LITERAL;
+        frame[MAGIC.len() + 2] |= FLAG_WORDS;
+        assert!(matches!(
+            decode(&frame),
+            Err(crate::Error::Frame(Error::UnsupportedFlags(0x80)))
+        ));
+    }
+
+    #[test]
+    fn concatenated_legacy() {
+        let first = encode(b"first message", &Header::default(), &huffman_only()).unwrap();
+        let second = huffman::encode(b"a legacy one", BitOrder::Lsb0).unwrap();
+        let third = encode(&[0, 0, 0, 1], &Header::default(), &huffman_only()).unwrap();
+
+        // Legacy messages are only read on their own, so iteration stops at one
+        let concatenated = [first, second, third].concat();
+        let frames: Vec<_> = Frames::new(&concatenated)
+            .map(|frame| frame.map(|frame| frame.payload))
+            .collect();
+        assert_eq!(
+            frames,
+            [
+                Ok(b"first message".to_vec()),
+                Err(crate::Error::Frame(Error::BadMagic))
+            ]
+        );
+    }
+}
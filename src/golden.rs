@@ -0,0 +1,213 @@
+//! Checked in compressed fixtures, which pin down the format. Every fixture must still decode to
+//! its input, and encoding the input again must reproduce the fixture byte for byte. A change
+//! that breaks either is a format change, and old data in the wild would stop decoding, or new
+//! data would stop decoding with old versions of the library.
+//!
+//! Fixtures are only regenerated deliberately, by running the tests with `HUFFNPUFF_BLESS=1`.
+
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{BitOrder, Codebook, EncodeOptions, Pipeline};
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Event {
+    id: u64,
+    kind: String,
+    values: Vec<i32>,
+}
+
+/// Words strung together by a fixed pseudorandom sequence, so inputs are the same everywhere
+fn text(len: usize) -> String {
+    const WORDS: [&str; 16] = [
+        "the", "quick", "brown", "fox", "jumps", "over", "lazy", "dog", "huffman", "codes",
+        "short", "for", "common", "symbols", "and", "long",
+    ];
+    let mut state = 0x2545_f491_u32;
+    let mut text = String::with_capacity(len + 8);
+    while text.len() < len {
+        state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+        text.push_str(WORDS[(state >> 16) as usize % WORDS.len()]);
+        text.push(if state.is_multiple_of(7) { '\n' } else { ' ' });
+    }
+    text.truncate(len);
+    text
+}
+
+fn events(count: u64) -> Vec<Event> {
+    (0..count)
+        .map(|id| Event {
+            id: 1_000_000 + id,
+            kind: ["click", "view", "scroll"][id as usize % 3].to_owned(),
+            values: (0..id % 5).map(|i| (id * 10 + i) as i32 - 20).collect(),
+        })
+        .collect()
+}
+
+fn codebook() -> Codebook {
+    Codebook::train(
+        events(50)
+            .iter()
+            .map(|event| bincode::serialize(event).unwrap()),
+    )
+}
+
+/// A fixture: how its input is encoded, and a check that the fixture decodes to that input
+struct Golden {
+    name: &'static str,
+    encode: fn() -> Vec<u8>,
+    check: fn(&[u8]),
+}
+
+const GOLDEN: &[Golden] = &[
+    Golden {
+        name: "literal",
+        encode: || crate::huff(&"tiny".to_owned()).unwrap(),
+        check: |bytes| assert_eq!(crate::puff::<String>(bytes).unwrap(), "tiny"),
+    },
+    Golden {
+        name: "text",
+        encode: || crate::huff(&text(2000)).unwrap(),
+        check: |bytes| assert_eq!(crate::puff::<String>(bytes).unwrap(), text(2000)),
+    },
+    Golden {
+        name: "large",
+        encode: || crate::huff(&text(200_000)).unwrap(),
+        check: |bytes| assert_eq!(crate::puff::<String>(bytes).unwrap(), text(200_000)),
+    },
+    Golden {
+        name: "msb0",
+        encode: || {
+            let options = EncodeOptions {
+                bit_order: BitOrder::Msb0,
+                ..EncodeOptions::default()
+            };
+            crate::huff_with_options(&text(2000), &options).unwrap()
+        },
+        check: |bytes| assert_eq!(crate::puff::<String>(bytes).unwrap(), text(2000)),
+    },
+    Golden {
+        name: "transforms",
+        encode: || {
+            Pipeline::new()
+                .delta()
+                .run_length()
+                .huff(&events(200))
+                .unwrap()
+        },
+        check: |bytes| assert_eq!(crate::puff::<Vec<Event>>(bytes).unwrap(), events(200)),
+    },
+    Golden {
+        name: "bwt",
+        encode: || {
+            Pipeline::new()
+                .bwt()
+                .move_to_front()
+                .huff(&text(5000))
+                .unwrap()
+        },
+        check: |bytes| assert_eq!(crate::puff::<String>(bytes).unwrap(), text(5000)),
+    },
+    Golden {
+        name: "checkpoints",
+        encode: || {
+            let options = EncodeOptions {
+                checkpoint_interval: Some(1024),
+                ..EncodeOptions::default()
+            };
+            crate::huff_with_options(&text(20_000), &options).unwrap()
+        },
+        check: |bytes| assert_eq!(crate::puff::<String>(bytes).unwrap(), text(20_000)),
+    },
+    Golden {
+        name: "codebook",
+        encode: || codebook().to_bytes(),
+        check: |bytes| {
+            let decoded = Codebook::from_bytes(bytes).unwrap();
+            assert_eq!(decoded.code_lengths(), codebook().code_lengths());
+        },
+    },
+    Golden {
+        name: "with_codebook",
+        encode: || crate::huff_with_codebook(&events(60)[55], &codebook()).unwrap(),
+        check: |bytes| {
+            let decoded: Event = crate::puff_with_codebook(bytes, &codebook()).unwrap();
+            assert_eq!(decoded, events(60).remove(55));
+        },
+    },
+    Golden {
+        name: "tagged",
+        encode: || crate::huff_tagged(&events(10), "golden").unwrap(),
+        check: |bytes| {
+            let decoded: Vec<Event> = crate::puff_tagged(bytes, "golden").unwrap();
+            assert_eq!(decoded, events(10));
+        },
+    },
+    Golden {
+        name: "versioned",
+        encode: || crate::huff_versioned(&events(10), 3).unwrap(),
+        check: |bytes| {
+            let migrations = crate::Migrations::<Vec<Event>>::new(3);
+            assert_eq!(
+                crate::puff_versioned(bytes, &migrations).unwrap(),
+                events(10)
+            );
+        },
+    },
+    Golden {
+        name: "blocks",
+        encode: || crate::compress_blocks(text(10_000).as_bytes(), 4096).unwrap(),
+        check: |bytes| {
+            assert_eq!(
+                crate::decompress_blocks(bytes).unwrap(),
+                text(10_000).as_bytes()
+            );
+        },
+    },
+    Golden {
+        name: "ints",
+        encode: || crate::huff_ints(&timestamps()).unwrap(),
+        check: |bytes| assert_eq!(crate::puff_ints(bytes).unwrap(), timestamps()),
+    },
+    Golden {
+        name: "columnar",
+        encode: || crate::huff_columnar(&events(100)).unwrap(),
+        check: |bytes| assert_eq!(crate::puff_columnar::<Event>(bytes).unwrap(), events(100)),
+    },
+];
+
+fn timestamps() -> Vec<u64> {
+    (0..1000)
+        .map(|i| 1_700_000_000_000 + i * 250 + i % 7)
+        .collect()
+}
+
+fn path(name: &str) -> PathBuf {
+    [env!("CARGO_MANIFEST_DIR"), "testdata", "golden", name]
+        .iter()
+        .collect::<PathBuf>()
+        .with_extension("hnp")
+}
+
+#[test]
+fn golden() {
+    let bless = std::env::var_os("HUFFNPUFF_BLESS").is_some();
+    for golden in GOLDEN {
+        let path = path(golden.name);
+        let encoded = (golden.encode)();
+        if bless {
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, &encoded).unwrap();
+        }
+
+        let fixture = std::fs::read(&path)
+            .unwrap_or_else(|error| panic!("reading {}: {error}", path.display()));
+        (golden.check)(&fixture);
+        assert!(
+            encoded == fixture,
+            "encoding {} no longer reproduces its fixture",
+            golden.name
+        );
+    }
+}
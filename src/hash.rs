@@ -0,0 +1,152 @@
+//! Hashing compressed output as it's written, for content-addressed storage that would otherwise
+//! read the whole output back to hash it
+
+use std::io::{self, Write};
+
+#[cfg(feature = "encode")]
+use crate::block::BlockWriter;
+
+/// A hash that can be computed incrementally over the compressed bytes written through a
+/// [`HashingWriter`]. [`Crc32`] is built in; with the `digest` feature, every hash implementing
+/// `digest::Digest`, such as `sha2::Sha256`, is one too. Other hashes, like xxh3, can be plugged in
+/// by implementing this for a wrapper around them.
+pub trait StreamHasher {
+    type Output;
+
+    fn update(&mut self, bytes: &[u8]);
+
+    fn finish(self) -> Self::Output;
+}
+
+/// CRC-32, as used for the checksums in block streams and archives
+#[derive(Debug, Clone, Default)]
+pub struct Crc32(crc32fast::Hasher);
+
+impl Crc32 {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl StreamHasher for Crc32 {
+    type Output = u32;
+
+    fn update(&mut self, bytes: &[u8]) {
+        self.0.update(bytes);
+    }
+
+    fn finish(self) -> u32 {
+        self.0.finalize()
+    }
+}
+
+#[cfg(feature = "digest")]
+impl<D: digest::Digest> StreamHasher for D {
+    type Output = digest::Output<D>;
+
+    fn update(&mut self, bytes: &[u8]) {
+        digest::Digest::update(self, bytes);
+    }
+
+    fn finish(self) -> Self::Output {
+        self.finalize()
+    }
+}
+
+/// Passes everything written to it on to a writer, hashing the bytes the writer accepts
+pub struct HashingWriter<W, H> {
+    writer: W,
+    hasher: H,
+}
+
+impl<W: Write, H: StreamHasher> HashingWriter<W, H> {
+    pub fn new(writer: W, hasher: H) -> Self {
+        Self { writer, hasher }
+    }
+
+    /// The underlying writer. Bytes written to it directly aren't hashed.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    /// The underlying writer and the hash of everything written to it
+    pub fn finish(self) -> (W, H::Output) {
+        (self.writer, self.hasher.finish())
+    }
+}
+
+impl<W: Write, H: StreamHasher> Write for HashingWriter<W, H> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.writer.write(buf)?;
+        self.hasher.update(&buf[..len]);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(feature = "encode")]
+impl<W: Write, H: StreamHasher> BlockWriter<HashingWriter<W, H>> {
+    /// Like [`BlockWriter::finish`], also returning the hash of the whole stream:
+    ///
+    /// ```
+    /// use std::io::Write;
+    ///
+    /// use huffnpuff::{BlockWriter, Crc32, HashingWriter};
+    ///
+    /// let mut writer = BlockWriter::new(HashingWriter::new(Vec::new(), Crc32::new()), 4096)?;
+    /// writer.write_all(b"a stream to store by its hash")?;
+    /// let (stream, hash) = writer.finish_hashed()?;
+    /// assert_eq!(hash, crc32fast::hash(&stream));
+    /// # Ok::<(), huffnpuff::Error>(())
+    /// ```
+    pub fn finish_hashed(self) -> Result<(W, H::Output), crate::Error> {
+        Ok(self.finish()?.finish())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Accepts at most a few bytes per write, so hashing must only cover what was accepted
+    struct Slow(Vec<u8>);
+
+    impl Write for Slow {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let len = buf.len().min(5);
+            self.0.extend_from_slice(&buf[..len]);
+            Ok(len)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn crc32() {
+        let input = b"some data worth compressing, some data worth compressing".repeat(100);
+        let mut writer =
+            BlockWriter::new(HashingWriter::new(Slow(Vec::new()), Crc32::new()), 1000).unwrap();
+        writer.write_all(&input).unwrap();
+        let (Slow(stream), hash) = writer.finish_hashed().unwrap();
+        assert_eq!(hash, crc32fast::hash(&stream));
+        assert_eq!(crate::decompress_blocks(&stream).unwrap(), input);
+    }
+
+    #[cfg(feature = "digest")]
+    #[test]
+    fn sha256() {
+        use sha2::{Digest, Sha256};
+
+        let input = b"content addressed".repeat(1000);
+        let mut writer =
+            BlockWriter::new(HashingWriter::new(Vec::new(), Sha256::new()), 4096).unwrap();
+        writer.write_all(&input).unwrap();
+        let (stream, hash) = writer.finish_hashed().unwrap();
+        assert_eq!(hash, Sha256::digest(&stream));
+    }
+}
@@ -0,0 +1,202 @@
+//! Method-style compression, so application code can write `value.huff()` and `T::puff(bytes)`
+//! instead of calling the free functions
+//!
+//! ```
+//! use huffnpuff::Huffable;
+//! use serde::{Deserialize, Serialize};
+//!
+//! #[derive(Debug, PartialEq, Serialize, Deserialize)]
+//! struct Point {
+//!     x: i32,
+//!     y: i32,
+//! }
+//!
+//! let bytes = Point { x: 1, y: 2 }.huff().unwrap();
+//! assert_eq!(Point::puff(&bytes).unwrap(), Point { x: 1, y: 2 });
+//! ```
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+#[cfg(feature = "decode")]
+use crate::Migrations;
+
+/// Values that can be compressed with [`huff`](crate::huff) and decompressed with
+/// [`puff`](crate::puff), as methods. Every serde type is `Huffable`.
+pub trait Huffable: Serialize + DeserializeOwned {
+    /// Compress the value, like [`huff`](crate::huff)
+    #[cfg(feature = "encode")]
+    fn huff(&self) -> Result<Vec<u8>, crate::Error> {
+        crate::huff(self)
+    }
+
+    /// Decompress a value of this type, like [`puff`](crate::puff)
+    #[cfg(feature = "decode")]
+    fn puff(bytes: &[u8]) -> Result<Self, crate::Error> {
+        crate::puff(bytes)
+    }
+}
+
+impl<T: Serialize + DeserializeOwned> Huffable for T {}
+
+/// Types with a stable, versioned compressed representation, see
+/// [`huff_versioned`](crate::huff_versioned). With the `derive` feature it can be derived, with the
+/// version and the older versions that convert to the type with `From`:
+///
+/// ```ignore
+/// #[derive(Serialize, Deserialize, Versioned)]
+/// #[huffnpuff(version = 2, from(1 = SaveV1))]
+/// struct Save {
+///     name: String,
+///     level: u32,
+/// }
+/// ```
+pub trait Versioned: Serialize + DeserializeOwned {
+    /// The version of the type's current layout
+    const VERSION: u32;
+
+    /// How values written with older versions are converted. There are none by default.
+    #[cfg(feature = "decode")]
+    fn migrations() -> Migrations<Self> {
+        Migrations::new(Self::VERSION)
+    }
+
+    /// Compress the value stamped with [`VERSION`](Self::VERSION)
+    #[cfg(feature = "encode")]
+    fn huff_versioned(&self) -> Result<Vec<u8>, crate::Error> {
+        crate::huff_versioned(self, Self::VERSION)
+    }
+
+    /// Decompress a value of this type, migrating it if it was written with an older version
+    #[cfg(feature = "decode")]
+    fn puff_versioned(bytes: &[u8]) -> Result<Self, crate::Error> {
+        crate::puff_versioned(bytes, &Self::migrations())
+    }
+}
+
+#[cfg(all(test, feature = "encode", feature = "decode"))]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Reading {
+        sensor: String,
+        celsius: f32,
+    }
+
+    #[test]
+    fn methods() {
+        let reading = Reading {
+            sensor: "attic".to_owned(),
+            celsius: 21.5,
+        };
+        let bytes = reading.huff().unwrap();
+        assert_eq!(bytes, crate::huff(&reading).unwrap());
+        assert_eq!(Reading::puff(&bytes).unwrap(), reading);
+        assert_eq!(
+            <Vec<u16>>::puff(&vec![7u16; 40].huff().unwrap()).unwrap(),
+            [7; 40]
+        );
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct SaveV1 {
+        name: String,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Save {
+        name: String,
+        level: u32,
+    }
+
+    impl Versioned for Save {
+        const VERSION: u32 = 2;
+
+        fn migrations() -> Migrations<Self> {
+            Migrations::new(Self::VERSION).migrate(1, |old: SaveV1| Save {
+                name: old.name,
+                level: 1,
+            })
+        }
+    }
+
+    #[test]
+    fn versioned() {
+        let save = Save {
+            name: "ada".to_owned(),
+            level: 3,
+        };
+        assert_eq!(
+            Save::puff_versioned(&save.huff_versioned().unwrap()).unwrap(),
+            save
+        );
+
+        let old = crate::huff_versioned(
+            &SaveV1 {
+                name: "ada".to_owned(),
+            },
+            1,
+        )
+        .unwrap();
+        assert_eq!(
+            Save::puff_versioned(&old).unwrap(),
+            Save {
+                name: "ada".to_owned(),
+                level: 1
+            }
+        );
+    }
+
+    #[cfg(feature = "derive")]
+    mod derive {
+        use serde::{Deserialize, Serialize};
+
+        use crate::Versioned;
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize, Versioned)]
+        #[huffnpuff(version = 1)]
+        struct PointV1 {
+            x: i32,
+        }
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize, Versioned)]
+        #[huffnpuff(version = 2, from(1 = PointV1))]
+        struct Point {
+            x: i32,
+            y: i32,
+        }
+
+        impl From<PointV1> for Point {
+            fn from(old: PointV1) -> Self {
+                Point { x: old.x, y: 0 }
+            }
+        }
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize, Versioned)]
+        #[huffnpuff(version = 5)]
+        struct Wrapper<T>(T);
+
+        #[test]
+        fn derived() {
+            assert_eq!(Point::VERSION, 2);
+            let old = PointV1 { x: 4 }.huff_versioned().unwrap();
+            assert_eq!(Point::puff_versioned(&old).unwrap(), Point { x: 4, y: 0 });
+            let point = Point { x: 1, y: 2 };
+            assert_eq!(
+                Point::puff_versioned(&point.huff_versioned().unwrap()).unwrap(),
+                point
+            );
+
+            let wrapped = Wrapper(vec![1u8, 2, 3]);
+            let bytes = wrapped.huff_versioned().unwrap();
+            assert_eq!(Wrapper::puff_versioned(&bytes).unwrap(), wrapped);
+            assert_eq!(
+                Point::puff_versioned(&bytes),
+                Err(crate::SchemaError::NewerVersion(5).into())
+            );
+        }
+    }
+}
@@ -1,41 +1,1421 @@
-use bitvec::{field::BitField, order::Lsb0, view::BitView};
-use std::collections::HashMap;
+use std::fmt;
 
-type BitSlice = bitvec::prelude::BitSlice<u8, Lsb0>;
-type BitVec = bitvec::prelude::BitVec<u8, Lsb0>;
+use crate::bits::{BitOrder, BitReader, BitWriter};
+#[cfg(feature = "decode")]
+use crate::reserve;
+#[cfg(feature = "decode")]
+use crate::ring::{SplitBytes, SplitOut};
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum Error {
     /// No data was provided to the encoding or decoding function
     NoData,
     /// It was not possible to decode the huffman tree from the provided data. Maybe this data was not encoded by huffnpuff?
     FailedToDecodeHuffmanTree,
+    /// The data ended before the end of message marker, so the message was cut off
+    TruncatedMessage,
+    /// The tree is deeper than a tree of 257 symbols can be, or, for a tree read on its own, deeper
+    /// than the 63 levels this library writes, which only crafted data produces
+    TreeTooDeep,
+    /// Strict decoding found data after the end of the message at this bit, either bits after EOM
+    /// that aren't zero padding or bytes after the frame
+    TrailingData(usize),
+    /// Strict decoding found a value with more than one leaf in the tree. The second leaf's value
+    /// is at this bit.
+    DuplicateSymbol(usize),
+    /// Strict decoding found a tree with more leaves than there are values. The first extra leaf's
+    /// value is at this bit.
+    InvalidLeafCount(usize),
 }
 
-pub(crate) fn encode(bytes: &[u8]) -> Result<Vec<u8>, Error> {
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Error::NoData => "there was no data",
+            Error::FailedToDecodeHuffmanTree => "the huffman tree couldn't be decoded",
+            Error::TruncatedMessage => "the message was cut off before its end",
+            Error::TreeTooDeep => "the huffman tree is deeper than any valid tree",
+            Error::TrailingData(bit) => {
+                return write!(f, "there is data after the message at bit {bit}");
+            }
+            Error::DuplicateSymbol(bit) => {
+                return write!(f, "the huffman tree has a value twice, at bit {bit}");
+            }
+            Error::InvalidLeafCount(bit) => {
+                return write!(f, "the huffman tree has too many leaves, at bit {bit}");
+            }
+        })
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl Error {
+    /// The error with its bit offset moved on by `bits`, for a message that starts partway into
+    /// the data
+    #[cfg(feature = "decode")]
+    pub(crate) fn offset_by(self, bits: usize) -> Self {
+        match self {
+            Error::TrailingData(bit) => Error::TrailingData(bit + bits),
+            Error::DuplicateSymbol(bit) => Error::DuplicateSymbol(bit + bits),
+            Error::InvalidLeafCount(bit) => Error::InvalidLeafCount(bit + bits),
+            error => error,
+        }
+    }
+}
+
+/// The deepest a leaf can be in a tree of 257 symbols, when every inner node has a leaf as one of
+/// its children
+const MAX_DEPTH: usize = 256;
+
+/// The deepest a leaf can be in a [`Tree`], whose codes are packed into 64 bits. This library's
+/// encoders never write deeper trees, so trees read as a `Tree` are held to it, while messages are
+/// still decoded with trees up to [`MAX_DEPTH`].
+const MAX_TREE_DEPTH: usize = 63;
+
+#[cfg(feature = "encode")]
+pub(crate) fn encode(bytes: &[u8], order: BitOrder) -> Result<Vec<u8>, Error> {
+    Ok(encode_with_checkpoints(bytes, order, usize::MAX, false)?.0)
+}
+
+/// Like [`encode`], with the tree built by [`Node::approximate_tree_for_message`]
+#[cfg(feature = "encode")]
+pub(crate) fn encode_fast(bytes: &[u8], order: BitOrder) -> Result<Vec<u8>, Error> {
+    Ok(encode_with_checkpoints(bytes, order, usize::MAX, true)?.0)
+}
+
+/// Inputs at least this long have their bytes counted on several threads
+#[cfg(all(feature = "encode", feature = "parallel"))]
+const PARALLEL_THRESHOLD: usize = 4 << 20;
+
+/// The number of times each byte value occurs in `bytes`
+#[cfg(feature = "encode")]
+pub(crate) fn histogram(bytes: &[u8]) -> [u32; 256] {
+    #[cfg(feature = "parallel")]
+    if bytes.len() >= PARALLEL_THRESHOLD {
+        return parallel_histogram(bytes);
+    }
+
+    let mut counts = [0; 256];
+    for &byte in bytes {
+        counts[byte as usize] += 1;
+    }
+    counts
+}
+
+/// Count each thread's share of `bytes` separately, then add the counts up
+#[cfg(all(feature = "encode", feature = "parallel"))]
+fn parallel_histogram(bytes: &[u8]) -> [u32; 256] {
+    let threads = std::thread::available_parallelism().map_or(1, |threads| threads.get());
+    // Small shares aren't worth a thread of their own
+    let share = bytes.len().div_ceil(threads).max(PARALLEL_THRESHOLD / 4);
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = bytes
+            .chunks(share)
+            .map(|chunk| {
+                scope.spawn(move || {
+                    let mut counts = [0u32; 256];
+                    for &byte in chunk {
+                        counts[byte as usize] += 1;
+                    }
+                    counts
+                })
+            })
+            .collect();
+        let mut counts = [0; 256];
+        for handle in handles {
+            let chunk_counts = handle.join().expect("counting bytes doesn't panic");
+            for (count, chunk_count) in counts.iter_mut().zip(chunk_counts) {
+                *count += chunk_count;
+            }
+        }
+        counts
+    })
+}
+
+/// Like [`encode`], also returning the bit offset in the message of every `interval`th symbol
+/// after the first, so a decoder can start from any of them with the tree. With `fast_tree`, the
+/// tree is built by [`Node::approximate_tree_for_message`].
+#[cfg(feature = "encode")]
+pub(crate) fn encode_with_checkpoints(
+    bytes: &[u8],
+    order: BitOrder,
+    interval: usize,
+    fast_tree: bool,
+) -> Result<(Vec<u8>, Vec<usize>), Error> {
+    if bytes.is_empty() {
+        return Err(Error::NoData);
+    }
+
+    let tree = match fast_tree {
+        true => Node::approximate_tree_for_message(bytes),
+        false => Node::tree_for_message(bytes),
+    };
+    let mut bits = BitWriter::with_order(order);
+    tree.serialize(&mut bits);
+    let codes = Codes::for_tree(&tree);
+    let mut offsets = Vec::new();
+    for (i, chunk) in bytes.chunks(interval).enumerate() {
+        if i > 0 {
+            offsets.push(bits.len());
+        }
+        codes.write_symbols(chunk, &mut bits);
+    }
+    Codes::write_code(codes.eom, &mut bits);
+
+    Ok((bits.into_bytes(), offsets))
+}
+
+/// Decode the first message in `bytes`, also returning the number of bytes that message occupied.
+/// Encoded messages are padded out to a whole byte, so another message may start right after it.
+/// Decoding stops once the output is longer than `max_len`, which callers treat as an error.
+#[cfg(feature = "decode")]
+pub(crate) fn decode_member(
+    bytes: &[u8],
+    order: BitOrder,
+    max_len: usize,
+) -> Result<(Vec<u8>, usize), crate::Error> {
+    if bytes.is_empty() {
+        return Err(Error::NoData.into());
+    }
+
+    let mut bits = BitReader::with_order(bytes, order);
+    let tree = Node::deserialize(&mut bits)?;
+    let decoded = tree.decode(&mut bits, max_len)?;
+    Ok((decoded, bits.position().div_ceil(8)))
+}
+
+/// Like [`decode_member`], but for untrusted data, failing on anything an encoder wouldn't write:
+/// a tree with more leaves than there are values or with a value twice, or bits after EOM that
+/// aren't zero padding. Bit offsets in errors are from the start of `bytes`.
+#[cfg(feature = "decode")]
+pub(crate) fn decode_member_strict(
+    bytes: &[u8],
+    order: BitOrder,
+    max_len: usize,
+) -> Result<(Vec<u8>, usize), crate::Error> {
+    if bytes.is_empty() {
+        return Err(Error::NoData.into());
+    }
+
+    let mut bits = BitReader::with_order(bytes, order);
+    let tree = Node::deserialize(&mut bits)?;
+    tree.check_leaves(bits.position())?;
+    let decoded = tree.decode(&mut bits, max_len)?;
+    let end = bits.position();
+    // Output past `max_len` stops decoding before EOM, and is the caller's error to report
+    if decoded.len() <= max_len && bits.read_n(end.next_multiple_of(8) - end) != Some(0) {
+        return Err(Error::TrailingData(end).into());
+    }
+    Ok((decoded, end.div_ceil(8)))
+}
+
+/// Like [`decode_member`], but walks the serialized tree where it lies in `bytes` instead of
+/// building it on the heap. Working memory is a few cursors rather than a node per symbol, at the
+/// cost of rescanning part of the tree's shape for every bit of the message.
+#[cfg(feature = "decode")]
+pub(crate) fn decode_member_in_place(
+    bytes: &[u8],
+    order: BitOrder,
+    max_len: usize,
+) -> Result<(Vec<u8>, usize), crate::Error> {
+    if bytes.is_empty() {
+        return Err(Error::NoData.into());
+    }
+
+    let mut bits = BitReader::with_order(bytes, order);
+    let tree = SerializedTree::read(&mut bits)?;
+    let mut decoded = Vec::new();
+    while decoded.len() <= max_len {
+        match tree.decode_symbol(&mut bits) {
+            Some(HuffmanValue::Symbol(s)) => reserve::push(&mut decoded, s)?,
+            Some(HuffmanValue::EndOfMessage) => break,
+            None => return Err(Error::TruncatedMessage.into()),
+        }
+    }
+    Ok((decoded, bits.position().div_ceil(8)))
+}
+
+/// Like [`decode_member_in_place`], but decodes into `out`, from a message that may be split in
+/// two, returning the number of bytes the message occupied. Nothing is allocated.
+#[cfg(feature = "decode")]
+pub(crate) fn decode_member_into_split(
+    bytes: SplitBytes,
+    order: BitOrder,
+    out: &mut SplitOut,
+) -> Result<usize, crate::Error> {
+    if bytes.len() == 0 {
+        return Err(Error::NoData.into());
+    }
+
+    let mut bits = bytes.bits(order);
+    let tree = SerializedTree::read(&mut bits)?;
+    loop {
+        match tree.decode_symbol(&mut bits) {
+            Some(HuffmanValue::Symbol(s)) => out.push(s)?,
+            Some(HuffmanValue::EndOfMessage) => return Ok(bits.position().div_ceil(8)),
+            None => return Err(Error::TruncatedMessage.into()),
+        }
+    }
+}
+
+/// Like [`encode`], but the message starts with the length of each code instead of the tree, and
+/// is coded with the [`Canonical`] code those lengths give. With `fast_tree`, the code lengths come
+/// from [`Node::approximate_tree_for_message`]. With `max_code_len`, the lengths are limited by
+/// [`limit_lengths`]. Returns `None` if some code would be longer than [`MAX_CANONICAL_LEN`].
+#[cfg(feature = "encode")]
+pub(crate) fn encode_canonical(
+    bytes: &[u8],
+    order: BitOrder,
+    fast_tree: bool,
+    max_code_len: Option<u8>,
+) -> Result<Option<Vec<u8>>, Error> {
+    if bytes.is_empty() {
+        return Err(Error::NoData);
+    }
+
+    let tree = match fast_tree {
+        true => Node::approximate_tree_for_message(bytes),
+        false => Node::tree_for_message(bytes),
+    };
+    let mut lengths = Tree::new(tree).code_lengths();
+    if let Some(max) = max_code_len {
+        limit_lengths(&mut lengths, &histogram(bytes), max);
+    }
+    let Some(code) = Canonical::build(lengths) else {
+        return Ok(None);
+    };
+    let mut bits = BitWriter::with_order(order);
+    code.write_lengths(&mut bits);
+    code.write(bytes, &mut bits);
+    Ok(Some(bits.into_bytes()))
+}
+
+/// Limit the code `lengths` for a message with the byte counts `counts` to `max` bits, keeping the
+/// code complete. Codes over the limit are cut to it, then the least frequent codes under it are
+/// lengthened until the code space is no longer overfilled, and finally the most frequent codes
+/// are shortened into any space that leaves. `max` must leave room for every code.
+#[cfg(feature = "encode")]
+fn limit_lengths(lengths: &mut [u8; 257], counts: &[u32; 256], max: u8) {
+    if lengths.iter().all(|&len| len <= max) {
+        return;
+    }
+    // EOM occurs once
+    let count = |value: usize| counts.get(value).copied().unwrap_or(1);
+    let mut values: Vec<usize> = (0..257).filter(|&value| lengths[value] > 0).collect();
+    values.sort_by_key(|&value| (count(value), value));
+
+    // The share of the code space a code of each length takes, out of `full`
+    let full = 1u64 << max;
+    let space = |len: u8| 1u64 << (max - len);
+    for &value in &values {
+        lengths[value] = lengths[value].min(max);
+    }
+    let mut used: u64 = values.iter().map(|&value| space(lengths[value])).sum();
+    while used > full {
+        for &value in &values {
+            if used <= full {
+                break;
+            }
+            if lengths[value] < max {
+                used -= space(lengths[value]) / 2;
+                lengths[value] += 1;
+            }
+        }
+    }
+    // Every code is a multiple of the space of the longest one, so this fills the space exactly
+    for &value in values.iter().rev() {
+        while lengths[value] > 1 && used + space(lengths[value]) <= full {
+            used += space(lengths[value]);
+            lengths[value] -= 1;
+        }
+    }
+}
+
+/// Read the code lengths at the start of a message written by [`encode_canonical`], returning the
+/// code and the number of bits they occupied
+#[cfg(feature = "decode")]
+pub(crate) fn read_canonical(bytes: &[u8], order: BitOrder) -> Result<(Canonical, usize), Error> {
+    let mut bits = BitReader::with_order(bytes, order);
+    let code = Canonical::read_lengths(&mut bits).ok_or(Error::FailedToDecodeHuffmanTree)?;
+    Ok((code, bits.position()))
+}
+
+/// Like [`decode_member`], for a message written by [`encode_canonical`]
+#[cfg(feature = "decode")]
+pub(crate) fn decode_canonical_member(
+    bytes: &[u8],
+    order: BitOrder,
+    max_len: usize,
+) -> Result<(Vec<u8>, usize), crate::Error> {
+    if bytes.is_empty() {
+        return Err(Error::NoData.into());
+    }
+
+    let mut bits = BitReader::with_order(bytes, order);
+    let code = Canonical::read_lengths(&mut bits).ok_or(Error::FailedToDecodeHuffmanTree)?;
+    let decoded = code.decode_bits(&mut bits, max_len)?;
+    Ok((decoded, bits.position().div_ceil(8)))
+}
+
+/// Like [`decode_member_into_split`], for a message written by [`encode_canonical`]
+#[cfg(feature = "decode")]
+pub(crate) fn decode_canonical_into_split(
+    bytes: SplitBytes,
+    order: BitOrder,
+    out: &mut SplitOut,
+) -> Result<usize, crate::Error> {
+    if bytes.len() == 0 {
+        return Err(Error::NoData.into());
+    }
+
+    let mut bits = bytes.bits(order);
+    let code = Canonical::read_lengths(&mut bits).ok_or(Error::FailedToDecodeHuffmanTree)?;
+    loop {
+        match code.decode_value(&mut bits) {
+            Some(value) if value < 256 => out.push(value as u8)?,
+            Some(_) => return Ok(bits.position().div_ceil(8)),
+            None => return Err(Error::TruncatedMessage.into()),
+        }
+    }
+}
+
+/// Read as much of the tree at the start of `bytes` as possible, the way [`Node::deserialize`]
+/// does, returning where reading stopped in bits and, if the tree is damaged, how much of it was
+/// read
+#[cfg(feature = "debug-errors")]
+pub(crate) fn read_partial_tree(
+    bytes: &[u8],
+    order: BitOrder,
+) -> (usize, Option<crate::debug::PartialTree>) {
+    let mut bits = BitReader::with_order(bytes, order);
+    let mut tree = crate::debug::PartialTree {
+        shape_bits: 0,
+        leaves: 0,
+        symbols: Vec::new(),
+        problem: "",
+    };
+    let damaged = |tree: crate::debug::PartialTree, bits: &BitReader, problem| {
+        let tree = crate::debug::PartialTree { problem, ..tree };
+        (bits.position(), Some(tree))
+    };
+
+    // The shape is the nodes in preorder, a set bit being a leaf. Each inner node adds a subtree
+    // still to be read.
+    let mut unread = 1usize;
+    while unread > 0 {
+        let Some(is_leaf) = bits.read_bit() else {
+            tree.shape_bits = bits.position();
+            return damaged(tree, &bits, "the data ends in the shape of the tree");
+        };
+        if is_leaf {
+            tree.leaves += 1;
+            unread -= 1;
+        } else {
+            unread += 1;
+        }
+    }
+    tree.shape_bits = bits.position();
+    if tree.shape_bits == 1 {
+        return damaged(tree, &bits, "the root is a leaf");
+    }
+
+    for _ in 0..tree.leaves {
+        let (Some(is_eom), Some(value)) = (bits.read_bit(), bits.read_n(8)) else {
+            return damaged(tree, &bits, "the data ends in the symbols of the tree");
+        };
+        tree.symbols.push((!is_eom).then_some(value as u8));
+    }
+    if !tree.symbols.contains(&None) {
+        return damaged(tree, &bits, "the tree has no end of message");
+    }
+    (bits.position(), None)
+}
+
+/// One code read from a message by [`decode_traced`]
+#[cfg(feature = "decode")]
+pub(crate) struct TracedCode {
+    /// The decoded byte, or `None` for the end of the message
+    pub symbol: Option<u8>,
+    /// The branches taken from the root to the symbol's leaf, `true` for right
+    pub path: Vec<bool>,
+    /// Where the code starts, in bits from the start of the message
+    pub bit_offset: usize,
+}
+
+/// Decode a message, recording every code along the way. Also returns the number of bits the
+/// tree occupies at the start of the message.
+#[cfg(feature = "decode")]
+pub(crate) fn decode_traced(
+    bytes: &[u8],
+    order: BitOrder,
+) -> Result<(usize, Vec<TracedCode>), Error> {
+    let mut bits = BitReader::with_order(bytes, order);
+    let tree = Node::deserialize(&mut bits)?;
+    let tree_bits = bits.position();
+
+    let mut codes = Vec::new();
+    loop {
+        let bit_offset = bits.position();
+        let mut path = Vec::new();
+        let mut cursor = &tree;
+        let symbol = loop {
+            let Node::Inner { left, right, .. } = cursor else {
+                unreachable!("the walk stops at leaves")
+            };
+            // Running out of bits before EOM ends the trace, as it ends `Node::decode`
+            let Some(bit) = bits.read_bit() else {
+                return Ok((tree_bits, codes));
+            };
+            path.push(bit);
+            cursor = if bit { right } else { left };
+            if let Node::Leaf { value, .. } = cursor {
+                break *value;
+            }
+        };
+        let symbol = match symbol {
+            HuffmanValue::Symbol(s) => Some(s),
+            HuffmanValue::EndOfMessage => None,
+        };
+        codes.push(TracedCode {
+            symbol,
+            path,
+            bit_offset,
+        });
+        if symbol.is_none() {
+            return Ok((tree_bits, codes));
+        }
+    }
+}
+
+/// A tree in its serialized form, see [`Node::serialize`]. The shape is a preorder traversal, so
+/// the left child of an inner node comes right after it, and the right child comes after the
+/// whole left subtree. The values of the leaves follow the shape, in the same order.
+#[cfg(feature = "decode")]
+struct SerializedTree<'a> {
+    /// Positioned at the root of the shape
+    shape: BitReader<'a>,
+    /// The position of the first leaf value
+    values: usize,
+    leaves: usize,
+}
+
+#[cfg(feature = "decode")]
+impl<'a> SerializedTree<'a> {
+    /// Check the tree at the reader's position, leaving it positioned at the start of the message.
+    /// The same trees are rejected as by [`Node::deserialize`].
+    fn read(bits: &mut BitReader<'a>) -> Result<Self, Error> {
+        let shape = bits.clone();
+        let leaves = Self::skip_checked(bits)?;
+        if leaves < 2 {
+            // Error, the tree should have at least one inner node
+            return Err(Error::FailedToDecodeHuffmanTree);
+        }
+        if leaves * Node::SYMBOL_SIZE > bits.remaining() {
+            // Error, there isn't enough data to fill out the leaf nodes
+            return Err(Error::FailedToDecodeHuffmanTree);
+        }
+
+        let values = bits.position();
+        let mut seen_eom = false;
+        for _ in 0..leaves {
+            seen_eom |= bits.read_bit().unwrap();
+            bits.read_n(8);
+        }
+        if !seen_eom {
+            // Error, the tree is required to have an EOM
+            return Err(Error::FailedToDecodeHuffmanTree);
+        }
+        Ok(Self {
+            shape,
+            values,
+            leaves,
+        })
+    }
+
+    /// Like [`SerializedTree::skip`] for a whole tree, checking that it is no deeper than
+    /// [`MAX_DEPTH`]
+    fn skip_checked(bits: &mut BitReader) -> Result<usize, Error> {
+        // Whether the path to the node being read goes right from each inner node along it, kept
+        // on the stack so that walking the tree in place doesn't allocate
+        let mut path = [false; MAX_DEPTH];
+        let mut depth = 0;
+        let mut leaves = 0;
+        loop {
+            if !bits.read_bit().ok_or(Error::FailedToDecodeHuffmanTree)? {
+                *path.get_mut(depth).ok_or(Error::TreeTooDeep)? = false;
+                depth += 1;
+                continue;
+            }
+            leaves += 1;
+            while depth > 0 && path[depth - 1] {
+                depth -= 1;
+            }
+            if depth == 0 {
+                return Ok(leaves);
+            }
+            path[depth - 1] = true;
+        }
+    }
+
+    /// Skip the subtree starting at the reader's position, returning the number of leaves in it
+    fn skip(bits: &mut BitReader) -> Option<usize> {
+        let mut pending = 1usize;
+        let mut leaves = 0;
+        while pending > 0 {
+            if bits.read_bit()? {
+                leaves += 1;
+                pending -= 1;
+            } else {
+                pending += 1;
+            }
+        }
+        Some(leaves)
+    }
+
+    /// Decode a single value, as [`Node::decode_symbol`] does
+    fn decode_symbol(&self, bits: &mut BitReader) -> Option<HuffmanValue> {
+        let mut node = self.shape.clone();
+        // The root was checked to be an inner node
+        node.read_bit();
+        let mut leaves_before = 0;
+
+        loop {
+            if bits.read_bit()? {
+                // The shape was checked to be complete, so skipping can't run out of bits
+                leaves_before += Self::skip(&mut node).unwrap();
+            }
+            if node.read_bit().unwrap() {
+                return Some(self.value(leaves_before));
+            }
+        }
+    }
+
+    fn value(&self, index: usize) -> HuffmanValue {
+        let mut value = self.shape.clone();
+        value.seek(self.values + index * Node::SYMBOL_SIZE);
+        let is_eom = value.read_bit().unwrap();
+        let value_bits = value.read_n(8).unwrap();
+        if is_eom {
+            HuffmanValue::EndOfMessage
+        } else {
+            HuffmanValue::Symbol(value_bits as u8)
+        }
+    }
+}
+
+/// The number of bytes of memory needed to build the tree at the start of `bytes`, without
+/// building it
+#[cfg(feature = "decode")]
+pub(crate) fn tree_memory(bytes: &[u8], order: BitOrder) -> Result<usize, Error> {
+    let mut bits = BitReader::with_order(bytes, order);
+    let tree = SerializedTree::read(&mut bits)?;
+    let table = match DecodeTable::worthwhile(&bits) {
+        true => std::mem::size_of::<[u16; 1 << TABLE_BITS]>(),
+        false => 0,
+    };
+    Ok((2 * tree.leaves - 1) * std::mem::size_of::<Node>() + table)
+}
+
+/// The number of bits a [`DecodeTable`] looks up at once
+#[cfg(feature = "decode")]
+const TABLE_BITS: usize = 10;
+
+/// Decodes a code of up to [`TABLE_BITS`] bits with one lookup, instead of walking the tree a bit
+/// at a time. Entries are indexed by the next [`TABLE_BITS`] bits of the message, as
+/// [`BitReader::peek`] returns them, and hold the value whose code those bits start with shifted
+/// left by 4, plus the code's length. Longer codes have entries of 0 and are decoded with the tree.
+#[cfg(feature = "decode")]
+#[derive(Clone)]
+pub(crate) struct DecodeTable {
+    entries: Box<[u16; 1 << TABLE_BITS]>,
+}
+
+#[cfg(feature = "decode")]
+impl DecodeTable {
+    fn new(tree: &Node, order: BitOrder) -> Self {
+        fn fill(entries: &mut [u16], node: &Node, path: usize, depth: usize, order: BitOrder) {
+            match node {
+                _ if depth > TABLE_BITS => {}
+                Node::Inner { left, right, .. } => {
+                    fill(entries, left, path, depth + 1, order);
+                    fill(entries, right, path | 1 << depth, depth + 1, order);
+                }
+                Node::Leaf { value, .. } => {
+                    let value = match value {
+                        HuffmanValue::Symbol(s) => *s as u16,
+                        HuffmanValue::EndOfMessage => 256,
+                    };
+                    // The code is followed by every combination of the bits after it
+                    let free = TABLE_BITS - depth;
+                    for rest in 0..1 << free {
+                        let index = match order {
+                            BitOrder::Lsb0 => path | rest << depth,
+                            BitOrder::Msb0 => {
+                                path.reverse_bits() >> (usize::BITS as usize - depth) << free | rest
+                            }
+                        };
+                        entries[index] = value << 4 | depth as u16;
+                    }
+                }
+            }
+        }
+
+        let mut entries = Box::new([0; 1 << TABLE_BITS]);
+        fill(entries.as_mut_slice(), tree, 0, 0, order);
+        Self { entries }
+    }
+
+    /// Whether the rest of a message is long enough that filling a table saves time
+    fn worthwhile(bits: &BitReader) -> bool {
+        bits.remaining() >= 8 << TABLE_BITS
+    }
+
+    /// Like [`Node::decode_symbol`], for the tree the table was built from
+    fn decode_symbol(&self, tree: &Node, bits: &mut BitReader) -> Option<HuffmanValue> {
+        if let Some(index) = bits.peek(TABLE_BITS) {
+            let entry = self.entries[index as usize];
+            let len = (entry & 0xf) as usize;
+            if len > 0 {
+                bits.seek(bits.position() + len);
+                return Some(match entry >> 4 {
+                    256 => HuffmanValue::EndOfMessage,
+                    value => HuffmanValue::Symbol(value as u8),
+                });
+            }
+        }
+        tree.decode_symbol(bits)
+    }
+}
+
+/// The number of bits the tree at the start of `bytes` occupies
+#[cfg(feature = "decode")]
+pub(crate) fn tree_bits(bytes: &[u8], order: BitOrder) -> Result<usize, Error> {
+    let mut bits = BitReader::with_order(bytes, order);
+    SerializedTree::read(&mut bits)?;
+    Ok(bits.position())
+}
+
+/// Lazily decodes the symbols of a message, see [`decode_iter`]
+#[cfg(feature = "decode")]
+pub(crate) struct DecodeIter<'a> {
+    tree: Node,
+    bits: BitReader<'a>,
+    done: bool,
+    /// Whether the bits ran out before EOM
+    truncated: bool,
+}
+
+#[cfg(feature = "decode")]
+impl Iterator for DecodeIter<'_> {
+    type Item = u8;
+
+    fn next(&mut self) -> Option<u8> {
+        if self.done {
+            return None;
+        }
+        match self.tree.decode_symbol(&mut self.bits) {
+            Some(HuffmanValue::Symbol(s)) => Some(s),
+            symbol => {
+                self.done = true;
+                self.truncated = symbol.is_none();
+                None
+            }
+        }
+    }
+}
+
+#[cfg(feature = "decode")]
+impl DecodeIter<'_> {
+    /// Continue decoding from the code at `bit_offset` in the message, which must be the start of
+    /// a code for the symbols that follow to make sense
+    pub fn resume(&mut self, bit_offset: usize) {
+        self.bits.seek(bit_offset);
+        self.done = false;
+        self.truncated = false;
+    }
+
+    /// Whether the iterator ended because the message was cut off before its end
+    pub fn truncated(&self) -> bool {
+        self.truncated
+    }
+}
+
+/// Decode the message in `bytes` one symbol at a time. Only the tree is decoded up front.
+#[cfg(feature = "decode")]
+pub(crate) fn decode_iter(bytes: &[u8], order: BitOrder) -> Result<DecodeIter<'_>, Error> {
     if bytes.is_empty() {
         return Err(Error::NoData);
     }
 
-    let tree = Node::tree_for_message(bytes);
-    let mut bits = tree.serialize();
-    let message = tree.encode(bytes);
+    let mut bits = BitReader::with_order(bytes, order);
+    let tree = Node::deserialize(&mut bits)?;
+    Ok(DecodeIter {
+        tree,
+        bits,
+        done: false,
+        truncated: false,
+    })
+}
+
+#[cfg(feature = "encode")]
+fn reverse_code(code: u64, len: usize) -> u64 {
+    if len == 0 {
+        return 0;
+    }
+    code.reverse_bits() >> (64 - len)
+}
+
+/// The code for every value in a tree. Codes are stored as the bits of the path to the leaf, with
+/// the first step in the least significant bit, and the path length. Byte values the tree has no
+/// leaf for have no code.
+#[cfg(feature = "encode")]
+#[derive(Debug, Clone)]
+struct Codes {
+    symbols: Box<[Option<(u64, usize)>; 256]>,
+    eom: (u64, usize),
+}
+
+#[cfg(feature = "encode")]
+impl Codes {
+    fn for_tree(tree: &Node) -> Self {
+        fn traverse(
+            codebook: &mut [Option<(u64, usize)>; 256],
+            eom_code: &mut Option<(u64, usize)>,
+            path: (u64, usize),
+            node: &Node,
+        ) {
+            match node {
+                Node::Leaf { value, .. } => match value {
+                    HuffmanValue::Symbol(s) => {
+                        codebook[*s as usize] = Some(path);
+                    }
+                    HuffmanValue::EndOfMessage => {
+                        *eom_code = Some(path);
+                    }
+                },
+                Node::Inner { left, right, .. } => {
+                    let (code, len) = path;
+                    assert!(len < 64, "huffman code too long");
+                    traverse(codebook, eom_code, (code, len + 1), left);
+                    traverse(codebook, eom_code, (code | (1 << len), len + 1), right);
+                }
+            }
+        }
+
+        let mut symbols = Box::new([None; 256]);
+        let mut eom = None;
+        traverse(&mut symbols, &mut eom, (0, 0), tree);
+        Self {
+            symbols,
+            eom: eom.expect("Missing EOM code"),
+        }
+    }
+
+    /// Write the codes for `bytes`, followed by EOM
+    fn write(&self, bytes: &[u8], bits: &mut BitWriter) {
+        self.write_symbols(bytes, bits);
+        Self::write_code(self.eom, bits);
+    }
+
+    /// Write the codes for `bytes`, without ending the message
+    fn write_symbols(&self, bytes: &[u8], bits: &mut BitWriter) {
+        for byte in bytes {
+            if let Some(code) = self.symbols[*byte as usize] {
+                Self::write_code(code, bits);
+            } else {
+                panic!("missing value in codebook");
+            }
+        }
+    }
+
+    fn write_code((code, len): (u64, usize), bits: &mut BitWriter) {
+        // The codes are written as single multi-bit values, which are written most significant bit
+        // first in Msb0 order, so the paths need to be reversed to start at the top
+        match bits.order() {
+            BitOrder::Lsb0 => bits.write_bits(code, len),
+            BitOrder::Msb0 => bits.write_bits(reverse_code(code, len), len),
+        }
+    }
+}
+
+/// Codes a message a few symbols at a time, for callers that can't code it all in one call. The
+/// result is laid out exactly as by [`encode`].
+#[cfg(feature = "encode")]
+pub(crate) struct MessageWriter {
+    codes: Codes,
+    bits: BitWriter,
+}
+
+#[cfg(feature = "encode")]
+impl MessageWriter {
+    /// Start a message with the tree [`encode`] builds for a message with these byte counts, at
+    /// least one of which must be more than zero
+    pub fn new(frequencies: &[u32; 256], order: BitOrder) -> Self {
+        let tree = Node::tree_for_frequencies(
+            (0..=255u8)
+                .map(|byte| (byte, frequencies[byte as usize]))
+                .filter(|&(_, count)| count > 0),
+            0,
+        );
+        let mut bits = BitWriter::with_order(order);
+        tree.serialize(&mut bits);
+        Self {
+            codes: Codes::for_tree(&tree),
+            bits,
+        }
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) {
+        self.codes.write_symbols(bytes, &mut self.bits);
+    }
+
+    /// End the message, returning its bytes
+    pub fn finish(mut self) -> Vec<u8> {
+        Codes::write_code(self.codes.eom, &mut self.bits);
+        self.bits.into_bytes()
+    }
+}
+
+/// A tree trained ahead of time on sample data, for coding messages that don't carry a tree of
+/// their own. Every byte value has a code, so any message can be coded with it.
+#[derive(Debug, Clone)]
+pub(crate) struct Tree {
+    root: Node,
+    #[cfg(feature = "encode")]
+    codes: Codes,
+}
+
+impl Tree {
+    /// Train a tree on the number of times each byte value was seen, across `messages` messages
+    #[cfg(feature = "encode")]
+    pub fn train(frequencies: &[u64; 256], messages: u64) -> Self {
+        // Counts are scaled down to fit in the tree's 32 bit counts. Every value gets a count of
+        // at least one, which also keeps codes well under 64 bits long.
+        let total = frequencies.iter().sum::<u64>() + messages + 256;
+        let scale = total.div_ceil(u32::MAX as u64 / 2).max(1);
+        let count = |count: u64| (count / scale).max(1) as u32;
+
+        let root = Node::tree_for_frequencies(
+            (0..=255u8).map(|byte| (byte, count(frequencies[byte as usize]))),
+            count(messages),
+        );
+        Self::new(root)
+    }
+
+    /// The tree [`encode`] builds for `bytes`, which must not be empty
+    #[cfg(feature = "encode")]
+    pub fn for_message(bytes: &[u8]) -> Self {
+        Self::new(Node::tree_for_message(bytes))
+    }
+
+    fn new(root: Node) -> Self {
+        Self {
+            #[cfg(feature = "encode")]
+            codes: Codes::for_tree(&root),
+            root,
+        }
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut bits = BitWriter::new();
+        self.root.serialize(&mut bits);
+        bits.into_bytes()
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Result<Self, Error> {
+        Self::deserialize_with_order(bytes, BitOrder::default())
+    }
+
+    /// Like [`Tree::deserialize`], for a tree written in a message with the given bit order
+    pub fn deserialize_with_order(bytes: &[u8], order: BitOrder) -> Result<Self, Error> {
+        let mut bits = BitReader::with_order(bytes, order);
+        let root = Node::deserialize(&mut bits)?;
+        if root.depth() > MAX_TREE_DEPTH {
+            return Err(Error::TreeTooDeep);
+        }
+        Ok(Self::new(root))
+    }
+
+    /// Write the tree as it's written at the start of a message that carries it
+    pub fn write_tree(&self, bits: &mut BitWriter) {
+        self.root.serialize(bits);
+    }
+
+    /// Write the codes for `bytes` followed by EOM, as a message that carries the tree codes them
+    /// after the tree. Writes nothing and returns `false` if the tree has no code for some byte.
+    #[cfg(feature = "encode")]
+    pub fn write_codes(&self, bytes: &[u8], bits: &mut BitWriter) -> bool {
+        if bytes
+            .iter()
+            .any(|&byte| self.codes.symbols[byte as usize].is_none())
+        {
+            return false;
+        }
+        self.codes.write(bytes, bits);
+        true
+    }
+
+    /// A table for [`Tree::decode_codes`] to decode with
+    #[cfg(feature = "decode")]
+    pub fn decode_table(&self, order: BitOrder) -> DecodeTable {
+        DecodeTable::new(&self.root, order)
+    }
+
+    /// Decode codes written by [`Tree::write_codes`] from `bits`, up to EOM. Decoding stops once
+    /// the output is longer than `max_len`.
+    #[cfg(feature = "decode")]
+    pub fn decode_codes(
+        &self,
+        bits: &mut BitReader,
+        table: &DecodeTable,
+        max_len: usize,
+    ) -> Result<Vec<u8>, crate::Error> {
+        self.root.decode_with_table(bits, max_len, Some(table))
+    }
+
+    /// The length of the code for `byte`, or for EOM if it's `None`
+    #[cfg(feature = "encode")]
+    pub fn code_len(&self, byte: Option<u8>) -> usize {
+        match byte {
+            Some(byte) => self.codes.symbols[byte as usize].map_or(0, |(_, len)| len),
+            None => self.codes.eom.1,
+        }
+    }
+
+    /// The length of the code for each byte value, followed by that for EOM
+    pub fn code_lengths(&self) -> [u8; 257] {
+        fn traverse(lengths: &mut [u8; 257], node: &Node, depth: u8) {
+            match node {
+                Node::Inner { left, right, .. } => {
+                    traverse(lengths, left, depth + 1);
+                    traverse(lengths, right, depth + 1);
+                }
+                Node::Leaf { value, .. } => {
+                    let index = match value {
+                        HuffmanValue::Symbol(s) => *s as usize,
+                        HuffmanValue::EndOfMessage => 256,
+                    };
+                    lengths[index] = depth;
+                }
+            }
+        }
+
+        let mut lengths = [0; 257];
+        traverse(&mut lengths, &self.root, 0);
+        lengths
+    }
+
+    /// The tree as JSON, in the shape described by [`Codebook::to_json`](crate::Codebook::to_json)
+    pub fn to_json(&self) -> String {
+        fn write(out: &mut String, node: &Node, code: &mut String, counts: bool) {
+            out.push_str(&format!("{{\"code\":\"{code}\""));
+            match node {
+                Node::Inner { count, left, right } => {
+                    if counts {
+                        out.push_str(&format!(",\"count\":{count}"));
+                    }
+                    for (name, bit, child) in [("left", '0', left), ("right", '1', right)] {
+                        out.push_str(&format!(",\"{name}\":"));
+                        code.push(bit);
+                        write(out, child, code, counts);
+                        code.pop();
+                    }
+                }
+                Node::Leaf { count, value } => {
+                    if counts {
+                        out.push_str(&format!(",\"count\":{count}"));
+                    }
+                    match value {
+                        HuffmanValue::Symbol(s) => out.push_str(&format!(",\"symbol\":{s}")),
+                        HuffmanValue::EndOfMessage => out.push_str(",\"eom\":true"),
+                    }
+                }
+            }
+            out.push('}');
+        }
+
+        let mut out = String::new();
+        write(&mut out, &self.root, &mut String::new(), self.has_counts());
+        out
+    }
+
+    /// Whether the tree has the counts it was built from, which deserialized trees don't
+    fn has_counts(&self) -> bool {
+        !matches!(self.root, Node::Inner { count: 0, .. })
+    }
+
+    /// Code `bytes` without including the tree. Trees loaded from elsewhere may have no code for
+    /// some bytes, which are escaped: coded as EOM, a set bit and the byte's 8 bits. A message
+    /// with escapes ends with EOM and a clear bit instead of EOM alone, so also returns whether
+    /// there were any.
+    #[cfg(feature = "encode")]
+    pub fn encode(&self, bytes: &[u8], order: BitOrder) -> (Vec<u8>, bool) {
+        let mut bits = BitWriter::with_order(order);
+        let escapes = bytes
+            .iter()
+            .any(|&byte| self.codes.symbols[byte as usize].is_none());
+        if !escapes {
+            self.codes.write(bytes, &mut bits);
+            return (bits.into_bytes(), false);
+        }
+
+        for byte in bytes {
+            match self.codes.symbols[*byte as usize] {
+                Some(code) => Codes::write_code(code, &mut bits),
+                None => {
+                    Codes::write_code(self.codes.eom, &mut bits);
+                    bits.write_bit(true);
+                    bits.write_bits(u64::from(*byte), 8);
+                }
+            }
+        }
+        Codes::write_code(self.codes.eom, &mut bits);
+        bits.write_bit(false);
+        (bits.into_bytes(), true)
+    }
+
+    /// Decode the first message coded with this tree in `bytes`, also returning the number of
+    /// bytes that message occupied. Decoding stops once the output is longer than `max_len`.
+    #[cfg(feature = "decode")]
+    pub fn decode_member(
+        &self,
+        bytes: &[u8],
+        order: BitOrder,
+        escapes: bool,
+        max_len: usize,
+    ) -> Result<(Vec<u8>, usize), crate::Error> {
+        let mut bits = BitReader::with_order(bytes, order);
+        if !escapes {
+            let decoded = self.root.decode(&mut bits, max_len)?;
+            return Ok((decoded, bits.position().div_ceil(8)));
+        }
+
+        let mut decoded = Vec::new();
+        while decoded.len() <= max_len {
+            let byte = match self.root.decode_symbol(&mut bits) {
+                Some(HuffmanValue::Symbol(s)) => s,
+                Some(HuffmanValue::EndOfMessage) => match bits.read_bit() {
+                    Some(true) => bits.read_n(8).ok_or(Error::TruncatedMessage)? as u8,
+                    Some(false) => break,
+                    None => return Err(Error::TruncatedMessage.into()),
+                },
+                None => return Err(Error::TruncatedMessage.into()),
+            };
+            reserve::push(&mut decoded, byte)?;
+        }
+        Ok((decoded, bits.position().div_ceil(8)))
+    }
+}
+
+/// The longest code a [`Canonical`] code may have
+pub(crate) const MAX_CANONICAL_LEN: usize = 32;
 
-    bits.extend_from_bitslice(&message);
-    bits.set_uninitialized(false);
-    Ok(bits.into_vec())
+/// A code given only by the length of the code for each byte value and for EOM, which is value
+/// 256. Codes of each length are assigned in order of value, shortest first, as in DEFLATE. It's
+/// built entirely in const fns, so a code can be a `static` in read-only memory.
+#[derive(Debug, Clone)]
+pub(crate) struct Canonical {
+    lengths: [u8; 257],
+    /// The code for each value, with the first bit in the least significant bit, as in [`Codes`]
+    codes: [u32; 257],
+    /// The values in order of their codes
+    // Only decoding needs `sorted` and `counts`
+    #[cfg_attr(not(feature = "decode"), allow(dead_code))]
+    sorted: [u16; 257],
+    /// The number of codes of each length
+    #[cfg_attr(not(feature = "decode"), allow(dead_code))]
+    counts: [u16; MAX_CANONICAL_LEN + 1],
 }
 
-pub(crate) fn decode(bytes: &[u8]) -> Result<Vec<u8>, Error> {
-    if bytes.is_empty() {
-        return Err(Error::NoData);
+impl Canonical {
+    /// Assign the codes for `lengths`. Panics, which is a compile error in a const, unless every
+    /// length is between 1 and [`MAX_CANONICAL_LEN`] and together they make a complete code, so
+    /// that every sequence of bits decodes.
+    pub const fn new(lengths: [u8; 257]) -> Self {
+        let mut value = 0;
+        while value < 257 {
+            let len = lengths[value] as usize;
+            assert!(
+                len >= 1 && len <= MAX_CANONICAL_LEN,
+                "code lengths must be between 1 and 32"
+            );
+            value += 1;
+        }
+        match Self::build(lengths) {
+            Some(code) => code,
+            None => panic!("code lengths must make a complete code"),
+        }
+    }
+
+    /// Like [`Canonical::new`], but values with a length of 0 have no code, and lengths that don't
+    /// make a complete code give `None`
+    const fn build(lengths: [u8; 257]) -> Option<Self> {
+        let mut counts = [0u16; MAX_CANONICAL_LEN + 1];
+        let mut value = 0;
+        while value < 257 {
+            let len = lengths[value] as usize;
+            if len > MAX_CANONICAL_LEN {
+                return None;
+            }
+            if len > 0 {
+                counts[len] += 1;
+            }
+            value += 1;
+        }
+
+        // A complete code uses up all of the code space, which is 2^32 codes of 32 bits
+        let mut space = 0u64;
+        let mut next = [0u64; MAX_CANONICAL_LEN + 1];
+        let mut offsets = [0usize; MAX_CANONICAL_LEN + 1];
+        let mut len = 1;
+        while len <= MAX_CANONICAL_LEN {
+            space += (counts[len] as u64) << (MAX_CANONICAL_LEN - len);
+            next[len] = (next[len - 1] + counts[len - 1] as u64) << 1;
+            offsets[len] = offsets[len - 1] + counts[len - 1] as usize;
+            len += 1;
+        }
+        if space != 1 << MAX_CANONICAL_LEN {
+            return None;
+        }
+
+        let mut codes = [0u32; 257];
+        let mut sorted = [0u16; 257];
+        let mut value = 0;
+        while value < 257 {
+            let len = lengths[value] as usize;
+            if len > 0 {
+                // Canonical codes are defined first bit first, so reverse them into path order
+                codes[value] = (next[len] as u32).reverse_bits() >> (32 - len);
+                next[len] += 1;
+                sorted[offsets[len]] = value as u16;
+                offsets[len] += 1;
+            }
+            value += 1;
+        }
+
+        Some(Self {
+            lengths,
+            codes,
+            sorted,
+            counts,
+        })
+    }
+
+    /// Write the code lengths at the start of a message: the number of byte values with a code,
+    /// less one, in 8 bits, and the longest length less one in 5 bits. Each length is then written
+    /// less one in just enough bits for the longest, first EOM's, and then for each byte value with
+    /// a code, in order, the gap since the one before as an exp-Golomb code and the value's length.
+    #[cfg(feature = "encode")]
+    fn write_lengths(&self, bits: &mut BitWriter) {
+        let coded = (0..256).filter(|&value| self.lengths[value] > 0);
+        let longest = self.lengths.iter().max().map_or(1, |&len| len as u64);
+        let len_bits = (u64::BITS - (longest - 1).leading_zeros()) as usize;
+        bits.write_bits(coded.clone().count() as u64 - 1, 8);
+        bits.write_bits(longest - 1, 5);
+        bits.write_bits(self.lengths[256] as u64 - 1, len_bits);
+        let mut next = 0;
+        for value in coded {
+            // The gap plus one, whose highest set bit follows as many clear bits as there are
+            // bits below it
+            let gap = (value - next + 1) as u64;
+            let width = gap.ilog2() as usize;
+            bits.write_bits(0, width);
+            bits.write_bit(true);
+            bits.write_bits(gap, width);
+            bits.write_bits(self.lengths[value] as u64 - 1, len_bits);
+            next = value + 1;
+        }
+    }
+
+    /// Read the code lengths written by [`Canonical::write_lengths`], or `None` if they run out or
+    /// don't make a complete code
+    #[cfg(feature = "decode")]
+    fn read_lengths(bits: &mut BitReader) -> Option<Self> {
+        let mut lengths = [0; 257];
+        let count = bits.read_n(8)? + 1;
+        let len_bits = (u64::BITS - bits.read_n(5)?.leading_zeros()) as usize;
+        lengths[256] = bits.read_n(len_bits)? as u8 + 1;
+        let mut next = 0usize;
+        for _ in 0..count {
+            let mut width = 0;
+            while !bits.read_bit()? {
+                width += 1;
+                if width > 8 {
+                    return None;
+                }
+            }
+            let gap = (1 << width | bits.read_n(width)?) as usize;
+            let value = next + gap - 1;
+            if value > 255 {
+                return None;
+            }
+            lengths[value] = bits.read_n(len_bits)? as u8 + 1;
+            next = value + 1;
+        }
+        Self::build(lengths)
+    }
+
+    pub fn lengths(&self) -> [u8; 257] {
+        self.lengths
+    }
+
+    /// The length of the code for `byte`, or for EOM if it's `None`
+    #[cfg(feature = "encode")]
+    pub fn code_len(&self, byte: Option<u8>) -> usize {
+        self.lengths[byte.map_or(256, usize::from)] as usize
+    }
+
+    /// Code `bytes`, followed by EOM
+    #[cfg(feature = "encode")]
+    pub fn encode(&self, bytes: &[u8], order: BitOrder) -> Vec<u8> {
+        let mut bits = BitWriter::with_order(order);
+        self.write(bytes, &mut bits);
+        bits.into_bytes()
+    }
+
+    /// Write the codes for `bytes`, which must all have one, followed by EOM
+    #[cfg(feature = "encode")]
+    fn write(&self, bytes: &[u8], bits: &mut BitWriter) {
+        for value in bytes.iter().map(|&byte| byte as usize).chain([256]) {
+            Codes::write_code(
+                (self.codes[value] as u64, self.lengths[value] as usize),
+                bits,
+            );
+        }
+    }
+
+    /// Decode the first message in `bytes`, as [`Tree::decode_member`] does
+    #[cfg(feature = "decode")]
+    pub fn decode_member(
+        &self,
+        bytes: &[u8],
+        order: BitOrder,
+        max_len: usize,
+    ) -> Result<(Vec<u8>, usize), crate::Error> {
+        let mut bits = BitReader::with_order(bytes, order);
+        let decoded = self.decode_bits(&mut bits, max_len)?;
+        Ok((decoded, bits.position().div_ceil(8)))
+    }
+
+    /// Decode values from `bits` up to EOM, stopping once the output is longer than `max_len`
+    #[cfg(feature = "decode")]
+    fn decode_bits(&self, bits: &mut BitReader, max_len: usize) -> Result<Vec<u8>, crate::Error> {
+        let mut decoded = Vec::new();
+        while decoded.len() <= max_len {
+            match self.decode_value(bits) {
+                Some(value) if value < 256 => reserve::push(&mut decoded, value as u8)?,
+                Some(_) => break,
+                None => return Err(Error::TruncatedMessage.into()),
+            }
+        }
+        Ok(decoded)
     }
 
-    let bits = bytes.view_bits();
-    if let Some((tree, bits)) = Node::deserialize(bits) {
-        return Ok(tree.decode(bits));
-    } else {
-        return Err(Error::FailedToDecodeHuffmanTree);
+    /// Decode a single value, or `None` if the bits run out first
+    #[cfg(feature = "decode")]
+    fn decode_value(&self, bits: &mut BitReader) -> Option<u16> {
+        // The first code of each length follows on from the last one of the length before
+        let (mut code, mut first, mut index) = (0u64, 0u64, 0usize);
+        for &count in &self.counts[1..] {
+            code |= bits.read_bit()? as u64;
+            if code < first + count as u64 {
+                return Some(self.sorted[index + (code - first) as usize]);
+            }
+            index += count as usize;
+            first = (first + count as u64) << 1;
+            code <<= 1;
+        }
+        unreachable!("the code is complete")
+    }
+
+    /// The tree with the same codes, for drawing and serializing the code
+    pub fn to_tree(&self) -> Tree {
+        fn build(canonical: &Canonical, path: u64, depth: usize) -> Node {
+            let leaf = (0..257).find(|&value| {
+                canonical.lengths[value] != 0
+                    && canonical.lengths[value] as usize == depth
+                    && canonical.codes[value] as u64 == path
+            });
+            match leaf {
+                Some(value) => Node::Leaf {
+                    count: 0,
+                    value: match u8::try_from(value) {
+                        Ok(byte) => HuffmanValue::Symbol(byte),
+                        Err(_) => HuffmanValue::EndOfMessage,
+                    },
+                },
+                None => Node::Inner {
+                    count: 0,
+                    left: Box::new(build(canonical, path, depth + 1)),
+                    right: Box::new(build(canonical, path | (1 << depth), depth + 1)),
+                },
+            }
+        }
+        Tree::new(build(self, 0, 0))
+    }
+}
+
+/// Draws the tree with each node's code, from the root down, along with the counts it was built
+/// from when it has them. Trees that were deserialized don't.
+impl fmt::Display for Tree {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn draw(
+            f: &mut fmt::Formatter<'_>,
+            node: &Node,
+            code: &mut String,
+            prefix: &mut String,
+            counts: bool,
+        ) -> fmt::Result {
+            let count = match node {
+                Node::Inner { count, .. } | Node::Leaf { count, .. } => *count,
+            };
+            let label = if code.is_empty() {
+                "root"
+            } else {
+                code.as_str()
+            };
+            write!(f, "{label}")?;
+            if let Node::Leaf { value, .. } = node {
+                match value {
+                    HuffmanValue::Symbol(s) if s.is_ascii_graphic() || *s == b' ' => {
+                        write!(f, " '{}'", *s as char)?
+                    }
+                    HuffmanValue::Symbol(s) => write!(f, " 0x{s:02x}")?,
+                    HuffmanValue::EndOfMessage => write!(f, " EOM")?,
+                }
+            }
+            if counts {
+                write!(f, " ({count})")?;
+            }
+            writeln!(f)?;
+
+            if let Node::Inner { left, right, .. } = node {
+                for (bit, child, last) in [('0', left, false), ('1', right, true)] {
+                    let (branch, indent) = if last {
+                        ("`-- ", "    ")
+                    } else {
+                        ("|-- ", "|   ")
+                    };
+                    write!(f, "{prefix}{branch}")?;
+                    code.push(bit);
+                    prefix.push_str(indent);
+                    draw(f, child, code, prefix, counts)?;
+                    code.pop();
+                    prefix.truncate(prefix.len() - indent.len());
+                }
+            }
+            Ok(())
+        }
+
+        draw(
+            f,
+            &self.root,
+            &mut String::new(),
+            &mut String::new(),
+            self.has_counts(),
+        )
     }
 }
 
@@ -45,7 +1425,9 @@ enum HuffmanValue {
     EndOfMessage,
 }
 
-#[derive(Debug)]
+// Counts are only needed to build trees, which decoding never does
+#[cfg_attr(not(feature = "encode"), allow(dead_code))]
+#[derive(Debug, Clone)]
 enum Node {
     Inner {
         count: u32,
@@ -59,6 +1441,7 @@ enum Node {
 }
 
 impl Node {
+    #[cfg(feature = "encode")]
     fn join(left: Self, right: Self) -> Self {
         Node::Inner {
             count: left.count() + right.count(),
@@ -67,27 +1450,94 @@ impl Node {
         }
     }
 
+    #[cfg(feature = "encode")]
     fn new(count: u32, value: HuffmanValue) -> Self {
         Self::Leaf { count, value }
     }
 
     /// Invariant: The tree returned by this constructor will always have at least one inner node.
     /// Calling this function with an empty slice is an error, and will panic.
+    #[cfg(feature = "encode")]
     fn tree_for_message(bytes: &[u8]) -> Self {
         assert!(!bytes.is_empty());
 
-        let frequencies = bytes.iter().fold(HashMap::new(), |mut acc, &byte| {
-            *acc.entry(byte).or_insert(0) += 1;
-            acc
-        });
+        let frequencies = histogram(bytes);
+        Self::tree_for_frequencies(
+            (0..=255u8)
+                .map(|byte| (byte, frequencies[byte as usize]))
+                .filter(|&(_, count)| count > 0),
+            0,
+        )
+    }
+
+    /// Like [`Node::tree_for_message`], but without sorting and merging nodes. Each value gets a
+    /// Shannon code length, the bits needed to pick it out of the message by its count, and codes
+    /// are then shortened, most frequent values first, until they use up the whole code space.
+    /// That gives a tree whose leaves can be laid out directly, shortest codes leftmost.
+    #[cfg(feature = "encode")]
+    fn approximate_tree_for_message(bytes: &[u8]) -> Self {
+        assert!(!bytes.is_empty());
+
+        let frequencies = histogram(bytes);
+        // EOM is counted once, so that it gets a code
+        let total = bytes.len() as u64 + 1;
+        let mut leaves: Vec<(u64, usize, HuffmanValue)> = (0..=255u8)
+            .filter(|&byte| frequencies[byte as usize] > 0)
+            .map(|byte| {
+                (
+                    frequencies[byte as usize] as u64,
+                    HuffmanValue::Symbol(byte),
+                )
+            })
+            .chain([(1, HuffmanValue::EndOfMessage)])
+            .map(|(count, value)| {
+                let len = total.div_ceil(count).next_power_of_two().trailing_zeros();
+                (count, len.max(1) as usize, value)
+            })
+            .collect();
 
+        // The code space left over, in units of the longest code. Shannon lengths never use more
+        // than the whole space, and shortening a code of length `len` uses up as many units as
+        // that code already takes.
+        let longest = leaves.iter().map(|&(_, len, _)| len).max().unwrap();
+        let mut space = (1u64 << longest)
+            - leaves
+                .iter()
+                .map(|&(_, len, _)| 1u64 << (longest - len))
+                .sum::<u64>();
+        leaves.sort_by_key(|&(count, _, _)| std::cmp::Reverse(count));
+        for (_, len, _) in &mut leaves {
+            while *len > 1 && 1 << (longest - *len) <= space {
+                space -= 1 << (longest - *len);
+                *len -= 1;
+            }
+        }
+        debug_assert_eq!(space, 0);
+
+        fn build(leaves: &[(u64, usize, HuffmanValue)], next: &mut usize, depth: usize) -> Node {
+            let (count, len, value) = leaves[*next];
+            if len == depth {
+                *next += 1;
+                return Node::new(count as u32, value);
+            }
+            let left = build(leaves, next, depth + 1);
+            let right = build(leaves, next, depth + 1);
+            Node::join(left, right)
+        }
+        leaves.sort_by_key(|&(_, len, _)| len);
+        build(&leaves, &mut 0, 0)
+    }
+
+    /// Build a tree for symbols with the given counts, plus an EOM leaf, which also ensures the
+    /// tree has an inner node
+    #[cfg(feature = "encode")]
+    fn tree_for_frequencies(frequencies: impl IntoIterator<Item = (u8, u32)>, eom: u32) -> Self {
         let mut nodes: Vec<Node> = frequencies
             .into_iter()
             .map(|(value, count)| Node::new(count, HuffmanValue::Symbol(value)))
             .collect();
 
-        // In addition to giving us a way to mark EOM, this also ensures we have an inner node
-        nodes.push(Node::new(0, HuffmanValue::EndOfMessage));
+        nodes.push(Node::new(eom, HuffmanValue::EndOfMessage));
 
         while nodes.len() > 1 {
             nodes.sort_by_key(|node| node.count());
@@ -99,6 +1549,7 @@ impl Node {
         nodes.pop().unwrap()
     }
 
+    #[cfg(feature = "encode")]
     fn count(&self) -> u32 {
         match self {
             Self::Inner { count, .. } => *count,
@@ -106,69 +1557,57 @@ impl Node {
         }
     }
 
-    fn encode(&self, bytes: &[u8]) -> BitVec {
-        let (codebook, eom_bitvec) = {
-            // Precompute a codebook for the tree
-            let mut codebook: HashMap<u8, BitVec> = HashMap::new();
-            let mut eom_bitvec: Option<BitVec> = None;
-            fn traverse(
-                codebook: &mut HashMap<u8, BitVec>,
-                eom_bitvec: &mut Option<BitVec>,
-                path: &mut BitVec,
-                node: &Node,
-            ) {
-                match node {
-                    Node::Leaf { value, .. } => match value {
-                        HuffmanValue::Symbol(s) => {
-                            codebook.insert(*s, path.clone());
-                        }
-                        HuffmanValue::EndOfMessage => {
-                            *eom_bitvec = Some(path.clone());
-                        }
-                    },
-                    Node::Inner { left, right, .. } => {
-                        path.push(false);
-                        traverse(codebook, eom_bitvec, path, left);
-                        path.pop();
-                        path.push(true);
-                        traverse(codebook, eom_bitvec, path, right);
-                        path.pop();
-                    }
-                }
-            }
-
-            let mut path = BitVec::new();
-            traverse(&mut codebook, &mut eom_bitvec, &mut path, self);
-
-            (codebook, eom_bitvec)
-        };
-
-        let mut bits = BitVec::new();
-        for byte in bytes {
-            if let Some(encoded) = codebook.get(byte) {
-                bits.extend_from_bitslice(encoded);
-            } else {
-                panic!("missing value in codebook");
-            }
+    /// The depth of the deepest leaf below this node
+    fn depth(&self) -> usize {
+        match self {
+            Self::Inner { left, right, .. } => 1 + left.depth().max(right.depth()),
+            Self::Leaf { .. } => 0,
         }
+    }
 
-        // EOM
-        bits.extend_from_bitslice(&eom_bitvec.expect("Missing EOM bitvec"));
-
-        bits
+    /// Decode symbols until EOM, or until more than `max_len` have been decoded. Running out of
+    /// bits before EOM fails with [`Error::TruncatedMessage`].
+    #[cfg(feature = "decode")]
+    fn decode(&self, bits: &mut BitReader, max_len: usize) -> Result<Vec<u8>, crate::Error> {
+        let table = DecodeTable::worthwhile(bits).then(|| DecodeTable::new(self, bits.order()));
+        self.decode_with_table(bits, max_len, table.as_ref())
     }
 
-    fn decode(&self, bits: &BitSlice) -> Vec<u8> {
+    /// Like [`Node::decode`], looking codes up in `table` if there is one
+    #[cfg(feature = "decode")]
+    fn decode_with_table(
+        &self,
+        bits: &mut BitReader,
+        max_len: usize,
+        table: Option<&DecodeTable>,
+    ) -> Result<Vec<u8>, crate::Error> {
         let mut ret = Vec::new();
+        while ret.len() <= max_len {
+            let symbol = match table {
+                Some(table) => table.decode_symbol(self, bits),
+                None => self.decode_symbol(bits),
+            };
+            match symbol {
+                Some(HuffmanValue::Symbol(s)) => reserve::push(&mut ret, s)?,
+                Some(HuffmanValue::EndOfMessage) => return Ok(ret),
+                None => return Err(Error::TruncatedMessage.into()),
+            }
+        }
+        Ok(ret)
+    }
 
+    /// Decode a single value, advancing the reader past the value's code. Returns `None` if the
+    /// bits run out before a complete code is read.
+    #[cfg(feature = "decode")]
+    fn decode_symbol(&self, bits: &mut BitReader) -> Option<HuffmanValue> {
         let mut cursor = self;
         // no single node trees allowed
         assert!(matches!(cursor, Node::Inner { .. }));
 
         // we're going to peel off one bit at a time, traversing the tree til we reach a leaf
-        for bit in bits {
+        while let Some(bit) = bits.read_bit() {
             match cursor {
-                Node::Inner { left, right, .. } => match *bit {
+                Node::Inner { left, right, .. } => match bit {
                     false => {
                         cursor = left.as_ref();
                     }
@@ -181,142 +1620,174 @@ impl Node {
                 }
             }
 
-            // If we have a leaf, save that value and reset the cursor state
             if let Node::Leaf { value, .. } = cursor {
-                match value {
-                    HuffmanValue::EndOfMessage => {
-                        return ret;
-                    }
-                    HuffmanValue::Symbol(s) => {
-                        ret.push(*s);
-                        cursor = &self;
-                    }
-                }
+                return Some(*value);
             }
         }
 
-        // If we've gotten here, we must have run out of bits without reaching EOM. This probably
-        // indicates that there was only a partial message. It's perhaps best to return what we
-        // have, since there's no affordance in our API for a result + error.
-        ret
+        None
     }
 
     /// A compact representation of a huffman encoding tree. A preorder traversal indicating whether
     /// nodes are leaves or not, followed by the value data.
-    fn serialize(&self) -> BitVec {
+    fn serialize(&self, bits: &mut BitWriter) {
         // traverse the tree
-        fn traverse(tree: &mut BitVec, values: &mut Vec<HuffmanValue>, n: &Node) {
+        fn traverse(tree: &mut BitWriter, values: &mut Vec<HuffmanValue>, n: &Node) {
             match n {
                 Node::Leaf { value, .. } => {
-                    tree.push(true);
+                    tree.write_bit(true);
                     values.push(*value)
                 }
                 Node::Inner { left, right, .. } => {
-                    tree.push(false);
+                    tree.write_bit(false);
                     traverse(tree, values, left);
                     traverse(tree, values, right);
                 }
             }
         }
 
-        let mut tree = BitVec::new();
         let mut values = Vec::<HuffmanValue>::new();
-        traverse(&mut tree, &mut values, self);
+        traverse(bits, &mut values, self);
 
         // Append the symbol values
         for value in values {
-            // This is an extended representation, which takes 9 bits. The most significant bit
-            // is 1 if the value is EOM, and 0 otherwise
+            // This is an extended representation, which takes 9 bits. The first bit is 1 if the
+            // value is EOM, and 0 otherwise
             match value {
                 HuffmanValue::EndOfMessage => {
-                    tree.push(true);
-                    tree.extend_from_bitslice(0u8.view_bits::<Lsb0>());
+                    bits.write_bit(true);
+                    bits.write_bits(0, 8);
                 }
                 HuffmanValue::Symbol(s) => {
-                    tree.push(false);
-                    tree.extend_from_bitslice(s.view_bits::<Lsb0>());
+                    bits.write_bit(false);
+                    bits.write_bits(s as u64, 8);
                 }
             }
         }
-
-        tree
     }
 
     const SYMBOL_SIZE: usize = 9;
-    /// Decode a tree from the prefix of a bitslice
-    fn deserialize(bits: &BitSlice) -> Option<(Self, &BitSlice)> {
-        fn helper<'a>(leaf_count: &mut usize, bits: &'a BitSlice) -> Option<(Node, &'a BitSlice)> {
-            let (is_leaf, rest) = bits.split_first()?;
-            if *is_leaf {
-                *leaf_count += 1;
-                // No counts in the rehydrated tree, no values yet
-                return Some((
-                    Node::Leaf {
-                        count: 0,
-                        value: HuffmanValue::Symbol(0),
-                    },
-                    rest,
-                ));
+
+    /// Check that a tree read by [`Node::deserialize`], which ended at bit `end`, has no more
+    /// leaves than there are values, and no value twice
+    #[cfg(feature = "decode")]
+    fn check_leaves(&self, end: usize) -> Result<(), Error> {
+        let mut values = Vec::new();
+        let mut unvisited = vec![self];
+        while let Some(node) = unvisited.pop() {
+            match node {
+                Node::Leaf { value, .. } => values.push(*value),
+                Node::Inner { left, right, .. } => {
+                    unvisited.push(right);
+                    unvisited.push(left);
+                }
             }
+        }
 
-            let (left, rest) = helper(leaf_count, rest)?;
-            let (right, rest) = helper(leaf_count, rest)?;
-            let node = Node::Inner {
-                count: 0,
-                left: Box::new(left),
-                right: Box::new(right),
+        // The values are at the end of the tree, in preorder
+        let start = end - values.len() * Self::SYMBOL_SIZE;
+        let at = |i: usize| start + i * Self::SYMBOL_SIZE;
+        if values.len() > 257 {
+            return Err(Error::InvalidLeafCount(at(257)));
+        }
+        let mut seen = [false; 257];
+        for (i, value) in values.into_iter().enumerate() {
+            let index = match value {
+                HuffmanValue::Symbol(byte) => byte as usize,
+                HuffmanValue::EndOfMessage => 256,
             };
-            Some((node, rest))
+            if std::mem::replace(&mut seen[index], true) {
+                return Err(Error::DuplicateSymbol(at(i)));
+            }
         }
-
+        Ok(())
+    }
+    /// Decode a tree from the bit reader, leaving it positioned at the start of the message. The
+    /// tree is read with a stack of its own rather than by recursion, so crafted input with a
+    /// long run of inner nodes can't overflow the call stack.
+    fn deserialize(bits: &mut BitReader) -> Result<Self, Error> {
+        // The inner nodes whose subtrees are being read, each with its left child once read
+        let mut pending: Vec<Option<Node>> = Vec::new();
         let mut leaf_count: usize = 0;
-        let (mut tree, remaining) = helper(&mut leaf_count, bits)?;
+        let mut tree = loop {
+            if pending.len() > MAX_DEPTH {
+                return Err(Error::TreeTooDeep);
+            }
+            let is_leaf = bits.read_bit().ok_or(Error::FailedToDecodeHuffmanTree)?;
+            if !is_leaf {
+                pending.push(None);
+                continue;
+            }
+            leaf_count += 1;
+            // No counts in the rehydrated tree, no values yet
+            let mut node = Node::Leaf {
+                count: 0,
+                value: HuffmanValue::Symbol(0),
+            };
+            // Complete every inner node whose right subtree this leaf finishes
+            let root = loop {
+                match pending.last_mut() {
+                    None => break Some(node),
+                    Some(left @ None) => {
+                        *left = Some(node);
+                        break None;
+                    }
+                    Some(Some(_)) => {
+                        let left = pending.pop().unwrap().unwrap();
+                        node = Node::Inner {
+                            count: 0,
+                            left: Box::new(left),
+                            right: Box::new(node),
+                        };
+                    }
+                }
+            };
+            if let Some(root) = root {
+                break root;
+            }
+        };
 
-        if (leaf_count * Self::SYMBOL_SIZE) > remaining.len() {
+        if matches!(tree, Node::Leaf { .. }) {
+            // Error, the tree should have at least one inner node
+            return Err(Error::FailedToDecodeHuffmanTree);
+        }
+        if (leaf_count * Self::SYMBOL_SIZE) > bits.remaining() {
             // Error, there isn't enough data to fill out the leaf nodes
-            return None;
+            return Err(Error::FailedToDecodeHuffmanTree);
         }
 
+        // Visit the leaves in preorder, deserializing byte values from the stream
         let mut seen_eom = false;
-        // traverse the new tree, deserializing byte values from the stream
-        fn traverse<'a>(bits: &'a BitSlice, seen_eom: &mut bool, node: &mut Node) -> &'a BitSlice {
+        let mut unvisited = vec![&mut tree];
+        while let Some(node) = unvisited.pop() {
             match node {
                 Node::Leaf { value, .. } => {
-                    let (value_bits, rest) = bits.split_at(Node::SYMBOL_SIZE);
-                    let (is_eom, value_bits) = value_bits.split_first().unwrap();
-                    if *is_eom {
-                        *seen_eom = true;
+                    // Enough bits for every leaf were checked for above
+                    let is_eom = bits.read_bit().unwrap();
+                    let value_bits = bits.read_n(8).unwrap();
+                    if is_eom {
+                        seen_eom = true;
                         *value = HuffmanValue::EndOfMessage
                     } else {
-                        *value = HuffmanValue::Symbol(value_bits.load());
+                        *value = HuffmanValue::Symbol(value_bits as u8);
                     }
-                    return rest;
                 }
                 Node::Inner { left, right, .. } => {
-                    let rest = traverse(bits, seen_eom, left);
-                    let rest = traverse(rest, seen_eom, right);
-                    return rest;
+                    unvisited.push(right);
+                    unvisited.push(left);
                 }
             }
         }
-
-        let remaining = traverse(remaining, &mut seen_eom, &mut tree);
         if !seen_eom {
             // Error, the tree is required to have an EOM
-            return None;
-        }
-        if matches!(tree, Node::Leaf { .. }) {
-            // Error, the tree should have at least one inner node
-            return None;
+            return Err(Error::FailedToDecodeHuffmanTree);
         }
-        return Some((tree, remaining));
+        Ok(tree)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use bitvec::bits;
-
     use super::*;
 
     #[test]
@@ -352,34 +1823,446 @@ mod tests {
          * Thus the whole message is 41 (48 including padding) bits long:
          * 0b00111_001100001_100000000_001100011_00_00_00_1_01_1111111
          */
-        let tree = bits![u8, Lsb0; 0, 0, 1, 1, 1];
-        let message = bits![u8, Lsb0; 0, 0, 0, 0, 0, 0, 1, 0, 1];
-        let padding = bits![u8, Lsb0; 1, 1, 1, 1, 1, 1, 1];
+        let tree = [false, false, true, true, true];
+        let message = [false, false, false, false, false, false, true, false, true];
+        let padding = [true, true, true, true, true, true, true];
 
-        let mut bytes = BitVec::new();
-        bytes.extend_from_bitslice(&tree);
+        let mut bytes = BitWriter::new();
+        for bit in tree {
+            bytes.write_bit(bit);
+        }
 
-        let values = vec![Some(0x61 as u8), None, Some(0x63)];
+        let values = vec![Some(0x61_u8), None, Some(0x63)];
         for value in values {
             if let Some(v) = value {
-                bytes.push(false);
-                bytes.extend_from_bitslice(v.view_bits::<Lsb0>());
+                bytes.write_bit(false);
+                bytes.write_bits(v as u64, 8);
             } else {
-                bytes.push(true);
-                bytes.extend_from_bitslice(0u8.view_bits::<Lsb0>());
+                bytes.write_bit(true);
+                bytes.write_bits(0, 8);
             }
         }
 
-        bytes.extend_from_bitslice(&message);
+        for bit in message {
+            bytes.write_bit(bit);
+        }
         assert_eq!(bytes.len(), 41);
 
-        bytes.extend_from_bitslice(&padding);
+        for bit in padding {
+            bytes.write_bit(bit);
+        }
         assert_eq!(bytes.len(), 48);
 
         dbg!(&bytes);
 
         let value = vec![0x61, 0x61, 0x61, 0x63];
-        let decoded = decode(&bytes.into_vec()).unwrap();
+        let (decoded, _) = decode_member(&bytes.into_bytes(), BitOrder::Lsb0, usize::MAX).unwrap();
         assert_eq!(dbg!(decoded), value);
     }
+
+    /// A message with a tree of the given shape, in preorder with `false` for inner nodes, whose
+    /// leaves have the given values, and the message `codes`
+    fn crafted(shape: &[bool], values: &[Option<u8>], codes: &[bool]) -> Vec<u8> {
+        let mut bits = BitWriter::new();
+        for &is_leaf in shape {
+            bits.write_bit(is_leaf);
+        }
+        for value in values {
+            bits.write_bit(value.is_none());
+            bits.write_bits(value.unwrap_or(0) as u64, 8);
+        }
+        for &bit in codes {
+            bits.write_bit(bit);
+        }
+        bits.into_bytes()
+    }
+
+    #[test]
+    fn strict() {
+        let message = b"strictly decoded";
+        let encoded = encode(message, BitOrder::Lsb0).unwrap();
+        assert_eq!(
+            decode_member_strict(&encoded, BitOrder::Lsb0, usize::MAX).unwrap(),
+            decode_member(&encoded, BitOrder::Lsb0, usize::MAX).unwrap()
+        );
+
+        // "a" then EOM, with a tree of three leaves: a at 0, EOM at 10 and c at 11
+        let shape = [false, false, true, true, true];
+        let codes = [false, false, false, true];
+        let valid = crafted(&shape, &[Some(b'a'), None, Some(b'c')], &codes);
+        assert_eq!(
+            decode_member_strict(&valid, BitOrder::Lsb0, usize::MAX).unwrap(),
+            (b"a".to_vec(), valid.len())
+        );
+
+        // Padding that isn't zero
+        let mut padded = valid.clone();
+        *padded.last_mut().unwrap() |= 0x80;
+        assert!(decode_member(&padded, BitOrder::Lsb0, usize::MAX).is_ok());
+        assert_eq!(
+            decode_member_strict(&padded, BitOrder::Lsb0, usize::MAX),
+            Err(Error::TrailingData(5 + 27 + 4).into())
+        );
+
+        // A value twice
+        let duplicate = crafted(&shape, &[Some(b'a'), None, Some(b'a')], &codes);
+        assert!(decode_member(&duplicate, BitOrder::Lsb0, usize::MAX).is_ok());
+        assert_eq!(
+            decode_member_strict(&duplicate, BitOrder::Lsb0, usize::MAX),
+            Err(Error::DuplicateSymbol(5 + 18).into())
+        );
+
+        // A complete tree of 512 leaves, with EOM last, so its code is all ones
+        fn complete(depth: usize, shape: &mut Vec<bool>) {
+            shape.push(depth == 0);
+            if depth > 0 {
+                complete(depth - 1, shape);
+                complete(depth - 1, shape);
+            }
+        }
+        let mut shape = Vec::new();
+        complete(9, &mut shape);
+        let mut values: Vec<Option<u8>> = (0..511).map(|i| Some(i as u8)).collect();
+        values.push(None);
+        let oversized = crafted(&shape, &values, &[true; 9]);
+        assert!(decode_member(&oversized, BitOrder::Lsb0, usize::MAX).is_ok());
+        assert_eq!(
+            decode_member_strict(&oversized, BitOrder::Lsb0, usize::MAX),
+            Err(Error::InvalidLeafCount(shape.len() + 257 * 9).into())
+        );
+    }
+
+    #[test]
+    fn lazy_decode() {
+        let message = b"decoded one symbol at a time";
+        let encoded = encode(message, BitOrder::Lsb0).unwrap();
+
+        let decoded: Vec<u8> = decode_iter(&encoded, BitOrder::Lsb0).unwrap().collect();
+        assert_eq!(decoded, message);
+
+        let mut iter = decode_iter(&encoded, BitOrder::Lsb0).unwrap();
+        let message_start = iter.bits.position();
+        assert_eq!(iter.by_ref().take(7).collect::<Vec<_>>(), b"decoded");
+        // Only the codes of the symbols that were asked for have been read
+        let consumed = iter.bits.position() - message_start;
+        assert!(consumed < (encoded.len() * 8 - message_start) / 2);
+    }
+
+    #[test]
+    fn msb0_roundtrip() {
+        let message = b"bits packed from the top of each byte";
+        let lsb0 = encode(message, BitOrder::Lsb0).unwrap();
+        let msb0 = encode(message, BitOrder::Msb0).unwrap();
+        assert_ne!(lsb0, msb0);
+
+        let (decoded, len) = decode_member(&msb0, BitOrder::Msb0, usize::MAX).unwrap();
+        assert_eq!(decoded, message);
+        assert_eq!(len, msb0.len());
+        assert_eq!(
+            decode_iter(&msb0, BitOrder::Msb0)
+                .unwrap()
+                .collect::<Vec<_>>(),
+            message
+        );
+    }
+
+    #[test]
+    fn trained_tree() {
+        let mut frequencies = [0; 256];
+        for &byte in b"the quick brown fox jumps over the lazy dog" {
+            frequencies[byte as usize] += 1;
+        }
+        let tree = Tree::train(&frequencies, 1);
+        let tree = Tree::deserialize(&tree.serialize()).unwrap();
+
+        for order in [BitOrder::Lsb0, BitOrder::Msb0] {
+            let (encoded, escapes) = tree.encode(b"the lazy fox", order);
+            assert!(!escapes);
+            assert_eq!(
+                tree.decode_member(&encoded, order, false, usize::MAX)
+                    .unwrap(),
+                (b"the lazy fox".to_vec(), encoded.len())
+            );
+
+            // Bytes that weren't seen in training can still be coded
+            let unseen = [0, 255, 128];
+            let (encoded, _) = tree.encode(&unseen, order);
+            assert_eq!(
+                tree.decode_member(&encoded, order, false, usize::MAX)
+                    .unwrap()
+                    .0,
+                unseen
+            );
+        }
+    }
+
+    #[test]
+    fn approximate_tree() {
+        let text: String = (0..40)
+            .map(|i| {
+                format!(
+                    "{{\"id\":{i},\"status\":\"ok\",\"latency_ms\":{}}}",
+                    i * 7 % 90
+                )
+            })
+            .collect();
+        let mut skewed = vec![b'a'; 5000];
+        skewed.extend(0..=255u8);
+        let messages: [&[u8]; 4] = [b"x", b"ab", text.as_bytes(), &skewed];
+        for message in messages {
+            for order in [BitOrder::Lsb0, BitOrder::Msb0] {
+                let fast = encode_fast(message, order).unwrap();
+                let (decoded, len) = decode_member(&fast, order, usize::MAX).unwrap();
+                assert_eq!((decoded.as_slice(), len), (message, fast.len()));
+            }
+        }
+
+        let exact = encode(text.as_bytes(), BitOrder::Lsb0).unwrap();
+        let fast = encode_fast(text.as_bytes(), BitOrder::Lsb0).unwrap();
+        assert!(fast.len() * 100 <= exact.len() * 105);
+    }
+
+    #[test]
+    fn in_place() {
+        let message = b"decoded without building the tree, decoded in place";
+        for order in [BitOrder::Lsb0, BitOrder::Msb0] {
+            let encoded = encode(message, order).unwrap();
+            let expected = decode_member(&encoded, order, usize::MAX).unwrap();
+            assert_eq!(
+                decode_member_in_place(&encoded, order, usize::MAX).unwrap(),
+                expected
+            );
+
+            // A message without its end is an error, rather than a shorter message
+            let truncated = &encoded[..encoded.len() - 4];
+            for decoded in [
+                decode_member_in_place(truncated, order, usize::MAX),
+                decode_member(truncated, order, usize::MAX),
+            ] {
+                assert_eq!(decoded, Err(Error::TruncatedMessage.into()));
+            }
+        }
+
+        // A lone leaf, and a tree without EOM
+        for tree in [vec![0b0000_0001, 0, 0], vec![0b0000_0110, 0, 0]] {
+            assert!(matches!(
+                decode_member_in_place(&tree, BitOrder::Lsb0, usize::MAX),
+                Err(crate::Error::Huffman(Error::FailedToDecodeHuffmanTree))
+            ));
+            assert!(decode_member(&tree, BitOrder::Lsb0, usize::MAX).is_err());
+        }
+    }
+
+    /// A comb: every inner node has a leaf on its left, so the last leaves are `inner` deep. EOM
+    /// is the first leaf, followed by the bytes in order.
+    fn comb(inner: usize) -> Vec<u8> {
+        let mut bits = BitWriter::new();
+        for _ in 0..inner {
+            bits.write_bit(false);
+            bits.write_bit(true);
+        }
+        bits.write_bit(true);
+        bits.write_bit(true);
+        bits.write_bits(0, 8);
+        for value in 0..inner {
+            bits.write_bit(false);
+            bits.write_bits(value as u64 % 256, 8);
+        }
+        // The message is just EOM
+        bits.write_bit(false);
+        bits.into_bytes()
+    }
+
+    #[test]
+    fn deep_tree() {
+        let deepest = comb(MAX_DEPTH);
+        assert_eq!(
+            decode_member(&deepest, BitOrder::Lsb0, usize::MAX).unwrap(),
+            (Vec::new(), deepest.len())
+        );
+        assert!(decode_member_in_place(&deepest, BitOrder::Lsb0, usize::MAX).is_ok());
+
+        let too_deep = comb(MAX_DEPTH + 1);
+        assert_eq!(
+            decode_member(&too_deep, BitOrder::Lsb0, usize::MAX),
+            Err(Error::TreeTooDeep.into())
+        );
+        assert_eq!(
+            decode_member_in_place(&too_deep, BitOrder::Lsb0, usize::MAX),
+            Err(Error::TreeTooDeep.into())
+        );
+
+        // A long run of inner nodes, which used to recurse once per bit
+        let mut inner = vec![0; 1 << 20];
+        inner.push(0xff);
+        assert_eq!(
+            decode_member(&inner, BitOrder::Lsb0, usize::MAX),
+            Err(Error::TreeTooDeep.into())
+        );
+        assert_eq!(tree_memory(&inner, BitOrder::Lsb0), Err(Error::TreeTooDeep));
+        assert_eq!(Tree::deserialize(&inner).unwrap_err(), Error::TreeTooDeep);
+
+        // Trees read as a `Tree`, as for inspecting a message, have their codes packed into 64 bits
+        assert!(Tree::deserialize(&comb(MAX_TREE_DEPTH)).is_ok());
+        let deep = comb(MAX_TREE_DEPTH + 1);
+        assert_eq!(Tree::deserialize(&deep).unwrap_err(), Error::TreeTooDeep);
+        let mut frame = crate::frame::plain_header(crate::frame::Encoding::Huffman).to_vec();
+        frame.extend(&deep);
+        assert_eq!(
+            crate::MessageTree::from_message(&frame).err(),
+            Some(Error::TreeTooDeep.into())
+        );
+        assert!(decode_member(&deep, BitOrder::Lsb0, usize::MAX).is_ok());
+    }
+
+    /// A message whose byte counts follow the Fibonacci numbers, which gives codes of every length
+    /// up to the number of distinct bytes
+    fn fibonacci_message(distinct: u8) -> Vec<u8> {
+        let (mut a, mut b) = (1, 1);
+        let mut message = Vec::new();
+        for byte in 0..distinct {
+            message.extend(std::iter::repeat_n(byte, a));
+            (a, b) = (b, a + b);
+        }
+        message
+    }
+
+    #[test]
+    fn limited_lengths() {
+        let message = fibonacci_message(24);
+        let unlimited = Tree::new(Node::tree_for_message(&message)).code_lengths();
+        assert!(unlimited.iter().any(|&len| len > 20));
+
+        for max in [9, 12, 20] {
+            let coded = encode_canonical(&message, BitOrder::Lsb0, false, Some(max))
+                .unwrap()
+                .unwrap();
+            let (code, _) = read_canonical(&coded, BitOrder::Lsb0).unwrap();
+            assert_eq!(code.lengths().into_iter().max(), Some(max));
+            assert_eq!(
+                decode_canonical_member(&coded, BitOrder::Lsb0, usize::MAX)
+                    .unwrap()
+                    .0,
+                message
+            );
+        }
+
+        // Lengths already within the limit are kept
+        let mut lengths = unlimited;
+        limit_lengths(&mut lengths, &histogram(&message), 32);
+        assert_eq!(lengths, unlimited);
+    }
+
+    #[test]
+    fn fuzz_trees() {
+        // Random data, mostly zero bits so the trees are deep, must be rejected without panicking
+        let mut state = 0x2545_f491u32;
+        for len in 1..400 {
+            let data: Vec<u8> = (0..len)
+                .map(|_| {
+                    state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                    let byte = (state >> 16) as u8;
+                    byte & (state >> 8) as u8 & (state >> 24) as u8
+                })
+                .collect();
+            for order in [BitOrder::Lsb0, BitOrder::Msb0] {
+                let built = decode_member(&data, order, 1 << 16).map(|(bytes, _)| bytes);
+                let walked = decode_member_in_place(&data, order, 1 << 16).map(|_| ());
+                assert_eq!(built.is_ok(), walked.is_ok());
+            }
+        }
+    }
+
+    #[test]
+    fn table() {
+        // Byte `i` occurs about 1.5^i times, so the rarest bytes have codes longer than the table
+        let mut message = Vec::new();
+        for i in 0..24u8 {
+            message.extend(std::iter::repeat_n(i, 1.5f64.powi(i as i32) as usize));
+        }
+        message.reverse();
+        for order in [BitOrder::Lsb0, BitOrder::Msb0] {
+            let encoded = encode(&message, order).unwrap();
+            let mut bits = BitReader::with_order(&encoded, order);
+            let tree = Node::deserialize(&mut bits).unwrap();
+            assert!(DecodeTable::worthwhile(&bits));
+            assert!(
+                Tree::new(tree)
+                    .code_lengths()
+                    .iter()
+                    .any(|&len| len as usize > TABLE_BITS)
+            );
+
+            let (decoded, len) = decode_member(&encoded, order, usize::MAX).unwrap();
+            assert_eq!(decoded, message);
+            assert_eq!(len, encoded.len());
+            assert_eq!(
+                decode_member(&encoded[..encoded.len() - 1], order, usize::MAX),
+                Err(Error::TruncatedMessage.into())
+            );
+        }
+    }
+
+    #[test]
+    fn display() {
+        // The tree from `test_bug_padding_decoded_as_data`
+        let mut bits = BitWriter::new();
+        for bit in [false, false, true, true, true] {
+            bits.write_bit(bit);
+        }
+        for (is_eom, value) in [(false, b'a'), (true, 0), (false, b'\n')] {
+            bits.write_bit(is_eom);
+            bits.write_bits(value as u64, 8);
+        }
+        let tree = Tree::deserialize(&bits.into_bytes()).unwrap();
+        assert_eq!(
+            tree.to_string(),
+            "root\n|-- 0\n|   |-- 00 'a'\n|   `-- 01 EOM\n`-- 1 0x0a\n"
+        );
+        assert_eq!(
+            tree.to_json(),
+            concat!(
+                r#"{"code":"","left":{"code":"0","left":{"code":"00","symbol":97},"#,
+                r#""right":{"code":"01","eom":true}},"right":{"code":"1","symbol":10}}"#
+            )
+        );
+
+        let mut frequencies = [0; 256];
+        frequencies[b'x' as usize] = 10;
+        let trained = Tree::train(&frequencies, 1).to_string();
+        assert!(trained.starts_with("root (266)\n"));
+        assert!(trained.contains(" 'x' (10)\n"));
+        let json = Tree::train(&frequencies, 1).to_json();
+        assert!(json.starts_with(r#"{"code":"","count":266,"left":"#));
+        assert!(json.contains(r#","count":10,"symbol":120}"#));
+        assert_eq!(trained.lines().count(), 2 * 257 - 1);
+    }
+
+    #[test]
+    fn decode_member_length() {
+        let first = encode(b"first message", BitOrder::Lsb0).unwrap();
+        let second = encode(b"and a second one", BitOrder::Lsb0).unwrap();
+
+        let (decoded, len) = decode_member(&first, BitOrder::Lsb0, usize::MAX).unwrap();
+        assert_eq!(decoded, b"first message");
+        assert_eq!(len, first.len());
+
+        let concatenated = [first.clone(), second].concat();
+        let (decoded, len) = decode_member(&concatenated, BitOrder::Lsb0, usize::MAX).unwrap();
+        assert_eq!(decoded, b"first message");
+        assert_eq!(len, first.len());
+    }
+
+    #[test]
+    #[cfg(feature = "parallel")]
+    fn parallel_histogram_matches() {
+        let bytes: Vec<u8> = (0..PARALLEL_THRESHOLD as u32 * 2 + 12345)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+            .collect();
+        let mut expected = [0u32; 256];
+        for &byte in &bytes {
+            expected[byte as usize] += 1;
+        }
+        assert_eq!(histogram(&bytes), expected);
+    }
 }
@@ -0,0 +1,196 @@
+//! Interning of strings into a compressed arena. Each distinct string is stored once and named by
+//! a small [`Symbol`]. Strings are gathered into blocks, and each full block is compressed with a
+//! [`Codebook`] trained on the first block, so large, repetitive tables of names take a fraction
+//! of their size. Resolving a symbol decompresses only the block holding it.
+
+use std::collections::HashMap;
+use std::hash::{BuildHasher, RandomState};
+
+use crate::codebook::Codebook;
+use crate::frame;
+use crate::options::EncodeOptions;
+use crate::varint;
+
+/// Names a string interned by a [`CompressedInterner`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Symbol(u32);
+
+impl Symbol {
+    /// The number of strings interned before this one
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// Interns strings, storing them compressed. Each distinct string is stored once and named by a
+/// [`Symbol`]. Full blocks of strings are compressed with a [`Codebook`] trained on the first
+/// block, and resolving a symbol decompresses only the block holding it.
+pub struct CompressedInterner {
+    block_len: usize,
+    codebook: Option<Codebook>,
+    blocks: Vec<Box<[u8]>>,
+    /// The strings interned since the last block was compressed
+    tail: Vec<String>,
+    /// The symbols of the strings with each hash, which are compared to find a string
+    symbols: HashMap<u64, Vec<Symbol>>,
+    hasher: RandomState,
+}
+
+impl CompressedInterner {
+    /// An empty interner that compresses `block_len` strings at a time. Larger blocks compress
+    /// better, but resolving a symbol decompresses its whole block.
+    ///
+    /// Panics if `block_len` is zero.
+    pub fn new(block_len: usize) -> Self {
+        assert!(block_len > 0, "block length must be greater than zero");
+        Self {
+            block_len,
+            codebook: None,
+            blocks: Vec::new(),
+            tail: Vec::with_capacity(block_len),
+            symbols: HashMap::new(),
+            hasher: RandomState::new(),
+        }
+    }
+
+    /// The number of distinct strings interned
+    pub fn len(&self) -> usize {
+        self.blocks.len() * self.block_len + self.tail.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The codebook the blocks are compressed with, once the first block is full
+    pub fn codebook(&self) -> Option<&Codebook> {
+        self.codebook.as_ref()
+    }
+
+    /// The number of bytes of the compressed blocks. Strings that haven't been compressed yet
+    /// aren't counted.
+    pub fn compressed_size(&self) -> usize {
+        self.blocks.iter().map(|block| block.len()).sum()
+    }
+
+    /// The symbol for `string`, interning it if it hasn't been already
+    pub fn intern(&mut self, string: &str) -> Result<Symbol, crate::Error> {
+        if let Some(symbol) = self.get(string)? {
+            return Ok(symbol);
+        }
+        let symbol = Symbol(u32::try_from(self.len()).expect("too many strings interned"));
+        self.tail.push(string.to_owned());
+        self.symbols
+            .entry(self.hasher.hash_one(string))
+            .or_default()
+            .push(symbol);
+        if self.tail.len() == self.block_len {
+            self.compress_tail()?;
+        }
+        Ok(symbol)
+    }
+
+    /// The symbol for `string`, if it has been interned
+    pub fn get(&self, string: &str) -> Result<Option<Symbol>, crate::Error> {
+        let Some(symbols) = self.symbols.get(&self.hasher.hash_one(string)) else {
+            return Ok(None);
+        };
+        for &symbol in symbols {
+            if self.resolve(symbol)?.as_deref() == Some(string) {
+                return Ok(Some(symbol));
+            }
+        }
+        Ok(None)
+    }
+
+    /// The string named by `symbol`, or `None` if it came from another interner
+    pub fn resolve(&self, symbol: Symbol) -> Result<Option<String>, crate::Error> {
+        let (block, offset) = (
+            symbol.index() / self.block_len,
+            symbol.index() % self.block_len,
+        );
+        let Some(block) = self.blocks.get(block) else {
+            let index = symbol.index() - self.blocks.len() * self.block_len;
+            return Ok(self.tail.get(index).cloned());
+        };
+        let payload = frame::decode_with_codebook(block, self.codebook.as_ref())?.payload;
+        let mut rest = payload.as_slice();
+        // Blocks are only written by `compress_tail`, so they hold `block_len` strings
+        let mut next = || {
+            let len = varint::read(&mut rest).expect("interned strings are well formed") as usize;
+            let (string, tail) = rest.split_at(len);
+            rest = tail;
+            string
+        };
+        for _ in 0..offset {
+            next();
+        }
+        Ok(Some(String::from_utf8(next().to_vec()).unwrap()))
+    }
+
+    fn compress_tail(&mut self) -> Result<(), crate::Error> {
+        let codebook = self
+            .codebook
+            .get_or_insert_with(|| Codebook::train(&self.tail));
+        let mut payload = Vec::new();
+        for string in &self.tail {
+            varint::write(string.len() as u64, &mut payload);
+            payload.extend_from_slice(string.as_bytes());
+        }
+        let block = frame::encode_with_codebook(
+            &payload,
+            &frame::Header::default(),
+            &EncodeOptions::default(),
+            Some(codebook),
+        )?;
+        self.blocks.push(block.into_boxed_slice());
+        self.tail.clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(i: usize) -> String {
+        format!(
+            "crate::module_{}::submodule_{}::function_{}",
+            i % 7,
+            i % 13,
+            i
+        )
+    }
+
+    #[test]
+    fn intern_and_resolve() {
+        let mut interner = CompressedInterner::new(64);
+        let symbols: Vec<Symbol> = (0..1000)
+            .map(|i| interner.intern(&path(i)).unwrap())
+            .collect();
+        assert_eq!(interner.len(), 1000);
+        assert!(interner.codebook().is_some());
+
+        // Interning a string again gives the same symbol, whether it's compressed yet or not
+        for i in [0, 63, 64, 500, 999] {
+            assert_eq!(interner.intern(&path(i)).unwrap(), symbols[i]);
+            assert_eq!(interner.resolve(symbols[i]).unwrap(), Some(path(i)));
+        }
+        assert_eq!(interner.len(), 1000);
+        assert_eq!(interner.get("missing").unwrap(), None);
+        assert_eq!(interner.resolve(Symbol(1000)).unwrap(), None);
+
+        let raw: usize = (0..1000).map(|i| path(i).len()).sum();
+        assert!(interner.compressed_size() * 3 < raw * 2);
+    }
+
+    #[test]
+    fn empty_strings() {
+        let mut interner = CompressedInterner::new(2);
+        let empty = interner.intern("").unwrap();
+        let other = interner.intern("x").unwrap();
+        assert_eq!(interner.intern("").unwrap(), empty);
+        assert_eq!(interner.resolve(empty).unwrap().as_deref(), Some(""));
+        assert_eq!(interner.resolve(other).unwrap().as_deref(), Some("x"));
+    }
+}
@@ -0,0 +1,173 @@
+//! Compression of integer sequences like ids, offsets and timestamps, which are usually sorted or
+//! close together. Each value is stored as the zigzag coded difference from the one before, as a
+//! varint, so nearby values take a byte each. The bytes of the varints are then split into planes,
+//! first bytes first, so that the low bytes, which vary, and the high bytes, which are mostly the
+//! same, are each grouped together before the whole is compressed.
+
+use crate::frame;
+#[cfg(feature = "encode")]
+use crate::options::EncodeOptions;
+use crate::varint;
+
+/// A varint of a 64 bit value has at most this many bytes
+const MAX_PLANES: usize = 10;
+
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The planes don't hold the number of values recorded before them
+    Malformed,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Error::Malformed => "the integer planes don't hold the values they describe",
+        })
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Marks integer sequences, so they can't be mistaken for serialized values
+fn tag() -> u32 {
+    crate::fingerprint("huffnpuff::ints")
+}
+
+/// Compress a sequence of integers, to be decoded with [`puff_ints`]
+#[cfg(feature = "encode")]
+pub fn huff_ints(values: &[u64]) -> Result<Vec<u8>, crate::Error> {
+    let mut planes: Vec<Vec<u8>> = vec![Vec::new(); MAX_PLANES];
+    let mut previous = 0u64;
+    let mut varint = Vec::with_capacity(MAX_PLANES);
+    for &value in values {
+        let delta = value.wrapping_sub(previous) as i64;
+        previous = value;
+        varint.clear();
+        varint::write(((delta << 1) ^ (delta >> 63)) as u64, &mut varint);
+        for (plane, &byte) in planes.iter_mut().zip(&varint) {
+            plane.push(byte);
+        }
+    }
+
+    let mut payload = Vec::new();
+    varint::write(values.len() as u64, &mut payload);
+    for plane in planes {
+        payload.extend(plane);
+    }
+    let header = frame::Header {
+        fingerprint: Some(tag()),
+        ..frame::Header::default()
+    };
+    frame::encode(&payload, &header, &EncodeOptions::default())
+}
+
+/// Decode a sequence of integers compressed with [`huff_ints`]
+#[cfg(feature = "decode")]
+pub fn puff_ints(bytes: &[u8]) -> Result<Vec<u64>, crate::Error> {
+    let frame = frame::decode(bytes)?;
+    if frame.header.fingerprint != Some(tag()) {
+        return Err(crate::Error::TypeMismatch);
+    }
+
+    let mut rest = frame.payload.as_slice();
+    let count = varint::read(&mut rest).ok_or(Error::Malformed)?;
+    let count = usize::try_from(count)
+        .ok()
+        .filter(|&count| count <= rest.len())
+        .ok_or(Error::Malformed)?;
+
+    // Each plane has a byte for every value whose varint continued past the plane before
+    let mut deltas = vec![0u64; count];
+    let mut continuing: Vec<usize> = (0..count).collect();
+    for plane in 0..MAX_PLANES {
+        if continuing.is_empty() {
+            break;
+        }
+        let (bytes, tail) = rest
+            .split_at_checked(continuing.len())
+            .ok_or(Error::Malformed)?;
+        rest = tail;
+        let mut next = Vec::new();
+        for (&index, &byte) in continuing.iter().zip(bytes) {
+            deltas[index] |= ((byte & 0x7f) as u64) << (7 * plane);
+            if byte & 0x80 != 0 {
+                next.push(index);
+            }
+        }
+        continuing = next;
+    }
+    if !continuing.is_empty() || !rest.is_empty() {
+        return Err(Error::Malformed.into());
+    }
+
+    let mut previous = 0u64;
+    Ok(deltas
+        .into_iter()
+        .map(|zigzag| {
+            let delta = (zigzag >> 1) as i64 ^ -((zigzag & 1) as i64);
+            previous = previous.wrapping_add(delta as u64);
+            previous
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let sequences: [Vec<u64>; 5] = [
+            vec![],
+            vec![0],
+            vec![u64::MAX, 0, u64::MAX, 1 << 63, 7],
+            (0..1000)
+                .map(|i| 1_700_000_000_000 + i * 1000 + i % 7)
+                .collect(),
+            (0..500).rev().map(|i| i * i).collect(),
+        ];
+        for values in sequences {
+            let compressed = huff_ints(&values).unwrap();
+            assert_eq!(puff_ints(&compressed).unwrap(), values);
+        }
+    }
+
+    #[test]
+    fn timestamps_are_small() {
+        let timestamps: Vec<u64> = (0..10_000)
+            .map(|i| 1_700_000_000_000 + i * 250 + (i * 7919) % 13)
+            .collect();
+        let compressed = huff_ints(&timestamps).unwrap();
+        assert!(compressed.len() * 4 < crate::huff(&timestamps).unwrap().len());
+        assert!(compressed.len() < 2 * timestamps.len());
+    }
+
+    #[test]
+    fn not_ints() {
+        let compressed = crate::huff(&vec![1u64, 2, 3]).unwrap();
+        assert_eq!(puff_ints(&compressed), Err(crate::Error::TypeMismatch));
+        let ints = huff_ints(&[1, 2, 3]).unwrap();
+        assert_eq!(
+            crate::puff::<Vec<u64>>(&ints),
+            Err(crate::Error::TypeMismatch)
+        );
+
+        // Planes with fewer bytes than the values need
+        let header = frame::Header {
+            fingerprint: Some(tag()),
+            ..frame::Header::default()
+        };
+        let options = EncodeOptions::default();
+        let truncated = frame::encode(&[3, 2, 2], &header, &options).unwrap();
+        assert_eq!(
+            puff_ints(&truncated),
+            Err(crate::Error::Ints(Error::Malformed))
+        );
+        let continued = frame::encode(&[1, 0x82], &header, &options).unwrap();
+        assert_eq!(
+            puff_ints(&continued),
+            Err(crate::Error::Ints(Error::Malformed))
+        );
+    }
+}
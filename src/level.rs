@@ -0,0 +1,196 @@
+//! Choosing how hard to work at compressing, either fixed with a [`Level`] or within a time
+//! budget with a [`BudgetedEncoder`]
+
+use std::time::{Duration, Instant};
+
+use crate::frame;
+use crate::options::EncodeOptions;
+use crate::transform::Transform;
+
+/// How much work goes into compressing a value. Each level tries everything the level below it
+/// does and keeps the smallest result, so a higher level never compresses worse.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Level {
+    /// Store the data as it is
+    Store,
+    /// Huffman code the data, as [`huff`](crate::huff) does
+    Huffman,
+    /// Also try delta and run length coding before huffman coding
+    Transforms,
+    /// Also try the Burrows-Wheeler and move to front transforms, which are much slower
+    Best,
+}
+
+impl Level {
+    const ALL: [Level; 4] = [Level::Store, Level::Huffman, Level::Transforms, Level::Best];
+
+    /// The transforms tried at this level, in addition to those of the levels below it
+    fn transforms(self) -> Option<Vec<Transform>> {
+        match self {
+            Level::Store => None,
+            Level::Huffman => Some(Vec::new()),
+            Level::Transforms => Some(vec![Transform::Delta, Transform::RunLength]),
+            Level::Best => Some(vec![Transform::Bwt, Transform::MoveToFront]),
+        }
+    }
+
+    fn encode(self, bytes: &[u8], deadline: Option<Instant>) -> Result<Vec<u8>, crate::Error> {
+        let store = EncodeOptions {
+            literal_threshold: usize::MAX,
+            ..EncodeOptions::default()
+        };
+        let mut smallest = frame::encode(bytes, &frame::Header::default(), &store)?;
+        for level in Level::ALL.into_iter().filter(|&level| level <= self) {
+            let Some(transforms) = level.transforms() else {
+                continue;
+            };
+            let options = EncodeOptions {
+                transforms,
+                deadline,
+                ..EncodeOptions::default()
+            };
+            let encoded = frame::encode(bytes, &frame::Header::default(), &options)?;
+            if encoded.len() < smallest.len() {
+                smallest = encoded;
+            }
+        }
+        Ok(smallest)
+    }
+}
+
+/// Like [`huff`](crate::huff), but compressing as hard as `level` says
+pub fn huff_with_level<T>(value: &T, level: Level) -> Result<Vec<u8>, crate::Error>
+where
+    T: serde::Serialize,
+{
+    level.encode(&bincode::serialize(value)?, None)
+}
+
+/// Values are grouped by the number of bits in their serialized length, and timings are kept
+/// for each group
+const SIZE_CLASSES: usize = usize::BITS as usize + 1;
+
+/// Compresses values at the highest [`Level`] expected to finish within a time budget. How long
+/// each level takes is learned from the values compressed so far, per level and for values of
+/// about the same size, weighting recent values most. A level that hasn't been timed for values
+/// of a size is tried once to time it, with the budget as its
+/// [deadline](EncodeOptions::deadline), so an attempt that's far too slow is cut short and the
+/// value stored.
+#[derive(Debug, Clone)]
+pub struct BudgetedEncoder {
+    budget: Duration,
+    /// The average nanoseconds per byte of each level, for each size class
+    costs: [[Option<f64>; SIZE_CLASSES]; Level::ALL.len()],
+    last_level: Option<Level>,
+}
+
+impl BudgetedEncoder {
+    /// The weight of the newest timing in the average
+    const WEIGHT: f64 = 0.25;
+
+    pub fn new(budget: Duration) -> Self {
+        Self {
+            budget,
+            costs: [[None; SIZE_CLASSES]; Level::ALL.len()],
+            last_level: None,
+        }
+    }
+
+    pub fn budget(&self) -> Duration {
+        self.budget
+    }
+
+    /// The level the last value was compressed at
+    pub fn last_level(&self) -> Option<Level> {
+        self.last_level
+    }
+
+    /// The level a value that serializes to `len` bytes would be compressed at
+    pub fn level_for(&self, len: usize) -> Level {
+        let class = size_class(len);
+        let budget = self.budget.as_nanos() as f64;
+        Level::ALL
+            .into_iter()
+            .rev()
+            .find(|&level| match self.costs[level as usize][class] {
+                Some(cost) => cost * len as f64 <= budget,
+                None => true,
+            })
+            .unwrap_or(Level::Store)
+    }
+
+    /// Compress `value` at the highest level expected to fit the budget. The result is decoded
+    /// with [`puff`](crate::puff) as usual.
+    pub fn huff<T>(&mut self, value: &T) -> Result<Vec<u8>, crate::Error>
+    where
+        T: serde::Serialize,
+    {
+        let bytes = bincode::serialize(value)?;
+        let level = self.level_for(bytes.len());
+        let start = Instant::now();
+        let encoded = level.encode(&bytes, Some(start + self.budget))?;
+        let cost = start.elapsed().as_nanos() as f64 / bytes.len().max(1) as f64;
+
+        let average = &mut self.costs[level as usize][size_class(bytes.len())];
+        *average = Some(match *average {
+            Some(average) => average + Self::WEIGHT * (cost - average),
+            None => cost,
+        });
+        self.last_level = Some(level);
+        Ok(encoded)
+    }
+}
+
+fn size_class(len: usize) -> usize {
+    (usize::BITS - len.leading_zeros()) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text() -> String {
+        (0..500)
+            .map(|i| format!("event {} from host-{}\n", i / 3, i % 4))
+            .collect()
+    }
+
+    #[test]
+    fn levels() {
+        let sizes: Vec<usize> = Level::ALL
+            .iter()
+            .map(|&level| huff_with_level(&text(), level).unwrap().len())
+            .collect();
+        assert!(sizes.windows(2).all(|pair| pair[1] <= pair[0]));
+        assert!(sizes[3] < sizes[1]);
+        for level in Level::ALL {
+            let compressed = huff_with_level(&text(), level).unwrap();
+            assert_eq!(crate::puff::<String>(&compressed).unwrap(), text());
+        }
+    }
+
+    #[test]
+    fn generous_budget() {
+        let mut encoder = BudgetedEncoder::new(Duration::from_secs(60));
+        for _ in 0..3 {
+            let compressed = encoder.huff(&text()).unwrap();
+            assert_eq!(encoder.last_level(), Some(Level::Best));
+            assert_eq!(crate::puff::<String>(&compressed).unwrap(), text());
+        }
+    }
+
+    #[test]
+    fn no_budget() {
+        let mut encoder = BudgetedEncoder::new(Duration::ZERO);
+        // Each level is tried once, and found to take longer than no time at all
+        for _ in 0..4 {
+            let compressed = encoder.huff(&text()).unwrap();
+            assert_eq!(crate::puff::<String>(&compressed).unwrap(), text());
+        }
+        assert_eq!(encoder.last_level(), Some(Level::Store));
+        assert_eq!(encoder.level_for(text().len() + 8), Level::Store);
+        // Values of other sizes haven't been timed yet
+        assert_eq!(encoder.level_for(10), Level::Best);
+    }
+}
@@ -1,13 +1,381 @@
 #![doc = include_str!("../README.md")]
 
+#[cfg(not(any(feature = "encode", feature = "decode")))]
+compile_error!("at least one of the `encode` and `decode` features must be enabled");
+
+// Lets the derive macros' `::huffnpuff` paths resolve in this crate's own tests
+#[cfg(all(test, feature = "derive"))]
+extern crate self as huffnpuff;
+
+mod adaptive;
+mod archive;
+#[cfg(feature = "arrow")]
+mod arrow;
+#[cfg(feature = "futures-io")]
+mod async_io;
+mod backend;
+mod bits;
+mod block;
+mod cell;
+mod code;
+mod codebook;
+mod columnar;
+#[cfg(feature = "decode")]
+mod compare;
+mod compressed;
+#[cfg(feature = "crypto")]
+mod crypto;
+mod csv;
+#[cfg(feature = "debug-errors")]
+mod debug;
+mod delta;
+#[cfg(feature = "embedded-io")]
+mod embedded;
+pub mod field;
+mod file;
+mod frame;
+#[cfg(all(test, feature = "encode", feature = "decode"))]
+mod golden;
+mod hash;
+mod huffable;
 mod huffman;
+#[cfg(all(feature = "encode", feature = "decode"))]
+mod interner;
+mod ints;
+#[cfg(feature = "encode")]
+mod level;
+mod lines;
+mod map;
+mod ndjson;
+mod negotiate;
+mod options;
+pub mod packets;
+mod prepared;
+#[cfg(feature = "encode")]
+mod profile;
+#[cfg(feature = "decode")]
+mod reader;
+mod records;
+#[cfg(feature = "decode")]
+mod reserve;
+#[cfg(feature = "decode")]
+mod ring;
+mod runs;
+mod schema;
+mod series;
+#[cfg(feature = "signing")]
+mod signing;
+mod slice;
+mod stats;
+#[cfg(feature = "encode")]
+mod step;
+#[cfg(feature = "ndarray")]
+mod tensor;
+mod text;
+#[cfg(feature = "tokio")]
+mod tokio_io;
+#[cfg(feature = "decode")]
+mod trace;
+mod transform;
+mod transport;
+#[cfg(feature = "encode")]
+mod typed;
+mod varint;
+mod vec;
+#[cfg(feature = "encode")]
+mod vectored;
+#[cfg(feature = "wasm")]
+mod wasm;
+#[cfg(feature = "encode")]
+mod watch;
+#[cfg(feature = "websocket")]
+mod websocket;
+mod words;
 
+#[cfg(feature = "encode")]
+pub use adaptive::AdaptiveWriter;
+#[cfg(feature = "encode")]
+pub use archive::ArchiveWriter;
+pub use archive::Error as ArchiveError;
+#[cfg(feature = "decode")]
+pub use archive::{ArchiveReader, ArchiveStream, StreamEntry};
+#[cfg(feature = "arrow")]
+pub use arrow::{CompressedArray, Error as ArrowError};
+#[cfg(all(feature = "arrow", feature = "encode"))]
+pub use arrow::{compress_array, compress_buffer};
+#[cfg(all(feature = "arrow", feature = "decode"))]
+pub use arrow::{decompress_array, decompress_buffer};
+#[cfg(all(feature = "futures-io", feature = "encode"))]
+pub use async_io::{AsyncBlockWriter, HuffSink};
+#[cfg(feature = "msgpack")]
+pub use backend::MessagePack;
+#[cfg(feature = "postcard")]
+pub use backend::Postcard;
+pub use backend::{Bincode, Error as BackendError, SerializeBackend};
+pub use bits::{BitOrder, BitReader, BitWriter};
+#[cfg(all(feature = "encode", feature = "parallel"))]
+pub use block::compress_blocks_parallel;
+#[cfg(all(feature = "decode", feature = "parallel"))]
+pub use block::decompress_blocks_parallel;
+pub use block::{BlockVerification, Error as BlockError, verify_blocks};
+#[cfg(feature = "encode")]
+pub use block::{BlockWriter, DEFAULT_BLOCK_SIZE, HuffWriter, compress_blocks, huff_blocks};
+#[cfg(feature = "decode")]
+pub use block::{PuffReader, decompress_blocks, puff_blocks};
+pub use cell::HuffCell;
+pub use code::{ErrorCode, error_message};
+#[cfg(all(feature = "encode", feature = "parallel"))]
+pub use codebook::TrainingProgress;
+pub use codebook::{Codebook, CodebookCache, MessageTree};
+#[cfg(feature = "encode")]
+pub use codebook::{CodebookStats, CodebookTrainer};
+pub use columnar::Error as ColumnarError;
+#[cfg(feature = "encode")]
+pub use columnar::huff_columnar;
+#[cfg(feature = "decode")]
+pub use columnar::puff_columnar;
+#[cfg(feature = "decode")]
+pub use compare::{
+    CodeChange, FrameLayout, HeaderChange, StreamDiff, StreamLayout, compare_streams,
+};
+pub use compressed::Compressed;
+#[cfg(feature = "crypto")]
+pub use crypto::Error as CryptoError;
+#[cfg(all(feature = "crypto", feature = "encode"))]
+pub use crypto::huff_encrypted;
+#[cfg(all(feature = "crypto", feature = "decode"))]
+pub use crypto::puff_encrypted;
+pub use csv::Error as CsvError;
+#[cfg(feature = "encode")]
+pub use csv::compress_csv;
+#[cfg(feature = "decode")]
+pub use csv::decompress_csv;
+#[cfg(feature = "debug-errors")]
+pub use debug::{DebugError, ErrorContext, Location, PartialTree, puff_debug};
+pub use delta::Error as DeltaError;
+#[cfg(feature = "encode")]
+pub use delta::huff_delta;
+#[cfg(feature = "decode")]
+pub use delta::puff_delta;
+#[cfg(all(feature = "embedded-io", feature = "encode"))]
+pub use embedded::EmbeddedBlockWriter;
+#[cfg(feature = "embedded-io")]
+pub use embedded::FromEmbedded;
+pub use file::{Error as FileError, HuffFile};
+pub use frame::Error as FrameError;
+pub use hash::{Crc32, HashingWriter, StreamHasher};
+pub use huffable::{Huffable, Versioned};
 pub use huffman::Error as HuffmanError;
+#[cfg(feature = "derive")]
+pub use huffnpuff_derive::Versioned;
+#[cfg(all(feature = "encode", feature = "decode"))]
+pub use interner::{CompressedInterner, Symbol};
+pub use ints::Error as IntsError;
+#[cfg(feature = "encode")]
+pub use ints::huff_ints;
+#[cfg(feature = "decode")]
+pub use ints::puff_ints;
+#[cfg(feature = "encode")]
+pub use level::{BudgetedEncoder, Level, huff_with_level};
+pub use lines::Error as LinesError;
+#[cfg(feature = "decode")]
+pub use lines::HuffLinesReader;
+#[cfg(feature = "encode")]
+pub use lines::HuffLinesWriter;
+pub use map::CompressedHashMap;
+pub use ndjson::Error as NdjsonError;
+#[cfg(feature = "decode")]
+pub use ndjson::NdjsonReader;
+#[cfg(feature = "encode")]
+pub use ndjson::compress_ndjson;
+pub use negotiate::{Error as NegotiateError, NegotiatedContext, Offer, negotiate};
+pub use options::{CompressionReport, DecodeOptions, EncodeOptions, EncodeReport, SymbolWidth};
+pub use packets::Error as PacketError;
+#[cfg(feature = "decode")]
+pub use prepared::Decoder;
+#[cfg(feature = "encode")]
+pub use prepared::Encoder;
+#[cfg(feature = "encode")]
+pub use profile::{ProfileReport, Redundancy, profile_corpus};
+#[cfg(feature = "decode")]
+pub use reader::DecodedReader;
+pub use records::Error as RecordError;
+#[cfg(feature = "decode")]
+pub use records::{RecordReader, SeekableRecords};
+#[cfg(feature = "encode")]
+pub use records::{RecordWriter, huff_records};
+#[cfg(feature = "decode")]
+pub use ring::decode_split;
+pub use runs::Error as RunsError;
+pub use schema::Error as SchemaError;
+#[cfg(feature = "encode")]
+pub use schema::huff_versioned;
+#[cfg(feature = "decode")]
+pub use schema::{Migrations, puff_versioned};
+pub use series::Error as SeriesError;
+#[cfg(feature = "decode")]
+pub use series::SeriesReader;
+#[cfg(feature = "encode")]
+pub use series::SeriesWriter;
+#[cfg(all(feature = "signing", feature = "encode"))]
+pub use signing::huff_signed;
+#[cfg(all(feature = "signing", feature = "decode"))]
+pub use signing::puff_verified;
+#[cfg(feature = "signing")]
+pub use signing::{Error as SigningError, public_key, sign_detached, verify_detached};
+#[cfg(all(feature = "allocator-api", feature = "decode"))]
+pub use slice::decompress_in;
+#[cfg(all(feature = "allocator-api", feature = "encode"))]
+pub use slice::huff_in;
+#[cfg(feature = "encode")]
+pub use slice::huff_into_slice;
+#[cfg(feature = "decode")]
+pub use slice::puff_into_slice;
+pub use stats::{ErrorCounts, OperationStats, StatsCollector, StatsSnapshot};
+#[cfg(feature = "encode")]
+pub use step::{Step, StepEncoder};
+#[cfg(all(feature = "ndarray", feature = "encode"))]
+pub use tensor::huff_ndarray;
+#[cfg(all(feature = "ndarray", feature = "decode"))]
+pub use tensor::puff_ndarray;
+#[cfg(feature = "ndarray")]
+pub use tensor::{Error as NdarrayError, NdarrayElement};
+pub use text::{Alphabet, Error as TextError};
+#[cfg(feature = "encode")]
+pub use text::{huff_to_alphabet, huff_to_base64, huff_to_hex};
+#[cfg(feature = "decode")]
+pub use text::{puff_from_alphabet, puff_from_base64, puff_from_hex};
+#[cfg(all(feature = "tokio", feature = "encode"))]
+pub use tokio_io::AsyncHuffWriter;
+#[cfg(all(feature = "tokio", feature = "decode"))]
+pub use tokio_io::AsyncPuffReader;
+#[cfg(all(feature = "encode", feature = "decode"))]
+pub use trace::huff_traced;
+#[cfg(feature = "decode")]
+pub use trace::{Trace, TraceStep, puff_traced};
+#[cfg(feature = "encode")]
+pub use transform::Pipeline;
+pub use transform::{Error as TransformError, Remap, Transform};
+#[cfg(feature = "encode")]
+pub use transport::write_frame;
+pub use transport::{DEFAULT_MAX_FRAME_LEN, Error as TransportError};
+#[cfg(feature = "decode")]
+pub use transport::{read_frame, read_frame_limited};
+#[cfg(feature = "encode")]
+pub use typed::TypedEncoder;
+pub use vec::CompressedVec;
+#[cfg(feature = "encode")]
+pub use vectored::huff_vectored;
+#[cfg(feature = "wasm")]
+pub use wasm::WasmError;
+#[cfg(all(feature = "wasm", feature = "encode"))]
+pub use wasm::compress_bytes;
+#[cfg(all(feature = "wasm", feature = "decode"))]
+pub use wasm::decompress_bytes;
+#[cfg(feature = "encode")]
+pub use watch::{DirWatcher, WatchOptions};
+#[cfg(feature = "websocket")]
+pub use websocket::{Error as WebSocketError, WebSocketCodec};
+
+#[cfg(feature = "encode")]
+use std::collections::hash_map::{Entry, HashMap};
 
 #[derive(Debug)]
 pub enum Error {
     Bincode(bincode::Error),
     Huffman(HuffmanError),
+    Frame(FrameError),
+    File(FileError),
+    Archive(ArchiveError),
+    #[cfg(feature = "arrow")]
+    Arrow(ArrowError),
+    Backend(BackendError),
+    Block(BlockError),
+    Columnar(ColumnarError),
+    #[cfg(feature = "crypto")]
+    Crypto(CryptoError),
+    Csv(CsvError),
+    Delta(DeltaError),
+    Ints(IntsError),
+    Lines(LinesError),
+    Text(TextError),
+    Ndjson(NdjsonError),
+    Negotiate(NegotiateError),
+    #[cfg(feature = "ndarray")]
+    Ndarray(NdarrayError),
+    Packet(PacketError),
+    Records(RecordError),
+    Runs(RunsError),
+    Schema(SchemaError),
+    Series(SeriesError),
+    #[cfg(feature = "signing")]
+    Signing(SigningError),
+    Transform(TransformError),
+    Transport(TransportError),
+    #[cfg(feature = "websocket")]
+    WebSocket(WebSocketError),
+    Io(std::io::Error),
+    /// The data was compressed with a type fingerprint that doesn't match the requested type
+    TypeMismatch,
+    /// Decoding would use more memory than [`DecodeOptions::memory_limit`] allows
+    MemoryLimit,
+    /// Memory for the decoded data couldn't be allocated
+    AllocationFailed,
+    /// Decoding didn't finish before [`DecodeOptions::deadline`]
+    TimedOut,
+    /// The compressed value needs this many bytes, which is more than [`huff_bounded`] was
+    /// allowed
+    DoesNotFit(usize),
+    /// The decompressed data is longer than [`DecodeOptions::max_decompressed_len`] allows
+    LimitExceeded,
+}
+
+/// Errors from other crates that don't support defmt are logged by name only
+#[cfg(feature = "defmt")]
+impl defmt::Format for Error {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            Error::Bincode(_) => defmt::write!(f, "Bincode"),
+            Error::Huffman(error) => defmt::write!(f, "Huffman({})", error),
+            Error::Frame(error) => defmt::write!(f, "Frame({})", error),
+            Error::File(error) => defmt::write!(f, "File({})", error),
+            Error::Archive(error) => defmt::write!(f, "Archive({})", error),
+            #[cfg(feature = "arrow")]
+            Error::Arrow(_) => defmt::write!(f, "Arrow"),
+            Error::Backend(error) => defmt::write!(f, "Backend({})", error),
+            Error::Block(error) => defmt::write!(f, "Block({})", error),
+            Error::Columnar(error) => defmt::write!(f, "Columnar({})", error),
+            #[cfg(feature = "crypto")]
+            Error::Crypto(error) => defmt::write!(f, "Crypto({})", error),
+            Error::Csv(error) => defmt::write!(f, "Csv({})", error),
+            Error::Delta(error) => defmt::write!(f, "Delta({})", error),
+            Error::Ints(error) => defmt::write!(f, "Ints({})", error),
+            Error::Lines(error) => defmt::write!(f, "Lines({})", error),
+            Error::Text(error) => defmt::write!(f, "Text({})", error),
+            Error::Ndjson(error) => defmt::write!(f, "Ndjson({})", error),
+            Error::Negotiate(error) => defmt::write!(f, "Negotiate({})", error),
+            #[cfg(feature = "ndarray")]
+            Error::Ndarray(error) => defmt::write!(f, "Ndarray({})", error),
+            Error::Packet(error) => defmt::write!(f, "Packet({})", error),
+            Error::Records(error) => defmt::write!(f, "Records({})", error),
+            Error::Runs(error) => defmt::write!(f, "Runs({})", error),
+            Error::Schema(error) => defmt::write!(f, "Schema({})", error),
+            Error::Series(error) => defmt::write!(f, "Series({})", error),
+            #[cfg(feature = "signing")]
+            Error::Signing(error) => defmt::write!(f, "Signing({})", error),
+            Error::Transform(error) => defmt::write!(f, "Transform({})", error),
+            Error::Transport(error) => defmt::write!(f, "Transport({})", error),
+            #[cfg(feature = "websocket")]
+            Error::WebSocket(error) => defmt::write!(f, "WebSocket({})", error),
+            Error::Io(_) => defmt::write!(f, "Io"),
+            Error::TypeMismatch => defmt::write!(f, "TypeMismatch"),
+            Error::MemoryLimit => defmt::write!(f, "MemoryLimit"),
+            Error::AllocationFailed => defmt::write!(f, "AllocationFailed"),
+            Error::TimedOut => defmt::write!(f, "TimedOut"),
+            Error::DoesNotFit(len) => defmt::write!(f, "DoesNotFit({})", len),
+            Error::LimitExceeded => defmt::write!(f, "LimitExceeded"),
+        }
+    }
 }
 
 impl From<bincode::Error> for Error {
@@ -20,23 +388,1080 @@ impl From<huffman::Error> for Error {
         Error::Huffman(error)
     }
 }
+impl From<frame::Error> for Error {
+    fn from(error: frame::Error) -> Self {
+        Error::Frame(error)
+    }
+}
+impl From<file::Error> for Error {
+    fn from(error: file::Error) -> Self {
+        Error::File(error)
+    }
+}
+impl From<archive::Error> for Error {
+    fn from(error: archive::Error) -> Self {
+        Error::Archive(error)
+    }
+}
+#[cfg(feature = "arrow")]
+impl From<arrow::Error> for Error {
+    fn from(error: arrow::Error) -> Self {
+        Error::Arrow(error)
+    }
+}
+impl From<backend::Error> for Error {
+    fn from(error: backend::Error) -> Self {
+        Error::Backend(error)
+    }
+}
+impl From<block::Error> for Error {
+    fn from(error: block::Error) -> Self {
+        Error::Block(error)
+    }
+}
+impl From<columnar::Error> for Error {
+    fn from(error: columnar::Error) -> Self {
+        Error::Columnar(error)
+    }
+}
+#[cfg(feature = "crypto")]
+impl From<crypto::Error> for Error {
+    fn from(error: crypto::Error) -> Self {
+        Error::Crypto(error)
+    }
+}
+impl From<csv::Error> for Error {
+    fn from(error: csv::Error) -> Self {
+        Error::Csv(error)
+    }
+}
+impl From<delta::Error> for Error {
+    fn from(error: delta::Error) -> Self {
+        Error::Delta(error)
+    }
+}
+impl From<ints::Error> for Error {
+    fn from(error: ints::Error) -> Self {
+        Error::Ints(error)
+    }
+}
+impl From<lines::Error> for Error {
+    fn from(error: lines::Error) -> Self {
+        Error::Lines(error)
+    }
+}
+impl From<text::Error> for Error {
+    fn from(error: text::Error) -> Self {
+        Error::Text(error)
+    }
+}
+impl From<ndjson::Error> for Error {
+    fn from(error: ndjson::Error) -> Self {
+        Error::Ndjson(error)
+    }
+}
+impl From<negotiate::Error> for Error {
+    fn from(error: negotiate::Error) -> Self {
+        Error::Negotiate(error)
+    }
+}
+#[cfg(feature = "ndarray")]
+impl From<tensor::Error> for Error {
+    fn from(error: tensor::Error) -> Self {
+        Error::Ndarray(error)
+    }
+}
+impl From<packets::Error> for Error {
+    fn from(error: packets::Error) -> Self {
+        Error::Packet(error)
+    }
+}
+impl From<records::Error> for Error {
+    fn from(error: records::Error) -> Self {
+        Error::Records(error)
+    }
+}
+impl From<runs::Error> for Error {
+    fn from(error: runs::Error) -> Self {
+        Error::Runs(error)
+    }
+}
+impl From<schema::Error> for Error {
+    fn from(error: schema::Error) -> Self {
+        Error::Schema(error)
+    }
+}
+impl From<series::Error> for Error {
+    fn from(error: series::Error) -> Self {
+        Error::Series(error)
+    }
+}
+#[cfg(feature = "signing")]
+impl From<signing::Error> for Error {
+    fn from(error: signing::Error) -> Self {
+        Error::Signing(error)
+    }
+}
+impl From<transform::Error> for Error {
+    fn from(error: transform::Error) -> Self {
+        Error::Transform(error)
+    }
+}
+impl From<transport::Error> for Error {
+    fn from(error: transport::Error) -> Self {
+        Error::Transport(error)
+    }
+}
+#[cfg(feature = "websocket")]
+impl From<websocket::Error> for Error {
+    fn from(error: websocket::Error) -> Self {
+        Error::WebSocket(error)
+    }
+}
+impl From<std::collections::TryReserveError> for Error {
+    fn from(_: std::collections::TryReserveError) -> Self {
+        Error::AllocationFailed
+    }
+}
+/// I/O errors that carry an error from this library, like those from the reader and writer
+/// adapters, give back the original error
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Self {
+        if error.get_ref().is_some_and(|inner| inner.is::<Error>()) {
+            let inner = error.into_inner().unwrap();
+            return *inner.downcast::<Error>().unwrap();
+        }
+        Error::Io(error)
+    }
+}
+
+/// Errors are carried inside the I/O error, where [`std::io::Error::get_ref`] and
+/// [`std::io::Error::into_inner`] can downcast them back. I/O errors that were wrapped come back
+/// out as they were.
+impl From<Error> for std::io::Error {
+    fn from(error: Error) -> Self {
+        use std::io::ErrorKind;
+
+        if let Error::Io(error) = error {
+            return error;
+        }
+        let kind = if error.is_truncation() {
+            ErrorKind::UnexpectedEof
+        } else if error.is_corruption()
+            || error.is_unsupported_format()
+            || error == Error::TypeMismatch
+        {
+            ErrorKind::InvalidData
+        } else {
+            match error {
+                Error::MemoryLimit | Error::AllocationFailed | Error::LimitExceeded => {
+                    ErrorKind::OutOfMemory
+                }
+                Error::TimedOut => ErrorKind::TimedOut,
+                Error::DoesNotFit(_) => ErrorKind::StorageFull,
+                _ => ErrorKind::Other,
+            }
+        };
+        std::io::Error::new(kind, error)
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            // These errors are returned by `source`, so their messages aren't repeated here
+            Error::Bincode(_) => f.write_str("serialization failed"),
+            Error::Huffman(_) => f.write_str("huffman coding failed"),
+            Error::Io(_) => f.write_str("I/O error"),
+            Error::TypeMismatch => f.write_str("the data holds a different type than requested"),
+            Error::MemoryLimit => f.write_str("decoding would exceed the memory limit"),
+            Error::AllocationFailed => {
+                f.write_str("memory for the decoded data couldn't be allocated")
+            }
+            Error::TimedOut => f.write_str("decoding didn't finish before the deadline"),
+            Error::DoesNotFit(len) => write!(f, "the compressed value needs {len} bytes"),
+            Error::LimitExceeded => f.write_str("the decompressed data is longer than the limit"),
+            Error::Frame(error) => write!(f, "{error}"),
+            Error::File(error) => write!(f, "{error}"),
+            Error::Archive(error) => write!(f, "{error}"),
+            #[cfg(feature = "arrow")]
+            Error::Arrow(error) => write!(f, "{error}"),
+            Error::Backend(error) => write!(f, "{error}"),
+            Error::Block(error) => write!(f, "{error}"),
+            Error::Columnar(error) => write!(f, "{error}"),
+            #[cfg(feature = "crypto")]
+            Error::Crypto(error) => write!(f, "{error}"),
+            Error::Csv(error) => write!(f, "{error}"),
+            Error::Delta(error) => write!(f, "{error}"),
+            Error::Ints(error) => write!(f, "{error}"),
+            Error::Lines(error) => write!(f, "{error}"),
+            Error::Text(error) => write!(f, "{error}"),
+            Error::Ndjson(error) => write!(f, "{error}"),
+            Error::Negotiate(error) => write!(f, "{error}"),
+            #[cfg(feature = "ndarray")]
+            Error::Ndarray(error) => write!(f, "{error}"),
+            Error::Packet(error) => write!(f, "{error}"),
+            Error::Records(error) => write!(f, "{error}"),
+            Error::Runs(error) => write!(f, "{error}"),
+            Error::Schema(error) => write!(f, "{error}"),
+            Error::Series(error) => write!(f, "{error}"),
+            #[cfg(feature = "signing")]
+            Error::Signing(error) => write!(f, "{error}"),
+            Error::Transform(error) => write!(f, "{error}"),
+            Error::Transport(error) => write!(f, "{error}"),
+            #[cfg(feature = "websocket")]
+            Error::WebSocket(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+/// The errors of each module are part of this error's message instead of being returned as the
+/// source
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Bincode(error) => Some(error),
+            Error::Huffman(error) => Some(error),
+            Error::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/// Errors from other crates can't be compared directly, so I/O errors are equal when they have the
+/// same kind, and serialization errors when they have the same message
+impl PartialEq for Error {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Error::Bincode(a), Error::Bincode(b)) => a.to_string() == b.to_string(),
+            (Error::Huffman(a), Error::Huffman(b)) => a == b,
+            (Error::Frame(a), Error::Frame(b)) => a == b,
+            (Error::File(a), Error::File(b)) => a == b,
+            (Error::Archive(a), Error::Archive(b)) => a == b,
+            #[cfg(feature = "arrow")]
+            (Error::Arrow(a), Error::Arrow(b)) => a == b,
+            (Error::Backend(a), Error::Backend(b)) => a == b,
+            (Error::Block(a), Error::Block(b)) => a == b,
+            (Error::Columnar(a), Error::Columnar(b)) => a == b,
+            #[cfg(feature = "crypto")]
+            (Error::Crypto(a), Error::Crypto(b)) => a == b,
+            (Error::Csv(a), Error::Csv(b)) => a == b,
+            (Error::Delta(a), Error::Delta(b)) => a == b,
+            (Error::Ints(a), Error::Ints(b)) => a == b,
+            (Error::Lines(a), Error::Lines(b)) => a == b,
+            (Error::Text(a), Error::Text(b)) => a == b,
+            (Error::Ndjson(a), Error::Ndjson(b)) => a == b,
+            (Error::Negotiate(a), Error::Negotiate(b)) => a == b,
+            #[cfg(feature = "ndarray")]
+            (Error::Ndarray(a), Error::Ndarray(b)) => a == b,
+            (Error::Packet(a), Error::Packet(b)) => a == b,
+            (Error::Records(a), Error::Records(b)) => a == b,
+            (Error::Runs(a), Error::Runs(b)) => a == b,
+            (Error::Schema(a), Error::Schema(b)) => a == b,
+            (Error::Series(a), Error::Series(b)) => a == b,
+            #[cfg(feature = "signing")]
+            (Error::Signing(a), Error::Signing(b)) => a == b,
+            (Error::Transform(a), Error::Transform(b)) => a == b,
+            (Error::Transport(a), Error::Transport(b)) => a == b,
+            #[cfg(feature = "websocket")]
+            (Error::WebSocket(a), Error::WebSocket(b)) => a == b,
+            (Error::Io(a), Error::Io(b)) => a.kind() == b.kind(),
+            (Error::DoesNotFit(a), Error::DoesNotFit(b)) => a == b,
+            (Error::TypeMismatch, Error::TypeMismatch)
+            | (Error::MemoryLimit, Error::MemoryLimit)
+            | (Error::AllocationFailed, Error::AllocationFailed)
+            | (Error::TimedOut, Error::TimedOut)
+            | (Error::LimitExceeded, Error::LimitExceeded) => true,
+            _ => false,
+        }
+    }
+}
+
+impl Error {
+    /// Whether the data ends too early, for example because it's still being received or a
+    /// transfer was cut off. Trying again with more of the data may succeed.
+    pub fn is_truncation(&self) -> bool {
+        matches!(
+            self,
+            Error::Archive(ArchiveError::Truncated)
+                | Error::Block(BlockError::Truncated)
+                | Error::Huffman(HuffmanError::TruncatedMessage)
+                | Error::File(FileError::Truncated)
+                | Error::Frame(
+                    FrameError::TruncatedHeader
+                        | FrameError::TruncatedLiteral
+                        | FrameError::TruncatedPadding
+                )
+                | Error::Lines(LinesError::MissingCodebook)
+                | Error::Ndjson(NdjsonError::MissingCodebook)
+                | Error::Records(RecordError::Truncated)
+                | Error::Runs(RunsError::Truncated)
+                | Error::Series(SeriesError::Truncated)
+                | Error::Transform(TransformError::MalformedRunLength)
+        ) || matches!(self, Error::Io(error) if error.kind() == std::io::ErrorKind::UnexpectedEof)
+    }
+
+    /// Whether the data is damaged: it's in a format this library reads, but its contents are
+    /// inconsistent, fail a checksum or signature, or don't decode. Trying again with the same
+    /// data won't help.
+    pub fn is_corruption(&self) -> bool {
+        match self {
+            Error::Bincode(_) => true,
+            Error::Huffman(error) => matches!(
+                error,
+                HuffmanError::FailedToDecodeHuffmanTree
+                    | HuffmanError::TreeTooDeep
+                    | HuffmanError::TrailingData(_)
+                    | HuffmanError::DuplicateSymbol(_)
+                    | HuffmanError::InvalidLeafCount(_)
+            ),
+            Error::Frame(error) => matches!(
+                error,
+                FrameError::InvalidRemap
+                    | FrameError::InvalidCheckpoints
+                    | FrameError::InvalidPadding
+                    | FrameError::ChecksumMismatch
+            ),
+            Error::File(error) => *error == FileError::ChecksumMismatch,
+            Error::Archive(error) => matches!(
+                error,
+                ArchiveError::CorruptTableOfContents
+                    | ArchiveError::ChecksumMismatch(_)
+                    | ArchiveError::CorruptEntryHeader
+            ),
+            #[cfg(feature = "arrow")]
+            Error::Arrow(_) => true,
+            Error::Backend(error) => matches!(error, BackendError::Deserialize(_)),
+            Error::Block(error) => matches!(
+                error,
+                BlockError::ChecksumMismatch(_)
+                    | BlockError::DigestMismatch(_)
+                    | BlockError::WrongBlockLength(_)
+            ),
+            #[cfg(feature = "crypto")]
+            Error::Crypto(error) => *error == CryptoError::AuthenticationFailed,
+            Error::Columnar(_)
+            | Error::Csv(_)
+            | Error::Delta(_)
+            | Error::Ints(_)
+            | Error::Text(_) => true,
+            Error::Ndjson(error) => *error == NdjsonError::NotUtf8,
+            Error::Negotiate(error) => *error == NegotiateError::MalformedOffer,
+            #[cfg(feature = "ndarray")]
+            Error::Ndarray(error) => *error == NdarrayError::Malformed,
+            Error::Packet(error) => matches!(
+                error,
+                PacketError::MalformedFragment | PacketError::InconsistentFragment(_)
+            ),
+            Error::Records(error) => matches!(
+                error,
+                RecordError::CorruptIndex | RecordError::ChecksumMismatch(_)
+            ),
+            Error::Series(error) => matches!(
+                error,
+                SeriesError::CorruptIndex
+                    | SeriesError::ChecksumMismatch(_)
+                    | SeriesError::CorruptBlock(_)
+            ),
+            #[cfg(feature = "signing")]
+            Error::Signing(error) => matches!(
+                error,
+                SigningError::BadSignature | SigningError::DigestMismatch
+            ),
+            Error::Transform(error) => *error == TransformError::MalformedBwt,
+            Error::Io(error) => error.kind() == std::io::ErrorKind::InvalidData,
+            _ => false,
+        }
+    }
+
+    /// Whether the data isn't in a format this library reads, or uses a newer version of one.
+    /// Upgrading the library, or using the function that matches the data, may help.
+    pub fn is_unsupported_format(&self) -> bool {
+        match self {
+            Error::Frame(error) => matches!(
+                error,
+                FrameError::BadMagic
+                    | FrameError::UnsupportedVersion(_)
+                    | FrameError::UnsupportedFlags(_)
+                    | FrameError::UnsupportedTransform(_)
+                    | FrameError::NotLegacy
+                    | FrameError::Transformed
+                    | FrameError::WordCoded
+                    | FrameError::Repeat
+            ),
+            Error::Archive(error) => matches!(
+                error,
+                ArchiveError::BadMagic
+                    | ArchiveError::UnsupportedVersion(_)
+                    | ArchiveError::NotStreamable
+            ),
+            Error::Block(error) => {
+                matches!(
+                    error,
+                    BlockError::BadMagic | BlockError::UnsupportedVersion(_)
+                )
+            }
+            #[cfg(feature = "crypto")]
+            Error::Crypto(error) => *error == CryptoError::NotEncrypted,
+            Error::Negotiate(error) => matches!(error, NegotiateError::UnsupportedVersion(_)),
+            Error::Schema(error) => matches!(error, SchemaError::NewerVersion(_)),
+            Error::Records(error) => matches!(
+                error,
+                RecordError::BadMagic | RecordError::UnsupportedVersion(_)
+            ),
+            Error::Series(error) => matches!(
+                error,
+                SeriesError::BadMagic | SeriesError::UnsupportedVersion(_)
+            ),
+            #[cfg(feature = "signing")]
+            Error::Signing(error) => matches!(
+                error,
+                SigningError::NotSigned | SigningError::UnsupportedVersion(_)
+            ),
+            _ => false,
+        }
+    }
+
+    /// The stable numeric code of the error's kind, see [`ErrorCode`]. [`error_message`]
+    /// describes the error with a code.
+    pub fn code(&self) -> u32 {
+        self.error_code() as u32
+    }
+
+    pub fn error_code(&self) -> ErrorCode {
+        match self {
+            Error::Bincode(_) => ErrorCode::Bincode,
+            Error::Huffman(_) => ErrorCode::Huffman,
+            Error::Frame(_) => ErrorCode::Frame,
+            Error::File(_) => ErrorCode::File,
+            Error::Archive(_) => ErrorCode::Archive,
+            #[cfg(feature = "arrow")]
+            Error::Arrow(_) => ErrorCode::Arrow,
+            Error::Backend(_) => ErrorCode::Backend,
+            Error::Block(_) => ErrorCode::Block,
+            Error::Columnar(_) => ErrorCode::Columnar,
+            #[cfg(feature = "crypto")]
+            Error::Crypto(_) => ErrorCode::Crypto,
+            Error::Csv(_) => ErrorCode::Csv,
+            Error::Delta(_) => ErrorCode::Delta,
+            Error::Ints(_) => ErrorCode::Ints,
+            Error::Lines(_) => ErrorCode::Lines,
+            Error::Text(_) => ErrorCode::Text,
+            Error::Ndjson(_) => ErrorCode::Ndjson,
+            Error::Negotiate(_) => ErrorCode::Negotiate,
+            #[cfg(feature = "ndarray")]
+            Error::Ndarray(_) => ErrorCode::Ndarray,
+            Error::Packet(_) => ErrorCode::Packet,
+            Error::Records(_) => ErrorCode::Records,
+            Error::Runs(_) => ErrorCode::Runs,
+            Error::Schema(_) => ErrorCode::Schema,
+            Error::Series(_) => ErrorCode::Series,
+            #[cfg(feature = "signing")]
+            Error::Signing(_) => ErrorCode::Signing,
+            Error::Transform(_) => ErrorCode::Transform,
+            Error::Transport(_) => ErrorCode::Transport,
+            #[cfg(feature = "websocket")]
+            Error::WebSocket(_) => ErrorCode::WebSocket,
+            Error::Io(_) => ErrorCode::Io,
+            Error::TypeMismatch => ErrorCode::TypeMismatch,
+            Error::MemoryLimit => ErrorCode::MemoryLimit,
+            Error::AllocationFailed => ErrorCode::AllocationFailed,
+            Error::TimedOut => ErrorCode::TimedOut,
+            Error::DoesNotFit(_) => ErrorCode::DoesNotFit,
+            Error::LimitExceeded => ErrorCode::LimitExceeded,
+        }
+    }
+}
 
 /// Encode and compress a value to a vector of bytes, which includes the metadata for decoding
+#[cfg(feature = "encode")]
 pub fn huff<T>(value: &T) -> Result<Vec<u8>, Error>
 where
     T: serde::Serialize,
 {
     let bincoded_bytes = bincode::serialize(value)?;
-    Ok(huffman::encode(&bincoded_bytes)?)
+    frame::encode(
+        &bincoded_bytes,
+        &frame::Header::default(),
+        &EncodeOptions::default(),
+    )
+}
+
+/// Like [`huff`], but fails with [`Error::DoesNotFit`] if the output would be longer than
+/// `max_len` bytes, for callers with fixed size slots like network packets. The value is coded by
+/// [`huff_into_slice`] into a buffer of at most `max_len` bytes, which works out the length of
+/// the output from the value's byte frequencies before coding it, so a value that doesn't fit is
+/// rejected without building its output. [`max_compressed_len`] gives a size that always fits.
+#[cfg(feature = "encode")]
+pub fn huff_bounded<T>(value: &T, max_len: usize) -> Result<Vec<u8>, Error>
+where
+    T: serde::Serialize,
+{
+    let input_len = bincode::serialized_size(value)? as usize;
+    let mut out = vec![0; max_len.min(max_compressed_len(input_len))];
+    let len = huff_into_slice(value, &mut out)?;
+    out.truncate(len);
+    Ok(out)
+}
+
+/// A guaranteed upper bound on the size of the output of [`huff`], [`huff_tagged`] or
+/// [`huff_with_codebook`] for a value that serializes to `input_len` bytes, which is given by
+/// `bincode::serialized_size`. Data that doesn't compress is stored as it is, so the bound is only
+/// a few bytes more than `input_len`. It doesn't cover encryption, or options with transforms,
+/// since transforms can grow the data.
+pub fn max_compressed_len(input_len: usize) -> usize {
+    frame::max_len(input_len)
+}
+
+/// Like [`huff`], but with control over how the data is encoded. The result is decoded with
+/// [`puff`] as usual.
+#[cfg(feature = "encode")]
+pub fn huff_with_options<T>(value: &T, options: &EncodeOptions) -> Result<Vec<u8>, Error>
+where
+    T: serde::Serialize,
+{
+    let bincoded_bytes = bincode::serialize(value)?;
+    frame::encode(&bincoded_bytes, &frame::Header::default(), options)
+}
+
+/// Like [`huff`], but with the value serialized by the backend `S` instead of bincode. The result
+/// is decoded with [`puff_with`] and the same backend.
+///
+/// ```
+/// use huffnpuff::Bincode;
+///
+/// let compressed = huffnpuff::huff_with::<Bincode, _>(&"any serde format".repeat(3))?;
+/// let decoded: String = huffnpuff::puff_with::<Bincode, _>(&compressed)?;
+/// # Ok::<(), huffnpuff::Error>(())
+/// ```
+#[cfg(feature = "encode")]
+pub fn huff_with<S, T>(value: &T) -> Result<Vec<u8>, Error>
+where
+    S: SerializeBackend,
+    T: serde::Serialize + ?Sized,
+{
+    let serialized = S::serialize(value)?;
+    frame::encode(
+        &serialized,
+        &frame::Header::default(),
+        &EncodeOptions::default(),
+    )
+}
+
+/// Like [`huff_with_options`], but also reports how the value was encoded, so callers can count
+/// values that didn't compress without comparing lengths themselves
+#[cfg(feature = "encode")]
+pub fn huff_with_report<T>(
+    value: &T,
+    options: &EncodeOptions,
+) -> Result<(Vec<u8>, EncodeReport), Error>
+where
+    T: serde::Serialize,
+{
+    let bincoded_bytes = bincode::serialize(value)?;
+    let (compressed, encoding) =
+        frame::encode_with_encoding(&bincoded_bytes, &frame::Header::default(), options, None)?;
+    let report = EncodeReport {
+        input_len: bincoded_bytes.len(),
+        output_len: compressed.len(),
+        stored: encoding == frame::Encoding::Literal,
+    };
+    Ok((compressed, report))
+}
+
+/// Like [`huff_with_report`], but reads the compressed frame back to report its header and tree
+/// sizes and code lengths, along with the entropy of the serialized value, for deciding whether
+/// values like it are worth compressing
+#[cfg(all(feature = "encode", feature = "decode"))]
+pub fn huff_with_stats<T>(
+    value: &T,
+    options: &EncodeOptions,
+) -> Result<(Vec<u8>, CompressionReport), Error>
+where
+    T: serde::Serialize,
+{
+    let bincoded_bytes = bincode::serialize(value)?;
+    let (compressed, encoding) =
+        frame::encode_with_encoding(&bincoded_bytes, &frame::Header::default(), options, None)?;
+    let layout = StreamLayout::read(&compressed)?;
+    let frame = &layout.frames[0];
+    let frequencies = huffman::histogram(&bincoded_bytes).map(u64::from);
+    let report = CompressionReport {
+        input_len: bincoded_bytes.len(),
+        output_len: compressed.len(),
+        header_len: frame.header_len,
+        tree_bits: frame.tree_bits,
+        code_lengths: frame.code_lengths(),
+        entropy: profile::entropy(&frequencies),
+        stored: encoding == frame::Encoding::Literal,
+    };
+    Ok((compressed, report))
+}
+
+/// Like [`huff`], but also stores a fingerprint of the type's name, so that [`puff`] can return
+/// [`Error::TypeMismatch`] when the data is decoded as a different type. Type names are not
+/// guaranteed to be stable between compiler versions, or when the type is moved or renamed; use
+/// [`huff_tagged`] to control the fingerprint explicitly.
+#[cfg(feature = "encode")]
+pub fn huff_fingerprinted<T>(value: &T) -> Result<Vec<u8>, Error>
+where
+    T: serde::Serialize,
+{
+    huff_tagged(value, std::any::type_name::<T>())
+}
+
+/// Like [`huff`], but also stores a fingerprint of `tag`. The data can only be decoded with
+/// [`puff_tagged`] and the same tag.
+#[cfg(feature = "encode")]
+pub fn huff_tagged<T>(value: &T, tag: &str) -> Result<Vec<u8>, Error>
+where
+    T: serde::Serialize,
+{
+    let bincoded_bytes = bincode::serialize(value)?;
+    let header = frame::Header {
+        fingerprint: Some(fingerprint(tag)),
+        ..frame::Header::default()
+    };
+    frame::encode(&bincoded_bytes, &header, &EncodeOptions::default())
+}
+
+/// Encode and compress several values into one buffer of frames, one per value, which
+/// [`puff_all`] and [`puff_iter`] decode. A value that serializes to the same bytes as an earlier
+/// one is stored as a reference to it, which takes a few bytes, instead of being compressed again.
+#[cfg(feature = "encode")]
+pub fn huff_many<'a, T>(values: impl IntoIterator<Item = &'a T>) -> Result<Vec<u8>, Error>
+where
+    T: serde::Serialize + 'a,
+{
+    let mut out = Vec::new();
+    // The index of the first value with each serialization
+    let mut seen = HashMap::new();
+    for (i, value) in values.into_iter().enumerate() {
+        match seen.entry(bincode::serialize(value)?) {
+            Entry::Occupied(first) => {
+                out.extend_from_slice(&frame::repeat_frame((i - first.get()) as u64));
+            }
+            Entry::Vacant(entry) => {
+                out.extend_from_slice(&frame::encode(
+                    entry.key(),
+                    &frame::Header::default(),
+                    &EncodeOptions::default(),
+                )?);
+                entry.insert(i);
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// Like [`huff`], but codes the value with a pre-trained [`Codebook`], so the message doesn't need
+/// to include a tree. It can only be decoded with [`puff_with_codebook`] and the same codebook.
+#[cfg(feature = "encode")]
+pub fn huff_with_codebook<T>(value: &T, codebook: &Codebook) -> Result<Vec<u8>, Error>
+where
+    T: serde::Serialize,
+{
+    let bincoded_bytes = bincode::serialize(value)?;
+    frame::encode_with_codebook(
+        &bincoded_bytes,
+        &frame::Header::default(),
+        &EncodeOptions::default(),
+        Some(codebook),
+    )
+}
+
+/// Like [`huff_with_codebook`], but the value is coded with a tree of its own instead when that's
+/// smaller, as for values unlike the ones the codebook was trained on. See
+/// [`EncodeOptions::hybrid_codebook`]. It's decoded with [`puff_with_codebook`].
+#[cfg(feature = "encode")]
+pub fn huff_hybrid<T>(value: &T, codebook: &Codebook) -> Result<Vec<u8>, Error>
+where
+    T: serde::Serialize,
+{
+    let bincoded_bytes = bincode::serialize(value)?;
+    let options = EncodeOptions {
+        hybrid_codebook: true,
+        ..EncodeOptions::default()
+    };
+    frame::encode_with_codebook(
+        &bincoded_bytes,
+        &frame::Header::default(),
+        &options,
+        Some(codebook),
+    )
+}
+
+/// Decode a buffer produced by [`huff_with_codebook`]. Buffers that don't need a codebook are
+/// decoded as by [`puff`].
+#[cfg(feature = "decode")]
+pub fn puff_with_codebook<T>(bytes: &[u8], codebook: &Codebook) -> Result<T, Error>
+where
+    T: serde::de::DeserializeOwned,
+{
+    let frame = frame::decode_with_codebook(bytes, Some(codebook))?;
+    deserialize_frame(&frame, std::any::type_name::<T>())
+}
+
+/// Decode a buffer encoded by this library into a DeserializeOwned type. The buffer is checked as
+/// by [`DecodeOptions::strict`], so anything an encoder wouldn't write, like data after the
+/// frame, is rejected; [`puff_with_options`] can turn the checks off.
+#[cfg(feature = "decode")]
+pub fn puff<T>(bytes: &[u8]) -> Result<T, Error>
+where
+    T: serde::de::DeserializeOwned,
+{
+    puff_tagged(bytes, std::any::type_name::<T>())
+}
+
+/// Like [`puff`], but with control over how the data is decoded
+#[cfg(feature = "decode")]
+pub fn puff_with_options<T>(bytes: &[u8], options: &DecodeOptions) -> Result<T, Error>
+where
+    T: serde::de::DeserializeOwned,
+{
+    let frame = frame::decode_with_options(bytes, None, options)?;
+    deserialize_frame(&frame, std::any::type_name::<T>())
 }
 
-/// Decode a buffer encoded by this library into a DeserializeOwned type
-pub fn puff<'a, T>(bytes: &'a [u8]) -> Result<T, Error>
+/// Like [`puff`], but fails with [`Error::LimitExceeded`] as soon as the decompressed data passes
+/// `max_decompressed_len` bytes, for values from untrusted sources that could otherwise expand
+/// to exhaust memory
+#[cfg(feature = "decode")]
+pub fn puff_with_limit<T>(bytes: &[u8], max_decompressed_len: usize) -> Result<T, Error>
 where
     T: serde::de::DeserializeOwned,
 {
-    let bincoded_bytes = huffman::decode(bytes)?;
-    Ok(bincode::deserialize(&bincoded_bytes)?)
+    let options = DecodeOptions {
+        max_decompressed_len: Some(max_decompressed_len),
+        ..DecodeOptions::default()
+    };
+    puff_with_options(bytes, &options)
+}
+
+/// Decode a buffer produced by [`huff_with`], deserializing the value with the backend `S`
+#[cfg(feature = "decode")]
+pub fn puff_with<S, T>(bytes: &[u8]) -> Result<T, Error>
+where
+    S: SerializeBackend,
+    T: serde::de::DeserializeOwned,
+{
+    S::deserialize(&frame::decode(bytes)?.payload)
+}
+
+/// Decode a buffer produced by [`huff_tagged`], checking that it was tagged with `tag`. Buffers
+/// without a fingerprint are decoded without any check.
+#[cfg(feature = "decode")]
+pub fn puff_tagged<T>(bytes: &[u8], tag: &str) -> Result<T, Error>
+where
+    T: serde::de::DeserializeOwned,
+{
+    let frame = frame::decode_with_options(bytes, None, &DecodeOptions::default())?;
+    deserialize_frame(&frame, tag)
+}
+
+/// Like [`puff`], for values that borrow from their serialized bytes. The data is decompressed into
+/// `scratch`, and strings and byte slices in the value are views into it rather than copies.
+/// `scratch` is cleared first, so one buffer can be reused for many calls.
+///
+/// ```
+/// let compressed = huffnpuff::huff(&("sensor-7", 21.5f32))?;
+/// let mut scratch = Vec::new();
+/// let (name, celsius): (&str, f32) = huffnpuff::puff_ref(&compressed, &mut scratch)?;
+/// assert_eq!((name, celsius), ("sensor-7", 21.5));
+/// # Ok::<(), huffnpuff::Error>(())
+/// ```
+#[cfg(feature = "decode")]
+pub fn puff_ref<'a, T>(bytes: &[u8], scratch: &'a mut Vec<u8>) -> Result<T, Error>
+where
+    T: serde::Deserialize<'a>,
+{
+    let frame = frame::decode(bytes)?;
+    check_fingerprint(&frame, std::any::type_name::<T>())?;
+    scratch.clear();
+    if scratch.capacity() < frame.payload.len() {
+        *scratch = frame.payload;
+    } else {
+        scratch.extend_from_slice(&frame.payload);
+    }
+    Ok(bincode::deserialize(scratch)?)
+}
+
+/// Decode a buffer made of several outputs of [`huff`] appended together, such as files that
+/// were joined with `cat`. Every member must decode to the same type.
+#[cfg(feature = "decode")]
+pub fn puff_all<T>(bytes: &[u8]) -> Result<Vec<T>, Error>
+where
+    T: serde::de::DeserializeOwned,
+{
+    if bytes.is_empty() {
+        return Err(HuffmanError::NoData.into());
+    }
+    puff_iter(bytes).collect()
+}
+
+/// Members each thread of [`puff_all_parallel`] deserializes at the least, since fewer aren't
+/// worth a thread of their own
+#[cfg(all(feature = "decode", feature = "parallel"))]
+const PARALLEL_MIN_MEMBERS: usize = 64;
+
+/// Like [`puff_all`], but once every member has been decompressed, their values are deserialized
+/// on several threads. That helps batches of values that are slow to deserialize, like records
+/// full of strings and collections. Values are returned in order.
+#[cfg(all(feature = "decode", feature = "parallel"))]
+pub fn puff_all_parallel<T>(bytes: &[u8]) -> Result<Vec<T>, Error>
+where
+    T: serde::de::DeserializeOwned + Send,
+{
+    if bytes.is_empty() {
+        return Err(HuffmanError::NoData.into());
+    }
+    let frames = frame::Frames::new(bytes).collect::<Result<Vec<_>, _>>()?;
+    let threads = std::thread::available_parallelism().map_or(1, |threads| threads.get());
+    let share = frames.len().div_ceil(threads).max(PARALLEL_MIN_MEMBERS);
+    let tag = std::any::type_name::<T>();
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = frames
+            .chunks(share)
+            .map(|chunk| {
+                scope.spawn(move || {
+                    chunk
+                        .iter()
+                        .map(|frame| deserialize_frame(frame, tag))
+                        .collect::<Result<Vec<T>, _>>()
+                })
+            })
+            .collect();
+        let mut values = Vec::with_capacity(frames.len());
+        for handle in handles {
+            let chunk = handle
+                .join()
+                .unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+            values.extend(chunk?);
+        }
+        Ok(values)
+    })
+}
+
+/// Like [`puff_all`], but lazily decodes one member at a time, so the values never all need to
+/// be in memory at once. Iteration stops after the first error.
+#[cfg(feature = "decode")]
+pub fn puff_iter<T>(bytes: &[u8]) -> impl Iterator<Item = Result<T, Error>> + '_
+where
+    T: serde::de::DeserializeOwned,
+{
+    frame::Frames::new(bytes).map(|frame| deserialize_frame(&frame?, std::any::type_name::<T>()))
+}
+
+/// Decode a buffer produced by this library and encode it again with `options`, without needing
+/// the type of the value it holds, for upgrading stored data to new settings. Fingerprints and
+/// schema versions are kept, and each frame of a buffer made of several is recompressed on its
+/// own. Frames that hold the same value as an earlier frame, like the repeats [`huff_many`]
+/// writes, are written as repeats of it.
+#[cfg(all(feature = "encode", feature = "decode"))]
+pub fn recompress(bytes: &[u8], options: &EncodeOptions) -> Result<Vec<u8>, Error> {
+    recompress_with_codebook(bytes, None, None, options)
+}
+
+/// Like [`recompress`], decoding with the codebook `from` and coding with the codebook `to`,
+/// such as for moving data to a newly trained codebook. Frames that weren't coded with a
+/// codebook don't need `from`.
+#[cfg(all(feature = "encode", feature = "decode"))]
+pub fn recompress_with_codebook(
+    bytes: &[u8],
+    from: Option<&Codebook>,
+    to: Option<&Codebook>,
+    options: &EncodeOptions,
+) -> Result<Vec<u8>, Error> {
+    if bytes.is_empty() {
+        return Err(huffman::Error::NoData.into());
+    }
+    let mut out = Vec::new();
+    // The index of the first frame with each payload and header
+    let mut seen = HashMap::new();
+    for (i, frame) in frame::Frames::with_codebook(bytes, from).enumerate() {
+        let frame = frame?;
+        let header = frame::Header {
+            fingerprint: frame.header.fingerprint,
+            schema_version: frame.header.schema_version,
+            ..frame::Header::default()
+        };
+        let key = (frame.payload, header.fingerprint, header.schema_version);
+        match seen.entry(key) {
+            Entry::Occupied(first) => {
+                out.extend_from_slice(&frame::repeat_frame((i - first.get()) as u64));
+            }
+            Entry::Vacant(entry) => {
+                out.extend(frame::encode_with_codebook(
+                    &entry.key().0,
+                    &header,
+                    options,
+                    to,
+                )?);
+                entry.insert(i);
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// Combine two buffers produced by this library into a single frame, with one tree built for the
+/// bytes of both, that decodes to the bytes of `a` followed by those of `b`. Buffers made of
+/// several frames, like those from [`huff_many`], contribute the bytes of every frame. The result
+/// holds the serialized values back to back, so it's read with [`DecodedReader`] or
+/// [`decode_iter`] rather than [`puff`].
+#[cfg(all(feature = "encode", feature = "decode"))]
+pub fn merge(a: &[u8], b: &[u8]) -> Result<Vec<u8>, Error> {
+    merge_all(&[a, b])
+}
+
+/// Like [`merge`], for any number of buffers, which is faster than merging them two at a time
+#[cfg(all(feature = "encode", feature = "decode"))]
+pub fn merge_all(buffers: &[&[u8]]) -> Result<Vec<u8>, Error> {
+    let mut payload = Vec::new();
+    for buffer in buffers {
+        if buffer.is_empty() {
+            return Err(huffman::Error::NoData.into());
+        }
+        for frame in frame::Frames::new(buffer) {
+            payload.extend(frame?.payload);
+        }
+    }
+    frame::encode(
+        &payload,
+        &frame::Header::default(),
+        &EncodeOptions::default(),
+    )
+}
+
+/// Compress bytes that are already serialized, in whatever format, without going through serde.
+/// The result is decompressed with [`decompress`]. As with [`huff`], there must be at least one
+/// byte. Bytes that wouldn't get smaller, such as short or already compressed ones, are stored as
+/// they are after a header of a few bytes, so the output is never longer than
+/// [`max_compressed_len`] of the input.
+#[cfg(feature = "encode")]
+pub fn compress(bytes: &[u8]) -> Result<Vec<u8>, Error> {
+    frame::encode(bytes, &frame::Header::default(), &EncodeOptions::default())
+}
+
+/// Decompress a buffer produced by [`compress`], or any other single frame, to its bytes, without
+/// deserializing them. A buffer from [`huff`] decompresses to the value serialized with bincode.
+#[cfg(feature = "decode")]
+pub fn decompress(bytes: &[u8]) -> Result<Vec<u8>, Error> {
+    Ok(frame::decode(bytes)?.payload)
+}
+
+/// Like [`decompress`], but appends the bytes to `out`, so one buffer can collect the payloads of
+/// many frames. Returns the number of bytes of `bytes` the frame occupied, which is where the
+/// next frame starts when frames are concatenated.
+#[cfg(feature = "decode")]
+pub fn decode_into(bytes: &[u8], out: &mut Vec<u8>) -> Result<usize, Error> {
+    let frame = frame::decode(bytes)?;
+    if out.is_empty() && out.capacity() < frame.payload.len() {
+        *out = frame.payload;
+    } else {
+        out.extend_from_slice(&frame.payload);
+    }
+    Ok(frame.len)
+}
+
+/// Lazily decompress the bytes of a buffer produced by this library, without deserializing them.
+/// Symbols are decoded on demand, so stopping early skips the work of decoding the rest. Errors
+/// in the header are reported by the first item, and a message that was cut off ends with
+/// [`HuffmanError::TruncatedMessage`] after the bytes that could be decoded. A frame with a
+/// checksum ends with [`FrameError::ChecksumMismatch`] if the bytes don't match it.
+#[cfg(feature = "decode")]
+pub fn decode_iter(bytes: &[u8]) -> impl Iterator<Item = Result<u8, Error>> + '_ {
+    let (mut symbols, mut error) = match frame::decode_iter(bytes) {
+        Ok(symbols) => (Some(symbols), None),
+        Err(error) => (None, Some(error)),
+    };
+    std::iter::from_fn(move || {
+        if let Some(error) = error.take() {
+            return Some(Err(error));
+        }
+        match symbols.as_mut()?.next() {
+            Some(byte) => Some(Ok(byte)),
+            None => symbols.take()?.error().map(Err),
+        }
+    })
+}
+
+/// Decompress as much of a buffer as can be decoded, for salvaging what's left of a message that
+/// was cut off. Where the other decoding functions fail with
+/// [`HuffmanError::TruncatedMessage`], this returns the bytes decoded before the data ran out,
+/// which may end partway through a value. Other errors are returned as usual, and running out of
+/// memory fails with [`Error::AllocationFailed`].
+#[cfg(feature = "decode")]
+pub fn decode_partial(bytes: &[u8]) -> Result<Vec<u8>, Error> {
+    decode_partial_with_limit(bytes, usize::MAX)
+}
+
+/// Like [`decode_partial`], but fails with [`Error::LimitExceeded`] as soon as the decoded data
+/// passes `max_decompressed_len` bytes, for damaged data that could otherwise claim to expand to
+/// gigabytes
+#[cfg(feature = "decode")]
+pub fn decode_partial_with_limit(
+    bytes: &[u8],
+    max_decompressed_len: usize,
+) -> Result<Vec<u8>, Error> {
+    let mut decoded = Vec::new();
+    for byte in decode_iter(bytes) {
+        match byte {
+            Ok(_) if decoded.len() == max_decompressed_len => return Err(Error::LimitExceeded),
+            Ok(byte) => reserve::push(&mut decoded, byte)?,
+            Err(Error::Huffman(HuffmanError::TruncatedMessage)) => break,
+            Err(error) => return Err(error),
+        }
+    }
+    Ok(decoded)
+}
+
+/// Whether `bytes` was written by a release of this library from before frames started with magic
+/// bytes, either before the format had a version byte and header or with a frame of version 1 or
+/// 2. These are decoded with [`puff_legacy`], for example to rewrite them in the current format.
+pub fn is_legacy(bytes: &[u8]) -> bool {
+    frame::is_legacy(bytes)
+}
+
+/// Decode a buffer written before frames started with magic bytes, failing with
+/// [`FrameError::NotLegacy`] for one in the current format. [`puff`] rejects these with
+/// [`FrameError::BadMagic`], since without magic bytes anything could look like one.
+#[cfg(feature = "decode")]
+pub fn puff_legacy<T>(bytes: &[u8]) -> Result<T, Error>
+where
+    T: serde::de::DeserializeOwned,
+{
+    let frame = frame::decode_legacy(bytes)?;
+    deserialize_frame(&frame, std::any::type_name::<T>())
+}
+
+/// 32 bit FNV-1a, which is small, fast and stable across platforms and releases
+fn fingerprint(tag: &str) -> u32 {
+    tag.bytes().fold(0x811c9dc5, |hash, byte| {
+        (hash ^ byte as u32).wrapping_mul(0x01000193)
+    })
+}
+
+#[cfg(feature = "decode")]
+fn deserialize_frame<T>(frame: &frame::Frame, tag: &str) -> Result<T, Error>
+where
+    T: serde::de::DeserializeOwned,
+{
+    check_fingerprint(frame, tag)?;
+    Ok(bincode::deserialize(&frame.payload)?)
+}
+
+/// Check that a frame with a fingerprint was tagged with `tag`
+#[cfg(feature = "decode")]
+fn check_fingerprint(frame: &frame::Frame, tag: &str) -> Result<(), Error> {
+    if frame
+        .header
+        .fingerprint
+        .is_some_and(|found| found != fingerprint(tag))
+    {
+        return Err(Error::TypeMismatch);
+    }
+    Ok(())
 }
 
 #[cfg(test)]
@@ -86,7 +1511,40 @@ mod tests {
         // );
         // println!("{}", puff::<String>(&encoded).unwrap());
 
-        assert!(encoded.len() < plaintext.as_bytes().len());
+        assert!(encoded.len() < plaintext.len());
+    }
+
+    #[test]
+    fn legacy() {
+        // What `huff` wrote before the format had a version byte and header
+        let value = ("stored long ago".to_owned(), 7u64);
+        let legacy = huffman::encode(&bincode::serialize(&value).unwrap(), BitOrder::Lsb0).unwrap();
+        assert!(is_legacy(&legacy));
+        assert_eq!(
+            puff::<(String, u64)>(&legacy),
+            Err(Error::Frame(FrameError::BadMagic))
+        );
+        assert_eq!(puff_legacy::<(String, u64)>(&legacy).unwrap(), value);
+
+        // What `huff` wrote before frames started with magic bytes
+        let framed = std::fs::read(
+            [env!("CARGO_MANIFEST_DIR"), "testdata", "legacy", "v2.hnp"]
+                .iter()
+                .collect::<std::path::PathBuf>(),
+        )
+        .unwrap();
+        assert!(is_legacy(&framed));
+        assert_eq!(
+            puff_legacy::<(String, u64)>(&framed).unwrap(),
+            ("framed without magic".repeat(4), 2)
+        );
+
+        let current = huff(&value).unwrap();
+        assert!(!is_legacy(&current));
+        assert_eq!(
+            puff_legacy::<(String, u64)>(&current),
+            Err(Error::Frame(FrameError::NotLegacy))
+        );
     }
 
     #[test]
@@ -112,6 +1570,685 @@ mod tests {
         assert_eq!(user, decompressed);
     }
 
+    #[test]
+    fn concatenated() {
+        let words = ["one", "two", "three"];
+        let concatenated: Vec<u8> = words.iter().flat_map(|word| huff(word).unwrap()).collect();
+
+        let decoded: Vec<String> = puff_all(&concatenated).unwrap();
+        assert_eq!(decoded, words);
+
+        // A single member is still a valid concatenation
+        let single = huff(&"one").unwrap();
+        assert_eq!(puff_all::<String>(&single).unwrap(), vec!["one"]);
+    }
+
+    #[test]
+    fn many_with_duplicates() {
+        #[derive(serde::Serialize, serde::Deserialize, Debug, Default, Clone, PartialEq)]
+        struct Record {
+            id: u32,
+            name: String,
+            tags: Vec<String>,
+        }
+        let mut records = vec![Record::default(); 1000];
+        records[10] = Record {
+            id: 10,
+            name: "not a default".to_owned(),
+            tags: vec!["edited".to_owned(); 20],
+        };
+        records[500] = records[10].clone();
+
+        let compressed = huff_many(&records).unwrap();
+        let separately: usize = records
+            .iter()
+            .map(|record| huff(record).unwrap().len())
+            .sum();
+        assert!(compressed.len() * 2 < separately);
+        assert_eq!(puff_all::<Record>(&compressed).unwrap(), records);
+        assert_eq!(
+            puff_iter::<Record>(&compressed)
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap(),
+            records
+        );
+
+        // A repeat can't be decoded without the frame it repeats
+        let first = huff(&records[0]).unwrap().len();
+        assert_eq!(
+            puff::<Record>(&compressed[first..]),
+            Err(Error::Frame(FrameError::Repeat))
+        );
+        assert!(puff_all::<Record>(&compressed[first..]).is_err());
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn concatenated_parallel() {
+        let records: Vec<(u32, String)> = (0..1000).map(|i| (i, format!("record {i}"))).collect();
+        let concatenated: Vec<u8> = records
+            .iter()
+            .flat_map(|record| huff(record).unwrap())
+            .collect();
+        assert_eq!(
+            puff_all_parallel::<(u32, String)>(&concatenated).unwrap(),
+            records
+        );
+        assert_eq!(
+            puff_all_parallel::<(u32, String)>(&concatenated[..huff(&records[0]).unwrap().len()])
+                .unwrap(),
+            records[..1]
+        );
+
+        // A member of another type partway through
+        let mut mixed = concatenated.clone();
+        mixed.extend(huff_fingerprinted(&1.5f64).unwrap());
+        mixed.extend(&concatenated);
+        assert_eq!(
+            puff_all_parallel::<(u32, String)>(&mixed),
+            Err(Error::TypeMismatch)
+        );
+    }
+
+    #[test]
+    fn lazy_values() {
+        let concatenated: Vec<u8> = (0..100u32).flat_map(|i| huff(&i).unwrap()).collect();
+
+        let mut values = puff_iter::<u32>(&concatenated);
+        assert_eq!(values.next().unwrap().unwrap(), 0);
+        assert_eq!(values.nth(9).unwrap().unwrap(), 10);
+        assert_eq!(values.count(), 89);
+
+        // A corrupt member ends the iteration
+        let mut corrupt = concatenated.clone();
+        corrupt.extend_from_slice(&[0xff; 3]);
+        let results: Vec<_> = puff_iter::<u32>(&corrupt).collect();
+        assert_eq!(results.len(), 101);
+        assert!(results[100].is_err());
+    }
+
+    #[test]
+    fn with_options() {
+        let options = EncodeOptions {
+            bit_order: BitOrder::Msb0,
+            ..EncodeOptions::default()
+        };
+        let message = "packed most significant bit first, ".repeat(4);
+        let compressed = huff_with_options(&message, &options).unwrap();
+
+        assert_ne!(compressed, huff(&message).unwrap());
+        assert_eq!(puff::<String>(&compressed).unwrap(), message);
+    }
+
+    #[test]
+    fn low_memory() {
+        let options = DecodeOptions {
+            low_memory: true,
+            ..DecodeOptions::default()
+        };
+        let message = "decoded straight from the serialized tree, ".repeat(4);
+        let compressed = huff(&message).unwrap();
+        assert_eq!(
+            puff_with_options::<String>(&compressed, &options).unwrap(),
+            message
+        );
+
+        let short = huff(&"too short for a tree").unwrap();
+        assert_eq!(
+            puff_with_options::<String>(&short, &options).unwrap(),
+            "too short for a tree"
+        );
+    }
+
+    #[test]
+    fn memory_limit() {
+        let limited = |memory_limit| DecodeOptions {
+            memory_limit: Some(memory_limit),
+            ..DecodeOptions::default()
+        };
+
+        // A zeroed buffer is stored as a single run, and would expand far beyond the limit
+        let zeros = huff(&vec![0u8; 100_000]).unwrap();
+        assert!(matches!(
+            puff_with_options::<Vec<u8>>(&zeros, &limited(10_000)),
+            Err(Error::MemoryLimit)
+        ));
+        assert_eq!(
+            puff_with_options::<Vec<u8>>(&zeros, &limited(200_000)).unwrap(),
+            vec![0u8; 100_000]
+        );
+
+        // The tree counts too, unless it isn't built
+        let message = "the tree takes more memory than the text, ".repeat(2);
+        let compressed = huff(&message).unwrap();
+        assert!(matches!(
+            puff_with_options::<String>(&compressed, &limited(200)),
+            Err(Error::MemoryLimit)
+        ));
+        let options = DecodeOptions {
+            low_memory: true,
+            ..limited(200)
+        };
+        assert_eq!(
+            puff_with_options::<String>(&compressed, &options).unwrap(),
+            message
+        );
+    }
+
+    #[test]
+    fn deadline() {
+        use std::time::{Duration, Instant};
+
+        let message = "compressible, but not if there's no time, ".repeat(20);
+        let expired = EncodeOptions {
+            deadline: Some(Instant::now()),
+            ..EncodeOptions::default()
+        };
+        let stored = huff_with_options(&message, &expired).unwrap();
+        assert!(stored.len() > message.len());
+        assert_eq!(puff::<String>(&stored).unwrap(), message);
+
+        let plenty = EncodeOptions {
+            deadline: Some(Instant::now() + Duration::from_secs(60)),
+            ..EncodeOptions::default()
+        };
+        let compressed = huff_with_options(&message, &plenty).unwrap();
+        assert!(compressed.len() < message.len());
+
+        let options = DecodeOptions {
+            deadline: Some(Instant::now()),
+            ..DecodeOptions::default()
+        };
+        assert!(matches!(
+            puff_with_options::<String>(&compressed, &options),
+            Err(Error::TimedOut)
+        ));
+    }
+
+    #[test]
+    fn report() {
+        let message = "repetitive text compresses well, ".repeat(10);
+        let (compressed, report) = huff_with_report(&message, &EncodeOptions::default()).unwrap();
+        assert_eq!(report.output_len, compressed.len());
+        assert_eq!(
+            report.input_len,
+            bincode::serialized_size(&message).unwrap() as usize
+        );
+        assert!(!report.stored);
+        assert!(!report.was_expanded());
+
+        let (_, report) = huff_with_report(&"tiny", &EncodeOptions::default()).unwrap();
+        assert!(report.stored);
+        assert!(report.was_expanded());
+    }
+
+    #[test]
+    fn limit() {
+        // A zeroed buffer is stored as a single run, and expands far beyond its compressed size
+        let zeros = huff(&vec![0u8; 100_000]).unwrap();
+        assert!(zeros.len() < 100);
+        assert_eq!(
+            puff_with_limit::<Vec<u8>>(&zeros, 10_000),
+            Err(Error::LimitExceeded)
+        );
+        assert_eq!(
+            puff_with_limit::<Vec<u8>>(&zeros, 100_008).unwrap(),
+            vec![0u8; 100_000]
+        );
+
+        // The tighter of the two limits is the one reported
+        let options = DecodeOptions {
+            memory_limit: Some(5_000),
+            max_decompressed_len: Some(10_000),
+            ..DecodeOptions::default()
+        };
+        assert_eq!(
+            puff_with_options::<Vec<u8>>(&zeros, &options),
+            Err(Error::MemoryLimit)
+        );
+
+        let text = "limited, and transformed ".repeat(100);
+        let options = EncodeOptions {
+            transforms: vec![Transform::Bwt, Transform::MoveToFront],
+            ..EncodeOptions::default()
+        };
+        let transformed = huff_with_options(&text, &options).unwrap();
+        assert_eq!(
+            puff_with_limit::<String>(&transformed, 1000),
+            Err(Error::LimitExceeded)
+        );
+        assert_eq!(puff_with_limit::<String>(&transformed, 4000).unwrap(), text);
+    }
+
+    #[test]
+    fn decode_into_buffer() {
+        let frames = [
+            compress(b"first, ").unwrap(),
+            compress(&[b'x'; 500]).unwrap(),
+        ];
+        let concatenated = frames.concat();
+        let mut out = Vec::new();
+        let len = decode_into(&concatenated, &mut out).unwrap();
+        assert_eq!(len, frames[0].len());
+        assert_eq!(
+            decode_into(&concatenated[len..], &mut out).unwrap(),
+            frames[1].len()
+        );
+        assert_eq!(out.len(), 507);
+        assert!(out.starts_with(b"first, ") && out.ends_with(&[b'x'; 500]));
+
+        out.clear();
+        decode_into(&frames[0], &mut out).unwrap();
+        assert_eq!(out, b"first, ");
+    }
+
+    #[test]
+    fn borrowed() {
+        #[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+        struct Record<'a> {
+            name: &'a str,
+            data: &'a [u8],
+        }
+
+        let record = Record {
+            name: "borrowed from the scratch buffer",
+            data: &[7; 100],
+        };
+        let compressed = huff(&record).unwrap();
+        let mut scratch = Vec::with_capacity(4096);
+        // There's room for the data, so the value borrows from this allocation
+        let start = scratch.as_ptr() as usize;
+        let buffer = start..start + scratch.capacity();
+        let decoded: Record = puff_ref(&compressed, &mut scratch).unwrap();
+        assert_eq!(decoded, record);
+        assert!(buffer.contains(&(decoded.name.as_ptr() as usize)));
+        assert!(buffer.contains(&(decoded.data.as_ptr() as usize)));
+
+        let tagged = huff_tagged(&record, std::any::type_name::<Record>()).unwrap();
+        assert!(puff_ref::<Record>(&tagged, &mut scratch).is_ok());
+        assert_eq!(
+            puff_ref::<(&str, u8)>(&tagged, &mut scratch),
+            Err(Error::TypeMismatch)
+        );
+    }
+
+    #[test]
+    fn stats() {
+        let message = "repetitive text compresses well, ".repeat(10);
+        let (compressed, report) = huff_with_stats(&message, &EncodeOptions::default()).unwrap();
+        assert_eq!(compressed, huff(&message).unwrap());
+        assert_eq!(report.output_len, compressed.len());
+        assert!(!report.stored);
+        assert!(report.ratio() < 1.0);
+        let lengths = report.code_lengths.unwrap();
+        assert!(lengths[b'e' as usize] < lengths[b'x' as usize]);
+        assert_eq!(lengths[b'z' as usize], 0);
+        assert!(report.entropy > 3.0 && report.entropy < 5.0);
+        let tree_len = report.tree_bits.unwrap().div_ceil(8);
+        assert!(report.entropy_len() + tree_len + report.header_len <= report.output_len);
+
+        let (_, report) = huff_with_stats(&"tiny", &EncodeOptions::default()).unwrap();
+        assert!(report.stored);
+        assert_eq!(report.tree_bits, None);
+        assert_eq!(report.code_lengths, None);
+    }
+
+    #[test]
+    fn strict_by_default() {
+        let message = "checked for anything an encoder wouldn't write".repeat(4);
+        let mut trailing = huff(&message).unwrap();
+        let len = trailing.len();
+        trailing.push(0);
+        assert_eq!(
+            puff::<String>(&trailing).unwrap_err(),
+            HuffmanError::TrailingData(len * 8).into()
+        );
+        let lenient = DecodeOptions {
+            strict: false,
+            ..DecodeOptions::default()
+        };
+        assert_eq!(
+            puff_with_options::<String>(&trailing, &lenient).unwrap(),
+            message
+        );
+    }
+
+    #[test]
+    fn bounded() {
+        let message = "fits once it's compressed, ".repeat(50);
+        let compressed = huff_bounded(&message, 1200).unwrap();
+        assert!(compressed.len() <= 1200);
+        assert_eq!(puff::<String>(&compressed).unwrap(), message);
+
+        let random: Vec<u8> = (0..2000u32)
+            .map(|i| (i.wrapping_mul(2654435761) >> 13) as u8)
+            .collect();
+        match huff_bounded(&random, 1200) {
+            Err(Error::DoesNotFit(len)) => assert!(len > 1200),
+            other => panic!("expected DoesNotFit, got {other:?}"),
+        }
+
+        // The length needed is the length of the output when there's room for it
+        let Err(Error::DoesNotFit(needed)) = huff_bounded(&message, 10) else {
+            panic!("expected DoesNotFit");
+        };
+        assert_eq!(huff_bounded(&message, needed).unwrap(), compressed);
+    }
+
+    #[test]
+    fn tiny_values_stay_small() {
+        let value = "a short string".to_owned();
+        let compressed = huff(&value).unwrap();
+        // The magic bytes, the version, two bytes of flags, the checksum and the length
+        assert_eq!(
+            compressed.len(),
+            11 + bincode::serialize(&value).unwrap().len()
+        );
+        assert_eq!(puff::<String>(&compressed).unwrap(), value);
+
+        let options = EncodeOptions {
+            literal_threshold: 0,
+            ..EncodeOptions::default()
+        };
+        // Coding it would make it bigger, so it is stored as it is either way
+        let coded = huff_with_options(&value, &options).unwrap();
+        assert_eq!(coded.len(), compressed.len());
+        assert_eq!(puff::<String>(&coded).unwrap(), value);
+    }
+
+    #[test]
+    fn compressed_len_bound() {
+        let values: Vec<Vec<u8>> = vec![
+            vec![],
+            vec![1, 2, 3],
+            (0..=255).collect(),
+            (0..10_000u32).map(|i| (i * 7919 % 256) as u8).collect(),
+            vec![0; 5000],
+        ];
+        for value in values {
+            let input_len = bincode::serialized_size(&value).unwrap() as usize;
+            let bound = max_compressed_len(input_len);
+            assert!(huff(&value).unwrap().len() <= bound);
+            assert!(huff_tagged(&value, "tag").unwrap().len() <= bound);
+        }
+    }
+
+    #[test]
+    fn zero_buffer() {
+        let zeros = vec![0u8; 1 << 20];
+        let compressed = huff(&zeros).unwrap();
+        assert!(compressed.len() < 32);
+        assert_eq!(puff::<Vec<u8>>(&compressed).unwrap(), zeros);
+    }
+
+    #[test]
+    fn truncated_message() {
+        let value = "a message that is cut off in transit".repeat(3);
+        let compressed = huff(&value).unwrap();
+        let truncated = &compressed[..compressed.len() - 5];
+        let error = puff::<String>(truncated).unwrap_err();
+        assert_eq!(error, Error::Huffman(HuffmanError::TruncatedMessage));
+        assert!(error.is_truncation());
+
+        let partial = decode_partial(truncated).unwrap();
+        let serialized = bincode::serialize(&value).unwrap();
+        assert!(partial.len() < serialized.len() && serialized.starts_with(&partial));
+        assert_eq!(decode_partial(&compressed).unwrap(), serialized);
+        assert_eq!(
+            decode_partial_with_limit(truncated, partial.len()).unwrap(),
+            partial
+        );
+        assert_eq!(
+            decode_partial_with_limit(truncated, 10),
+            Err(Error::LimitExceeded)
+        );
+        assert_eq!(
+            decode_iter(truncated).last(),
+            Some(Err(Error::Huffman(HuffmanError::TruncatedMessage)))
+        );
+
+        let mut reader = DecodedReader::new(truncated).unwrap();
+        let error = std::io::Read::read_to_end(&mut reader, &mut Vec::new()).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn raw_bytes() {
+        let protobuf = [
+            0x08, 0x96, 0x01, 0x12, 0x07, b't', b'e', b's', b't', b'i', b'n', b'g',
+        ];
+        let bytes = protobuf.repeat(50);
+        let compressed = compress(&bytes).unwrap();
+        assert!(compressed.len() < bytes.len());
+        assert_eq!(decompress(&compressed).unwrap(), bytes);
+        assert_eq!(compress(&[]), Err(Error::Huffman(HuffmanError::NoData)));
+
+        let value = "a serde value".to_owned();
+        assert_eq!(
+            decompress(&huff(&value).unwrap()).unwrap(),
+            bincode::serialize(&value).unwrap()
+        );
+        assert!(decompress(&compressed[..1]).unwrap_err().is_truncation());
+    }
+
+    #[test]
+    fn stored() {
+        // Already compressed, so coding would only add a tree
+        let noise: Vec<u8> = (0..4000u32)
+            .map(|i| (i.wrapping_mul(2654435761) >> 13) as u8)
+            .collect();
+        for bytes in [&noise[..], b"ok"] {
+            let compressed = compress(bytes).unwrap();
+            assert!(compressed.len() <= bytes.len() + 13);
+            assert!(compressed.len() <= max_compressed_len(bytes.len()));
+            assert_eq!(decompress(&compressed).unwrap(), bytes);
+        }
+    }
+
+    #[test]
+    fn codebook() {
+        let samples: Vec<Vec<u8>> = (0..20)
+            .map(|i| bincode::serialize(&format!("event {i}: ok")).unwrap())
+            .collect();
+        let codebook = Codebook::train(&samples);
+
+        let value = "event 21: ok".to_owned();
+        let compressed = huff_with_codebook(&value, &codebook).unwrap();
+        assert!(compressed.len() < huff(&value).unwrap().len());
+        assert_eq!(
+            puff_with_codebook::<String>(&compressed, &codebook).unwrap(),
+            value
+        );
+
+        // Buffers without a codebook decode too
+        let plain = huff(&value).unwrap();
+        assert_eq!(
+            puff_with_codebook::<String>(&plain, &codebook).unwrap(),
+            value
+        );
+        let unusual = "QXZJ#%&*".repeat(20);
+        let hybrid = huff_hybrid(&unusual, &codebook).unwrap();
+        assert!(hybrid.len() < huff_with_codebook(&unusual, &codebook).unwrap().len());
+        assert_eq!(
+            puff_with_codebook::<String>(&hybrid, &codebook).unwrap(),
+            unusual
+        );
+    }
+
+    #[test]
+    fn recompress_to_codebook() {
+        let samples: Vec<Vec<u8>> = (0..20)
+            .map(|i| bincode::serialize(&format!("stored object {i}")).unwrap())
+            .collect();
+        let codebook = Codebook::train(&samples);
+        let value = "stored object 99".to_owned();
+        let options = EncodeOptions::default();
+
+        let stored = huff_tagged(&value, "object").unwrap();
+        let upgraded = recompress_with_codebook(&stored, None, Some(&codebook), &options).unwrap();
+        assert!(upgraded.len() < stored.len());
+        let frame = frame::decode_with_codebook(&upgraded, Some(&codebook)).unwrap();
+        assert_eq!(
+            deserialize_frame::<String>(&frame, "object").unwrap(),
+            value
+        );
+        assert_eq!(
+            deserialize_frame::<String>(&frame, "another"),
+            Err(Error::TypeMismatch)
+        );
+
+        let restored =
+            recompress_with_codebook(&upgraded, Some(&codebook), None, &options).unwrap();
+        assert_eq!(puff_tagged::<String>(&restored, "object").unwrap(), value);
+        assert!(matches!(
+            recompress(&upgraded, &options),
+            Err(Error::Frame(FrameError::CodebookRequired(_)))
+        ));
+    }
+
+    #[test]
+    fn merged() {
+        let first = huff(&"the first of several logs".repeat(3)).unwrap();
+        let second = huff(&"the second of several logs".repeat(3)).unwrap();
+        let merged = merge(&first, &second).unwrap();
+        assert!(merged.len() < first.len() + second.len());
+
+        let decoded: Vec<u8> = decode_iter(&merged).map(Result::unwrap).collect();
+        let mut expected = bincode::serialize(&"the first of several logs".repeat(3)).unwrap();
+        expected.extend(bincode::serialize(&"the second of several logs".repeat(3)).unwrap());
+        assert_eq!(decoded, expected);
+
+        let (a, b): (String, String) =
+            bincode::deserialize_from(DecodedReader::new(&merged).unwrap()).unwrap();
+        assert_eq!((a.len(), b.len()), (75, 78));
+        assert!(merge_all(&[&first, &second, &first]).unwrap().len() < 3 * first.len());
+        assert!(merge(&first, &[]).is_err());
+    }
+
+    #[test]
+    fn merge_many() {
+        let values = ["a value in a batch".repeat(2), "another value".repeat(3)];
+        let batch = huff_many(&values).unwrap();
+        let single = huff(&"a value on its own".to_owned()).unwrap();
+        let merged = merge_all(&[&batch, &single]).unwrap();
+
+        let decoded: Vec<u8> = decode_iter(&merged).map(Result::unwrap).collect();
+        let mut expected = Vec::new();
+        for value in [&values[0], &values[1], &"a value on its own".to_owned()] {
+            expected.extend(bincode::serialize(value).unwrap());
+        }
+        assert_eq!(decoded, expected);
+
+        let repeated = huff_many(&[values[0].clone(), values[0].clone()]).unwrap();
+        let decoded: Vec<u8> = decode_iter(&merge(&repeated, &single).unwrap())
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(decoded.len(), 2 * (values[0].len() + 8) + 18 + 8);
+    }
+
+    #[test]
+    fn recompress_concatenated() {
+        let mut stored = huff(&"first".to_owned()).unwrap();
+        stored.extend(huff(&"second, second, second".repeat(5)).unwrap());
+        let options = EncodeOptions {
+            bit_order: BitOrder::Msb0,
+            ..EncodeOptions::default()
+        };
+        let recompressed = recompress(&stored, &options).unwrap();
+        assert_ne!(recompressed, stored);
+        assert_eq!(
+            puff_all::<String>(&recompressed).unwrap(),
+            ["first".to_owned(), "second, second, second".repeat(5)]
+        );
+        assert!(recompress(&[], &options).is_err());
+    }
+
+    #[test]
+    fn recompress_repeats() {
+        let values = [
+            "repeated".repeat(10),
+            "once".to_owned(),
+            "repeated".repeat(10),
+        ];
+        let stored = huff_many(&values).unwrap();
+        let options = EncodeOptions {
+            bit_order: BitOrder::Msb0,
+            ..EncodeOptions::default()
+        };
+        let recompressed = recompress(&stored, &options).unwrap();
+        assert_eq!(puff_all::<String>(&recompressed).unwrap(), values);
+        assert_eq!(recompressed.len(), stored.len());
+
+        let codebook = Codebook::train(["repeated once"]);
+        let coded = recompress_with_codebook(&stored, None, Some(&codebook), &options).unwrap();
+        let restored = recompress_with_codebook(&coded, Some(&codebook), None, &options).unwrap();
+        assert_eq!(puff_all::<String>(&restored).unwrap(), values);
+    }
+
+    #[test]
+    fn fingerprinted() {
+        #[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq)]
+        struct Point {
+            x: u32,
+            y: u32,
+        }
+        #[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq)]
+        struct Size {
+            width: u32,
+            height: u32,
+        }
+
+        let point = Point { x: 3, y: 4 };
+        let compressed = huff_fingerprinted(&point).unwrap();
+        assert_eq!(puff::<Point>(&compressed).unwrap(), point);
+        assert!(matches!(
+            puff::<Size>(&compressed),
+            Err(Error::TypeMismatch)
+        ));
+
+        // Without a fingerprint, the bytes are happily misinterpreted
+        let compressed = huff(&point).unwrap();
+        assert_eq!(
+            puff::<Size>(&compressed).unwrap(),
+            Size {
+                width: 3,
+                height: 4
+            }
+        );
+    }
+
+    #[test]
+    fn tagged() {
+        let compressed = huff_tagged(&42u32, "answer/v1").unwrap();
+        assert_eq!(puff_tagged::<u32>(&compressed, "answer/v1").unwrap(), 42);
+        assert!(matches!(
+            puff_tagged::<u32>(&compressed, "answer/v2"),
+            Err(Error::TypeMismatch)
+        ));
+        assert!(matches!(puff::<u32>(&compressed), Err(Error::TypeMismatch)));
+    }
+
+    #[test]
+    fn lazy_bytes() {
+        let compressed = huff(&"needle in a haystack").unwrap();
+        let bincoded = bincode::serialize(&"needle in a haystack").unwrap();
+
+        let decoded: Result<Vec<u8>, Error> = decode_iter(&compressed).collect();
+        assert_eq!(decoded.unwrap(), bincoded);
+
+        let position = decode_iter(&compressed)
+            .map(Result::unwrap)
+            .position(|byte| byte == b'n');
+        assert_eq!(position, Some(8));
+
+        let mut errors = decode_iter(&[]);
+        assert!(matches!(
+            errors.next(),
+            Some(Err(Error::Huffman(HuffmanError::NoData)))
+        ));
+        assert!(errors.next().is_none());
+    }
+
     #[test]
     fn test_empty() {
         let empty: Vec<u8> = vec![];
@@ -131,10 +2268,116 @@ mod tests {
 
         assert!(matches!(
             decompressed,
-            Err(Error::Huffman(huffman::Error::FailedToDecodeHuffmanTree))
+            Err(Error::Frame(FrameError::BadMagic))
         ));
     }
 
+    #[test]
+    fn error_classes() {
+        let compressed = huff(&"a value worth compressing".repeat(4)).unwrap();
+        let truncated = puff::<String>(&compressed[..1]).unwrap_err();
+        assert_eq!(truncated, Error::Frame(FrameError::TruncatedHeader));
+        assert!(truncated.is_truncation());
+        assert!(!truncated.is_corruption() && !truncated.is_unsupported_format());
+
+        let invalid = puff::<String>(b"Hello, world!").unwrap_err();
+        assert!(invalid.is_unsupported_format());
+        assert!(!invalid.is_truncation() && !invalid.is_corruption());
+
+        // A stored value, so the damaged byte still decodes and only the checksum catches it
+        let mut damaged = huff(&"stored").unwrap();
+        *damaged.last_mut().unwrap() ^= 1;
+        let damaged = puff::<String>(&damaged).unwrap_err();
+        assert_eq!(damaged, Error::Frame(FrameError::ChecksumMismatch));
+        assert!(damaged.is_corruption());
+        assert!(!damaged.is_truncation() && !damaged.is_unsupported_format());
+
+        let mut newer = compressed.clone();
+        newer[frame::MAGIC.len()] = 0xff;
+        let unsupported = puff::<String>(&newer).unwrap_err();
+        assert!(unsupported.is_unsupported_format());
+        assert!(!unsupported.is_truncation() && !unsupported.is_corruption());
+
+        let eof = Error::from(std::io::Error::from(std::io::ErrorKind::UnexpectedEof));
+        assert!(eof.is_truncation());
+        assert_eq!(
+            eof,
+            Error::Io(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "cut off"
+            ))
+        );
+        assert_ne!(eof, Error::TimedOut);
+        assert!(!Error::TimedOut.is_truncation());
+    }
+
+    #[test]
+    fn error_sources() {
+        use std::error::Error as _;
+
+        let error = puff::<String>(&huff(&u64::MAX).unwrap()).unwrap_err();
+        let Error::Bincode(inner) = &error else {
+            panic!("expected a bincode error, got {error:?}");
+        };
+        assert_eq!(error.to_string(), "serialization failed");
+        assert_eq!(error.source().unwrap().to_string(), inner.to_string());
+
+        let error = Error::from(HuffmanError::TruncatedMessage);
+        assert_eq!(error.to_string(), "huffman coding failed");
+        let source = error.source().unwrap();
+        assert_eq!(
+            source.downcast_ref::<HuffmanError>(),
+            Some(&HuffmanError::TruncatedMessage)
+        );
+        assert_eq!(source.to_string(), "the message was cut off before its end");
+        assert!(Error::TimedOut.source().is_none());
+
+        // The errors of each module have messages of their own
+        let error = Error::from(BlockError::ChecksumMismatch(3));
+        assert_eq!(error.to_string(), "block 3 doesn't match its checksum");
+        assert!(error.source().is_none());
+        assert_eq!(
+            Error::from(ArchiveError::EntryNotFound("a.bin".into())).to_string(),
+            "the archive has no entry named \"a.bin\""
+        );
+
+        // Errors convert into boxed errors for `?`, keeping the chain
+        let boxed: Box<dyn std::error::Error> = Error::from(HuffmanError::TruncatedMessage).into();
+        assert!(boxed.source().unwrap().is::<HuffmanError>());
+    }
+
+    #[test]
+    fn io_errors() {
+        use std::io::{ErrorKind, Read};
+
+        let io = std::io::Error::from(Error::Block(BlockError::Truncated));
+        assert_eq!(io.kind(), ErrorKind::UnexpectedEof);
+        assert_eq!(
+            io.get_ref().and_then(|inner| inner.downcast_ref::<Error>()),
+            Some(&Error::Block(BlockError::Truncated))
+        );
+        assert_eq!(Error::from(io), Error::Block(BlockError::Truncated));
+
+        let io = std::io::Error::from(Error::TypeMismatch);
+        assert_eq!(io.kind(), ErrorKind::InvalidData);
+        assert_eq!(io.to_string(), Error::TypeMismatch.to_string());
+        let denied = std::io::Error::from(Error::Io(ErrorKind::PermissionDenied.into()));
+        assert_eq!(denied.kind(), ErrorKind::PermissionDenied);
+        assert!(denied.get_ref().is_none());
+
+        // Errors from the reader adapters come back out of `?` as they were
+        let data = vec![7u8; 10_000];
+        let mut compressed = compress_blocks(&data, 4096).unwrap();
+        let last = compressed.len() - 20;
+        compressed[last] ^= 1;
+        let read = || -> Result<Vec<u8>, Error> {
+            let mut decompressed = Vec::new();
+            PuffReader::new(compressed.as_slice())?.read_to_end(&mut decompressed)?;
+            Ok(decompressed)
+        };
+        assert!(read().unwrap_err().is_corruption());
+    }
+
     // #[test]
     // fn test_statistics() {
     //     let short_message = "Hello, world!";
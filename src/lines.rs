@@ -0,0 +1,294 @@
+//! Compression of line-oriented text such as log files. Lines are gathered into blocks of about
+//! a fixed size, and each block is written as a length-prefixed frame, so the stream can be
+//! appended to and read back while it's still being written.
+//!
+//! The first block is used to train a [`Codebook`], which codes every block of the stream,
+//! including the first. The codebook is written as the first frame of the stream, and is empty if
+//! the stream has no lines.
+
+#[cfg(feature = "decode")]
+use std::io::Read;
+#[cfg(feature = "encode")]
+use std::io::{self, Write};
+
+use crate::codebook::Codebook;
+use crate::frame;
+#[cfg(feature = "encode")]
+use crate::options::EncodeOptions;
+use crate::transport;
+
+/// Blocks are kept well below the largest frame a reader accepts, leaving room for long lines
+/// that run past the end of a block
+#[cfg(feature = "encode")]
+const MAX_BLOCK_SIZE: usize = transport::DEFAULT_MAX_FRAME_LEN / 4;
+
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The block size must be greater than zero and at most 4 MiB
+    InvalidBlockSize,
+    /// The stream ended before its first frame, which holds the codebook
+    MissingCodebook,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Error::InvalidBlockSize => "the block size must be greater than zero and at most 4 MiB",
+            Error::MissingCodebook => "the stream ended before the frame holding its codebook",
+        })
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Writes lines to a compressed stream, to be read back with [`HuffLinesReader`]. Everything
+/// written is split at each `\n`, and once the complete lines buffered reach `block_size` bytes
+/// they're compressed as a block.
+///
+/// [`HuffLinesWriter::finish`] must be called to write the final block. A final line without a
+/// `\n` is ended with one.
+#[cfg(feature = "encode")]
+pub struct HuffLinesWriter<W: Write> {
+    writer: W,
+    block_size: usize,
+    codebook: Option<Codebook>,
+    buffer: Vec<u8>,
+    /// The length of the complete lines at the start of `buffer`
+    complete: usize,
+}
+
+#[cfg(feature = "encode")]
+impl<W: Write> HuffLinesWriter<W> {
+    /// Start a stream that compresses about `block_size` bytes of lines at a time
+    pub fn new(writer: W, block_size: usize) -> Result<Self, crate::Error> {
+        if block_size == 0 || block_size > MAX_BLOCK_SIZE {
+            return Err(Error::InvalidBlockSize.into());
+        }
+        Ok(Self {
+            writer,
+            block_size,
+            codebook: None,
+            buffer: Vec::with_capacity(block_size),
+            complete: 0,
+        })
+    }
+
+    /// Compress and write out the complete lines buffered, training the codebook on them if
+    /// they're the first
+    fn write_block(&mut self) -> Result<(), crate::Error> {
+        if self.complete == 0 {
+            return Ok(());
+        }
+        let lines = &self.buffer[..self.complete];
+        if self.codebook.is_none() {
+            let codebook = Codebook::train(lines.split(|&byte| byte == b'\n'));
+            transport::write_prefixed(&mut self.writer, &codebook.to_bytes())?;
+            self.codebook = Some(codebook);
+        }
+        let compressed = frame::encode_with_codebook(
+            lines,
+            &frame::Header::default(),
+            &EncodeOptions::default(),
+            self.codebook.as_ref(),
+        )?;
+        transport::write_prefixed(&mut self.writer, &compressed)?;
+        self.buffer.drain(..self.complete);
+        self.complete = 0;
+        Ok(())
+    }
+
+    /// The underlying writer. Writing to it directly will corrupt the stream.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    /// Write the lines still buffered, returning the underlying writer
+    pub fn finish(mut self) -> Result<W, crate::Error> {
+        if self.complete < self.buffer.len() {
+            self.buffer.push(b'\n');
+            self.complete = self.buffer.len();
+        }
+        self.write_block()?;
+        if self.codebook.is_none() {
+            transport::write_prefixed(&mut self.writer, &[])?;
+        }
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+#[cfg(feature = "encode")]
+impl<W: Write> Write for HuffLinesWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let start = self.buffer.len();
+        self.buffer.extend_from_slice(buf);
+        if let Some(end) = self.buffer[start..].iter().rposition(|&byte| byte == b'\n') {
+            self.complete = start + end + 1;
+        }
+        if self.complete >= self.block_size {
+            self.write_block().map_err(io::Error::from)?;
+        }
+        Ok(buf.len())
+    }
+
+    /// Flushes the underlying writer. The lines being buffered aren't written, since that would
+    /// cut their block short.
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Reads back the lines of a stream written by [`HuffLinesWriter`], without their `\n`. Blocks
+/// are decompressed one at a time, as their lines are reached.
+#[cfg(feature = "decode")]
+pub struct HuffLinesReader<R> {
+    reader: R,
+    codebook: Option<Codebook>,
+    block: Vec<u8>,
+    /// Where the next line of `block` starts
+    position: usize,
+}
+
+#[cfg(feature = "decode")]
+impl<R: Read> HuffLinesReader<R> {
+    /// Start reading a stream, which reads its codebook
+    pub fn new(mut reader: R) -> Result<Self, crate::Error> {
+        let codebook = transport::read_prefixed(&mut reader, transport::DEFAULT_MAX_FRAME_LEN)?
+            .ok_or(Error::MissingCodebook)?;
+        let codebook = match codebook.is_empty() {
+            true => None,
+            false => Some(Codebook::from_bytes(&codebook)?),
+        };
+        Ok(Self {
+            reader,
+            codebook,
+            block: Vec::new(),
+            position: 0,
+        })
+    }
+
+    /// The codebook trained on the first block, if the stream has any lines
+    pub fn codebook(&self) -> Option<&Codebook> {
+        self.codebook.as_ref()
+    }
+
+    /// Decompress the next block, returning `false` at the end of the stream
+    fn next_block(&mut self) -> Result<bool, crate::Error> {
+        let Some(compressed) =
+            transport::read_prefixed(&mut self.reader, transport::DEFAULT_MAX_FRAME_LEN)?
+        else {
+            return Ok(false);
+        };
+        self.block = frame::decode_with_codebook(&compressed, self.codebook.as_ref())?.payload;
+        self.position = 0;
+        Ok(true)
+    }
+}
+
+#[cfg(feature = "decode")]
+impl<R: Read> Iterator for HuffLinesReader<R> {
+    type Item = Result<Vec<u8>, crate::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.position == self.block.len() {
+            match self.next_block() {
+                Ok(true) => {}
+                Ok(false) => return None,
+                Err(error) => return Some(Err(error)),
+            }
+        }
+        let rest = &self.block[self.position..];
+        let len = rest
+            .iter()
+            .position(|&byte| byte == b'\n')
+            .unwrap_or(rest.len());
+        let line = rest[..len].to_vec();
+        self.position = (self.position + len + 1).min(self.block.len());
+        Some(Ok(line))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn logs(lines: usize) -> String {
+        (0..lines)
+            .map(|i| {
+                format!(
+                    "2024-05-{:02} 12:00:{:02} {} request {} served in {}ms\n",
+                    1 + i % 28,
+                    i % 60,
+                    ["INFO", "INFO", "WARN"][i % 3],
+                    i,
+                    i * 7 % 100
+                )
+            })
+            .collect()
+    }
+
+    fn read_lines(stream: &[u8]) -> Vec<String> {
+        HuffLinesReader::new(stream)
+            .unwrap()
+            .map(|line| String::from_utf8(line.unwrap()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn roundtrip() {
+        let input = logs(500);
+        for block_size in [1, 100, 4096, 1 << 20] {
+            let mut writer = HuffLinesWriter::new(Vec::new(), block_size).unwrap();
+            // Writes that split lines, as a logger flushing its buffer would
+            for chunk in input.as_bytes().chunks(37) {
+                writer.write_all(chunk).unwrap();
+            }
+            let stream = writer.finish().unwrap();
+            assert_eq!(read_lines(&stream), input.lines().collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn shared_codebook_is_small() {
+        let input = logs(2000);
+        let mut writer = HuffLinesWriter::new(Vec::new(), 1024).unwrap();
+        writer.write_all(input.as_bytes()).unwrap();
+        let stream = writer.finish().unwrap();
+        assert!(
+            HuffLinesReader::new(stream.as_slice())
+                .unwrap()
+                .codebook()
+                .is_some()
+        );
+        assert!(stream.len() * 3 < input.len() * 2);
+    }
+
+    #[test]
+    fn unterminated_and_empty_lines() {
+        let mut writer = HuffLinesWriter::new(Vec::new(), 4).unwrap();
+        writer.write_all(b"first\n\n\nlast").unwrap();
+        let stream = writer.finish().unwrap();
+        assert_eq!(read_lines(&stream), ["first", "", "", "last"]);
+    }
+
+    #[test]
+    fn empty() {
+        let stream = HuffLinesWriter::new(Vec::new(), 4)
+            .unwrap()
+            .finish()
+            .unwrap();
+        let reader = HuffLinesReader::new(stream.as_slice()).unwrap();
+        assert!(reader.codebook().is_none());
+        assert_eq!(reader.count(), 0);
+
+        assert!(matches!(
+            HuffLinesReader::new(&b""[..]),
+            Err(crate::Error::Lines(Error::MissingCodebook))
+        ));
+        assert!(matches!(
+            HuffLinesWriter::new(Vec::new(), 0),
+            Err(crate::Error::Lines(Error::InvalidBlockSize))
+        ));
+    }
+}
@@ -0,0 +1,233 @@
+//! A map that holds its values compressed
+
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::marker::PhantomData;
+
+use crate::codebook::Codebook;
+#[cfg(feature = "encode")]
+use crate::frame;
+#[cfg(feature = "encode")]
+use crate::options::EncodeOptions;
+
+/// A compressed value, and the length of the value serialized
+struct Entry {
+    bytes: Box<[u8]>,
+    logical_len: usize,
+}
+
+/// A [`HashMap`] whose values are stored compressed, and decoded each time they're read. Small
+/// values hardly compress with trees of their own, so a map meant to hold many of them should be
+/// given a [`Codebook`] trained on typical values with [`CompressedHashMap::with_codebook`].
+pub struct CompressedHashMap<K, V> {
+    entries: HashMap<K, Entry>,
+    codebook: Option<Codebook>,
+    resident: usize,
+    logical: usize,
+    _values: PhantomData<fn() -> V>,
+}
+
+impl<K: Eq + Hash, V> CompressedHashMap<K, V> {
+    /// An empty map, which codes each value with a tree of its own
+    pub fn new() -> Self {
+        Self {
+            entries: HashMap::new(),
+            codebook: None,
+            resident: 0,
+            logical: 0,
+            _values: PhantomData,
+        }
+    }
+
+    /// An empty map, which codes every value with `codebook`
+    pub fn with_codebook(codebook: Codebook) -> Self {
+        Self {
+            codebook: Some(codebook),
+            ..Self::new()
+        }
+    }
+
+    pub fn codebook(&self) -> Option<&Codebook> {
+        self.codebook.as_ref()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.entries.contains_key(key)
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.entries.keys()
+    }
+
+    /// Remove the value for `key`, returning whether there was one
+    pub fn remove<Q>(&mut self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        match self.entries.remove(key) {
+            Some(entry) => {
+                self.forget(&entry);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.resident = 0;
+        self.logical = 0;
+    }
+
+    /// The number of bytes of compressed values held
+    pub fn resident_size(&self) -> usize {
+        self.resident
+    }
+
+    /// The number of bytes the values held would take serialized
+    pub fn logical_size(&self) -> usize {
+        self.logical
+    }
+
+    /// The compressed value for `key`, which decodes with [`puff`](crate::puff), or with
+    /// [`puff_with_codebook`](crate::puff_with_codebook) if the map has a codebook
+    pub fn get_compressed<Q>(&self, key: &Q) -> Option<&[u8]>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.entries.get(key).map(|entry| &*entry.bytes)
+    }
+
+    fn forget(&mut self, entry: &Entry) {
+        self.resident -= entry.bytes.len();
+        self.logical -= entry.logical_len;
+    }
+}
+
+#[cfg(feature = "encode")]
+impl<K: Eq + Hash, V: serde::Serialize> CompressedHashMap<K, V> {
+    /// Compress `value` and store it under `key`, replacing any value already there. Returns
+    /// whether there was one.
+    pub fn insert(&mut self, key: K, value: &V) -> Result<bool, crate::Error> {
+        let serialized = bincode::serialize(value)?;
+        // Values never leave memory, where a checksum would only make each one bigger
+        let options = EncodeOptions {
+            checksum: false,
+            ..EncodeOptions::default()
+        };
+        let bytes = frame::encode_with_codebook(
+            &serialized,
+            &frame::Header::default(),
+            &options,
+            self.codebook.as_ref(),
+        )?;
+        let entry = Entry {
+            bytes: bytes.into_boxed_slice(),
+            logical_len: serialized.len(),
+        };
+        self.resident += entry.bytes.len();
+        self.logical += entry.logical_len;
+        match self.entries.insert(key, entry) {
+            Some(previous) => {
+                self.forget(&previous);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+#[cfg(feature = "decode")]
+impl<K: Eq + Hash, V: serde::de::DeserializeOwned> CompressedHashMap<K, V> {
+    /// Decode the value for `key`
+    pub fn get<Q>(&self, key: &Q) -> Result<Option<V>, crate::Error>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        let Some(bytes) = self.get_compressed(key) else {
+            return Ok(None);
+        };
+        match &self.codebook {
+            Some(codebook) => crate::puff_with_codebook(bytes, codebook).map(Some),
+            None => crate::puff(bytes).map(Some),
+        }
+    }
+}
+
+impl<K: Eq + Hash, V> Default for CompressedHashMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Session {
+        user: String,
+        role: String,
+        visits: u32,
+    }
+
+    fn session(i: u32) -> Session {
+        Session {
+            user: format!("user-{i}@example.com"),
+            role: ["viewer", "editor", "admin"][i as usize % 3].to_owned(),
+            visits: i % 50,
+        }
+    }
+
+    #[test]
+    fn get_and_sizes() {
+        let codebook = Codebook::train((0..100).map(|i| bincode::serialize(&session(i)).unwrap()));
+        let mut map = CompressedHashMap::with_codebook(codebook);
+        for i in 0..1000 {
+            assert!(!map.insert(i, &session(i)).unwrap());
+        }
+        assert_eq!(map.len(), 1000);
+        assert_eq!(map.get(&7).unwrap(), Some(session(7)));
+        assert_eq!(map.get(&1000).unwrap(), None);
+        assert!(map.resident_size() * 5 < map.logical_size() * 4);
+
+        let (resident, logical) = (map.resident_size(), map.logical_size());
+        assert!(map.insert(7, &session(8)).unwrap());
+        assert_eq!(map.get(&7).unwrap(), Some(session(8)));
+        assert!(map.remove(&7));
+        assert!(!map.remove(&7));
+        assert!(map.resident_size() < resident && map.logical_size() < logical);
+
+        map.clear();
+        assert!(map.is_empty());
+        assert_eq!((map.resident_size(), map.logical_size()), (0, 0));
+    }
+
+    #[test]
+    fn without_codebook() {
+        let mut map = CompressedHashMap::new();
+        map.insert("long".to_owned(), &"abc".repeat(100)).unwrap();
+        assert_eq!(map.get("long").unwrap(), Some("abc".repeat(100)));
+        assert!(map.resident_size() < map.logical_size());
+        let bytes = map.get_compressed("long").unwrap();
+        assert_eq!(crate::puff::<String>(bytes).unwrap(), "abc".repeat(100));
+    }
+}
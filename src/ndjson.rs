@@ -0,0 +1,201 @@
+//! Compression of newline-delimited JSON, such as logs, one record at a time. Records are written
+//! as length-prefixed frames like those of [`write_frame`](crate::write_frame), so a stream can be
+//! read back while it's still being written.
+//!
+//! Single records are usually too small to be worth giving each its own tree, so the stream can
+//! start with a [`Codebook`] trained on its first lines, which then codes every record. The first
+//! frame of the stream is always the codebook, and is empty when there isn't one.
+
+#[cfg(feature = "encode")]
+use std::io::BufRead;
+#[cfg(feature = "decode")]
+use std::io::Read;
+#[cfg(feature = "encode")]
+use std::io::Write;
+
+use crate::codebook::Codebook;
+use crate::frame;
+#[cfg(feature = "encode")]
+use crate::options::EncodeOptions;
+use crate::transport;
+
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The stream ended before its first frame, which holds the codebook
+    MissingCodebook,
+    /// A record isn't valid UTF-8, so it can't be JSON
+    NotUtf8,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Error::MissingCodebook => "the stream ended before the frame holding its codebook",
+            Error::NotUtf8 => "a record isn't valid UTF-8",
+        })
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Compress every line of `input` to `output`, returning the number of records written. Blank
+/// lines are skipped, and the line endings are not kept. If `dictionary_lines` is more than zero,
+/// a codebook is trained on that many lines, which are held back until it's ready.
+#[cfg(feature = "encode")]
+pub fn compress_ndjson<R, W>(
+    input: R,
+    output: &mut W,
+    dictionary_lines: usize,
+) -> Result<u64, crate::Error>
+where
+    R: BufRead,
+    W: Write,
+{
+    let mut lines = input
+        .lines()
+        .filter(|line| !matches!(line, Ok(line) if line.trim().is_empty()));
+
+    let mut held = Vec::new();
+    let codebook = if dictionary_lines > 0 {
+        for line in lines.by_ref().take(dictionary_lines) {
+            held.push(line?);
+        }
+        let codebook = Codebook::train(&held);
+        transport::write_prefixed(output, &codebook.to_bytes())?;
+        Some(codebook)
+    } else {
+        transport::write_prefixed(output, &[])?;
+        None
+    };
+
+    let options = EncodeOptions::default();
+    let mut records = 0;
+    for line in held.into_iter().map(Ok).chain(lines) {
+        let line = line?;
+        let compressed = frame::encode_with_codebook(
+            line.as_bytes(),
+            &frame::Header::default(),
+            &options,
+            codebook.as_ref(),
+        )?;
+        transport::write_prefixed(output, &compressed)?;
+        records += 1;
+    }
+    output.flush()?;
+    Ok(records)
+}
+
+/// Reads back the records of a stream written by [`compress_ndjson`], one line at a time
+#[cfg(feature = "decode")]
+pub struct NdjsonReader<R> {
+    reader: R,
+    codebook: Option<Codebook>,
+}
+
+#[cfg(feature = "decode")]
+impl<R: Read> NdjsonReader<R> {
+    /// Start reading a stream, which reads its codebook
+    pub fn new(mut reader: R) -> Result<Self, crate::Error> {
+        let codebook = transport::read_prefixed(&mut reader, transport::DEFAULT_MAX_FRAME_LEN)?
+            .ok_or(Error::MissingCodebook)?;
+        let codebook = match codebook.is_empty() {
+            true => None,
+            false => Some(Codebook::from_bytes(&codebook)?),
+        };
+        Ok(Self { reader, codebook })
+    }
+
+    /// The codebook the stream was coded with, if it has one
+    pub fn codebook(&self) -> Option<&Codebook> {
+        self.codebook.as_ref()
+    }
+}
+
+#[cfg(feature = "decode")]
+impl<R: Read> Iterator for NdjsonReader<R> {
+    type Item = Result<String, crate::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let compressed =
+            match transport::read_prefixed(&mut self.reader, transport::DEFAULT_MAX_FRAME_LEN) {
+                Ok(compressed) => compressed?,
+                Err(error) => return Some(Err(error)),
+            };
+        let line = frame::decode_with_codebook(&compressed, self.codebook.as_ref())
+            .and_then(|frame| String::from_utf8(frame.payload).map_err(|_| Error::NotUtf8.into()));
+        Some(line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn logs(lines: usize) -> String {
+        (0..lines)
+            .map(|i| {
+                format!(
+                    "{{\"ts\":{},\"level\":\"{}\",\"msg\":\"request {} served\"}}\n",
+                    1_700_000_000 + i,
+                    ["info", "info", "warn"][i % 3],
+                    i
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn roundtrip() {
+        let input = logs(50);
+        for dictionary_lines in [0, 10, 100] {
+            let mut stream = Vec::new();
+            let records = compress_ndjson(input.as_bytes(), &mut stream, dictionary_lines).unwrap();
+            assert_eq!(records, 50);
+
+            let reader = NdjsonReader::new(stream.as_slice()).unwrap();
+            assert_eq!(reader.codebook().is_some(), dictionary_lines > 0);
+            let lines: Vec<String> = reader.map(Result::unwrap).collect();
+            assert_eq!(lines, input.lines().collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn dictionary_is_smaller() {
+        let input = logs(200);
+        let mut plain = Vec::new();
+        compress_ndjson(input.as_bytes(), &mut plain, 0).unwrap();
+        let mut shared = Vec::new();
+        compress_ndjson(input.as_bytes(), &mut shared, 20).unwrap();
+        // Records coded with their own trees end up larger than the input
+        assert!(shared.len() < input.len());
+        assert!(shared.len() * 5 < plain.len() * 4);
+    }
+
+    #[test]
+    fn blank_lines_and_crlf() {
+        let input = "{\"a\":1}\r\n\r\n  \n{\"b\":2}";
+        let mut stream = Vec::new();
+        assert_eq!(
+            compress_ndjson(input.as_bytes(), &mut stream, 5).unwrap(),
+            2
+        );
+        let lines: Vec<String> = NdjsonReader::new(stream.as_slice())
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(lines, ["{\"a\":1}", "{\"b\":2}"]);
+    }
+
+    #[test]
+    fn empty() {
+        let mut stream = Vec::new();
+        assert_eq!(compress_ndjson(&b""[..], &mut stream, 5).unwrap(), 0);
+        assert_eq!(NdjsonReader::new(stream.as_slice()).unwrap().count(), 0);
+
+        assert!(matches!(
+            NdjsonReader::new(&b""[..]),
+            Err(crate::Error::Ndjson(Error::MissingCodebook))
+        ));
+    }
+}
@@ -0,0 +1,201 @@
+//! Agreeing on a shared codebook at the start of a connection. Each peer sends an [`Offer`]
+//! listing the codebooks it holds, most preferred first, and then calls [`negotiate`] with its own
+//! codebooks and the offer it received. Both sides apply the same rule to the same two lists, so
+//! they agree on a codebook without another round trip, or on coding every message with its own
+//! tree if they have none in common.
+
+use crate::codebook::Codebook;
+
+const VERSION: u8 = 1;
+
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The offer doesn't hold a count followed by that many ids
+    MalformedOffer,
+    /// The offer was made by a newer version of the protocol
+    UnsupportedVersion(u8),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Error::MalformedOffer => "the codebook offer is malformed",
+            Error::UnsupportedVersion(version) => {
+                return write!(
+                    f,
+                    "the codebook offer has unsupported protocol version {version}"
+                );
+            }
+        })
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// The ids of the codebooks a peer holds, in order of preference
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Offer {
+    ids: Vec<u32>,
+}
+
+impl Offer {
+    /// An offer of `codebooks`, the first being the most preferred
+    pub fn new<'a>(codebooks: impl IntoIterator<Item = &'a Codebook>) -> Self {
+        let mut ids = Vec::new();
+        for codebook in codebooks {
+            if !ids.contains(&codebook.id()) {
+                ids.push(codebook.id());
+            }
+        }
+        Self { ids }
+    }
+
+    pub fn ids(&self) -> &[u32] {
+        &self.ids
+    }
+
+    /// Serialize the offer to be sent to the other peer
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![VERSION];
+        bytes.extend_from_slice(&(self.ids.len() as u32).to_le_bytes());
+        for id in &self.ids {
+            bytes.extend_from_slice(&id.to_le_bytes());
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, crate::Error> {
+        let (&version, rest) = bytes.split_first().ok_or(Error::MalformedOffer)?;
+        if version != VERSION {
+            return Err(Error::UnsupportedVersion(version).into());
+        }
+        let (count, rest) = rest.split_first_chunk().ok_or(Error::MalformedOffer)?;
+        if u32::from_le_bytes(*count) as u64 * 4 != rest.len() as u64 {
+            return Err(Error::MalformedOffer.into());
+        }
+        let ids = rest
+            .chunks_exact(4)
+            .map(|id| u32::from_le_bytes(id.try_into().unwrap()))
+            .collect();
+        Ok(Self { ids })
+    }
+}
+
+/// Choose the codebook to use with a peer that sent `remote`, from the `local` codebooks this
+/// side offered. Of the codebooks both sides hold, the one with the lowest sum of its positions
+/// in the two offers wins, and ties go to the lower id, so both peers reach the same choice.
+pub fn negotiate(local: &[Codebook], remote: &Offer) -> NegotiatedContext {
+    let local_ids = Offer::new(local).ids;
+    let chosen = local_ids
+        .iter()
+        .enumerate()
+        .filter_map(|(local_rank, id)| {
+            let remote_rank = remote.ids.iter().position(|remote_id| remote_id == id)?;
+            Some((local_rank + remote_rank, *id))
+        })
+        .min()
+        .map(|(_, id)| id);
+    NegotiatedContext {
+        codebook: chosen.and_then(|id| local.iter().find(|codebook| codebook.id() == id).cloned()),
+    }
+}
+
+/// The outcome of [`negotiate`], for coding the messages that follow it
+#[derive(Debug, Clone)]
+pub struct NegotiatedContext {
+    codebook: Option<Codebook>,
+}
+
+impl NegotiatedContext {
+    /// The agreed codebook, or `None` if the peers have none in common and messages carry their
+    /// own trees
+    pub fn codebook(&self) -> Option<&Codebook> {
+        self.codebook.as_ref()
+    }
+
+    /// Compress `value` with the agreed codebook, if there is one
+    #[cfg(feature = "encode")]
+    pub fn huff<T>(&self, value: &T) -> Result<Vec<u8>, crate::Error>
+    where
+        T: serde::Serialize,
+    {
+        match &self.codebook {
+            Some(codebook) => crate::huff_with_codebook(value, codebook),
+            None => crate::huff(value),
+        }
+    }
+
+    /// Decode a message compressed by the peer's [`NegotiatedContext::huff`]
+    #[cfg(feature = "decode")]
+    pub fn puff<T>(&self, bytes: &[u8]) -> Result<T, crate::Error>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        match &self.codebook {
+            Some(codebook) => crate::puff_with_codebook(bytes, codebook),
+            None => crate::puff(bytes),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn codebook(sample: &str) -> Codebook {
+        Codebook::train([sample.repeat(10)])
+    }
+
+    #[test]
+    fn offer_roundtrip() {
+        let offer = Offer::new(&[codebook("abc"), codebook("xyz")]);
+        assert_eq!(Offer::from_bytes(&offer.to_bytes()).unwrap(), offer);
+        assert_eq!(Offer::new(&[]).to_bytes(), [VERSION, 0, 0, 0, 0]);
+
+        let bytes = offer.to_bytes();
+        assert!(matches!(
+            Offer::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(crate::Error::Negotiate(Error::MalformedOffer))
+        ));
+        assert!(matches!(
+            Offer::from_bytes(&[VERSION + 1, 0, 0, 0, 0]),
+            Err(crate::Error::Negotiate(Error::UnsupportedVersion(_)))
+        ));
+    }
+
+    #[test]
+    fn agreement() {
+        let [a, b, c, d] = ["aaab", "bbbc", "cccd", "ddde"].map(codebook);
+        let client = [a.clone(), b.clone(), c.clone()];
+        let server = [d, c.clone(), b.clone()];
+
+        let client_context = negotiate(&client, &Offer::new(&server));
+        let server_context = negotiate(&server, &Offer::new(&client));
+        // b and c both have a rank sum of 3, so the lower id wins on both sides
+        let expected = b.id().min(c.id());
+        assert_eq!(client_context.codebook().map(Codebook::id), Some(expected));
+        assert_eq!(server_context.codebook().map(Codebook::id), Some(expected));
+
+        let message = "bbbbcccc".to_owned();
+        let compressed = client_context.huff(&message).unwrap();
+        assert_eq!(server_context.puff::<String>(&compressed).unwrap(), message);
+    }
+
+    #[test]
+    fn nothing_in_common() {
+        let client = [codebook("aaab")];
+        let server = [codebook("bbbc")];
+        let client_context = negotiate(&client, &Offer::new(&server));
+        let server_context = negotiate(&server, &Offer::new(&client));
+        assert!(client_context.codebook().is_none());
+        assert!(server_context.codebook().is_none());
+
+        let message = vec![1u8, 2, 3];
+        let compressed = client_context.huff(&message).unwrap();
+        assert_eq!(
+            server_context.puff::<Vec<u8>>(&compressed).unwrap(),
+            message
+        );
+    }
+}
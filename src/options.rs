@@ -0,0 +1,201 @@
+use std::time::Instant;
+
+use crate::bits::BitOrder;
+use crate::transform::Transform;
+
+/// Settings that control how data is encoded. Everything needed to decode the data is recorded in
+/// the frame header, so decoding never needs the options that were used.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EncodeOptions {
+    /// How bits are packed into bytes. Most consumers should keep the default, but hardware
+    /// decoders for DEFLATE-like formats generally expect [`BitOrder::Msb0`].
+    pub bit_order: BitOrder,
+    /// Inputs shorter than this many bytes are stored as they are, without a tree. The tree alone
+    /// usually costs more than coding saves on tiny inputs, so they would otherwise grow.
+    pub literal_threshold: usize,
+    /// Transforms to apply to the data, in order, before coding it. See
+    /// [`Pipeline`](crate::Pipeline) for a convenient way to build these.
+    pub transforms: Vec<Transform>,
+    /// If encoding is still going when this time passes, the data is stored as it is instead of
+    /// being coded, for callers that would rather send larger data than miss a latency budget.
+    /// The deadline is checked between the stages of encoding, so a stage that has started, like
+    /// coding the message, runs to completion.
+    pub deadline: Option<Instant>,
+    /// Record where decoding can resume every this many bytes of a huffman coded message, so
+    /// [`DecodedReader::seek_to_output_offset`](crate::DecodedReader::seek_to_output_offset) can
+    /// start near the offset it's asked for instead of at the start. Each checkpoint costs a
+    /// byte or two in the header. Checkpoints aren't recorded when there are transforms, or for
+    /// messages coded with a codebook.
+    pub checkpoint_interval: Option<usize>,
+    /// Build the tree from approximate code lengths, each about the log of how rare its byte is,
+    /// instead of the exact huffman construction. That's much cheaper for short messages, where
+    /// building the tree costs more than coding, at the price of codes a percent or two longer.
+    /// Messages coded with a codebook don't build a tree.
+    pub fast_tree: bool,
+    /// Pad the frame with zeros to a multiple of this many bytes, such as 8 for containers that
+    /// need aligned fields, or 512 for writing with `O_DIRECT`. Must be a power of two no more
+    /// than 4096. The padding is recorded in the header and checked when decoding, and
+    /// concatenated frames stay aligned, since each is padded.
+    pub alignment: Option<usize>,
+    /// When coding with a codebook, also code the message with a tree of its own, and keep
+    /// whichever is smaller. Typical messages keep the codebook's savings, while messages unlike
+    /// the ones it was trained on don't grow. The header says which was used, so decoding is the
+    /// same either way, but building the tree costs time on every message.
+    pub hybrid_codebook: bool,
+    /// Write the length of each code at the start of the message instead of the tree, and code
+    /// with the canonical code those lengths give. That takes about two thirds of the bits of a
+    /// tree, which matters most for short messages. Messages with checkpoints or coded with a
+    /// codebook are unaffected, and so are those whose longest code would be over 32 bits.
+    pub canonical: bool,
+    /// Limit codes to this many bits, such as 15 as in DEFLATE, so a decoder can use a table of a
+    /// known size. The message is coded as with [`canonical`](Self::canonical), whose code lengths
+    /// start with the longest one, and without checkpoints. Codes that would be longer are
+    /// shortened and others lengthened to make room, which costs a little compression. Must be
+    /// between 9 and 32. Messages coded with a codebook, adaptively or by words are unaffected.
+    pub max_code_len: Option<u8>,
+    /// Put a CRC-32 of the data in the header, which decoding checks, failing with
+    /// [`FrameError::ChecksumMismatch`](crate::FrameError::ChecksumMismatch) if the data was
+    /// damaged in a way that still decodes. On by default; turning it off saves 4 bytes a frame.
+    /// Lazy decoding checks it once every byte has been read in order.
+    pub checksum: bool,
+    /// Code the message with adaptive huffman coding, which sends no tree, instead of a tree built
+    /// for it. That's smaller for short messages, whose tree is a large part of the output, but
+    /// decoding is slower. Messages coded with a codebook are unaffected. For data that isn't all
+    /// known up front, see [`AdaptiveWriter`](crate::AdaptiveWriter).
+    pub adaptive: bool,
+    /// The size of the symbols the message is huffman coded in. Coding by 16 bit words suits data
+    /// made of 16 bit values, like UTF-16 text. The width is recorded in the frame, so decoding
+    /// needs nothing extra. Word coded messages don't have checkpoints, and are coded by bytes if
+    /// there's less than a word.
+    pub symbol_width: SymbolWidth,
+}
+
+/// The size of the symbols a message is huffman coded in, see [`EncodeOptions::symbol_width`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SymbolWidth {
+    /// Each byte is a symbol
+    #[default]
+    Byte,
+    /// Each pair of bytes is a symbol, read as a little endian `u16`. An odd byte at the end is
+    /// stored as it is.
+    Word,
+}
+
+/// What happened when a value was encoded, see [`huff_with_report`](crate::huff_with_report)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct EncodeReport {
+    /// The size of the serialized value
+    pub input_len: usize,
+    /// The size of the compressed output, including the header
+    pub output_len: usize,
+    /// Whether the data was stored as it is, because coding it wouldn't have made it smaller
+    pub stored: bool,
+}
+
+impl EncodeReport {
+    /// Whether the output is larger than the serialized value. Data that doesn't compress is
+    /// stored, so this is at most a few bytes, but it still happens for small or random values.
+    pub fn was_expanded(&self) -> bool {
+        self.output_len > self.input_len
+    }
+}
+
+/// A detailed account of how a value was compressed, see
+/// [`huff_with_stats`](crate::huff_with_stats)
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompressionReport {
+    /// The size of the serialized value
+    pub input_len: usize,
+    /// The size of the compressed output, including the header
+    pub output_len: usize,
+    /// The number of bytes of the frame header, including the magic bytes and version byte
+    pub header_len: usize,
+    /// The number of bits of the tree at the start of the message, or `None` if the message
+    /// doesn't carry one, as when the data was stored
+    pub tree_bits: Option<usize>,
+    /// The length of the code for each byte value, followed by that for EOM, or `None` if the
+    /// message doesn't carry a tree. With transforms, these are the codes of the transformed bytes.
+    pub code_lengths: Option<[u8; 257]>,
+    /// The order 0 entropy of the serialized value, in bits per byte. Huffman coding without
+    /// transforms can't do better than this, and comes within a bit per byte of it.
+    pub entropy: f64,
+    /// Whether the data was stored as it is, because coding it wouldn't have made it smaller
+    pub stored: bool,
+}
+
+impl CompressionReport {
+    /// The compressed size as a fraction of the serialized size
+    pub fn ratio(&self) -> f64 {
+        self.output_len as f64 / self.input_len.max(1) as f64
+    }
+
+    /// The fewest bytes any coding of the serialized value by byte frequencies alone could take,
+    /// not counting a tree or header
+    pub fn entropy_len(&self) -> usize {
+        (self.entropy * self.input_len as f64 / 8.0).ceil() as usize
+    }
+}
+
+/// Settings that control how data is decoded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodeOptions {
+    /// Decode messages by walking the tree where it lies in the compressed data, instead of
+    /// building it in memory first. This needs almost no working memory besides the output, for
+    /// targets that can't spare a few kilobytes for the tree, but decoding is several times slower.
+    pub low_memory: bool,
+    /// The most memory, in bytes, that decoding a message may use for the tree and the decoded
+    /// output. Messages that would need more fail with
+    /// [`Error::MemoryLimit`](crate::Error::MemoryLimit) as soon as the limit is reached, so many
+    /// decodes can run at once with a known ceiling.
+    /// Codebooks are owned by the caller and don't count against the limit.
+    pub memory_limit: Option<usize>,
+    /// The longest the decompressed data may be, in bytes. Decoding stops with
+    /// [`Error::LimitExceeded`](crate::Error::LimitExceeded) as soon as the output passes it, so
+    /// a small message that expands to gigabytes is rejected without allocating them.
+    pub max_decompressed_len: Option<usize>,
+    /// Decoding fails with [`Error::TimedOut`](crate::Error::TimedOut) if it is still going when
+    /// this time passes. As when encoding, the deadline is checked between stages.
+    pub deadline: Option<Instant>,
+    /// Reject anything an encoder wouldn't write, even where it would still decode: data after the
+    /// frame, bits after the end of the message that aren't zero padding, and trees with more
+    /// leaves than there are values or with a value twice. The errors, like
+    /// [`HuffmanError::TrailingData`](crate::HuffmanError::TrailingData), give the bit of the
+    /// frame where the problem is, for telling apart the ways untrusted data can be malformed.
+    /// On by default, so [`puff`](crate::puff) makes these checks; turn it off to accept such
+    /// data. Trees aren't checked when decoding with [`low_memory`](Self::low_memory).
+    pub strict: bool,
+}
+
+impl Default for DecodeOptions {
+    fn default() -> Self {
+        Self {
+            low_memory: false,
+            memory_limit: None,
+            max_decompressed_len: None,
+            deadline: None,
+            strict: true,
+        }
+    }
+}
+
+impl Default for EncodeOptions {
+    fn default() -> Self {
+        Self {
+            bit_order: BitOrder::default(),
+            literal_threshold: 64,
+            transforms: Vec::new(),
+            deadline: None,
+            checkpoint_interval: None,
+            fast_tree: false,
+            alignment: None,
+            hybrid_codebook: false,
+            canonical: false,
+            max_code_len: None,
+            checksum: true,
+            adaptive: false,
+            symbol_width: SymbolWidth::Byte,
+        }
+    }
+}
@@ -0,0 +1,276 @@
+//! Splitting compressed messages into datagram-sized fragments, and putting them back together.
+//!
+//! Each fragment starts with an 8 byte header: the message id (`u32`), the fragment's sequence
+//! number within the message (`u16`) and the total number of fragments in the message (`u16`),
+//! all little endian. Fragments of a message may arrive in any order, interleaved with fragments
+//! of other messages, and may be duplicated.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+#[cfg(feature = "encode")]
+use crate::huff;
+
+pub const HEADER_SIZE: usize = 8;
+
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The MTU doesn't leave room for any data after the fragment header
+    MtuTooSmall,
+    /// The message would need more than `u16::MAX` fragments at this MTU
+    TooManyFragments,
+    /// The packet is too short to hold a fragment header, or its header is inconsistent
+    MalformedFragment,
+    /// The fragment disagrees with earlier fragments of the same message about the fragment count
+    InconsistentFragment(u32),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Error::MtuTooSmall => "the MTU leaves no room for data after the fragment header",
+            Error::TooManyFragments => "the message needs too many fragments at this MTU",
+            Error::MalformedFragment => "the fragment is malformed",
+            Error::InconsistentFragment(message) => {
+                return write!(
+                    f,
+                    "a fragment of message {message} disagrees with the others about their count"
+                );
+            }
+        })
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Split `message` into fragments of at most `mtu` bytes, headers included
+pub fn fragment(message_id: u32, message: &[u8], mtu: usize) -> Result<Vec<Vec<u8>>, Error> {
+    let chunk_size = mtu.checked_sub(HEADER_SIZE).filter(|&size| size > 0);
+    let chunk_size = chunk_size.ok_or(Error::MtuTooSmall)?;
+
+    let total = message.len().div_ceil(chunk_size).max(1);
+    let total = u16::try_from(total).map_err(|_| Error::TooManyFragments)?;
+
+    let mut chunks: Vec<&[u8]> = message.chunks(chunk_size).collect();
+    if chunks.is_empty() {
+        chunks.push(&[]);
+    }
+
+    Ok(chunks
+        .into_iter()
+        .enumerate()
+        .map(|(sequence, chunk)| {
+            let mut packet = Vec::with_capacity(HEADER_SIZE + chunk.len());
+            packet.extend_from_slice(&message_id.to_le_bytes());
+            packet.extend_from_slice(&(sequence as u16).to_le_bytes());
+            packet.extend_from_slice(&total.to_le_bytes());
+            packet.extend_from_slice(chunk);
+            packet
+        })
+        .collect())
+}
+
+/// Compress a value with [`huff`] and split it into fragments of at most `mtu` bytes
+#[cfg(feature = "encode")]
+pub fn huff_fragments<T>(
+    value: &T,
+    message_id: u32,
+    mtu: usize,
+) -> Result<Vec<Vec<u8>>, crate::Error>
+where
+    T: serde::Serialize,
+{
+    Ok(fragment(message_id, &huff(value)?, mtu)?)
+}
+
+/// The fragments that never arrived for a message that was given up on, or is still pending
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Loss {
+    pub message_id: u32,
+    pub total: u16,
+    pub missing: Vec<u16>,
+}
+
+#[derive(Debug)]
+struct Partial {
+    total: u16,
+    fragments: BTreeMap<u16, Vec<u8>>,
+}
+
+impl Partial {
+    fn loss(&self, message_id: u32) -> Loss {
+        Loss {
+            message_id,
+            total: self.total,
+            missing: (0..self.total)
+                .filter(|sequence| !self.fragments.contains_key(sequence))
+                .collect(),
+        }
+    }
+}
+
+/// Collects fragments produced by [`fragment`] until whole messages can be reassembled
+#[derive(Debug, Default)]
+pub struct Reassembler {
+    partial: BTreeMap<u32, Partial>,
+    /// Recently completed messages, so late duplicates of their fragments are ignored
+    completed: BTreeSet<u32>,
+}
+
+impl Reassembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a received packet. Returns the reassembled message once its last missing fragment
+    /// arrives. Duplicate fragments, including those of messages that were already completed, are
+    /// ignored.
+    pub fn push(&mut self, packet: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        let (header, data) = packet
+            .split_first_chunk::<HEADER_SIZE>()
+            .ok_or(Error::MalformedFragment)?;
+        let message_id = u32::from_le_bytes(header[0..4].try_into().unwrap());
+        let sequence = u16::from_le_bytes(header[4..6].try_into().unwrap());
+        let total = u16::from_le_bytes(header[6..8].try_into().unwrap());
+        if sequence >= total {
+            return Err(Error::MalformedFragment);
+        }
+
+        if self.completed.contains(&message_id) {
+            return Ok(None);
+        }
+
+        let partial = self.partial.entry(message_id).or_insert_with(|| Partial {
+            total,
+            fragments: BTreeMap::new(),
+        });
+        if partial.total != total {
+            return Err(Error::InconsistentFragment(message_id));
+        }
+        partial
+            .fragments
+            .entry(sequence)
+            .or_insert_with(|| data.to_vec());
+
+        if partial.fragments.len() < total as usize {
+            return Ok(None);
+        }
+
+        let partial = self.partial.remove(&message_id).unwrap();
+        self.completed.insert(message_id);
+        Ok(Some(partial.fragments.into_values().flatten().collect()))
+    }
+
+    /// The fragments still missing from each incomplete message, in message id order
+    pub fn missing(&self) -> Vec<Loss> {
+        self.partial
+            .iter()
+            .map(|(&message_id, partial)| partial.loss(message_id))
+            .collect()
+    }
+
+    /// Give up on every incomplete message with an id lower than `message_id`, returning what
+    /// was lost. Bookkeeping for completed messages below `message_id` is also dropped, so this
+    /// should be called regularly as ids advance.
+    pub fn expire_before(&mut self, message_id: u32) -> Vec<Loss> {
+        let still_pending = self.partial.split_off(&message_id);
+        let expired = std::mem::replace(&mut self.partial, still_pending);
+        self.completed = self.completed.split_off(&message_id);
+
+        expired
+            .iter()
+            .map(|(&message_id, partial)| partial.loss(message_id))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fragment_sizes() {
+        let message: Vec<u8> = (0..=255).collect();
+        let packets = fragment(7, &message, 108).unwrap();
+
+        assert_eq!(packets.len(), 3);
+        assert!(packets.iter().all(|packet| packet.len() <= 108));
+        assert_eq!(packets[2].len(), HEADER_SIZE + 56);
+
+        assert_eq!(fragment(7, &[], 108).unwrap().len(), 1);
+        assert!(matches!(
+            fragment(7, &message, HEADER_SIZE),
+            Err(Error::MtuTooSmall)
+        ));
+        assert!(matches!(
+            fragment(7, &vec![0; 70_000], HEADER_SIZE + 1),
+            Err(Error::TooManyFragments)
+        ));
+    }
+
+    #[test]
+    fn reassemble_out_of_order() {
+        let state: Vec<u32> = (0..500).collect();
+        let mut first = huff_fragments(&state, 1, 64).unwrap();
+        let mut second = huff_fragments(&"next", 2, 64).unwrap();
+        assert!(first.len() > 2);
+
+        first.reverse();
+        let mut reassembler = Reassembler::new();
+        let mut completed = Vec::new();
+        // Interleave the two messages and duplicate a fragment
+        second.push(first[0].clone());
+        for packet in second.iter().chain(first.iter()) {
+            if let Some(message) = reassembler.push(packet).unwrap() {
+                completed.push(message);
+            }
+        }
+
+        assert_eq!(completed.len(), 2);
+        assert_eq!(crate::puff::<String>(&completed[0]).unwrap(), "next");
+        assert_eq!(crate::puff::<Vec<u32>>(&completed[1]).unwrap(), state);
+        assert!(reassembler.missing().is_empty());
+    }
+
+    #[test]
+    fn report_loss() {
+        let message = vec![0xab; 100];
+        let packets = fragment(3, &message, 28).unwrap();
+        assert_eq!(packets.len(), 5);
+
+        let mut reassembler = Reassembler::new();
+        for (i, packet) in packets.iter().enumerate() {
+            if i != 1 && i != 3 {
+                assert_eq!(reassembler.push(packet).unwrap(), None);
+            }
+        }
+
+        let loss = Loss {
+            message_id: 3,
+            total: 5,
+            missing: vec![1, 3],
+        };
+        assert_eq!(reassembler.missing(), vec![loss.clone()]);
+        assert_eq!(reassembler.expire_before(3), vec![]);
+        assert_eq!(reassembler.expire_before(4), vec![loss]);
+        assert!(reassembler.missing().is_empty());
+    }
+
+    #[test]
+    fn malformed_packets() {
+        let mut reassembler = Reassembler::new();
+        assert!(matches!(
+            reassembler.push(&[1, 2, 3]),
+            Err(Error::MalformedFragment)
+        ));
+
+        let packets = fragment(9, &[1, 2, 3, 4], 10).unwrap();
+        reassembler.push(&packets[0]).unwrap();
+        let mut inconsistent = packets[1].clone();
+        inconsistent[6] = 9;
+        assert!(matches!(
+            reassembler.push(&inconsistent),
+            Err(Error::InconsistentFragment(9))
+        ));
+    }
+}
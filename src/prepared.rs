@@ -0,0 +1,192 @@
+//! Encoders and decoders prepared once for a tree, for coding many messages with the same
+//! alphabet without building a tree or a code table for each one
+
+#[cfg(feature = "decode")]
+use std::fmt;
+
+#[cfg(feature = "decode")]
+use crate::bits::BitReader;
+use crate::bits::{BitOrder, BitWriter};
+use crate::frame;
+#[cfg(feature = "decode")]
+use crate::frame::Encoding;
+use crate::huffman;
+
+/// Compresses messages with one tree, built once from a sample, with the tree written out and the
+/// code for every byte value looked up ahead of time. Every frame carries the tree, as frames
+/// from [`compress`](crate::compress) do, so they can be decompressed by
+/// [`decompress`](crate::decompress), and faster by the encoder's [`Decoder`].
+///
+/// ```
+/// use huffnpuff::Encoder;
+///
+/// let encoder = Encoder::new(b"GET /items/1 200\nGET /items/2 404\nPUT /items/3 201\n")?;
+/// let decoder = encoder.decoder();
+///
+/// let frame = encoder.encode(b"GET /items/312 200\nGET /items/1 404\nPUT /items/2 200\n")?;
+/// assert_eq!(decoder.decode(&frame)?, huffnpuff::decompress(&frame)?);
+/// # Ok::<(), huffnpuff::Error>(())
+/// ```
+#[cfg(feature = "encode")]
+#[derive(Debug, Clone)]
+pub struct Encoder {
+    tree: huffman::Tree,
+    /// The frame's message up to the end of the tree, which every message starts with
+    prefix: BitWriter,
+}
+
+#[cfg(feature = "encode")]
+impl Encoder {
+    /// An encoder with the tree [`compress`](crate::compress) would build for `sample`, which has
+    /// codes for the bytes in `sample` only
+    pub fn new(sample: &[u8]) -> Result<Self, crate::Error> {
+        if sample.is_empty() {
+            return Err(huffman::Error::NoData.into());
+        }
+        let tree = huffman::Tree::for_message(sample);
+        let mut prefix = BitWriter::with_order(BitOrder::Lsb0);
+        tree.write_tree(&mut prefix);
+        Ok(Self { tree, prefix })
+    }
+
+    /// Compress `bytes` to a frame. Data that doesn't compress is stored as it is, as by
+    /// [`compress`](crate::compress), and data with bytes the tree has no code for is compressed
+    /// with a tree of its own.
+    pub fn encode(&self, bytes: &[u8]) -> Result<Vec<u8>, crate::Error> {
+        let mut bits = self.prefix.clone();
+        if bytes.is_empty() || !self.tree.write_codes(bytes, &mut bits) {
+            return crate::compress(bytes);
+        }
+        Ok(frame::frame_message(bits.into_bytes(), &[bytes]))
+    }
+
+    /// A decoder for this encoder's frames
+    #[cfg(feature = "decode")]
+    pub fn decoder(&self) -> Decoder {
+        Decoder::new(self.tree.clone())
+    }
+}
+
+/// Decompresses frames that carry the same tree, like those from one [`Encoder`], with the table
+/// for decoding the tree built ahead of time. The tree in each frame is only compared with the
+/// decoder's rather than read. Any other frame is decompressed by
+/// [`decompress`](crate::decompress).
+#[cfg(feature = "decode")]
+#[derive(Clone)]
+pub struct Decoder {
+    tree: huffman::Tree,
+    table: huffman::DecodeTable,
+    /// The frame header and tree that frames with this tree start with, and the number of bits
+    /// the tree takes up
+    header: [u8; frame::PLAIN_HEADER_LEN],
+    tree_bytes: Vec<u8>,
+    tree_bits: usize,
+}
+
+#[cfg(feature = "decode")]
+impl Decoder {
+    /// A decoder for the tree carried by the first frame in `bytes`, such as a frame from an
+    /// [`Encoder`]. Frames that don't carry a tree give `None`.
+    pub fn from_frame(bytes: &[u8]) -> Result<Option<Self>, crate::Error> {
+        Ok(frame::embedded_tree(bytes)?.map(Self::new))
+    }
+
+    fn new(tree: huffman::Tree) -> Self {
+        let mut bits = BitWriter::with_order(BitOrder::Lsb0);
+        tree.write_tree(&mut bits);
+        let tree_bits = bits.len();
+        Self {
+            table: tree.decode_table(BitOrder::Lsb0),
+            tree,
+            header: frame::plain_header(Encoding::Huffman),
+            tree_bytes: bits.into_bytes(),
+            tree_bits,
+        }
+    }
+
+    /// Decompress the frame at the start of `bytes`, like [`decompress`](crate::decompress)
+    pub fn decode(&self, bytes: &[u8]) -> Result<Vec<u8>, crate::Error> {
+        match bytes.strip_prefix(&self.header) {
+            Some(message) if self.starts_with_tree(message) => {
+                let mut bits = BitReader::with_order(message, BitOrder::Lsb0);
+                bits.seek(self.tree_bits);
+                self.tree.decode_codes(&mut bits, &self.table, usize::MAX)
+            }
+            _ => crate::decompress(bytes),
+        }
+    }
+
+    /// Whether `message` starts with the decoder's tree
+    fn starts_with_tree(&self, message: &[u8]) -> bool {
+        let whole = self.tree_bits / 8;
+        let rest = self.tree_bits % 8;
+        // The tree's last bits share a byte with the first codes, in its low bits
+        let mask = (1u8 << rest).wrapping_sub(1);
+        message.len() > whole
+            && message[..whole] == self.tree_bytes[..whole]
+            && (rest == 0 || message[whole] & mask == self.tree_bytes[whole] & mask)
+    }
+}
+
+#[cfg(feature = "decode")]
+impl fmt::Debug for Decoder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Decoder")
+            .field("tree_bits", &self.tree_bits)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(all(test, feature = "encode", feature = "decode"))]
+mod tests {
+    use super::*;
+
+    fn message(i: usize) -> String {
+        format!("{{\"user\":{},\"action\":\"logout\"}}", i * 37).repeat(i % 4 + 1)
+    }
+
+    #[test]
+    fn roundtrip() {
+        let encoder = Encoder::new(message(3).as_bytes()).unwrap();
+        let decoder = encoder.decoder();
+        for i in 0..50 {
+            let message = message(i);
+            let frame = encoder.encode(message.as_bytes()).unwrap();
+            assert_eq!(decoder.decode(&frame).unwrap(), message.as_bytes());
+            assert_eq!(crate::decompress(&frame).unwrap(), message.as_bytes());
+        }
+
+        // Frames from elsewhere, and data the tree can't code, are decoded the usual way
+        for message in [&b"x"[..], &[0xfe; 300], b"not from the encoder at all"] {
+            let frame = crate::compress(message).unwrap();
+            assert_eq!(decoder.decode(&frame).unwrap(), message);
+            let frame = encoder.encode(message).unwrap();
+            assert_eq!(decoder.decode(&frame).unwrap(), message);
+        }
+        assert!(Encoder::new(b"").is_err());
+    }
+
+    #[test]
+    fn from_frame() {
+        let encoder = Encoder::new(message(6).as_bytes()).unwrap();
+        let frame = encoder.encode(message(6).as_bytes()).unwrap();
+        let decoder = Decoder::from_frame(&frame).unwrap().unwrap();
+        assert_eq!(decoder.tree_bits, encoder.decoder().tree_bits);
+        assert_eq!(decoder.decode(&frame).unwrap(), message(6).as_bytes());
+
+        let stored = crate::compress(b"ab").unwrap();
+        assert!(Decoder::from_frame(&stored).unwrap().is_none());
+    }
+
+    #[test]
+    fn damaged() {
+        let encoder = Encoder::new(message(1).as_bytes()).unwrap();
+        let frame = encoder.encode(message(1).as_bytes()).unwrap();
+        let decoder = encoder.decoder();
+        assert_eq!(
+            decoder.decode(&frame[..frame.len() - 2]),
+            Err(huffman::Error::TruncatedMessage.into())
+        );
+        assert!(decoder.decode(&frame[..5]).is_err());
+    }
+}
@@ -0,0 +1,227 @@
+//! Measuring a corpus of samples to choose how to compress data like it
+
+use std::collections::HashSet;
+
+use crate::codebook::Codebook;
+use crate::frame;
+use crate::level::Level;
+use crate::options::EncodeOptions;
+use crate::transform::Transform;
+
+/// The most of the corpus that candidate settings are tried on
+const TRIAL_LEN: usize = 256 << 10;
+
+/// The kind of redundancy that dominates a corpus
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Redundancy {
+    /// Runs of a repeated byte, which run length coding shortens
+    Runs,
+    /// Repeated sequences of bytes, which the Burrows-Wheeler transform gathers together
+    Matches,
+    /// Some byte values are much more common than others, which huffman coding exploits directly
+    Skew,
+    /// Bytes are close to uniformly random, and won't compress
+    None,
+}
+
+/// What [`profile_corpus`] found
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProfileReport {
+    pub samples: usize,
+    pub total_len: usize,
+    /// The number of times each byte value occurs
+    pub frequencies: [u64; 256],
+    /// The order 0 entropy of the bytes, in bits per byte, which is about what huffman coding
+    /// without transforms achieves
+    pub entropy: f64,
+    /// The fraction of bytes that repeat the byte before them
+    pub run_fraction: f64,
+    /// The fraction of 4 byte sequences that already occurred earlier in the same sample
+    pub match_fraction: f64,
+    pub redundancy: Redundancy,
+    /// The total size of the samples compressed each with a tree of its own
+    pub compressed_len: usize,
+    /// The total size of the samples compressed with a codebook trained on the corpus
+    pub codebook_len: usize,
+    /// The size of that codebook, which is stored once alongside the samples
+    pub codebook_size: usize,
+    /// The options that compressed the corpus best
+    pub options: EncodeOptions,
+}
+
+impl ProfileReport {
+    /// Whether compressing samples with a shared [`Codebook`] trained on the corpus beats each
+    /// sample carrying its own tree, once the codebook is paid for. Transforms aren't applied
+    /// with codebooks.
+    pub fn recommends_codebook(&self) -> bool {
+        self.codebook_len + self.codebook_size < self.compressed_len
+    }
+
+    /// The lowest [`Level`] that tries the recommended options, for use with
+    /// [`huff_with_level`](crate::huff_with_level)
+    pub fn level(&self) -> Level {
+        match self.options.transforms.first() {
+            _ if self.compressed_len >= self.total_len => Level::Store,
+            None => Level::Huffman,
+            Some(Transform::Bwt) => Level::Best,
+            Some(_) => Level::Transforms,
+        }
+    }
+}
+
+/// The order 0 entropy of bytes with these frequencies, in bits per byte
+pub(crate) fn entropy(frequencies: &[u64; 256]) -> f64 {
+    let total = frequencies.iter().sum::<u64>() as f64;
+    frequencies
+        .iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| {
+            let p = count as f64 / total;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+/// Measure the byte distribution and redundancy of `samples`, and try the ways of compressing
+/// them, to recommend options for data like them. Candidate transforms are tried on the first
+/// 256 KiB of the corpus.
+pub fn profile_corpus<S: AsRef<[u8]>>(
+    samples: impl IntoIterator<Item = S>,
+) -> Result<ProfileReport, crate::Error> {
+    let samples: Vec<S> = samples.into_iter().collect();
+    let mut frequencies = [0u64; 256];
+    let (mut runs, mut matches, mut windows) = (0usize, 0usize, 0usize);
+    for sample in &samples {
+        let sample = sample.as_ref();
+        for &byte in sample {
+            frequencies[byte as usize] += 1;
+        }
+        runs += sample.windows(2).filter(|pair| pair[0] == pair[1]).count();
+        let mut seen = HashSet::new();
+        for window in sample.windows(4) {
+            windows += 1;
+            matches += !seen.insert(window) as usize;
+        }
+    }
+    let total_len: usize = frequencies.iter().sum::<u64>() as usize;
+    let entropy = entropy(&frequencies);
+    let run_fraction = runs as f64 / total_len.max(1) as f64;
+    let match_fraction = matches as f64 / windows.max(1) as f64;
+    let redundancy = if run_fraction >= 0.3 {
+        Redundancy::Runs
+    } else if match_fraction >= 0.5 {
+        Redundancy::Matches
+    } else if entropy < 7.5 {
+        Redundancy::Skew
+    } else {
+        Redundancy::None
+    };
+
+    let encode = |bytes: &[u8], options: &EncodeOptions, codebook| {
+        frame::encode_with_codebook(bytes, &frame::Header::default(), options, codebook)
+            .map(|frame| frame.len())
+    };
+    let default = EncodeOptions::default();
+    let codebook = Codebook::train(&samples);
+    let (mut compressed_len, mut codebook_len) = (0, 0);
+    for sample in &samples {
+        compressed_len += encode(sample.as_ref(), &default, None)?;
+        codebook_len += encode(sample.as_ref(), &default, Some(&codebook))?;
+    }
+
+    let mut trial = Vec::new();
+    for sample in &samples {
+        let rest = TRIAL_LEN - trial.len();
+        let sample = sample.as_ref();
+        trial.extend_from_slice(&sample[..sample.len().min(rest)]);
+    }
+    let candidates = [
+        vec![],
+        vec![Transform::RunLength],
+        vec![Transform::Delta, Transform::RunLength],
+        vec![Transform::Bwt, Transform::MoveToFront],
+    ];
+    let mut options = default.clone();
+    let mut best = encode(&trial, &options, None)?;
+    for transforms in candidates {
+        let candidate = EncodeOptions {
+            transforms,
+            ..default.clone()
+        };
+        let len = encode(&trial, &candidate, None)?;
+        if len < best {
+            (options, best) = (candidate, len);
+        }
+    }
+
+    Ok(ProfileReport {
+        samples: samples.len(),
+        total_len,
+        frequencies,
+        entropy,
+        run_fraction,
+        match_fraction,
+        redundancy,
+        compressed_len,
+        codebook_len,
+        codebook_size: codebook.to_bytes().len(),
+        options,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runs() {
+        let samples: Vec<Vec<u8>> = (0..20u8)
+            .map(|i| [vec![i; 100], vec![0; 300], vec![255; 50]].concat())
+            .collect();
+        let report = profile_corpus(&samples).unwrap();
+        assert_eq!(report.samples, 20);
+        assert_eq!(report.total_len, 20 * 450);
+        assert_eq!(report.redundancy, Redundancy::Runs);
+        assert!(report.options.transforms.contains(&Transform::RunLength));
+        assert_eq!(report.level(), Level::Transforms);
+    }
+
+    #[test]
+    fn matches() {
+        let samples: Vec<String> = (0..50)
+            .map(|i| "the quick brown fox jumps over the lazy dog. ".repeat(5 + i % 3))
+            .collect();
+        let report = profile_corpus(&samples).unwrap();
+        assert_eq!(report.redundancy, Redundancy::Matches);
+        assert_eq!(
+            report.options.transforms,
+            [Transform::Bwt, Transform::MoveToFront]
+        );
+    }
+
+    #[test]
+    fn small_skewed_samples_want_a_codebook() {
+        let samples: Vec<String> = (0..200)
+            .map(|i| format!("id={i} ok={}", i % 2 == 0))
+            .collect();
+        let report = profile_corpus(&samples).unwrap();
+        assert_eq!(report.redundancy, Redundancy::Skew);
+        assert!(report.entropy < 5.0);
+        assert!(report.recommends_codebook());
+
+        let mut state = 0x2545_f491_u32;
+        let random: Vec<u8> = (0..4096)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect();
+        let report = profile_corpus([random]).unwrap();
+        assert_eq!(report.redundancy, Redundancy::None);
+        assert!(!report.recommends_codebook());
+        assert_eq!(report.level(), Level::Store);
+    }
+}
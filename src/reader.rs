@@ -0,0 +1,164 @@
+use std::io::Read;
+
+use crate::frame;
+
+/// Reads the decompressed bytes of a buffer produced by this library, decoding only as many
+/// symbols as are asked for. This lets a deserializer consume the payload directly, stopping as
+/// soon as it has read a complete value:
+///
+/// ```
+/// use huffnpuff::{DecodedReader, huff};
+///
+/// let compressed = huff(&("a header", vec![0u8; 10_000])).unwrap();
+///
+/// // Only the bytes of the first element are decoded
+/// let reader = DecodedReader::new(&compressed).unwrap();
+/// let header: String = bincode::deserialize_from(reader).unwrap();
+/// assert_eq!(header, "a header");
+/// ```
+pub struct DecodedReader<'a> {
+    bytes: &'a [u8],
+    symbols: frame::Symbols<'a>,
+    checkpoints: Option<frame::Checkpoints>,
+    /// The number of bytes read so far
+    position: usize,
+}
+
+impl<'a> DecodedReader<'a> {
+    /// Decode the header of `bytes`, failing if it isn't a buffer this library can decode
+    pub fn new(bytes: &'a [u8]) -> Result<Self, crate::Error> {
+        Ok(Self {
+            bytes,
+            symbols: frame::decode_iter(bytes)?,
+            checkpoints: frame::read_header(bytes)?.0.checkpoints,
+            position: 0,
+        })
+    }
+
+    /// Move to `offset` in the decompressed bytes, so the next read starts there. Offsets past the
+    /// end move to the end.
+    ///
+    /// Data encoded with a
+    /// [`checkpoint_interval`](crate::EncodeOptions::checkpoint_interval) is decoded from the last
+    /// checkpoint before `offset`. Otherwise, moving forwards decodes the bytes in between, and
+    /// moving backwards decodes from the start.
+    pub fn seek_to_output_offset(&mut self, offset: usize) -> Result<(), crate::Error> {
+        let checkpoint = match self.symbols.can_resume() {
+            true => self.checkpoints.as_ref().and_then(|c| c.before(offset)),
+            false => None,
+        };
+        match checkpoint {
+            Some((output_offset, bit_offset))
+                if offset < self.position || output_offset > self.position =>
+            {
+                self.symbols.resume(bit_offset);
+                self.position = output_offset;
+            }
+            _ if offset < self.position => {
+                self.symbols = frame::decode_iter(self.bytes)?;
+                self.position = 0;
+            }
+            _ => {}
+        }
+        self.position += (&mut self.symbols).take(offset - self.position).count();
+        match self.symbols.error() {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Read for DecodedReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let mut len = 0;
+        for (slot, byte) in buf.iter_mut().zip(&mut self.symbols) {
+            *slot = byte;
+            len += 1;
+        }
+        self.position += len;
+        if len == 0
+            && !buf.is_empty()
+            && let Some(error) = self.symbols.error()
+        {
+            return Err(error.into());
+        }
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_all() {
+        let message = "read through std::io::Read".repeat(10);
+        let compressed = crate::huff(&message).unwrap();
+
+        let mut decoded = Vec::new();
+        DecodedReader::new(&compressed)
+            .unwrap()
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, bincode::serialize(&message).unwrap());
+    }
+
+    #[test]
+    fn small_reads() {
+        let compressed = crate::huff(&"abcdefgh").unwrap();
+        let mut reader = DecodedReader::new(&compressed).unwrap();
+
+        let mut buf = [0; 3];
+        // The first 8 bytes are the string's length
+        reader.read_exact(&mut [0; 8]).unwrap();
+        assert_eq!(reader.read(&mut buf).unwrap(), 3);
+        assert_eq!(&buf, b"abc");
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"def");
+        assert_eq!(reader.read(&mut buf).unwrap(), 2);
+        assert_eq!(reader.read(&mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn deserialize_from() {
+        let value = (42u64, "streamed".to_owned());
+        let compressed = crate::huff(&value).unwrap();
+
+        let reader = DecodedReader::new(&compressed).unwrap();
+        let decoded: (u64, String) = bincode::deserialize_from(reader).unwrap();
+        assert_eq!(decoded, value);
+
+        assert!(DecodedReader::new(&[]).is_err());
+    }
+
+    #[test]
+    fn seek() {
+        let document: String = (0..2000).map(|i| format!("line {i}\n")).collect();
+        let serialized = bincode::serialize(&document).unwrap();
+        let plain = crate::huff(&document).unwrap();
+        let options = crate::EncodeOptions {
+            checkpoint_interval: Some(1024),
+            ..Default::default()
+        };
+        let checkpointed = crate::huff_with_options(&document, &options).unwrap();
+        assert!(checkpointed.len() > plain.len());
+        assert!(checkpointed.len() < plain.len() + 64);
+        assert_eq!(crate::puff::<String>(&checkpointed).unwrap(), document);
+
+        for compressed in [&plain, &checkpointed] {
+            let mut reader = DecodedReader::new(compressed).unwrap();
+            for offset in [5000, 100, 1024, 1023, 9000, 0, serialized.len() - 3] {
+                reader.seek_to_output_offset(offset).unwrap();
+                let mut buf = [0; 3];
+                reader.read_exact(&mut buf).unwrap();
+                assert_eq!(buf, serialized[offset..offset + 3]);
+            }
+            reader.seek_to_output_offset(usize::MAX).unwrap();
+            assert_eq!(reader.read(&mut [0; 3]).unwrap(), 0);
+            reader.seek_to_output_offset(8).unwrap();
+            let mut rest = Vec::new();
+            reader.read_to_end(&mut rest).unwrap();
+            assert_eq!(rest, document.as_bytes());
+        }
+    }
+}
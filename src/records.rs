@@ -0,0 +1,643 @@
+//! An append-only file of records of a single type. Records are grouped into blocks of a fixed
+//! number of records, each compressed on its own, and an index at the end of the file records
+//! where each block and each record within it starts. Reading a record only decompresses the block
+//! that holds it, which is found directly from the record's number.
+//!
+//! For a fixed set of records, [`huff_records`] codes every record with one shared tree and
+//! indexes the bit each one starts at, so [`SeekableRecords`] decodes a record and nothing else.
+
+#[cfg(feature = "encode")]
+use std::io::Write;
+use std::marker::PhantomData;
+#[cfg(feature = "decode")]
+use std::ops::Range;
+
+use serde::{Deserialize, Serialize};
+
+use crate::bits::BitOrder;
+#[cfg(feature = "decode")]
+use crate::bits::BitReader;
+#[cfg(feature = "encode")]
+use crate::bits::BitWriter;
+#[cfg(feature = "encode")]
+use crate::options::EncodeOptions;
+use crate::{frame, huffman};
+
+const MAGIC: &[u8; 4] = b"HNPR";
+const VERSION: u8 = 1;
+/// Magic, version and the number of records in each block
+const HEADER_SIZE: usize = MAGIC.len() + 1 + 4;
+/// The offset of the index followed by the magic bytes again, so the index can be located from
+/// the end of the file
+#[cfg(feature = "decode")]
+const FOOTER_SIZE: usize = 8 + MAGIC.len();
+
+const SEEKABLE_MAGIC: &[u8; 4] = b"HNPI";
+/// Magic, version, the number of records, the length of the tree and the number of bits of each
+/// index entry. The tree follows, then the index of the bit each record starts at, and then the
+/// coded records.
+const SEEKABLE_HEADER_SIZE: usize = SEEKABLE_MAGIC.len() + 1 + 8 + 4 + 1;
+
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The data does not look like a record file produced by huffnpuff
+    BadMagic,
+    /// The file was written with a format version this library doesn't understand
+    UnsupportedVersion(u8),
+    /// The number of records per block must be greater than zero and fit in 32 bits
+    InvalidBlockSize,
+    /// The file is too short to contain a header, index and footer
+    Truncated,
+    /// The index points outside the file, or could not be decoded
+    CorruptIndex,
+    /// The stored checksum for the block with this index doesn't match its data
+    ChecksumMismatch(usize),
+    /// There is no record with this number, or the range of records ends past the last one
+    OutOfRange(usize),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Error::BadMagic => "the data is not a record file",
+            Error::UnsupportedVersion(version) => {
+                return write!(
+                    f,
+                    "the record file has unsupported format version {version}"
+                );
+            }
+            Error::InvalidBlockSize => {
+                "the number of records per block must be greater than zero and fit in 32 bits"
+            }
+            Error::Truncated => "the record file is too short to hold its index",
+            Error::CorruptIndex => "the record file's index is damaged",
+            Error::ChecksumMismatch(index) => {
+                return write!(f, "block {index} doesn't match its checksum");
+            }
+            Error::OutOfRange(record) => {
+                return write!(f, "there is no record {record}");
+            }
+        })
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct BlockEntry {
+    offset: u64,
+    len: u64,
+    checksum: u32,
+    /// Where each record ends in the decompressed block
+    ends: Vec<u64>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Index {
+    blocks: Vec<BlockEntry>,
+}
+
+/// Appends records to a record file, compressing them a block at a time. Records are buffered
+/// until a block is full, and [`RecordWriter::finish`] must be called to write the final partial
+/// block and the index.
+#[cfg(feature = "encode")]
+pub struct RecordWriter<W: Write, T> {
+    writer: W,
+    position: u64,
+    records_per_block: usize,
+    buffer: Vec<u8>,
+    ends: Vec<u64>,
+    index: Index,
+    len: usize,
+    records: PhantomData<fn(&T)>,
+}
+
+#[cfg(feature = "encode")]
+impl<W: Write, T: Serialize> RecordWriter<W, T> {
+    /// Start a record file, writing its header to `writer`. Larger blocks compress better, but
+    /// reading any record decompresses the whole block holding it.
+    pub fn new(mut writer: W, records_per_block: usize) -> Result<Self, crate::Error> {
+        let records_per_block_field = u32::try_from(records_per_block)
+            .ok()
+            .filter(|&records| records > 0)
+            .ok_or(Error::InvalidBlockSize)?;
+
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[VERSION])?;
+        writer.write_all(&records_per_block_field.to_le_bytes())?;
+        Ok(Self {
+            writer,
+            position: HEADER_SIZE as u64,
+            records_per_block,
+            buffer: Vec::new(),
+            ends: Vec::with_capacity(records_per_block),
+            index: Index::default(),
+            len: 0,
+            records: PhantomData,
+        })
+    }
+
+    /// Add a record to the end of the file
+    pub fn append(&mut self, record: &T) -> Result<(), crate::Error> {
+        bincode::serialize_into(&mut self.buffer, record)?;
+        self.ends.push(self.buffer.len() as u64);
+        self.len += 1;
+        if self.ends.len() == self.records_per_block {
+            self.write_block()?;
+        }
+        Ok(())
+    }
+
+    /// The number of records appended so far
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Compress and write out the buffered block
+    fn write_block(&mut self) -> Result<(), crate::Error> {
+        if self.ends.is_empty() {
+            return Ok(());
+        }
+        let compressed = frame::encode(
+            &self.buffer,
+            &frame::Header::default(),
+            &EncodeOptions::default(),
+        )?;
+        self.writer.write_all(&compressed)?;
+
+        self.index.blocks.push(BlockEntry {
+            offset: self.position,
+            len: compressed.len() as u64,
+            checksum: crc32fast::hash(&compressed),
+            ends: std::mem::take(&mut self.ends),
+        });
+        self.position += compressed.len() as u64;
+        self.buffer.clear();
+        Ok(())
+    }
+
+    /// Write the final block, the index and the footer, returning the underlying writer
+    pub fn finish(mut self) -> Result<W, crate::Error> {
+        self.write_block()?;
+        let index = bincode::serialize(&self.index)?;
+        self.writer.write_all(&index)?;
+        self.writer.write_all(&self.position.to_le_bytes())?;
+        self.writer.write_all(MAGIC)?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// Random access to the records of a file produced by [`RecordWriter`]. Only the index is decoded
+/// up front; blocks are checked and decompressed when records in them are requested.
+#[cfg(feature = "decode")]
+pub struct RecordReader<'a, T> {
+    bytes: &'a [u8],
+    records_per_block: usize,
+    index: Index,
+    len: usize,
+    records: PhantomData<fn() -> T>,
+}
+
+#[cfg(feature = "decode")]
+impl<'a, T: serde::de::DeserializeOwned> RecordReader<'a, T> {
+    pub fn new(bytes: &'a [u8]) -> Result<Self, crate::Error> {
+        if bytes.len() < HEADER_SIZE + FOOTER_SIZE {
+            return Err(Error::Truncated.into());
+        }
+        if &bytes[..MAGIC.len()] != MAGIC {
+            return Err(Error::BadMagic.into());
+        }
+        if bytes[MAGIC.len()] != VERSION {
+            return Err(Error::UnsupportedVersion(bytes[MAGIC.len()]).into());
+        }
+        let records_per_block =
+            u32::from_le_bytes(bytes[MAGIC.len() + 1..HEADER_SIZE].try_into().unwrap()) as usize;
+        if records_per_block == 0 {
+            return Err(Error::InvalidBlockSize.into());
+        }
+
+        let footer = &bytes[bytes.len() - FOOTER_SIZE..];
+        if &footer[8..] != MAGIC {
+            return Err(Error::BadMagic.into());
+        }
+        let index_offset = u64::from_le_bytes(footer[..8].try_into().unwrap());
+        let index_end = (bytes.len() - FOOTER_SIZE) as u64;
+        if index_offset < HEADER_SIZE as u64 || index_offset > index_end {
+            return Err(Error::CorruptIndex.into());
+        }
+        let index: Index = bincode::deserialize(&bytes[index_offset as usize..index_end as usize])
+            .map_err(|_| Error::CorruptIndex)?;
+
+        // Every block but the last must be full, so a record's block follows from its number
+        let last = index.blocks.len().saturating_sub(1);
+        for (i, block) in index.blocks.iter().enumerate() {
+            let in_bounds = block
+                .offset
+                .checked_add(block.len)
+                .is_some_and(|end| block.offset >= HEADER_SIZE as u64 && end <= index_offset);
+            let full = block.ends.len() == records_per_block
+                || (i == last && (1..records_per_block).contains(&block.ends.len()));
+            if !in_bounds || !full || !block.ends.is_sorted() {
+                return Err(Error::CorruptIndex.into());
+            }
+        }
+        let len = index.blocks.iter().map(|block| block.ends.len()).sum();
+
+        Ok(Self {
+            bytes,
+            records_per_block,
+            index,
+            len,
+            records: PhantomData,
+        })
+    }
+
+    /// The number of records in the file
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Decompress the record with number `n`, counting from zero
+    pub fn get(&self, n: usize) -> Result<T, crate::Error> {
+        if n >= self.len {
+            return Err(Error::OutOfRange(n).into());
+        }
+        let block = n / self.records_per_block;
+        let data = self.block(block)?;
+        self.record(&data, block, n % self.records_per_block)
+    }
+
+    /// Decompress the records in `range`, decompressing each block they are in once
+    pub fn range(&self, range: Range<usize>) -> Result<Vec<T>, crate::Error> {
+        if range.end > self.len {
+            return Err(Error::OutOfRange(range.end).into());
+        }
+        let mut records = Vec::with_capacity(range.len());
+        let mut current: Option<(usize, Vec<u8>)> = None;
+        for n in range {
+            let block = n / self.records_per_block;
+            let data = match current {
+                Some((i, data)) if i == block => data,
+                _ => self.block(block)?,
+            };
+            records.push(self.record(&data, block, n % self.records_per_block)?);
+            current = Some((block, data));
+        }
+        Ok(records)
+    }
+
+    /// Check and decompress a block
+    fn block(&self, block: usize) -> Result<Vec<u8>, crate::Error> {
+        let entry = &self.index.blocks[block];
+        // Bounds were checked when the index was read
+        let start = entry.offset as usize;
+        let compressed = &self.bytes[start..start + entry.len as usize];
+        if crc32fast::hash(compressed) != entry.checksum {
+            return Err(Error::ChecksumMismatch(block).into());
+        }
+        Ok(frame::decode(compressed)?.payload)
+    }
+
+    fn record(&self, data: &[u8], block: usize, i: usize) -> Result<T, crate::Error> {
+        let ends = &self.index.blocks[block].ends;
+        let start = if i == 0 { 0 } else { ends[i - 1] as usize };
+        let record = data
+            .get(start..ends[i] as usize)
+            .ok_or(Error::CorruptIndex)?;
+        Ok(bincode::deserialize(record)?)
+    }
+}
+
+/// Compress `records` for random access with [`SeekableRecords`]. The records are coded one after
+/// another with a single tree built from all of them, which is stored once, so small records
+/// don't each pay for a tree. The index of the bit each record starts at takes as many bits a
+/// record as the largest of them needs.
+///
+/// ```
+/// use huffnpuff::SeekableRecords;
+///
+/// let names: Vec<String> = (0..100).map(|i| format!("user-{i}")).collect();
+/// let bytes = huffnpuff::huff_records(&names)?;
+/// let records = SeekableRecords::new(&bytes)?;
+/// assert_eq!(records.get::<String>(42)?, "user-42");
+/// # Ok::<(), huffnpuff::Error>(())
+/// ```
+#[cfg(feature = "encode")]
+pub fn huff_records<T: Serialize>(records: &[T]) -> Result<Vec<u8>, crate::Error> {
+    let serialized = records
+        .iter()
+        .map(bincode::serialize)
+        .collect::<Result<Vec<_>, _>>()?;
+    let all = serialized.concat();
+    // A tree needs a byte to code, which records that serialize to nothing don't have
+    let tree = huffman::Tree::for_message(if all.is_empty() { &[0] } else { &all });
+
+    let mut codes = BitWriter::with_order(BitOrder::Lsb0);
+    let mut starts = Vec::with_capacity(records.len());
+    for record in &serialized {
+        starts.push(codes.len() as u64);
+        // The tree has a code for every byte of every record
+        let coded = tree.write_codes(record, &mut codes);
+        debug_assert!(coded);
+    }
+
+    let width = starts
+        .last()
+        .map_or(0, |last| u64::BITS - last.leading_zeros()) as usize;
+    let mut index = BitWriter::with_order(BitOrder::Lsb0);
+    for start in starts {
+        index.write_bits(start, width);
+    }
+
+    let tree = tree.serialize();
+    let index = index.into_bytes();
+    let codes = codes.into_bytes();
+    let mut out = Vec::with_capacity(SEEKABLE_HEADER_SIZE + tree.len() + index.len() + codes.len());
+    out.extend_from_slice(SEEKABLE_MAGIC);
+    out.push(VERSION);
+    out.extend_from_slice(&(records.len() as u64).to_le_bytes());
+    out.extend_from_slice(&(tree.len() as u32).to_le_bytes());
+    out.push(width as u8);
+    out.extend_from_slice(&tree);
+    out.extend_from_slice(&index);
+    out.extend_from_slice(&codes);
+    Ok(out)
+}
+
+/// Random access to records compressed by [`huff_records`]. Opening them reads the tree and checks
+/// the index, and getting a record decodes that record alone.
+#[cfg(feature = "decode")]
+pub struct SeekableRecords<'a> {
+    len: usize,
+    /// The bit each record starts at, `width` bits each
+    index: &'a [u8],
+    width: usize,
+    codes: &'a [u8],
+    tree: huffman::Tree,
+    table: huffman::DecodeTable,
+}
+
+#[cfg(feature = "decode")]
+impl<'a> SeekableRecords<'a> {
+    pub fn new(bytes: &'a [u8]) -> Result<Self, crate::Error> {
+        if bytes.len() < SEEKABLE_HEADER_SIZE {
+            return Err(Error::Truncated.into());
+        }
+        if &bytes[..SEEKABLE_MAGIC.len()] != SEEKABLE_MAGIC {
+            return Err(Error::BadMagic.into());
+        }
+        let version = bytes[SEEKABLE_MAGIC.len()];
+        if version != VERSION {
+            return Err(Error::UnsupportedVersion(version).into());
+        }
+        let len = u64::from_le_bytes(bytes[5..13].try_into().unwrap());
+        let tree_len = u32::from_le_bytes(bytes[13..17].try_into().unwrap()) as usize;
+        let width = bytes[17] as usize;
+        // Only the first record starts at bit zero, so an index without bits has one at most
+        if width > 64 || (width == 0 && len > 1) {
+            return Err(Error::CorruptIndex.into());
+        }
+
+        let rest = &bytes[SEEKABLE_HEADER_SIZE..];
+        if tree_len > rest.len() {
+            return Err(Error::Truncated.into());
+        }
+        let (tree, rest) = rest.split_at(tree_len);
+        let index_len = len
+            .checked_mul(width as u64)
+            .map(|bits| bits.div_ceil(8))
+            .filter(|&index_len| index_len <= rest.len() as u64)
+            .ok_or(Error::Truncated)? as usize;
+        let (index, codes) = rest.split_at(index_len);
+        let tree = huffman::Tree::deserialize(tree)?;
+
+        let records = Self {
+            len: len as usize,
+            index,
+            width,
+            codes,
+            table: tree.decode_table(BitOrder::Lsb0),
+            tree,
+        };
+        let mut last = 0;
+        for n in 0..records.len {
+            let start = records.start(n);
+            if start < last {
+                return Err(Error::CorruptIndex.into());
+            }
+            last = start;
+        }
+        if records.len > 0 && last >= codes.len() * 8 {
+            return Err(Error::CorruptIndex.into());
+        }
+        Ok(records)
+    }
+
+    /// The number of records
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Decompress the record with number `n`, counting from zero
+    pub fn get<T: serde::de::DeserializeOwned>(&self, n: usize) -> Result<T, crate::Error> {
+        if n >= self.len() {
+            return Err(Error::OutOfRange(n).into());
+        }
+        let start = self.start(n);
+        let end = match n + 1 < self.len() {
+            true => self.start(n + 1),
+            false => self.codes.len() * 8,
+        };
+        let mut bits = BitReader::with_order(self.codes, BitOrder::Lsb0);
+        bits.seek(start);
+        // Every code takes at least a bit, so a record has fewer bytes than bits
+        let record = self
+            .tree
+            .decode_codes(&mut bits, &self.table, end - start)?;
+        if n + 1 < self.len() && bits.position() != end {
+            return Err(Error::CorruptIndex.into());
+        }
+        Ok(bincode::deserialize(&record)?)
+    }
+
+    /// The bit record `n` starts at
+    fn start(&self, n: usize) -> usize {
+        let mut index = BitReader::with_order(self.index, BitOrder::Lsb0);
+        index.seek(n * self.width);
+        // The index was checked to be long enough for every record
+        index.read_n(self.width).unwrap() as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Reading {
+        sensor: String,
+        value: u32,
+    }
+
+    fn reading(i: usize) -> Reading {
+        Reading {
+            sensor: format!("sensor-{}", i % 7),
+            value: (i * 37 % 1000) as u32,
+        }
+    }
+
+    fn sample_file(records: usize) -> Vec<u8> {
+        let mut writer = RecordWriter::new(Vec::new(), 64).unwrap();
+        for i in 0..records {
+            writer.append(&reading(i)).unwrap();
+        }
+        assert_eq!(writer.len(), records);
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn roundtrip() {
+        let bytes = sample_file(1000);
+        let reader = RecordReader::<Reading>::new(&bytes).unwrap();
+
+        assert_eq!(reader.len(), 1000);
+        for n in [0, 1, 63, 64, 500, 999] {
+            assert_eq!(reader.get(n).unwrap(), reading(n));
+        }
+        assert_eq!(
+            reader.range(60..200).unwrap(),
+            (60..200).map(reading).collect::<Vec<_>>()
+        );
+        assert!(reader.range(10..10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn compresses() {
+        let raw: usize = (0..1000)
+            .map(|i| bincode::serialized_size(&reading(i)).unwrap() as usize)
+            .sum();
+        assert!(sample_file(1000).len() < raw);
+    }
+
+    #[test]
+    fn empty_and_partial() {
+        let bytes = sample_file(0);
+        let reader = RecordReader::<Reading>::new(&bytes).unwrap();
+        assert!(reader.is_empty());
+
+        let bytes = sample_file(3);
+        let reader = RecordReader::<Reading>::new(&bytes).unwrap();
+        assert_eq!(
+            reader.range(0..3).unwrap(),
+            vec![reading(0), reading(1), reading(2)]
+        );
+    }
+
+    #[test]
+    fn out_of_range() {
+        let bytes = sample_file(100);
+        let reader = RecordReader::<Reading>::new(&bytes).unwrap();
+        assert!(matches!(
+            reader.get(100),
+            Err(crate::Error::Records(Error::OutOfRange(100)))
+        ));
+        assert!(matches!(
+            reader.range(90..101),
+            Err(crate::Error::Records(Error::OutOfRange(101)))
+        ));
+        assert!(matches!(
+            RecordWriter::<_, Reading>::new(Vec::new(), 0),
+            Err(crate::Error::Records(Error::InvalidBlockSize))
+        ));
+    }
+
+    #[test]
+    fn corrupted_block() {
+        let mut bytes = sample_file(200);
+        // The first block starts right after the header
+        bytes[HEADER_SIZE + 4] ^= 0xff;
+
+        let reader = RecordReader::<Reading>::new(&bytes).unwrap();
+        assert!(matches!(
+            reader.get(3),
+            Err(crate::Error::Records(Error::ChecksumMismatch(0)))
+        ));
+        // Other blocks are unaffected
+        assert_eq!(reader.get(64).unwrap(), reading(64));
+    }
+
+    #[test]
+    fn seekable() {
+        let records: Vec<Reading> = (0..1000).map(reading).collect();
+        let bytes = huff_records(&records).unwrap();
+        let seekable = SeekableRecords::new(&bytes).unwrap();
+        assert_eq!(seekable.len(), 1000);
+        for n in [0, 1, 500, 999] {
+            assert_eq!(seekable.get::<Reading>(n).unwrap(), reading(n));
+        }
+        // Records too small to compress on their own compress with a shared tree
+        let separately: usize = records.iter().map(|r| crate::huff(r).unwrap().len()).sum();
+        assert!(bytes.len() < separately);
+
+        assert!(matches!(
+            seekable.get::<Reading>(1000),
+            Err(crate::Error::Records(Error::OutOfRange(1000)))
+        ));
+        assert!(matches!(
+            SeekableRecords::new(&bytes[..bytes.len() / 2]),
+            Err(crate::Error::Records(Error::CorruptIndex))
+        ));
+        assert!(matches!(
+            SeekableRecords::new(&bytes[..20]),
+            Err(crate::Error::Records(Error::Truncated))
+        ));
+
+        let empty = huff_records::<Reading>(&[]).unwrap();
+        assert!(SeekableRecords::new(&empty).unwrap().is_empty());
+        let units = huff_records(&[(), ()]).unwrap();
+        SeekableRecords::new(&units).unwrap().get::<()>(1).unwrap();
+
+        // A tree length past the end, where there's no index to be past the end as well
+        let mut unit = huff_records(&[()]).unwrap();
+        unit[13..17].copy_from_slice(&1000u32.to_le_bytes());
+        assert!(matches!(
+            SeekableRecords::new(&unit),
+            Err(crate::Error::Records(Error::Truncated))
+        ));
+    }
+
+    #[test]
+    fn invalid_files() {
+        let bytes = sample_file(10);
+        assert!(matches!(
+            RecordReader::<Reading>::new(&bytes[..HEADER_SIZE]),
+            Err(crate::Error::Records(Error::Truncated))
+        ));
+        assert!(matches!(
+            RecordReader::<Reading>::new(&bytes[..bytes.len() - 1]),
+            Err(crate::Error::Records(Error::BadMagic))
+        ));
+
+        let mut bad_offset = bytes.clone();
+        let footer_start = bad_offset.len() - FOOTER_SIZE;
+        bad_offset[footer_start..footer_start + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(matches!(
+            RecordReader::<Reading>::new(&bad_offset),
+            Err(crate::Error::Records(Error::CorruptIndex))
+        ));
+    }
+}
@@ -0,0 +1,33 @@
+//! Growth of the buffers that decoding fills. Memory is reserved with `try_reserve`, so running
+//! out of it is reported as [`Error::AllocationFailed`](crate::Error::AllocationFailed) rather
+//! than aborting the process.
+
+use std::collections::TryReserveError;
+
+/// Append `byte`, reserving more room first if `buffer` is full
+#[inline]
+pub(crate) fn push(buffer: &mut Vec<u8>, byte: u8) -> Result<(), TryReserveError> {
+    if buffer.len() == buffer.capacity() {
+        buffer.try_reserve(1)?;
+    }
+    buffer.push(byte);
+    Ok(())
+}
+
+/// Resize `buffer` to `len`, filling any new room with `value`
+pub(crate) fn resize<T: Clone>(
+    buffer: &mut Vec<T>,
+    len: usize,
+    value: T,
+) -> Result<(), TryReserveError> {
+    buffer.try_reserve(len.saturating_sub(buffer.len()))?;
+    buffer.resize(len, value);
+    Ok(())
+}
+
+/// An empty vector with room for exactly `len` elements
+pub(crate) fn with_capacity<T>(len: usize) -> Result<Vec<T>, TryReserveError> {
+    let mut buffer = Vec::new();
+    buffer.try_reserve_exact(len)?;
+    Ok(buffer)
+}
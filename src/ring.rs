@@ -0,0 +1,285 @@
+//! Decoding from and into memory split in two, like the halves of a ring buffer that wrapped
+//! around or the slices of a `VecDeque<u8>`, so the codec can work directly on DMA ring buffers
+//! without first copying their contents into one place. Encoding from split memory is done with
+//! [`huff_vectored`](crate::huff_vectored).
+
+use crate::bits::{BitOrder, BitReader};
+use crate::frame;
+
+/// The most bytes of a frame's header that are gathered from both halves of the input when the
+/// header is split between them
+const MAX_SPLIT_HEADER: usize = 256;
+
+/// Bytes split in two, read as if they were one slice
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct SplitBytes<'a> {
+    first: &'a [u8],
+    second: &'a [u8],
+}
+
+impl<'a> SplitBytes<'a> {
+    pub(crate) fn new(first: &'a [u8], second: &'a [u8]) -> Self {
+        // Keeping the bytes in `first` whenever there are any keeps most reads on one slice
+        match first.is_empty() {
+            true => Self {
+                first: second,
+                second: &[],
+            },
+            false => Self { first, second },
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.first.len() + self.second.len()
+    }
+
+    pub(crate) fn parts(&self) -> (&'a [u8], &'a [u8]) {
+        (self.first, self.second)
+    }
+
+    pub(crate) fn bits(&self, order: BitOrder) -> BitReader<'a> {
+        BitReader::split(self.first, self.second, order)
+    }
+
+    /// Skip `count` bytes, or all of them if there are fewer
+    pub(crate) fn advance(&mut self, count: usize) {
+        *self = match self.first.get(count..) {
+            Some(first) => Self::new(first, self.second),
+            None => Self::new(
+                &[],
+                self.second.get(count - self.first.len()..).unwrap_or(&[]),
+            ),
+        };
+    }
+
+    pub(crate) fn read_byte(&mut self) -> Option<u8> {
+        let byte = *self.first.first()?;
+        self.advance(1);
+        Some(byte)
+    }
+
+    /// Like [`varint::read`](crate::varint::read)
+    pub(crate) fn read_varint(&mut self) -> Option<u64> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self.read_byte()?;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Some(value);
+            }
+        }
+        None
+    }
+
+    /// Split off the first `count` bytes, or `None` if there are fewer
+    pub(crate) fn take(&mut self, count: usize) -> Option<Self> {
+        if count > self.len() {
+            return None;
+        }
+        let taken = match self.first.split_at_checked(count) {
+            Some((first, _)) => Self::new(first, &[]),
+            None => Self::new(self.first, &self.second[..count - self.first.len()]),
+        };
+        self.advance(count);
+        Some(taken)
+    }
+
+    /// Copy as many of the bytes as fit into the start of `out`, returning how many were copied
+    fn copy_prefix(&self, out: &mut [u8]) -> usize {
+        let mut len = 0;
+        for (slot, &byte) in out.iter_mut().zip(self.first.iter().chain(self.second)) {
+            *slot = byte;
+            len += 1;
+        }
+        len
+    }
+}
+
+/// Memory split in two that decoded bytes are written into, filling the first part and then the
+/// second. Writing past the end of both fails with [`MemoryLimit`](crate::Error::MemoryLimit).
+pub(crate) struct SplitOut<'a> {
+    first: &'a mut [u8],
+    second: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> SplitOut<'a> {
+    pub(crate) fn new(first: &'a mut [u8], second: &'a mut [u8]) -> Self {
+        Self {
+            first,
+            second,
+            len: 0,
+        }
+    }
+
+    /// The number of bytes written
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    /// The bytes written, in the two parts they were written to
+    pub(crate) fn written(&self) -> (&[u8], &[u8]) {
+        let split = self.len.min(self.first.len());
+        (&self.first[..split], &self.second[..self.len - split])
+    }
+
+    pub(crate) fn push(&mut self, byte: u8) -> Result<(), crate::Error> {
+        let slot = match self.first.get_mut(self.len) {
+            Some(slot) => slot,
+            None => self
+                .second
+                .get_mut(self.len - self.first.len())
+                .ok_or(crate::Error::MemoryLimit)?,
+        };
+        *slot = byte;
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Write `count` copies of `byte`
+    pub(crate) fn fill(&mut self, byte: u8, count: usize) -> Result<(), crate::Error> {
+        let (first, second) = self.reserve(count)?;
+        first.fill(byte);
+        second.fill(byte);
+        Ok(())
+    }
+
+    pub(crate) fn extend(&mut self, bytes: &[u8]) -> Result<(), crate::Error> {
+        let (first, second) = self.reserve(bytes.len())?;
+        let (head, tail) = bytes.split_at(first.len());
+        first.copy_from_slice(head);
+        second.copy_from_slice(tail);
+        Ok(())
+    }
+
+    /// The parts of the memory that the next `count` bytes go in, counting them as written
+    fn reserve(&mut self, count: usize) -> Result<(&mut [u8], &mut [u8]), crate::Error> {
+        let start = self.len;
+        let end = start
+            .checked_add(count)
+            .filter(|&end| end <= self.first.len() + self.second.len())
+            .ok_or(crate::Error::MemoryLimit)?;
+        self.len = end;
+
+        let split = self.first.len();
+        let first = &mut self.first[start.min(split)..end.min(split)];
+        let second = &mut self.second[start.saturating_sub(split)..end.saturating_sub(split)];
+        Ok((first, second))
+    }
+}
+
+/// Read the header at the start of `bytes`, gathering it from both parts if it's split between
+/// them. Returns the header and the number of bytes it occupies.
+pub(crate) fn read_header(bytes: SplitBytes) -> Result<(frame::Header, usize), crate::Error> {
+    match frame::read_header(bytes.first) {
+        Err(error) if error.is_truncation() && !bytes.second.is_empty() => {
+            let mut header = [0; MAX_SPLIT_HEADER];
+            let len = bytes.copy_prefix(&mut header);
+            frame::read_header(&header[..len])
+        }
+        header => header,
+    }
+}
+
+/// Decode the payload of the frame at the start of `input` into `out`, without allocating or
+/// copying either into one place first. `input` is the frame's bytes split in two, such as the
+/// halves of a ring buffer holding it, or the slices of a `VecDeque<u8>`, and the payload is
+/// written across the two parts of `out`, filling the first one first. Returns the length of the
+/// payload, and the number of bytes of `input` the frame occupied, by which a ring buffer's read
+/// position can be advanced.
+///
+/// The same frames can be decoded as by [`puff_into_slice`](crate::puff_into_slice). A frame's
+/// header can be split between the parts of `input` if it's no longer than 256 bytes, which it
+/// only is when it lists many checkpoints.
+///
+/// ```
+/// let compressed = huffnpuff::huff(&"a message received over DMA".repeat(4))?;
+/// // The frame wrapped around the end of the ring buffer
+/// let (head, tail) = compressed.split_at(10);
+/// let mut ring = [0; 128];
+/// let (front, back) = ring.split_at_mut(100);
+/// let (len, frame_len) = huffnpuff::decode_split((head, tail), (back, front))?;
+/// assert_eq!(frame_len, compressed.len());
+/// assert_eq!(&ring[100..108], &108u64.to_le_bytes());
+/// assert_eq!(len, 116);
+/// # Ok::<(), huffnpuff::Error>(())
+/// ```
+pub fn decode_split(
+    input: (&[u8], &[u8]),
+    out: (&mut [u8], &mut [u8]),
+) -> Result<(usize, usize), crate::Error> {
+    let mut out = SplitOut::new(out.0, out.1);
+    let (_, len) = frame::decode_into_split(SplitBytes::new(input.0, input.1), &mut out)?;
+    Ok((out.len(), len))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::io::IoSlice;
+
+    use super::*;
+
+    /// The bytes of `bytes` in a ring buffer of `capacity` bytes, starting at `start`, as the two
+    /// parts that hold them
+    fn wrapped(bytes: &[u8], capacity: usize, start: usize) -> VecDeque<u8> {
+        let mut ring = VecDeque::with_capacity(capacity);
+        ring.extend(std::iter::repeat_n(0, start));
+        for _ in 0..start {
+            ring.pop_front();
+        }
+        ring.extend(bytes);
+        ring
+    }
+
+    #[test]
+    fn every_split() {
+        let payload = b"a sensor frame, a sensor frame, another one".repeat(3);
+        let frames = [
+            crate::huff_vectored(&[IoSlice::new(&payload)]).unwrap(),
+            crate::huff_vectored(&[IoSlice::new(&[5; 300])]).unwrap(),
+            crate::huff_vectored(&[IoSlice::new(&(0..=255).collect::<Vec<u8>>())]).unwrap(),
+        ];
+        for frame in &frames {
+            let expected: Vec<u8> = crate::decode_iter(frame).map(Result::unwrap).collect();
+            for split in 0..=frame.len() {
+                let (head, tail) = frame.split_at(split);
+                // The payload fills the output from `at` to its end, then wraps to its start
+                let mut out = vec![0; expected.len()];
+                let at = split.min(expected.len());
+                let (front, back) = out.split_at_mut(at);
+                let (len, frame_len) = decode_split((head, tail), (back, front)).unwrap();
+                assert_eq!((len, frame_len), (expected.len(), frame.len()));
+                out.rotate_left(at);
+                assert_eq!(out, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn deque() {
+        let payload = b"logged while the ring buffer wrapped ".repeat(10);
+        let input = wrapped(&payload, 512, 400);
+        let (head, tail) = input.as_slices();
+        assert!(!tail.is_empty());
+        let compressed = crate::huff_vectored(&[IoSlice::new(head), IoSlice::new(tail)]).unwrap();
+
+        let mut received = wrapped(&compressed, 512, 500);
+        received.extend([0xaa; 3]);
+        let mut out = wrapped(&[0; 400], 512, 300);
+        let (out_head, out_tail) = out.as_mut_slices();
+        let (len, frame_len) = decode_split(received.as_slices(), (out_head, out_tail)).unwrap();
+        assert_eq!((len, frame_len), (payload.len(), compressed.len()));
+        assert_eq!(out.iter().take(len).copied().collect::<Vec<u8>>(), payload);
+
+        let (short_head, short_tail) = out.as_mut_slices();
+        let error = decode_split(
+            received.as_slices(),
+            (&mut short_head[..10], &mut short_tail[..10]),
+        );
+        assert_eq!(error, Err(crate::Error::MemoryLimit));
+        // Cut off in the tree
+        let truncated = wrapped(&compressed[..6], 512, 510);
+        assert!(decode_split(truncated.as_slices(), out.as_mut_slices()).is_err());
+    }
+}
@@ -0,0 +1,195 @@
+//! A compact encoding for degenerate inputs made of a few long runs of repeated bytes, such as
+//! zeroed pages or padding, which huffman coding can't shrink below one bit per byte.
+//!
+//! The message is the number of runs followed by each run's byte and length, with the counts
+//! stored as LEB128 varints.
+
+#[cfg(feature = "decode")]
+use crate::reserve;
+#[cfg(feature = "decode")]
+use crate::ring::{SplitBytes, SplitOut};
+use crate::varint;
+
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The data ended in the middle of the list of runs
+    Truncated,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Error::Truncated => "the data ends in the middle of the list of runs",
+        })
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Split `bytes` into runs of a repeated byte
+#[cfg(feature = "encode")]
+fn runs(bytes: &[u8]) -> Vec<(u8, u64)> {
+    let mut runs: Vec<(u8, u64)> = Vec::new();
+    for &byte in bytes {
+        match runs.last_mut() {
+            Some((symbol, len)) if *symbol == byte => *len += 1,
+            _ => runs.push((byte, 1)),
+        }
+    }
+    runs
+}
+
+/// Encode `bytes` as runs, but only if that is guaranteed to be smaller than huffman coding them,
+/// which takes at least one bit per byte
+#[cfg(feature = "encode")]
+pub(crate) fn encode_if_smaller(bytes: &[u8]) -> Option<Vec<u8>> {
+    let budget = bytes.len() / 8;
+    let mut out = Vec::new();
+    let runs = runs(bytes);
+    varint::write(runs.len() as u64, &mut out);
+    for (symbol, len) in runs {
+        out.push(symbol);
+        varint::write(len, &mut out);
+        if out.len() >= budget {
+            return None;
+        }
+    }
+    (out.len() < budget).then_some(out)
+}
+
+/// Decode the runs at the start of `bytes`, also returning the number of bytes they occupied
+#[cfg(feature = "decode")]
+fn read_runs(bytes: &[u8]) -> Result<(Vec<(u8, u64)>, usize), Error> {
+    let mut rest = bytes;
+    let count = varint::read(&mut rest).ok_or(Error::Truncated)?;
+    let mut runs = Vec::new();
+    for _ in 0..count {
+        let (&symbol, tail) = rest.split_first().ok_or(Error::Truncated)?;
+        rest = tail;
+        runs.push((symbol, varint::read(&mut rest).ok_or(Error::Truncated)?));
+    }
+    Ok((runs, bytes.len() - rest.len()))
+}
+
+/// Decode the runs at the start of `bytes`, also returning the number of bytes they occupied.
+/// Expanding stops once the output is longer than `max_len`, which callers treat as an error.
+#[cfg(feature = "decode")]
+pub(crate) fn decode_member(
+    bytes: &[u8],
+    max_len: usize,
+) -> Result<(Vec<u8>, usize), crate::Error> {
+    let (runs, len) = read_runs(bytes)?;
+    let mut decoded = Vec::new();
+    for (symbol, count) in runs {
+        let end = usize::try_from(count)
+            .ok()
+            .and_then(|count| decoded.len().checked_add(count))
+            .unwrap_or(usize::MAX)
+            .min(max_len.saturating_add(1));
+        reserve::resize(&mut decoded, end, symbol)?;
+        if decoded.len() > max_len {
+            break;
+        }
+    }
+    Ok((decoded, len))
+}
+
+/// Like [`decode_member`], but decodes into `out`, from a message that may be split in two,
+/// returning the number of bytes the message occupied. Nothing is allocated.
+#[cfg(feature = "decode")]
+pub(crate) fn decode_member_into_split(
+    bytes: SplitBytes,
+    out: &mut SplitOut,
+) -> Result<usize, crate::Error> {
+    let mut rest = bytes;
+    let count = rest.read_varint().ok_or(Error::Truncated)?;
+    for _ in 0..count {
+        let symbol = rest.read_byte().ok_or(Error::Truncated)?;
+        let run = rest.read_varint().ok_or(Error::Truncated)?;
+        let run = usize::try_from(run).map_err(|_| crate::Error::MemoryLimit)?;
+        out.fill(symbol, run)?;
+    }
+    Ok(bytes.len() - rest.len())
+}
+
+/// Lazily expands the runs of a message, see [`decode_iter`]
+#[cfg(feature = "decode")]
+pub(crate) struct DecodeIter {
+    runs: std::vec::IntoIter<(u8, u64)>,
+    current: Option<(u8, u64)>,
+}
+
+#[cfg(feature = "decode")]
+impl Iterator for DecodeIter {
+    type Item = u8;
+
+    fn next(&mut self) -> Option<u8> {
+        loop {
+            match &mut self.current {
+                Some((symbol, remaining)) if *remaining > 0 => {
+                    *remaining -= 1;
+                    return Some(*symbol);
+                }
+                _ => self.current = Some(self.runs.next()?),
+            }
+        }
+    }
+}
+
+/// Decode the list of runs in `bytes`, expanding them one byte at a time
+#[cfg(feature = "decode")]
+pub(crate) fn decode_iter(bytes: &[u8]) -> Result<DecodeIter, Error> {
+    let (runs, _) = read_runs(bytes)?;
+    Ok(DecodeIter {
+        runs: runs.into_iter(),
+        current: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zero_page() {
+        let zeros = vec![0u8; 1 << 20];
+        let encoded = encode_if_smaller(&zeros).unwrap();
+        assert_eq!(encoded, vec![1, 0, 0x80, 0x80, 0x40]);
+
+        let (decoded, len) = decode_member(&encoded, usize::MAX).unwrap();
+        assert_eq!(decoded, zeros);
+        assert_eq!(len, encoded.len());
+        assert!(decode_iter(&encoded).unwrap().eq(zeros));
+    }
+
+    #[test]
+    fn only_when_smaller() {
+        assert!(encode_if_smaller(b"not repetitive at all").is_none());
+        assert!(encode_if_smaller(&[7; 8]).is_none());
+
+        let mut mostly_runs = vec![1u8; 1000];
+        mostly_runs.extend_from_slice(&[2; 1000]);
+        let encoded = encode_if_smaller(&mostly_runs).unwrap();
+        assert_eq!(decode_member(&encoded, usize::MAX).unwrap().0, mostly_runs);
+    }
+
+    #[test]
+    fn truncated() {
+        let encoded = encode_if_smaller(&[3; 4096]).unwrap();
+        for len in 0..encoded.len() {
+            assert!(decode_member(&encoded[..len], usize::MAX).is_err());
+        }
+    }
+
+    #[test]
+    fn allocation_failed() {
+        // A single run far longer than could ever be allocated
+        let mut encoded = vec![1, 0];
+        varint::write(u64::MAX, &mut encoded);
+        assert_eq!(
+            decode_member(&encoded, usize::MAX),
+            Err(crate::Error::AllocationFailed)
+        );
+    }
+}
@@ -0,0 +1,233 @@
+//! Versioned values, for data like save games and config files that outlive the types they were
+//! written with. The encoder stamps the value with the version of its type's layout, and the
+//! decoder converts values written with older layouts using migrations registered for them.
+//!
+//! ```
+//! use huffnpuff::{Migrations, huff_versioned, puff_versioned};
+//! use serde::{Deserialize, Serialize};
+//!
+//! #[derive(Serialize, Deserialize)]
+//! struct SaveV1 {
+//!     name: String,
+//! }
+//!
+//! #[derive(Debug, PartialEq, Serialize, Deserialize)]
+//! struct Save {
+//!     name: String,
+//!     level: u32,
+//! }
+//!
+//! let old = huff_versioned(&SaveV1 { name: "ada".into() }, 1).unwrap();
+//!
+//! let migrations = Migrations::new(2).migrate(1, |old: SaveV1| Save {
+//!     name: old.name,
+//!     level: 1,
+//! });
+//! let save: Save = puff_versioned(&old, &migrations).unwrap();
+//! assert_eq!(save, Save { name: "ada".into(), level: 1 });
+//! ```
+
+#[cfg(feature = "decode")]
+use std::collections::BTreeMap;
+
+use crate::frame;
+#[cfg(feature = "encode")]
+use crate::options::EncodeOptions;
+
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The value was written with this version, which is older than the current one and has no
+    /// migration
+    NoMigration(u32),
+    /// The value was written with this version, which is newer than the current one
+    NewerVersion(u32),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::NoMigration(version) => {
+                write!(f, "there is no migration from version {version}")
+            }
+            Error::NewerVersion(version) => {
+                write!(f, "the value was written with newer version {version}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Compress a value stamped with `version`, the version of the layout of its type, to be decoded
+/// with [`puff_versioned`]
+#[cfg(feature = "encode")]
+pub fn huff_versioned<T>(value: &T, version: u32) -> Result<Vec<u8>, crate::Error>
+where
+    T: serde::Serialize,
+{
+    let bincoded_bytes = bincode::serialize(value)?;
+    let header = frame::Header {
+        schema_version: Some(version),
+        ..frame::Header::default()
+    };
+    frame::encode(&bincoded_bytes, &header, &EncodeOptions::default())
+}
+
+#[cfg(feature = "decode")]
+type Migration<T> = Box<dyn Fn(&[u8]) -> Result<T, crate::Error> + Send + Sync>;
+
+/// The current version of `T`, and how to convert values written with older versions to it
+#[cfg(feature = "decode")]
+pub struct Migrations<T> {
+    current: u32,
+    migrations: BTreeMap<u32, Migration<T>>,
+}
+
+#[cfg(feature = "decode")]
+impl<T> Migrations<T> {
+    /// Migrations for a type whose current layout has version `current`
+    pub fn new(current: u32) -> Self {
+        Self {
+            current,
+            migrations: BTreeMap::new(),
+        }
+    }
+
+    /// Convert values written with `version`, which are decoded as the type they were written
+    /// with, `Old`, and then passed to `migration`. Values without a version, such as those
+    /// compressed with [`huff`](crate::huff), count as version 0.
+    pub fn migrate<Old, F>(mut self, version: u32, migration: F) -> Self
+    where
+        Old: serde::de::DeserializeOwned,
+        F: Fn(Old) -> T + Send + Sync + 'static,
+    {
+        let migration = move |bytes: &[u8]| Ok(migration(bincode::deserialize(bytes)?));
+        self.migrations.insert(version, Box::new(migration));
+        self
+    }
+
+    pub fn current(&self) -> u32 {
+        self.current
+    }
+}
+
+/// Decode a value compressed with [`huff_versioned`], migrating it to the current version of `T`
+/// if it was written with an older one
+#[cfg(feature = "decode")]
+pub fn puff_versioned<T>(bytes: &[u8], migrations: &Migrations<T>) -> Result<T, crate::Error>
+where
+    T: serde::de::DeserializeOwned,
+{
+    let frame = frame::decode(bytes)?;
+    let version = frame.header.schema_version.unwrap_or(0);
+    if version == migrations.current {
+        return Ok(bincode::deserialize(&frame.payload)?);
+    }
+    if version > migrations.current {
+        return Err(Error::NewerVersion(version).into());
+    }
+    let migration = migrations
+        .migrations
+        .get(&version)
+        .ok_or(Error::NoMigration(version))?;
+    migration(&frame.payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+
+    #[derive(Serialize, Deserialize)]
+    struct ConfigV0 {
+        volume: u8,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct ConfigV1 {
+        volume: u8,
+        muted: bool,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Config {
+        volume: f32,
+        muted: bool,
+        theme: String,
+    }
+
+    fn migrations() -> Migrations<Config> {
+        Migrations::new(2)
+            .migrate(0, |old: ConfigV0| Config {
+                volume: old.volume as f32 / 100.0,
+                muted: false,
+                theme: "light".to_owned(),
+            })
+            .migrate(1, |old: ConfigV1| Config {
+                volume: old.volume as f32 / 100.0,
+                muted: old.muted,
+                theme: "light".to_owned(),
+            })
+    }
+
+    #[test]
+    fn migrates_old_versions() {
+        let unversioned = crate::huff(&ConfigV0 { volume: 50 }).unwrap();
+        let v1 = huff_versioned(
+            &ConfigV1 {
+                volume: 20,
+                muted: true,
+            },
+            1,
+        )
+        .unwrap();
+        let current = Config {
+            volume: 0.8,
+            muted: false,
+            theme: "dark".to_owned(),
+        };
+        let v2 = huff_versioned(&current, 2).unwrap();
+
+        let migrations = migrations();
+        assert_eq!(migrations.current(), 2);
+        assert_eq!(
+            puff_versioned(&unversioned, &migrations).unwrap(),
+            Config {
+                volume: 0.5,
+                muted: false,
+                theme: "light".to_owned(),
+            }
+        );
+        assert_eq!(
+            puff_versioned(&v1, &migrations).unwrap(),
+            Config {
+                volume: 0.2,
+                muted: true,
+                theme: "light".to_owned(),
+            }
+        );
+        assert_eq!(puff_versioned(&v2, &migrations).unwrap(), current);
+
+        // The version survives recompression
+        let recompressed = crate::recompress(&v1, &EncodeOptions::default()).unwrap();
+        assert!(puff_versioned(&recompressed, &migrations).unwrap().muted);
+    }
+
+    #[test]
+    fn unknown_versions() {
+        let v3 = huff_versioned(&(1u8, true, 2u64), 3).unwrap();
+        assert_eq!(
+            puff_versioned(&v3, &migrations()),
+            Err(crate::Error::Schema(Error::NewerVersion(3)))
+        );
+
+        let without = Migrations::<Config>::new(2);
+        let v1 = huff_versioned(&(20u8, true), 1).unwrap();
+        assert_eq!(
+            puff_versioned(&v1, &without),
+            Err(crate::Error::Schema(Error::NoMigration(1)))
+        );
+    }
+}
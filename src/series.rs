@@ -0,0 +1,466 @@
+//! A file of timestamped samples, such as sensor readings or events, that can be read back by
+//! time. Samples are appended in time order and grouped into blocks of a fixed number of samples,
+//! each compressed on its own. An index at the end of the file records where each block starts and
+//! the first and last timestamps in it, so reading a span of time only decompresses the blocks
+//! that overlap it.
+//!
+//! Within a block each timestamp is stored as a varint difference from the one before it, followed
+//! by the sample's value.
+
+#[cfg(feature = "encode")]
+use std::io::Write;
+use std::marker::PhantomData;
+#[cfg(feature = "decode")]
+use std::ops::{Range, RangeInclusive};
+
+use serde::{Deserialize, Serialize};
+
+use crate::frame;
+#[cfg(feature = "encode")]
+use crate::options::EncodeOptions;
+use crate::varint;
+
+const MAGIC: &[u8; 4] = b"HNPT";
+const VERSION: u8 = 1;
+const HEADER_SIZE: usize = MAGIC.len() + 1;
+/// The offset of the index followed by the magic bytes again, so the index can be located from
+/// the end of the file
+#[cfg(feature = "decode")]
+const FOOTER_SIZE: usize = 8 + MAGIC.len();
+
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The data does not look like a time series file produced by huffnpuff
+    BadMagic,
+    /// The file was written with a format version this library doesn't understand
+    UnsupportedVersion(u8),
+    /// The number of samples per block must be greater than zero
+    InvalidBlockSize,
+    /// A sample was appended with this timestamp, which is earlier than the sample before it
+    OutOfOrder(u64),
+    /// The file is too short to contain a header, index and footer
+    Truncated,
+    /// The index points outside the file, its timestamps are out of order, or it could not be
+    /// decoded
+    CorruptIndex,
+    /// The stored checksum for the block with this index doesn't match its data
+    ChecksumMismatch(usize),
+    /// The block with this index doesn't hold the samples the index describes
+    CorruptBlock(usize),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Error::BadMagic => "the data is not a time series file",
+            Error::UnsupportedVersion(version) => {
+                return write!(
+                    f,
+                    "the time series file has unsupported format version {version}"
+                );
+            }
+            Error::InvalidBlockSize => "the number of samples per block must be greater than zero",
+            Error::OutOfOrder(timestamp) => {
+                return write!(
+                    f,
+                    "the sample at {timestamp} is earlier than the sample before it"
+                );
+            }
+            Error::Truncated => "the time series file is too short to hold its index",
+            Error::CorruptIndex => "the time series file's index is damaged",
+            Error::ChecksumMismatch(index) => {
+                return write!(f, "block {index} doesn't match its checksum");
+            }
+            Error::CorruptBlock(index) => {
+                return write!(
+                    f,
+                    "block {index} doesn't hold the samples the index describes"
+                );
+            }
+        })
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct BlockEntry {
+    offset: u64,
+    len: u64,
+    checksum: u32,
+    first: u64,
+    last: u64,
+    samples: u64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Index {
+    blocks: Vec<BlockEntry>,
+}
+
+/// Appends timestamped samples to a time series file, compressing them a block at a time.
+/// Timestamps are in whatever unit the caller chooses, and must never decrease. Samples are
+/// buffered until a block is full, and [`SeriesWriter::finish`] must be called to write the final
+/// partial block and the index.
+#[cfg(feature = "encode")]
+pub struct SeriesWriter<W: Write, T> {
+    writer: W,
+    position: u64,
+    samples_per_block: usize,
+    buffer: Vec<u8>,
+    /// The first and last timestamps of the samples buffered, and how many there are
+    first: u64,
+    last: Option<u64>,
+    buffered: usize,
+    index: Index,
+    len: usize,
+    samples: PhantomData<fn(&T)>,
+}
+
+#[cfg(feature = "encode")]
+impl<W: Write, T: Serialize> SeriesWriter<W, T> {
+    /// Start a time series file, writing its header to `writer`. Larger blocks compress better,
+    /// but reading any span of time decompresses every block that overlaps it in full.
+    pub fn new(mut writer: W, samples_per_block: usize) -> Result<Self, crate::Error> {
+        if samples_per_block == 0 {
+            return Err(Error::InvalidBlockSize.into());
+        }
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[VERSION])?;
+        Ok(Self {
+            writer,
+            position: HEADER_SIZE as u64,
+            samples_per_block,
+            buffer: Vec::new(),
+            first: 0,
+            last: None,
+            buffered: 0,
+            index: Index::default(),
+            len: 0,
+            samples: PhantomData,
+        })
+    }
+
+    /// Add a sample to the end of the file. Its timestamp may equal that of the sample before
+    /// it, but not be earlier.
+    pub fn append(&mut self, timestamp: u64, value: &T) -> Result<(), crate::Error> {
+        let previous = match (self.last, self.index.blocks.last()) {
+            (Some(last), _) => last,
+            (None, Some(block)) => block.last,
+            (None, None) => timestamp,
+        };
+        if timestamp < previous {
+            return Err(Error::OutOfOrder(timestamp).into());
+        }
+        if self.buffered == 0 {
+            self.first = timestamp;
+        }
+        let delta = timestamp - self.last.unwrap_or(self.first);
+        varint::write(delta, &mut self.buffer);
+        bincode::serialize_into(&mut self.buffer, value)?;
+        self.last = Some(timestamp);
+        self.buffered += 1;
+        self.len += 1;
+        if self.buffered == self.samples_per_block {
+            self.write_block()?;
+        }
+        Ok(())
+    }
+
+    /// The number of samples appended so far
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Compress and write out the buffered block
+    fn write_block(&mut self) -> Result<(), crate::Error> {
+        let Some(last) = self.last else {
+            return Ok(());
+        };
+        let compressed = frame::encode(
+            &self.buffer,
+            &frame::Header::default(),
+            &EncodeOptions::default(),
+        )?;
+        self.writer.write_all(&compressed)?;
+
+        self.index.blocks.push(BlockEntry {
+            offset: self.position,
+            len: compressed.len() as u64,
+            checksum: crc32fast::hash(&compressed),
+            first: self.first,
+            last,
+            samples: self.buffered as u64,
+        });
+        self.position += compressed.len() as u64;
+        self.buffer.clear();
+        self.last = None;
+        self.buffered = 0;
+        Ok(())
+    }
+
+    /// Write the final block, the index and the footer, returning the underlying writer
+    pub fn finish(mut self) -> Result<W, crate::Error> {
+        self.write_block()?;
+        let index = bincode::serialize(&self.index)?;
+        self.writer.write_all(&index)?;
+        self.writer.write_all(&self.position.to_le_bytes())?;
+        self.writer.write_all(MAGIC)?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// Reads spans of time from a file produced by [`SeriesWriter`]. Only the index is decoded up
+/// front; blocks are checked and decompressed when a span that overlaps them is read.
+#[cfg(feature = "decode")]
+pub struct SeriesReader<'a, T> {
+    bytes: &'a [u8],
+    index: Index,
+    len: usize,
+    samples: PhantomData<fn() -> T>,
+}
+
+#[cfg(feature = "decode")]
+impl<'a, T: serde::de::DeserializeOwned> SeriesReader<'a, T> {
+    pub fn new(bytes: &'a [u8]) -> Result<Self, crate::Error> {
+        if bytes.len() < HEADER_SIZE + FOOTER_SIZE {
+            return Err(Error::Truncated.into());
+        }
+        if &bytes[..MAGIC.len()] != MAGIC {
+            return Err(Error::BadMagic.into());
+        }
+        if bytes[MAGIC.len()] != VERSION {
+            return Err(Error::UnsupportedVersion(bytes[MAGIC.len()]).into());
+        }
+
+        let footer = &bytes[bytes.len() - FOOTER_SIZE..];
+        if &footer[8..] != MAGIC {
+            return Err(Error::BadMagic.into());
+        }
+        let index_offset = u64::from_le_bytes(footer[..8].try_into().unwrap());
+        let index_end = (bytes.len() - FOOTER_SIZE) as u64;
+        if index_offset < HEADER_SIZE as u64 || index_offset > index_end {
+            return Err(Error::CorruptIndex.into());
+        }
+        let index: Index = bincode::deserialize(&bytes[index_offset as usize..index_end as usize])
+            .map_err(|_| Error::CorruptIndex)?;
+
+        // Blocks must follow one another in time, so the ones overlapping a span can be found by
+        // binary search
+        for (i, block) in index.blocks.iter().enumerate() {
+            let in_bounds = block
+                .offset
+                .checked_add(block.len)
+                .is_some_and(|end| block.offset >= HEADER_SIZE as u64 && end <= index_offset);
+            let ordered = block.first <= block.last
+                && index.blocks[..i]
+                    .last()
+                    .is_none_or(|before| before.last <= block.first);
+            if !in_bounds || !ordered || block.samples == 0 {
+                return Err(Error::CorruptIndex.into());
+            }
+        }
+        let len = index
+            .blocks
+            .iter()
+            .try_fold(0usize, |len, block| {
+                len.checked_add(usize::try_from(block.samples).ok()?)
+            })
+            .ok_or(Error::CorruptIndex)?;
+
+        Ok(Self {
+            bytes,
+            index,
+            len,
+            samples: PhantomData,
+        })
+    }
+
+    /// The number of samples in the file
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The timestamps of the first and last samples, if there are any
+    pub fn time_range(&self) -> Option<RangeInclusive<u64>> {
+        let first = self.index.blocks.first()?.first;
+        let last = self.index.blocks.last()?.last;
+        Some(first..=last)
+    }
+
+    /// Decompress the samples with timestamps in `range`, in the order they were appended, with
+    /// their timestamps. Only the blocks that overlap the range are decompressed.
+    pub fn read_range(&self, range: Range<u64>) -> Result<Vec<(u64, T)>, crate::Error> {
+        let start = self
+            .index
+            .blocks
+            .partition_point(|block| block.last < range.start);
+        let mut samples = Vec::new();
+        for (i, block) in self.index.blocks.iter().enumerate().skip(start) {
+            if block.first >= range.end {
+                break;
+            }
+            for (timestamp, value) in self.block(i)? {
+                if range.contains(&timestamp) {
+                    samples.push((timestamp, value));
+                }
+            }
+        }
+        Ok(samples)
+    }
+
+    /// Check and decompress a block
+    fn block(&self, i: usize) -> Result<Vec<(u64, T)>, crate::Error> {
+        let entry = &self.index.blocks[i];
+        // Bounds were checked when the index was read
+        let start = entry.offset as usize;
+        let compressed = &self.bytes[start..start + entry.len as usize];
+        if crc32fast::hash(compressed) != entry.checksum {
+            return Err(Error::ChecksumMismatch(i).into());
+        }
+        let payload = frame::decode(compressed)?.payload;
+
+        let mut rest = payload.as_slice();
+        let mut timestamp = entry.first;
+        let mut samples = Vec::new();
+        while !rest.is_empty() {
+            timestamp = varint::read(&mut rest)
+                .and_then(|delta| timestamp.checked_add(delta))
+                .ok_or(Error::CorruptBlock(i))?;
+            samples.push((timestamp, bincode::deserialize_from(&mut rest)?));
+        }
+        if samples.len() as u64 != entry.samples || timestamp != entry.last {
+            return Err(Error::CorruptBlock(i).into());
+        }
+        Ok(samples)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Reading {
+        sensor: String,
+        celsius: f32,
+    }
+
+    /// A reading every 250ms, with a burst of readings sharing a timestamp at 100s
+    fn readings(count: u64) -> Vec<(u64, Reading)> {
+        (0..count)
+            .map(|i| {
+                let timestamp = match i {
+                    400..410 => 100_000,
+                    _ => i * 250,
+                };
+                let reading = Reading {
+                    sensor: format!("probe-{}", i % 3),
+                    celsius: 20.0 + (i % 40) as f32 * 0.1,
+                };
+                (timestamp, reading)
+            })
+            .collect()
+    }
+
+    fn sample_file(samples: &[(u64, Reading)]) -> Vec<u8> {
+        let mut writer = SeriesWriter::new(Vec::new(), 64).unwrap();
+        for (timestamp, reading) in samples {
+            writer.append(*timestamp, reading).unwrap();
+        }
+        assert_eq!(writer.len(), samples.len());
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn read_range() {
+        let samples = readings(1000);
+        let bytes = sample_file(&samples);
+        let reader = SeriesReader::<Reading>::new(&bytes).unwrap();
+        assert_eq!(reader.len(), 1000);
+        assert_eq!(reader.time_range(), Some(0..=999 * 250));
+
+        for range in [
+            0..1,
+            1000..20_000,
+            99_000..100_001,
+            0..u64::MAX,
+            300_000..400_000,
+        ] {
+            let expected: Vec<_> = samples
+                .iter()
+                .filter(|(timestamp, _)| range.contains(timestamp))
+                .cloned()
+                .collect();
+            assert_eq!(reader.read_range(range).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn only_overlapping_blocks_are_decoded() {
+        let mut bytes = sample_file(&readings(1000));
+        // Corrupt the first block, which holds the first 64 readings
+        bytes[HEADER_SIZE + 4] ^= 0xff;
+        let reader = SeriesReader::<Reading>::new(&bytes).unwrap();
+        assert_eq!(reader.read_range(64 * 250..65 * 250).unwrap().len(), 1);
+        assert!(matches!(
+            reader.read_range(0..65 * 250),
+            Err(crate::Error::Series(Error::ChecksumMismatch(0)))
+        ));
+    }
+
+    #[test]
+    fn compresses() {
+        let samples = readings(1000);
+        let raw: usize = samples
+            .iter()
+            .map(|sample| bincode::serialized_size(sample).unwrap() as usize)
+            .sum();
+        assert!(sample_file(&samples).len() * 2 < raw);
+    }
+
+    #[test]
+    fn empty() {
+        let bytes = sample_file(&[]);
+        let reader = SeriesReader::<Reading>::new(&bytes).unwrap();
+        assert!(reader.is_empty());
+        assert_eq!(reader.time_range(), None);
+        assert!(reader.read_range(0..u64::MAX).unwrap().is_empty());
+    }
+
+    #[test]
+    fn errors() {
+        let mut writer = SeriesWriter::new(Vec::new(), 2).unwrap();
+        for timestamp in [5, 5, 6] {
+            writer.append(timestamp, &()).unwrap();
+        }
+        assert!(matches!(
+            writer.append(4, &()),
+            Err(crate::Error::Series(Error::OutOfOrder(4)))
+        ));
+        assert!(matches!(
+            SeriesWriter::<_, ()>::new(Vec::new(), 0),
+            Err(crate::Error::Series(Error::InvalidBlockSize))
+        ));
+
+        let bytes = sample_file(&readings(10));
+        assert!(matches!(
+            SeriesReader::<Reading>::new(&bytes[..HEADER_SIZE]),
+            Err(crate::Error::Series(Error::Truncated))
+        ));
+        assert!(matches!(
+            SeriesReader::<Reading>::new(&bytes[..bytes.len() - 1]),
+            Err(crate::Error::Series(Error::BadMagic))
+        ));
+    }
+}
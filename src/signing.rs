@@ -0,0 +1,237 @@
+//! Ed25519 signatures over compressed values, for data like firmware images that must be shown
+//! to come from a known sender.
+//!
+//! A signed container is a small header followed by an ordinary frame:
+//!
+//! | bytes | contents                                                   |
+//! |-------|------------------------------------------------------------|
+//! | 4     | `HNPS`                                                     |
+//! | 1     | version                                                    |
+//! | 64    | signature                                                  |
+//! | 64    | SHA-512 digest of the serialized value before compression  |
+//! | rest  | the frame                                                  |
+//!
+//! The signature is Ed25519ph, over the SHA-512 hash of the digest followed by the frame. Since
+//! the signature and digest come first, a receiver can hash the frame as it arrives and check the
+//! signature as soon as the last byte is in, without holding on to the whole container.
+
+use ed25519_dalek::{Digest, Sha512, Signature, SigningKey, VerifyingKey};
+
+use crate::frame;
+#[cfg(feature = "encode")]
+use crate::options::EncodeOptions;
+
+const MAGIC: &[u8; 4] = b"HNPS";
+const VERSION: u8 = 1;
+#[cfg(feature = "encode")]
+const HEADER_SIZE: usize = MAGIC.len() + 1 + 64 + 64;
+
+/// Keeps signatures made for this crate from being valid for anything else signed with the same
+/// key, and the embedded and detached signatures from being swapped for each other
+const EMBEDDED_CONTEXT: &[u8] = b"huffnpuff signed container";
+const DETACHED_CONTEXT: &[u8] = b"huffnpuff detached signature";
+
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The data doesn't start with the header of a signed container
+    NotSigned,
+    /// The container was written by a newer version of this library
+    UnsupportedVersion(u8),
+    /// The public key isn't a valid Ed25519 key
+    InvalidKey,
+    /// The signature doesn't match the data and key
+    BadSignature,
+    /// The decompressed value doesn't match the digest that was signed
+    DigestMismatch,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Error::NotSigned => "the data is not a signed container",
+            Error::UnsupportedVersion(version) => {
+                return write!(
+                    f,
+                    "the signed container has unsupported format version {version}"
+                );
+            }
+            Error::InvalidKey => "the public key isn't a valid Ed25519 key",
+            Error::BadSignature => "the signature doesn't match the data and key",
+            Error::DigestMismatch => "the decompressed value doesn't match the signed digest",
+        })
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Compress a value and sign it with the Ed25519 secret key `key`. The result is decoded with
+/// [`puff_verified`] and the matching public key.
+#[cfg(feature = "encode")]
+pub fn huff_signed<T>(value: &T, key: &[u8; 32]) -> Result<Vec<u8>, crate::Error>
+where
+    T: serde::Serialize,
+{
+    let bincoded_bytes = bincode::serialize(value)?;
+    let frame = frame::encode(
+        &bincoded_bytes,
+        &frame::Header::default(),
+        &EncodeOptions::default(),
+    )?;
+    let digest = Sha512::digest(&bincoded_bytes);
+
+    let signature = SigningKey::from_bytes(key)
+        .sign_prehashed(prehash(&digest, &frame), Some(EMBEDDED_CONTEXT))
+        .expect("the context is short enough");
+
+    let mut out = Vec::with_capacity(HEADER_SIZE + frame.len());
+    out.extend_from_slice(MAGIC);
+    out.push(VERSION);
+    out.extend_from_slice(&signature.to_bytes());
+    out.extend_from_slice(&digest);
+    out.extend_from_slice(&frame);
+    Ok(out)
+}
+
+/// Check the signature of a container produced by [`huff_signed`] against the public key `key`,
+/// then decode it. Nothing is decompressed unless the signature is valid.
+#[cfg(feature = "decode")]
+pub fn puff_verified<T>(bytes: &[u8], key: &[u8; 32]) -> Result<T, crate::Error>
+where
+    T: serde::de::DeserializeOwned,
+{
+    let rest = bytes.strip_prefix(MAGIC).ok_or(Error::NotSigned)?;
+    let (&version, rest) = rest.split_first().ok_or(Error::NotSigned)?;
+    if version != VERSION {
+        return Err(Error::UnsupportedVersion(version).into());
+    }
+    let (signature, rest) = rest.split_first_chunk::<64>().ok_or(Error::NotSigned)?;
+    let (digest, frame) = rest.split_first_chunk::<64>().ok_or(Error::NotSigned)?;
+
+    verifying_key(key)?
+        .verify_prehashed_strict(
+            prehash(digest, frame),
+            Some(EMBEDDED_CONTEXT),
+            &Signature::from_bytes(signature),
+        )
+        .map_err(|_| Error::BadSignature)?;
+
+    let frame = frame::decode(frame)?;
+    if Sha512::digest(&frame.payload).as_slice() != digest {
+        return Err(Error::DigestMismatch.into());
+    }
+    crate::deserialize_frame(&frame, std::any::type_name::<T>())
+}
+
+/// Sign data that's stored or sent separately from its signature, such as the output of
+/// [`huff`](crate::huff), with the Ed25519 secret key `key`
+pub fn sign_detached(bytes: &[u8], key: &[u8; 32]) -> [u8; 64] {
+    SigningKey::from_bytes(key)
+        .sign_prehashed(Sha512::new().chain_update(bytes), Some(DETACHED_CONTEXT))
+        .expect("the context is short enough")
+        .to_bytes()
+}
+
+/// Check a signature made by [`sign_detached`] against the public key `key`
+pub fn verify_detached(
+    bytes: &[u8],
+    signature: &[u8; 64],
+    key: &[u8; 32],
+) -> Result<(), crate::Error> {
+    verifying_key(key)?
+        .verify_prehashed_strict(
+            Sha512::new().chain_update(bytes),
+            Some(DETACHED_CONTEXT),
+            &Signature::from_bytes(signature),
+        )
+        .map_err(|_| Error::BadSignature.into())
+}
+
+/// The public key that goes with the secret key `key`
+pub fn public_key(key: &[u8; 32]) -> [u8; 32] {
+    SigningKey::from_bytes(key).verifying_key().to_bytes()
+}
+
+fn prehash(digest: &[u8], frame: &[u8]) -> Sha512 {
+    Sha512::new().chain_update(digest).chain_update(frame)
+}
+
+fn verifying_key(key: &[u8; 32]) -> Result<VerifyingKey, Error> {
+    VerifyingKey::from_bytes(key).map_err(|_| Error::InvalidKey)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: [u8; 32] = [3; 32];
+
+    #[test]
+    fn roundtrip() {
+        let image = vec![0xa5u8; 4096];
+        let signed = huff_signed(&image, &SECRET).unwrap();
+        assert!(signed.len() < 1024);
+        assert_eq!(
+            puff_verified::<Vec<u8>>(&signed, &public_key(&SECRET)).unwrap(),
+            image
+        );
+    }
+
+    #[test]
+    fn tampering_and_wrong_key() {
+        let signed = huff_signed(&"firmware v1.2.3".to_owned(), &SECRET).unwrap();
+        let public = public_key(&SECRET);
+
+        for i in MAGIC.len() + 1..signed.len() {
+            let mut tampered = signed.clone();
+            tampered[i] ^= 1;
+            assert!(matches!(
+                puff_verified::<String>(&tampered, &public),
+                Err(crate::Error::Signing(Error::BadSignature))
+            ));
+        }
+        assert!(matches!(
+            puff_verified::<String>(&signed, &public_key(&[4; 32])),
+            Err(crate::Error::Signing(Error::BadSignature))
+        ));
+        assert!(matches!(
+            puff_verified::<String>(&signed[..signed.len() - 1], &public),
+            Err(crate::Error::Signing(Error::BadSignature))
+        ));
+    }
+
+    #[test]
+    fn not_signed() {
+        let plain = crate::huff(&7u32).unwrap();
+        assert!(matches!(
+            puff_verified::<u32>(&plain, &public_key(&SECRET)),
+            Err(crate::Error::Signing(Error::NotSigned))
+        ));
+    }
+
+    #[test]
+    fn detached() {
+        let compressed = crate::huff(&"release notes").unwrap();
+        let signature = sign_detached(&compressed, &SECRET);
+        let public = public_key(&SECRET);
+        assert!(verify_detached(&compressed, &signature, &public).is_ok());
+
+        let mut tampered = compressed.clone();
+        tampered[0] ^= 1;
+        assert!(matches!(
+            verify_detached(&tampered, &signature, &public),
+            Err(crate::Error::Signing(Error::BadSignature))
+        ));
+
+        // A detached signature can't stand in for an embedded one
+        let signed = huff_signed(&"release notes", &SECRET).unwrap();
+        let frame = &signed[HEADER_SIZE..];
+        let mut forged = signed[..MAGIC.len() + 1].to_vec();
+        forged.extend_from_slice(&sign_detached(frame, &SECRET));
+        forged.extend_from_slice(&signed[MAGIC.len() + 1 + 64..]);
+        assert!(matches!(
+            puff_verified::<String>(&forged, &public),
+            Err(crate::Error::Signing(Error::BadSignature))
+        ));
+    }
+}
@@ -0,0 +1,579 @@
+//! Encoding into and decoding from storage the caller provides, such as a stack array, a DMA
+//! buffer or memory from an arena, without allocating. The value is serialized twice: once to
+//! count its bytes and choose their codes, and once more to code them straight into the output.
+//! Working memory is a few fixed size arrays on the stack. Decoding walks the tree where it lies in
+//! the input, and the value is deserialized from the caller's buffer, so it can borrow from it.
+//!
+//! With the `allocator-api` feature, [`huff_in`] and [`decompress_in`] do the same into vectors
+//! from any [`Allocator`], for callers that would rather hand over an arena than size a buffer.
+
+#[cfg(feature = "allocator-api")]
+use allocator_api2::alloc::Allocator;
+#[cfg(feature = "allocator-api")]
+use allocator_api2::vec::Vec as AllocVec;
+#[cfg(feature = "encode")]
+use std::io::{self, Write};
+
+use crate::frame;
+#[cfg(feature = "encode")]
+use crate::frame::Encoding;
+#[cfg(feature = "encode")]
+use crate::options::EncodeOptions;
+#[cfg(feature = "encode")]
+use crate::varint;
+
+/// Every byte value, then end of message
+#[cfg(feature = "encode")]
+const SYMBOLS: usize = 257;
+#[cfg(feature = "encode")]
+const EOM: usize = 256;
+/// The bits a serialized tree uses for the value of each leaf: an end of message flag, then the
+/// byte
+#[cfg(feature = "encode")]
+const VALUE_BITS: usize = 9;
+
+/// Counts and checksums the bytes written to it, without keeping them
+#[cfg(feature = "encode")]
+struct Histogram {
+    counts: [u64; 256],
+    len: usize,
+    hasher: crc32fast::Hasher,
+}
+
+#[cfg(feature = "encode")]
+impl Write for Histogram {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for &byte in buf {
+            self.counts[byte as usize] += 1;
+        }
+        self.len += buf.len();
+        self.hasher.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The huffman code length of every byte value that occurs, and of end of message, which is
+/// counted as never occurring. Values that don't occur have no code, and a length of zero.
+#[cfg(feature = "encode")]
+fn code_lengths(counts: &[u64; 256]) -> [u8; SYMBOLS] {
+    // The first `SYMBOLS` nodes are leaves, and each merge adds an inner node after them, so a
+    // parent of zero marks the root
+    let mut weight = [0u64; 2 * SYMBOLS];
+    let mut parent = [0usize; 2 * SYMBOLS];
+    let mut alive = [false; 2 * SYMBOLS];
+    weight[..256].copy_from_slice(counts);
+    for (alive, &count) in alive.iter_mut().zip(counts) {
+        *alive = count > 0;
+    }
+    alive[EOM] = true;
+
+    let lightest = |weight: &[u64], alive: &[bool], skip: usize| {
+        (0..weight.len())
+            .filter(|&node| alive[node] && node != skip)
+            .min_by_key(|&node| weight[node])
+    };
+    let mut next = SYMBOLS;
+    while let Some(a) = lightest(&weight, &alive, usize::MAX)
+        && let Some(b) = lightest(&weight, &alive, a)
+    {
+        weight[next] = weight[a] + weight[b];
+        (parent[a], parent[b]) = (next, next);
+        (alive[a], alive[b], alive[next]) = (false, false, true);
+        next += 1;
+    }
+
+    let mut lengths = [0; SYMBOLS];
+    for (symbol, len) in lengths.iter_mut().enumerate() {
+        if symbol == EOM || counts[symbol] > 0 {
+            let mut node = symbol;
+            while parent[node] != 0 {
+                node = parent[node];
+                *len += 1;
+            }
+        }
+    }
+    lengths
+}
+
+/// Writes bits into a slice in [`BitOrder::Lsb0`](crate::BitOrder::Lsb0) order, as
+/// [`BitWriter`](crate::bits::BitWriter) packs them
+#[cfg(feature = "encode")]
+struct BitCursor<'a> {
+    out: &'a mut [u8],
+    len: usize,
+    pending: u128,
+    filled: usize,
+}
+
+#[cfg(feature = "encode")]
+impl<'a> BitCursor<'a> {
+    fn new(out: &'a mut [u8]) -> Self {
+        Self {
+            out,
+            len: 0,
+            pending: 0,
+            filled: 0,
+        }
+    }
+
+    /// Write the low `count` bits of `value`, least significant first
+    fn write_bits(&mut self, value: u64, count: usize) -> io::Result<()> {
+        let mask = u64::MAX
+            .checked_shl(count as u32)
+            .map_or(u64::MAX, |high| !high);
+        self.pending |= u128::from(value & mask) << self.filled;
+        self.filled += count;
+        while self.filled >= 8 {
+            self.push(self.pending as u8)?;
+            self.pending >>= 8;
+            self.filled -= 8;
+        }
+        Ok(())
+    }
+
+    fn push(&mut self, byte: u8) -> io::Result<()> {
+        let slot = self.out.get_mut(self.len).ok_or(io::ErrorKind::WriteZero)?;
+        *slot = byte;
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Pad the final byte with zero bits, returning the number of bytes written
+    fn finish(mut self) -> io::Result<usize> {
+        if self.filled > 0 {
+            self.push(self.pending as u8)?;
+        }
+        Ok(self.len)
+    }
+}
+
+/// Codes the bytes written to it into a [`BitCursor`]
+#[cfg(feature = "encode")]
+struct Coder<'a, 'b> {
+    /// The path to each symbol's leaf, first step in the least significant bit, and its length
+    codes: &'b [(u64, u8); SYMBOLS],
+    bits: BitCursor<'a>,
+}
+
+#[cfg(feature = "encode")]
+impl Write for Coder<'_, '_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for &byte in buf {
+            let (code, len) = self.codes[byte as usize];
+            if len == 0 {
+                return Err(io::Error::other(
+                    "value serialized differently the second time",
+                ));
+            }
+            self.bits.write_bits(code, len as usize)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Write the shape of the tree whose leaves are `leaves`, left to right, each at the depth of its
+/// code length, as the preorder traversal the huffman coder writes for its own trees. Records the
+/// path to each leaf in `codes`.
+#[cfg(feature = "encode")]
+fn write_shape(
+    leaves: &[u16],
+    lengths: &[u8; SYMBOLS],
+    next: &mut usize,
+    (path, depth): (u64, u8),
+    bits: &mut BitCursor,
+    codes: &mut [(u64, u8); SYMBOLS],
+) -> io::Result<()> {
+    let leaf = leaves[*next] as usize;
+    if lengths[leaf] == depth {
+        *next += 1;
+        codes[leaf] = (path, depth);
+        return bits.write_bits(1, 1);
+    }
+    bits.write_bits(0, 1)?;
+    write_shape(leaves, lengths, next, (path, depth + 1), bits, codes)?;
+    let right = path | (1 << depth);
+    write_shape(leaves, lengths, next, (right, depth + 1), bits, codes)
+}
+
+/// Like [`huff`](crate::huff), but writes the compressed value into `out` and returns its length,
+/// without allocating. Fails with [`Error::DoesNotFit`](crate::Error::DoesNotFit) and the length
+/// the output needs if it doesn't fit, leaving `out` untouched; [`max_compressed_len`] gives a
+/// size that always fits.
+///
+/// The value is serialized twice, so its serialization must not change in between. The output
+/// decodes with [`puff`](crate::puff) as usual, but may differ from the output of `huff`, since
+/// runs of repeated bytes aren't tried and the tree is laid out differently.
+///
+/// [`max_compressed_len`]: crate::max_compressed_len
+#[cfg(feature = "encode")]
+pub fn huff_into_slice<T>(value: &T, out: &mut [u8]) -> Result<usize, crate::Error>
+where
+    T: serde::Serialize,
+{
+    let mut histogram = Histogram {
+        counts: [0; 256],
+        len: 0,
+        hasher: crc32fast::Hasher::new(),
+    };
+    bincode::serialize_into(&mut histogram, value)?;
+    let Histogram {
+        counts,
+        len,
+        hasher,
+    } = histogram;
+
+    // Short values are stored as they are, like `huff` does, and always have a byte to code
+    // otherwise
+    let lengths = code_lengths(&counts);
+    let leaves = lengths.iter().filter(|&&len| len > 0).count();
+    let coded_len = (len >= EncodeOptions::default().literal_threshold).then(|| {
+        let message_bits = counts
+            .iter()
+            .zip(&lengths)
+            .map(|(&count, &len)| count * u64::from(len))
+            .sum::<u64>()
+            + u64::from(lengths[EOM]);
+        ((2 * leaves - 1 + VALUE_BITS * leaves) as u64 + message_bits).div_ceil(8) as usize
+    });
+    let literal = coded_len.is_none_or(|coded_len| frame::literal_len(len) < coded_len);
+    let encoding = match literal {
+        true => Encoding::Literal,
+        false => Encoding::Huffman,
+    };
+    let header = frame::checksummed_header(encoding, hasher.finalize());
+    let total = header.len()
+        + match literal {
+            true => frame::literal_len(len),
+            false => coded_len.unwrap(),
+        };
+    if total > out.len() {
+        return Err(crate::Error::DoesNotFit(total));
+    }
+
+    let (prefix, mut rest) = out[..total].split_at_mut(header.len());
+    prefix.copy_from_slice(&header);
+    if literal {
+        let start = varint::write_to_slice(len as u64, rest);
+        rest = &mut rest[start..];
+        bincode::serialize_into(&mut rest, value)?;
+        if !rest.is_empty() {
+            return Err(io::Error::other("value serialized differently the second time").into());
+        }
+        return Ok(total);
+    }
+
+    // Leaves ordered by code length are laid out left to right, giving a canonical tree
+    let mut order: [u16; SYMBOLS] = std::array::from_fn(|symbol| symbol as u16);
+    order.sort_unstable_by_key(|&symbol| {
+        let len = lengths[symbol as usize];
+        (len == 0, len, symbol)
+    });
+    let order = &order[..leaves];
+    let mut bits = BitCursor::new(rest);
+    let mut codes = [(0, 0); SYMBOLS];
+    write_shape(order, &lengths, &mut 0, (0, 0), &mut bits, &mut codes)?;
+    for &symbol in order {
+        let is_eom = symbol as usize == EOM;
+        bits.write_bits(is_eom as u64, 1)?;
+        bits.write_bits(if is_eom { 0 } else { u64::from(symbol) }, 8)?;
+    }
+    let mut coder = Coder {
+        codes: &codes,
+        bits,
+    };
+    bincode::serialize_into(&mut coder, value)?;
+    let (eom, eom_len) = codes[EOM];
+    coder.bits.write_bits(eom, eom_len as usize)?;
+    if Some(coder.bits.finish()?) != coded_len {
+        return Err(io::Error::other("value serialized differently the second time").into());
+    }
+    Ok(total)
+}
+
+/// Like [`puff`](crate::puff), but decodes the serialized value into `buffer` and deserializes it
+/// from there, without allocating, so that memory for decoding can come from an arena or a pool.
+/// The value can borrow from `buffer`, as a `&str` or `&[u8]` does; owned types like `String`
+/// still allocate as they're deserialized. Fails with
+/// [`Error::MemoryLimit`](crate::Error::MemoryLimit) if the serialized value doesn't fit in
+/// `buffer`.
+///
+/// Frames that were transformed fail with [`FrameError::Transformed`](crate::FrameError), and
+/// frames coded with a codebook with [`FrameError::CodebookRequired`](crate::FrameError). Values
+/// compressed with [`huff_fingerprinted`](crate::huff_fingerprinted) must be read as the type
+/// they were written as, so a `String` can't be read as a `&str`.
+///
+/// ```
+/// let compressed = huffnpuff::huff(&"borrowed from the buffer".repeat(4))?;
+/// let mut buffer = [0; 256];
+/// let text: &str = huffnpuff::puff_into_slice(&compressed, &mut buffer)?;
+/// assert_eq!(text, "borrowed from the buffer".repeat(4));
+/// # Ok::<(), huffnpuff::Error>(())
+/// ```
+#[cfg(feature = "decode")]
+pub fn puff_into_slice<'a, T>(bytes: &[u8], buffer: &'a mut [u8]) -> Result<T, crate::Error>
+where
+    T: serde::Deserialize<'a>,
+{
+    let (header, len) = frame::decode_into_slice(bytes, buffer)?;
+    if header
+        .fingerprint
+        .is_some_and(|found| found != crate::fingerprint(std::any::type_name::<T>()))
+    {
+        return Err(crate::Error::TypeMismatch);
+    }
+    Ok(bincode::deserialize(&buffer[..len])?)
+}
+
+/// Like [`huff_into_slice`], but writes the compressed value into a vector from `alloc`, such as
+/// a per-frame arena, so neither coding it nor its output touch the global heap. The vector has
+/// room for [`max_compressed_len`](crate::max_compressed_len) of the serialized value, of which
+/// only the start is used.
+#[cfg(all(feature = "allocator-api", feature = "encode"))]
+pub fn huff_in<T, A>(value: &T, alloc: A) -> Result<AllocVec<u8, A>, crate::Error>
+where
+    T: serde::Serialize,
+    A: Allocator,
+{
+    let len = usize::try_from(bincode::serialized_size(value)?)
+        .map_err(|_| crate::Error::AllocationFailed)?;
+    let max_len = frame::max_len(len);
+    let mut out = AllocVec::new_in(alloc);
+    out.try_reserve_exact(max_len)
+        .map_err(|_| crate::Error::AllocationFailed)?;
+    out.resize(max_len, 0);
+    let len = huff_into_slice(value, &mut out)?;
+    out.truncate(len);
+    Ok(out)
+}
+
+/// Like [`decompress`](crate::decompress), but decodes into a vector from `alloc`, such as a
+/// per-frame arena, so decoding doesn't touch the global heap. The decompressed length isn't known
+/// up front, so the vector starts at a guess and doubles, with decoding starting over, until the
+/// bytes fit. Data that would decompress to more than `max_len` bytes fails with
+/// [`Error::LimitExceeded`](crate::Error::LimitExceeded).
+///
+/// Frames that can't be decoded in place fail as they do with [`puff_into_slice`].
+#[cfg(all(feature = "allocator-api", feature = "decode"))]
+pub fn decompress_in<A: Allocator>(
+    bytes: &[u8],
+    max_len: usize,
+    alloc: A,
+) -> Result<AllocVec<u8, A>, crate::Error> {
+    let mut out = AllocVec::new_in(alloc);
+    let mut len = bytes.len().saturating_mul(4).max(64).min(max_len);
+    loop {
+        out.try_reserve_exact(len - out.len())
+            .map_err(|_| crate::Error::AllocationFailed)?;
+        out.resize(len, 0);
+        match frame::decode_into_slice(bytes, &mut out) {
+            Ok((_, decoded_len)) => {
+                out.truncate(decoded_len);
+                return Ok(out);
+            }
+            Err(crate::Error::MemoryLimit) if len < max_len => {
+                len = len.saturating_mul(2).min(max_len);
+            }
+            Err(crate::Error::MemoryLimit) => return Err(crate::Error::LimitExceeded),
+            Err(error) => return Err(error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+
+    use super::*;
+
+    /// Counts the allocations made on each thread, so a test can check it made none
+    struct CountingAllocator;
+
+    thread_local! {
+        static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            ALLOCATIONS.with(|count| count.set(count.get() + 1));
+            unsafe { System.alloc(layout) }
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            unsafe { System.dealloc(ptr, layout) }
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    fn allocations<R>(f: impl FnOnce() -> R) -> (R, usize) {
+        let before = ALLOCATIONS.with(Cell::get);
+        let result = f();
+        (result, ALLOCATIONS.with(Cell::get) - before)
+    }
+
+    #[test]
+    fn roundtrip() {
+        let text = "short codes for common symbols and long codes for rare ones ".repeat(20);
+        let readings: Vec<(u32, f32)> = (0..300).map(|i| (i % 7, i as f32 * 0.5)).collect();
+        let mut out = [0; 4096];
+
+        let (len, count) = allocations(|| huff_into_slice(&text, &mut out).unwrap());
+        assert_eq!(count, 0);
+        // Both trees are optimal, so they code to the same length
+        assert_eq!(len, crate::huff(&text).unwrap().len());
+        assert_eq!(crate::puff::<String>(&out[..len]).unwrap(), text);
+
+        let (len, count) = allocations(|| huff_into_slice(&readings, &mut out).unwrap());
+        assert_eq!(count, 0);
+        assert_eq!(
+            crate::puff::<Vec<(u32, f32)>>(&out[..len]).unwrap(),
+            readings
+        );
+
+        // Small values are stored as they are
+        let len = huff_into_slice(&7u16, &mut out).unwrap();
+        assert_eq!(&out[..len], crate::huff(&7u16).unwrap());
+        let len = huff_into_slice(&(), &mut out).unwrap();
+        crate::puff::<()>(&out[..len]).unwrap();
+    }
+
+    #[test]
+    fn runs_not_tried() {
+        let value = vec![3u8; 500];
+        let mut out = [0; 256];
+        let len = huff_into_slice(&value, &mut out).unwrap();
+        let runs = crate::huff(&value).unwrap();
+        assert!(len < 100 && runs.len() < len);
+        assert_eq!(crate::puff::<Vec<u8>>(&out[..len]).unwrap(), value);
+        assert_eq!(
+            frame::plain_header(Encoding::Huffman)[..],
+            frame::write_header(&frame::Header::default())[..]
+        );
+    }
+
+    #[test]
+    fn does_not_fit() {
+        let text = "the quick brown fox jumps over the lazy dog ".repeat(10);
+        let mut small = [0xaa; 32];
+        let Err(crate::Error::DoesNotFit(needed)) = huff_into_slice(&text, &mut small) else {
+            panic!("expected the value not to fit");
+        };
+        assert_eq!(small, [0xaa; 32]);
+
+        let mut exact = vec![0; needed];
+        assert_eq!(huff_into_slice(&text, &mut exact).unwrap(), needed);
+        assert_eq!(crate::puff::<String>(&exact).unwrap(), text);
+
+        let bound = crate::max_compressed_len(bincode::serialized_size(&text).unwrap() as usize);
+        assert!(needed <= bound);
+    }
+
+    #[test]
+    fn decode_without_allocating() {
+        let text = "decoded into a buffer the caller owns ".repeat(20);
+        let compressed = crate::huff(&text).unwrap();
+        let mut buffer = [0; 1024];
+        let (decoded, count) =
+            allocations(|| puff_into_slice::<&str>(&compressed, &mut buffer).unwrap());
+        assert_eq!(count, 0);
+        assert_eq!(decoded, text);
+
+        // Runs and literals
+        let zeros = crate::huff(&vec![0u8; 500]).unwrap();
+        let (decoded, count) =
+            allocations(|| puff_into_slice::<&[u8]>(&zeros, &mut buffer).unwrap());
+        assert_eq!((decoded, count), (&[0; 500][..], 0));
+        let literal = crate::huff(&7u16).unwrap();
+        assert_eq!(puff_into_slice::<u16>(&literal, &mut buffer).unwrap(), 7);
+
+        let mut small = [0; 100];
+        for compressed in [&compressed, &zeros] {
+            assert_eq!(
+                puff_into_slice::<&[u8]>(compressed, &mut small),
+                Err(crate::Error::MemoryLimit)
+            );
+        }
+
+        let options = EncodeOptions {
+            transforms: vec![crate::Transform::Delta],
+            ..EncodeOptions::default()
+        };
+        let transformed = crate::huff_with_options(&text, &options).unwrap();
+        assert_eq!(
+            puff_into_slice::<&str>(&transformed, &mut buffer),
+            Err(crate::Error::Frame(frame::Error::Transformed))
+        );
+    }
+
+    /// Hands out memory from a fixed array and never frees it, like a per-frame bump arena
+    #[cfg(feature = "allocator-api")]
+    struct Arena {
+        memory: std::cell::UnsafeCell<[u64; 2048]>,
+        used: Cell<usize>,
+    }
+
+    #[cfg(feature = "allocator-api")]
+    unsafe impl Allocator for &Arena {
+        fn allocate(
+            &self,
+            layout: Layout,
+        ) -> Result<std::ptr::NonNull<[u8]>, allocator_api2::alloc::AllocError> {
+            let base = self.memory.get() as *mut u8;
+            let start =
+                (base as usize + self.used.get()).next_multiple_of(layout.align()) - base as usize;
+            let end = start + layout.size();
+            if end > size_of::<[u64; 2048]>() {
+                return Err(allocator_api2::alloc::AllocError);
+            }
+            self.used.set(end);
+            let start = std::ptr::NonNull::new(unsafe { base.add(start) }).unwrap();
+            Ok(std::ptr::NonNull::slice_from_raw_parts(
+                start,
+                layout.size(),
+            ))
+        }
+
+        unsafe fn deallocate(&self, _: std::ptr::NonNull<u8>, _: Layout) {}
+    }
+
+    #[cfg(feature = "allocator-api")]
+    #[test]
+    fn arena() {
+        let arena = Arena {
+            memory: std::cell::UnsafeCell::new([0; 2048]),
+            used: Cell::new(0),
+        };
+        let text = "compressed into an arena and decompressed out of it ".repeat(20);
+
+        let (compressed, count) = allocations(|| huff_in(&text, &arena).unwrap());
+        assert_eq!(count, 0);
+        assert_eq!(crate::puff::<String>(&compressed).unwrap(), text);
+
+        // The guess of four times the compressed length is too small, so the vector grows
+        let serialized = bincode::serialize(&text).unwrap();
+        let (decompressed, count) =
+            allocations(|| decompress_in(&compressed, 4096, &arena).unwrap());
+        assert_eq!(count, 0);
+        assert_eq!(decompressed[..], serialized);
+
+        assert_eq!(
+            decompress_in(&compressed, 100, &arena),
+            Err(crate::Error::LimitExceeded)
+        );
+        let huge = "x".repeat(20_000);
+        assert_eq!(
+            huff_in(&huge, &arena).map(|_| ()),
+            Err(crate::Error::AllocationFailed)
+        );
+    }
+}
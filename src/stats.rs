@@ -0,0 +1,338 @@
+//! Rolling statistics about compression in a running service. Encoders and decoders report what
+//! they did to a shared [`StatsCollector`], and the host application reads aggregates over the
+//! most recent operations whenever it likes, for a dashboard or health check, without sending
+//! every call to a metrics system.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+#[cfg(any(feature = "encode", feature = "decode"))]
+use std::time::Instant;
+
+use crate::frame;
+
+/// One operation in the rolling window
+#[derive(Debug, Clone, Copy)]
+struct Operation {
+    compressed: usize,
+    uncompressed: usize,
+    elapsed: Duration,
+}
+
+#[derive(Debug, Default)]
+struct Window {
+    operations: VecDeque<Operation>,
+    total: u64,
+}
+
+impl Window {
+    fn record(&mut self, operation: Operation, capacity: usize) {
+        if self.operations.len() == capacity {
+            self.operations.pop_front();
+        }
+        self.operations.push_back(operation);
+        self.total += 1;
+    }
+
+    fn stats(&self) -> OperationStats {
+        let mut ratios: Vec<f64> = self
+            .operations
+            .iter()
+            .map(|operation| operation.compressed as f64 / operation.uncompressed.max(1) as f64)
+            .collect();
+        ratios.sort_by(f64::total_cmp);
+        OperationStats {
+            total: self.total,
+            recent: self.operations.len(),
+            uncompressed_bytes: self
+                .operations
+                .iter()
+                .map(|operation| operation.uncompressed as u64)
+                .sum(),
+            compressed_bytes: self
+                .operations
+                .iter()
+                .map(|operation| operation.compressed as u64)
+                .sum(),
+            elapsed: self
+                .operations
+                .iter()
+                .map(|operation| operation.elapsed)
+                .sum(),
+            ratios,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Collected {
+    encodes: Window,
+    decodes: Window,
+    errors: ErrorCounts,
+}
+
+/// Collects statistics about the values a service compresses and decompresses. Operations are
+/// reported with [`record_encode`](StatsCollector::record_encode) and
+/// [`record_decode`](StatsCollector::record_decode), or made through the collector's own
+/// [`huff`](StatsCollector::huff) and [`puff`](StatsCollector::puff), which time and report them.
+/// Ratios and throughput cover the most recent operations, up to the window size, while counts
+/// cover every operation since the collector was created.
+///
+/// Reporting only takes `&self`, so one collector can be shared between threads, for example in
+/// an `Arc` or a `static`.
+#[derive(Debug)]
+pub struct StatsCollector {
+    window: usize,
+    collected: Mutex<Collected>,
+}
+
+impl StatsCollector {
+    /// A collector that aggregates over the last `window` encodes and the last `window` decodes.
+    /// `window` must be more than zero.
+    pub fn new(window: usize) -> Self {
+        assert!(window > 0, "the window must hold at least one operation");
+        Self {
+            window,
+            collected: Mutex::new(Collected::default()),
+        }
+    }
+
+    fn collected(&self) -> std::sync::MutexGuard<'_, Collected> {
+        // The statistics are always consistent between calls, so a panic elsewhere while holding
+        // the lock doesn't leave them broken
+        self.collected
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Report that `uncompressed` bytes were compressed to `compressed` bytes in `elapsed`
+    pub fn record_encode(&self, uncompressed: usize, compressed: usize, elapsed: Duration) {
+        let operation = Operation {
+            compressed,
+            uncompressed,
+            elapsed,
+        };
+        self.collected().encodes.record(operation, self.window);
+    }
+
+    /// Report that `compressed` bytes were decompressed to `uncompressed` bytes in `elapsed`
+    pub fn record_decode(&self, compressed: usize, uncompressed: usize, elapsed: Duration) {
+        let operation = Operation {
+            compressed,
+            uncompressed,
+            elapsed,
+        };
+        self.collected().decodes.record(operation, self.window);
+    }
+
+    /// Report an error from encoding or decoding, which is counted by its kind
+    pub fn record_error(&self, error: &crate::Error) {
+        let mut collected = self.collected();
+        let errors = &mut collected.errors;
+        if error.is_truncation() {
+            errors.truncation += 1;
+        } else if error.is_corruption() {
+            errors.corruption += 1;
+        } else if error.is_unsupported_format() {
+            errors.unsupported_format += 1;
+        } else {
+            errors.other += 1;
+        }
+    }
+
+    /// Like [`huff`](crate::huff), reporting the encode, or the error
+    #[cfg(feature = "encode")]
+    pub fn huff<T>(&self, value: &T) -> Result<Vec<u8>, crate::Error>
+    where
+        T: serde::Serialize,
+    {
+        let start = Instant::now();
+        let result = bincode::serialize(value)
+            .map_err(crate::Error::from)
+            .and_then(|bytes| {
+                let compressed = frame::encode(
+                    &bytes,
+                    &frame::Header::default(),
+                    &crate::EncodeOptions::default(),
+                )?;
+                Ok((bytes.len(), compressed))
+            });
+        match result {
+            Ok((uncompressed, compressed)) => {
+                self.record_encode(uncompressed, compressed.len(), start.elapsed());
+                Ok(compressed)
+            }
+            Err(error) => {
+                self.record_error(&error);
+                Err(error)
+            }
+        }
+    }
+
+    /// Like [`puff`](crate::puff), reporting the decode, or the error
+    #[cfg(feature = "decode")]
+    pub fn puff<T>(&self, bytes: &[u8]) -> Result<T, crate::Error>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        let start = Instant::now();
+        let result = frame::decode(bytes).and_then(|frame| {
+            let value = crate::deserialize_frame(&frame, std::any::type_name::<T>())?;
+            Ok((frame, value))
+        });
+        match result {
+            Ok((frame, value)) => {
+                self.record_decode(frame.len, frame.payload.len(), start.elapsed());
+                Ok(value)
+            }
+            Err(error) => {
+                self.record_error(&error);
+                Err(error)
+            }
+        }
+    }
+
+    /// The statistics collected so far
+    pub fn snapshot(&self) -> StatsSnapshot {
+        let collected = self.collected();
+        StatsSnapshot {
+            encodes: collected.encodes.stats(),
+            decodes: collected.decodes.stats(),
+            errors: collected.errors,
+        }
+    }
+
+    /// Forget everything collected so far
+    pub fn reset(&self) {
+        *self.collected() = Collected::default();
+    }
+}
+
+/// What a [`StatsCollector`] had collected when [`snapshot`](StatsCollector::snapshot) was
+/// called
+#[derive(Debug, Clone, PartialEq)]
+pub struct StatsSnapshot {
+    pub encodes: OperationStats,
+    pub decodes: OperationStats,
+    pub errors: ErrorCounts,
+}
+
+/// Aggregates over encodes or over decodes. Sizes, times and ratios cover the operations in the
+/// collector's window.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OperationStats {
+    /// The operations reported since the collector was created or reset
+    pub total: u64,
+    /// The operations in the window
+    pub recent: usize,
+    pub uncompressed_bytes: u64,
+    pub compressed_bytes: u64,
+    /// The time the operations took, summed
+    pub elapsed: Duration,
+    /// Each operation's compressed size over its uncompressed size, in ascending order
+    ratios: Vec<f64>,
+}
+
+impl OperationStats {
+    /// The compression ratio, compressed size over uncompressed size, that `percentile` percent
+    /// of the operations in the window are at or below, so `ratio_percentile(50.0)` is the median.
+    /// `None` if there are no operations.
+    pub fn ratio_percentile(&self, percentile: f64) -> Option<f64> {
+        let last = self.ratios.len().checked_sub(1)?;
+        let rank = (percentile.clamp(0.0, 100.0) / 100.0 * last as f64).round() as usize;
+        Some(self.ratios[rank])
+    }
+
+    /// The ratio of all the compressed bytes to all the uncompressed bytes in the window
+    pub fn overall_ratio(&self) -> Option<f64> {
+        (self.uncompressed_bytes > 0)
+            .then(|| self.compressed_bytes as f64 / self.uncompressed_bytes as f64)
+    }
+
+    /// Uncompressed bytes handled per second of time spent in the operations. `None` if no time
+    /// was recorded.
+    pub fn throughput(&self) -> Option<f64> {
+        let seconds = self.elapsed.as_secs_f64();
+        (seconds > 0.0).then(|| self.uncompressed_bytes as f64 / seconds)
+    }
+}
+
+/// Errors reported since the collector was created or reset, by their kind. See
+/// [`Error::is_truncation`](crate::Error::is_truncation) and the methods after it.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ErrorCounts {
+    pub truncation: u64,
+    pub corruption: u64,
+    pub unsupported_format: u64,
+    /// Every other error, such as I/O errors and type mismatches
+    pub other: u64,
+}
+
+impl ErrorCounts {
+    pub fn total(&self) -> u64 {
+        self.truncation + self.corruption + self.unsupported_format + self.other
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rolling_window() {
+        let collector = StatsCollector::new(4);
+        for compressed in [10, 20, 30, 40, 50, 60] {
+            collector.record_encode(100, compressed, Duration::from_millis(1));
+        }
+        let encodes = collector.snapshot().encodes;
+        assert_eq!((encodes.total, encodes.recent), (6, 4));
+        assert_eq!(encodes.uncompressed_bytes, 400);
+        assert_eq!(encodes.ratio_percentile(0.0), Some(0.3));
+        assert_eq!(encodes.ratio_percentile(50.0), Some(0.5));
+        assert_eq!(encodes.ratio_percentile(100.0), Some(0.6));
+        assert_eq!(encodes.overall_ratio(), Some(0.45));
+        assert_eq!(encodes.throughput(), Some(100_000.0));
+
+        let decodes = collector.snapshot().decodes;
+        assert_eq!(decodes.total, 0);
+        assert_eq!(decodes.ratio_percentile(50.0), None);
+        assert_eq!(decodes.throughput(), None);
+
+        collector.reset();
+        assert_eq!(collector.snapshot().encodes.total, 0);
+    }
+
+    #[test]
+    fn through_collector() {
+        let collector = std::sync::Arc::new(StatsCollector::new(100));
+        let handles: Vec<_> = (0..4)
+            .map(|i| {
+                let collector = collector.clone();
+                std::thread::spawn(move || {
+                    let value = format!("value from thread {i} ").repeat(20);
+                    let compressed = collector.huff(&value).unwrap();
+                    assert_eq!(collector.puff::<String>(&compressed).unwrap(), value);
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let snapshot = collector.snapshot();
+        assert_eq!((snapshot.encodes.total, snapshot.decodes.total), (4, 4));
+        assert!(snapshot.encodes.ratio_percentile(90.0).unwrap() < 1.0);
+        assert_eq!(
+            snapshot.encodes.uncompressed_bytes,
+            snapshot.decodes.uncompressed_bytes
+        );
+        assert_eq!(snapshot.errors.total(), 0);
+
+        let compressed = crate::huff_fingerprinted(&1u8).unwrap();
+        assert!(collector.puff::<String>(&compressed[..1]).is_err());
+        assert!(collector.puff::<u16>(&compressed).is_err());
+        let errors = collector.snapshot().errors;
+        assert_eq!((errors.truncation, errors.other), (1, 1));
+        assert_eq!(collector.snapshot().decodes.total, 4);
+    }
+}
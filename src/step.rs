@@ -0,0 +1,161 @@
+use crate::bits::BitOrder;
+use crate::{frame, huffman};
+
+/// Whether a [`StepEncoder`] has finished
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    InProgress,
+    Done,
+}
+
+/// Compresses a value a bounded amount at a time, for main loops and schedulers that can't block
+/// for as long as compressing a large value takes. Each call to [`step`](StepEncoder::step) does
+/// work proportional to its `max_symbols`. The result is decoded with [`puff`](crate::puff) like
+/// that of [`huff`](crate::huff), but the value is always huffman coded, unless storing it as it
+/// is turns out smaller.
+///
+/// The value is serialized up front by [`StepEncoder::new`]. After that, every byte is visited
+/// twice, once to count it and once to code it, and each visit uses up one symbol of a step.
+pub struct StepEncoder {
+    bytes: Vec<u8>,
+    position: usize,
+    stage: Stage,
+}
+
+enum Stage {
+    Counting(Box<[u32; 256]>),
+    Coding(huffman::MessageWriter),
+    Done(Vec<u8>),
+}
+
+impl StepEncoder {
+    pub fn new<T>(value: &T) -> Result<Self, crate::Error>
+    where
+        T: serde::Serialize,
+    {
+        let bytes = bincode::serialize(value)?;
+        if bytes.is_empty() {
+            return Err(huffman::Error::NoData.into());
+        }
+        Ok(Self {
+            bytes,
+            position: 0,
+            stage: Stage::Counting(Box::new([0; 256])),
+        })
+    }
+
+    /// Count or code up to `max_symbols` more bytes
+    pub fn step(&mut self, max_symbols: usize) -> Step {
+        let mut budget = max_symbols;
+        loop {
+            let end = self.bytes.len().min(self.position.saturating_add(budget));
+            let chunk = &self.bytes[self.position..end];
+            budget -= chunk.len();
+            self.position = end;
+
+            match &mut self.stage {
+                Stage::Counting(frequencies) => {
+                    for &byte in chunk {
+                        frequencies[byte as usize] += 1;
+                    }
+                    if self.position < self.bytes.len() {
+                        return Step::InProgress;
+                    }
+                    self.position = 0;
+                    self.stage = Stage::Coding(huffman::MessageWriter::new(
+                        frequencies,
+                        BitOrder::default(),
+                    ));
+                }
+                Stage::Coding(writer) => {
+                    writer.write(chunk);
+                    if self.position < self.bytes.len() {
+                        return Step::InProgress;
+                    }
+                    let Stage::Coding(writer) =
+                        std::mem::replace(&mut self.stage, Stage::Done(Vec::new()))
+                    else {
+                        unreachable!()
+                    };
+                    self.stage = Stage::Done(frame::frame_message(writer.finish(), &[&self.bytes]));
+                }
+                Stage::Done(_) => return Step::Done,
+            }
+        }
+    }
+
+    /// The fraction of the work that's been done, between 0 and 1
+    pub fn progress(&self) -> f64 {
+        let done = match self.stage {
+            Stage::Counting(_) => self.position,
+            Stage::Coding(_) => self.bytes.len() + self.position,
+            Stage::Done(_) => return 1.0,
+        };
+        done as f64 / (2 * self.bytes.len()) as f64
+    }
+
+    /// The compressed value, once [`step`](StepEncoder::step) has returned [`Step::Done`]
+    pub fn into_output(self) -> Option<Vec<u8>> {
+        match self.stage {
+            Stage::Done(output) => Some(output),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(encoder: &mut StepEncoder, max_symbols: usize) -> usize {
+        let mut steps = 1;
+        let mut progress = encoder.progress();
+        while encoder.step(max_symbols) == Step::InProgress {
+            assert!(encoder.progress() > progress);
+            progress = encoder.progress();
+            steps += 1;
+        }
+        steps
+    }
+
+    #[test]
+    fn roundtrip() {
+        let value: Vec<String> = (0..200).map(|i| format!("entry number {i}")).collect();
+        let len = bincode::serialized_size(&value).unwrap() as usize;
+
+        for max_symbols in [1, 100, 1000, usize::MAX] {
+            let mut encoder = StepEncoder::new(&value).unwrap();
+            let steps = run(&mut encoder, max_symbols);
+            assert_eq!(steps, (2 * len).div_ceil(max_symbols).max(1));
+            assert_eq!(encoder.step(max_symbols), Step::Done);
+            assert_eq!(encoder.progress(), 1.0);
+
+            let compressed = encoder.into_output().unwrap();
+            assert!(compressed.len() < len);
+            assert_eq!(crate::puff::<Vec<String>>(&compressed).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn incompressible() {
+        let value: Vec<u8> = (0..=255).collect();
+        let mut encoder = StepEncoder::new(&value).unwrap();
+        run(&mut encoder, 64);
+        let compressed = encoder.into_output().unwrap();
+        assert!(compressed.len() <= crate::max_compressed_len(264));
+        assert_eq!(crate::puff::<Vec<u8>>(&compressed).unwrap(), value);
+    }
+
+    #[test]
+    fn unfinished() {
+        let mut encoder = StepEncoder::new(&"not done yet").unwrap();
+        assert_eq!(encoder.step(0), Step::InProgress);
+        assert_eq!(encoder.step(3), Step::InProgress);
+        assert!(encoder.into_output().is_none());
+
+        assert!(matches!(
+            StepEncoder::new(&()),
+            Err(crate::Error::Huffman(huffman::Error::NoData))
+        ));
+    }
+}
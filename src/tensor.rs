@@ -0,0 +1,299 @@
+//! Compression of n-dimensional numeric arrays from the `ndarray` crate. The shape and memory
+//! order of the array are recorded, and its elements are stored in memory order, each as its
+//! difference from the element before it along the innermost axis. The first element of each row
+//! is stored as its difference from the first element of the row before. For smoothly varying
+//! data like simulation fields these differences are small, and their bytes are split into planes,
+//! the lowest byte of every element first, so that the mostly constant high bytes are grouped
+//! together.
+
+#[cfg(feature = "decode")]
+use ndarray::{Array, Dimension, IxDyn, ShapeBuilder};
+#[cfg(feature = "encode")]
+use ndarray::{ArrayBase, Data};
+
+use crate::frame;
+#[cfg(feature = "encode")]
+use crate::options::EncodeOptions;
+use crate::varint;
+
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The shape doesn't match the number of elements, or is missing
+    Malformed,
+    /// The array has this many dimensions, which the requested array type can't hold
+    Dimensions(usize),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Error::Malformed => "the array's shape is missing or doesn't match its elements",
+            Error::Dimensions(dimensions) => {
+                return write!(
+                    f,
+                    "the array has {dimensions} dimensions, which the array type can't hold"
+                );
+            }
+        })
+    }
+}
+
+impl std::error::Error for Error {}
+
+mod sealed {
+    pub trait Sealed: Copy {
+        /// Identifies the element type in the compressed array
+        const TAG: u8;
+        const SIZE: usize;
+        fn to_bits(self) -> u64;
+        fn from_bits(bits: u64) -> Self;
+    }
+}
+
+/// The numeric types whose arrays can be compressed with [`huff_ndarray`]
+pub trait NdarrayElement: sealed::Sealed {}
+
+macro_rules! element {
+    ($($type:ty => $tag:literal),* $(,)?) => {$(
+        impl sealed::Sealed for $type {
+            const TAG: u8 = $tag;
+            const SIZE: usize = size_of::<$type>();
+            fn to_bits(self) -> u64 {
+                let mut bytes = [0; 8];
+                bytes[..Self::SIZE].copy_from_slice(&self.to_le_bytes());
+                u64::from_le_bytes(bytes)
+            }
+            fn from_bits(bits: u64) -> Self {
+                let bytes = bits.to_le_bytes();
+                <$type>::from_le_bytes(bytes[..Self::SIZE].try_into().unwrap())
+            }
+        }
+        impl NdarrayElement for $type {}
+    )*};
+}
+
+element! {
+    u8 => 0, u16 => 1, u32 => 2, u64 => 3,
+    i8 => 4, i16 => 5, i32 => 6, i64 => 7,
+    f32 => 8, f64 => 9,
+}
+
+/// Memory orders of the stored elements
+const ROW_MAJOR: u8 = 0;
+const COLUMN_MAJOR: u8 = 1;
+
+fn tag() -> u32 {
+    crate::fingerprint("huffnpuff::ndarray")
+}
+
+/// The element before each element in memory order, which it's stored as a difference from
+fn previous(index: usize, row: usize) -> Option<usize> {
+    match index % row {
+        0 => index.checked_sub(row),
+        _ => Some(index - 1),
+    }
+}
+
+/// Compress an array of numbers, to be decoded with [`puff_ndarray`]. Arrays laid out in column
+/// major order are kept in that order, and any other array is stored in row major order.
+#[cfg(feature = "encode")]
+pub fn huff_ndarray<A, S, D>(array: &ArrayBase<S, D>) -> Result<Vec<u8>, crate::Error>
+where
+    A: NdarrayElement,
+    S: Data<Elem = A>,
+    D: ndarray::Dimension,
+{
+    let column_major = array.ndim() > 1 && array.t().is_standard_layout();
+    let elements: Vec<u64> = match column_major {
+        true => array.t().iter().map(|&element| element.to_bits()).collect(),
+        false => array.iter().map(|&element| element.to_bits()).collect(),
+    };
+    let shape = array.shape();
+    let row = match column_major {
+        true => shape.first(),
+        false => shape.last(),
+    }
+    .copied()
+    .unwrap_or(1)
+    .max(1);
+
+    let mut payload = vec![
+        A::TAG,
+        if column_major {
+            COLUMN_MAJOR
+        } else {
+            ROW_MAJOR
+        },
+    ];
+    varint::write(shape.len() as u64, &mut payload);
+    for &len in shape {
+        varint::write(len as u64, &mut payload);
+    }
+    let deltas: Vec<u64> = (0..elements.len())
+        .map(|index| {
+            let before = previous(index, row).map_or(0, |before| elements[before]);
+            elements[index].wrapping_sub(before)
+        })
+        .collect();
+    for plane in 0..A::SIZE {
+        payload.extend(deltas.iter().map(|delta| (delta >> (8 * plane)) as u8));
+    }
+
+    let header = frame::Header {
+        fingerprint: Some(tag()),
+        ..frame::Header::default()
+    };
+    frame::encode(&payload, &header, &EncodeOptions::default())
+}
+
+/// Decode an array compressed with [`huff_ndarray`]. The array has the shape and memory order it
+/// was compressed with, and `D` can be [`type@IxDyn`] to accept any number of dimensions.
+#[cfg(feature = "decode")]
+pub fn puff_ndarray<A, D>(bytes: &[u8]) -> Result<Array<A, D>, crate::Error>
+where
+    A: NdarrayElement,
+    D: Dimension,
+{
+    let frame = frame::decode(bytes)?;
+    let [element_tag, order, ref rest @ ..] = frame.payload[..] else {
+        return Err(Error::Malformed.into());
+    };
+    if frame.header.fingerprint != Some(tag()) || element_tag != A::TAG {
+        return Err(crate::Error::TypeMismatch);
+    }
+
+    let mut rest = rest;
+    let ndim = varint::read(&mut rest)
+        .and_then(|ndim| usize::try_from(ndim).ok())
+        .filter(|&ndim| ndim <= rest.len())
+        .ok_or(Error::Malformed)?;
+    if D::NDIM.is_some_and(|expected| expected != ndim) {
+        return Err(Error::Dimensions(ndim).into());
+    }
+    let shape = (0..ndim)
+        .map(|_| {
+            varint::read(&mut rest)
+                .and_then(|len| usize::try_from(len).ok())
+                .ok_or(Error::Malformed)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let count = shape
+        .iter()
+        .try_fold(1usize, |count, &len| count.checked_mul(len))
+        .filter(|count| count.checked_mul(A::SIZE) == Some(rest.len()))
+        .ok_or(Error::Malformed)?;
+
+    let column_major = match order {
+        ROW_MAJOR => false,
+        COLUMN_MAJOR => true,
+        _ => return Err(Error::Malformed.into()),
+    };
+    let row = match column_major {
+        true => shape.first(),
+        false => shape.last(),
+    }
+    .copied()
+    .unwrap_or(1)
+    .max(1);
+
+    let mut elements = vec![0u64; count];
+    for (plane, bytes) in rest.chunks_exact(count.max(1)).enumerate() {
+        for (element, &byte) in elements.iter_mut().zip(bytes) {
+            *element |= u64::from(byte) << (8 * plane);
+        }
+    }
+    for index in 0..count {
+        if let Some(before) = previous(index, row) {
+            elements[index] = elements[index].wrapping_add(elements[before]);
+        }
+    }
+    let elements = elements.into_iter().map(A::from_bits).collect();
+
+    let shape = IxDyn(&shape);
+    let array = match column_major {
+        true => Array::from_shape_vec(shape.f(), elements),
+        false => Array::from_shape_vec(shape, elements),
+    }
+    .map_err(|_| Error::Malformed)?;
+    Ok(array
+        .into_dimensionality()
+        .map_err(|_| Error::Dimensions(ndim))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::{Array1, Array2, Array3, Ix2, ShapeBuilder, s};
+
+    use super::*;
+
+    /// A smooth field, like a temperature at each point of a grid
+    fn field(x: usize, y: usize, z: usize) -> Array3<f32> {
+        Array3::from_shape_fn((x, y, z), |(i, j, k)| {
+            (i as f32 * 0.1).sin() + (j as f32 * 0.05).cos() * (k as f32 * 0.2)
+        })
+    }
+
+    #[test]
+    fn roundtrip() {
+        let array = field(10, 20, 30);
+        let decoded: Array3<f32> = puff_ndarray(&huff_ndarray(&array).unwrap()).unwrap();
+        assert_eq!(decoded, array);
+
+        let ints = Array2::from_shape_fn((50, 40).f(), |(i, j)| (i * 40 + j) as i64 - 1000);
+        let decoded: Array2<i64> = puff_ndarray(&huff_ndarray(&ints).unwrap()).unwrap();
+        assert_eq!(decoded, ints);
+        assert!(decoded.t().is_standard_layout());
+
+        // A view that isn't contiguous, which is stored in row major order
+        let view = array.slice(s![..;2, 3.., ..;3]);
+        let decoded: Array<f32, IxDyn> = puff_ndarray(&huff_ndarray(&view).unwrap()).unwrap();
+        assert_eq!(decoded, view.into_dyn());
+        assert!(decoded.is_standard_layout());
+
+        for array in [Array1::<u8>::zeros(0), Array1::from_elem(1, 7)] {
+            let decoded: Array1<u8> = puff_ndarray(&huff_ndarray(&array).unwrap()).unwrap();
+            assert_eq!(decoded, array);
+        }
+        let scalar = ndarray::arr0(u16::MAX);
+        let decoded: Array<u16, _> = puff_ndarray(&huff_ndarray(&scalar).unwrap()).unwrap();
+        assert_eq!(decoded, scalar);
+    }
+
+    #[test]
+    fn smaller_than_serde() {
+        let array = Array2::from_shape_fn((200, 200), |(i, j)| (i * 3 + j * 7) as u32);
+        let compressed = huff_ndarray(&array).unwrap();
+        let elements: Vec<u32> = array.iter().copied().collect();
+        assert!(compressed.len() * 10 < crate::huff(&elements).unwrap().len());
+    }
+
+    #[test]
+    fn mismatches() {
+        let compressed = huff_ndarray(&field(2, 3, 4)).unwrap();
+        assert_eq!(
+            puff_ndarray::<f64, IxDyn>(&compressed),
+            Err(crate::Error::TypeMismatch)
+        );
+        assert_eq!(
+            puff_ndarray::<f32, Ix2>(&compressed),
+            Err(crate::Error::Ndarray(Error::Dimensions(3)))
+        );
+        assert_eq!(
+            puff_ndarray::<u8, IxDyn>(&crate::huff(&[1u8, 2]).unwrap()),
+            Err(crate::Error::TypeMismatch)
+        );
+
+        let header = frame::Header {
+            fingerprint: Some(tag()),
+            ..frame::Header::default()
+        };
+        let options = EncodeOptions::default();
+        // A 2 by 2 array of bytes with only three elements
+        let short = frame::encode(&[0, ROW_MAJOR, 2, 2, 2, 1, 2, 3], &header, &options).unwrap();
+        assert_eq!(
+            puff_ndarray::<u8, IxDyn>(&short),
+            Err(crate::Error::Ndarray(Error::Malformed))
+        );
+    }
+}
@@ -0,0 +1,351 @@
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+
+#[cfg(feature = "encode")]
+use crate::huff;
+#[cfg(feature = "decode")]
+use crate::puff;
+
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The input was not valid unpadded URL-safe base64
+    InvalidBase64,
+    /// The input was not an even number of hexadecimal digits
+    InvalidHex,
+    /// An [`Alphabet`] needs at least two byte values that aren't excluded
+    InvalidAlphabet,
+    /// The input has a byte outside the alphabet it was decoded with, or isn't a length the
+    /// alphabet produces
+    InvalidPacking,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Error::InvalidBase64 => "the text is not valid unpadded URL-safe base64",
+            Error::InvalidHex => "the text is not an even number of hexadecimal digits",
+            Error::InvalidAlphabet => "the alphabet needs at least two byte values",
+            Error::InvalidPacking => "the text doesn't decode with the alphabet",
+        })
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// The bytes of a compressed value are packed this many at a time
+const CHUNK_LEN: usize = 256;
+
+/// A restricted set of byte values for compressed output, so it can be embedded in protocols that
+/// give some bytes a meaning, like line-oriented ones, without a base64 pass. Output is packed
+/// as base `n` numbers, `n` being the number of values in the alphabet, so leaving out a few values
+/// costs a fraction of a percent rather than the third that base64 does.
+#[derive(Debug, Clone)]
+pub struct Alphabet {
+    /// The byte values in the alphabet, in ascending order. A digit stands for the value at its
+    /// index.
+    symbols: Vec<u8>,
+    /// The digit each byte value stands for, or `None` for values outside the alphabet
+    digits: [Option<u8>; 256],
+    /// The number of digits a chunk of each length from 0 to [`CHUNK_LEN`] is packed into
+    chunk_digits: Vec<usize>,
+}
+
+impl Alphabet {
+    /// Every byte value except those in `excluded`, of which there must be no more than 254
+    pub fn excluding(excluded: &[u8]) -> Result<Self, crate::Error> {
+        let symbols: Vec<u8> = (0..=255).filter(|byte| !excluded.contains(byte)).collect();
+        if symbols.len() < 2 {
+            return Err(Error::InvalidAlphabet.into());
+        }
+        let mut digits = [None; 256];
+        for (digit, &symbol) in symbols.iter().enumerate() {
+            // With all 256 values, the last digit is 255
+            digits[symbol as usize] = Some(digit as u8);
+        }
+
+        // A chunk of `len` bytes needs the fewest digits `d` with `base^d > 256^len - 1`, which is
+        // when `base^d` takes more than `len` bytes. Powers are kept as little endian bytes.
+        let base = symbols.len() as u32;
+        let mut power = vec![1u8];
+        let mut count = 0;
+        let chunk_digits = (0..=CHUNK_LEN)
+            .map(|len| {
+                while power.len() <= len {
+                    let mut carry = 0;
+                    for byte in &mut power {
+                        let value = *byte as u32 * base + carry;
+                        *byte = value as u8;
+                        carry = value >> 8;
+                    }
+                    while carry > 0 {
+                        power.push(carry as u8);
+                        carry >>= 8;
+                    }
+                    count += 1;
+                }
+                count
+            })
+            .collect();
+        Ok(Self {
+            symbols,
+            digits,
+            chunk_digits,
+        })
+    }
+
+    /// Every byte value except NUL, LF and CR, for line-oriented protocols and C strings
+    pub fn line_safe() -> Self {
+        Self::excluding(b"\0\n\r").expect("most values are left")
+    }
+
+    /// The byte values in the alphabet, in ascending order
+    pub fn symbols(&self) -> &[u8] {
+        &self.symbols
+    }
+
+    /// Pack `bytes` into bytes of the alphabet
+    pub fn encode(&self, bytes: &[u8]) -> Vec<u8> {
+        let base = self.symbols.len() as u32;
+        let mut out = Vec::with_capacity(bytes.len() + bytes.len() / 64 + 2);
+        for chunk in bytes.chunks(CHUNK_LEN) {
+            // Divide the chunk, a big endian number, by the base for each digit, least
+            // significant digit first
+            let mut number = chunk.to_vec();
+            let start = out.len();
+            for _ in 0..self.chunk_digits[chunk.len()] {
+                let mut remainder = 0;
+                for byte in &mut number {
+                    let value = (remainder << 8) | *byte as u32;
+                    *byte = (value / base) as u8;
+                    remainder = value % base;
+                }
+                out.push(self.symbols[remainder as usize]);
+            }
+            out[start..].reverse();
+        }
+        out
+    }
+
+    /// Unpack bytes produced by [`Alphabet::encode`]
+    pub fn decode(&self, packed: &[u8]) -> Result<Vec<u8>, crate::Error> {
+        let base = self.symbols.len() as u32;
+        let full = self.chunk_digits[CHUNK_LEN];
+        let last_digits = packed.len() % full;
+        let last_len = self
+            .chunk_digits
+            .iter()
+            .position(|&digits| digits == last_digits)
+            .ok_or(Error::InvalidPacking)?;
+
+        let mut out = Vec::with_capacity(packed.len());
+        for digits in packed.chunks(full) {
+            let len = if digits.len() == full {
+                CHUNK_LEN
+            } else {
+                last_len
+            };
+            // Multiply up a big endian number of `len` bytes, which must not overflow
+            let mut number = vec![0u8; len];
+            for &symbol in digits {
+                let mut carry = self.digits[symbol as usize].ok_or(Error::InvalidPacking)? as u32;
+                for byte in number.iter_mut().rev() {
+                    let value = *byte as u32 * base + carry;
+                    *byte = value as u8;
+                    carry = value >> 8;
+                }
+                if carry != 0 {
+                    return Err(Error::InvalidPacking.into());
+                }
+            }
+            out.extend_from_slice(&number);
+        }
+        Ok(out)
+    }
+}
+
+/// Compress a value and pack it into bytes of `alphabet`
+#[cfg(feature = "encode")]
+pub fn huff_to_alphabet<T>(value: &T, alphabet: &Alphabet) -> Result<Vec<u8>, crate::Error>
+where
+    T: serde::Serialize,
+{
+    Ok(alphabet.encode(&huff(value)?))
+}
+
+/// Decode bytes produced by [`huff_to_alphabet`] with the same alphabet
+#[cfg(feature = "decode")]
+pub fn puff_from_alphabet<T>(packed: &[u8], alphabet: &Alphabet) -> Result<T, crate::Error>
+where
+    T: serde::de::DeserializeOwned,
+{
+    puff(&alphabet.decode(packed)?)
+}
+
+/// Compress a value and encode it as URL-safe base64 without padding (RFC 4648 §5), which can be
+/// embedded in JSON strings, URLs and environment variables without escaping
+#[cfg(feature = "encode")]
+pub fn huff_to_base64<T>(value: &T) -> Result<String, crate::Error>
+where
+    T: serde::Serialize,
+{
+    Ok(URL_SAFE_NO_PAD.encode(huff(value)?))
+}
+
+/// Decode a string produced by [`huff_to_base64`]
+#[cfg(feature = "decode")]
+pub fn puff_from_base64<T>(text: &str) -> Result<T, crate::Error>
+where
+    T: serde::de::DeserializeOwned,
+{
+    let bytes = URL_SAFE_NO_PAD
+        .decode(text)
+        .map_err(|_| Error::InvalidBase64)?;
+    puff(&bytes)
+}
+
+/// Compress a value and encode it as lowercase hexadecimal
+#[cfg(feature = "encode")]
+pub fn huff_to_hex<T>(value: &T) -> Result<String, crate::Error>
+where
+    T: serde::Serialize,
+{
+    const DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+    let bytes = huff(value)?;
+    let mut text = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        text.push(DIGITS[(byte >> 4) as usize] as char);
+        text.push(DIGITS[(byte & 0xf) as usize] as char);
+    }
+    Ok(text)
+}
+
+/// Decode a string produced by [`huff_to_hex`]. Upper and lower case digits are both accepted.
+#[cfg(feature = "decode")]
+pub fn puff_from_hex<T>(text: &str) -> Result<T, crate::Error>
+where
+    T: serde::de::DeserializeOwned,
+{
+    fn digit(c: u8) -> Result<u8, Error> {
+        match c {
+            b'0'..=b'9' => Ok(c - b'0'),
+            b'a'..=b'f' => Ok(c - b'a' + 10),
+            b'A'..=b'F' => Ok(c - b'A' + 10),
+            _ => Err(Error::InvalidHex),
+        }
+    }
+
+    if !text.len().is_multiple_of(2) {
+        return Err(Error::InvalidHex.into());
+    }
+    let bytes = text
+        .as_bytes()
+        .chunks_exact(2)
+        .map(|pair| Ok((digit(pair[0])? << 4) | digit(pair[1])?))
+        .collect::<Result<Vec<u8>, Error>>()?;
+    puff(&bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base64_roundtrip() {
+        let message = "Hello, world! Hello, world!".to_owned();
+        let text = huff_to_base64(&message).unwrap();
+
+        assert!(
+            text.bytes()
+                .all(|c| c.is_ascii_alphanumeric() || c == b'-' || c == b'_')
+        );
+        assert_eq!(puff_from_base64::<String>(&text).unwrap(), message);
+    }
+
+    #[test]
+    fn hex_roundtrip() {
+        let values = vec![1u32, 1, 2, 3, 5, 8, 13];
+        let text = huff_to_hex(&values).unwrap();
+
+        assert!(text.bytes().all(|c| c.is_ascii_hexdigit()));
+        assert_eq!(puff_from_hex::<Vec<u32>>(&text).unwrap(), values);
+        assert_eq!(
+            puff_from_hex::<Vec<u32>>(&text.to_uppercase()).unwrap(),
+            values
+        );
+    }
+
+    #[test]
+    fn alphabet_roundtrip() {
+        let alphabets = [
+            Alphabet::line_safe(),
+            Alphabet::excluding(b"\0\r\n\"'\\").unwrap(),
+            Alphabet::excluding(&[]).unwrap(),
+            Alphabet::excluding(&(2..=255).collect::<Vec<u8>>()).unwrap(),
+        ];
+        let inputs: Vec<Vec<u8>> = vec![
+            vec![],
+            vec![0],
+            vec![255; 3],
+            (0..=255).cycle().take(1000).collect(),
+            (0..=255).cycle().take(CHUNK_LEN * 2).collect(),
+        ];
+        for alphabet in &alphabets {
+            for input in &inputs {
+                let packed = alphabet.encode(input);
+                assert!(packed.iter().all(|byte| alphabet.symbols().contains(byte)));
+                assert_eq!(&alphabet.decode(&packed).unwrap(), input);
+            }
+        }
+
+        let message = "a line of text\r\n".repeat(50);
+        let alphabet = Alphabet::line_safe();
+        let packed = huff_to_alphabet(&message, &alphabet).unwrap();
+        assert!(!packed.iter().any(|byte| b"\0\n\r".contains(byte)));
+        assert_eq!(
+            puff_from_alphabet::<String>(&packed, &alphabet).unwrap(),
+            message
+        );
+        // Far less than base64's third
+        let compressed = huff(&message).unwrap();
+        assert!(packed.len() * 100 <= compressed.len() * 102);
+    }
+
+    #[test]
+    fn invalid_alphabet() {
+        let alphabet = Alphabet::line_safe();
+        let packed = alphabet.encode(b"some bytes");
+        let mut outside = packed.clone();
+        outside[0] = b'\n';
+        assert!(matches!(
+            alphabet.decode(&outside),
+            Err(crate::Error::Text(Error::InvalidPacking))
+        ));
+        // The largest digits overflow the chunk
+        assert!(matches!(
+            alphabet.decode(&[255; 2]),
+            Err(crate::Error::Text(Error::InvalidPacking))
+        ));
+        assert!(matches!(
+            Alphabet::excluding(&(1..=255).collect::<Vec<u8>>()),
+            Err(crate::Error::Text(Error::InvalidAlphabet))
+        ));
+    }
+
+    #[test]
+    fn invalid_text() {
+        assert!(matches!(
+            puff_from_base64::<String>("not base64!"),
+            Err(crate::Error::Text(Error::InvalidBase64))
+        ));
+        assert!(matches!(
+            puff_from_hex::<String>("abc"),
+            Err(crate::Error::Text(Error::InvalidHex))
+        ));
+        assert!(matches!(
+            puff_from_hex::<String>("zz"),
+            Err(crate::Error::Text(Error::InvalidHex))
+        ));
+    }
+}
@@ -0,0 +1,342 @@
+//! Block stream adapters for tokio's `AsyncRead` and `AsyncWrite`, so compression can sit directly
+//! in a tokio pipeline
+
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll, ready};
+
+#[cfg(feature = "encode")]
+use tokio::io::AsyncWrite;
+#[cfg(feature = "decode")]
+use tokio::io::{AsyncRead, ReadBuf};
+
+#[cfg(feature = "encode")]
+use crate::block::BlockWriter;
+#[cfg(feature = "decode")]
+use crate::block::{self, BLOCK_HEADER_SIZE, HEADER_SIZE};
+#[cfg(feature = "decode")]
+use crate::frame;
+
+/// The tokio counterpart of [`HuffWriter`](crate::HuffWriter), writing a block stream to an
+/// [`AsyncWrite`]. Blocks are compressed as they fill up and written out as the underlying writer
+/// accepts them. Shutting the writer down writes the final block and the end marker.
+#[cfg(feature = "encode")]
+pub struct AsyncHuffWriter<W> {
+    writer: W,
+    /// Compresses into a buffer that is drained into `writer`. `None` once shut down.
+    blocks: Option<BlockWriter<Vec<u8>>>,
+    /// Output waiting to be written to `writer`
+    pending: Vec<u8>,
+    /// How much of `pending` has been written
+    written: usize,
+}
+
+#[cfg(feature = "encode")]
+impl<W: AsyncWrite + Unpin> AsyncHuffWriter<W> {
+    pub fn new(writer: W, block_size: usize) -> Result<Self, crate::Error> {
+        Ok(Self {
+            writer,
+            blocks: Some(BlockWriter::new(Vec::new(), block_size)?),
+            pending: Vec::new(),
+            written: 0,
+        })
+    }
+
+    /// A writer with [`DEFAULT_BLOCK_SIZE`](crate::DEFAULT_BLOCK_SIZE)
+    pub fn with_default_block_size(writer: W) -> Result<Self, crate::Error> {
+        Self::new(writer, crate::DEFAULT_BLOCK_SIZE)
+    }
+
+    /// The underlying writer
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    /// Write out everything that has been compressed so far
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if let Some(blocks) = &mut self.blocks {
+            self.pending.append(blocks.get_mut());
+        }
+        while self.written < self.pending.len() {
+            let buf = &self.pending[self.written..];
+            let len = ready!(Pin::new(&mut self.writer).poll_write(cx, buf))?;
+            if len == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.written += len;
+        }
+        self.pending.clear();
+        self.written = 0;
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(feature = "encode")]
+impl<W: AsyncWrite + Unpin> AsyncWrite for AsyncHuffWriter<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain(cx))?;
+        let Some(blocks) = &mut this.blocks else {
+            return Poll::Ready(Err(io::Error::other("write after shutdown")));
+        };
+        Poll::Ready(io::Write::write(blocks, buf))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.writer).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if let Some(blocks) = this.blocks.take() {
+            let mut rest = blocks.finish().map_err(io::Error::from)?;
+            this.pending.append(&mut rest);
+        }
+        ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.writer).poll_shutdown(cx)
+    }
+}
+
+/// What an [`AsyncPuffReader`] is reading from the source
+#[cfg(feature = "decode")]
+enum Next {
+    Header,
+    BlockHeader,
+    Frame { checksum: u32, digest: u32 },
+    End,
+}
+
+/// The tokio counterpart of [`PuffReader`](crate::PuffReader), reading a block stream from an
+/// [`AsyncRead`] and decompressing one block at a time. Each block's checksum and the rolling
+/// digest are checked before any of the block is returned.
+#[cfg(feature = "decode")]
+pub struct AsyncPuffReader<R> {
+    reader: R,
+    next: Next,
+    /// The part of the stream being read, which is `input.len()` bytes long once complete
+    input: Vec<u8>,
+    /// How much of `input` has been read
+    filled: usize,
+    block_size: usize,
+    /// The index of the next block
+    index: usize,
+    digest: crc32fast::Hasher,
+    /// The decompressed block being returned, and how much of it has been
+    block: Vec<u8>,
+    position: usize,
+}
+
+#[cfg(feature = "decode")]
+impl<R: AsyncRead + Unpin> AsyncPuffReader<R> {
+    /// Start reading a block stream. The header is read along with the first block.
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            next: Next::Header,
+            input: vec![0; HEADER_SIZE],
+            filled: 0,
+            block_size: 0,
+            index: 0,
+            digest: crc32fast::Hasher::new(),
+            block: Vec::new(),
+            position: 0,
+        }
+    }
+
+    /// The underlying reader
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Read the rest of `input` from the source
+    fn poll_input(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.filled < self.input.len() {
+            let mut buf = ReadBuf::new(&mut self.input[self.filled..]);
+            ready!(Pin::new(&mut self.reader).poll_read(cx, &mut buf))?;
+            if buf.filled().is_empty() {
+                return Poll::Ready(Err(crate::Error::from(block::Error::Truncated).into()));
+            }
+            self.filled += buf.filled().len();
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    /// Act on a complete `input`, and start reading whatever follows it
+    fn advance(&mut self) -> Result<(), crate::Error> {
+        let next_len = match self.next {
+            Next::Header => {
+                self.block_size =
+                    block::read_header(self.input.as_slice().try_into().unwrap())? as usize;
+                self.next = Next::BlockHeader;
+                BLOCK_HEADER_SIZE
+            }
+            Next::BlockHeader => {
+                let header = self.input.as_slice().try_into().unwrap();
+                let (len, checksum, digest) = block::read_block_header(header);
+                // Checked before `input` grows to the length, which a damaged header can make huge
+                if len > frame::max_len(self.block_size) {
+                    return Err(block::Error::WrongBlockLength(self.index).into());
+                }
+                self.next = Next::Frame { checksum, digest };
+                len
+            }
+            Next::Frame { checksum, digest } => {
+                let frame = &self.input;
+                if !frame.is_empty() && crc32fast::hash(frame) != checksum {
+                    return Err(block::Error::ChecksumMismatch(self.index).into());
+                }
+                self.digest.update(frame);
+                if self.digest.clone().finalize() != digest {
+                    return Err(block::Error::DigestMismatch(self.index).into());
+                }
+                if frame.is_empty() {
+                    self.next = Next::End;
+                    return Ok(());
+                }
+
+                // Only the last block may be short, so a short block before this one is an error
+                let short = !self.block.is_empty() && self.block.len() < self.block_size;
+                if short {
+                    return Err(block::Error::WrongBlockLength(self.index - 1).into());
+                }
+                self.block = block::decode_block(frame, self.block_size, self.index)?;
+                self.position = 0;
+                self.index += 1;
+                self.next = Next::BlockHeader;
+                BLOCK_HEADER_SIZE
+            }
+            Next::End => return Ok(()),
+        };
+        self.input.clear();
+        self.input.resize(next_len, 0);
+        self.filled = 0;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "decode")]
+impl<R: AsyncRead + Unpin> AsyncRead for AsyncPuffReader<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        while this.position == this.block.len() && !matches!(this.next, Next::End) {
+            ready!(this.poll_input(cx))?;
+            this.advance().map_err(io::Error::from)?;
+        }
+        let remaining = &this.block[this.position..];
+        let len = buf.remaining().min(remaining.len());
+        buf.put_slice(&remaining[..len]);
+        this.position += len;
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(all(test, feature = "encode", feature = "decode"))]
+mod tests {
+    use futures::executor::block_on;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    fn records() -> Vec<u8> {
+        (0..5_000u32)
+            .flat_map(|i| format!("{{\"id\":{i},\"status\":\"ok\"}}\n").into_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn roundtrip() {
+        let data = records();
+        let mut writer = AsyncHuffWriter::new(Vec::new(), 4096).unwrap();
+        block_on(async {
+            for chunk in data.chunks(1000) {
+                writer.write_all(chunk).await.unwrap();
+            }
+            writer.shutdown().await.unwrap();
+        });
+        let compressed = writer.into_inner();
+        assert_eq!(crate::decompress_blocks(&compressed).unwrap(), data);
+
+        let mut decompressed = Vec::new();
+        let mut reader = AsyncPuffReader::new(compressed.as_slice());
+        block_on(reader.read_to_end(&mut decompressed)).unwrap();
+        assert_eq!(decompressed, data);
+    }
+
+    #[test]
+    fn pipeline() {
+        let data = records();
+        // A small pipe, so the writer and reader take turns many times
+        let (client, server) = tokio::io::duplex(64);
+        let write = async {
+            let mut writer = AsyncHuffWriter::new(client, 1000).unwrap();
+            writer.write_all(&data).await.unwrap();
+            writer.shutdown().await.unwrap();
+        };
+        let read = async {
+            let mut decompressed = Vec::new();
+            let mut reader = AsyncPuffReader::new(server);
+            reader.read_to_end(&mut decompressed).await.unwrap();
+            decompressed
+        };
+        let ((), decompressed) = block_on(futures::future::join(write, read));
+        assert_eq!(decompressed, data);
+    }
+
+    #[test]
+    fn damaged() {
+        let data = records();
+        let compressed = crate::compress_blocks(&data, 4096).unwrap();
+        let read = |bytes: &[u8]| {
+            let mut decompressed = Vec::new();
+            let mut reader = AsyncPuffReader::new(bytes);
+            block_on(reader.read_to_end(&mut decompressed)).map_err(crate::Error::from)
+        };
+
+        assert_eq!(
+            read(&compressed[..compressed.len() - 1]),
+            Err(block::Error::Truncated.into())
+        );
+        let mut flipped = compressed.clone();
+        flipped[HEADER_SIZE + BLOCK_HEADER_SIZE + 5] ^= 1;
+        assert_eq!(
+            read(&flipped),
+            Err(block::Error::ChecksumMismatch(0).into())
+        );
+        // A frame of a few bytes that expands to a mebibyte, where blocks hold 16 bytes
+        let mut bomb = BlockWriter::new(Vec::new(), 16).unwrap().finish().unwrap();
+        let frame = frame::encode(
+            &vec![0; 1 << 20],
+            &frame::Header::default(),
+            &crate::EncodeOptions::default(),
+        )
+        .unwrap();
+        assert!(frame.len() < 32);
+        let mut digest = crc32fast::Hasher::new();
+        digest.update(&frame);
+        let mut block = Vec::new();
+        block.extend((frame.len() as u32).to_le_bytes());
+        block.extend(crc32fast::hash(&frame).to_le_bytes());
+        block.extend(digest.finalize().to_le_bytes());
+        block.extend(&frame);
+        bomb.splice(HEADER_SIZE..HEADER_SIZE, block);
+        assert_eq!(read(&bomb), Err(block::Error::WrongBlockLength(0).into()));
+
+        let mut long = compressed.clone();
+        long[HEADER_SIZE..HEADER_SIZE + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(read(&long), Err(block::Error::WrongBlockLength(0).into()));
+        assert_eq!(
+            read(b"not a block stream"),
+            Err(block::Error::BadMagic.into())
+        );
+    }
+}
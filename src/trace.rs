@@ -0,0 +1,191 @@
+//! A record of every code in a huffman coded message, for seeing exactly how a value was coded,
+//! for example when learning how huffman coding works or debugging a decoder of one's own.
+
+use std::fmt;
+
+use crate::{frame, huffman};
+
+/// One symbol of a message and the code that stands for it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceStep {
+    /// The byte, or `None` for the end of message marker
+    pub symbol: Option<u8>,
+    /// The code as a string of `0`s and `1`s, which is also the path from the root of the tree to
+    /// the symbol's leaf, `0` being left
+    pub code: String,
+    /// Where the code starts, in bits from the start of the message, which begins with the tree
+    pub bit_offset: usize,
+}
+
+/// Every code in a message, in order, as recorded by [`huff_traced`] or [`puff_traced`]
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Trace {
+    /// The number of bits the tree occupies at the start of the message, or `None` if the message
+    /// wasn't huffman coded with a tree of its own, in which case there are no steps
+    pub tree_bits: Option<usize>,
+    pub steps: Vec<TraceStep>,
+}
+
+impl Trace {
+    /// Trace the huffman coded message in a frame. Transforms are applied before coding, so with
+    /// transforms the symbols are the transformed bytes.
+    fn of_frame(bytes: &[u8]) -> Result<Self, crate::Error> {
+        let (header, start) = frame::read_header(bytes)?;
+        if header.encoding != frame::Encoding::Huffman
+            || header.codebook.is_some()
+            || header.canonical
+            || header.sealed.is_some()
+        {
+            return Ok(Self::default());
+        }
+
+        let (tree_bits, codes) = huffman::decode_traced(&bytes[start..], header.bit_order)?;
+        let steps = codes
+            .into_iter()
+            .map(|code| TraceStep {
+                symbol: code.symbol,
+                code: code
+                    .path
+                    .iter()
+                    .map(|&bit| if bit { '1' } else { '0' })
+                    .collect(),
+                bit_offset: code.bit_offset,
+            })
+            .collect();
+        Ok(Self {
+            tree_bits: Some(tree_bits),
+            steps,
+        })
+    }
+
+    /// The steps as a JSON array of objects with the `symbol`, `code` and `bit_offset` of each
+    /// step, where the end of message has `"eom": true` instead of a symbol
+    pub fn to_json(&self) -> String {
+        let steps: Vec<String> = self
+            .steps
+            .iter()
+            .map(|step| {
+                let symbol = match step.symbol {
+                    Some(symbol) => format!("\"symbol\":{symbol}"),
+                    None => "\"eom\":true".to_owned(),
+                };
+                format!(
+                    "{{{symbol},\"code\":\"{}\",\"bit_offset\":{}}}",
+                    step.code, step.bit_offset
+                )
+            })
+            .collect();
+        format!("[{}]", steps.join(","))
+    }
+}
+
+/// One line per step, with the bit offset, the symbol and its code
+impl fmt::Display for Trace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Some(tree_bits) = self.tree_bits else {
+            return writeln!(f, "not huffman coded");
+        };
+        writeln!(f, "tree: {tree_bits} bits")?;
+        for step in &self.steps {
+            let symbol = match step.symbol {
+                Some(byte) if byte.is_ascii_graphic() || byte == b' ' => {
+                    format!("'{}'", byte as char)
+                }
+                Some(byte) => format!("0x{byte:02x}"),
+                None => "EOM".to_owned(),
+            };
+            writeln!(f, "{:>6}  {symbol:<4}  {}", step.bit_offset, step.code)?;
+        }
+        Ok(())
+    }
+}
+
+/// Like [`huff`](crate::huff), but also traces the codes that were written
+#[cfg(feature = "encode")]
+pub fn huff_traced<T>(value: &T) -> Result<(Vec<u8>, Trace), crate::Error>
+where
+    T: serde::Serialize,
+{
+    let compressed = crate::huff(value)?;
+    let trace = Trace::of_frame(&compressed)?;
+    Ok((compressed, trace))
+}
+
+/// Like [`puff`](crate::puff), but also traces the codes that were read
+pub fn puff_traced<T>(bytes: &[u8]) -> Result<(T, Trace), crate::Error>
+where
+    T: serde::de::DeserializeOwned,
+{
+    let value = crate::puff(bytes)?;
+    Ok((value, Trace::of_frame(bytes)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codes_match_the_tree() {
+        let value = "abracadabra, abracadabra".repeat(4);
+        let (compressed, trace) = huff_traced(&value).unwrap();
+        let (decoded, decode_trace) = puff_traced::<String>(&compressed).unwrap();
+        assert_eq!(decoded, value);
+        assert_eq!(decode_trace, trace);
+
+        // Every byte of the serialized value, then EOM
+        let bytes = bincode::serialize(&value).unwrap();
+        assert_eq!(trace.steps.len(), bytes.len() + 1);
+        assert_eq!(trace.steps.last().unwrap().symbol, None);
+        for (step, byte) in trace.steps.iter().zip(&bytes) {
+            assert_eq!(step.symbol, Some(*byte));
+        }
+
+        // Codes follow each other without gaps, each always the same for its symbol
+        let mut offset = trace.tree_bits.unwrap();
+        for step in &trace.steps {
+            assert_eq!(step.bit_offset, offset);
+            offset += step.code.len();
+            let first = trace
+                .steps
+                .iter()
+                .find(|s| s.symbol == step.symbol)
+                .unwrap();
+            assert_eq!(step.code, first.code);
+        }
+        // 'a' is the most common letter, so it has the shortest code of any letter
+        let code_len = |symbol| {
+            let step = trace.steps.iter().find(|s| s.symbol == Some(symbol));
+            step.unwrap().code.len()
+        };
+        assert!(
+            b"brcd, "
+                .iter()
+                .all(|&symbol| code_len(b'a') <= code_len(symbol))
+        );
+    }
+
+    #[test]
+    fn output() {
+        let (_, trace) = huff_traced(&"hello, world. ".repeat(10)).unwrap();
+        let text = trace.to_string();
+        assert!(text.starts_with(&format!("tree: {} bits\n", trace.tree_bits.unwrap())));
+        assert_eq!(text.lines().count(), trace.steps.len() + 1);
+        assert!(text.lines().last().unwrap().contains("EOM"));
+
+        let json = trace.to_json();
+        assert!(json.starts_with("[{\"symbol\":"));
+        assert!(json.ends_with("}]"));
+        assert_eq!(json.matches("\"eom\":true").count(), 1);
+    }
+
+    #[test]
+    fn not_huffman_coded() {
+        let (compressed, trace) = huff_traced(&7u8).unwrap();
+        assert_eq!(trace, Trace::default());
+        assert_eq!(
+            puff_traced::<u8>(&compressed).unwrap(),
+            (7, Trace::default())
+        );
+        assert_eq!(trace.to_string(), "not huffman coded\n");
+    }
+}
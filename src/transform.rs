@@ -0,0 +1,605 @@
+//! Reversible transforms that can be applied to the data before it is coded, to expose structure
+//! that the huffman coder can't see by itself. The transforms applied are recorded in the frame
+//! header, and reversed automatically when decoding.
+
+#[cfg(feature = "decode")]
+use std::collections::TryReserveError;
+
+#[cfg(feature = "decode")]
+use crate::frame;
+#[cfg(feature = "encode")]
+use crate::options::EncodeOptions;
+#[cfg(feature = "decode")]
+use crate::reserve;
+use crate::varint;
+
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The run length coded data ends in the middle of a run
+    MalformedRunLength,
+    /// The Burrows-Wheeler transformed data is missing its index, or the index is out of range
+    MalformedBwt,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Error::MalformedRunLength => "the run length coded data ends in the middle of a run",
+            Error::MalformedBwt => "the Burrows-Wheeler transformed data has no valid index",
+        })
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Transform {
+    /// Replaces each byte with its difference from the previous one, which turns slowly changing
+    /// sequences like counters and samples into runs of small values
+    Delta,
+    /// Shortens runs of a repeated byte: after two equal bytes, a count of further repetitions
+    /// follows
+    RunLength,
+    /// Replaces each byte with its position in a list of recently seen bytes, which turns local
+    /// repetition into small values. Works well after [`Transform::Bwt`].
+    MoveToFront,
+    /// The Burrows-Wheeler transform, which groups bytes that appear in similar contexts together.
+    /// It needs the whole input in memory, and sorts it, so it is the slowest transform.
+    Bwt,
+    /// Replaces bytes according to a table, see [`Remap`]
+    Remap(Remap),
+}
+
+impl Transform {
+    #[cfg(feature = "encode")]
+    fn id(&self) -> u8 {
+        match self {
+            Transform::Delta => 0,
+            Transform::RunLength => 1,
+            Transform::MoveToFront => 2,
+            Transform::Bwt => 3,
+            Transform::Remap(_) => 4,
+        }
+    }
+
+    /// Write the transform to a frame header: its id, followed by any parameters
+    #[cfg(feature = "encode")]
+    pub(crate) fn write(&self, out: &mut Vec<u8>) {
+        out.push(self.id());
+        if let Transform::Remap(remap) = self {
+            remap.write(out);
+        }
+    }
+
+    /// Read a transform written by [`Transform::write`], advancing past it
+    #[cfg(feature = "decode")]
+    pub(crate) fn read(bytes: &mut &[u8]) -> Result<Self, frame::Error> {
+        let (&id, rest) = bytes.split_first().ok_or(frame::Error::TruncatedHeader)?;
+        *bytes = rest;
+        match id {
+            0 => Ok(Transform::Delta),
+            1 => Ok(Transform::RunLength),
+            2 => Ok(Transform::MoveToFront),
+            3 => Ok(Transform::Bwt),
+            4 => Ok(Transform::Remap(Remap::read(bytes)?)),
+            _ => Err(frame::Error::UnsupportedTransform(id)),
+        }
+    }
+
+    #[cfg(feature = "encode")]
+    pub(crate) fn apply(&self, bytes: &[u8]) -> Vec<u8> {
+        match self {
+            Transform::Delta => delta(bytes),
+            Transform::RunLength => run_length(bytes),
+            Transform::MoveToFront => move_to_front(bytes),
+            Transform::Bwt => bwt(bytes),
+            Transform::Remap(remap) => remap.apply(bytes),
+        }
+    }
+
+    /// Only run length coding makes the data longer when it's reversed, and it stops once the
+    /// output is longer than `max_len`
+    #[cfg(feature = "decode")]
+    pub(crate) fn invert(&self, bytes: &[u8], max_len: usize) -> Result<Vec<u8>, crate::Error> {
+        match self {
+            Transform::Delta => Ok(undo_delta(bytes)?),
+            Transform::RunLength => {
+                Ok(undo_run_length(bytes, max_len)?.ok_or(Error::MalformedRunLength)?)
+            }
+            Transform::MoveToFront => Ok(undo_move_to_front(bytes)?),
+            Transform::Bwt => Ok(undo_bwt(bytes)?.ok_or(Error::MalformedBwt)?),
+            Transform::Remap(remap) => Ok(remap.inverse().apply(bytes)),
+        }
+    }
+}
+
+/// A bijective mapping of byte values. Data that only uses a few scattered byte values, like
+/// text in a custom character set, can get a better tree when those values are mapped to a dense
+/// range. The header only records the bytes that move, so small remappings are cheap.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Remap {
+    table: Box<[u8; 256]>,
+}
+
+impl Remap {
+    /// A remapping that replaces each byte `b` with `table[b]`. Returns `None` unless every byte
+    /// value appears in `table` exactly once, since the mapping couldn't be reversed otherwise.
+    pub fn new(table: [u8; 256]) -> Option<Self> {
+        let mut seen = [false; 256];
+        for &byte in &table {
+            if std::mem::replace(&mut seen[byte as usize], true) {
+                return None;
+            }
+        }
+        Some(Self {
+            table: Box::new(table),
+        })
+    }
+
+    /// A remapping that maps `symbols` to `0..symbols.len()`, in order, with the remaining byte
+    /// values filling the rest of the range. Returns `None` if `symbols` has duplicates.
+    pub fn dense(symbols: &[u8]) -> Option<Self> {
+        let mut table = [0u8; 256];
+        let mut used = [false; 256];
+        for (to, &from) in symbols.iter().enumerate() {
+            if std::mem::replace(&mut used[from as usize], true) {
+                return None;
+            }
+            table[from as usize] = to as u8;
+        }
+        let others = (0..=255u8).filter(|&byte| !used[byte as usize]);
+        for (to, from) in (symbols.len()..).zip(others) {
+            table[from as usize] = to as u8;
+        }
+        Self::new(table)
+    }
+
+    pub fn table(&self) -> &[u8; 256] {
+        &self.table
+    }
+
+    /// The remapping that undoes this one
+    pub fn inverse(&self) -> Self {
+        let mut table = [0u8; 256];
+        for (from, &to) in self.table.iter().enumerate() {
+            table[to as usize] = from as u8;
+        }
+        Self {
+            table: Box::new(table),
+        }
+    }
+
+    fn apply(&self, bytes: &[u8]) -> Vec<u8> {
+        bytes
+            .iter()
+            .map(|&byte| self.table[byte as usize])
+            .collect()
+    }
+
+    /// The number of moved bytes as a varint, followed by each one and its replacement
+    #[cfg(feature = "encode")]
+    fn write(&self, out: &mut Vec<u8>) {
+        let moved = (0..=255u8).filter(|&byte| self.table[byte as usize] != byte);
+        varint::write(moved.clone().count() as u64, out);
+        for byte in moved {
+            out.extend_from_slice(&[byte, self.table[byte as usize]]);
+        }
+    }
+
+    #[cfg(feature = "decode")]
+    fn read(bytes: &mut &[u8]) -> Result<Self, frame::Error> {
+        let count = varint::read(bytes).ok_or(frame::Error::TruncatedHeader)?;
+        let mut table: [u8; 256] = std::array::from_fn(|byte| byte as u8);
+        for _ in 0..count {
+            let ([from, to], rest) = bytes
+                .split_first_chunk()
+                .ok_or(frame::Error::TruncatedHeader)?;
+            table[*from as usize] = *to;
+            *bytes = rest;
+        }
+        Self::new(table).ok_or(frame::Error::InvalidRemap)
+    }
+}
+
+/// Builds a chain of transforms to apply before coding:
+///
+/// ```
+/// use huffnpuff::{Pipeline, puff};
+///
+/// // A slowly rising signal, which is all small steps once delta coded
+/// let samples: Vec<u8> = (0..1000).map(|i| (i / 4) as u8).collect();
+/// let compressed = Pipeline::new().delta().run_length().huff(&samples).unwrap();
+/// assert!(compressed.len() < huffnpuff::huff(&samples).unwrap().len());
+///
+/// // The transforms are recorded in the header, so decoding doesn't need to know about them
+/// assert_eq!(puff::<Vec<u8>>(&compressed).unwrap(), samples);
+/// ```
+#[cfg(feature = "encode")]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Pipeline {
+    options: EncodeOptions,
+}
+
+#[cfg(feature = "encode")]
+impl Pipeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start from `options`, adding stages after any transforms it already has
+    pub fn with_options(options: EncodeOptions) -> Self {
+        Self { options }
+    }
+
+    /// Add a stage, which is applied after the stages added before it
+    pub fn then(mut self, transform: Transform) -> Self {
+        self.options.transforms.push(transform);
+        self
+    }
+
+    pub fn delta(self) -> Self {
+        self.then(Transform::Delta)
+    }
+
+    pub fn run_length(self) -> Self {
+        self.then(Transform::RunLength)
+    }
+
+    pub fn move_to_front(self) -> Self {
+        self.then(Transform::MoveToFront)
+    }
+
+    pub fn bwt(self) -> Self {
+        self.then(Transform::Bwt)
+    }
+
+    pub fn remap(self, remap: Remap) -> Self {
+        self.then(Transform::Remap(remap))
+    }
+
+    /// The options for coding with this pipeline, for use with the other encoding functions
+    pub fn options(&self) -> &EncodeOptions {
+        &self.options
+    }
+
+    /// The options for coding with this pipeline, taking them out of it
+    pub fn into_options(self) -> EncodeOptions {
+        self.options
+    }
+
+    /// Encode and compress a value through the pipeline. The result is decoded with
+    /// [`puff`](crate::puff) as usual.
+    pub fn huff<T>(&self, value: &T) -> Result<Vec<u8>, crate::Error>
+    where
+        T: serde::Serialize,
+    {
+        crate::huff_with_options(value, &self.options)
+    }
+}
+
+/// Apply `transforms` in order
+#[cfg(feature = "encode")]
+pub(crate) fn apply_all(transforms: &[Transform], bytes: &[u8]) -> Vec<u8> {
+    let mut bytes = bytes.to_vec();
+    for transform in transforms {
+        bytes = transform.apply(&bytes);
+    }
+    bytes
+}
+
+/// Reverse `transforms`, which were applied in order. Reversing stops once the output is longer
+/// than `max_len`, which callers treat as an error.
+#[cfg(feature = "decode")]
+pub(crate) fn invert_all(
+    transforms: &[Transform],
+    bytes: Vec<u8>,
+    max_len: usize,
+) -> Result<Vec<u8>, crate::Error> {
+    let mut bytes = bytes;
+    for transform in transforms.iter().rev() {
+        if bytes.len() > max_len {
+            break;
+        }
+        bytes = transform.invert(&bytes, max_len)?;
+    }
+    Ok(bytes)
+}
+
+#[cfg(feature = "encode")]
+fn delta(bytes: &[u8]) -> Vec<u8> {
+    let mut previous = 0u8;
+    bytes
+        .iter()
+        .map(|&byte| {
+            let delta = byte.wrapping_sub(previous);
+            previous = byte;
+            delta
+        })
+        .collect()
+}
+
+#[cfg(feature = "decode")]
+fn undo_delta(bytes: &[u8]) -> Result<Vec<u8>, TryReserveError> {
+    let mut out = reserve::with_capacity(bytes.len())?;
+    let mut previous = 0u8;
+    out.extend(bytes.iter().map(|&delta| {
+        previous = previous.wrapping_add(delta);
+        previous
+    }));
+    Ok(out)
+}
+
+#[cfg(feature = "encode")]
+fn run_length(bytes: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut rest = bytes;
+    while let Some(&byte) = rest.first() {
+        // Two bytes and up to 255 repetitions
+        let run = rest
+            .iter()
+            .take(2 + u8::MAX as usize)
+            .take_while(|&&b| b == byte)
+            .count();
+        if run == 1 {
+            out.push(byte);
+            rest = &rest[1..];
+        } else {
+            out.extend_from_slice(&[byte, byte, (run - 2) as u8]);
+            rest = &rest[run..];
+        }
+    }
+    out
+}
+
+#[cfg(feature = "decode")]
+/// `None` if the data is malformed
+fn undo_run_length(bytes: &[u8], max_len: usize) -> Result<Option<Vec<u8>>, TryReserveError> {
+    let mut out = reserve::with_capacity(bytes.len())?;
+    let mut rest = bytes;
+    while out.len() <= max_len
+        && let Some((&byte, tail)) = rest.split_first()
+    {
+        match tail {
+            [next, count, tail @ ..] if *next == byte => {
+                let len = out.len() + 2 + *count as usize;
+                reserve::resize(&mut out, len, byte)?;
+                rest = tail;
+            }
+            [next] if *next == byte => return Ok(None),
+            _ => {
+                reserve::push(&mut out, byte)?;
+                rest = tail;
+            }
+        }
+    }
+    Ok(Some(out))
+}
+
+#[cfg(feature = "encode")]
+fn move_to_front(bytes: &[u8]) -> Vec<u8> {
+    let mut recent: Vec<u8> = (0..=255).collect();
+    bytes
+        .iter()
+        .map(|&byte| {
+            let position = recent.iter().position(|&b| b == byte).unwrap();
+            recent.remove(position);
+            recent.insert(0, byte);
+            position as u8
+        })
+        .collect()
+}
+
+#[cfg(feature = "decode")]
+fn undo_move_to_front(bytes: &[u8]) -> Result<Vec<u8>, TryReserveError> {
+    let mut out = reserve::with_capacity(bytes.len())?;
+    let mut recent: Vec<u8> = (0..=255).collect();
+    out.extend(bytes.iter().map(|&position| {
+        let byte = recent.remove(position as usize);
+        recent.insert(0, byte);
+        byte
+    }));
+    Ok(out)
+}
+
+/// The last column of the sorted rotations of `bytes`, preceded by the index of the original
+/// string among the rotations as a varint
+#[cfg(feature = "encode")]
+fn bwt(bytes: &[u8]) -> Vec<u8> {
+    let n = bytes.len();
+    let mut out = Vec::with_capacity(n + 4);
+    if n == 0 {
+        varint::write(0, &mut out);
+        return out;
+    }
+
+    // Sort the rotations by prefix doubling: after each round, rotations are ranked by their
+    // first `2 * k` bytes
+    let mut rotations: Vec<usize> = (0..n).collect();
+    let mut rank: Vec<usize> = bytes.iter().map(|&byte| byte as usize).collect();
+    let mut next_rank = vec![0; n];
+    let mut k = 1;
+    loop {
+        let key = |i: usize| (rank[i], rank[(i + k) % n]);
+        rotations.sort_unstable_by_key(|&i| key(i));
+        next_rank[rotations[0]] = 0;
+        for pair in rotations.windows(2) {
+            let distinct = key(pair[0]) != key(pair[1]);
+            next_rank[pair[1]] = next_rank[pair[0]] + distinct as usize;
+        }
+        std::mem::swap(&mut rank, &mut next_rank);
+        if rank[rotations[n - 1]] == n - 1 || k >= n {
+            break;
+        }
+        k *= 2;
+    }
+
+    let primary = rotations.iter().position(|&i| i == 0).unwrap();
+    varint::write(primary as u64, &mut out);
+    out.extend(rotations.iter().map(|&i| bytes[(i + n - 1) % n]));
+    out
+}
+
+#[cfg(feature = "decode")]
+/// `None` if the data is malformed
+fn undo_bwt(bytes: &[u8]) -> Result<Option<Vec<u8>>, TryReserveError> {
+    let mut last = bytes;
+    let Some(primary) = varint::read(&mut last).and_then(|primary| usize::try_from(primary).ok())
+    else {
+        return Ok(None);
+    };
+    let n = last.len();
+    if n == 0 {
+        return Ok((primary == 0).then(Vec::new));
+    }
+    if primary >= n {
+        return Ok(None);
+    }
+
+    // The first column is the sorted last column, and the same occurrence of a byte has the same
+    // rank in both. Following that mapping from the last column to the first walks the original
+    // string backwards.
+    let mut starts = [0usize; 256];
+    for &byte in last {
+        starts[byte as usize] += 1;
+    }
+    let mut total = 0;
+    for start in starts.iter_mut() {
+        let count = *start;
+        *start = total;
+        total += count;
+    }
+    let mut seen = [0usize; 256];
+    let mut next = reserve::with_capacity(n)?;
+    next.extend(last.iter().map(|&byte| {
+        let index = starts[byte as usize] + seen[byte as usize];
+        seen[byte as usize] += 1;
+        index
+    }));
+
+    let mut out = Vec::new();
+    reserve::resize(&mut out, n, 0)?;
+    let mut row = primary;
+    for slot in out.iter_mut().rev() {
+        *slot = last[row];
+        row = next[row];
+    }
+    Ok(Some(out))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn all() -> [Transform; 5] {
+        [
+            Transform::Delta,
+            Transform::RunLength,
+            Transform::MoveToFront,
+            Transform::Bwt,
+            Transform::Remap(Remap::new(std::array::from_fn(|i| 255 - i as u8)).unwrap()),
+        ]
+    }
+
+    fn inputs() -> Vec<Vec<u8>> {
+        vec![
+            vec![],
+            vec![7],
+            vec![0; 1000],
+            b"banana".to_vec(),
+            b"abracadabra abracadabra abracadabra".to_vec(),
+            (0..=255).chain((0..=255).rev()).collect(),
+            (0..2000u32).map(|i| (i * i % 251) as u8).collect(),
+        ]
+    }
+
+    #[test]
+    fn roundtrip() {
+        for transform in all() {
+            let mut written = Vec::new();
+            transform.write(&mut written);
+            assert_eq!(Transform::read(&mut &written[..]).unwrap(), transform);
+            for input in inputs() {
+                let transformed = transform.apply(&input);
+                assert_eq!(
+                    transform.invert(&transformed, usize::MAX).unwrap(),
+                    input,
+                    "{transform:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn bwt_banana() {
+        assert_eq!(bwt(b"banana"), [3, b'n', b'n', b'b', b'a', b'a', b'a']);
+    }
+
+    #[test]
+    fn malformed() {
+        assert!(Transform::RunLength.invert(&[1, 1], usize::MAX).is_err());
+        assert!(Transform::Bwt.invert(&[5, b'a', b'b'], usize::MAX).is_err());
+        assert!(Transform::Bwt.invert(&[], usize::MAX).is_err());
+    }
+
+    #[test]
+    fn remap() {
+        let remap = Remap::dense(b"-+*/").unwrap();
+        assert_eq!(remap.apply(b"+-/"), [1, 0, 3]);
+        assert_eq!(remap.inverse().apply(&[1, 0, 3]), b"+-/");
+        assert!(Remap::dense(b"aa").is_none());
+        assert!(Remap::new([0; 256]).is_none());
+
+        // Only the bytes that move are stored
+        let mut written = Vec::new();
+        Transform::Remap(Remap::dense(&[0, 1, 3, 2]).unwrap()).write(&mut written);
+        assert_eq!(written, [4, 2, 2, 3, 3, 2]);
+
+        let mut invalid: &[u8] = &[1, 0, 1];
+        assert!(matches!(
+            Remap::read(&mut invalid),
+            Err(frame::Error::InvalidRemap)
+        ));
+    }
+
+    #[test]
+    fn chained() {
+        let transforms = [Transform::Bwt, Transform::MoveToFront, Transform::RunLength];
+        let input = b"she sells sea shells by the sea shore".repeat(5);
+        let transformed = apply_all(&transforms, &input);
+        assert_eq!(
+            invert_all(&transforms, transformed, usize::MAX).unwrap(),
+            input
+        );
+    }
+
+    #[test]
+    fn sparse_structs() {
+        #[derive(serde::Serialize)]
+        struct Sparse {
+            id: u64,
+            counts: [u32; 32],
+        }
+        let records: Vec<Sparse> = (0..100)
+            .map(|i| {
+                let mut counts = [0; 32];
+                counts[i % 32] = i as u32 + 1;
+                Sparse {
+                    id: i as u64,
+                    counts,
+                }
+            })
+            .collect();
+        let options = Pipeline::new().run_length().into_options();
+        let with_runs = crate::huff_with_options(&records, &options).unwrap();
+        assert!(with_runs.len() < crate::huff(&records).unwrap().len());
+
+        let options = Pipeline::with_options(options)
+            .move_to_front()
+            .into_options();
+        let both = crate::huff_with_options(&records, &options).unwrap();
+        let decoded: Vec<(u64, [u32; 32])> = crate::puff(&both).unwrap();
+        assert_eq!(decoded.len(), 100);
+        assert_eq!(decoded[7], (7, records[7].counts));
+    }
+}
@@ -0,0 +1,174 @@
+//! Length-prefixed frames over blocking byte streams like sockets and pipes. Each value is
+//! compressed and written after its length as a little-endian `u32`, so the reader knows how much
+//! to read before decoding it.
+
+#[cfg(feature = "encode")]
+use std::io::Write;
+#[cfg(feature = "decode")]
+use std::io::{self, Read};
+
+/// The largest frame [`read_frame`] accepts
+pub const DEFAULT_MAX_FRAME_LEN: usize = 16 * 1024 * 1024;
+
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The frame is this many bytes long, which is more than the reader accepts, or more than the
+    /// length prefix can hold
+    FrameTooLarge(usize),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::FrameTooLarge(len) => {
+                write!(f, "the frame is {len} bytes long, which is too large")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Compress `value` and write it to `writer` as a length-prefixed frame
+#[cfg(feature = "encode")]
+pub fn write_frame<W, T>(writer: &mut W, value: &T) -> Result<(), crate::Error>
+where
+    W: Write,
+    T: serde::Serialize,
+{
+    write_prefixed(writer, &crate::huff(value)?)
+}
+
+/// Write `bytes` after their length
+#[cfg(feature = "encode")]
+pub(crate) fn write_prefixed<W: Write>(writer: &mut W, bytes: &[u8]) -> Result<(), crate::Error> {
+    let len = u32::try_from(bytes.len()).map_err(|_| Error::FrameTooLarge(bytes.len()))?;
+    writer.write_all(&len.to_le_bytes())?;
+    writer.write_all(bytes)?;
+    Ok(())
+}
+
+/// Read a frame written by [`write_frame`] and decode it, waiting for the whole frame to arrive.
+/// Returns `None` if the stream ends cleanly before the next frame starts; ending anywhere else
+/// is an error. Frames longer than [`DEFAULT_MAX_FRAME_LEN`] are rejected before anything is
+/// allocated for them.
+#[cfg(feature = "decode")]
+pub fn read_frame<R, T>(reader: &mut R) -> Result<Option<T>, crate::Error>
+where
+    R: Read,
+    T: serde::de::DeserializeOwned,
+{
+    read_frame_limited(reader, DEFAULT_MAX_FRAME_LEN)
+}
+
+/// Like [`read_frame`], but with a different limit on the length of a frame
+#[cfg(feature = "decode")]
+pub fn read_frame_limited<R, T>(reader: &mut R, max_len: usize) -> Result<Option<T>, crate::Error>
+where
+    R: Read,
+    T: serde::de::DeserializeOwned,
+{
+    match read_prefixed(reader, max_len)? {
+        Some(compressed) => crate::puff(&compressed).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Read bytes written by [`write_prefixed`], or `None` if the stream ends before their length
+#[cfg(feature = "decode")]
+pub(crate) fn read_prefixed<R: Read>(
+    reader: &mut R,
+    max_len: usize,
+) -> Result<Option<Vec<u8>>, crate::Error> {
+    let mut prefix = [0; 4];
+    let mut filled = 0;
+    while filled < prefix.len() {
+        match reader.read(&mut prefix[filled..]) {
+            Ok(0) if filled == 0 => return Ok(None),
+            Ok(0) => return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+            Ok(n) => filled += n,
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+            Err(error) => return Err(error.into()),
+        }
+    }
+
+    let len = u32::from_le_bytes(prefix) as usize;
+    if len > max_len {
+        return Err(Error::FrameTooLarge(len).into());
+    }
+    let mut bytes = vec![0; len];
+    reader.read_exact(&mut bytes)?;
+    Ok(Some(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Hands out at most `chunk` bytes per read, like a socket receiving a few packets at a time
+    struct Trickle<'a> {
+        bytes: &'a [u8],
+        chunk: usize,
+    }
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = buf.len().min(self.chunk).min(self.bytes.len());
+            buf[..len].copy_from_slice(&self.bytes[..len]);
+            self.bytes = &self.bytes[len..];
+            Ok(len)
+        }
+    }
+
+    #[test]
+    fn roundtrip() {
+        let messages = ["first", "second, which is a little longer", ""].map(str::to_owned);
+        let mut stream = Vec::new();
+        for message in &messages {
+            write_frame(&mut stream, message).unwrap();
+        }
+
+        let mut reader = Trickle {
+            bytes: &stream,
+            chunk: 3,
+        };
+        for message in &messages {
+            assert_eq!(
+                read_frame::<_, String>(&mut reader).unwrap().as_ref(),
+                Some(message)
+            );
+        }
+        assert!(read_frame::<_, String>(&mut reader).unwrap().is_none());
+    }
+
+    #[test]
+    fn truncated() {
+        let mut stream = Vec::new();
+        write_frame(&mut stream, &"cut off in the middle".repeat(4)).unwrap();
+
+        for len in [2, stream.len() - 1] {
+            let mut reader = &stream[..len];
+            assert!(matches!(
+                read_frame::<_, String>(&mut reader),
+                Err(crate::Error::Io(error)) if error.kind() == io::ErrorKind::UnexpectedEof
+            ));
+        }
+    }
+
+    #[test]
+    fn too_large() {
+        let mut stream = Vec::new();
+        write_frame(&mut stream, &vec![7u32; 1000]).unwrap();
+        assert!(matches!(
+            read_frame_limited::<_, Vec<u32>>(&mut stream.as_slice(), 8),
+            Err(crate::Error::Transport(Error::FrameTooLarge(_)))
+        ));
+
+        let huge = u32::MAX.to_le_bytes();
+        assert!(matches!(
+            read_frame::<_, Vec<u32>>(&mut huge.as_slice()),
+            Err(crate::Error::Transport(Error::FrameTooLarge(_)))
+        ));
+    }
+}
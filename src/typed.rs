@@ -0,0 +1,157 @@
+use std::marker::PhantomData;
+
+use crate::codebook::{Codebook, CodebookStats};
+use crate::options::EncodeOptions;
+use crate::{Error, frame};
+
+/// Compresses a series of values of one type, training a [`Codebook`] on them as it goes. The
+/// first `warmup` values are compressed on their own, as with [`huff`](crate::huff), and
+/// collected as samples. After that the codebook is trained and frozen, and every following value
+/// is coded with it, leaving the tree out of the message. For many small, similar values this
+/// gets close to the ratio of compressing them all together, while each value can still be
+/// decoded on its own.
+///
+/// Everything this produces can be decoded with [`puff_with_codebook`](crate::puff_with_codebook)
+/// and the encoder's [codebook](TypedEncoder::codebook), which the receiving side needs to be sent
+/// once it's trained.
+pub struct TypedEncoder<T> {
+    warmup: usize,
+    samples: Vec<Vec<u8>>,
+    codebook: Option<Codebook>,
+    stats: Option<CodebookStats>,
+    options: EncodeOptions,
+    _type: PhantomData<fn(&T)>,
+}
+
+impl<T: serde::Serialize> TypedEncoder<T> {
+    /// An encoder that trains its codebook on the first `warmup` values
+    pub fn new(warmup: usize) -> Self {
+        Self {
+            warmup,
+            samples: Vec::new(),
+            codebook: None,
+            stats: None,
+            options: EncodeOptions::default(),
+            _type: PhantomData,
+        }
+    }
+
+    /// An encoder that uses an existing codebook from the start, for example one trained by an
+    /// earlier run
+    pub fn with_codebook(codebook: Codebook) -> Self {
+        Self {
+            stats: Some(CodebookStats::new(&codebook)),
+            codebook: Some(codebook),
+            ..Self::new(0)
+        }
+    }
+
+    /// Compress `value`, training the codebook if this completes the warmup
+    pub fn encode(&mut self, value: &T) -> Result<Vec<u8>, Error> {
+        let bincoded_bytes = bincode::serialize(value)?;
+        let header = frame::Header {
+            fingerprint: Some(crate::fingerprint(std::any::type_name::<T>())),
+            ..frame::Header::default()
+        };
+        let encoded = frame::encode_with_codebook(
+            &bincoded_bytes,
+            &header,
+            &self.options,
+            self.codebook.as_ref(),
+        )?;
+
+        match &mut self.stats {
+            Some(stats) => stats.record(&bincoded_bytes),
+            None => {
+                self.samples.push(bincoded_bytes);
+                if self.samples.len() >= self.warmup {
+                    let codebook = Codebook::train(std::mem::take(&mut self.samples));
+                    self.stats = Some(CodebookStats::new(&codebook));
+                    self.codebook = Some(codebook);
+                }
+            }
+        }
+        Ok(encoded)
+    }
+
+    /// How well the codebook fits the values coded with it since it was trained, or since the
+    /// last [`reset_stats`](TypedEncoder::reset_stats)
+    pub fn stats(&self) -> Option<&CodebookStats> {
+        self.stats.as_ref()
+    }
+
+    /// Start collecting statistics afresh, for example to watch how the fit changes from one
+    /// period to the next
+    pub fn reset_stats(&mut self) {
+        if let Some(codebook) = &self.codebook {
+            self.stats = Some(CodebookStats::new(codebook));
+        }
+    }
+
+    /// The codebook, once the warmup is complete
+    pub fn codebook(&self) -> Option<&Codebook> {
+        self.codebook.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct Reading {
+        sensor: String,
+        value: u16,
+    }
+
+    fn reading(i: u16) -> Reading {
+        Reading {
+            sensor: format!("temperature-sensor-{}", i % 4),
+            value: 200 + i % 17,
+        }
+    }
+
+    #[test]
+    fn trains_after_warmup() {
+        let mut encoder = TypedEncoder::new(50);
+        let encoded: Vec<Vec<u8>> = (0..100)
+            .map(|i| encoder.encode(&reading(i)).unwrap())
+            .collect();
+        let codebook = encoder.codebook().unwrap();
+
+        // Values after the warmup leave out the tree
+        let warmup_size: usize = encoded[..50].iter().map(Vec::len).sum();
+        let trained_size: usize = encoded[50..].iter().map(Vec::len).sum();
+        assert!(trained_size < warmup_size);
+
+        for (i, bytes) in encoded.iter().enumerate() {
+            let decoded: Reading = crate::puff_with_codebook(bytes, codebook).unwrap();
+            assert_eq!(decoded, reading(i as u16));
+        }
+
+        // Only the values coded with the codebook are counted
+        assert_eq!(encoder.stats().unwrap().messages(), 50);
+        encoder.reset_stats();
+        assert_eq!(encoder.stats().unwrap().messages(), 0);
+    }
+
+    #[test]
+    fn existing_codebook() {
+        let mut trainer = TypedEncoder::new(10);
+        for i in 0..10 {
+            trainer.encode(&reading(i)).unwrap();
+        }
+        let codebook = Codebook::from_bytes(&trainer.codebook().unwrap().to_bytes()).unwrap();
+
+        let mut encoder = TypedEncoder::with_codebook(codebook.clone());
+        let encoded = encoder.encode(&reading(3)).unwrap();
+        assert!(crate::puff::<Reading>(&encoded).is_err());
+        assert_eq!(
+            crate::puff_with_codebook::<Reading>(&encoded, &codebook).unwrap(),
+            reading(3)
+        );
+        assert!(crate::puff_with_codebook::<String>(&encoded, &codebook).is_err());
+    }
+}
@@ -0,0 +1,75 @@
+//! LEB128 variable length integers, for lengths and counts that are usually small
+
+#[cfg(feature = "encode")]
+pub(crate) fn write(mut value: u64, out: &mut Vec<u8>) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+/// Like [`write`], into the start of `out`, returning the number of bytes written. `out` must
+/// have room for [`len`] bytes.
+#[cfg(feature = "encode")]
+pub(crate) fn write_to_slice(mut value: u64, out: &mut [u8]) -> usize {
+    let mut i = 0;
+    while value >= 0x80 {
+        out[i] = value as u8 | 0x80;
+        value >>= 7;
+        i += 1;
+    }
+    out[i] = value as u8;
+    i + 1
+}
+
+/// The number of bytes [`write`] uses for `value`
+pub(crate) fn len(value: u64) -> usize {
+    (64 - value.leading_zeros() as usize).div_ceil(7).max(1)
+}
+
+/// Read a varint from the start of `bytes`, advancing past it. Returns `None` if `bytes` ends
+/// before the varint does, or it doesn't fit in 64 bits.
+#[cfg(feature = "decode")]
+pub(crate) fn read(bytes: &mut &[u8]) -> Option<u64> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = bytes.split_first()?;
+        *bytes = rest;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        for value in [0, 1, 127, 128, 300, u32::MAX as u64, u64::MAX] {
+            let mut out = Vec::new();
+            write(value, &mut out);
+            assert_eq!(out.len(), len(value));
+            let mut bytes = &out[..];
+            assert_eq!(read(&mut bytes), Some(value));
+            assert!(bytes.is_empty());
+        }
+
+        let mut slice = [0; 10];
+        for value in [0, 300, u64::MAX] {
+            let mut out = Vec::new();
+            write(value, &mut out);
+            let len = write_to_slice(value, &mut slice);
+            assert_eq!(out, slice[..len]);
+        }
+
+        let mut out = Vec::new();
+        write(300, &mut out);
+        assert_eq!(out, [0xac, 0x02]);
+        assert_eq!(read(&mut &out[..1]), None);
+    }
+}
@@ -0,0 +1,151 @@
+//! A growable vector that holds its elements compressed, a block at a time
+
+/// A vector whose elements are appended to an uncompressed tail, which is compressed as a block
+/// once it holds `block_len` elements. Reading an element only decompresses the block holding
+/// it, so old elements can be kept around cheaply while new ones keep arriving, as with retained
+/// telemetry.
+pub struct CompressedVec<T> {
+    block_len: usize,
+    blocks: Vec<Box<[u8]>>,
+    tail: Vec<T>,
+}
+
+impl<T> CompressedVec<T> {
+    /// An empty vector that compresses `block_len` elements at a time. Larger blocks compress
+    /// better, but each read decompresses a whole block.
+    ///
+    /// Panics if `block_len` is zero.
+    pub fn new(block_len: usize) -> Self {
+        assert!(block_len > 0, "block length must be greater than zero");
+        Self {
+            block_len,
+            blocks: Vec::new(),
+            tail: Vec::with_capacity(block_len),
+        }
+    }
+
+    pub fn block_len(&self) -> usize {
+        self.block_len
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.len() * self.block_len + self.tail.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The number of bytes of the compressed blocks. Elements in the tail aren't counted.
+    pub fn compressed_size(&self) -> usize {
+        self.blocks.iter().map(|block| block.len()).sum()
+    }
+
+    /// The elements that haven't been compressed yet
+    pub fn tail(&self) -> &[T] {
+        &self.tail
+    }
+}
+
+#[cfg(feature = "encode")]
+impl<T: serde::Serialize> CompressedVec<T> {
+    /// Append `value`, compressing the tail if it's full. If compressing fails, `value` isn't
+    /// appended and the vector is left as it was.
+    pub fn push(&mut self, value: T) -> Result<(), crate::Error> {
+        self.tail.push(value);
+        if self.tail.len() == self.block_len {
+            let block = match crate::huff(&self.tail) {
+                Ok(block) => block,
+                Err(error) => {
+                    self.tail.pop();
+                    return Err(error);
+                }
+            };
+            self.blocks.push(block.into_boxed_slice());
+            self.tail.clear();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "decode")]
+impl<T: serde::de::DeserializeOwned + Clone> CompressedVec<T> {
+    /// The element at `index`, decompressing the block that holds it
+    pub fn get(&self, index: usize) -> Result<Option<T>, crate::Error> {
+        let (block, offset) = (index / self.block_len, index % self.block_len);
+        match self.blocks.get(block) {
+            Some(block) => Ok(crate::puff::<Vec<T>>(block)?.into_iter().nth(offset)),
+            None => Ok(self
+                .tail
+                .get(index - self.blocks.len() * self.block_len)
+                .cloned()),
+        }
+    }
+
+    /// Decompress the block with index `block`, giving its elements
+    pub fn get_block(&self, block: usize) -> Result<Option<Vec<T>>, crate::Error> {
+        match self.blocks.get(block) {
+            Some(block) => crate::puff(block).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn push_and_get() {
+        let mut vec = CompressedVec::new(100);
+        for i in 0..1050u64 {
+            vec.push(1_700_000_000 + i * 15).unwrap();
+        }
+        assert_eq!(vec.len(), 1050);
+        assert_eq!(vec.tail().len(), 50);
+        for i in [0, 99, 100, 555, 999, 1000, 1049] {
+            assert_eq!(vec.get(i).unwrap(), Some(1_700_000_000 + i as u64 * 15));
+        }
+        assert_eq!(vec.get(1050).unwrap(), None);
+        assert_eq!(vec.get_block(1).unwrap().unwrap()[0], 1_700_001_500);
+        assert!(vec.get_block(10).unwrap().is_none());
+        assert!(vec.compressed_size() < 1000 * 8);
+    }
+
+    /// Fails to serialize when it holds `None`
+    #[derive(Clone, Debug, PartialEq, serde::Deserialize)]
+    struct Fallible(Option<u32>);
+
+    impl serde::Serialize for Fallible {
+        fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            match self.0 {
+                Some(value) => serializer.serialize_some(&value),
+                None => Err(serde::ser::Error::custom("nothing to serialize")),
+            }
+        }
+    }
+
+    #[test]
+    fn failed_push() {
+        let mut vec = CompressedVec::new(3);
+        vec.push(Fallible(Some(1))).unwrap();
+        vec.push(Fallible(None)).unwrap();
+        assert!(vec.push(Fallible(Some(3))).is_err());
+        assert_eq!(vec.len(), 2);
+
+        vec.tail.pop();
+        vec.push(Fallible(Some(2))).unwrap();
+        vec.push(Fallible(Some(3))).unwrap();
+        vec.push(Fallible(Some(4))).unwrap();
+        assert_eq!(vec.len(), 4);
+        assert_eq!(vec.tail(), [Fallible(Some(4))]);
+        assert_eq!(vec.get(2).unwrap(), Some(Fallible(Some(3))));
+    }
+
+    #[test]
+    fn empty() {
+        let vec = CompressedVec::<String>::new(1);
+        assert!(vec.is_empty());
+        assert_eq!(vec.get(0).unwrap(), None);
+    }
+}
@@ -0,0 +1,64 @@
+use std::io::IoSlice;
+
+use crate::bits::BitOrder;
+use crate::{frame, huffman};
+
+/// Compress several buffers as one message, as if they were concatenated, without copying them
+/// together first. This suits data like a header and body held in separate buffers. The result
+/// decodes to the bytes of all the buffers in order, so it's read with
+/// [`DecodedReader`](crate::DecodedReader), or with [`puff`](crate::puff) if the buffers together
+/// hold a serialized value.
+///
+/// Messages are always huffman coded, unless storing the bytes as they are is smaller.
+pub fn huff_vectored(buffers: &[IoSlice<'_>]) -> Result<Vec<u8>, crate::Error> {
+    let mut frequencies = [0u32; 256];
+    for buffer in buffers {
+        for (count, buffer_count) in frequencies.iter_mut().zip(huffman::histogram(buffer)) {
+            *count = count.saturating_add(buffer_count);
+        }
+    }
+    if frequencies.iter().all(|&count| count == 0) {
+        return Err(huffman::Error::NoData.into());
+    }
+
+    let mut writer = huffman::MessageWriter::new(&frequencies, BitOrder::default());
+    for buffer in buffers {
+        writer.write(buffer);
+    }
+    let parts: Vec<&[u8]> = buffers.iter().map(|buffer| &**buffer).collect();
+    Ok(frame::frame_message(writer.finish(), &parts))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let value = (7u32, "a body that follows its header".repeat(5));
+        let serialized = bincode::serialize(&value).unwrap();
+        let (header, body) = serialized.split_at(4);
+
+        let compressed =
+            huff_vectored(&[IoSlice::new(header), IoSlice::new(&[]), IoSlice::new(body)]).unwrap();
+        assert!(compressed.len() < serialized.len());
+        assert_eq!(crate::puff::<(u32, String)>(&compressed).unwrap(), value);
+    }
+
+    #[test]
+    fn stored() {
+        let bytes: Vec<u8> = (0..=255).collect();
+        let (a, b) = bytes.split_at(100);
+        let compressed = huff_vectored(&[IoSlice::new(a), IoSlice::new(b)]).unwrap();
+        assert!(compressed.len() <= crate::max_compressed_len(bytes.len()));
+        let decoded: Vec<u8> = crate::decode_iter(&compressed)
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(decoded, bytes);
+
+        assert!(matches!(
+            huff_vectored(&[IoSlice::new(&[])]),
+            Err(crate::Error::Huffman(huffman::Error::NoData))
+        ));
+    }
+}
@@ -0,0 +1,85 @@
+//! `wasm_bindgen` wrappers, so data compressed in Rust can be decompressed in the browser and the
+//! other way around. Errors reach JavaScript as a [`WasmError`], whose code is one of the
+//! [`ErrorCode`] values, which are exported as a TypeScript enum.
+
+use wasm_bindgen::prelude::wasm_bindgen;
+
+use crate::ErrorCode;
+
+/// An error from [`compress_bytes`] or [`decompress_bytes`], thrown to JavaScript
+#[wasm_bindgen]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WasmError {
+    code: ErrorCode,
+    message: String,
+}
+
+#[wasm_bindgen]
+impl WasmError {
+    /// The kind of error, which stays the same across versions
+    #[wasm_bindgen(getter)]
+    pub fn code(&self) -> ErrorCode {
+        self.code
+    }
+
+    /// A description of what went wrong, which may change between versions
+    #[wasm_bindgen(getter)]
+    pub fn message(&self) -> String {
+        self.message.clone()
+    }
+}
+
+/// The message includes those of the errors the error was caused by, since JavaScript only gets
+/// the message
+impl From<crate::Error> for WasmError {
+    fn from(error: crate::Error) -> Self {
+        let mut message = error.to_string();
+        let mut source = std::error::Error::source(&error);
+        while let Some(cause) = source {
+            message.push_str(&format!(": {cause}"));
+            source = cause.source();
+        }
+        Self {
+            code: error.error_code(),
+            message,
+        }
+    }
+}
+
+/// Compress `bytes` to a frame, like [`compress`](crate::compress)
+#[cfg(feature = "encode")]
+#[wasm_bindgen]
+pub fn compress_bytes(bytes: &[u8]) -> Result<Vec<u8>, WasmError> {
+    Ok(crate::compress(bytes)?)
+}
+
+/// Decompress a frame, like [`decompress`](crate::decompress)
+#[cfg(feature = "decode")]
+#[wasm_bindgen]
+pub fn decompress_bytes(bytes: &[u8]) -> Result<Vec<u8>, WasmError> {
+    Ok(crate::decompress(bytes)?)
+}
+
+#[cfg(all(test, feature = "encode", feature = "decode"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let data = b"compressed by a backend, decompressed by a browser".repeat(10);
+        let compressed = compress_bytes(&data).unwrap();
+        assert_eq!(compressed, crate::compress(&data).unwrap());
+        assert_eq!(decompress_bytes(&compressed).unwrap(), data);
+    }
+
+    #[test]
+    fn errors() {
+        let error = decompress_bytes(&[0xff; 4]).unwrap_err();
+        let expected = crate::decompress(&[0xff; 4]).unwrap_err();
+        assert_eq!(error.code(), expected.error_code());
+        assert_eq!(error.message(), expected.to_string());
+        let error = compress_bytes(b"").unwrap_err();
+        assert_eq!(error.code(), ErrorCode::Huffman);
+        assert_eq!(error.message(), "huffman coding failed: there was no data");
+    }
+}
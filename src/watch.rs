@@ -0,0 +1,324 @@
+//! Watching a directory and compressing files into another as they appear or change, for archiving
+//! logs or build artifacts without a wrapper script. The directory is polled, which works the same
+//! on every platform and on network filesystems, where change notifications often don't.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::block::{BlockWriter, DEFAULT_BLOCK_SIZE};
+
+/// The extension added to the name of each compressed file
+const EXTENSION: &str = "hnp";
+
+/// How a [`DirWatcher`] watches and compresses
+#[derive(Debug, Clone)]
+pub struct WatchOptions {
+    /// How often [`DirWatcher::run`] scans the directory
+    pub poll_interval: Duration,
+    /// How long a file must go unchanged before it's compressed, so files still being written
+    /// aren't compressed over and over
+    pub debounce: Duration,
+    /// Glob patterns for files to leave alone. `*` matches any part of a name and `?` any one
+    /// character, and `**` also matches across directories. Patterns without a `/` are matched
+    /// against file names, and others against paths relative to the watched directory.
+    pub exclude: Vec<String>,
+    /// The block size of the compressed files, see [`BlockWriter::new`]
+    pub block_size: usize,
+}
+
+impl Default for WatchOptions {
+    fn default() -> Self {
+        Self {
+            poll_interval: Duration::from_secs(1),
+            debounce: Duration::from_secs(2),
+            exclude: Vec::new(),
+            block_size: DEFAULT_BLOCK_SIZE,
+        }
+    }
+}
+
+/// The size and modification time of a file, which tell whether it changed
+type Version = (u64, Option<SystemTime>);
+
+/// Compresses the files in a directory and its subdirectories into another directory, as block
+/// streams that [`decompress_blocks`](crate::decompress_blocks) or a
+/// [`PuffReader`](crate::PuffReader) reads. Each file is written to the same relative path in the
+/// output directory with `.hnp` added to its name, replacing it atomically, and again whenever the
+/// file changes. Files whose compressed copy is already newer than them are left alone, so a
+/// restarted watcher carries on where it left off.
+pub struct DirWatcher {
+    dir: PathBuf,
+    out: PathBuf,
+    options: WatchOptions,
+    /// The version of each file that was last compressed
+    compressed: HashMap<PathBuf, Version>,
+    /// Files that changed since they were compressed, with when they were last seen changing
+    pending: HashMap<PathBuf, (Version, Instant)>,
+}
+
+impl DirWatcher {
+    /// Watch `dir`, compressing into `out`, which is created if it doesn't exist
+    pub fn new(
+        dir: impl Into<PathBuf>,
+        out: impl Into<PathBuf>,
+        options: WatchOptions,
+    ) -> Result<Self, crate::Error> {
+        let out = out.into();
+        fs::create_dir_all(&out)?;
+        Ok(Self {
+            dir: dir.into(),
+            out,
+            options,
+            compressed: HashMap::new(),
+            pending: HashMap::new(),
+        })
+    }
+
+    /// Scan the directory once, compressing the files that have settled since they changed, and
+    /// return the paths of the compressed files that were written
+    pub fn poll(&mut self) -> Result<Vec<PathBuf>, crate::Error> {
+        let mut files = Vec::new();
+        self.scan(&self.dir.clone(), &mut files)?;
+
+        let now = Instant::now();
+        let mut written = Vec::new();
+        for (relative, version) in files {
+            if self.compressed.get(&relative) == Some(&version) {
+                continue;
+            }
+            let out = self.out_path(&relative);
+            if !self.compressed.contains_key(&relative) && is_newer(&out, version.1) {
+                self.compressed.insert(relative, version);
+                continue;
+            }
+
+            let changed = match self.pending.get(&relative) {
+                Some((pending, _)) => *pending != version,
+                None => true,
+            };
+            if changed {
+                self.pending.insert(relative.clone(), (version, now));
+                if !self.options.debounce.is_zero() {
+                    continue;
+                }
+            } else if now - self.pending[&relative].1 < self.options.debounce {
+                continue;
+            }
+
+            match self.compress(&relative, &out) {
+                Ok(()) => written.push(out),
+                // The file was removed before it could be read
+                Err(crate::Error::Io(error)) if error.kind() == io::ErrorKind::NotFound => {}
+                Err(error) => return Err(error),
+            }
+            self.pending.remove(&relative);
+            self.compressed.insert(relative, version);
+        }
+        Ok(written)
+    }
+
+    /// Poll every [`poll_interval`](WatchOptions::poll_interval) until `stop` is set, calling
+    /// `written` with the path of each compressed file as it's written
+    pub fn run(
+        &mut self,
+        stop: &AtomicBool,
+        mut written: impl FnMut(&Path),
+    ) -> Result<(), crate::Error> {
+        while !stop.load(Ordering::Relaxed) {
+            for path in self.poll()? {
+                written(&path);
+            }
+            std::thread::sleep(self.options.poll_interval);
+        }
+        Ok(())
+    }
+
+    /// Collect the files under `dir` that aren't excluded, by their path relative to the watched
+    /// directory
+    fn scan(&self, dir: &Path, files: &mut Vec<(PathBuf, Version)>) -> io::Result<()> {
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            // The output directory may be inside the watched one
+            if path == self.out {
+                continue;
+            }
+            let relative = path.strip_prefix(&self.dir).unwrap_or(&path).to_path_buf();
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            if metadata.is_dir() {
+                self.scan(&path, files)?;
+            } else if metadata.is_file() && !self.is_excluded(&relative) {
+                files.push((relative, (metadata.len(), metadata.modified().ok())));
+            }
+        }
+        Ok(())
+    }
+
+    fn is_excluded(&self, relative: &Path) -> bool {
+        let path = relative.to_string_lossy().replace('\\', "/");
+        let name = path.rsplit('/').next().unwrap_or(&path);
+        self.options
+            .exclude
+            .iter()
+            .any(|pattern| match pattern.contains('/') {
+                true => glob_matches(pattern.as_bytes(), path.as_bytes()),
+                false => glob_matches(pattern.as_bytes(), name.as_bytes()),
+            })
+    }
+
+    fn out_path(&self, relative: &Path) -> PathBuf {
+        let mut name = relative.as_os_str().to_owned();
+        name.push(".");
+        name.push(EXTENSION);
+        self.out.join(name)
+    }
+
+    /// Compress the file at `relative` to `out`, through a temporary file that's renamed over it
+    fn compress(&self, relative: &Path, out: &Path) -> Result<(), crate::Error> {
+        if let Some(parent) = out.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut temp_name = std::ffi::OsString::from(".");
+        temp_name.push(out.file_name().unwrap_or_default());
+        temp_name.push(format!(".{}.tmp", std::process::id()));
+        let temp = out.with_file_name(temp_name);
+
+        let written = File::open(self.dir.join(relative))
+            .map_err(crate::Error::from)
+            .and_then(|mut file| {
+                let output = BufWriter::new(File::create(&temp)?);
+                let mut writer = BlockWriter::new(output, self.options.block_size)?;
+                io::copy(&mut file, &mut writer)?;
+                let output = writer.finish()?;
+                output.into_inner().map_err(io::Error::from)?.sync_all()?;
+                Ok(())
+            });
+        if let Err(error) = written.and_then(|()| Ok(fs::rename(&temp, out)?)) {
+            let _ = fs::remove_file(&temp);
+            return Err(error);
+        }
+        Ok(())
+    }
+}
+
+/// Whether the file at `path` exists and was modified no earlier than `modified`
+fn is_newer(path: &Path, modified: Option<SystemTime>) -> bool {
+    let out_modified = fs::metadata(path).and_then(|metadata| metadata.modified());
+    matches!((out_modified, modified), (Ok(out), Some(modified)) if out >= modified)
+}
+
+/// Match `text` against a glob pattern, see [`WatchOptions::exclude`]
+fn glob_matches(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern {
+        [] => text.is_empty(),
+        [b'*', b'*', rest @ ..] => (0..=text.len()).any(|i| glob_matches(rest, &text[i..])),
+        [b'*', rest @ ..] => {
+            let segment = text.iter().position(|&byte| byte == b'/');
+            (0..=segment.unwrap_or(text.len())).any(|i| glob_matches(rest, &text[i..]))
+        }
+        [b'?', rest @ ..] => {
+            matches!(text, [first, tail @ ..] if *first != b'/' && glob_matches(rest, tail))
+        }
+        [first, rest @ ..] => {
+            matches!(text, [byte, tail @ ..] if byte == first && glob_matches(rest, tail))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("huffnpuff-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn decompressed(path: &Path) -> Vec<u8> {
+        crate::decompress_blocks(&fs::read(path).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn compresses_changes() {
+        let dir = test_dir("watch");
+        let out = dir.join("archive");
+        let options = WatchOptions {
+            debounce: Duration::ZERO,
+            exclude: vec!["*.tmp".to_owned()],
+            ..WatchOptions::default()
+        };
+        let mut watcher = DirWatcher::new(&dir, &out, options.clone()).unwrap();
+
+        let log = b"GET /index.html 200\n".repeat(500);
+        fs::write(dir.join("access.log"), &log).unwrap();
+        fs::write(dir.join("partial.tmp"), b"still being written").unwrap();
+        fs::create_dir_all(dir.join("builds")).unwrap();
+        fs::write(dir.join("builds/artifact.bin"), [7; 3000]).unwrap();
+
+        let mut written = watcher.poll().unwrap();
+        written.sort();
+        assert_eq!(
+            written,
+            [
+                out.join("access.log.hnp"),
+                out.join("builds/artifact.bin.hnp")
+            ]
+        );
+        assert_eq!(decompressed(&written[0]), log);
+        assert_eq!(decompressed(&written[1]), [7; 3000]);
+        assert!(watcher.poll().unwrap().is_empty());
+
+        let longer = b"POST /login 302\n".repeat(700);
+        fs::write(dir.join("access.log"), &longer).unwrap();
+        assert_eq!(watcher.poll().unwrap(), [out.join("access.log.hnp")]);
+        assert_eq!(decompressed(&out.join("access.log.hnp")), longer);
+
+        // A new watcher skips files that were already compressed
+        let mut restarted = DirWatcher::new(&dir, &out, options).unwrap();
+        assert!(restarted.poll().unwrap().is_empty());
+    }
+
+    #[test]
+    fn debounce() {
+        let dir = test_dir("watch-debounce");
+        let options = WatchOptions {
+            debounce: Duration::from_millis(50),
+            ..WatchOptions::default()
+        };
+        let mut watcher = DirWatcher::new(dir.join("in"), dir.join("out"), options).unwrap();
+        fs::create_dir_all(dir.join("in")).unwrap();
+
+        fs::write(dir.join("in/growing.log"), b"first line\n").unwrap();
+        assert!(watcher.poll().unwrap().is_empty());
+        fs::write(dir.join("in/growing.log"), b"first line\nsecond line\n").unwrap();
+        assert!(watcher.poll().unwrap().is_empty());
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(watcher.poll().unwrap().len(), 1);
+        assert_eq!(
+            decompressed(&dir.join("out/growing.log.hnp")),
+            b"first line\nsecond line\n"
+        );
+    }
+
+    #[test]
+    fn globs() {
+        assert!(glob_matches(b"*.tmp", b"a.tmp"));
+        assert!(glob_matches(b"*.tmp", b".tmp"));
+        assert!(!glob_matches(b"*.tmp", b"a.tmp.log"));
+        assert!(glob_matches(b"log-??.txt", b"log-01.txt"));
+        assert!(!glob_matches(b"log-??.txt", b"log-1.txt"));
+        assert!(glob_matches(b"cache/*", b"cache/x"));
+        assert!(!glob_matches(b"cache/*", b"cache/x/y"));
+        assert!(glob_matches(b"**/*.o", b"target/debug/main.o"));
+        assert!(glob_matches(b"target/**", b"target/debug/main.o"));
+    }
+}
@@ -0,0 +1,119 @@
+//! Per-message compression of WebSocket messages, for protocols where both ends use huffnpuff
+//! instead of permessage-deflate. The first message each peer sends on a connection is an
+//! [`Offer`] of the codebooks it holds. Once a peer has received the other's offer, both hold the
+//! same [`NegotiatedContext`], and every message after that is a value compressed with it and sent
+//! as a binary message.
+
+use tungstenite::Message;
+
+use crate::codebook::Codebook;
+use crate::negotiate::{NegotiatedContext, Offer, negotiate};
+
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// A text, ping, pong or close message was received where a binary one was expected
+    NotBinary,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Error::NotBinary => "a WebSocket message that isn't binary was received",
+        })
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// The compression context of one connection
+#[derive(Debug, Clone)]
+pub struct WebSocketCodec {
+    context: NegotiatedContext,
+}
+
+impl WebSocketCodec {
+    /// The message to send first on a connection, offering `codebooks`, the first being the most
+    /// preferred
+    pub fn offer(codebooks: &[Codebook]) -> Message {
+        Message::binary(Offer::new(codebooks).to_bytes())
+    }
+
+    /// Agree on a codebook with the peer, given the `codebooks` this side offered and the offer
+    /// the peer sent first
+    pub fn accept(codebooks: &[Codebook], offer: &Message) -> Result<Self, crate::Error> {
+        let offer = Offer::from_bytes(binary(offer)?)?;
+        Ok(Self {
+            context: negotiate(codebooks, &offer),
+        })
+    }
+
+    pub fn context(&self) -> &NegotiatedContext {
+        &self.context
+    }
+
+    /// Compress `value` into a binary message
+    #[cfg(feature = "encode")]
+    pub fn encode<T>(&self, value: &T) -> Result<Message, crate::Error>
+    where
+        T: serde::Serialize,
+    {
+        Ok(Message::binary(self.context.huff(value)?))
+    }
+
+    /// Decode a message sent by the peer's [`WebSocketCodec::encode`]
+    #[cfg(feature = "decode")]
+    pub fn decode<T>(&self, message: &Message) -> Result<T, crate::Error>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        self.context.puff(binary(message)?)
+    }
+}
+
+fn binary(message: &Message) -> Result<&[u8], Error> {
+    match message {
+        Message::Binary(bytes) => Ok(bytes),
+        _ => Err(Error::NotBinary),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conversation() {
+        let shared = Codebook::train(["{\"op\":\"move\",\"x\":1,\"y\":2}".repeat(10)]);
+        let client = [shared.clone()];
+        let server = [Codebook::train(["unrelated".repeat(10)]), shared.clone()];
+
+        let client_offer = WebSocketCodec::offer(&client);
+        let server_offer = WebSocketCodec::offer(&server);
+        let client_codec = WebSocketCodec::accept(&client, &server_offer).unwrap();
+        let server_codec = WebSocketCodec::accept(&server, &client_offer).unwrap();
+        assert_eq!(
+            client_codec.context().codebook().map(Codebook::id),
+            Some(shared.id())
+        );
+
+        let request = "{\"op\":\"move\",\"x\":3,\"y\":4}".to_owned();
+        let message = client_codec.encode(&request).unwrap();
+        assert!(message.is_binary());
+        assert_eq!(server_codec.decode::<String>(&message).unwrap(), request);
+    }
+
+    #[test]
+    fn not_binary() {
+        let codec = WebSocketCodec::accept(&[], &WebSocketCodec::offer(&[])).unwrap();
+        assert!(codec.context().codebook().is_none());
+        assert_eq!(
+            codec.decode::<String>(&Message::text("hello")),
+            Err(crate::Error::WebSocket(Error::NotBinary))
+        );
+        assert_eq!(
+            WebSocketCodec::accept(&[], &Message::Ping(Vec::new().into())).unwrap_err(),
+            crate::Error::WebSocket(Error::NotBinary)
+        );
+    }
+}
@@ -0,0 +1,359 @@
+//! Huffman coding of 16 bit words instead of bytes, for data made of 16 bit values such as UTF-16
+//! text or arrays of `u16`. Splitting such values into bytes mixes their high and low halves into
+//! one alphabet, and codes the two without regard to each other, where a code for whole words
+//! keeps that structure.
+//!
+//! The bytes are read as little endian words, and a byte left over at the end is stored as it is.
+//! With up to 65536 words in the alphabet, sending a tree would be costly, so the code is
+//! canonical and the message starts with the code lengths of the words that occur:
+//!
+//! - one bit that is set if there's a byte left over, followed by that byte in 8 bits if so
+//! - the number of distinct words, less one, in 16 bits, and the longest length, less one, in 5
+//! - each length less one in just enough bits for the longest, first EOM's, and then for each
+//!   word that occurs, in order, the gap since the one before as an exp-Golomb code and the
+//!   word's length
+//!
+//! Codes of each length are assigned in order of value, shortest first, with EOM after every word.
+
+#[cfg(feature = "encode")]
+use std::cmp::Reverse;
+#[cfg(feature = "encode")]
+use std::collections::{BinaryHeap, HashMap};
+
+use crate::bits::BitOrder;
+#[cfg(feature = "decode")]
+use crate::bits::BitReader;
+#[cfg(feature = "encode")]
+use crate::bits::BitWriter;
+#[cfg(feature = "decode")]
+use crate::huffman::Error;
+#[cfg(feature = "decode")]
+use crate::reserve;
+
+/// The value standing for EOM, after every word
+const EOM: u32 = 1 << 16;
+
+/// The longest code a word may have
+const MAX_LEN: usize = 32;
+
+/// Code `bytes` by words, or `None` if there isn't a whole word to code, or the code would have
+/// codes longer than [`MAX_LEN`], which takes billions of words with very skewed counts
+#[cfg(feature = "encode")]
+pub(crate) fn encode(bytes: &[u8], order: BitOrder) -> Option<Vec<u8>> {
+    if bytes.len() < 2 {
+        return None;
+    }
+    let (words, odd) = split(bytes);
+
+    let mut counts = vec![0u64; 1 << 16];
+    for word in words.clone() {
+        counts[word as usize] += 1;
+    }
+    let symbols: Vec<(u32, u64)> = (0..1 << 16)
+        .filter(|&word| counts[word as usize] > 0)
+        .map(|word| (word, counts[word as usize]))
+        .chain([(EOM, 1)])
+        .collect();
+    let lengths = code_lengths(&symbols);
+    let longest = *lengths.iter().max().unwrap();
+    if longest > MAX_LEN {
+        return None;
+    }
+    let codes = assign_codes(symbols.iter().map(|&(value, _)| value).zip(lengths));
+
+    let mut bits = BitWriter::with_order(order);
+    bits.write_bit(odd.is_some());
+    if let Some(byte) = odd {
+        bits.write_bits(byte as u64, 8);
+    }
+    let len_bits = (usize::BITS - (longest - 1).leading_zeros()) as usize;
+    bits.write_bits(symbols.len() as u64 - 2, 16);
+    bits.write_bits(longest as u64 - 1, 5);
+    bits.write_bits(codes[&EOM].1 as u64 - 1, len_bits);
+    let mut next = 0;
+    for &(word, _) in &symbols[..symbols.len() - 1] {
+        // The gap plus one, whose highest set bit follows as many clear bits as there are bits
+        // below it
+        let gap = (word - next + 1) as u64;
+        let width = gap.ilog2() as usize;
+        bits.write_bits(0, width);
+        bits.write_bit(true);
+        bits.write_bits(gap, width);
+        bits.write_bits(codes[&word].1 as u64 - 1, len_bits);
+        next = word + 1;
+    }
+
+    for word in words.chain([EOM]) {
+        let (code, len) = codes[&word];
+        // Codes are read a bit at a time, first bit first, and multi-bit values are written most
+        // significant bit first only in Msb0 order
+        match order {
+            BitOrder::Msb0 => bits.write_bits(code as u64, len),
+            BitOrder::Lsb0 => bits.write_bits((code.reverse_bits() >> (32 - len)) as u64, len),
+        }
+    }
+    Some(bits.into_bytes())
+}
+
+/// The little endian words of `bytes`, and the byte left over at the end if there's an odd number
+#[cfg(feature = "encode")]
+fn split(bytes: &[u8]) -> (impl ExactSizeIterator<Item = u32> + Clone + '_, Option<u8>) {
+    let chunks = bytes.chunks_exact(2);
+    let odd = chunks.remainder().first().copied();
+    let words = chunks.map(|pair| u16::from_le_bytes([pair[0], pair[1]]) as u32);
+    (words, odd)
+}
+
+/// The length of the huffman code for each of `symbols`, which are values and their counts
+#[cfg(feature = "encode")]
+fn code_lengths(symbols: &[(u32, u64)]) -> Vec<usize> {
+    // The leaves come first, and each node joining two others is added after them
+    let mut parents = vec![usize::MAX; symbols.len()];
+    let mut heap: BinaryHeap<_> = symbols
+        .iter()
+        .enumerate()
+        .map(|(node, &(_, count))| Reverse((count, node)))
+        .collect();
+    while let (Some(Reverse((a, left))), Some(Reverse((b, right)))) = (heap.pop(), heap.pop()) {
+        let node = parents.len();
+        parents.push(usize::MAX);
+        parents[left] = node;
+        parents[right] = node;
+        heap.push(Reverse((a + b, node)));
+    }
+
+    // Parents come after their children, so depths can be filled in from the root down
+    let mut depths = vec![0; parents.len()];
+    for node in (0..parents.len() - 1).rev() {
+        depths[node] = depths[parents[node]] + 1;
+    }
+    depths.truncate(symbols.len());
+    depths
+}
+
+/// The canonical code for each value with its length, as the code and its length
+#[cfg(feature = "encode")]
+fn assign_codes(lengths: impl Iterator<Item = (u32, usize)>) -> HashMap<u32, (u32, usize)> {
+    let mut sorted: Vec<(usize, u32)> = lengths.map(|(value, len)| (len, value)).collect();
+    sorted.sort_unstable();
+    let mut codes = HashMap::with_capacity(sorted.len());
+    let (mut code, mut previous) = (0u64, sorted[0].0);
+    for (len, value) in sorted {
+        code <<= len - previous;
+        codes.insert(value, (code as u32, len));
+        code += 1;
+        previous = len;
+    }
+    codes
+}
+
+/// A canonical code read from the start of a message
+#[cfg(feature = "decode")]
+struct Code {
+    /// The values in order of their codes
+    sorted: Vec<u32>,
+    /// The number of codes of each length
+    counts: [u32; MAX_LEN + 1],
+    /// The byte left over after the words
+    odd: Option<u8>,
+}
+
+#[cfg(feature = "decode")]
+impl Code {
+    /// Read the code lengths at the start of a message, or fail if they run out or don't make a
+    /// complete code
+    fn read(bits: &mut BitReader) -> Result<Self, Error> {
+        let odd = match bits.read_bit().ok_or(Error::FailedToDecodeHuffmanTree)? {
+            true => Some(bits.read_n(8).ok_or(Error::FailedToDecodeHuffmanTree)? as u8),
+            false => None,
+        };
+        let (count, longest) = Self::read_sizes(bits)?;
+        let len_bits = (usize::BITS - (longest - 1).leading_zeros()) as usize;
+        let read_len = |bits: &mut BitReader| match bits.read_n(len_bits) {
+            Some(len) if (len as usize) < longest => Ok(len as usize + 1),
+            _ => Err(Error::FailedToDecodeHuffmanTree),
+        };
+
+        let mut lengths = Vec::with_capacity(count + 1);
+        lengths.push((read_len(bits)?, EOM));
+        let mut next = 0;
+        for _ in 0..count {
+            let mut width = 0;
+            while !bits.read_bit().ok_or(Error::FailedToDecodeHuffmanTree)? {
+                width += 1;
+                if width > 16 {
+                    return Err(Error::FailedToDecodeHuffmanTree);
+                }
+            }
+            let gap =
+                (1 << width | bits.read_n(width).ok_or(Error::FailedToDecodeHuffmanTree)?) as u32;
+            let word = next + gap - 1;
+            if word >= EOM {
+                return Err(Error::FailedToDecodeHuffmanTree);
+            }
+            lengths.push((read_len(bits)?, word));
+            next = word + 1;
+        }
+
+        // A complete code uses up all of the code space, which is 2^32 codes of 32 bits
+        let mut counts = [0u32; MAX_LEN + 1];
+        let mut space = 0u64;
+        for &(len, _) in &lengths {
+            counts[len] += 1;
+            space += 1 << (MAX_LEN - len);
+        }
+        if space != 1 << MAX_LEN {
+            return Err(Error::FailedToDecodeHuffmanTree);
+        }
+        // EOM sorts after every word of the same length
+        lengths.sort_unstable();
+        Ok(Self {
+            sorted: lengths.into_iter().map(|(_, value)| value).collect(),
+            counts,
+            odd,
+        })
+    }
+
+    /// Read the number of distinct words and the longest length, which follow the leftover byte
+    fn read_sizes(bits: &mut BitReader) -> Result<(usize, usize), Error> {
+        let count = bits.read_n(16).ok_or(Error::FailedToDecodeHuffmanTree)? as usize + 1;
+        let longest = bits.read_n(5).ok_or(Error::FailedToDecodeHuffmanTree)? as usize + 1;
+        Ok((count, longest))
+    }
+
+    /// Decode a single value, or `None` if the bits run out first
+    fn decode_value(&self, bits: &mut BitReader) -> Option<u32> {
+        // The first code of each length follows on from the last one of the length before
+        let (mut code, mut first, mut index) = (0u64, 0u64, 0usize);
+        for &count in &self.counts[1..] {
+            code |= bits.read_bit()? as u64;
+            if code < first + count as u64 {
+                return Some(self.sorted[index + (code - first) as usize]);
+            }
+            index += count as usize;
+            first = (first + count as u64) << 1;
+            code <<= 1;
+        }
+        unreachable!("the code is complete")
+    }
+}
+
+/// The number of bytes of memory needed for the code at the start of `bytes`, without reading
+/// the whole code
+#[cfg(feature = "decode")]
+pub(crate) fn code_memory(bytes: &[u8], order: BitOrder) -> Result<usize, Error> {
+    let mut bits = BitReader::with_order(bytes, order);
+    if bits.read_bit().ok_or(Error::FailedToDecodeHuffmanTree)? {
+        bits.read_n(8);
+    }
+    let (count, _) = Code::read_sizes(&mut bits)?;
+    Ok((count + 1) * std::mem::size_of::<u32>())
+}
+
+/// Decode the first message in `bytes`, as [`huffman::decode_member`](crate::huffman) does,
+/// stopping once the output is longer than `max_len`
+#[cfg(feature = "decode")]
+pub(crate) fn decode_member(
+    bytes: &[u8],
+    order: BitOrder,
+    max_len: usize,
+) -> Result<(Vec<u8>, usize), crate::Error> {
+    let mut bits = BitReader::with_order(bytes, order);
+    let code = Code::read(&mut bits)?;
+    let mut decoded = Vec::new();
+    while decoded.len() <= max_len {
+        match code.decode_value(&mut bits) {
+            Some(EOM) => {
+                if let Some(byte) = code.odd {
+                    reserve::push(&mut decoded, byte)?;
+                }
+                break;
+            }
+            Some(word) => {
+                let [low, high] = (word as u16).to_le_bytes();
+                reserve::push(&mut decoded, low)?;
+                reserve::push(&mut decoded, high)?;
+            }
+            None => return Err(Error::TruncatedMessage.into()),
+        }
+    }
+    Ok((decoded, bits.position().div_ceil(8)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::huffman;
+
+    fn utf16(text: &str) -> Vec<u8> {
+        text.encode_utf16().flat_map(u16::to_le_bytes).collect()
+    }
+
+    #[test]
+    fn roundtrip() {
+        let inputs = [
+            utf16("ab"),
+            utf16("Ελληνικά και English, Ελληνικά και English"),
+            b"odd".to_vec(),
+            (0..=u16::MAX).flat_map(u16::to_le_bytes).collect(),
+        ];
+        for input in inputs {
+            for order in [BitOrder::Lsb0, BitOrder::Msb0] {
+                let encoded = encode(&input, order).unwrap();
+                let (decoded, len) = decode_member(&encoded, order, usize::MAX).unwrap();
+                assert_eq!(decoded, input);
+                assert_eq!(len, encoded.len());
+            }
+        }
+        assert_eq!(encode(b"a", BitOrder::Lsb0), None);
+    }
+
+    #[test]
+    fn beats_bytes() {
+        // Greek letters share their high byte, which byte coding codes over and over
+        let text = utf16(&"αβγδεζηθικλμ νξοπρστυφχψω ".repeat(40));
+        let words = encode(&text, BitOrder::Lsb0).unwrap();
+        let bytes = huffman::encode(&text, BitOrder::Lsb0).unwrap();
+        assert!(words.len() < bytes.len() * 3 / 4);
+
+        // Values of a u16 array, skewed towards a few
+        let values: Vec<u8> = (0..5000u32)
+            .map(|i| [1000u16, 1001, 2000, 4000][(i * i % 7 % 4) as usize])
+            .flat_map(u16::to_le_bytes)
+            .collect();
+        let words = encode(&values, BitOrder::Lsb0).unwrap();
+        let bytes = huffman::encode(&values, BitOrder::Lsb0).unwrap();
+        assert!(words.len() < bytes.len());
+    }
+
+    #[test]
+    fn damaged() {
+        let text = utf16("cut off partway through");
+        let encoded = encode(&text, BitOrder::Lsb0).unwrap();
+        assert_eq!(
+            decode_member(&encoded[..encoded.len() - 2], BitOrder::Lsb0, usize::MAX),
+            Err(Error::TruncatedMessage.into())
+        );
+        assert_eq!(
+            decode_member(&encoded[..3], BitOrder::Lsb0, usize::MAX),
+            Err(Error::FailedToDecodeHuffmanTree.into())
+        );
+        // Two words and EOM, all with codes of length 2, leave part of the code space unused
+        let mut bits = BitWriter::new();
+        bits.write_bit(false);
+        bits.write_bits(1, 16);
+        bits.write_bits(1, 5);
+        bits.write_bits(1, 1);
+        for _ in 0..2 {
+            bits.write_bit(true);
+            bits.write_bits(1, 1);
+        }
+        assert_eq!(
+            decode_member(&bits.into_bytes(), BitOrder::Lsb0, usize::MAX),
+            Err(Error::FailedToDecodeHuffmanTree.into())
+        );
+
+        let (decoded, _) = decode_member(&encoded, BitOrder::Lsb0, 5).unwrap();
+        assert_eq!(decoded, text[..6]);
+    }
+}